@@ -0,0 +1,112 @@
+use super::{BatchOp, WriteBatch};
+use crate::{cfg::WriteOptions, err::Error, storage::DataStore, types::Key};
+use std::{mem, sync::Arc};
+use tokio::sync::{oneshot, RwLock};
+
+type Waiter = oneshot::Sender<Result<(), Error>>;
+
+#[derive(Default)]
+struct Queue {
+    batch: WriteBatch,
+    waiters: Vec<Waiter>,
+
+    /// Is a task draining the queue?
+    draining: bool,
+}
+
+/// Coalesces writes from many tasks into one `write_batch` call
+///
+/// Writes submitted while a group is being written wait in the queue and go out together in the next group,
+/// which takes the store lock once and syncs the value log once no matter how many writes it carries.
+///
+/// Keys and values too large for the store are rejected before joining a group. If a group fails anyway, its writes
+/// are written again one at a time so that every waiter gets the result of its own write. A group is not rolled
+/// back: the writes it applied before failing stay applied and are applied again, which leaves an extra copy of their
+/// value in the value log for garbage collection. A waiter whose own write then fails may still find the copy the
+/// group applied.
+#[derive(Clone)]
+pub struct GroupCommit {
+    store: Arc<RwLock<DataStore<'static, Key>>>,
+    options: WriteOptions,
+    queue: Arc<std::sync::Mutex<Queue>>,
+}
+
+impl GroupCommit {
+    /// Creates a group commit writing to `store` with `options`, set `options.sync` to fsync once per group
+    ///
+    /// The idempotency token of `options` is ignored, every group would carry it and all but the first would be
+    /// skipped.
+    pub fn new(store: Arc<RwLock<DataStore<'static, Key>>>, options: WriteOptions) -> Self {
+        Self {
+            store,
+            options: WriteOptions {
+                idempotency_token: None,
+                ..options
+            },
+            queue: Arc::default(),
+        }
+    }
+
+    /// Inserts `key` and returns once the group carrying it is written
+    pub async fn put(&self, key: impl AsRef<[u8]>, val: impl AsRef<[u8]>) -> Result<(), Error> {
+        self.submit(BatchOp::Put {
+            key: key.as_ref().to_vec(),
+            value: val.as_ref().to_vec(),
+        })
+        .await
+    }
+
+    /// Deletes `key` and returns once the group carrying it is written
+    pub async fn delete(&self, key: impl AsRef<[u8]>) -> Result<(), Error> {
+        self.submit(BatchOp::Delete {
+            key: key.as_ref().to_vec(),
+        })
+        .await
+    }
+
+    async fn submit(&self, op: BatchOp) -> Result<(), Error> {
+        self.store
+            .read()
+            .await
+            .check_entry_size(op.key().len(), op.value().len())?;
+        let (tx, rx) = oneshot::channel();
+        let start_draining = {
+            let mut queue = self.queue.lock().unwrap();
+            queue.batch.push(op);
+            queue.waiters.push(tx);
+            !mem::replace(&mut queue.draining, true)
+        };
+        // Drained on its own task so a caller dropping its future does not strand the queue
+        if start_draining {
+            tokio::spawn(self.clone().drain());
+        }
+        rx.await
+            .unwrap_or_else(|_| Err(Error::GroupCommitFailed("commit task stopped".to_owned())))
+    }
+
+    async fn drain(self) {
+        loop {
+            let (batch, waiters) = {
+                let mut queue = self.queue.lock().unwrap();
+                if queue.waiters.is_empty() {
+                    queue.draining = false;
+                    return;
+                }
+                (mem::take(&mut queue.batch), mem::take(&mut queue.waiters))
+            };
+            let mut store = self.store.write().await;
+            if store.write_batch(&batch, &self.options).await.is_ok() {
+                for waiter in waiters {
+                    let _ = waiter.send(Ok(()));
+                }
+                continue;
+            }
+            // Written again in order, the writes the failed group applied are overwritten with the same values
+            for (op, waiter) in batch.ops().iter().zip(waiters) {
+                let mut write = WriteBatch::new();
+                write.push(op.to_owned());
+                let _ = waiter.send(store.write_batch(&write, &self.options).await);
+            }
+        }
+    }
+}
@@ -0,0 +1,6 @@
+mod group_commit;
+mod write_batch;
+pub use group_commit::GroupCommit;
+pub use write_batch::BatchOp;
+pub use write_batch::BatchProgress;
+pub use write_batch::WriteBatch;
@@ -0,0 +1,219 @@
+use crate::{
+    consts::TOMB_STONE_MARKER,
+    err::Error,
+    types::{Key, Value},
+};
+
+/// A single write recorded in a `WriteBatch`
+#[derive(Clone, Debug, PartialEq)]
+pub enum BatchOp {
+    Put { key: Key, value: Value },
+    Delete { key: Key },
+}
+
+impl BatchOp {
+    pub fn key(&self) -> &Key {
+        match self {
+            BatchOp::Put { key, .. } => key,
+            BatchOp::Delete { key } => key,
+        }
+    }
+
+    /// Returns the value written by the operation, deletes write the tombstone marker
+    pub(crate) fn value(&self) -> &[u8] {
+        match self {
+            BatchOp::Put { value, .. } => value,
+            BatchOp::Delete { .. } => TOMB_STONE_MARKER.as_bytes(),
+        }
+    }
+
+    /// Returns true if the operation writes a tombstone
+    pub(crate) fn is_tombstone(&self) -> bool {
+        matches!(self, BatchOp::Delete { .. })
+    }
+
+    /// Number of key and value bytes written by the operation
+    pub fn size(&self) -> usize {
+        self.key().len() + self.value().len()
+    }
+}
+
+/// Writes collected in memory and applied together by `DataStore::write_batch`
+///
+/// Operations are applied in the order they were added, so a later write to a key wins over an earlier one
+#[derive(Clone, Debug, Default)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+    size: usize,
+
+    /// Number of operations and size of the batch when each savepoint was set, most recent last
+    savepoints: Vec<(usize, usize)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(&mut self, key: impl AsRef<[u8]>, val: impl AsRef<[u8]>) {
+        self.push(BatchOp::Put {
+            key: key.as_ref().to_vec(),
+            value: val.as_ref().to_vec(),
+        });
+    }
+
+    pub fn delete(&mut self, key: impl AsRef<[u8]>) {
+        self.push(BatchOp::Delete {
+            key: key.as_ref().to_vec(),
+        });
+    }
+
+    pub(crate) fn push(&mut self, op: BatchOp) {
+        self.size += op.size();
+        self.ops.push(op);
+    }
+
+    /// Records the current state of the batch so later operations can be undone
+    pub fn set_savepoint(&mut self) {
+        self.savepoints.push((self.ops.len(), self.size));
+    }
+
+    /// Removes every operation added since the most recent savepoint, then removes the savepoint
+    ///
+    /// Returns `NoSavepoint` if no savepoint is set
+    pub fn rollback_to_savepoint(&mut self) -> Result<(), Error> {
+        let (len, size) = self.savepoints.pop().ok_or(Error::NoSavepoint)?;
+        self.ops.truncate(len);
+        self.size = size;
+        Ok(())
+    }
+
+    /// Removes the most recent savepoint and keeps the operations added since
+    ///
+    /// Returns `NoSavepoint` if no savepoint is set
+    pub fn pop_savepoint(&mut self) -> Result<(), Error> {
+        self.savepoints.pop().map(|_| ()).ok_or(Error::NoSavepoint)
+    }
+
+    pub fn ops(&self) -> &[BatchOp] {
+        &self.ops
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Number of key and value bytes written by the batch
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Splits the operations into consecutive chunks of at most `max_size` bytes
+    ///
+    /// An operation bigger than `max_size` gets a chunk of its own
+    pub(crate) fn chunks(&self, max_size: usize) -> Vec<&[BatchOp]> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        let mut chunk_size = 0;
+        for (i, op) in self.ops.iter().enumerate() {
+            if i > start && chunk_size + op.size() > max_size {
+                chunks.push(&self.ops[start..i]);
+                start = i;
+                chunk_size = 0;
+            }
+            chunk_size += op.size();
+        }
+        if start < self.ops.len() {
+            chunks.push(&self.ops[start..]);
+        }
+        chunks
+    }
+}
+
+/// Progress of `DataStore::write_batch_with_progress`, reported after each chunk is written
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchProgress {
+    /// Number of chunks written so far
+    pub chunks_written: usize,
+
+    /// Number of chunks the batch was split into
+    pub total_chunks: usize,
+
+    /// Number of operations written so far
+    pub ops_written: usize,
+
+    /// Number of key and value bytes written so far
+    pub bytes_written: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks_respect_max_size() {
+        let mut batch = WriteBatch::new();
+        for i in 0..10 {
+            batch.put(format!("key_{}", i), "value");
+        }
+        // Each operation is 10 bytes
+        assert_eq!(batch.size(), 100);
+        let chunks = batch.chunks(35);
+        assert_eq!(chunks.iter().map(|c| c.len()).collect::<Vec<_>>(), vec![3, 3, 3, 1]);
+        assert_eq!(chunks.concat(), batch.ops());
+        assert_eq!(batch.chunks(1000).len(), 1);
+    }
+
+    #[test]
+    fn test_oversized_op_gets_own_chunk() {
+        let mut batch = WriteBatch::new();
+        batch.put("a", "1");
+        batch.put("b", "x".repeat(100));
+        batch.delete("c");
+        let chunks = batch.chunks(10);
+        assert_eq!(chunks.iter().map(|c| c.len()).collect::<Vec<_>>(), vec![1, 1, 1]);
+        assert!(WriteBatch::new().chunks(10).is_empty());
+    }
+
+    #[test]
+    fn test_rollback_to_savepoint() {
+        let mut batch = WriteBatch::new();
+        batch.put("a", "1");
+        batch.set_savepoint();
+        batch.put("b", "2");
+        batch.set_savepoint();
+        batch.delete("a");
+        assert_eq!(batch.len(), 3);
+
+        assert!(batch.rollback_to_savepoint().is_ok());
+        assert_eq!(batch.len(), 2);
+        assert!(batch.rollback_to_savepoint().is_ok());
+        assert_eq!(
+            batch.ops(),
+            &[BatchOp::Put {
+                key: b"a".to_vec(),
+                value: b"1".to_vec()
+            }]
+        );
+        assert_eq!(batch.size(), 2);
+        assert!(matches!(batch.rollback_to_savepoint(), Err(Error::NoSavepoint)));
+    }
+
+    #[test]
+    fn test_pop_savepoint() {
+        let mut batch = WriteBatch::new();
+        batch.set_savepoint();
+        batch.put("a", "1");
+        batch.set_savepoint();
+        batch.put("b", "2");
+        assert!(batch.pop_savepoint().is_ok());
+        // Rolls back to the first savepoint since the second one was popped
+        assert!(batch.rollback_to_savepoint().is_ok());
+        assert!(batch.is_empty());
+        assert!(matches!(batch.pop_savepoint(), Err(Error::NoSavepoint)));
+    }
+}
@@ -37,6 +37,19 @@
 //! |   | | (1 bytes, little- |  |     |   
 //! |   | |   endian format)  |  |     |    
 //! |   | +-------------------+  |     |
+//! |   | |   Expires At      |  |     |
+//! |   | | (8 bytes, only if |  |     |
+//! |   | |  expiry is set)   |  |     |
+//! |   | +-------------------+  |     |
+//! |   | |   Written At      |  |     |
+//! |   | | (8 bytes, little- |  |     |
+//! |   | |  endian format)   |  |     |
+//! |   | +-------------------+  |     |
+//! |   | |   Value           |  |     |
+//! |   | | (4-byte length    |  |     |
+//! |   | |  and bytes, only  |  |     |
+//! |   | |  if inlined)      |  |     |
+//! |   | +-------------------+  |     |
 //! |   +------------------------+     |
 //! |   |   Entry 2              |     |
 //! |   |       ...              |     |
@@ -53,20 +66,44 @@
 //! 1. Length Prefix: A 4-byte length prefix in little-endian format, indicating the length of the key.
 //! 2. Key: Variable-length key bytes.
 //! 3. Value Offset: A 4-byte length prefix in little-endian format, indicating the position of the value in the value log
-//! 4. Creation Date: A 8-byte length prefix in little-endian format, indicating the time the insertion was made
-//! 5. Is Tombstone: A 1-byte length prefix in little-endian format, indicating if the key has been deleted or not
+//! 4. Creation Date: A 8-byte length prefix in little-endian format, the sequence number of the insertion
+//! 5. Is Tombstone: A 1-byte flags field, bit 0 indicates if the key has been deleted, bit 1 if an expiry time follows,
+//!    bits 2-3 the class of the value log holding the value, see `ValueClass`, bit 4 if the value follows and bit 5
+//!    if the write time follows
+//! 6. Expires At: An optional 8-byte field in little-endian format, indicating the time after which the entry is treated as deleted
+//! 7. Written At: An 8-byte field in little-endian format, the wall-clock time the insertion was made at. Entries
+//!    written before it was recorded do not have it, their creation date stands in for it
+//! 8. Value: An optional copy of a value shorter than `Config::inline_value_threshold`, prefixed by its 4-byte length.
+//!    Reads find it without a second seek in the value log
 //!
 //! The block's entries vector (`entries`) stores these entries sequentially. Each entry follows the format mentioned above, and they are concatenated one after another within the entries vector.
 //!
+//! On disk the serialized entries are preceded by their total length (4 bytes) and followed by a checksum of the length
+//! and the entries, so that a corrupted block is detected when it is read back. The checksum is a 4 byte CRC32C or an
+//! 8 byte XXH64, as recorded in the header of the data file.
+//!
+//! The entries are compressed with `Config::sstable_compression` when that makes the block shorter. The length then
+//! has `BLOCK_COMPRESSED_FLAG` set and counts the compressed bytes, which start with the byte of the algorithm, see
+//! `CompressionType`. The checksum covers the bytes as stored, so a corrupted block is detected before it is
+//! decompressed. Blocks written uncompressed, or before the config changed, remain readable.
+//!
 // NOTE: For creation time while a 32-bit integer can technically hold milliseconds, the usable range is limited,
 // making it unsuitable for long-term timekeeping applications. For those scenarios, 64-bit(8 byte) integers are typically used.
 
 use err::Error::*;
 
 use crate::{
-    consts::{SIZE_OF_U32, SIZE_OF_U64, SIZE_OF_U8},
+    checksum::ChecksumType,
+    compression::CompressionType,
+    consts::{
+        BLOCK_COMPRESSED_FLAG, EXPIRY_FLAG, INLINE_VALUE_FLAG, SIZE_OF_U32, SIZE_OF_U64, SIZE_OF_U8, TOMBSTONE_FLAG,
+        VALUE_CLASS_MASK, VALUE_CLASS_SHIFT, WRITTEN_AT_FLAG,
+    },
     err::{self, Error},
-    fs::{FileAsync, FileNode},
+    fs::{encode_flags, flags_len, FileAsync, FileNode},
+    memtable::Entry,
+    types::{ExpiresAt, Key, ValOffset, Value, WriteTime},
+    value_log::ValueClass,
 };
 type BytesWritten = usize;
 const BLOCK_SIZE: usize = 4 * 1024; // 4KB
@@ -83,9 +120,25 @@ pub struct BlockEntry {
     pub key_prefix: u32,
     pub key: Vec<u8>,
     pub value_offset: u32,
+    pub class: ValueClass,
     pub creation_date: u64,
+
+    /// Wall-clock time the entry was written at, see `Entry::written_at`
+    pub written_at: WriteTime,
     pub is_tombstone: bool,
+    pub expires_at: ExpiresAt,
+
+    /// Copy of the value stored at `value_offset`, see `Entry::inline`
+    pub inline: Option<Value>,
+}
+
+impl BlockEntry {
+    /// Returns the location of the value, in the value log of its class
+    pub fn location(&self) -> ValOffset {
+        self.class.location(self.value_offset as ValOffset)
+    }
 }
+
 impl Block {
     /// Creates a new empty Block.
     pub fn new() -> Self {
@@ -100,31 +153,27 @@ impl Block {
         self.entries[self.entries.len() - 1].to_owned()
     }
 
-    /// Sets an entry with the provided key and value offset in the Block.
+    /// Sets an entry with the key, value location and metadata of `entry` in the Block.
     ///
     /// Returns an `Result` indicating success or failure. An error is returned if the Block
     /// is already full and cannot accommodate the new entry.
-    pub fn set_entry(
-        &mut self,
-        key_prefix: u32,
-        key: Vec<u8>,
-        value_offset: u32,
-        creation_date: u64,
-        is_tombstone: bool,
-    ) -> Result<(), Error> {
-        // Key + Key Prefix + Value Offset +  Creation Date + Tombstone Marker
-        let entry_size = key.len() + SIZE_OF_U32 + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8;
+    pub fn set_entry(&mut self, entry: Entry<Key, ValOffset>) -> Result<(), Error> {
+        let entry_size = Block::entry_size(entry.key.len(), entry.expires_at, entry.inline.as_ref());
 
         if self.is_full(entry_size) {
             return Err(Error::BlockIsFullError);
         }
 
         let entry = BlockEntry {
-            key,
-            key_prefix,
-            creation_date,
-            is_tombstone,
-            value_offset,
+            key_prefix: entry.key.len() as u32,
+            key: entry.key,
+            creation_date: entry.created_at,
+            written_at: entry.written_at,
+            is_tombstone: entry.is_tombstone,
+            value_offset: ValueClass::offset(entry.val_offset) as u32,
+            class: ValueClass::of(entry.val_offset),
+            expires_at: entry.expires_at,
+            inline: entry.inline,
         };
         self.entries.push(entry);
         self.size += entry_size;
@@ -133,17 +182,130 @@ impl Block {
         Ok(())
     }
 
-    /// Writes entries in the block to the sstable file
+    /// Returns the number of bytes an entry occupies in a block
+    pub(crate) fn entry_size(key_len: usize, expires_at: ExpiresAt, inline: Option<&Value>) -> usize {
+        // Key + Key Prefix + Value Offset +  Creation Date + Tombstone Marker + Expiry + Write Time + Value
+        key_len
+            + SIZE_OF_U32
+            + SIZE_OF_U32
+            + SIZE_OF_U64
+            + flags_len(expires_at)
+            + SIZE_OF_U64
+            + inline.map_or(0, |inline| SIZE_OF_U32 + inline.len())
+    }
+
+    /// Writes entries in the block to the sstable file, compressed with `compression` if that makes them shorter
     ///
     /// Returns an `Result` indicating success or failure. An error is returned if write fails
-    pub async fn write_to_file(&self, file: FileNode) -> Result<BytesWritten, Error> {
-        let mut bytes_written = 0;
+    pub async fn write_to_file(
+        &self,
+        file: FileNode,
+        checksum_type: ChecksumType,
+        compression: CompressionType,
+    ) -> Result<BytesWritten, Error> {
+        let mut entries = Vec::with_capacity(self.size);
         for entry in &self.entries {
-            let serialized_entry = self.serialize(entry)?;
-            file.write_all(&serialized_entry).await?;
-            bytes_written+=serialized_entry.len();
+            entries.extend_from_slice(&self.serialize(entry)?);
         }
-        Ok(bytes_written)
+        let (stored, len) = match compression.compress(&entries) {
+            Some(compressed) => {
+                let len = compressed.len() as u32 | BLOCK_COMPRESSED_FLAG;
+                (compressed, len)
+            }
+            None => {
+                let len = entries.len() as u32;
+                (entries, len)
+            }
+        };
+        let mut block = Vec::with_capacity(SIZE_OF_U32 + stored.len() + checksum_type.size());
+        block.extend_from_slice(&len.to_le_bytes());
+        block.extend_from_slice(&stored);
+        let checksum = checksum_type.checksum(&block);
+        block.extend_from_slice(&checksum);
+        file.write_all(&block).await?;
+        Ok(block.len())
+    }
+
+    /// Returns the number of bytes stored for a block and whether they are compressed, read from the length in
+    /// front of the block
+    pub(crate) fn stored_len(len_bytes: [u8; SIZE_OF_U32]) -> (usize, bool) {
+        let len = u32::from_le_bytes(len_bytes);
+        (
+            (len & !BLOCK_COMPRESSED_FLAG) as usize,
+            len & BLOCK_COMPRESSED_FLAG != 0,
+        )
+    }
+
+    /// Parses the bytes stored for a block between its length and its checksum, decompressing them first if
+    /// `is_compressed` is set
+    pub(crate) fn decode(stored: &[u8], is_compressed: bool) -> Result<Vec<BlockEntry>, Error> {
+        if !is_compressed {
+            return Block::deserialize(stored);
+        }
+        let entries = CompressionType::decompress(stored).ok_or(SerializationError("Block cannot be decompressed"))?;
+        Block::deserialize(&entries)
+    }
+
+    /// Parses the serialized entries of a block, as read between its length and its checksum
+    ///
+    /// Returns `Ok(entries)` or an error if an entry ends past the end of `bytes`
+    pub(crate) fn deserialize(bytes: &[u8]) -> Result<Vec<BlockEntry>, Error> {
+        let mut entries = Vec::new();
+        let mut read = 0;
+        while read < bytes.len() {
+            let key_prefix = u32::from_le_bytes(Self::take::<SIZE_OF_U32>(bytes, &mut read)?);
+            let key = bytes
+                .get(read..read + key_prefix as usize)
+                .ok_or(SerializationError("Block entry is truncated"))?
+                .to_vec();
+            read += key.len();
+            let value_offset = u32::from_le_bytes(Self::take::<SIZE_OF_U32>(bytes, &mut read)?);
+            let creation_date = u64::from_le_bytes(Self::take::<SIZE_OF_U64>(bytes, &mut read)?);
+            let [flags] = Self::take::<SIZE_OF_U8>(bytes, &mut read)?;
+            let expires_at = if flags & EXPIRY_FLAG != 0 {
+                Some(u64::from_le_bytes(Self::take::<SIZE_OF_U64>(bytes, &mut read)?))
+            } else {
+                None
+            };
+            let written_at = if flags & WRITTEN_AT_FLAG != 0 {
+                u64::from_le_bytes(Self::take::<SIZE_OF_U64>(bytes, &mut read)?)
+            } else {
+                creation_date
+            };
+            let inline = if flags & INLINE_VALUE_FLAG != 0 {
+                let len = u32::from_le_bytes(Self::take::<SIZE_OF_U32>(bytes, &mut read)?) as usize;
+                let inline = bytes
+                    .get(read..read + len)
+                    .ok_or(SerializationError("Block entry is truncated"))?
+                    .to_vec();
+                read += len;
+                Some(inline)
+            } else {
+                None
+            };
+            entries.push(BlockEntry {
+                key_prefix,
+                key,
+                value_offset,
+                class: ValueClass::from_bits((flags & VALUE_CLASS_MASK) >> VALUE_CLASS_SHIFT)
+                    .ok_or(SerializationError("Block entry has an unknown value class"))?,
+                creation_date,
+                written_at,
+                is_tombstone: flags & TOMBSTONE_FLAG != 0,
+                expires_at,
+                inline,
+            });
+        }
+        Ok(entries)
+    }
+
+    // Reads the `N` bytes at `read` and moves past them
+    fn take<const N: usize>(bytes: &[u8], read: &mut usize) -> Result<[u8; N], Error> {
+        let field = bytes
+            .get(*read..*read + N)
+            .ok_or(SerializationError("Block entry is truncated"))?;
+        *read += N;
+        Ok(field.try_into().unwrap())
     }
 
     /// Checks if the Block is full given the size of an entry.
@@ -161,13 +323,24 @@ impl Block {
     ///
     /// Returns `Ok(entry_vec)`or Error if not
     pub(crate) fn serialize(&self, entry: &BlockEntry) -> Result<Vec<u8>, Error> {
-        let entry_len = entry.key.len() + SIZE_OF_U32 + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8;
+        let entry_len = Block::entry_size(entry.key.len(), entry.expires_at, entry.inline.as_ref());
         let mut entry_vec = Vec::with_capacity(entry_len);
         entry_vec.extend_from_slice(&(entry.key_prefix).to_le_bytes());
         entry_vec.extend_from_slice(&entry.key);
         entry_vec.extend_from_slice(&(entry.value_offset as u32).to_le_bytes());
         entry_vec.extend_from_slice(&entry.creation_date.to_le_bytes());
-        entry_vec.push(entry.is_tombstone as u8);
+        let mut flags = encode_flags(entry.is_tombstone, entry.expires_at);
+        flags[0] |= entry.class.bits() << VALUE_CLASS_SHIFT;
+        flags[0] |= WRITTEN_AT_FLAG;
+        if entry.inline.is_some() {
+            flags[0] |= INLINE_VALUE_FLAG;
+        }
+        entry_vec.extend_from_slice(&flags);
+        entry_vec.extend_from_slice(&entry.written_at.to_le_bytes());
+        if let Some(inline) = &entry.inline {
+            entry_vec.extend_from_slice(&(inline.len() as u32).to_le_bytes());
+            entry_vec.extend_from_slice(inline);
+        }
         if entry_len != entry_vec.len() {
             return Err(SerializationError("Invalid input"));
         }
@@ -212,17 +385,11 @@ mod tests {
     fn test_set_entry() {
         let mut block = Block::new();
         let key: Vec<u8> = vec![1, 2, 3];
-        let value_offset: u32 = 1000;
+        let value_offset: ValOffset = 1000;
         let creation_date: u64 = 16345454545;
         let is_tombstone: bool = false;
 
-        let res = block.set_entry(
-            key.len() as u32,
-            key.to_owned(),
-            value_offset,
-            creation_date,
-            is_tombstone,
-        );
+        let res = block.set_entry(Entry::new(key.to_owned(), value_offset, creation_date, is_tombstone));
         // check if we have Error.
         assert!(res.is_ok());
 
@@ -231,7 +398,7 @@ mod tests {
 
         assert_eq!(
             block.size,
-            key.len() + SIZE_OF_U32 + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8
+            key.len() + SIZE_OF_U32 + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8 + SIZE_OF_U64
         );
     }
 
@@ -239,48 +406,87 @@ mod tests {
     fn test_serialize() {
         let block = Block::new();
         let key: Vec<u8> = vec![1, 2, 3];
-        let value_offset: u32 = 1000;
+        let value_offset: ValOffset = 1000;
         let creation_date: u64 = 16345454545;
         let is_tombstone: bool = false;
 
         let entry = BlockEntry {
             key_prefix: key.len() as u32,
             key: key.clone(),
-            value_offset,
+            value_offset: value_offset as u32,
+            class: ValueClass::Small,
             creation_date,
+            written_at: creation_date,
             is_tombstone,
+            expires_at: None,
+            inline: None,
         };
         let res = block.serialize(&entry);
         // check if we have Error.
         assert!(res.is_ok());
         assert_eq!(
             res.unwrap().len(),
-            key.len() + SIZE_OF_U32 + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8
+            key.len() + SIZE_OF_U32 + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8 + SIZE_OF_U64
+        );
+    }
+
+    #[test]
+    fn test_serialize_with_expiry() {
+        let block = Block::new();
+        let key: Vec<u8> = vec![1, 2, 3];
+        let expires_at: u64 = 16345464545;
+
+        let entry = BlockEntry {
+            key_prefix: key.len() as u32,
+            key: key.clone(),
+            value_offset: 1000,
+            class: ValueClass::Small,
+            creation_date: 16345454545,
+            written_at: 16345454546,
+            is_tombstone: false,
+            expires_at: Some(expires_at),
+            inline: None,
+        };
+        let serialized = block.serialize(&entry).unwrap();
+        assert_eq!(
+            serialized.len(),
+            key.len() + SIZE_OF_U32 + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8 + SIZE_OF_U64 + SIZE_OF_U64
+        );
+        let flags_at = serialized.len() - SIZE_OF_U64 * 2 - SIZE_OF_U8;
+        assert_eq!(serialized[flags_at], EXPIRY_FLAG | WRITTEN_AT_FLAG);
+        assert_eq!(
+            &serialized[flags_at + SIZE_OF_U8..flags_at + SIZE_OF_U8 + SIZE_OF_U64],
+            &expires_at.to_le_bytes()
+        );
+        assert_eq!(
+            &serialized[serialized.len() - SIZE_OF_U64..],
+            &16345454546u64.to_le_bytes()
         );
+
+        // Entries written before the write time was recorded fall back to their creation date
+        let mut legacy = serialized[..flags_at + SIZE_OF_U8 + SIZE_OF_U64].to_vec();
+        legacy[flags_at] = EXPIRY_FLAG;
+        let entries = Block::deserialize(&legacy).unwrap();
+        assert_eq!(entries[0].written_at, 16345454545);
+        assert_eq!(entries[0].expires_at, Some(expires_at));
     }
 
     #[tokio::test]
     async fn test_write_to_file() {
         let mut block = Block::new();
         let key: Vec<u8> = vec![1, 2, 3];
-        let value_offset: u32 = 1000;
+        let value_offset: ValOffset = 1000;
         let creation_date: u64 = 16345454545;
         let is_tombstone: bool = false;
 
-        let res = block.set_entry(
-            key.len() as u32,
-            key.to_owned(),
-            value_offset,
-            creation_date,
-            is_tombstone,
-        );
+        let res = block.set_entry(Entry::new(key.to_owned(), value_offset, creation_date, is_tombstone));
         // check if we have Error.
         assert!(res.is_ok());
         assert_eq!(block.entries.len(), 1);
         assert_eq!(block.entry_count, 1);
         assert_eq!(
             block.size,
-            key.len() + SIZE_OF_U32 + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8
+            key.len() + SIZE_OF_U32 + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8 + SIZE_OF_U64
         );
         let temp_file = NamedTempFile::new().unwrap();
         let temp_file_path = temp_file.path().to_path_buf();
@@ -293,25 +499,99 @@ mod tests {
             file: Arc::new(RwLock::new(tokio_file)),
             file_type: crate::fs::FileType::Data,
         };
-        let write_res = block.write_to_file(file.clone()).await;
+        let write_res = block
+            .write_to_file(file.clone(), ChecksumType::Crc32c, CompressionType::None)
+            .await;
         assert!(write_res.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_write_to_file_round_trip() {
+        let mut block = Block::new();
+        for (i, key) in [b"key_1", b"key_2"].iter().enumerate() {
+            let entry = Entry::new(key.to_vec(), ValueClass::Large.location(i), 16345454545, i == 1)
+                .with_written_at(16345454546)
+                .with_expiry(Some(16345464545))
+                .with_inline(Some(key.repeat(i + 1)));
+            block.set_entry(entry).unwrap();
+        }
+        let temp_file = NamedTempFile::new().unwrap();
+        let temp_file_path = temp_file.path().to_path_buf();
+        let file = FileNode {
+            file_path: temp_file_path.to_owned(),
+            file: Arc::new(RwLock::new(File::from_std(temp_file.reopen().unwrap()))),
+            file_type: crate::fs::FileType::Data,
+        };
+        let bytes_written = block
+            .write_to_file(file.clone(), ChecksumType::XxHash64, CompressionType::None)
+            .await
+            .unwrap();
+        file.sync_all().await.unwrap();
+
+        let bytes = fs::read(&temp_file_path).unwrap();
+        assert_eq!(bytes.len(), bytes_written);
+        assert_eq!(bytes.len(), SIZE_OF_U32 + block.size + SIZE_OF_U64);
+        let (block_bytes, checksum) = bytes.split_at(bytes.len() - SIZE_OF_U64);
+        assert_eq!(ChecksumType::XxHash64.checksum(block_bytes), checksum);
+
+        let entries = Block::deserialize(&block_bytes[SIZE_OF_U32..]).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].key, b"key_2".to_vec());
+        assert_eq!(entries[1].value_offset, 1);
+        assert_eq!(entries[1].class, ValueClass::Large);
+        assert_eq!(entries[1].location(), ValueClass::Large.location(1));
+        assert!(entries[1].is_tombstone);
+        assert_eq!(entries[1].expires_at, Some(16345464545));
+        assert_eq!(entries[1].written_at, 16345454546);
+        assert_eq!(entries[1].inline, Some(b"key_2key_2".to_vec()));
+        assert_eq!(entries[0].inline, Some(b"key_1".to_vec()));
+        assert!(Block::deserialize(&block_bytes[SIZE_OF_U32..block_bytes.len() - 1]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_write_compressed_block() {
+        let mut block = Block::new();
+        for i in 0..50 {
+            let entry = Entry::new(format!("key_{:04}", i).into_bytes(), i * 100, 16345454545, false);
+            block.set_entry(entry).unwrap();
+        }
+        for compression in [CompressionType::Lz4, CompressionType::Snappy] {
+            let temp_file = NamedTempFile::new().unwrap();
+            let file = FileNode {
+                file_path: temp_file.path().to_path_buf(),
+                file: Arc::new(RwLock::new(File::from_std(temp_file.reopen().unwrap()))),
+                file_type: crate::fs::FileType::Data,
+            };
+            let bytes_written = block
+                .write_to_file(file.clone(), ChecksumType::Crc32c, compression)
+                .await
+                .unwrap();
+            file.sync_all().await.unwrap();
+            assert!(bytes_written < SIZE_OF_U32 + block.size + SIZE_OF_U32);
+
+            let bytes = fs::read(temp_file.path()).unwrap();
+            let (len, is_compressed) = Block::stored_len(bytes[..SIZE_OF_U32].try_into().unwrap());
+            assert!(is_compressed);
+            assert_eq!(len, bytes_written - SIZE_OF_U32 - SIZE_OF_U32);
+            let stored = &bytes[SIZE_OF_U32..SIZE_OF_U32 + len];
+            assert_eq!(stored[0], compression.as_byte());
+            let entries = Block::decode(stored, is_compressed).unwrap();
+            assert_eq!(entries.len(), 50);
+            assert_eq!(entries[49].key, b"key_0049".to_vec());
+            assert_eq!(entries[49].value_offset, 4900);
+            assert!(Block::decode(&stored[..len - 1], is_compressed).is_err());
+        }
+    }
+
     #[test]
     fn test_get_entry() {
         let mut block = Block::new();
         let key: Vec<u8> = vec![1, 2, 3];
-        let value_offset: u32 = 1000;
+        let value_offset: ValOffset = 1000;
         let creation_date: u64 = 16345454545;
         let is_tombstone: bool = false;
 
-        let res = block.set_entry(
-            key.len() as u32,
-            key.to_owned(),
-            value_offset,
-            creation_date,
-            is_tombstone,
-        );
+        let res = block.set_entry(Entry::new(key.to_owned(), value_offset, creation_date, is_tombstone));
         assert!(res.is_ok());
         let entry = block.get_entry(&key);
         assert!(entry.is_some());
@@ -332,35 +612,23 @@ mod tests {
         // Test case to check setting an entry when the block is already full
         let mut block = Block::new();
         let key: Vec<u8> = vec![1, 2, 3];
-        let value_offset: u32 = 1000;
+        let value_offset: ValOffset = 1000;
         let creation_date: u64 = 16345454545;
         let is_tombstone: bool = false;
 
         // Fill the block to its maximum capacity
-        while !block.is_full(key.len() + SIZE_OF_U32 + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8) {
+        while !block.is_full(key.len() + SIZE_OF_U32 + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8 + SIZE_OF_U64) {
             block
-                .set_entry(
-                    key.len() as u32,
-                    key.to_owned(),
-                    value_offset,
-                    creation_date,
-                    is_tombstone,
-                )
+                .set_entry(Entry::new(key.to_owned(), value_offset, creation_date, is_tombstone))
                 .unwrap();
         }
 
         // Attempt to set a new entry, which should result in an error
-        let res = block.set_entry(
-            key.len() as u32,
-            key.to_owned(),
-            value_offset,
-            creation_date,
-            is_tombstone,
-        );
+        let res = block.set_entry(Entry::new(key.to_owned(), value_offset, creation_date, is_tombstone));
         assert!(res.is_err());
         assert_eq!(
             block.get_entry_count(),
-            BLOCK_SIZE / (key.len() + SIZE_OF_U32 + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8)
+            BLOCK_SIZE / (key.len() + SIZE_OF_U32 + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8 + SIZE_OF_U64)
         );
     }
 }
@@ -1 +1,215 @@
-// TODO: Implement block cache
\ No newline at end of file
+//! # Block cache
+//!
+//! Every SSTable lookup reads and decodes the block the index points to, even for the hottest keys. `BlockCache`
+//! keeps the decoded blocks last read by gets and scans in memory, up to a budget of `Config::block_cache_size`
+//! bytes shared by all the SSTables of the store, and evicts the least recently used block to make room for a new
+//! one.
+//!
+//! Blocks are identified by the path of their data file and their offset in it. SSTables are never rewritten in
+//! place and their file numbers are never reused, so a cached block cannot go stale. The blocks of SSTables deleted
+//! by compaction are not looked up again and are evicted as new blocks are inserted.
+//!
+//! Only blocks whose checksum was verified are cached, so a read verifying checksums never returns a block that
+//! was read without verifying it. Compaction, garbage collection and recovery read without the cache, they would
+//! evict the blocks of the hot keys for blocks read once.
+
+use crate::{
+    block::{Block, BlockEntry},
+    types::NoBytesRead,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+type BlockKey = (PathBuf, usize);
+
+/// Least recently used blocks of the SSTables, clones share the same blocks and budget
+#[derive(Debug, Clone)]
+pub struct BlockCache {
+    // Bytes of decoded entries kept at most, 0 disables the cache
+    capacity: usize,
+
+    state: Arc<Mutex<CacheState>>,
+
+    // Lookups that found their block in the cache
+    hits: Arc<AtomicU64>,
+
+    // Lookups that read their block from the file
+    misses: Arc<AtomicU64>,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    blocks: HashMap<BlockKey, CachedBlock>,
+
+    // Blocks from the least to the most recently used, by the tick of their last use
+    recency: BTreeMap<u64, BlockKey>,
+
+    // Incremented on every use of a block
+    tick: u64,
+
+    // Bytes charged for the blocks cached
+    size: usize,
+}
+
+#[derive(Debug)]
+struct CachedBlock {
+    entries: Arc<Vec<BlockEntry>>,
+
+    // Bytes the block takes in the file, the offset of the next block follows
+    len: NoBytesRead,
+
+    // Bytes charged against the budget, the size of the entries decoded
+    charge: usize,
+
+    last_used: u64,
+}
+
+impl BlockCache {
+    /// Creates a cache keeping at most `capacity` bytes of blocks, 0 caches nothing
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Arc::new(Mutex::new(CacheState::default())),
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Returns the entries of the block at `offset` of the data file at `path` along with the bytes it takes in the
+    /// file, `None` if it is not cached
+    pub(crate) fn get(&self, path: &Path, offset: usize) -> Option<(Arc<Vec<BlockEntry>>, NoBytesRead)> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        state.tick += 1;
+        let key = (path.to_path_buf(), offset);
+        let Some(block) = state.blocks.get_mut(&key) else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        state.recency.remove(&block.last_used);
+        state.recency.insert(state.tick, key);
+        block.last_used = state.tick;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some((block.entries.clone(), block.len))
+    }
+
+    /// Caches the entries of the block at `offset` of the data file at `path`, which takes `len` bytes in the file,
+    /// evicting the least recently used blocks to stay within the budget
+    ///
+    /// A block bigger than the whole budget is not cached.
+    pub(crate) fn insert(&self, path: &Path, offset: usize, entries: Arc<Vec<BlockEntry>>, len: NoBytesRead) {
+        let charge: usize = entries
+            .iter()
+            .map(|entry| Block::entry_size(entry.key.len(), entry.expires_at, entry.inline.as_ref()))
+            .sum();
+        if charge > self.capacity {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let key = (path.to_path_buf(), offset);
+        if let Some(replaced) = state.blocks.remove(&key) {
+            state.recency.remove(&replaced.last_used);
+            state.size -= replaced.charge;
+        }
+        while state.size + charge > self.capacity {
+            let Some((_, evicted)) = state.recency.pop_first() else {
+                break;
+            };
+            if let Some(evicted) = state.blocks.remove(&evicted) {
+                state.size -= evicted.charge;
+            }
+        }
+        state.tick += 1;
+        state.recency.insert(state.tick, key.to_owned());
+        state.blocks.insert(
+            key,
+            CachedBlock {
+                entries,
+                len,
+                charge,
+                last_used: state.tick,
+            },
+        );
+        state.size += charge;
+    }
+
+    /// Returns the bytes of blocks the cache keeps at most
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the bytes charged for the blocks cached
+    pub fn size(&self) -> usize {
+        self.state.lock().unwrap().size
+    }
+
+    /// Returns the number of lookups that found their block in the cache since the store was opened
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of lookups that read their block from the file since the store was opened
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memtable::Entry;
+
+    fn block(keys: &[&str]) -> Arc<Vec<BlockEntry>> {
+        let mut block = Block::new();
+        for key in keys {
+            block
+                .set_entry(Entry::new(key.as_bytes().to_vec(), 10, 1000, false))
+                .unwrap();
+        }
+        Arc::new(block.entries)
+    }
+
+    #[test]
+    fn test_block_cache_evicts_least_recently_used() {
+        let charge = Block::entry_size(4, None, None);
+        let cache = BlockCache::new(2 * charge);
+        let path = Path::new("data_000001.db");
+        assert!(cache.get(path, 1).is_none());
+        cache.insert(path, 1, block(&["key1"]), 40);
+        cache.insert(path, 41, block(&["key2"]), 40);
+        assert_eq!(cache.size(), 2 * charge);
+        let (entries, len) = cache.get(path, 1).unwrap();
+        assert_eq!(entries[0].key, b"key1".to_vec());
+        assert_eq!(len, 40);
+
+        // The block at 41 is the least recently used since the block at 1 was read again
+        cache.insert(path, 81, block(&["key3"]), 40);
+        assert_eq!(cache.size(), 2 * charge);
+        assert!(cache.get(path, 41).is_none());
+        assert!(cache.get(path, 1).is_some());
+        assert!(cache.get(path, 81).is_some());
+        assert!(cache.get(Path::new("data_000002.db"), 1).is_none());
+        assert_eq!(cache.hits(), 3);
+        assert_eq!(cache.misses(), 3);
+
+        // A block bigger than the budget is not cached
+        cache.insert(path, 121, block(&["key4", "key5", "key6"]), 120);
+        assert!(cache.get(path, 121).is_none());
+        assert_eq!(cache.size(), 2 * charge);
+
+        let cache = BlockCache::new(0);
+        cache.insert(path, 1, block(&["key1"]), 40);
+        assert!(cache.get(path, 1).is_none());
+        assert_eq!(cache.misses(), 0);
+    }
+}
@@ -1,3 +1,6 @@
 mod block;
+mod cache;
 
 pub use block::Block;
+pub use block::BlockEntry;
+pub use cache::BlockCache;
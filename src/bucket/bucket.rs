@@ -1,18 +1,20 @@
-use crate::consts::{BUCKET_DIRECTORY_PREFIX, BUCKET_HIGH, BUCKET_LOW, MAX_TRESHOLD, MIN_SSTABLE_SIZE, MIN_TRESHOLD};
+use crate::bucket::{BucketFallback, BucketPolicy, CompressionPolicy};
+use crate::checksum::ChecksumType;
+use crate::consts::BUCKET_DIRECTORY_PREFIX;
 use crate::err::Error;
 use crate::fs::{FileAsync, FileNode};
-use crate::sst::Table;
+use crate::meta::{FileNumbers, Manifest};
+use crate::sst::{Table, TablePins};
 use crate::types::{Bool, Key, SkipMapEntries};
-use chrono::Utc;
 use indexmap::IndexMap;
 use std::fmt::Debug;
-use std::{path::PathBuf, sync::Arc};
+use std::{cmp, path::PathBuf, sync::Arc};
 use tokio::fs;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 use Error::*;
 
-static SST_PREFIX: &str = "sstable";
+pub(crate) static SST_PREFIX: &str = "sstable";
 pub type SSTablesToRemove = Vec<(BucketID, Vec<Table>)>;
 pub type BucketsToCompact = Result<(Vec<Bucket>, SSTablesToRemove), Error>;
 pub type BucketID = Uuid;
@@ -21,6 +23,31 @@ pub type BucketID = Uuid;
 pub struct BucketMap {
     pub dir: PathBuf,
     pub buckets: IndexMap<BucketID, Bucket>,
+
+    /// SSTables currently read by iterators, their files are kept until released
+    pub(crate) pins: TablePins,
+
+    /// Persisted list of the SSTables in the buckets, updated with every SSTable added or deleted
+    pub(crate) manifest: Option<Manifest>,
+
+    /// Allocator of the numbers naming new bucket and SSTable directories
+    pub(crate) file_numbers: FileNumbers,
+
+    /// Algorithm checksumming the blocks of new SSTables
+    pub(crate) checksum_type: ChecksumType,
+
+    /// Algorithms compressing the blocks of new SSTables, depending on their size
+    pub(crate) compression: CompressionPolicy,
+
+    /// Share of dead entries from which a bucket of fewer than `min_sstables_per_merge` SSTables is compacted,
+    /// 0 disables it
+    pub(crate) tombstone_compaction_ratio: f64,
+
+    /// Sizes of the SSTables sharing a bucket and number of SSTables merged at once
+    pub(crate) policy: BucketPolicy,
+
+    /// True while gets read many SSTables, buckets of at least two SSTables are then compacted
+    pub(crate) read_heavy: bool,
 }
 #[derive(Debug, Clone)]
 pub struct Bucket {
@@ -39,9 +66,10 @@ pub trait InsertableToBucket: Debug + Send + Sync {
 }
 
 impl Bucket {
-    pub async fn new(dir: PathBuf) -> Self {
+    /// Creates an empty bucket in a directory of `dir` named after `file_number`
+    pub async fn new(dir: PathBuf, file_number: u64) -> Self {
         let id = Uuid::new_v4();
-        let dir = dir.join(BUCKET_DIRECTORY_PREFIX.to_string() + id.to_string().as_str());
+        let dir = dir.join(format!("{}_{:06}", BUCKET_DIRECTORY_PREFIX, file_number));
         let _ = FileNode::create_dir_all(dir.to_owned()).await;
         Self {
             id,
@@ -86,29 +114,57 @@ impl Bucket {
         Ok(all_sstable_size / sst.len() as u64 as usize)
     }
 
-    pub fn fits_into_bucket<T: InsertableToBucket + ?Sized>(&mut self, table: Arc<Box<T>>) -> Bool {
-        (self.avarage_size as f64 * BUCKET_LOW < table.size() as f64)
-            && (table.size() < (self.avarage_size as f64 * BUCKET_HIGH) as usize)
-            || (table.size() < MIN_SSTABLE_SIZE && self.avarage_size < MIN_SSTABLE_SIZE)
+    pub fn fits_into_bucket<T: InsertableToBucket + ?Sized>(
+        &mut self,
+        table: Arc<Box<T>>,
+        policy: &BucketPolicy,
+    ) -> Bool {
+        policy.fits(self.avarage_size, table.size())
     }
 
-    pub(crate) async fn extract_sstables(&self) -> Result<(Vec<Table>, usize), Error> {
-        if self.sstables.read().await.len() < MIN_TRESHOLD {
+    pub(crate) async fn extract_sstables(&self, policy: &BucketPolicy) -> Result<(Vec<Table>, usize), Error> {
+        if self.sstables.read().await.len() < policy.min_sstables_per_merge {
             return Ok((vec![], 0));
         }
-        let extracted_sstables = self
-            .sstables
-            .read()
-            .await
-            .get(0..MAX_TRESHOLD)
-            .unwrap_or(&self.sstables.read().await.clone())
-            .to_vec();
+        self.select_sstables(policy).await
+    }
+
+    // Returns at most `max_sstables_per_merge` SSTables to compact, the ones holding the most dead entries, with
+    // their average size
+    async fn select_sstables(&self, policy: &BucketPolicy) -> Result<(Vec<Table>, usize), Error> {
+        let sstables = self.sstables.read().await;
+        let mut selected: Vec<usize> = (0..sstables.len()).collect();
+        // Stable so that SSTables holding as many dead entries are picked oldest first
+        selected.sort_by_key(|&i| cmp::Reverse(sstables[i].properties.dead_entries()));
+        selected.truncate(policy.max_sstables_per_merge);
+        // Merged in the order they were added to the bucket
+        selected.sort();
+        let extracted_sstables: Vec<Table> = selected.into_iter().map(|i| sstables[i].to_owned()).collect();
+        drop(sstables);
         let average = Bucket::cal_average_size(extracted_sstables.clone()).await?;
         Ok((extracted_sstables, average))
     }
 
-    pub async fn sstable_count_exceeds_threshhold(&self) -> bool {
-        self.sstables.read().await.len() >= MIN_TRESHOLD
+    /// Returns the share of the entries of the SSTables that are dead
+    pub async fn dead_ratio(&self) -> f64 {
+        let sstables = self.sstables.read().await;
+        let entries: usize = sstables.iter().map(|sst| sst.properties.entries).sum();
+        if entries == 0 {
+            return 0.0;
+        }
+        let dead: usize = sstables.iter().map(|sst| sst.properties.dead_entries()).sum();
+        dead as f64 / entries as f64
+    }
+
+    // Returns true if the bucket holds at least two SSTables with `tombstone_compaction_ratio` of dead entries
+    async fn holds_dead_entries(&self, tombstone_compaction_ratio: f64) -> bool {
+        tombstone_compaction_ratio > 0.0
+            && self.sstables.read().await.len() >= 2
+            && self.dead_ratio().await >= tombstone_compaction_ratio
+    }
+
+    pub async fn sstable_count_exceeds_threshhold(&self, policy: &BucketPolicy) -> bool {
+        self.sstables.read().await.len() >= policy.min_sstables_per_merge
     }
 }
 
@@ -118,81 +174,184 @@ impl BucketMap {
         Self {
             dir,
             buckets: IndexMap::new(),
+            pins: TablePins::new(),
+            manifest: None,
+            file_numbers: FileNumbers::default(),
+            checksum_type: ChecksumType::default(),
+            compression: CompressionPolicy::default(),
+            tombstone_compaction_ratio: 0.0,
+            policy: BucketPolicy::default(),
+            read_heavy: false,
         }
     }
     pub fn set_buckets(&mut self, buckets: IndexMap<BucketID, Bucket>) {
         self.buckets = buckets
     }
 
+    pub fn set_manifest(&mut self, manifest: Manifest) {
+        self.manifest = Some(manifest)
+    }
+
+    pub fn set_file_numbers(&mut self, file_numbers: FileNumbers) {
+        self.file_numbers = file_numbers
+    }
+
+    pub fn set_checksum_type(&mut self, checksum_type: ChecksumType) {
+        self.checksum_type = checksum_type
+    }
+
+    pub fn set_compression(&mut self, compression: CompressionPolicy) {
+        self.compression = compression
+    }
+
+    pub fn set_tombstone_compaction_ratio(&mut self, tombstone_compaction_ratio: f64) {
+        self.tombstone_compaction_ratio = tombstone_compaction_ratio
+    }
+
+    /// Sets the policy of the buckets, a merge takes at least two SSTables and no maximum is below the minimum
+    pub fn set_policy(&mut self, mut policy: BucketPolicy) {
+        policy.min_sstables_per_merge = policy.min_sstables_per_merge.max(2);
+        policy.max_sstables_per_merge = policy.max_sstables_per_merge.max(policy.min_sstables_per_merge);
+        self.policy = policy
+    }
+
+    pub fn set_read_heavy(&mut self, read_heavy: bool) {
+        self.read_heavy = read_heavy
+    }
+
+    /// Returns the policy buckets are compacted with, a read-heavy workload merges buckets of two SSTables
+    pub(crate) fn merge_policy(&self) -> BucketPolicy {
+        let mut policy = self.policy;
+        if self.read_heavy {
+            policy.min_sstables_per_merge = 2;
+        }
+        policy
+    }
+
+    // Records `sst` of `bucket` in the manifest before it is visible to readers
+    async fn add_to_manifest(&mut self, bucket: &Bucket, sst: &Table) -> Result<(), Error> {
+        if let Some(manifest) = &mut self.manifest {
+            manifest.record(bucket.id, &bucket.dir, sst)?;
+            manifest.write().await?;
+        }
+        Ok(())
+    }
+
     pub async fn insert_to_appropriate_bucket<T: InsertableToBucket + ?Sized>(
         &mut self,
         table: Arc<Box<T>>,
     ) -> Result<Table, Error> {
-        let added_to_bucket = false;
-        let created_at = Utc::now();
-        for (_, bucket) in &mut self.buckets.clone() {
-            if bucket.fits_into_bucket(table.clone()) {
-                let sst_dir = bucket
-                    .dir
-                    .join(format!("{}_{}", SST_PREFIX, created_at.timestamp_millis()));
-                let mut sst = Table::new(sst_dir).await?;
-                sst.set_entries(table.get_entries());
-                sst.write_to_file().await?;
-                bucket.sstables.write().await.push(sst.clone());
-                bucket
-                    .sstables
-                    .write()
-                    .await
-                    .iter_mut()
-                    .for_each(|s| s.increase_hotness());
-                bucket.avarage_size = Bucket::cal_average_size((&bucket.sstables.read().await).to_vec()).await?;
-                bucket.size = bucket.avarage_size * bucket.sstables.read().await.len();
-                return Ok(sst);
+        let mut target = None;
+        for (bucket_id, bucket) in self.buckets.iter_mut() {
+            if bucket.fits_into_bucket(table.clone(), &self.policy) {
+                target = Some(*bucket_id);
+                break;
             }
         }
-
-        // create a new bucket if none of the condition above was satisfied
-        if !added_to_bucket {
-            let mut bucket = Bucket::new(self.dir.clone()).await;
-            let sst_dir = bucket
-                .dir
-                .join(format!("{}_{}", SST_PREFIX, created_at.timestamp_millis()));
-            let mut sst = Table::new(sst_dir).await?;
-            sst.set_entries(table.get_entries());
-            sst.write_to_file().await?;
+        if target.is_none() && self.policy.bucket_fallback == BucketFallback::ClosestBucket {
+            target = self
+                .buckets
+                .iter()
+                .min_by(|(_, a), (_, b)| {
+                    let a = BucketPolicy::distance(a.avarage_size, table.size());
+                    let b = BucketPolicy::distance(b.avarage_size, table.size());
+                    a.total_cmp(&b)
+                })
+                .map(|(bucket_id, _)| *bucket_id);
+        }
+        if let Some(mut bucket) = target.and_then(|bucket_id| self.buckets.get(&bucket_id).cloned()) {
+            let file_number = self.file_numbers.next();
+            let sst_dir = bucket.dir.join(format!("{}_{:06}", SST_PREFIX, file_number));
+            let sst = Table::write_new(
+                sst_dir,
+                file_number,
+                table.get_entries(),
+                self.checksum_type,
+                self.compression.for_size(table.size()),
+            )
+            .await?;
+            self.add_to_manifest(&bucket, &sst).await?;
             bucket.sstables.write().await.push(sst.clone());
-            bucket.avarage_size = fs::metadata(sst.clone().data_file.path)
+            bucket
+                .sstables
+                .write()
                 .await
-                .map_err(|err| GetFileMetaDataError(err))?
-                .len() as usize;
-            self.buckets.insert(bucket.id, bucket.clone());
+                .iter_mut()
+                .for_each(|s| s.increase_hotness());
+            bucket.avarage_size = Bucket::cal_average_size((&bucket.sstables.read().await).to_vec()).await?;
+            bucket.size = bucket.avarage_size * bucket.sstables.read().await.len();
+            self.buckets.insert(bucket.id, bucket);
             return Ok(sst);
         }
 
-        Err(ConditionsToInsertToBucketNotMetError)
+        // create a new bucket if the SSTable fits in none
+        let mut bucket = Bucket::new(self.dir.clone(), self.file_numbers.next()).await;
+        let file_number = self.file_numbers.next();
+        let sst_dir = bucket.dir.join(format!("{}_{:06}", SST_PREFIX, file_number));
+        let sst = Table::write_new(
+            sst_dir,
+            file_number,
+            table.get_entries(),
+            self.checksum_type,
+            self.compression.for_size(table.size()),
+        )
+        .await?;
+        self.add_to_manifest(&bucket, &sst).await?;
+        bucket.sstables.write().await.push(sst.clone());
+        bucket.avarage_size = fs::metadata(sst.clone().data_file.path)
+            .await
+            .map_err(|err| GetFileMetaDataError(err))?
+            .len() as usize;
+        self.buckets.insert(bucket.id, bucket.clone());
+        Ok(sst)
+    }
+
+    /// Returns the id of the bucket holding `sst`
+    pub(crate) fn bucket_id_of(&self, sst: &Table) -> Option<BucketID> {
+        let bucket_dir = sst.dir.parent()?;
+        self.buckets
+            .iter()
+            .find(|(_, bucket)| bucket.dir == bucket_dir)
+            .map(|(id, _)| *id)
     }
 
+    /// Returns the buckets to compact and the SSTables they are built from, the buckets holding the most dead
+    /// entries first
     pub async fn extract_imbalanced_buckets(&self) -> BucketsToCompact {
-        let mut ssts_to_delete: Vec<(BucketID, Vec<Table>)> = Vec::new();
-        let mut imbalanced_buckets: Vec<Bucket> = Vec::new();
+        let mut extracted: Vec<(f64, Bucket)> = Vec::new();
+        let policy = self.merge_policy();
         for (_, (bucket_id, bucket)) in self.buckets.iter().enumerate() {
-            let (ssts, avg) = Bucket::extract_sstables(&bucket).await?;
+            let (ssts, avg) = if bucket.holds_dead_entries(self.tombstone_compaction_ratio).await {
+                bucket.select_sstables(&policy).await?
+            } else {
+                bucket.extract_sstables(&policy).await?
+            };
             if !ssts.is_empty() {
-                ssts_to_delete.push((*bucket_id, ssts.clone()));
-                imbalanced_buckets.push(Bucket {
+                let imbalanced = Bucket {
                     size: avg * ssts.len(),
                     sstables: Arc::new(RwLock::new(ssts)),
                     id: *bucket_id,
                     dir: bucket.dir.to_owned(),
                     avarage_size: avg,
-                });
+                };
+                extracted.push((imbalanced.dead_ratio().await, imbalanced));
             }
         }
+        extracted.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        let mut ssts_to_delete: Vec<(BucketID, Vec<Table>)> = Vec::new();
+        let mut imbalanced_buckets: Vec<Bucket> = Vec::new();
+        for (_, bucket) in extracted {
+            ssts_to_delete.push((bucket.id, bucket.sstables.read().await.clone()));
+            imbalanced_buckets.push(bucket);
+        }
         Ok((imbalanced_buckets, ssts_to_delete))
     }
     pub async fn is_balanced(&self) -> bool {
+        let policy = self.merge_policy();
         for (_, bucket) in self.buckets.iter() {
-            if bucket.sstable_count_exceeds_threshhold().await {
+            if bucket.sstable_count_exceeds_threshhold(&policy).await
+                || bucket.holds_dead_entries(self.tombstone_compaction_ratio).await
+            {
                 return false;
             }
         }
@@ -202,11 +361,23 @@ impl BucketMap {
     pub async fn delete_ssts(&mut self, ssts_to_delete: &SSTablesToRemove) -> Result<bool, Error> {
         let mut all_ssts_deleted = true;
         let mut buckets_to_delete: Vec<&BucketID> = Vec::new();
+        // Forgotten before the files are deleted so that a crash never leaves the manifest listing missing files
+        if let Some(manifest) = &mut self.manifest {
+            for sst in ssts_to_delete.iter().flat_map(|(_, ssts)| ssts) {
+                manifest.forget(&sst.dir);
+            }
+            manifest.write().await?;
+        }
         for (bucket_id, ssts) in ssts_to_delete {
             if let Some(bucket) = self.buckets.get_mut(bucket_id) {
                 let bucket_clone = bucket.clone();
                 let b = bucket_clone.sstables.read().await;
-                let ssts_remaining = b.get(ssts.len()..).unwrap_or_default();
+                // Compaction does not always pick the oldest SSTables of a bucket
+                let ssts_remaining: Vec<Table> = b
+                    .iter()
+                    .filter(|sst| !ssts.iter().any(|deleted| deleted.dir == sst.dir))
+                    .cloned()
+                    .collect();
                 if !ssts_remaining.is_empty() {
                     let new_average = Bucket::cal_average_size(ssts_remaining.to_vec()).await?;
                     *bucket = Bucket {
@@ -218,16 +389,17 @@ impl BucketMap {
                     };
                 } else {
                     buckets_to_delete.push(bucket_id);
-                    if let Err(err) = fs::remove_dir_all(&bucket.dir).await {
-                        log::error!("{}", DirDeleteError(err));
+                    if let Err(err) = self.pins.remove_dir_all(&bucket.dir).await {
+                        log::error!("{}", err);
                     }
                 }
             }
             for sst in ssts {
                 if fs::metadata(&sst.dir).await.is_ok() {
-                    if let Err(err) = fs::remove_dir_all(&sst.dir).await {
+                    // Pinned SSTables are removed once released by the readers
+                    if let Err(err) = self.pins.remove_dir_all(&sst.dir).await {
                         all_ssts_deleted = false;
-                        log::error!("{}", DirDeleteError(err));
+                        log::error!("{}", err);
                     }
                 }
             }
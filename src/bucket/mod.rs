@@ -1,7 +1,11 @@
 pub(crate) mod bucket;
+mod policy;
 pub use bucket::Bucket;
 pub use bucket::BucketID;
 pub use bucket::BucketMap;
 pub use bucket::BucketsToCompact;
 pub use bucket::InsertableToBucket;
 pub use bucket::SSTablesToRemove;
+pub use policy::BucketFallback;
+pub use policy::BucketPolicy;
+pub use policy::CompressionPolicy;
@@ -0,0 +1,79 @@
+use crate::{
+    compression::CompressionType,
+    consts::{BUCKET_HIGH, BUCKET_LOW, MAX_TRESHOLD, MIN_SSTABLE_SIZE, MIN_TRESHOLD},
+};
+
+/// Where an SSTable fitting in no bucket is inserted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BucketFallback {
+    /// A new bucket is created for it
+    #[default]
+    NewBucket,
+
+    /// It joins the bucket whose average SSTable size is the closest to its size, a new bucket is only created
+    /// for the first SSTable
+    ClosestBucket,
+}
+
+/// Size-tiered parameters of the buckets, see the `Config` fields of the same name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketPolicy {
+    pub bucket_low: f64,
+    pub bucket_high: f64,
+    pub min_sstable_size: usize,
+    pub min_sstables_per_merge: usize,
+    pub max_sstables_per_merge: usize,
+    pub bucket_fallback: BucketFallback,
+}
+
+impl Default for BucketPolicy {
+    fn default() -> Self {
+        Self {
+            bucket_low: BUCKET_LOW,
+            bucket_high: BUCKET_HIGH,
+            min_sstable_size: MIN_SSTABLE_SIZE,
+            min_sstables_per_merge: MIN_TRESHOLD,
+            max_sstables_per_merge: MAX_TRESHOLD,
+            bucket_fallback: BucketFallback::default(),
+        }
+    }
+}
+
+impl BucketPolicy {
+    /// Returns true if an SSTable of `size` bytes fits in a bucket of SSTables of `average_size` bytes on average
+    pub fn fits(&self, average_size: usize, size: usize) -> bool {
+        (average_size as f64 * self.bucket_low < size as f64)
+            && (size < (average_size as f64 * self.bucket_high) as usize)
+            || (size < self.min_sstable_size && average_size < self.min_sstable_size)
+    }
+
+    /// Returns how far an SSTable of `size` bytes is from a bucket of SSTables of `average_size` bytes on average,
+    /// as the ratio of the bigger size to the smaller one
+    pub(crate) fn distance(average_size: usize, size: usize) -> f64 {
+        let (average_size, size) = (average_size.max(1) as f64, size.max(1) as f64);
+        average_size.max(size) / average_size.min(size)
+    }
+}
+
+/// Algorithms compressing the blocks of new SSTables, see the `Config` fields of the same name
+///
+/// Buckets are tiered by size so the SSTables of the small buckets, written by flushes, hold the newest and hottest
+/// entries while compactions merge the oldest into ever bigger SSTables. The size of an SSTable thus tells whether
+/// its blocks are read often enough to be left uncompressed or are better compressed harder.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionPolicy {
+    pub sstable_compression: CompressionType,
+    pub cold_sstable_compression: CompressionType,
+    pub cold_sstable_size: usize,
+}
+
+impl CompressionPolicy {
+    /// Returns the algorithm compressing the blocks of an SSTable of `size` bytes
+    pub fn for_size(&self, size: usize) -> CompressionType {
+        if self.cold_sstable_size > 0 && size >= self.cold_sstable_size {
+            self.cold_sstable_compression
+        } else {
+            self.sstable_compression
+        }
+    }
+}
@@ -1,15 +1,35 @@
 use crate::{
-    compactors,
+    bucket::{BucketFallback, BucketPolicy, CompressionPolicy},
+    checksum::ChecksumType,
+    compactors::{self, CompactionFilter},
+    compression::CompressionType,
     consts::{
-        DEFAULT_ALLOW_PREFETCH, DEFAULT_COMPACTION_FLUSH_LISTNER_INTERVAL_MILLI, DEFAULT_COMPACTION_INTERVAL_MILLI,
-        DEFAULT_FALSE_POSITIVE_RATE, DEFAULT_MAX_WRITE_BUFFER_NUMBER, DEFAULT_ONLINE_GARBAGE_COLLECTION_INTERVAL_MILLI,
-        DEFAULT_PREFETCH_SIZE, DEFAULT_TOMBSTONE_COMPACTION_INTERVAL_MILLI, DEFAULT_TOMBSTONE_TTL, DEFUALT_ENABLE_TTL,
-        ENTRY_TTL, GC_CHUNK_SIZE, WRITE_BUFFER_SIZE,
+        BUCKET_HIGH, BUCKET_LOW, DEFAULT_ALLOW_PREFETCH, DEFAULT_BLOCK_CACHE_SIZE, DEFAULT_COLD_SSTABLE_SIZE,
+        DEFAULT_COMPACTION_FLUSH_LISTNER_INTERVAL_MILLI, DEFAULT_COMPACTION_INTERVAL_MILLI,
+        DEFAULT_COMPACTION_RATE_LIMIT, DEFAULT_FALSE_POSITIVE_RATE, DEFAULT_GC_CHUNK_INTERVAL_MILLI,
+        DEFAULT_GC_GARBAGE_RATIO, DEFAULT_GC_REWRITE_RATE_LIMIT, DEFAULT_HOTNESS_HALF_LIFE_MILLI,
+        DEFAULT_IDEMPOTENCY_TOKEN_TTL, DEFAULT_INLINE_VALUE_THRESHOLD, DEFAULT_LARGE_VALUE_THRESHOLD,
+        DEFAULT_MAX_BATCH_SIZE, DEFAULT_MAX_KEY_SIZE, DEFAULT_MAX_PARALLEL_SSTABLE_READS, DEFAULT_MAX_SUBCOMPACTIONS,
+        DEFAULT_MAX_VALUE_SIZE, DEFAULT_MAX_WRITE_BUFFER_NUMBER, DEFAULT_MEDIUM_VALUE_THRESHOLD,
+        DEFAULT_ONLINE_GARBAGE_COLLECTION_INTERVAL_MILLI, DEFAULT_PREFETCH_SIZE, DEFAULT_READ_AMP_COMPACTION_THRESHOLD,
+        DEFAULT_RESERVED_DISK_SPACE, DEFAULT_TOMBSTONE_COMPACTION_INTERVAL_MILLI, DEFAULT_TOMBSTONE_COMPACTION_RATIO,
+        DEFAULT_TOMBSTONE_TTL, DEFAULT_VALUE_COMPRESSION_THRESHOLD, DEFAULT_VALUE_LOG_BUFFER_INTERVAL_MILLI,
+        DEFAULT_VALUE_LOG_BUFFER_SIZE, DEFAULT_VALUE_LOG_PREALLOCATION_SIZE, DEFAULT_VALUE_LOG_SYNC_INTERVAL_MILLI,
+        DEFAULT_VERSION_RETENTION_MILLI, DEFUALT_ENABLE_TTL, ENTRY_TTL, GC_CHUNK_SIZE, MAX_TRESHOLD, MIN_SSTABLE_SIZE,
+        MIN_TRESHOLD, WRITE_BUFFER_SIZE,
     },
+    events::EventListener,
+    gc::GCStrategy,
+    types::{ValOffset, Value},
+    value_log::ValueClass,
 };
+use std::sync::Arc;
 
 #[derive(Clone, Debug)]
 /// Configuration options for the storage engine.
+///
+/// Set the options to change over the defaults, e.g. `Config { write_buffer_size: 1024, ..Config::default() }`,
+/// options added later then keep their default value.
 pub struct Config {
     /// False positive rate for the Bloom filter. The lower the value, the more accurate,
     /// but it incurs extra cost on the CPU.
@@ -22,6 +42,9 @@ pub struct Config {
     pub entry_ttl_millis: u64,
 
     /// Time for a tombstone to exist before it is removed automatically (in days).
+    ///
+    /// Past it a tombstone is only dropped by a compaction that merges every SSTable that may hold an older
+    /// version of its key.
     pub tombstone_ttl: u64,
 
     /// Should we prefetch upcoming values in case of range queries?
@@ -30,6 +53,14 @@ pub struct Config {
     /// How many keys should we prefetch in case of range queries?
     pub prefetch_size: usize,
 
+    /// How many SSTables should be read concurrently in case of range queries and while opening the store?
+    pub max_parallel_sstable_reads: usize,
+
+    /// Bytes of SSTable blocks gets and range queries keep in memory, shared by all the SSTables, 0 disables it
+    ///
+    /// The least recently used blocks are evicted first, see `DataStore::block_cache`.
+    pub block_cache_size: usize,
+
     /// The size of each memtable in bytes
     pub write_buffer_size: usize,
 
@@ -53,44 +84,234 @@ pub struct Config {
 
     pub online_gc_interval: u64,
 
+    /// Bytes of the value log read from its tail by a garbage collection pass
+    ///
+    /// A run collecting several chunks goes through them one pass at a time, see `gc_chunk_interval`.
     pub gc_chunk_size: usize,
+
+    /// Maximum number of key and value bytes written by `write_batch` before it lets other tasks run
+    pub max_batch_size: usize,
+
+    /// How long an idempotency token is remembered after its write was applied (in milliseconds)
+    pub idempotency_token_ttl: u64,
+
+    /// Largest key accepted by writes (in bytes)
+    ///
+    /// Keys are what fills a memtable up to `write_buffer_size`, a key close to that size freezes a memtable
+    /// on almost every write. Keys are also held in 4KB SSTable blocks so they should stay well below that.
+    pub max_key_size: usize,
+
+    /// Largest value accepted by writes (in bytes), capped at 4GB by the value log format
+    ///
+    /// Values live in the value log and only their offset is held in memtables, so they do not count
+    /// towards `write_buffer_size`, but recovery and garbage collection read them back whole.
+    pub max_value_size: usize,
+
+    /// How long overwritten and deleted versions stay readable with `get_at` (in milliseconds)
+    ///
+    /// Compaction keeps the versions a read within the window could need and garbage collection does not reclaim
+    /// the value log space they take. Versions overwritten within the same memtable
+    /// are not retained.
+    pub version_retention: u64,
+
+    /// Algorithm checksumming the value log entries and SSTable blocks written from now on
+    ///
+    /// The algorithm is recorded with what it protects, files written with another one remain readable.
+    pub checksum_type: ChecksumType,
+
+    /// Algorithm compressing the values appended to the value log from now on
+    ///
+    /// Every value records whether it is compressed, values appended uncompressed or with another algorithm remain
+    /// readable. A value that does not shrink is stored uncompressed, values written with `put_stream` are never
+    /// compressed.
+    pub value_compression: CompressionType,
+
+    /// Values shorter than this are not compressed (in bytes)
+    pub value_compression_threshold: usize,
+
+    /// Algorithm compressing the blocks of SSTables written from now on, by flushes and compactions, but the cold
+    /// ones, see `cold_sstable_compression`
+    ///
+    /// Every block records whether it is compressed and with which algorithm, SSTables written uncompressed or with
+    /// another algorithm remain readable. A block that does not shrink is stored uncompressed.
+    pub sstable_compression: CompressionType,
+
+    /// Algorithm compressing the blocks of SSTables of at least `cold_sstable_size` bytes written from now on
+    ///
+    /// Flushes write small SSTables of recent entries while compactions merge older entries into bigger ones, so
+    /// the biggest buckets hold the coldest data. They can be compressed harder than the hot SSTables, which can be
+    /// left uncompressed to keep their reads cheap.
+    pub cold_sstable_compression: CompressionType,
+
+    /// SSTables at least this big are compressed with `cold_sstable_compression` (in bytes), 0 disables it
+    pub cold_sstable_size: usize,
+
+    /// Values at least this long are appended to a value log of their own, `val_log_medium.bin` (in bytes)
+    ///
+    /// Garbage collection of the small values then never copies them. Disabled by default.
+    pub medium_value_threshold: usize,
+
+    /// Values at least this long are appended to `val_log_large.bin` rather than the medium value log (in bytes)
+    ///
+    /// Disabled by default.
+    pub large_value_threshold: usize,
+
+    /// Values shorter than this are also stored in the SSTable entries pointing to them (in bytes)
+    ///
+    /// Reads of such values then skip the value log and garbage collection reclaims their space once they are
+    /// flushed. Values written with `put_stream` are never inlined. Disabled by default.
+    pub inline_value_threshold: usize,
+
+    /// Records appended to the value log are grouped in memory until they take this many bytes, then written with a
+    /// single write (in bytes)
+    ///
+    /// Buffered records are read, synced and garbage collected like written ones, but they are lost if the process
+    /// stops before they are written. Disabled by default.
+    pub value_log_buffer_size: usize,
+
+    /// Buffered records are written once the oldest one waited this long even if the buffer is not full (in
+    /// milliseconds)
+    pub value_log_buffer_interval: u64,
+
+    /// Disk space is allocated this many bytes at a time past the end of the value log files ahead of the appends
+    /// (in bytes)
+    ///
+    /// Appends then seldom update the metadata of the file system and the files are less fragmented, the length of
+    /// the files is unchanged. Ignored unless the file system supports `fallocate`, only Linux ones can. Disabled by
+    /// default.
+    pub value_log_preallocation_size: usize,
+
+    /// The value log is synced in the background this often whatever `WriteOptions::sync` says (in milliseconds)
+    ///
+    /// Writes made without a sync are then on the disk within this long of being acknowledged. Disabled by
+    /// default.
+    pub value_log_sync_interval: u64,
+
+    /// Free disk space compaction leaves untouched (in bytes), 0 disables the check
+    ///
+    /// Compaction writes the merged SSTables before it removes the ones they replace, it is skipped while the
+    /// file system holding the store has less free space than this so that flushes and value log appends can
+    /// still use what is left.
+    pub reserved_disk_space: u64,
+
+    /// Called for every value compaction merges to keep, remove or rewrite it, `None` keeps every value
+    ///
+    /// See `CompactionFilter` for what removing or rewriting a value does.
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
+
+    /// How many tasks merge the SSTables of a bucket in parallel, 1 merges them on a single task
+    ///
+    /// The key space of the bucket is split into as many disjoint ranges, each merged into its own SSTable.
+    /// Compactions of fewer than 1024 entries per range are split into fewer ranges. Capped at one less than
+    /// `min_sstables_per_merge` since the SSTables written land in the same bucket and would make it compacted again.
+    pub max_subcompactions: usize,
+
+    /// Bytes per second compaction reads and writes, 0 for no limit
+    ///
+    /// Keeps compaction from taking the disk bandwidth reads need, it can be changed while the store is open
+    /// with `DataStore::set_compaction_rate_limit`.
+    pub compaction_rate_limit: u64,
+
+    /// Share of tombstones and expired entries from which a bucket is compacted, 0 disables it
+    ///
+    /// Buckets are otherwise only compacted once they hold `min_sstables_per_merge` SSTables. A bucket holding at least two SSTables is
+    /// compacted as soon as this share of their entries is dead, and buckets holding the most dead entries are
+    /// compacted first.
+    pub tombstone_compaction_ratio: f64,
+
+    /// An SSTable joins a bucket if it is bigger than this ratio of the average size of the SSTables of the bucket
+    pub bucket_low: f64,
+
+    /// An SSTable joins a bucket if it is smaller than this ratio of the average size of the SSTables of the bucket
+    pub bucket_high: f64,
+
+    /// SSTables smaller than this (in bytes) join a bucket of SSTables of this size whatever their ratio
+    pub min_sstable_size: usize,
+
+    /// Number of SSTables from which a bucket is compacted, at least 2
+    pub min_sstables_per_merge: usize,
+
+    /// Most SSTables of a bucket merged at once, the ones holding the most dead entries are picked
+    pub max_sstables_per_merge: usize,
+
+    /// Where an SSTable fitting in no bucket is inserted
+    ///
+    /// Large values make SSTables of sizes too far apart to fit in the same bucket. With `BucketFallback::NewBucket`
+    /// each of them lands in a bucket of its own and is never compacted, `BucketFallback::ClosestBucket` inserts it
+    /// in the bucket whose SSTables are the closest in size instead.
+    pub bucket_fallback: BucketFallback,
+
+    /// Interval (in milliseconds) at which the hotness of every SSTable halves, 0 disables the decay
+    ///
+    /// Hotness only grows otherwise, SSTables that were hot long ago would keep being read first.
+    pub hotness_half_life: u64,
+
+    /// SSTables read per get on average from which compaction merges buckets of at least two SSTables, 0 disables it
+    ///
+    /// Taken over the gets made since the previous compaction round, once there are at least 100 of them. Buckets
+    /// are otherwise only compacted once they hold `min_sstables_per_merge` SSTables, read-heavy workloads then
+    /// get keys spread over fewer SSTables. See `DataStore::read_amplification`.
+    pub read_amp_compaction_threshold: f64,
+
+    /// How garbage collection removes reclaimed values from the value log
+    ///
+    /// `GCStrategy::Auto` punches holes if the file system holding the store can, which is only the case on Linux,
+    /// and copies the live values to a new value log elsewhere. So does `GCStrategy::PunchHole`, see
+    /// `DataStore::fs_capabilities`.
+    pub gc_strategy: GCStrategy,
+
+    /// Estimated share of the value log from its tail on that is garbage from which it is collected, 0 disables it
+    ///
+    /// Disabled by default, garbage collection then only runs every `online_gc_interval`. Values replaced in the
+    /// active memtable and values of the versions compaction drops are counted as garbage, which costs a read of the
+    /// value log each.
+    pub gc_garbage_ratio: f64,
+
+    /// How long background garbage collection sleeps between the chunks it collects in a run (in milliseconds)
+    ///
+    /// Leaves the disk to foreground writes between passes, 0 collects the chunks back to back.
+    pub gc_chunk_interval: u64,
+
+    /// Bytes per second of live values garbage collection rewrites at the head of the value log, 0 for no limit
+    ///
+    /// Rewritten values compete with foreground writes appended to the same file.
+    pub gc_rewrite_rate_limit: u64,
+
+    /// Notified when background work completes, see `EventListener`
+    pub event_listener: Option<Arc<dyn EventListener>>,
 }
 impl Config {
-    pub fn new(
-        false_positive_rate: f64,
-        enable_ttl: bool,
-        entry_ttl_millis: u64,
-        allow_prefetch: bool,
-        prefetch_size: usize,
-        write_buffer_size: usize,
-        max_buffer_write_number: usize,
-        online_garbage_collection_interval: u64,
-        compactor_flush_listener_interval: u64,
-        background_compaction_interval: u64,
-        tombstone_ttl: u64,
-        tombstone_compaction_interval: u64,
-        compaction_strategy: compactors::Strategy,
-        online_gc_interval: u64,
-        gc_chunk_size: usize,
-    ) -> Self {
-        Self {
-            false_positive_rate,
-            enable_ttl,
-            entry_ttl_millis,
-            allow_prefetch,
-            prefetch_size,
-            max_buffer_write_number,
-            write_buffer_size,
-            online_garbage_collection_interval,
-            compactor_flush_listener_interval,
-            background_compaction_interval,
-            tombstone_ttl,
-            tombstone_compaction_interval,
-            compaction_strategy,
-            online_gc_interval,
-            gc_chunk_size,
+    /// Returns the size-tiered parameters of the buckets
+    pub(crate) fn bucket_policy(&self) -> BucketPolicy {
+        BucketPolicy {
+            bucket_low: self.bucket_low,
+            bucket_high: self.bucket_high,
+            min_sstable_size: self.min_sstable_size,
+            min_sstables_per_merge: self.min_sstables_per_merge,
+            max_sstables_per_merge: self.max_sstables_per_merge,
+            bucket_fallback: self.bucket_fallback,
         }
     }
+
+    /// Returns the algorithms compressing the blocks of new SSTables
+    pub(crate) fn compression_policy(&self) -> CompressionPolicy {
+        CompressionPolicy {
+            sstable_compression: self.sstable_compression,
+            cold_sstable_compression: self.cold_sstable_compression,
+            cold_sstable_size: self.cold_sstable_size,
+        }
+    }
+
+    /// Returns the copy of `value` stored along with `location` in memtables and SSTables, `None` if the value is
+    /// only read from the value log
+    ///
+    /// Values stored in the log of another class are never inlined since garbage collection of the main log does
+    /// not reach them
+    pub(crate) fn inline_value(&self, value: &[u8], is_tombstone: bool, location: ValOffset) -> Option<Value> {
+        let inlined =
+            !is_tombstone && value.len() < self.inline_value_threshold && ValueClass::of(location) == ValueClass::Small;
+        inlined.then(|| value.to_vec())
+    }
 }
 
 impl Default for Config {
@@ -101,6 +322,8 @@ impl Default for Config {
             entry_ttl_millis: ENTRY_TTL, // 1 year
             allow_prefetch: DEFAULT_ALLOW_PREFETCH,
             prefetch_size: DEFAULT_PREFETCH_SIZE,
+            max_parallel_sstable_reads: DEFAULT_MAX_PARALLEL_SSTABLE_READS,
+            block_cache_size: DEFAULT_BLOCK_CACHE_SIZE,
             max_buffer_write_number: DEFAULT_MAX_WRITE_BUFFER_NUMBER,
             write_buffer_size: WRITE_BUFFER_SIZE,
             online_garbage_collection_interval: DEFAULT_ONLINE_GARBAGE_COLLECTION_INTERVAL_MILLI,
@@ -111,6 +334,42 @@ impl Default for Config {
             compaction_strategy: compactors::Strategy::STCS,
             online_gc_interval: DEFAULT_ONLINE_GARBAGE_COLLECTION_INTERVAL_MILLI,
             gc_chunk_size: GC_CHUNK_SIZE,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            idempotency_token_ttl: DEFAULT_IDEMPOTENCY_TOKEN_TTL,
+            max_key_size: DEFAULT_MAX_KEY_SIZE,
+            max_value_size: DEFAULT_MAX_VALUE_SIZE,
+            version_retention: DEFAULT_VERSION_RETENTION_MILLI,
+            checksum_type: ChecksumType::Crc32c,
+            value_compression: CompressionType::None,
+            value_compression_threshold: DEFAULT_VALUE_COMPRESSION_THRESHOLD,
+            sstable_compression: CompressionType::None,
+            cold_sstable_compression: CompressionType::None,
+            cold_sstable_size: DEFAULT_COLD_SSTABLE_SIZE,
+            medium_value_threshold: DEFAULT_MEDIUM_VALUE_THRESHOLD,
+            large_value_threshold: DEFAULT_LARGE_VALUE_THRESHOLD,
+            inline_value_threshold: DEFAULT_INLINE_VALUE_THRESHOLD,
+            value_log_buffer_size: DEFAULT_VALUE_LOG_BUFFER_SIZE,
+            value_log_buffer_interval: DEFAULT_VALUE_LOG_BUFFER_INTERVAL_MILLI,
+            value_log_preallocation_size: DEFAULT_VALUE_LOG_PREALLOCATION_SIZE,
+            value_log_sync_interval: DEFAULT_VALUE_LOG_SYNC_INTERVAL_MILLI,
+            reserved_disk_space: DEFAULT_RESERVED_DISK_SPACE,
+            compaction_filter: None,
+            max_subcompactions: DEFAULT_MAX_SUBCOMPACTIONS,
+            compaction_rate_limit: DEFAULT_COMPACTION_RATE_LIMIT,
+            tombstone_compaction_ratio: DEFAULT_TOMBSTONE_COMPACTION_RATIO,
+            bucket_low: BUCKET_LOW,
+            bucket_high: BUCKET_HIGH,
+            min_sstable_size: MIN_SSTABLE_SIZE,
+            min_sstables_per_merge: MIN_TRESHOLD,
+            max_sstables_per_merge: MAX_TRESHOLD,
+            bucket_fallback: BucketFallback::NewBucket,
+            hotness_half_life: DEFAULT_HOTNESS_HALF_LIFE_MILLI,
+            read_amp_compaction_threshold: DEFAULT_READ_AMP_COMPACTION_THRESHOLD,
+            gc_strategy: GCStrategy::Auto,
+            gc_garbage_ratio: DEFAULT_GC_GARBAGE_RATIO,
+            gc_chunk_interval: DEFAULT_GC_CHUNK_INTERVAL_MILLI,
+            gc_rewrite_rate_limit: DEFAULT_GC_REWRITE_RATE_LIMIT,
+            event_listener: None,
         }
     }
 }
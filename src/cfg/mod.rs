@@ -1,2 +1,6 @@
 mod config;
+mod read_options;
+mod write_options;
 pub use config::Config;
+pub use read_options::{ReadOptions, ReadTier};
+pub use write_options::WriteOptions;
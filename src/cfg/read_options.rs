@@ -0,0 +1,70 @@
+use crate::{
+    consts::{DEFAULT_FILL_CACHE, DEFAULT_VERIFY_CHECKSUMS},
+    types::{CreationTime, WriteTime},
+};
+
+/// Which storage tiers a read is allowed to reach
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReadTier {
+    /// Read memtables and SSTables
+    #[default]
+    All,
+
+    /// Only read memtables, keys that only exist in SSTables are reported as missing
+    MemtableOnly,
+}
+
+#[derive(Clone, Debug)]
+/// Per-call options for reads (`get`, `multi_get` and range scans).
+pub struct ReadOptions {
+    /// Only versions created at or before this sequence number are visible, `None` reads the latest state.
+    /// A version that was overwritten in the same memtable or discarded by compaction can not be read back.
+    pub snapshot: Option<CreationTime>,
+
+    /// Only versions written at or before this wall-clock time (in milliseconds) are visible, `None` reads the
+    /// latest state. Versions are kept as for `snapshot`
+    pub as_of: Option<WriteTime>,
+
+    /// Should we verify checksums of the data read from disk?
+    pub verify_checksums: bool,
+
+    /// Should blocks read from SSTables be kept in the block cache? When false the cache is bypassed altogether
+    pub fill_cache: bool,
+
+    /// Which storage tiers the read can reach
+    pub read_tier: ReadTier,
+}
+impl ReadOptions {
+    pub fn new(snapshot: Option<CreationTime>, verify_checksums: bool, fill_cache: bool, read_tier: ReadTier) -> Self {
+        Self {
+            snapshot,
+            as_of: None,
+            verify_checksums,
+            fill_cache,
+            read_tier,
+        }
+    }
+
+    /// Returns true if a version created at `created_at` and written at `written_at` is visible to the read
+    pub(crate) fn is_visible(&self, created_at: CreationTime, written_at: WriteTime) -> bool {
+        self.snapshot.is_none_or(|snapshot| created_at <= snapshot)
+            && self.as_of.is_none_or(|as_of| written_at <= as_of)
+    }
+
+    /// Returns true if the read can reach SSTables
+    pub(crate) fn reads_sstables(&self) -> bool {
+        self.read_tier == ReadTier::All
+    }
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        ReadOptions {
+            snapshot: None,
+            as_of: None,
+            verify_checksums: DEFAULT_VERIFY_CHECKSUMS,
+            fill_cache: DEFAULT_FILL_CACHE,
+            read_tier: ReadTier::All,
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use crate::consts::DEFAULT_SYNC_WRITES;
+
+#[derive(Clone, Debug)]
+/// Per-call options for writes (`put` and `delete`).
+///
+/// Values are appended to the value log which also serves as the write-ahead log, every write goes through it
+/// so there is no option to skip it.
+pub struct WriteOptions {
+    /// Should the value log be synced to disk before the write returns?
+    /// Without it a write survives a process crash but can be lost if the machine crashes.
+    pub sync: bool,
+
+    /// Token identifying the write, a write whose token was already applied within the idempotency token TTL
+    /// is skipped. Lets clients retry a write without applying it twice.
+    pub idempotency_token: Option<String>,
+}
+impl WriteOptions {
+    pub fn new(sync: bool) -> Self {
+        Self {
+            sync,
+            idempotency_token: None,
+        }
+    }
+
+    /// Attaches an idempotency token to the write
+    pub fn with_idempotency_token<T: Into<String>>(mut self, token: T) -> Self {
+        self.idempotency_token = Some(token.into());
+        self
+    }
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            sync: DEFAULT_SYNC_WRITES,
+            idempotency_token: None,
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! # Change data capture
+//!
+//! `changes_since` replays the puts and deletes recorded in the value log after a given sequence number so that
+//! downstream systems, such as a search index, can follow the store incrementally: they remember the sequence
+//! number of the last change they applied and ask for the changes after it.
+//!
+//! The value log is read the same way memtables are recovered on startup, from its tail, so changes whose space
+//! was reclaimed by garbage collection are no longer returned. Values relocated by garbage collection are
+//! returned again as puts with a new sequence number. Range deletions are not recorded in the value log and are
+//! not returned.
+
+use crate::consts::{HEAD_ENTRY_KEY, TAIL_ENTRY_KEY};
+use crate::err::Error;
+use crate::storage::DataStore;
+use crate::types::{CreationTime, Key, Value};
+use crate::value_log::ValueLogEntry;
+
+/// A write recorded in the value log
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Put {
+        sequence: CreationTime,
+        key: Key,
+        value: Value,
+    },
+    Delete {
+        sequence: CreationTime,
+        key: Key,
+    },
+}
+
+impl Change {
+    /// Sequence number of the write, pass it to `changes_since` to resume after this change
+    pub fn sequence(&self) -> CreationTime {
+        match self {
+            Change::Put { sequence, .. } => *sequence,
+            Change::Delete { sequence, .. } => *sequence,
+        }
+    }
+
+    pub fn key(&self) -> &Key {
+        match self {
+            Change::Put { key, .. } => key,
+            Change::Delete { key, .. } => key,
+        }
+    }
+}
+
+/// Iterates over the changes returned by `DataStore::changes_since` in the order they were written
+#[derive(Debug)]
+pub struct ChangeIterator {
+    entries: std::vec::IntoIter<ValueLogEntry>,
+}
+
+impl Iterator for ChangeIterator {
+    type Item = Change;
+
+    fn next(&mut self) -> Option<Change> {
+        self.entries.next().map(|e| {
+            if e.is_tombstone {
+                Change::Delete {
+                    sequence: e.created_at,
+                    key: e.key,
+                }
+            } else {
+                Change::Put {
+                    sequence: e.created_at,
+                    key: e.key,
+                    value: e.value,
+                }
+            }
+        })
+    }
+}
+
+impl DataStore<'_, Key> {
+    /// Returns the puts and deletes written after `sequence` in the order they were written
+    ///
+    /// `changes_since(0)` returns every change still held by the value log
+    pub async fn changes_since(&self, sequence: CreationTime) -> Result<ChangeIterator, Error> {
+        let entries: Vec<ValueLogEntry> = self
+            .logged_writes()
+            .await?
+            .into_iter()
+            .filter(|e| e.created_at > sequence)
+            .collect();
+        Ok(ChangeIterator {
+            entries: entries.into_iter(),
+        })
+    }
+
+    // Returns the puts and deletes still held by the value log in the order they were written
+    pub(crate) async fn logged_writes(&self) -> Result<Vec<ValueLogEntry>, Error> {
+        // Garbage collection moves the tail of its own handle on the value log
+        let tail = self.val_log.tail_offset.max(self.gc_log.read().await.tail_offset);
+        Ok(self
+            .val_log
+            .recover(tail, !self.is_read_only())
+            .await?
+            .into_iter()
+            .filter(|e| e.key != HEAD_ENTRY_KEY && e.key != TAIL_ENTRY_KEY && !e.is_rewritten)
+            .collect())
+    }
+}
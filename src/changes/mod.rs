@@ -0,0 +1,7 @@
+mod change;
+mod restore;
+mod subscriptions;
+pub use change::Change;
+pub use change::ChangeIterator;
+pub use subscriptions::ChangeEvent;
+pub use subscriptions::Subscriptions;
@@ -0,0 +1,49 @@
+//! # Point-in-time restore
+//!
+//! `restore_to` rebuilds the store as it was at a past sequence number in a new directory, e.g. to get back the
+//! data overwritten by a bad deploy. The puts and deletes held by the value log are replayed in the order they were
+//! written, up to that sequence number, so the restored store is only as complete as the value log:
+//! - changes whose space was reclaimed by garbage collection are missing, as are values relocated by garbage
+//!   collection after that sequence number since they were given a bigger one
+//! - range deletions are not recorded in the value log, keys they removed are restored
+//!
+//! Replayed writes are given new sequence numbers in the restored store. Values whose TTL has run out by the time
+//! they are replayed are deleted, the others keep the time they expire at.
+
+use crate::err::Error;
+use crate::err::Error::*;
+use crate::storage::DataStore;
+use crate::types::{CreationTime, Key};
+use chrono::Utc;
+use std::path::PathBuf;
+
+impl DataStore<'_, Key> {
+    /// Creates a store in `dir` holding what this store held as of `sequence`, see the module documentation
+    ///
+    /// `sequence` is e.g. the sequence number of a snapshot or of a change read from the change feed. `dir` must
+    /// not exist or be empty. The restored store is opened with the configuration of this store and returned.
+    pub async fn restore_to(&self, sequence: CreationTime, dir: PathBuf) -> Result<DataStore<'static, Key>, Error> {
+        let is_empty = !dir.exists() || dir.read_dir().is_ok_and(|mut entries| entries.next().is_none());
+        if !is_empty {
+            return Err(DirectoryOpenError {
+                path: dir,
+                error: std::io::Error::from(std::io::ErrorKind::AlreadyExists),
+            });
+        }
+        let writes = self.logged_writes().await?;
+        let mut store = DataStore::new_with_custom_config(dir, self.config.clone()).await?;
+        for entry in writes.into_iter().filter(|e| e.created_at <= sequence) {
+            let now = Utc::now().timestamp_millis() as u64;
+            match entry.expires_at {
+                _ if entry.is_tombstone => store.delete(&entry.key).await.map(|_| ())?,
+                Some(expires_at) if expires_at <= now => store.delete(&entry.key).await.map(|_| ())?,
+                Some(expires_at) => store
+                    .put_with_ttl(&entry.key, &entry.value, expires_at - now)
+                    .await
+                    .map(|_| ())?,
+                None => store.put(&entry.key, &entry.value).await.map(|_| ())?,
+            }
+        }
+        Ok(store)
+    }
+}
@@ -0,0 +1,49 @@
+use super::Change;
+use crate::types::Key;
+use std::sync::Mutex;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+/// Event sent to subscribers on every put and delete of a matching key, the same as a change read back by
+/// `changes_since`
+pub type ChangeEvent = Change;
+
+/// Subscribers of the store with the key prefix each one watches
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    subscribers: Mutex<Vec<(Key, Sender<ChangeEvent>)>>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a receiver of the events of keys starting with `prefix`, holding up to `capacity` events
+    pub fn subscribe(&self, prefix: &[u8], capacity: usize) -> Receiver<ChangeEvent> {
+        let (tx, rx) = broadcast::channel(capacity);
+        self.subscribers.lock().unwrap().push((prefix.to_vec(), tx));
+        rx
+    }
+
+    /// Sends `change` to the subscribers watching its key, subscribers whose receivers were all dropped are removed
+    pub(crate) fn notify(&self, change: impl FnOnce() -> ChangeEvent, key: &[u8]) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        subscribers.retain(|(_, tx)| tx.receiver_count() > 0);
+        let mut matching = subscribers
+            .iter()
+            .filter(|(prefix, _)| key.starts_with(prefix))
+            .peekable();
+        if matching.peek().is_none() {
+            return;
+        }
+        // The event is only built when somebody watches the key
+        let change = change();
+        for (_, tx) in matching {
+            // Fails only if the receivers were dropped in the meantime
+            let _ = tx.send(change.clone());
+        }
+    }
+}
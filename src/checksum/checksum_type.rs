@@ -0,0 +1,84 @@
+//! # Checksum
+//!
+//! Records written to disk end with a checksum computed by the algorithm selected in the config. The algorithm
+//! is recorded next to the records it protects, in the header of SSTable files and in the flags byte of value
+//! log entries, so files written before the config changed can still be verified.
+
+use super::{crc32c, xxhash64, Crc32c, XxHash64};
+use crate::consts::{SIZE_OF_U32, SIZE_OF_U64};
+
+/// Algorithm used to checksum the records written to disk
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChecksumType {
+    /// 4 byte CRC32C
+    #[default]
+    Crc32c,
+
+    /// 8 byte XXH64
+    XxHash64,
+}
+
+impl ChecksumType {
+    /// Returns the number of bytes the checksum occupies on disk
+    pub fn size(&self) -> usize {
+        match self {
+            ChecksumType::Crc32c => SIZE_OF_U32,
+            ChecksumType::XxHash64 => SIZE_OF_U64,
+        }
+    }
+
+    /// Returns the byte identifying the algorithm in file headers
+    pub(crate) fn as_byte(&self) -> u8 {
+        match self {
+            ChecksumType::Crc32c => 0,
+            ChecksumType::XxHash64 => 1,
+        }
+    }
+
+    pub(crate) fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(ChecksumType::Crc32c),
+            1 => Some(ChecksumType::XxHash64),
+            _ => None,
+        }
+    }
+
+    /// Returns the checksum of `bytes` as stored on disk
+    pub fn checksum(&self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            ChecksumType::Crc32c => crc32c(bytes).to_le_bytes().to_vec(),
+            ChecksumType::XxHash64 => xxhash64(bytes).to_le_bytes().to_vec(),
+        }
+    }
+}
+
+/// Running checksum of the selected algorithm over bytes fed through `update`
+#[derive(Debug, Clone, Copy)]
+pub enum Checksum {
+    Crc32c(Crc32c),
+    XxHash64(XxHash64),
+}
+
+impl Checksum {
+    pub fn new(checksum_type: ChecksumType) -> Self {
+        match checksum_type {
+            ChecksumType::Crc32c => Checksum::Crc32c(Crc32c::new()),
+            ChecksumType::XxHash64 => Checksum::XxHash64(XxHash64::new()),
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Checksum::Crc32c(crc) => crc.update(bytes),
+            Checksum::XxHash64(hash) => hash.update(bytes),
+        }
+    }
+
+    /// Returns the little-endian checksum of every byte fed so far, as stored on disk
+    pub fn finish(&self) -> Vec<u8> {
+        match self {
+            Checksum::Crc32c(crc) => crc.finish().to_le_bytes().to_vec(),
+            Checksum::XxHash64(hash) => hash.finish().to_le_bytes().to_vec(),
+        }
+    }
+}
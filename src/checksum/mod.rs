@@ -0,0 +1,9 @@
+mod checksum_type;
+mod crc32c;
+mod xxhash64;
+pub use checksum_type::Checksum;
+pub use checksum_type::ChecksumType;
+use crc32c::crc32c;
+pub use crc32c::Crc32c;
+use xxhash64::xxhash64;
+pub use xxhash64::XxHash64;
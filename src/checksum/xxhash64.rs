@@ -0,0 +1,150 @@
+//! # XXH64
+//!
+//! 64-bit xxHash, faster than CRC32C on large inputs when the CPU has no CRC instructions. `XxHash64` buffers
+//! input into 32 byte stripes so that a record can be hashed as it is written or read in pieces.
+
+const PRIME_1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME_2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME_3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME_4: u64 = 0x85EB_CA77_C2B2_AE63;
+const PRIME_5: u64 = 0x27D4_EB2F_1656_67C5;
+const STRIPE_LEN: usize = 32;
+
+/// Running XXH64 with a seed of 0 over bytes fed through `update`
+#[derive(Debug, Clone, Copy)]
+pub struct XxHash64 {
+    accumulators: [u64; 4],
+    buffer: [u8; STRIPE_LEN],
+    buffered: usize,
+    total_len: u64,
+}
+
+impl Default for XxHash64 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl XxHash64 {
+    pub fn new() -> Self {
+        Self {
+            accumulators: [PRIME_1.wrapping_add(PRIME_2), PRIME_2, 0, 0u64.wrapping_sub(PRIME_1)],
+            buffer: [0; STRIPE_LEN],
+            buffered: 0,
+            total_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        self.total_len += bytes.len() as u64;
+        if self.buffered > 0 {
+            let to_copy = (STRIPE_LEN - self.buffered).min(bytes.len());
+            self.buffer[self.buffered..self.buffered + to_copy].copy_from_slice(&bytes[..to_copy]);
+            self.buffered += to_copy;
+            bytes = &bytes[to_copy..];
+            if self.buffered < STRIPE_LEN {
+                return;
+            }
+            let stripe = self.buffer;
+            self.consume(&stripe);
+            self.buffered = 0;
+        }
+        let mut stripes = bytes.chunks_exact(STRIPE_LEN);
+        for stripe in &mut stripes {
+            self.consume(stripe);
+        }
+        let rest = stripes.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    /// Returns the hash of every byte fed so far
+    pub fn finish(&self) -> u64 {
+        let [acc_1, acc_2, acc_3, acc_4] = self.accumulators;
+        let mut hash = if self.total_len >= STRIPE_LEN as u64 {
+            let mut hash = acc_1
+                .rotate_left(1)
+                .wrapping_add(acc_2.rotate_left(7))
+                .wrapping_add(acc_3.rotate_left(12))
+                .wrapping_add(acc_4.rotate_left(18));
+            for acc in self.accumulators {
+                hash = (hash ^ round(0, acc)).wrapping_mul(PRIME_1).wrapping_add(PRIME_4);
+            }
+            hash
+        } else {
+            PRIME_5
+        };
+        hash = hash.wrapping_add(self.total_len);
+
+        let mut rest = &self.buffer[..self.buffered];
+        while rest.len() >= 8 {
+            let lane = u64::from_le_bytes(rest[..8].try_into().unwrap());
+            hash = (hash ^ round(0, lane))
+                .rotate_left(27)
+                .wrapping_mul(PRIME_1)
+                .wrapping_add(PRIME_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            let lane = u32::from_le_bytes(rest[..4].try_into().unwrap()) as u64;
+            hash = (hash ^ lane.wrapping_mul(PRIME_1))
+                .rotate_left(23)
+                .wrapping_mul(PRIME_2)
+                .wrapping_add(PRIME_3);
+            rest = &rest[4..];
+        }
+        for byte in rest {
+            hash = (hash ^ (*byte as u64).wrapping_mul(PRIME_5))
+                .rotate_left(11)
+                .wrapping_mul(PRIME_1);
+        }
+
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(PRIME_2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(PRIME_3);
+        hash ^ (hash >> 32)
+    }
+
+    fn consume(&mut self, stripe: &[u8]) {
+        for (acc, lane) in self.accumulators.iter_mut().zip(stripe.chunks_exact(8)) {
+            *acc = round(*acc, u64::from_le_bytes(lane.try_into().unwrap()));
+        }
+    }
+}
+
+fn round(acc: u64, lane: u64) -> u64 {
+    acc.wrapping_add(lane.wrapping_mul(PRIME_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME_1)
+}
+
+/// Returns the XXH64 of `bytes` with a seed of 0
+pub fn xxhash64(bytes: &[u8]) -> u64 {
+    let mut hash = XxHash64::new();
+    hash.update(bytes);
+    hash.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xxhash64_matches_reference_values() {
+        assert_eq!(xxhash64(b""), 0xEF46_DB37_51D8_E999);
+        assert_eq!(xxhash64(b"a"), 0xD24E_C4F1_A98C_6E5B);
+        assert_eq!(xxhash64(b"abc"), 0x44BC_2CF5_AD77_0999);
+        assert_eq!(
+            xxhash64(b"Nobody inspects the spammish repetition"),
+            0xFBCE_A83C_8A37_8BF1
+        );
+
+        let bytes: Vec<u8> = (0..200u8).collect();
+        let mut hash = XxHash64::new();
+        for chunk in bytes.chunks(7) {
+            hash.update(chunk);
+        }
+        assert_eq!(hash.finish(), xxhash64(&bytes));
+    }
+}
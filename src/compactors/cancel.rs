@@ -0,0 +1,119 @@
+//! # Compaction cancellation
+//!
+//! Dropping the future of a running compaction could leave the SSTables it already wrote in their buckets and in
+//! the manifest, next to the SSTables they were merged from. A compaction instead checks for cancellation before
+//! it loads, merges or writes an SSTable and while it waits for the rate limiter. Once cancelled it discards the
+//! SSTables it wrote, the merged SSTables are left as they were, and returns `Error::CompactionCancelled`. Removing
+//! the merged SSTables is not cancelled, the compaction is complete by then.
+//!
+//! `DataStore::cancel_compaction` cancels the compactions running when it is called, `DataStore::close` also stops
+//! background compaction and waits for the running one to stop.
+
+use std::sync::Arc;
+use tokio::sync::watch;
+
+use crate::err::Error;
+
+/// Cancels compactions of a store, clones share the same requests
+#[derive(Debug, Clone)]
+pub struct CompactionCancel {
+    tx: Arc<watch::Sender<Requests>>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Requests {
+    // Number of times compactions were cancelled
+    cancelled: u64,
+
+    // True once no compaction is allowed to run anymore
+    shut_down: bool,
+}
+
+/// Cancellation state of a compaction, taken when it starts
+#[derive(Debug, Clone)]
+pub(crate) struct Checkpoint {
+    cancel: CompactionCancel,
+
+    // Number of cancellations requested before the compaction started
+    cancelled: u64,
+}
+
+impl Default for CompactionCancel {
+    fn default() -> Self {
+        let (tx, _) = watch::channel(Requests::default());
+        Self { tx: Arc::new(tx) }
+    }
+}
+
+impl CompactionCancel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the compactions running now, later ones run
+    pub fn cancel(&self) {
+        self.tx.send_modify(|requests| requests.cancelled += 1);
+    }
+
+    /// Cancels the compactions running now and every later one
+    pub fn shut_down(&self) {
+        self.tx.send_modify(|requests| {
+            requests.cancelled += 1;
+            requests.shut_down = true;
+        });
+    }
+
+    /// Returns true once compactions were shut down
+    pub fn is_shut_down(&self) -> bool {
+        self.tx.borrow().shut_down
+    }
+
+    /// Returns the checkpoint of a compaction starting now
+    pub(crate) fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            cancel: self.clone(),
+            cancelled: self.tx.borrow().cancelled,
+        }
+    }
+}
+
+impl Checkpoint {
+    fn is_cancelled(&self, requests: &Requests) -> bool {
+        requests.shut_down || requests.cancelled != self.cancelled
+    }
+
+    /// Returns `Error::CompactionCancelled` if the compaction was cancelled since it started
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        if self.is_cancelled(&self.cancel.tx.borrow()) {
+            return Err(Error::CompactionCancelled);
+        }
+        Ok(())
+    }
+
+    /// Resolves once the compaction is cancelled
+    pub(crate) async fn cancelled(&self) {
+        let mut rx = self.cancel.tx.subscribe();
+        // The sender is held by `self.cancel`, waiting never fails
+        let _ = rx.wait_for(|requests| self.is_cancelled(requests)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_running_compactions_only() {
+        let cancel = CompactionCancel::new();
+        let running = cancel.checkpoint();
+        assert!(running.check().is_ok());
+        cancel.cancel();
+        assert!(matches!(running.check(), Err(Error::CompactionCancelled)));
+        running.cancelled().await;
+        assert!(cancel.checkpoint().check().is_ok());
+
+        cancel.shut_down();
+        assert!(cancel.is_shut_down());
+        assert!(cancel.checkpoint().check().is_err());
+    }
+}
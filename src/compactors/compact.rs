@@ -1,14 +1,26 @@
 use super::sized::SizedTierRunner;
-use super::TableInsertor;
+use super::{CompactionCancel, CompactionFilter, CompactionProgress, RateLimiter, ReadAmplification, TableInsertor};
 /// Compaction involves merging multiple SSTables into a new, optimized one. During this process, VikingsDB considers both data and tombstones.
 ///
 /// Expired Tombstones: If a tombstone's timestamp is older than a specific threshold (defined by tombstone_ttl), it's considered expired.
-/// These expired tombstones are removed entirely during compaction, freeing up disk space.
+/// These expired tombstones are removed during compaction, freeing up disk space, unless an SSTable left out of the compaction may
+/// still hold an older version of the key. Such a version would become readable again, the tombstone is kept until the SSTables
+/// holding the key are compacted together. Garbage collection reclaims the values of the key either way since it finds the key
+/// deleted or missing.
 ///
 /// Unexpired Tombstones: If a tombstone is not expired, it means the data it shadows might still be relevant on other tiers.  In
 /// this case, VikingsDB keeps both the tombstone and the data in the new SSTable. This ensures consistency across the tiers and allows for repairs if needed.
 use crate::bucket::{BucketMap, InsertableToBucket};
-use crate::types::{BloomFilterHandle, Bool, BucketMapHandle, Duration, FlushReceiver, KeyRangeHandle};
+use crate::cfg::Config as StoreConfig;
+use crate::consts::{DEFAULT_HOTNESS_HALF_LIFE_MILLI, DEFAULT_READ_AMP_COMPACTION_THRESHOLD};
+use crate::fs::available_space;
+use crate::gc::{DeadBytes, FreedValues};
+use crate::snapshot::Snapshots;
+use crate::storage::BackgroundErrors;
+use crate::types::{
+    BloomFilterHandle, Bool, BucketMapHandle, Duration, FlushReceiver, KeyRangeHandle, RangeTombstonesHandle,
+};
+use crate::value_log::ValueLog;
 use crate::{err::Error, filter::BloomFilter};
 use futures::lock::Mutex;
 use std::sync::Arc;
@@ -26,6 +38,15 @@ pub struct Compactor {
 
     ///  is compaction active or sleeping
     pub is_active: Arc<Mutex<CompState>>,
+
+    /// Failed background compactions are recorded in it, compaction is skipped while an error is recorded
+    pub background_errors: BackgroundErrors,
+
+    /// Value log the compaction filter reads values from and appends rewritten values to
+    pub vlog: Option<ValueLog>,
+
+    /// Flushes and background compaction rounds, awaited by `DataStore::wait_for_compaction`
+    pub progress: CompactionProgress,
 }
 
 #[derive(Debug, Clone)]
@@ -52,27 +73,63 @@ pub struct Config {
     pub strategy: Strategy,
 
     pub filter_false_positive: f64,
+
+    /// how long overwritten versions stay readable
+    pub version_retention: Duration,
+
+    /// free disk space below which compaction is skipped (in bytes), 0 disables the check
+    pub reserved_disk_space: u64,
+
+    /// called for every value merged to keep, remove or rewrite it
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
+
+    /// how many tasks merge the sstables of a bucket in parallel
+    pub max_subcompactions: usize,
+
+    /// paces the sstables read and written, shared by the clones of the config
+    pub rate_limiter: RateLimiter,
+
+    /// cancels running compactions, shared by the clones of the config
+    pub cancel: CompactionCancel,
+
+    /// interval at which the hotness of the sstables halves, 0 disables the decay
+    pub hotness_half_life: Duration,
+
+    /// values of the expired entries dropped, reclaimed by garbage collection
+    pub freed_values: FreedValues,
+
+    /// sstables read per get on average from which buckets of at least two sstables are compacted, 0 disables it
+    pub read_amp_compaction_threshold: f64,
+
+    /// sstables read by gets, shared by the clones of the config
+    pub read_amp: ReadAmplification,
+
+    /// values of the versions dropped, estimated garbage of the value log
+    pub dead_bytes: DeadBytes,
 }
 impl Config {
-    pub fn new(
-        use_ttl: Bool,
-        entry_ttl: Duration,
-        tombstone_ttl: Duration,
-        flush_listener_interval: Duration,
-        background_interval: Duration,
-        tombstone_compaction_interval: Duration,
-        strategy: Strategy,
-        filter_false_positive: f64,
-    ) -> Self {
+    /// Creates the compaction config from the settings of the store
+    pub fn new(config: &StoreConfig) -> Self {
         Config {
-            use_ttl,
-            entry_ttl,
-            tombstone_ttl,
-            flush_listener_interval,
-            background_interval,
-            tombstone_compaction_interval,
-            strategy,
-            filter_false_positive,
+            use_ttl: config.enable_ttl,
+            entry_ttl: config.entry_ttl_millis,
+            tombstone_ttl: config.tombstone_ttl,
+            flush_listener_interval: config.compactor_flush_listener_interval,
+            background_interval: config.background_compaction_interval,
+            tombstone_compaction_interval: config.tombstone_compaction_interval,
+            strategy: config.compaction_strategy,
+            filter_false_positive: config.false_positive_rate,
+            version_retention: config.version_retention,
+            reserved_disk_space: config.reserved_disk_space,
+            compaction_filter: config.compaction_filter.clone(),
+            max_subcompactions: config.max_subcompactions,
+            rate_limiter: RateLimiter::default(),
+            cancel: CompactionCancel::default(),
+            hotness_half_life: DEFAULT_HOTNESS_HALF_LIFE_MILLI,
+            freed_values: FreedValues::new(),
+            read_amp_compaction_threshold: DEFAULT_READ_AMP_COMPACTION_THRESHOLD,
+            read_amp: ReadAmplification::new(),
+            dead_bytes: DeadBytes::default(),
         }
     }
 }
@@ -108,25 +165,6 @@ impl WriteTracker {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct MergePointer {
-    pub a: usize,
-    pub b: usize,
-}
-impl MergePointer {
-    pub fn new() -> Self {
-        Self { a: 0, b: 0 }
-    }
-
-    pub fn increment_a(&mut self) {
-        self.a += 1;
-    }
-
-    pub fn increment_b(&mut self) {
-        self.b += 1;
-    }
-}
-
 #[derive(Debug)]
 pub struct MergedSSTable {
     pub sstable: Box<dyn InsertableToBucket>,
@@ -155,32 +193,66 @@ impl MergedSSTable {
 }
 
 impl Compactor {
-    pub fn new(
-        use_ttl: Bool,
-        entry_ttl: Duration,
-        tombstone_ttl: Duration,
-        background_interval: Duration,
-        flush_listener_interval: Duration,
-        tombstone_compaction_interval: Duration,
-        strategy: Strategy,
-        reason: CompactionReason,
-        filter_false_positive: f64,
-    ) -> Self {
+    pub fn new(config: &StoreConfig, reason: CompactionReason) -> Self {
         Self {
             is_active: Arc::new(Mutex::new(CompState::Sleep)),
+            background_errors: BackgroundErrors::new(),
+            vlog: None,
+            progress: CompactionProgress::new(),
             reason,
-            config: Config::new(
-                use_ttl,
-                entry_ttl,
-                tombstone_ttl,
-                flush_listener_interval,
-                background_interval,
-                tombstone_compaction_interval,
-                strategy,
-                filter_false_positive,
-            ),
+            config: Config::new(config),
         }
     }
+    /// Shares `background_errors` with the store
+    pub fn with_background_errors(mut self, background_errors: BackgroundErrors) -> Self {
+        self.background_errors = background_errors;
+        self
+    }
+
+    /// Paces compaction with `rate_limiter`
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.config.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// Shares `progress` with the flusher
+    pub fn with_compaction_progress(mut self, progress: CompactionProgress) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Halves the hotness of the sstables every `hotness_half_life` milliseconds, 0 disables the decay
+    pub fn with_hotness_half_life(mut self, hotness_half_life: Duration) -> Self {
+        self.config.hotness_half_life = hotness_half_life;
+        self
+    }
+
+    /// Compacts buckets of at least two sstables once gets read `read_amp_compaction_threshold` sstables on
+    /// average, 0 disables it
+    pub fn with_read_amp_compaction_threshold(mut self, read_amp_compaction_threshold: f64) -> Self {
+        self.config.read_amp_compaction_threshold = read_amp_compaction_threshold;
+        self
+    }
+
+    /// Shares `freed_values` with the garbage collector
+    pub fn with_freed_values(mut self, freed_values: FreedValues) -> Self {
+        self.config.freed_values = freed_values;
+        self
+    }
+
+    /// Shares `dead_bytes` with the writes and the garbage collector
+    pub fn with_dead_bytes(mut self, dead_bytes: DeadBytes) -> Self {
+        self.config.dead_bytes = dead_bytes;
+        self
+    }
+
+    /// Sets the value log the compaction filter reads values from, the filter is not called without it and the
+    /// values dropped are not recorded as dead bytes
+    pub fn with_value_log(mut self, vlog: ValueLog) -> Self {
+        self.vlog = Some(vlog);
+        self
+    }
+
     /// FUTURE: Maybe trigger tombstone compaction on interval in addtion to normal periodic sstable compaction
     pub fn tombstone_compaction_condition_background_checker(
         &self,
@@ -196,24 +268,75 @@ impl Compactor {
         });
     }
 
+    /// Halves the hotness of every sstable every `hotness_half_life` so that sstables read often long ago stop
+    /// being preferred to the ones read often now
+    pub fn start_hotness_decay(&self, buckets: BucketMapHandle, filters: BloomFilterHandle) {
+        let cfg = self.config.to_owned();
+        if cfg.hotness_half_life == 0 {
+            return;
+        }
+        tokio::spawn(async move {
+            loop {
+                Compactor::sleep_compaction(cfg.hotness_half_life).await;
+                if cfg.cancel.is_shut_down() {
+                    break;
+                }
+                Compactor::decay_hotness(&buckets, &filters).await;
+            }
+        });
+    }
+
+    pub(crate) async fn decay_hotness(buckets: &BucketMapHandle, filters: &BloomFilterHandle) {
+        for (_, bucket) in buckets.read().await.buckets.iter() {
+            bucket
+                .sstables
+                .write()
+                .await
+                .iter_mut()
+                .for_each(|sst| sst.decay_hotness());
+        }
+        let mut filters = filters.write().await;
+        for filter in filters.iter_mut() {
+            if let Some(sst) = filter.sst.as_mut() {
+                sst.decay_hotness();
+            }
+        }
+        // Filters are probed from the hottest sstable
+        filters.sort_by_key(|filter| std::cmp::Reverse(filter.get_sst().get_hotness()));
+    }
+
     pub fn start_flush_listener(
         &self,
         flush_rx: FlushReceiver,
         bucket_map: BucketMapHandle,
         filter: BloomFilterHandle,
         key_range: KeyRangeHandle,
+        range_tombstones: RangeTombstonesHandle,
+        snapshots: Snapshots,
     ) {
         let mut rx = flush_rx.clone();
         let comp_state = Arc::clone(&self.is_active);
         let cfg = self.config.to_owned();
+        let background_errors = self.background_errors.clone();
+        let vlog = self.vlog.clone();
+        let progress = self.progress.clone();
         tokio::spawn(async move {
             loop {
-                Compactor::sleep_compaction(cfg.flush_listener_interval).await;
+                // A caller waiting for compaction wakes the listener up before the interval elapsed
+                let woken = tokio::select! {
+                    _ = Compactor::sleep_compaction(cfg.flush_listener_interval) => false,
+                    _ = progress.woken() => true,
+                };
+                if cfg.cancel.is_shut_down() {
+                    break;
+                }
+                if background_errors.get().is_some() {
+                    continue;
+                }
                 let signal = rx.try_recv();
                 let mut state = comp_state.lock().await;
                 if let CompState::Sleep = *state {
                     if let Err(err) = signal {
-                        drop(state);
                         match err {
                             async_broadcast::TryRecvError::Overflowed(_) => {
                                 log::error!("{}", FlushSignalChannelOverflowError)
@@ -223,18 +346,32 @@ impl Compactor {
                             }
                             async_broadcast::TryRecvError::Empty => {}
                         }
-                        continue;
+                        // Flushes a caller waits for are compacted without waiting for their signal
+                        if !(woken && progress.is_behind()) {
+                            continue;
+                        }
                     }
                     *state = CompState::Active;
                     drop(state);
-                    if let Err(err) =
-                        Compactor::handle_compaction(bucket_map.clone(), filter.clone(), key_range.clone(), &cfg).await
-                    {
-                        log::info!("{}", Error::CompactionFailed(Box::new(err)));
-                        continue;
+                    let flushed = progress.start();
+                    let res = Compactor::handle_compaction(
+                        bucket_map.clone(),
+                        filter.clone(),
+                        key_range.clone(),
+                        range_tombstones.clone(),
+                        snapshots.clone(),
+                        vlog.clone(),
+                        &cfg,
+                    )
+                    .await;
+                    if let Err(err) = res {
+                        let err = Error::CompactionFailed(Box::new(err));
+                        log::info!("{}", err);
+                        background_errors.record(err);
                     }
                     let mut state = comp_state.lock().await;
                     *state = CompState::Sleep;
+                    progress.finish(flushed);
                 }
             }
         });
@@ -246,28 +383,46 @@ impl Compactor {
         buckets: BucketMapHandle,
         filter: BloomFilterHandle,
         key_range: KeyRangeHandle,
+        range_tombstones: RangeTombstonesHandle,
+        snapshots: Snapshots,
     ) {
         let cfg = self.config.to_owned();
         let comp_state = Arc::clone(&self.is_active);
+        let background_errors = self.background_errors.clone();
+        let vlog = self.vlog.clone();
+        let progress = self.progress.clone();
         tokio::spawn(async move {
             loop {
                 Compactor::sleep_compaction(cfg.background_interval).await;
+                if cfg.cancel.is_shut_down() {
+                    break;
+                }
+                if background_errors.get().is_some() {
+                    continue;
+                }
                 let mut state = comp_state.lock().await;
                 if let CompState::Sleep = *state {
                     *state = CompState::Active;
                     drop(state);
+                    let flushed = progress.start();
                     if let Err(err) = Compactor::handle_compaction(
                         Arc::clone(&buckets),
                         Arc::clone(&filter),
                         Arc::clone(&key_range),
+                        Arc::clone(&range_tombstones),
+                        snapshots.clone(),
+                        vlog.clone(),
                         &cfg,
                     )
                     .await
                     {
-                        log::info!("{}", Error::CompactionFailed(Box::new(err)))
+                        let err = Error::CompactionFailed(Box::new(err));
+                        log::info!("{}", err);
+                        background_errors.record(err);
                     }
                     let mut state = comp_state.lock().await;
                     *state = CompState::Sleep;
+                    progress.finish(flushed);
                 }
             }
         });
@@ -277,12 +432,35 @@ impl Compactor {
         buckets: BucketMapHandle,
         filter: BloomFilterHandle,
         key_range: KeyRangeHandle,
+        range_tombstones: RangeTombstonesHandle,
+        snapshots: Snapshots,
+        vlog: Option<ValueLog>,
         cfg: &Config,
     ) -> Result<(), Error> {
+        if cfg.reserved_disk_space > 0 {
+            // Merged SSTables are written before the ones they replace are removed
+            let available = available_space(&buckets.read().await.dir)?;
+            if available < cfg.reserved_disk_space {
+                return Err(NotEnoughDiskSpace {
+                    available,
+                    reserved: cfg.reserved_disk_space,
+                });
+            }
+        }
+        // Gets reading many sstables make the round compact smaller buckets
+        let read_heavy = cfg.read_amp.is_read_heavy(cfg.read_amp_compaction_threshold);
+        buckets.write().await.set_read_heavy(read_heavy);
         match cfg.strategy {
             Strategy::STCS => {
-                let mut runner =
-                    SizedTierRunner::new(Arc::clone(&buckets), Arc::clone(&filter), Arc::clone(&key_range), cfg);
+                let mut runner = SizedTierRunner::new(
+                    Arc::clone(&buckets),
+                    Arc::clone(&filter),
+                    Arc::clone(&key_range),
+                    Arc::clone(&range_tombstones),
+                    snapshots,
+                    vlog,
+                    cfg,
+                );
                 return runner.run_compaction().await;
             }
             Strategy::LCS => {
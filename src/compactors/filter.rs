@@ -0,0 +1,37 @@
+//! # Compaction filter
+//!
+//! A compaction filter registered with `Config::compaction_filter` is called for every live value compaction
+//! writes to a merged SSTable, e.g. to expire entries on a condition of the application or to migrate values to
+//! a new encoding. Tombstones, expired entries and the head and tail entries of the value log are not passed to it.
+//!
+//! - a removed value is replaced by a tombstone carrying the same creation time, so it shadows older versions of
+//!   the key held by other SSTables. Garbage collection finds the key deleted and reclaims the value
+//! - a changed value is appended to the value log and the merged SSTable points to it. The version keeps its
+//!   creation time, garbage collection reclaims the value it replaced since no SSTable points to it anymore
+//!
+//! A value is only filtered when it is compacted, reads return it unchanged until then. A filter can be called
+//! again on a value it kept, it should decide the same way for the same value. Writes replayed from the value log
+//! into the memtable when the store is opened are read before the SSTables, they are filtered once compacted again.
+
+use crate::types::WriteTime;
+use std::fmt::Debug;
+
+/// What compaction does with a value, returned by `CompactionFilter::filter`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompactionDecision {
+    /// The value is kept as it is
+    Keep,
+
+    /// The key is deleted
+    Remove,
+
+    /// The value is replaced by the one given
+    ChangeValue(Vec<u8>),
+}
+
+/// Decides what compaction does with each value it merges, see the module documentation
+pub trait CompactionFilter: Debug + Send + Sync {
+    /// Called with the key, the value and the wall-clock time (in milliseconds) a version compaction writes to a
+    /// merged SSTable was written at
+    fn filter(&self, key: &[u8], value: &[u8], written_at: WriteTime) -> CompactionDecision;
+}
@@ -3,7 +3,6 @@ use crate::{
     consts::{SIZE_OF_U64, SIZE_OF_U8, SIZE_OF_USIZE},
     err::Error::{BiggestKeyIndexError, LowestKeyIndexError},
 };
-use crossbeam_skiplist::SkipMap;
 use std::sync::Arc;
 
 #[derive(Debug, Clone)]
@@ -37,12 +36,6 @@ impl InsertableToBucket for TableInsertor {
 }
 
 impl TableInsertor {
-    pub fn new() -> Self {
-        Self {
-            entries: Arc::new(SkipMap::new()),
-            size: 0,
-        }
-    }
     pub fn from(entries: SkipMapEntries<Key>) -> Self {
         let size = entries
             .iter()
@@ -50,16 +43,4 @@ impl TableInsertor {
             .sum::<usize>();
         Self { entries, size }
     }
-
-    pub(crate) fn set_entries(&mut self, entries: SkipMapEntries<Key>) {
-        self.entries = entries;
-        self.set_sst_size_from_entries();
-    }
-    pub(crate) fn set_sst_size_from_entries(&mut self) {
-        self.size = self
-            .entries
-            .iter()
-            .map(|e| e.key().len() + SIZE_OF_USIZE + SIZE_OF_U64 + SIZE_OF_U8)
-            .sum::<usize>();
-    }
 }
@@ -1,10 +1,23 @@
+mod cancel;
 mod compact;
+mod filter;
 mod insertor;
+mod progress;
+mod rate_limiter;
+mod read_amp;
 mod sized;
 
+pub use cancel::CompactionCancel;
 pub use compact::CompState;
 pub use compact::CompactionReason;
 pub use compact::Compactor;
 pub use compact::MergedSSTable;
 pub use compact::Strategy;
+pub use filter::CompactionDecision;
+pub use filter::CompactionFilter;
 pub use insertor::TableInsertor;
+pub use progress::CompactionProgress;
+pub use rate_limiter::RateLimiter;
+pub use read_amp::ReadAmpStats;
+pub use read_amp::ReadAmplification;
+pub(crate) use read_amp::ReadCost;
@@ -0,0 +1,152 @@
+//! # Compaction progress
+//!
+//! Background compaction runs on timers: the flush listener checks for flushed memtables every
+//! `Config::compactor_flush_listener_interval` and the periodic compaction runs every
+//! `Config::background_compaction_interval`. Callers that need the SSTables written by a flush to be compacted
+//! used to sleep for longer than these intervals.
+//!
+//! `CompactionProgress` counts the flushes and records the number of flushes a background compaction round found
+//! when it started. `DataStore::wait_for_compaction` wakes the flush listener up and resolves once a round started
+//! after every flush that completed before the call, no round is left running then.
+
+use std::sync::Arc;
+use tokio::sync::{watch, Notify};
+
+use crate::err::Error;
+use crate::storage::BackgroundErrors;
+
+/// Shared by the flusher and the background compactions of a store, clones share the same counters
+#[derive(Debug, Clone)]
+pub struct CompactionProgress {
+    tx: Arc<watch::Sender<Progress>>,
+
+    // Wakes the flush listener up before its interval elapsed
+    wake: Arc<Notify>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Progress {
+    // Number of memtables flushed
+    flushed: u64,
+
+    // Number of flushes found by the last background compaction round when it started
+    compacted: u64,
+
+    // True while a background compaction round runs
+    active: bool,
+}
+
+impl Default for CompactionProgress {
+    fn default() -> Self {
+        let (tx, _) = watch::channel(Progress::default());
+        Self {
+            tx: Arc::new(tx),
+            wake: Arc::new(Notify::new()),
+        }
+    }
+}
+
+impl CompactionProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a flushed memtable
+    pub(crate) fn flushed(&self) {
+        self.tx.send_modify(|progress| progress.flushed += 1);
+    }
+
+    /// Returns true if a memtable was flushed after the last background compaction round started
+    pub(crate) fn is_behind(&self) -> bool {
+        let progress = self.tx.borrow();
+        progress.compacted < progress.flushed
+    }
+
+    /// Marks a background compaction round as running and returns the number of flushes it will cover
+    pub(crate) fn start(&self) -> u64 {
+        let mut flushed = 0;
+        self.tx.send_modify(|progress| {
+            progress.active = true;
+            flushed = progress.flushed;
+        });
+        flushed
+    }
+
+    /// Marks the background compaction round that found `flushed` flushes as done, whether it succeeded or not
+    pub(crate) fn finish(&self, flushed: u64) {
+        self.tx.send_modify(|progress| {
+            progress.active = false;
+            progress.compacted = progress.compacted.max(flushed);
+        });
+    }
+
+    /// Resolves once the flush listener is woken up by `wait`
+    pub(crate) async fn woken(&self) {
+        self.wake.notified().await
+    }
+
+    /// Waits until no background compaction round runs
+    pub(crate) async fn idle(&self) {
+        let mut rx = self.tx.subscribe();
+        // The sender is held by `self`, waiting never fails
+        let _ = rx.wait_for(|progress| !progress.active).await;
+    }
+
+    /// Waits until a background compaction round started after every flush counted so far and none is running
+    ///
+    /// Returns the error that halted background work if one is recorded, compactions are skipped until the store
+    /// resumes.
+    pub(crate) async fn wait(&self, background_errors: &BackgroundErrors) -> Result<(), Error> {
+        let mut errors = background_errors.subscribe();
+        let mut rx = self.tx.subscribe();
+        let target = rx.borrow().flushed;
+        loop {
+            if let Some(err) = background_errors.get() {
+                return Err(Error::BackgroundError(err.to_string()));
+            }
+            let progress = *rx.borrow_and_update();
+            if !progress.active {
+                if progress.compacted >= target {
+                    return Ok(());
+                }
+                self.wake.notify_one();
+            }
+            tokio::select! {
+                _ = rx.changed() => {}
+                _ = errors.recv() => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_wait_for_rounds_started_after_flushes() {
+        let progress = CompactionProgress::new();
+        let errors = BackgroundErrors::new();
+        assert!(progress.wait(&errors).await.is_ok());
+
+        // A round that started before the flush does not cover it
+        let before = progress.start();
+        progress.flushed();
+        progress.finish(before);
+        assert!(progress.is_behind());
+
+        let waiter = {
+            let progress = progress.clone();
+            tokio::spawn(async move { progress.wait(&BackgroundErrors::new()).await })
+        };
+        progress.woken().await;
+        assert!(!waiter.is_finished());
+        let flushed = progress.start();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!waiter.is_finished());
+        progress.finish(flushed);
+        assert!(waiter.await.unwrap().is_ok());
+        assert!(!progress.is_behind());
+    }
+}
@@ -0,0 +1,123 @@
+//! # Compaction rate limiting
+//!
+//! Compaction reads every SSTable it merges and writes the merged ones, on a busy disk this competes with the
+//! reads of `get`. A token bucket refilled at `Config::compaction_rate_limit` bytes per second and holding at most
+//! one second worth of tokens paces it: compaction takes tokens for every SSTable it read or is about to write and
+//! waits while it took more than the bucket held. SSTables are read and written whole so the limit is reached on
+//! average rather than at every instant.
+//!
+//! The limit can be changed while the store is open with `DataStore::set_compaction_rate_limit`, 0 removes it.
+//!
+//! Garbage collection paces the live values it rewrites at the head of the value log with a limiter of its own,
+//! refilled at `Config::gc_rewrite_rate_limit`.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Token bucket shared by every compaction of a store or by its garbage collection, clones share the same bucket
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    // 0 for no limit
+    bytes_per_sec: u64,
+
+    // Bytes that can be taken without waiting, negative once more was taken than the bucket held
+    tokens: f64,
+
+    refilled_at: Instant,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl RateLimiter {
+    /// Creates a limiter of `bytes_per_sec`, 0 for no limit
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bucket: Arc::new(Mutex::new(TokenBucket {
+                bytes_per_sec,
+                tokens: bytes_per_sec as f64,
+                refilled_at: Instant::now(),
+            })),
+        }
+    }
+
+    /// Returns the limit in bytes per second, 0 for no limit
+    pub fn rate(&self) -> u64 {
+        self.bucket.lock().unwrap().bytes_per_sec
+    }
+
+    /// Sets the limit to `bytes_per_sec`, 0 for no limit. Compactions waiting for tokens keep waiting for the time
+    /// they were given
+    pub fn set_rate(&self, bytes_per_sec: u64) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill();
+        bucket.bytes_per_sec = bytes_per_sec;
+        bucket.tokens = bucket.tokens.min(bytes_per_sec as f64);
+    }
+
+    /// Takes tokens for `bytes` and waits until the bucket refilled what was missing
+    pub async fn acquire(&self, bytes: usize) {
+        let wait = self.bucket.lock().unwrap().take(bytes);
+        if let Some(wait) = wait {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+impl TokenBucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.refilled_at = now;
+        let capacity = self.bytes_per_sec as f64;
+        self.tokens = (self.tokens + elapsed * capacity).min(capacity);
+    }
+
+    // Returns how long to wait for the tokens taken to be refilled, `None` if the bucket held them
+    fn take(&mut self, bytes: usize) -> Option<Duration> {
+        if self.bytes_per_sec == 0 {
+            return None;
+        }
+        self.refill();
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(-self.tokens / self.bytes_per_sec as f64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_waits_for_missing_tokens() {
+        let limiter = RateLimiter::new(1000);
+        let mut bucket = limiter.bucket.lock().unwrap();
+        assert_eq!(bucket.take(1000), None);
+        let wait = bucket.take(500).unwrap();
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+        // Later takes wait for the tokens taken before them
+        assert!(bucket.take(500).unwrap() > Duration::from_millis(900));
+    }
+
+    #[test]
+    fn test_set_rate() {
+        let limiter = RateLimiter::new(1000);
+        limiter.set_rate(10);
+        assert_eq!(limiter.rate(), 10);
+        assert!(limiter.bucket.lock().unwrap().take(20).is_some());
+
+        limiter.set_rate(0);
+        assert_eq!(limiter.bucket.lock().unwrap().take(usize::MAX), None);
+    }
+}
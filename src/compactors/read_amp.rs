@@ -0,0 +1,153 @@
+//! # Read amplification
+//!
+//! Every `get` records how many memtables, bloom filters and SSTables it consulted. Buckets are otherwise only
+//! compacted once they hold `min_sstables_per_merge` SSTables, a key spread over fewer SSTables in as many
+//! buckets is still read from each of them.
+//!
+//! Each compaction round takes the gets recorded since the previous round. Once at least
+//! `MIN_READ_AMP_SAMPLE_GETS` gets read `Config::read_amp_compaction_threshold` SSTables or more on average,
+//! the round compacts every bucket holding at least two SSTables so that read-heavy phases read fewer SSTables.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::consts::MIN_READ_AMP_SAMPLE_GETS;
+
+/// Shared by the reads and the compactions of a store, clones share the same counters
+#[derive(Debug, Clone, Default)]
+pub struct ReadAmplification {
+    // Recorded since the store was opened
+    total: Arc<Counters>,
+
+    // Recorded since the last compaction round
+    window: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    gets: AtomicU64,
+    memtables: AtomicU64,
+    filters: AtomicU64,
+    sstables: AtomicU64,
+}
+
+/// What a single get consulted
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ReadCost {
+    pub(crate) memtables: u64,
+    pub(crate) filters: u64,
+    pub(crate) sstables: u64,
+}
+
+/// Totals of the gets recorded, returned by `DataStore::read_amplification`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadAmpStats {
+    pub gets: u64,
+
+    /// Active and read-only memtables looked up
+    pub memtables: u64,
+
+    /// Bloom filters of the SSTables whose key range holds the key
+    pub filters: u64,
+
+    /// SSTables whose index was read once their bloom filter matched
+    pub sstables: u64,
+}
+
+impl Counters {
+    fn add(&self, cost: &ReadCost) {
+        self.gets.fetch_add(1, Ordering::Relaxed);
+        self.memtables.fetch_add(cost.memtables, Ordering::Relaxed);
+        self.filters.fetch_add(cost.filters, Ordering::Relaxed);
+        self.sstables.fetch_add(cost.sstables, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> ReadAmpStats {
+        ReadAmpStats {
+            gets: self.gets.load(Ordering::Relaxed),
+            memtables: self.memtables.load(Ordering::Relaxed),
+            filters: self.filters.load(Ordering::Relaxed),
+            sstables: self.sstables.load(Ordering::Relaxed),
+        }
+    }
+
+    fn take(&self) -> ReadAmpStats {
+        ReadAmpStats {
+            gets: self.gets.swap(0, Ordering::Relaxed),
+            memtables: self.memtables.swap(0, Ordering::Relaxed),
+            filters: self.filters.swap(0, Ordering::Relaxed),
+            sstables: self.sstables.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+impl ReadAmpStats {
+    /// Returns the average number of SSTables read per get
+    pub fn sstables_per_get(&self) -> f64 {
+        if self.gets == 0 {
+            return 0.0;
+        }
+        self.sstables as f64 / self.gets as f64
+    }
+}
+
+impl ReadAmplification {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a get that consulted `cost`
+    pub(crate) fn record(&self, cost: &ReadCost) {
+        self.total.add(cost);
+        self.window.add(cost);
+    }
+
+    /// Returns the totals of the gets recorded since the store was opened
+    pub fn stats(&self) -> ReadAmpStats {
+        self.total.stats()
+    }
+
+    /// Returns true if the gets recorded since the last call read `threshold` SSTables or more on average, 0
+    /// never does
+    ///
+    /// Too few gets say nothing of the workload, they are left for the next call.
+    pub(crate) fn is_read_heavy(&self, threshold: f64) -> bool {
+        if threshold <= 0.0 || self.window.gets.load(Ordering::Relaxed) < MIN_READ_AMP_SAMPLE_GETS {
+            return false;
+        }
+        self.window.take().sstables_per_get() >= threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_heavy_window() {
+        let read_amp = ReadAmplification::new();
+        let cost = ReadCost {
+            memtables: 2,
+            filters: 4,
+            sstables: 3,
+        };
+        for _ in 0..MIN_READ_AMP_SAMPLE_GETS - 1 {
+            read_amp.record(&cost);
+        }
+        assert!(!read_amp.is_read_heavy(3.0));
+        read_amp.record(&cost);
+        assert!(!read_amp.is_read_heavy(0.0));
+        assert!(!read_amp.is_read_heavy(3.5));
+        // The window was taken by the last call
+        for _ in 0..MIN_READ_AMP_SAMPLE_GETS {
+            read_amp.record(&cost);
+        }
+        assert!(read_amp.is_read_heavy(3.0));
+        assert!(!read_amp.is_read_heavy(3.0));
+
+        let stats = read_amp.stats();
+        assert_eq!(stats.gets, 2 * MIN_READ_AMP_SAMPLE_GETS);
+        assert_eq!(stats.filters, 8 * MIN_READ_AMP_SAMPLE_GETS);
+        assert_eq!(stats.sstables_per_get(), 3.0);
+    }
+}
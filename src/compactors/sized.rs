@@ -1,6 +1,7 @@
 use std::{
-    cmp::{self},
-    collections::HashMap,
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    ops::Bound,
     path::PathBuf,
     sync::Arc,
 };
@@ -9,26 +10,96 @@ use crossbeam_skiplist::SkipMap;
 use uuid::Uuid;
 
 use super::{
-    compact::{Config, MergePointer, WriteTracker},
-    MergedSSTable, TableInsertor,
+    cancel::Checkpoint,
+    compact::{Config, WriteTracker},
+    CompactionDecision, MergedSSTable, TableInsertor,
 };
 use crate::{
     bucket::{Bucket, BucketsToCompact, InsertableToBucket, SSTablesToRemove},
+    consts::{HEAD_ENTRY_KEY, MIN_SUBCOMPACTION_ENTRIES, TAIL_ENTRY_KEY},
     err::Error,
     filter::BloomFilter,
-    memtable::Entry,
+    iterator::MergeIterator,
+    key_range::Range,
+    memtable::{is_expired, Entry},
+    snapshot::{ReadHorizon, Snapshots},
     sst::Table,
-    types::{BloomFilterHandle, Bool, BucketMapHandle, Key, KeyRangeHandle, ValOffset},
+    types::{
+        BloomFilterHandle, Bool, BucketMapHandle, IsTombStone, Key, KeyRangeHandle, RangeTombstonesHandle,
+        SkipMapEntries, ValOffset,
+    },
+    value_log::ValueLog,
 };
 use crate::{err::Error::*, memtable::SkipMapValue};
 
+// Bounds of the keys merged by a sub-compaction
+type KeySubRange = (Bound<Key>, Bound<Key>);
+
+// Returns the entries of `entries` whose key is in `range`
+fn entries_in_range(entries: &SkipMapEntries<Key>, range: &KeySubRange) -> SkipMapEntries<Key> {
+    let in_range = SkipMap::new();
+    for entry in entries.range(range.to_owned()) {
+        in_range.insert(entry.key().to_owned(), entry.value().to_owned());
+    }
+    Arc::new(in_range)
+}
+
+// SSTables left out of a merge, a tombstone is only dropped if none of them may hold an older version of its key
+#[derive(Debug, Clone, Default)]
+struct OlderVersions {
+    tables: Vec<(Range, BloomFilter)>,
+}
+
+impl OlderVersions {
+    // Collects the SSTables of the store other than `merged`
+    async fn outside(key_range: &KeyRangeHandle, filters: &BloomFilterHandle, merged: &[Table]) -> Self {
+        let key_range = key_range.read().await;
+        let tables = filters
+            .read()
+            .await
+            .iter()
+            .filter(|filter| !merged.iter().any(|sst| sst.dir == filter.get_sst().dir))
+            .filter_map(|filter| {
+                let range = key_range.key_ranges.get(filter.get_sst().data_file.path.as_path())?;
+                Some((range.to_owned(), filter.to_owned()))
+            })
+            .collect();
+        Self { tables }
+    }
+
+    // Returns true if one of the SSTables may hold a version of `key`
+    fn may_hold(&self, key: &Key) -> bool {
+        self.tables.iter().any(|(range, filter)| {
+            range.smallest_key.as_slice() <= key.as_slice()
+                && key.as_slice() <= range.biggest_key.as_slice()
+                && filter.contains(key)
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SizedTierRunner<'a> {
     bucket_map: BucketMapHandle,
     filters: BloomFilterHandle,
     key_range: KeyRangeHandle,
+    range_tombstones: RangeTombstonesHandle,
+    snapshots: Snapshots,
+    vlog: Option<ValueLog>,
     config: &'a Config,
     tombstones: HashMap<Key, u64>,
+    older_versions: OlderVersions,
+    checkpoint: Checkpoint,
+
+    // Oldest point a snapshot or a read within the retention window can read at, versions it may read are kept
+    horizon: Option<ReadHorizon>,
+
+    // Values of the expired entries dropped by the merge, handed to garbage collection once the merged sstables
+    // are removed
+    freed: Vec<(ValOffset, Key)>,
+
+    // Values of every version dropped by the merge and whether the version is a tombstone, recorded as dead bytes
+    // once the merged sstables are removed
+    dropped: Vec<(ValOffset, IsTombStone)>,
 }
 
 impl<'a> SizedTierRunner<'a> {
@@ -36,13 +107,24 @@ impl<'a> SizedTierRunner<'a> {
         bucket_map: BucketMapHandle,
         filters: BloomFilterHandle,
         key_range: KeyRangeHandle,
+        range_tombstones: RangeTombstonesHandle,
+        snapshots: Snapshots,
+        vlog: Option<ValueLog>,
         config: &'a Config,
     ) -> SizedTierRunner<'a> {
         Self {
             tombstones: HashMap::new(),
+            older_versions: OlderVersions::default(),
+            checkpoint: config.cancel.checkpoint(),
+            horizon: None,
+            freed: Vec::new(),
+            dropped: Vec::new(),
             bucket_map,
             filters,
             key_range,
+            range_tombstones,
+            snapshots,
+            vlog,
             config,
         }
     }
@@ -55,31 +137,47 @@ impl<'a> SizedTierRunner<'a> {
         }
         // The compaction loop will keep running until there
         // are no more buckets with more than minimum treshold size
-        // TODO: Handle this with multiple threads
         loop {
+            self.checkpoint.check()?;
             let buckets: BucketMapHandle = Arc::clone(&self.bucket_map);
             let filters = Arc::clone(&self.filters);
             let key_range = Arc::clone(&self.key_range);
             // Step 1: Extract imbalanced buckets
-            let (imbalanced_buckets, ssts_to_remove) =
+            let (imbalanced_buckets, mut ssts_to_remove) =
                 SizedTierRunner::fetch_imbalanced_buckets(buckets.clone()).await?;
             if imbalanced_buckets.is_empty() {
                 self.tombstones.clear();
+                self.range_tombstones
+                    .write()
+                    .await
+                    .remove_expired(self.config.tombstone_ttl)
+                    .await?;
                 return Ok(());
             }
 
             // Step 2: Merge SSTs in each imbalanced buckct
-            match self.merge_ssts_in_buckets(&imbalanced_buckets.to_owned()).await {
+            match self
+                .merge_ssts_in_buckets(&imbalanced_buckets.to_owned(), &mut ssts_to_remove)
+                .await
+            {
+                // Versions a snapshot or a read within the retention window can still read leave no bucket smaller
+                Ok(_) if ssts_to_remove.is_empty() => return Ok(()),
                 Ok(merged_sstables) => {
                     let mut tracker = WriteTracker::new(merged_sstables.len());
+                    let mut inserted = Vec::with_capacity(merged_sstables.len());
                     // Step 3: Insert Merged SSTs to appropriate buckets
                     for mut merged_sst in merged_sstables.into_iter() {
+                        if let Err(err) = self.acquire(merged_sst.sstable.size()).await {
+                            self.discard_merged(&inserted).await;
+                            return Err(err);
+                        }
                         let mut bucket = buckets.write().await;
                         let table = merged_sst.clone().sstable;
                         let insert_res = bucket.insert_to_appropriate_bucket(Arc::new(table)).await;
                         drop(bucket);
                         match insert_res {
                             Ok(sst) => {
+                                inserted.push(sst.clone());
                                 log::info!(
                                     "SST written, data: {:?}, index {:?}",
                                     sst.data_file.path,
@@ -106,21 +204,9 @@ impl<'a> SizedTierRunner<'a> {
                                 tracker.actual += 1;
                             }
                             Err(err) => {
-                                // Step 6 (Optional): Trigger recovery in case compaction failed at any point
-                                // Ensure filters are restored to the previous state
-                                // Remove merged sstables written to disk so far to prevent stale data
-                                while tracker.actual > 0 {
-                                    if let Some(filter) = filters.write().await.pop() {
-                                        let table = filter.get_sst().to_owned();
-                                        if let Err(err) = tokio::fs::remove_dir_all(table.dir).await {
-                                            log::error!("{}", CompactionFailed(Box::new(DirDeleteError(err))));
-                                            tracker.actual -= 1;
-                                            continue;
-                                        }
-                                        key_range.write().await.remove(table.data_file.path);
-                                    }
-                                    tracker.actual -= 1;
-                                }
+                                // Step 6 (Optional): Remove merged sstables written to disk so far to prevent
+                                // stale data, the sstables they were merged from are kept
+                                self.discard_merged(&inserted).await;
                                 return Err(CompactionFailed(Box::new(err)));
                             }
                         }
@@ -132,6 +218,10 @@ impl<'a> SizedTierRunner<'a> {
                             .clean_up_after_compaction(buckets, &ssts_to_remove.clone(), filters, key_range)
                             .await;
                         match filters_updated {
+                            Ok(Some(())) => {
+                                self.config.freed_values.record(std::mem::take(&mut self.freed));
+                                self.record_dead_bytes().await;
+                            }
                             Ok(None) => {
                                 return Err(Error::CompactionPartiallyFailed(Box::new(
                                     CompactionCleanupPartialError,
@@ -140,7 +230,6 @@ impl<'a> SizedTierRunner<'a> {
                             Err(err) => {
                                 return Err(Error::CompactionCleanupError(Box::new(err)));
                             }
-                            _ => {}
                         }
                     } else {
                         log::error!("{}", Error::CannotRemoveObsoleteSSTError)
@@ -151,6 +240,47 @@ impl<'a> SizedTierRunner<'a> {
         }
     }
 
+    // Takes tokens for `bytes` from the rate limiter, stops waiting for them once the compaction is cancelled
+    async fn acquire(&self, bytes: usize) -> Result<(), Error> {
+        tokio::select! {
+            _ = self.config.rate_limiter.acquire(bytes) => self.checkpoint.check(),
+            _ = self.checkpoint.cancelled() => Err(CompactionCancelled),
+        }
+    }
+
+    // Removes the merged sstables written by a compaction that did not complete from their buckets, the manifest,
+    // the key range and the filters, the sstables they were merged from are left in place
+    async fn discard_merged(&self, inserted: &[Table]) {
+        if inserted.is_empty() {
+            return;
+        }
+        let buckets = self.bucket_map.read().await;
+        let mut ssts_to_remove: SSTablesToRemove = Vec::new();
+        for sst in inserted.iter() {
+            let Some(bucket_id) = buckets.bucket_id_of(sst) else {
+                continue;
+            };
+            match ssts_to_remove.iter_mut().find(|(id, _)| *id == bucket_id) {
+                Some((_, ssts)) => ssts.push(sst.to_owned()),
+                None => ssts_to_remove.push((bucket_id, vec![sst.to_owned()])),
+            }
+        }
+        drop(buckets);
+        let res = self
+            .clean_up_after_compaction(
+                Arc::clone(&self.bucket_map),
+                &ssts_to_remove,
+                Arc::clone(&self.filters),
+                Arc::clone(&self.key_range),
+            )
+            .await;
+        match res {
+            Ok(Some(())) => log::info!("Discarded {} merged sstables", inserted.len()),
+            Ok(None) => log::error!("{}", CompactionCleanupPartialError),
+            Err(err) => log::error!("{}", err),
+        }
+    }
+
     pub async fn clean_up_after_compaction(
         &self,
         buckets: BucketMapHandle,
@@ -158,16 +288,14 @@ impl<'a> SizedTierRunner<'a> {
         filters: BloomFilterHandle,
         key_range: KeyRangeHandle,
     ) -> Result<Option<()>, Error> {
-        // Remove obsolete keys from keys range
-        ssts_to_delete.iter().for_each(|(_, sstables)| {
-            sstables.iter().for_each(|s| {
-                let range = Arc::clone(&key_range);
-                let path = s.get_data_file_path();
-                tokio::spawn(async move {
-                    range.write().await.remove(path);
-                });
-            })
-        });
+        // Remove obsolete keys from keys range before deleting the files so that readers
+        // either pinned the obsolete sstables already or no longer see them
+        for (_, sstables) in ssts_to_delete.iter() {
+            let mut range = key_range.write().await;
+            for s in sstables.iter() {
+                range.remove(s.get_data_file_path());
+            }
+        }
 
         // if all obsolete sstables were not deleted then don't remove the associated filters
         // although this can lead to redundancy but bloom filters are in-memory and its also less costly
@@ -196,113 +324,363 @@ impl<'a> SizedTierRunner<'a> {
         filters.write().await.extend(filter_map.into_values());
     }
 
-    async fn merge_ssts_in_buckets(&mut self, buckets: &Vec<Bucket>) -> Result<Vec<MergedSSTable>, Error> {
+    // Merges the tables of each bucket, a bucket whose merge would not leave fewer tables because it holds versions
+    // that can still be read is left as it is and taken out of `ssts_to_remove`
+    async fn merge_ssts_in_buckets(
+        &mut self,
+        buckets: &Vec<Bucket>,
+        ssts_to_remove: &mut SSTablesToRemove,
+    ) -> Result<Vec<MergedSSTable>, Error> {
         let mut merged_ssts = Vec::new();
+        self.freed.clear();
+        self.dropped.clear();
+        self.horizon = self.snapshots.oldest_readable(self.config.version_retention);
         for bucket in buckets.iter() {
-            let mut hotness = 0;
             let tables = &bucket.sstables.read().await;
-            let mut merged_sst: Box<dyn InsertableToBucket> = Box::new(tables.get(0).unwrap().to_owned());
-            for sst in tables[1..].iter() {
-                hotness += sst.hotness;
+            let hotness = tables.iter().map(|sst| sst.hotness).sum();
+            // Entries of the tables are not held in memory, they are loaded from their files
+            let mut entries = Vec::with_capacity(tables.len());
+            for sst in tables.iter() {
                 let table = sst
-                    .load_entries_from_file()
-                    .await
-                    .map_err(|err| CompactionFailed(Box::new(err)))?;
-                merged_sst = self
-                    .merge_sstables(merged_sst, Box::new(table))
+                    .load_entries_from_file(true)
                     .await
                     .map_err(|err| CompactionFailed(Box::new(err)))?;
+                self.acquire(table.size).await?;
+                entries.push(table.entries);
+            }
+            self.older_versions = OlderVersions::outside(&self.key_range, &self.filters, tables).await;
+            let freed = self.freed.len();
+            let merged = self.run_subcompactions(entries.clone()).await?;
+            if merged.len() >= tables.len() {
+                self.freed.truncate(freed);
+                ssts_to_remove.retain(|(id, _)| *id != bucket.id);
+                continue;
+            }
+            if self.config.dead_bytes.is_tracked() {
+                self.dropped.extend(SizedTierRunner::dropped_values(&entries, &merged));
+            }
+            for merged_sst in merged {
+                let filter =
+                    Table::build_filter_from_sstable(&merged_sst.get_entries(), self.config.filter_false_positive);
+                merged_ssts.push(MergedSSTable::new(merged_sst, filter, hotness));
             }
-            let filter = Table::build_filter_from_sstable(&merged_sst.get_entries(), self.config.filter_false_positive);
-            merged_ssts.push(MergedSSTable::new(merged_sst, filter, hotness));
         }
-        if merged_ssts.is_empty() {
+        if merged_ssts.is_empty() && !ssts_to_remove.is_empty() {
             return Err(CompactionFailed(Box::new(MergeSSTContainsZeroEntries)));
         }
         Ok(merged_ssts)
     }
 
-    async fn merge_sstables(
-        &mut self,
-        sst1: Box<dyn InsertableToBucket>,
-        sst2: Box<dyn InsertableToBucket>,
-    ) -> Result<Box<dyn InsertableToBucket>, Error> {
-        let mut new_sst = TableInsertor::new();
-        let new_sst_map = Arc::new(SkipMap::new());
-        let mut merged_entries = Vec::new();
-        let entries1 = sst1
-            .get_entries()
+    // Returns the offsets of the values of `tables` none of the `merged` tables points to, the head and tail entries
+    // hold offsets rather than values. A value the compaction filter removed is dropped even though the tombstone
+    // replacing it points to it
+    fn dropped_values(
+        tables: &[SkipMapEntries<Key>],
+        merged: &[Box<dyn InsertableToBucket>],
+    ) -> Vec<(ValOffset, IsTombStone)> {
+        let merged: Vec<SkipMapEntries<Key>> = merged.iter().map(|table| table.get_entries()).collect();
+        tables
             .iter()
-            .map(|e| {
-                Entry::new(
-                    e.key().to_vec(),       // key
-                    e.value().val_offset,   // v_offset
-                    e.value().created_at,   // insertion time
-                    e.value().is_tombstone, // is tombstone
-                )
+            .flat_map(|entries| entries.iter())
+            .filter(|entry| {
+                let (key, val_offset) = (entry.key(), entry.value().val_offset);
+                key != HEAD_ENTRY_KEY
+                    && key != TAIL_ENTRY_KEY
+                    && !merged.iter().any(|entries| {
+                        entries.get(key).is_some_and(|kept| {
+                            kept.value().val_offset == val_offset
+                                && kept.value().is_tombstone == entry.value().is_tombstone
+                        })
+                    })
             })
-            .collect::<Vec<Entry<Key, ValOffset>>>();
-        let entries2 = sst2
-            .get_entries()
+            .map(|entry| (entry.value().val_offset, entry.value().is_tombstone))
+            .collect()
+    }
+
+    // Records the values of the versions the merge dropped as dead bytes of the value log
+    async fn record_dead_bytes(&mut self) {
+        let dropped = std::mem::take(&mut self.dropped);
+        let Some(vlog) = self.vlog.as_ref() else {
+            return;
+        };
+        let mut dead_bytes = 0;
+        for (val_offset, is_tombstone) in dropped {
+            // The tombstone left by the compaction filter points to the value it removed, which was already counted
+            if is_tombstone && matches!(vlog.get(val_offset).await, Ok(Some((_, false)))) {
+                continue;
+            }
+            match vlog.entry_len(val_offset).await {
+                Ok(entry_len) => dead_bytes += entry_len.unwrap_or_default(),
+                Err(err) => log::warn!(
+                    "Length of the value dropped at offset {} is unknown: {}",
+                    val_offset,
+                    err
+                ),
+            }
+        }
+        self.config.dead_bytes.record(dead_bytes);
+    }
+
+    // Splits the key space of `tables` into disjoint ranges and merges each on its own task, returns the merged
+    // tables that hold entries ordered by range
+    async fn run_subcompactions(
+        &mut self,
+        tables: Vec<SkipMapEntries<Key>>,
+    ) -> Result<Vec<Box<dyn InsertableToBucket>>, Error> {
+        let total_entries: usize = tables.iter().map(|entries| entries.len()).sum();
+        // The SSTables written land in the same bucket, there must be fewer of them than `min_sstables_per_merge`
+        // so that they do not make it compacted again
+        let min_sstables_per_merge = self.bucket_map.read().await.merge_policy().min_sstables_per_merge;
+        let max_subcompactions = self.config.max_subcompactions.clamp(1, min_sstables_per_merge - 1);
+        // Fewer tables are written than merged so that repeated compactions of a bucket come to an end
+        let max_subcompactions = max_subcompactions.min(tables.len().saturating_sub(1)).max(1);
+        let subcompactions = (total_entries / MIN_SUBCOMPACTION_ENTRIES).clamp(1, max_subcompactions);
+        let ranges = SizedTierRunner::split_key_space(&tables, subcompactions);
+        let mut tasks = Vec::with_capacity(ranges.len());
+        for range in ranges.iter() {
+            let range_tables: Vec<SkipMapEntries<Key>> = if ranges.len() == 1 {
+                tables.clone()
+            } else {
+                tables.iter().map(|entries| entries_in_range(entries, range)).collect()
+            };
+            let (bucket_map, filters, key_range) = (
+                Arc::clone(&self.bucket_map),
+                Arc::clone(&self.filters),
+                Arc::clone(&self.key_range),
+            );
+            let (range_tombstones, snapshots) = (Arc::clone(&self.range_tombstones), self.snapshots.clone());
+            let (vlog, config, tombstones) = (self.vlog.clone(), self.config.clone(), self.tombstones.clone());
+            let (older_versions, checkpoint, horizon) =
+                (self.older_versions.clone(), self.checkpoint.clone(), self.horizon);
+            tasks.push(tokio::spawn(async move {
+                let mut runner = SizedTierRunner::new(
+                    bucket_map,
+                    filters,
+                    key_range,
+                    range_tombstones,
+                    snapshots,
+                    vlog,
+                    &config,
+                );
+                runner.tombstones = tombstones;
+                runner.older_versions = older_versions;
+                runner.checkpoint = checkpoint;
+                runner.horizon = horizon;
+                let merged = runner.merge_tables(range_tables).await?;
+                Ok::<_, Error>((merged, runner.tombstones, runner.freed))
+            }));
+        }
+        let mut merged_ssts = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            let (merged, tombstones, freed) = task
+                .await
+                .map_err(|_| CompactionFailed(Box::new(TokioJoinError)))?
+                .map_err(|err| CompactionFailed(Box::new(err)))?;
+            for (key, created_at) in tombstones {
+                let latest = self.tombstones.entry(key).or_insert(created_at);
+                *latest = (*latest).max(created_at);
+            }
+            self.freed.extend(freed);
+            // A range whose versions were all dropped leaves nothing to write
+            merged_ssts.extend(merged.into_iter().filter(|table| !table.get_entries().is_empty()));
+        }
+        Ok(merged_ssts)
+    }
+
+    // Returns `count` disjoint ranges covering the key space, split at evenly spaced keys of the largest table
+    fn split_key_space(tables: &[SkipMapEntries<Key>], count: usize) -> Vec<KeySubRange> {
+        let Some(largest) = tables.iter().max_by_key(|entries| entries.len()) else {
+            return vec![(Bound::Unbounded, Bound::Unbounded)];
+        };
+        let step = (largest.len() / count).max(1);
+        let split_keys: Vec<Key> = largest
             .iter()
-            .map(|e| {
-                Entry::new(
-                    e.key().to_vec(),       // key
-                    e.value().val_offset,   // v_offset
-                    e.value().created_at,   // insertion time
-                    e.value().is_tombstone, // is tombstone
-                )
-            })
-            .collect::<Vec<Entry<Key, ValOffset>>>();
-        let mut ptr = MergePointer::new();
-
-        while ptr.a < entries1.len() && ptr.b < entries2.len() {
-            match entries1[ptr.a].key.cmp(&entries2[ptr.b].key) {
-                cmp::Ordering::Less => {
-                    self.tombstone_check(&entries1[ptr.a], &mut merged_entries)
-                        .map_err(|err| TombStoneCheckFailed(err.to_string()))?;
-                    ptr.increment_a();
-                }
-                cmp::Ordering::Equal => {
-                    if entries1[ptr.a].created_at > entries2[ptr.b].created_at {
-                        self.tombstone_check(&entries1[ptr.a], &mut merged_entries)
-                            .map_err(|err| TombStoneCheckFailed(err.to_string()))?;
-                    } else {
-                        self.tombstone_check(&entries2[ptr.b], &mut merged_entries)
-                            .map_err(|err| TombStoneCheckFailed(err.to_string()))?;
-                    }
-                    ptr.increment_a();
-                    ptr.increment_b();
-                }
-                cmp::Ordering::Greater => {
-                    self.tombstone_check(&entries2[ptr.b], &mut merged_entries)
-                        .map_err(|err| TombStoneCheckFailed(err.to_string()))?;
-                    ptr.increment_b();
+            .skip(step)
+            .step_by(step)
+            .take(count - 1)
+            .map(|entry| entry.key().to_owned())
+            .collect();
+        let mut ranges = Vec::with_capacity(split_keys.len() + 1);
+        let mut start = Bound::Unbounded;
+        for key in split_keys {
+            ranges.push((start, Bound::Excluded(key.to_owned())));
+            start = Bound::Included(key);
+        }
+        ranges.push((start, Bound::Unbounded));
+        ranges
+    }
+
+    // Merges `tables` ordered from the oldest to the newest, the newest version of each key goes to the first table
+    // returned and the older versions a snapshot or a read within the retention window can still read to the next
+    // ones. The compaction filter runs on the first table
+    async fn merge_tables(
+        &mut self,
+        tables: Vec<SkipMapEntries<Key>>,
+    ) -> Result<Vec<Box<dyn InsertableToBucket>>, Error> {
+        self.checkpoint.check()?;
+        let range_tombstones = Arc::clone(&self.range_tombstones);
+        let range_tombstones = range_tombstones.read().await;
+        // Range tombstones created after the oldest readable point in time do not hide versions from it
+        let visible = self.horizon.map(|horizon| horizon.read_options()).unwrap_or_default();
+        let mut layers: Vec<Vec<Entry<Key, ValOffset>>> = vec![Vec::new()];
+        for (key, versions) in SizedTierRunner::versions_by_key(&tables) {
+            // Versions deleted by a range tombstone are dropped
+            let mut versions = versions
+                .into_iter()
+                .filter(|e| !range_tombstones.covers(&key, e.created_at, &visible));
+            let Some(newest) = versions.next() else {
+                continue;
+            };
+            let readable = self.readable_versions(&newest, versions);
+            if readable.is_empty() {
+                self.tombstone_check(&newest, &mut layers[0])
+                    .map_err(|err| TombStoneCheckFailed(err.to_string()))?;
+            } else {
+                // The newest version keeps hiding the older ones, whether it is a tombstone or has expired
+                layers[0].push(newest);
+            }
+            for (layer, entry) in readable.into_iter().enumerate() {
+                if layers.len() == layer + 1 {
+                    layers.push(Vec::new());
                 }
+                layers[layer + 1].push(entry);
+            }
+        }
+        drop(range_tombstones);
+
+        let mut merged: Vec<Box<dyn InsertableToBucket>> = Vec::with_capacity(layers.len());
+        for (layer, entries) in layers.into_iter().enumerate() {
+            let table = Arc::new(SkipMap::new());
+            for e in entries {
+                table.insert(
+                    e.key.to_owned(),
+                    SkipMapValue::new(e.val_offset, e.created_at, e.is_tombstone)
+                        .with_expiry(e.expires_at)
+                        .with_written_at(e.written_at)
+                        .with_inline(e.inline),
+                );
+            }
+            // Versions kept for older reads are written as they are
+            let table = if layer == 0 && self.config.compaction_filter.is_some() {
+                self.apply_compaction_filter(table)
+                    .await
+                    .map_err(|err| CompactionFailed(Box::new(err)))?
+            } else {
+                table
+            };
+            merged.push(Box::new(TableInsertor::from(table)));
+        }
+        Ok(merged)
+    }
+
+    // Returns the versions of each key of `tables`, ordered from the oldest to the newest, newest version first. On
+    // equal creation time the version from the most recent table comes first
+    fn versions_by_key(tables: &[SkipMapEntries<Key>]) -> BTreeMap<Key, Vec<Entry<Key, ValOffset>>> {
+        let mut versions: BTreeMap<Key, Vec<Entry<Key, ValOffset>>> = BTreeMap::new();
+        for entries in tables.iter().rev() {
+            for entry in MergeIterator::source_from_entries(entries) {
+                versions.entry(entry.key.to_owned()).or_default().push(entry);
             }
         }
-        while ptr.a < entries1.len() {
-            self.tombstone_check(&entries1[ptr.a], &mut merged_entries)
-                .map_err(|err| TombStoneCheckFailed(err.to_string()))?;
-            ptr.increment_a();
+        for key_versions in versions.values_mut() {
+            key_versions.sort_by_key(|entry| Reverse(entry.created_at));
         }
+        versions
+    }
 
-        while ptr.b < entries2.len() {
-            self.tombstone_check(&entries2[ptr.b], &mut merged_entries)
-                .map_err(|err| TombStoneCheckFailed(err.to_string()))?;
-            ptr.increment_b();
+    // Returns the versions of `older`, newest first, that follow `newest` and can still be read: a version is read
+    // by the snapshots taken and within the retention window until the one replacing it was written
+    fn readable_versions(
+        &self,
+        newest: &Entry<Key, ValOffset>,
+        older: impl Iterator<Item = Entry<Key, ValOffset>>,
+    ) -> Vec<Entry<Key, ValOffset>> {
+        let Some(horizon) = self.horizon else {
+            return Vec::new();
+        };
+        let mut readable = Vec::new();
+        let (mut created_at, mut written_at) = (newest.created_at, newest.written_at);
+        for entry in older {
+            // A version held by two tables is kept once
+            if entry.created_at == created_at {
+                continue;
+            }
+            if !horizon.reads_replaced(created_at, written_at) {
+                break;
+            }
+            (created_at, written_at) = (entry.created_at, entry.written_at);
+            readable.push(entry);
         }
+        readable
+    }
 
-        merged_entries.iter().for_each(|e| {
-            new_sst_map.insert(
-                e.key.to_owned(),
-                SkipMapValue::new(e.val_offset, e.created_at, e.is_tombstone),
-            );
-        });
-        new_sst.set_entries(new_sst_map);
-        Ok(Box::new(new_sst))
+    // Returns the entries left once the compaction filter decided what to do with each live value of `entries`,
+    // see `CompactionFilter`
+    async fn apply_compaction_filter(&mut self, entries: SkipMapEntries<Key>) -> Result<SkipMapEntries<Key>, Error> {
+        let (Some(filter), Some(vlog)) = (self.config.compaction_filter.as_ref(), self.vlog.as_mut()) else {
+            return Ok(entries);
+        };
+        let filtered = Arc::new(SkipMap::new());
+        let mut rewritten = false;
+        for entry in entries.iter() {
+            let (key, value) = (entry.key(), entry.value());
+            // The head and tail entries hold offsets into the value log rather than the offset of a value
+            let is_offset = key == HEAD_ENTRY_KEY || key == TAIL_ENTRY_KEY;
+            if is_offset || value.is_tombstone || is_expired(value.expires_at) {
+                filtered.insert(key.to_owned(), value.to_owned());
+                continue;
+            }
+            let stored = match value.inline.to_owned() {
+                Some(inline) => Ok(Some((inline, false))),
+                None => vlog.get(value.val_offset).await,
+            };
+            let stored = match stored {
+                Ok(Some((stored, false))) => stored,
+                Ok(_) => {
+                    filtered.insert(key.to_owned(), value.to_owned());
+                    continue;
+                }
+                // Garbage collection reclaimed the value after relocating it, the relocated version shadows this one
+                Err(err) => {
+                    log::warn!("Compaction filter skipped a value that cannot be read: {}", err);
+                    filtered.insert(key.to_owned(), value.to_owned());
+                    continue;
+                }
+            };
+            match filter.filter(key, &stored, value.written_at) {
+                CompactionDecision::Keep => {
+                    filtered.insert(key.to_owned(), value.to_owned());
+                }
+                CompactionDecision::Remove => {
+                    filtered.insert(
+                        key.to_owned(),
+                        SkipMapValue::new(value.val_offset, value.created_at, true).with_written_at(value.written_at),
+                    );
+                }
+                CompactionDecision::ChangeValue(new_value) => {
+                    let val_offset = vlog
+                        .append_rewritten(key, &new_value, value.created_at, value.written_at, value.expires_at)
+                        .await?;
+                    rewritten = true;
+                    filtered.insert(
+                        key.to_owned(),
+                        SkipMapValue::new(val_offset, value.created_at, false)
+                            .with_expiry(value.expires_at)
+                            .with_written_at(value.written_at),
+                    );
+                }
+            }
+        }
+        // The merged SSTable must not point to values that could be lost
+        if rewritten {
+            vlog.sync_to_disk().await?;
+        }
+        Ok(filtered)
     }
 
+    // Expired entries are handled like tombstones, they keep shadowing older versions until `tombstone_ttl`. Past
+    // it they are only dropped if no SSTable left out of the merge may hold an older version of their key, which
+    // would otherwise become readable again
     fn tombstone_check(
         &mut self,
         entry: &Entry<Vec<u8>, usize>,
@@ -311,10 +689,11 @@ impl<'a> SizedTierRunner<'a> {
         let mut should_insert = false;
         if self.tombstones.contains_key(&entry.key) {
             let tomb_insert_time = *self.tombstones.get(&entry.key).unwrap();
-            if entry.created_at > tomb_insert_time {
-                if entry.is_tombstone {
-                    self.tombstones.insert(entry.key.to_owned(), entry.created_at);
-                    should_insert = !entry.has_expired(self.config.tombstone_ttl);
+            // A tombstone merged from another bucket hides the older versions of its key
+            if entry.created_at >= tomb_insert_time {
+                if entry.is_deleted() {
+                    self.record_tombstone(entry);
+                    should_insert = self.retains_tombstone(entry);
                 } else {
                     if self.config.use_ttl {
                         should_insert = !entry.has_expired(self.config.entry_ttl);
@@ -324,9 +703,9 @@ impl<'a> SizedTierRunner<'a> {
                 }
             }
         } else {
-            if entry.is_tombstone {
-                self.tombstones.insert(entry.key.clone(), entry.created_at);
-                should_insert = !entry.has_expired(self.config.tombstone_ttl);
+            if entry.is_deleted() {
+                self.record_tombstone(entry);
+                should_insert = self.retains_tombstone(entry);
             } else {
                 if self.config.use_ttl {
                     should_insert = !entry.has_expired(self.config.entry_ttl);
@@ -337,7 +716,34 @@ impl<'a> SizedTierRunner<'a> {
         }
         if should_insert {
             merged_entries.push(entry.clone())
+        } else if self.is_expired_value(entry) {
+            self.freed.push((entry.val_offset, entry.key.to_owned()));
         }
         Ok(true)
     }
+
+    // Records the tombstone `entry` so that the merges of the other buckets drop the versions it hides, unless a
+    // snapshot or a read within the retention window can still read them
+    fn record_tombstone(&mut self, entry: &Entry<Vec<u8>, usize>) {
+        if !self
+            .horizon
+            .is_some_and(|horizon| horizon.reads_replaced(entry.created_at, entry.written_at))
+        {
+            self.tombstones.insert(entry.key.to_owned(), entry.created_at);
+        }
+    }
+
+    // Returns true if `entry` holds a value dropped because it expired, tombstones and the head and tail entries
+    // do not point to values
+    fn is_expired_value(&self, entry: &Entry<Vec<u8>, usize>) -> bool {
+        if entry.is_tombstone || entry.key == HEAD_ENTRY_KEY || entry.key == TAIL_ENTRY_KEY {
+            return false;
+        }
+        is_expired(entry.expires_at) || (self.config.use_ttl && entry.has_expired(self.config.entry_ttl))
+    }
+
+    // Returns true if the tombstone `entry` must be written to the merged SSTable
+    fn retains_tombstone(&self, entry: &Entry<Vec<u8>, usize>) -> bool {
+        !entry.has_expired(self.config.tombstone_ttl) || self.older_versions.may_hold(&entry.key)
+    }
 }
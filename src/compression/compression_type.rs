@@ -0,0 +1,105 @@
+//! # Compression
+//!
+//! Values at least `Config::value_compression_threshold` bytes long are compressed before they are appended to
+//! the value log, with the algorithm selected in the config. A compressed value is stored behind a byte naming
+//! its algorithm and the 4-byte length of the uncompressed value, the flags byte of its entry records that it is
+//! compressed so entries written uncompressed, or before the config changed, remain readable.
+//!
+//! The data blocks of SSTables are compressed the same way with `Config::sstable_compression`, the length in front
+//! of a block records that it is compressed, see `Block::write_to_file`.
+//!
+//! Zstandard is not offered: LZ4 and Snappy are implemented in-tree and no zstd crate is available to the build. A
+//! zstd codec would take the next free algorithm byte, the stored data already names the algorithm it was written
+//! with.
+
+use super::{lz4, snappy};
+use crate::consts::{SIZE_OF_U32, SIZE_OF_U8};
+
+/// Algorithm compressing the values appended to the value log and the blocks of SSTables
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompressionType {
+    /// Values are stored as they are
+    #[default]
+    None,
+
+    /// LZ4 block format
+    Lz4,
+
+    /// Snappy raw format
+    Snappy,
+}
+
+impl CompressionType {
+    /// Returns the byte identifying the algorithm in front of a compressed value
+    pub(crate) fn as_byte(&self) -> u8 {
+        match self {
+            CompressionType::None => 0,
+            CompressionType::Lz4 => 1,
+            CompressionType::Snappy => 2,
+        }
+    }
+
+    pub(crate) fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(CompressionType::None),
+            1 => Some(CompressionType::Lz4),
+            2 => Some(CompressionType::Snappy),
+            _ => None,
+        }
+    }
+
+    /// Returns `value` compressed along with the byte of the algorithm and its length, `None` if compressing
+    /// does not make it shorter
+    pub(crate) fn compress(&self, value: &[u8]) -> Option<Vec<u8>> {
+        let compressed = match self {
+            CompressionType::None => return None,
+            CompressionType::Lz4 => lz4::compress(value),
+            CompressionType::Snappy => snappy::compress(value),
+        };
+        let stored_len = SIZE_OF_U8 + SIZE_OF_U32 + compressed.len();
+        if stored_len >= value.len() || value.len() > u32::MAX as usize {
+            return None;
+        }
+        let mut stored = Vec::with_capacity(stored_len);
+        stored.push(self.as_byte());
+        stored.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        stored.extend_from_slice(&compressed);
+        Some(stored)
+    }
+
+    /// Returns the value `stored` was compressed from, `None` if it names an unknown algorithm or is corrupted
+    pub(crate) fn decompress(stored: &[u8]) -> Option<Vec<u8>> {
+        let (&algorithm, rest) = stored.split_first()?;
+        if rest.len() < SIZE_OF_U32 {
+            return None;
+        }
+        let (len_bytes, compressed) = rest.split_at(SIZE_OF_U32);
+        let len = u32::from_le_bytes(len_bytes.try_into().unwrap()) as usize;
+        match CompressionType::from_byte(algorithm)? {
+            CompressionType::None => (compressed.len() == len).then(|| compressed.to_vec()),
+            CompressionType::Lz4 => lz4::decompress(compressed, len),
+            CompressionType::Snappy => snappy::decompress(compressed, len),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_round_trip() {
+        let value = b"value_value_value_value_value_value_value_value".repeat(20);
+        let stored = CompressionType::Lz4.compress(&value).unwrap();
+        assert!(stored.len() < value.len());
+        assert_eq!(CompressionType::decompress(&stored).unwrap(), value);
+        let stored = CompressionType::Snappy.compress(&value).unwrap();
+        assert!(stored.len() < value.len());
+        assert_eq!(CompressionType::decompress(&stored).unwrap(), value);
+
+        // Values that do not shrink are stored as they are
+        assert!(CompressionType::Lz4.compress(b"short").is_none());
+        assert!(CompressionType::None.compress(&value).is_none());
+        assert!(CompressionType::decompress(&[9, 0, 0, 0, 0]).is_none());
+    }
+}
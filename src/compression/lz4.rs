@@ -0,0 +1,159 @@
+//! # LZ4
+//!
+//! Block format of LZ4: a sequence of literals copied as they are, each followed by a match copying bytes already
+//! decompressed from a 2-byte offset back. A token packs the number of literals and the length of the match, both
+//! extended by bytes of 255 when they do not fit in its 4 bits. The last sequence only holds literals.
+
+use std::cmp::min;
+
+const MIN_MATCH: usize = 4;
+
+// The last 5 bytes are always literals and a match cannot start within the last 12 bytes of the input
+const LAST_LITERALS: usize = 5;
+const MF_LIMIT: usize = 12;
+
+const MAX_OFFSET: usize = u16::MAX as usize;
+
+const HASH_LOG: u32 = 12;
+
+/// Compresses `input` into an LZ4 block
+pub(crate) fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() + input.len() / 255 + 16);
+    let mut anchor = 0;
+    if input.len() > MF_LIMIT {
+        // Last position each hashed 4 bytes were found at
+        let mut table = vec![usize::MAX; 1 << HASH_LOG];
+        let match_limit = input.len() - LAST_LITERALS;
+        let mut pos = 0;
+        while pos < input.len() - MF_LIMIT {
+            let sequence = read_u32(input, pos);
+            let slot = hash(sequence);
+            let candidate = table[slot];
+            table[slot] = pos;
+            if candidate == usize::MAX || pos - candidate > MAX_OFFSET || read_u32(input, candidate) != sequence {
+                pos += 1;
+                continue;
+            }
+            let mut match_len = MIN_MATCH;
+            while pos + match_len < match_limit && input[candidate + match_len] == input[pos + match_len] {
+                match_len += 1;
+            }
+            write_sequence(&mut output, &input[anchor..pos], Some((pos - candidate, match_len)));
+            pos += match_len;
+            anchor = pos;
+        }
+    }
+    write_sequence(&mut output, &input[anchor..], None);
+    output
+}
+
+/// Decompresses the LZ4 block `input` into `len` bytes, `None` if the block is corrupted or does not hold `len`
+/// bytes
+pub(crate) fn decompress(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(len);
+    let mut pos = 0;
+    loop {
+        let token = *input.get(pos)?;
+        pos += 1;
+        let literals_len = read_len((token >> 4) as usize, input, &mut pos)?;
+        let literals = input.get(pos..pos.checked_add(literals_len)?)?;
+        if output.len() + literals_len > len {
+            return None;
+        }
+        output.extend_from_slice(literals);
+        pos += literals_len;
+        if pos == input.len() {
+            break;
+        }
+
+        let offset = u16::from_le_bytes(input.get(pos..pos + 2)?.try_into().unwrap()) as usize;
+        pos += 2;
+        let match_len = read_len((token & 0xF) as usize, input, &mut pos)? + MIN_MATCH;
+        if offset == 0 || offset > output.len() || output.len() + match_len > len {
+            return None;
+        }
+        // The match can overlap the bytes it produces, it is copied one byte at a time
+        let start = output.len() - offset;
+        for i in start..start + match_len {
+            output.push(output[i]);
+        }
+    }
+    (output.len() == len).then_some(output)
+}
+
+// Writes a sequence of `literals` followed by the match at `offset` back of `len` bytes, the last sequence has
+// no match
+fn write_sequence(output: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_len = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    let token = ((min(literals.len(), 15) as u8) << 4) | min(match_len, 15) as u8;
+    output.push(token);
+    write_len(output, literals.len());
+    output.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        output.extend_from_slice(&(offset as u16).to_le_bytes());
+        write_len(output, match_len);
+    }
+}
+
+// Writes the bytes extending a length of 15 or more held by a token
+fn write_len(output: &mut Vec<u8>, len: usize) {
+    if len < 15 {
+        return;
+    }
+    let mut rest = len - 15;
+    while rest >= 255 {
+        output.push(255);
+        rest -= 255;
+    }
+    output.push(rest as u8);
+}
+
+// Reads the bytes extending the length `len` held by a token
+fn read_len(mut len: usize, input: &[u8], pos: &mut usize) -> Option<usize> {
+    if len < 15 {
+        return Some(len);
+    }
+    loop {
+        let byte = *input.get(*pos)?;
+        *pos += 1;
+        len = len.checked_add(byte as usize)?;
+        if byte != 255 {
+            return Some(len);
+        }
+    }
+}
+
+fn read_u32(input: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(input[pos..pos + 4].try_into().unwrap())
+}
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lz4_round_trip() {
+        let inputs: Vec<Vec<u8>> = vec![
+            Vec::new(),
+            b"abc".to_vec(),
+            b"a".repeat(1000),
+            b"abcdefgh".repeat(300),
+            (0..5000u32).map(|i| (i * 7 % 251) as u8).collect(),
+            (0..3000u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect(),
+        ];
+        for input in inputs {
+            let compressed = compress(&input);
+            assert_eq!(decompress(&compressed, input.len()).unwrap(), input);
+        }
+        assert!(compress(&b"a".repeat(1000)).len() < 50);
+
+        // A block decompressing to another length than expected is rejected
+        let compressed = compress(&b"abcdefgh".repeat(300));
+        assert!(decompress(&compressed, 100).is_none());
+        assert!(decompress(&compressed[..compressed.len() - 1], 2400).is_none());
+    }
+}
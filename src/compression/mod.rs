@@ -0,0 +1,4 @@
+mod compression_type;
+mod lz4;
+mod snappy;
+pub use compression_type::CompressionType;
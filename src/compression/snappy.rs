@@ -0,0 +1,215 @@
+//! # Snappy
+//!
+//! Raw format of Snappy: the length of the uncompressed input as a varint followed by elements, each starting
+//! with a tag whose 2 low bits give its kind. A literal holds its length minus one in the 6 high bits of the tag,
+//! or in the 1 to 4 bytes that follow when it does not fit. A copy repeats bytes already decompressed from an
+//! offset back, with an 11-bit offset and a length of 4 to 11 or with a 2 or 4-byte offset and a length of 1 to
+//! 64.
+
+use std::cmp::min;
+
+const MIN_MATCH: usize = 4;
+
+// Longest match a copy can hold, longer matches are split
+const MAX_COPY_LEN: usize = 64;
+
+// Matches are not looked for within the last bytes of the input
+const INPUT_MARGIN: usize = 15;
+
+const MAX_OFFSET: usize = u16::MAX as usize;
+
+const HASH_LOG: u32 = 12;
+
+const TAG_LITERAL: u8 = 0;
+const TAG_COPY_1: u8 = 1;
+const TAG_COPY_2: u8 = 2;
+
+/// Compresses `input` into a Snappy block
+pub(crate) fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() + input.len() / 6 + 32);
+    write_varint(&mut output, input.len());
+    let mut anchor = 0;
+    if input.len() > INPUT_MARGIN {
+        // Last position each hashed 4 bytes were found at
+        let mut table = vec![usize::MAX; 1 << HASH_LOG];
+        let mut pos = 0;
+        while pos < input.len() - INPUT_MARGIN {
+            let sequence = read_u32(input, pos);
+            let slot = hash(sequence);
+            let candidate = table[slot];
+            table[slot] = pos;
+            if candidate == usize::MAX || pos - candidate > MAX_OFFSET || read_u32(input, candidate) != sequence {
+                pos += 1;
+                continue;
+            }
+            let mut match_len = MIN_MATCH;
+            while pos + match_len < input.len() && input[candidate + match_len] == input[pos + match_len] {
+                match_len += 1;
+            }
+            write_literal(&mut output, &input[anchor..pos]);
+            write_copy(&mut output, pos - candidate, match_len);
+            pos += match_len;
+            anchor = pos;
+        }
+    }
+    write_literal(&mut output, &input[anchor..]);
+    output
+}
+
+/// Decompresses the Snappy block `input` into `len` bytes, `None` if the block is corrupted or does not hold `len`
+/// bytes
+pub(crate) fn decompress(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut pos = 0;
+    if read_varint(input, &mut pos)? != len {
+        return None;
+    }
+    let mut output = Vec::with_capacity(len);
+    while pos < input.len() {
+        let tag = input[pos];
+        pos += 1;
+        let (offset, copy_len) = match tag & 0b11 {
+            TAG_LITERAL => {
+                let literal_len = match (tag >> 2) as usize {
+                    short if short < 60 => short + 1,
+                    long => {
+                        let width = long - 59;
+                        let bytes = input.get(pos..pos.checked_add(width)?)?;
+                        pos += width;
+                        let mut len_bytes = [0; 4];
+                        len_bytes[..width].copy_from_slice(bytes);
+                        (u32::from_le_bytes(len_bytes) as usize).checked_add(1)?
+                    }
+                };
+                let literal = input.get(pos..pos.checked_add(literal_len)?)?;
+                if output.len() + literal_len > len {
+                    return None;
+                }
+                output.extend_from_slice(literal);
+                pos += literal_len;
+                continue;
+            }
+            TAG_COPY_1 => {
+                let low = *input.get(pos)? as usize;
+                pos += 1;
+                (((tag as usize >> 5) << 8) | low, ((tag >> 2) & 0b111) as usize + 4)
+            }
+            TAG_COPY_2 => {
+                let offset = u16::from_le_bytes(input.get(pos..pos + 2)?.try_into().unwrap()) as usize;
+                pos += 2;
+                (offset, (tag >> 2) as usize + 1)
+            }
+            // Copy with a 4-byte offset, never written since offsets are at most `MAX_OFFSET`
+            _ => {
+                let offset = u32::from_le_bytes(input.get(pos..pos + 4)?.try_into().unwrap()) as usize;
+                pos += 4;
+                (offset, (tag >> 2) as usize + 1)
+            }
+        };
+        if offset == 0 || offset > output.len() || output.len() + copy_len > len {
+            return None;
+        }
+        // The copy can overlap the bytes it produces, it is copied one byte at a time
+        let start = output.len() - offset;
+        for i in start..start + copy_len {
+            output.push(output[i]);
+        }
+    }
+    (output.len() == len).then_some(output)
+}
+
+// Writes `literal` as a literal element, nothing if it is empty
+fn write_literal(output: &mut Vec<u8>, literal: &[u8]) {
+    if literal.is_empty() {
+        return;
+    }
+    let n = literal.len() - 1;
+    if n < 60 {
+        output.push(((n as u8) << 2) | TAG_LITERAL);
+    } else {
+        let width = (usize::BITS - n.leading_zeros()).div_ceil(8) as usize;
+        output.push((((59 + width) as u8) << 2) | TAG_LITERAL);
+        output.extend_from_slice(&(n as u32).to_le_bytes()[..width]);
+    }
+    output.extend_from_slice(literal);
+}
+
+// Writes copies of `len` bytes from `offset` back, split in copies of at most `MAX_COPY_LEN` bytes, the 1-byte
+// offset form is used where it fits
+fn write_copy(output: &mut Vec<u8>, offset: usize, mut len: usize) {
+    while len > 0 {
+        let copy_len = min(len, MAX_COPY_LEN);
+        if (MIN_MATCH..12).contains(&copy_len) && offset < 2048 {
+            output.push((((offset >> 8) as u8) << 5) | (((copy_len - 4) as u8) << 2) | TAG_COPY_1);
+            output.push(offset as u8);
+        } else {
+            output.push((((copy_len - 1) as u8) << 2) | TAG_COPY_2);
+            output.extend_from_slice(&(offset as u16).to_le_bytes());
+        }
+        len -= copy_len;
+    }
+}
+
+fn write_varint(output: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        output.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+fn read_varint(input: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut value = 0usize;
+    for shift in (0..35).step_by(7) {
+        let byte = *input.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn read_u32(input: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(input[pos..pos + 4].try_into().unwrap())
+}
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(0x1E35A7BD) >> (32 - HASH_LOG)) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snappy_round_trip() {
+        let inputs: Vec<Vec<u8>> = vec![
+            Vec::new(),
+            b"abc".to_vec(),
+            b"a".repeat(1000),
+            b"abcdefgh".repeat(300),
+            (0..5000u32).map(|i| (i * 7 % 251) as u8).collect(),
+            (0..3000u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect(),
+            (0..70000u32)
+                .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+                .collect(),
+        ];
+        for input in inputs {
+            let compressed = compress(&input);
+            assert_eq!(decompress(&compressed, input.len()).unwrap(), input);
+        }
+        assert!(compress(&b"a".repeat(1000)).len() < 60);
+
+        // A block of the Snappy reference implementation: literal "abcd" then a copy of 4 bytes 4 back
+        assert_eq!(
+            decompress(&[8, 0b1100, b'a', b'b', b'c', b'd', 0b1, 4], 8).unwrap(),
+            b"abcdabcd".to_vec()
+        );
+
+        // A block decompressing to another length than expected is rejected
+        let compressed = compress(&b"abcdefgh".repeat(300));
+        assert!(decompress(&compressed, 100).is_none());
+        assert!(decompress(&compressed[..compressed.len() - 1], 2400).is_none());
+    }
+}
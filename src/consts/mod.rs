@@ -4,6 +4,9 @@ pub const GC_CHUNK_SIZE: usize = SizeUnit::Kilobytes.to_bytes(1);
 pub const KB: usize = 1024;
 pub const WRITE_BUFFER_SIZE: usize = SizeUnit::Kilobytes.to_bytes(50);
 
+/// Entry size (in bytes) assumed when sizing the bloom filter of a memtable that has not observed any entry yet
+pub const DEFAULT_AVG_ENTRY_SIZE: usize = 100;
+
 pub const DEFAULT_MAX_WRITE_BUFFER_NUMBER: usize = 2;
 
 pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 1e-300;
@@ -16,8 +19,32 @@ pub const BUCKET_DIRECTORY_PREFIX: &str = "bucket";
 
 pub const VLOG_FILE_NAME: &str = "val_log.bin";
 
+pub const VLOG_MEDIUM_FILE_NAME: &str = "val_log_medium.bin";
+
+pub const VLOG_LARGE_FILE_NAME: &str = "val_log_large.bin";
+
 pub const META_DIRECTORY_NAME: &str = "meta";
 
+pub const QUARANTINE_DIRECTORY_NAME: &str = "quarantine";
+
+pub const META_FILE_NAME: &str = "meta.bin";
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.bin";
+
+pub const RANGE_TOMBSTONES_FILE_NAME: &str = "range_tombstones.bin";
+
+pub const IDEMPOTENCY_TOKENS_FILE_NAME: &str = "idempotency_tokens.bin";
+
+pub const PREPARED_BATCHES_FILE_NAME: &str = "prepared_batches.bin";
+
+pub const LOCK_FILE_NAME: &str = "LOCK";
+
+/// Written to the value log directory on open to find out what its file system supports, then removed
+pub const FS_PROBE_FILE_NAME: &str = "fs_probe.tmp";
+
+/// Extension of files and directories being written, they are renamed once complete
+pub const TEMP_EXTENSION: &str = "tmp";
+
 pub const TOMB_STONE_MARKER: &str = "*";
 
 // This is a minimum time that must pass since the last compaction attempt for a specific data file (SSTable).
@@ -36,6 +63,15 @@ pub const DEFAULT_ONLINE_GARBAGE_COLLECTION_INTERVAL_MILLI: u64 = 36000000;
 
 pub const DEFAULT_FLUSH_SIGNAL_CHANNEL_SIZE: usize = 1;
 
+// Compaction does not check the free disk space by default
+pub const DEFAULT_RESERVED_DISK_SPACE: u64 = 0;
+
+// Events a subscriber can lag behind before it misses some
+pub const DEFAULT_SUBSCRIPTION_CHANNEL_SIZE: usize = 1024;
+
+// Errors a subscriber to background errors can lag behind, only one is sent until writes are resumed
+pub const BACKGROUND_ERROR_CHANNEL_SIZE: usize = 16;
+
 // tombstone should only be removed after 120 days to guarantee that obsolete data don't
 // resurrect by prematurelly deleting tombstone
 pub const DEFAULT_TOMBSTONE_TTL: u64 = 120 * 86400000;
@@ -48,6 +84,7 @@ pub const DEFUALT_ENABLE_TTL: bool = false;
 
 pub const ENTRY_TTL: u64 = 1 * 86400000;
 
+// Default size-tiered parameters of the buckets, see the `Config` fields of the same name
 pub const BUCKET_LOW: f64 = 0.5;
 
 pub const BUCKET_HIGH: f64 = 1.5;
@@ -58,10 +95,68 @@ pub const MIN_TRESHOLD: usize = 4;
 
 pub const MAX_TRESHOLD: usize = 32;
 
+// The SSTables written by the sub-compactions of a bucket land in the same bucket, there must be fewer of them
+// than MIN_TRESHOLD so that they do not make it compacted again
+pub const DEFAULT_MAX_SUBCOMPACTIONS: usize = MIN_TRESHOLD - 1;
+
+// Share of dead entries from which a bucket of at least two SSTables is compacted
+pub const DEFAULT_TOMBSTONE_COMPACTION_RATIO: f64 = 0.5;
+
+// Hotness of the SSTables halves every day
+pub const DEFAULT_HOTNESS_HALF_LIFE_MILLI: u64 = 86400000;
+
+// Gets reading this many SSTables on average make compaction merge buckets of at least two SSTables
+pub const DEFAULT_READ_AMP_COMPACTION_THRESHOLD: f64 = 4.0;
+
+// Garbage collection only runs on its interval unless a share of garbage that wakes it up is set
+pub const DEFAULT_GC_GARBAGE_RATIO: f64 = 0.0;
+
+// Background garbage collection collects the chunks of a run back to back by default
+pub const DEFAULT_GC_CHUNK_INTERVAL_MILLI: u64 = 0;
+
+// Live values rewritten by garbage collection are not limited by default
+pub const DEFAULT_GC_REWRITE_RATE_LIMIT: u64 = 0;
+
+// Garbage collection runs kept by `DataStore::gc_history`, older runs are dropped
+pub const GC_RUN_HISTORY_LEN: usize = 64;
+
+// Fewest gets from which the SSTables they read say something of the workload
+pub const MIN_READ_AMP_SAMPLE_GETS: u64 = 100;
+
+// Compaction reads and writes are not limited by default
+pub const DEFAULT_COMPACTION_RATE_LIMIT: u64 = 0;
+
+// Fewest entries a sub-compaction is given, smaller compactions are not split
+pub const MIN_SUBCOMPACTION_ENTRIES: usize = 1024;
+
 pub const DEFAULT_ALLOW_PREFETCH: bool = true;
 
 pub const DEFAULT_PREFETCH_SIZE: usize = 10;
 
+pub const DEFAULT_MAX_PARALLEL_SSTABLE_READS: usize = 8;
+
+pub const DEFAULT_BLOCK_CACHE_SIZE: usize = SizeUnit::Megabytes.to_bytes(8);
+
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 4 * 1024 * 1024; // 4MB
+
+pub const DEFAULT_IDEMPOTENCY_TOKEN_TTL: u64 = 86400000; // 1 day
+
+// Expired idempotency tokens left in the file before it is rewritten, if they also outnumber the live ones
+pub const IDEMPOTENCY_TOKENS_REWRITE_THRESHOLD: usize = 1024;
+
+// Only the latest version of a key is kept by default
+pub const DEFAULT_VERSION_RETENTION_MILLI: u64 = 0;
+
+pub const DEFAULT_MAX_KEY_SIZE: usize = KB; // 1KB
+
+pub const DEFAULT_MAX_VALUE_SIZE: usize = 1024 * 1024 * 1024; // 1GB
+
+// Size of the chunks a streamed value is copied to the value log in
+pub const VLOG_STREAM_CHUNK_SIZE: usize = 64 * KB;
+
+// Records of a batched value log read that lie closer than this are read at once, the bytes between them included
+pub const VLOG_READ_COALESCE_GAP: usize = 16 * KB;
+
 pub const EOF: &str = "EOF";
 
 pub const HEAD_ENTRY_KEY: &[u8; 4] = b"head";
@@ -88,4 +183,96 @@ pub const SIZE_OF_U64: usize = std::mem::size_of::<u64>();
 
 pub const SIZE_OF_U8: usize = std::mem::size_of::<u8>();
 
+// Bits of the flags byte stored after the creation date of sstable and value log entries,
+// entries written before expiry support only ever set the tombstone bit
+pub const TOMBSTONE_FLAG: u8 = 1;
+
+// When set, the flags byte is followed by an 8 byte expiry time in milliseconds
+pub const EXPIRY_FLAG: u8 = 1 << 1;
+
+// When set on a value log entry, the entry ends with an 8 byte XXH64 instead of a 4 byte CRC32C
+pub const XXHASH64_FLAG: u8 = 1 << 2;
+
+// When set on a value log entry, the entry holds a value rewritten by a compaction filter. The merged SSTable
+// references it, it is not replayed into a memtable on recovery
+pub const REWRITTEN_FLAG: u8 = 1 << 3;
+
+// When set on a value log entry, its value is compressed and starts with the byte of its algorithm and its
+// uncompressed length, see `CompressionType`
+pub const COMPRESSED_FLAG: u8 = 1 << 4;
+
+// Values shorter than this are not compressed
+pub const DEFAULT_VALUE_COMPRESSION_THRESHOLD: usize = 512;
+
+// When set on a value log entry, its value is the 8-byte location of a value appended to the log of another
+// `ValueClass`
+pub const REDIRECT_FLAG: u8 = 1 << 5;
+
+// Bits of the flags byte of sstable entries holding the `ValueClass` of the value log their offset belongs to
+pub const VALUE_CLASS_SHIFT: u8 = 2;
+
+pub const VALUE_CLASS_MASK: u8 = 0b11 << VALUE_CLASS_SHIFT;
+
+// Values are appended to the main value log whatever their size unless the thresholds are set
+pub const DEFAULT_MEDIUM_VALUE_THRESHOLD: usize = usize::MAX;
+
+pub const DEFAULT_LARGE_VALUE_THRESHOLD: usize = usize::MAX;
+
+// When set on an sstable entry, its value follows the expiry time, prefixed by its 4-byte length
+pub const INLINE_VALUE_FLAG: u8 = 1 << 4;
+
+// When set on an sstable entry, its expiry time is followed by the 8 byte wall-clock time in milliseconds the entry was
+// written at. Entries written before it was recorded were created at a wall-clock time, which stands in for it
+pub const WRITTEN_AT_FLAG: u8 = 1 << 5;
+
+// Values are only stored in the value log unless the threshold is set
+pub const DEFAULT_INLINE_VALUE_THRESHOLD: usize = 0;
+
+// SSTables are all compressed with `sstable_compression` unless the threshold is set
+pub const DEFAULT_COLD_SSTABLE_SIZE: usize = 0;
+
+// When set on the length in front of an sstable block, the bytes it counts are the compressed entries of the block
+pub const BLOCK_COMPRESSED_FLAG: u32 = 1 << 31;
+
+// Records appended to the value log are written right away unless the buffer size is set
+pub const DEFAULT_VALUE_LOG_BUFFER_SIZE: usize = 0;
+
+pub const DEFAULT_VALUE_LOG_BUFFER_INTERVAL_MILLI: u64 = 10;
+
+// The value log file grows with every append unless the preallocation size is set
+pub const DEFAULT_VALUE_LOG_PREALLOCATION_SIZE: usize = 0;
+
+// The value log is only synced by the writes that ask for it unless the sync interval is set
+pub const DEFAULT_VALUE_LOG_SYNC_INTERVAL_MILLI: u64 = 0;
+
+// Value log files start with this magic followed by the version of their record format, files written
+// before the header existed start with their first entry
+pub const VLOG_MAGIC: &[u8; 4] = b"VLOG";
+
+pub const VLOG_FORMAT_VERSION: u32 = 1;
+
+// Magic followed by the 4 byte format version
+pub const VLOG_HEADER_SIZE: usize = 8;
+
+// Version of the value log files rewritten by copy-and-truncate garbage collection, their records are laid out as in
+// `VLOG_FORMAT_VERSION` but do not start at offset 0
+pub const VLOG_SEGMENT_FORMAT_VERSION: u32 = 2;
+
+// Magic, format version and the 8 byte offset of the first byte of the file
+pub const VLOG_SEGMENT_HEADER_SIZE: usize = 16;
+
+// First byte of every value log record, a record starting with another byte was not fully written
+pub const VLOG_RECORD_MAGIC: u8 = 0xA5;
+
 pub const FLUSH_SIGNAL: u8 = 1;
+
+// Number of random keys used to estimate the realized false positive rate of a bloom filter during verification
+pub const VERIFY_ABSENT_KEY_SAMPLE_SIZE: usize = 1000;
+
+pub const VERIFY_ABSENT_KEY_LENGTH: usize = 16;
+
+pub const DEFAULT_VERIFY_CHECKSUMS: bool = true;
+
+pub const DEFAULT_FILL_CACHE: bool = true;
+
+pub const DEFAULT_SYNC_WRITES: bool = false;
@@ -1,5 +1,6 @@
 use std::{io, path::PathBuf};
 use thiserror::Error;
+use Error::*;
 
 #[derive(Debug, Error)]
 #[non_exhaustive]
@@ -37,6 +38,25 @@ pub enum Error {
     #[error("Failed to open bucket directory `{path}`: {error}")]
     DirectoryOpenError { path: PathBuf, error: io::Error },
 
+    #[error("The store was opened read-only")]
+    ReadOnlyStore,
+
+    #[error("Writes are halted after a background error, call `resume` once its cause is addressed: {0}")]
+    BackgroundError(String),
+
+    #[error("Only {available} bytes are free on disk, below the {reserved} bytes kept in reserve")]
+    NotEnoughDiskSpace { available: u64, reserved: u64 },
+
+    #[error(
+        "Failed to acquire lock on `{path}`, the directory is already opened by {}: {error}",
+        .holder.map_or("another process".to_string(), |pid| format!("process {}", pid))
+    )]
+    FileLockError {
+        path: PathBuf,
+        holder: Option<u32>,
+        error: io::Error,
+    },
+
     #[error("File read ended unexpectedly")]
     UnexpectedEOF(#[source] io::Error),
 
@@ -150,4 +170,72 @@ pub enum Error {
 
     #[error("Tokio join tasks error")]
     TokioJoinError,
+
+    #[error("Compaction was cancelled, the SSTables it wrote were discarded")]
+    CompactionCancelled,
+
+    #[error("Invalid continuation token `{0}`")]
+    InvalidContinuationToken(String),
+
+    #[error("Value of key `{0}` is not an 8 byte counter")]
+    InvalidCounterValue(String),
+
+    #[error("Counter of key `{0}` overflowed")]
+    CounterOverflow(String),
+
+    #[error("No savepoint is set on the write batch")]
+    NoSavepoint,
+
+    #[error("No batch is prepared under token `{0}`")]
+    UnknownPreparedToken(u64),
+
+    #[error("Group commit failed: {0}")]
+    GroupCommitFailed(String),
+
+    #[error("Key of {size} bytes exceeds the maximum of {max} bytes")]
+    KeyTooLarge { size: usize, max: usize },
+
+    #[error("Value of {size} bytes exceeds the maximum of {max} bytes")]
+    ValueTooLarge { size: usize, max: usize },
+
+    #[error("Checksum mismatch for the value log entry at offset {offset}, the entry is corrupted")]
+    CorruptedValueLogEntry { offset: usize },
+
+    #[error("Value log `{path}` was written in format version {version}, which is not supported")]
+    UnsupportedValueLogVersion { path: PathBuf, version: u32 },
+
+    #[error("Checksum mismatch for the block at offset {offset} of `{path}`, the block is corrupted")]
+    ChecksumMismatch { path: PathBuf, offset: usize },
+
+    #[error("Unknown checksum algorithm `{checksum_type}` recorded in `{path}`")]
+    UnknownChecksumType { path: PathBuf, checksum_type: u8 },
+}
+
+impl Error {
+    /// Returns true if the error was caused by the disk being full
+    pub fn is_disk_full(&self) -> bool {
+        let io_error = match self {
+            FileSyncError { error }
+            | FileCreationError { error, .. }
+            | FileOpenError { error, .. }
+            | DirCreationError { error, .. }
+            | FileWriteError { error, .. }
+            | GCErrorFailedToPunchHoleInVlogFile(error) => error,
+            FlushToDiskError { error } => return error.is_disk_full(),
+            FlushError(err) | CompactionFailed(err) | CompactionPartiallyFailed(err) | CompactionCleanupError(err) => {
+                return err.is_disk_full()
+            }
+            _ => return false,
+        };
+        io_error.kind() == io::ErrorKind::StorageFull
+    }
+
+    /// Returns true if the error was returned by a cancelled compaction
+    pub fn is_cancelled(&self) -> bool {
+        match self {
+            CompactionCancelled => true,
+            CompactionFailed(err) | CompactionPartiallyFailed(err) | CompactionCleanupError(err) => err.is_cancelled(),
+            _ => false,
+        }
+    }
 }
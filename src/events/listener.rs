@@ -0,0 +1,14 @@
+//! # Event listener
+//!
+//! An event listener registered with `Config::event_listener` is called when background work of the store
+//! completes, e.g. to export metrics or to alert when garbage collection reclaims less than it used to. It is
+//! called on the task that did the work, it should return quickly and must not call back into the store.
+
+use crate::gc::GCRun;
+use std::fmt::Debug;
+
+/// Notified of the work the store does in the background, every method does nothing unless implemented
+pub trait EventListener: Debug + Send + Sync {
+    /// Called once a garbage collection run completed, in the background or through `DataStore::run_gc`
+    fn on_gc_completed(&self, _run: &GCRun) {}
+}
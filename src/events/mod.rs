@@ -0,0 +1,2 @@
+mod listener;
+pub use listener::EventListener;
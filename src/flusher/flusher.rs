@@ -1,6 +1,9 @@
 use crate::bucket::bucket::InsertableToBucket;
-use crate::consts::FLUSH_SIGNAL;
+use crate::compactors::CompactionProgress;
+use crate::consts::{FLUSH_SIGNAL, HEAD_ENTRY_KEY};
 use crate::flusher::flusher::Error::FlushError;
+use crate::meta::Meta;
+use crate::storage::BackgroundErrors;
 use crate::types::{self, BloomFilterHandle, BucketMapHandle, FlushSignal, ImmutableMemTable, KeyRangeHandle};
 use crate::{err::Error, memtable::MemTable};
 use std::sync::Arc;
@@ -15,6 +18,15 @@ pub struct Flusher {
     pub(crate) bucket_map: BucketMapHandle,
     pub(crate) filters: BloomFilterHandle,
     pub(crate) key_range: KeyRangeHandle,
+
+    /// Records the head of the value log once the entries before it are flushed
+    pub(crate) meta: Meta,
+
+    /// Failed background flushes are recorded in it
+    pub(crate) background_errors: BackgroundErrors,
+
+    /// Counts the flushes background compaction has yet to cover
+    pub(crate) progress: CompactionProgress,
 }
 
 impl Flusher {
@@ -23,15 +35,26 @@ impl Flusher {
         bucket_map: BucketMapHandle,
         filters: BloomFilterHandle,
         key_range: KeyRangeHandle,
+        meta: Meta,
+        background_errors: BackgroundErrors,
     ) -> Self {
         Self {
             read_only_memtable,
             bucket_map,
             filters,
             key_range,
+            meta,
+            background_errors,
+            progress: CompactionProgress::new(),
         }
     }
 
+    /// Shares `progress` with the compactor
+    pub fn with_compaction_progress(mut self, progress: CompactionProgress) -> Self {
+        self.progress = progress;
+        self
+    }
+
     pub async fn flush(&mut self, table: InActiveMemtable) -> Result<(), Error> {
         let flush_data = self;
         let table_lock = table.read().await;
@@ -42,11 +65,19 @@ impl Flusher {
         let filter = &mut table_lock.bloom_filter.to_owned();
         let biggest_key = table_lock.find_biggest_key()?;
         let smallest_key = table_lock.find_smallest_key()?;
+        let head = table_lock
+            .entries
+            .get(HEAD_ENTRY_KEY.as_slice())
+            .map(|e| e.value().val_offset);
         let mut bucket_lock = flush_data.bucket_map.write().await;
         let sst = bucket_lock
             .insert_to_appropriate_bucket(Arc::new(Box::new(table_lock.to_owned())))
             .await?;
         drop(table_lock);
+        // The SSTable is in the manifest, the entries before the head it holds no longer need to be replayed
+        if let Some(head) = head {
+            flush_data.meta.advance_v_log_head(head).await?;
+        }
         let data_file_path = sst.get_data_file_path().clone();
         flush_data
             .key_range
@@ -62,6 +93,7 @@ impl Flusher {
             .write()
             .await
             .sort_by(|a, b| b.get_sst().get_hotness().cmp(&a.get_sst().get_hotness()));
+        flush_data.progress.flushed();
 
         Ok(())
     }
@@ -77,8 +109,19 @@ impl Flusher {
         let filters = self.filters.clone();
         let key_range = self.key_range.clone();
         let read_only_memtable = self.read_only_memtable.clone();
+        let meta = self.meta.clone();
+        let background_errors = self.background_errors.clone();
+        let progress = self.progress.clone();
         tokio::spawn(async move {
-            let mut flusher = Flusher::new(read_only_memtable.clone(), buckets, filters, key_range);
+            let mut flusher = Flusher::new(
+                read_only_memtable.clone(),
+                buckets,
+                filters,
+                key_range,
+                meta,
+                background_errors.clone(),
+            )
+            .with_compaction_progress(progress);
             match flusher.flush(table_to_flush).await {
                 Ok(_) => {
                     let mut tables = read_only_memtable.write().await;
@@ -93,7 +136,10 @@ impl Flusher {
                     }
                 }
                 Err(err) => {
-                    log::error!("{}", FlushError(Box::new(err)))
+                    // The memtable is kept, it is flushed again with the next ones
+                    let err = FlushError(Box::new(err));
+                    log::error!("{}", err);
+                    background_errors.record(err);
                 }
             }
         });
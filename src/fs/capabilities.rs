@@ -0,0 +1,81 @@
+//! # File system capabilities
+//!
+//! Not every file system can punch holes, allocate space ahead or bypass the page cache, and outside Linux none of
+//! them is available. The file system holding the value log is probed when the store is opened so that garbage
+//! collection and the value log pick a strategy it supports instead of failing at runtime.
+
+use crate::consts::FS_PROBE_FILE_NAME;
+#[cfg(target_os = "linux")]
+use std::os::unix::{fs::OpenOptionsExt, io::AsRawFd};
+use std::path::Path;
+
+// Length of the hole punched in the probe file and of the space allocated past its end, a file system block
+#[cfg(target_os = "linux")]
+const PROBE_BLOCK_LEN: usize = 4096;
+
+/// Operations supported by the file system holding the value log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FsCapabilities {
+    /// Disk space can be released from the middle of a file (`FALLOC_FL_PUNCH_HOLE`)
+    pub punch_hole: bool,
+
+    /// Disk space can be allocated past the end of a file without changing its length (`fallocate` with
+    /// `FALLOC_FL_KEEP_SIZE`)
+    pub fallocate: bool,
+
+    /// Files can be opened with `O_DIRECT` to bypass the page cache
+    pub direct_io: bool,
+}
+
+impl FsCapabilities {
+    /// Probes the file system of the directory `dir` on a file written to it, the file is removed afterwards
+    ///
+    /// A capability that cannot be probed is reported as missing, as is every capability outside Linux
+    pub async fn probe(dir: &Path) -> FsCapabilities {
+        let probe_path = dir.join(FS_PROBE_FILE_NAME);
+        let capabilities = FsCapabilities::probe_file(&probe_path).await;
+        let _ = tokio::fs::remove_file(&probe_path).await;
+        log::info!("File system of {:?} supports {:?}", dir, capabilities);
+        capabilities
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn probe_file(probe_path: &Path) -> FsCapabilities {
+        if let Err(err) = tokio::fs::write(probe_path, vec![0; 2 * PROBE_BLOCK_LEN]).await {
+            log::warn!("Failed to write {:?}: {}", probe_path, err);
+            return FsCapabilities::default();
+        }
+        let probe_path = probe_path.to_owned();
+        tokio::task::spawn_blocking(move || {
+            let Ok(file) = std::fs::OpenOptions::new().write(true).open(&probe_path) else {
+                return FsCapabilities::default();
+            };
+            let fallocate = |mode, offset, len| unsafe {
+                libc::fallocate(file.as_raw_fd(), mode, offset as libc::off_t, len as libc::off_t) == 0
+            };
+            let punch_hole = fallocate(
+                libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                0,
+                PROBE_BLOCK_LEN,
+            );
+            let fallocate = fallocate(libc::FALLOC_FL_KEEP_SIZE, 2 * PROBE_BLOCK_LEN, PROBE_BLOCK_LEN);
+            let direct_io = std::fs::OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_DIRECT)
+                .open(&probe_path)
+                .is_ok();
+            FsCapabilities {
+                punch_hole,
+                fallocate,
+                direct_io,
+            }
+        })
+        .await
+        .unwrap_or_default()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn probe_file(_probe_path: &Path) -> FsCapabilities {
+        FsCapabilities::default()
+    }
+}
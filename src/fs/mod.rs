@@ -1,6 +1,21 @@
+mod capabilities;
+
+pub use capabilities::FsCapabilities;
+
 use async_trait::async_trait;
 use crossbeam_skiplist::SkipMap;
-use std::{fmt::Debug, fs::Metadata, io::SeekFrom, path::PathBuf, sync::Arc};
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::{
+    fmt::Debug,
+    fs::Metadata,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 use tokio::{
     fs::{self, File, OpenOptions},
     io::{self, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
@@ -8,13 +23,19 @@ use tokio::{
 };
 
 use crate::{
-    consts::{EOF, SIZE_OF_U32, SIZE_OF_U64, SIZE_OF_U8},
+    block::{Block, BlockCache, BlockEntry},
+    checksum::{Checksum, ChecksumType},
+    compression::CompressionType,
+    consts::{
+        COMPRESSED_FLAG, EOF, EXPIRY_FLAG, REDIRECT_FLAG, REWRITTEN_FLAG, SIZE_OF_U32, SIZE_OF_U64, SIZE_OF_U8,
+        TEMP_EXTENSION, TOMBSTONE_FLAG, VLOG_FORMAT_VERSION, VLOG_HEADER_SIZE, VLOG_MAGIC, VLOG_READ_COALESCE_GAP,
+        VLOG_RECORD_MAGIC, VLOG_SEGMENT_FORMAT_VERSION, VLOG_SEGMENT_HEADER_SIZE, XXHASH64_FLAG,
+    },
     err::Error::{self, *},
-    index::RangeOffset,
     load_buffer,
-    memtable::{Entry, SkipMapValue},
-    types::{CreationTime, IsTombStone, Key, NoBytesRead, SkipMapEntries, ValOffset},
-    value_log::ValueLogEntry,
+    memtable::{is_expired, SkipMapValue},
+    types::{ExpiresAt, FoundEntry, IsTombStone, Key, NoBytesRead, SkipMapEntries, ValOffset, ValueReader},
+    value_log::{ValueLogEntry, ValueLogFormat},
 };
 
 #[derive(Debug, Clone)]
@@ -65,15 +86,19 @@ pub trait FileAsync: Send + Sync + Debug + Clone {
 #[async_trait]
 pub trait DataFs: Send + Sync + Debug + Clone {
     async fn new(path: PathBuf, file_type: FileType) -> Result<Self, Error>;
-    async fn load_entries(&self) -> Result<(SkipMapEntries<Key>, usize), Error>;
+    async fn load_entries(
+        &self,
+        verify_checksums: bool,
+        cache: Option<&BlockCache>,
+    ) -> Result<(SkipMapEntries<Key>, usize), Error>;
 
     async fn find_entry(
         &self,
         offset: u32,
         searched_key: &[u8],
-    ) -> Result<Option<(ValOffset, CreationTime, IsTombStone)>, Error>;
-
-    async fn load_entries_within_range(&self, range_offset: RangeOffset) -> Result<Vec<Entry<Vec<u8>, usize>>, Error>;
+        verify_checksums: bool,
+        cache: Option<&BlockCache>,
+    ) -> Result<Option<FoundEntry>, Error>;
 }
 
 #[async_trait]
@@ -82,7 +107,13 @@ pub trait VLogFs: Send + Sync + Debug + Clone {
 
     async fn get(&self, start_offset: usize) -> Result<Option<(Vec<u8>, bool)>, Error>;
 
-    async fn recover(&self, start_offset: usize) -> Result<Vec<ValueLogEntry>, Error>;
+    async fn get_many(&self, start_offsets: &[usize]) -> Result<Vec<Option<(Vec<u8>, bool)>>, Error>;
+
+    async fn get_stream(&self, start_offset: usize) -> Result<Option<(ValueReader, bool)>, Error>;
+
+    async fn recover(&self, start_offset: usize, truncate: bool) -> Result<Vec<ValueLogEntry>, Error>;
+
+    async fn verify(&self, start_offset: usize) -> Result<(Vec<(ValOffset, Key)>, Option<(ValOffset, Error)>), Error>;
 
     async fn read_chunk_to_garbage_collect(
         &self,
@@ -97,7 +128,7 @@ pub trait IndexFs: Send + Sync + Debug + Clone {
 
     async fn get_from_index(&self, searched_key: &[u8]) -> Result<Option<u32>, Error>;
 
-    async fn get_block_range(&self, start_key: &[u8], end_key: &[u8]) -> Result<RangeOffset, Error>;
+    async fn load_keys(&self) -> Result<Vec<Key>, Error>;
 }
 
 #[derive(Debug, Clone)]
@@ -213,7 +244,11 @@ impl DataFs for DataFileNode {
         let node = FileNode::new(path, file_type).await?;
         Ok(DataFileNode { node })
     }
-    async fn load_entries(&self) -> Result<(SkipMapEntries<Key>, NoBytesRead), Error> {
+    async fn load_entries(
+        &self,
+        verify_checksums: bool,
+        cache: Option<&BlockCache>,
+    ) -> Result<(SkipMapEntries<Key>, NoBytesRead), Error> {
         let entries = Arc::new(SkipMap::new());
         let mut total_bytes_read = 0;
         let path = &self.node.file_path;
@@ -222,47 +257,53 @@ impl DataFs for DataFileNode {
             .await
             .map_err(|err| FileSeekError(err))?;
 
-        loop {
-            let mut key_len_bytes = [0; SIZE_OF_U32];
-            let mut bytes_read = load_buffer!(file, &mut key_len_bytes, path.to_owned())?;
-            total_bytes_read += bytes_read;
-            if bytes_read == 0 {
-                break;
-            }
-
-            let key_len = u32::from_le_bytes(key_len_bytes);
-            let mut key = vec![0; key_len as usize];
-            bytes_read = load_buffer!(file, &mut key, path.to_owned())?;
-            total_bytes_read += bytes_read;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
-            }
-
-            let mut val_offset_bytes = [0; SIZE_OF_U32];
-            bytes_read = load_buffer!(file, &mut val_offset_bytes, path.to_owned())?;
-            total_bytes_read += bytes_read;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
-            }
-
-            let mut created_at_bytes = [0; SIZE_OF_U64];
-            bytes_read = load_buffer!(file, &mut created_at_bytes, path.to_owned())?;
-            total_bytes_read += bytes_read;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
-            }
-
-            let mut is_tombstone_byte = [0; SIZE_OF_U8];
-            bytes_read = load_buffer!(file, &mut is_tombstone_byte, path.to_owned())?;
+        let Some(checksum_type) = FileNode::load_checksum_type(&mut file, path.to_owned()).await? else {
+            return Ok((entries, total_bytes_read));
+        };
+        total_bytes_read += SIZE_OF_U8;
+        let file_len = file.metadata().await.map_err(GetFileMetaDataError)?.len() as usize;
+        // Set once cached blocks were skipped, the file is then no longer positioned on the next block
+        let mut needs_seek = false;
+        while total_bytes_read < file_len {
+            let cached = cache.and_then(|cache| cache.get(path, total_bytes_read));
+            let (block, bytes_read) = match cached {
+                Some(cached) => {
+                    needs_seek = true;
+                    cached
+                }
+                None => {
+                    if needs_seek {
+                        file.seek(std::io::SeekFrom::Start(total_bytes_read as u64))
+                            .await
+                            .map_err(FileSeekError)?;
+                        needs_seek = false;
+                    }
+                    let Some((block, bytes_read)) = FileNode::load_block(
+                        &mut file,
+                        total_bytes_read,
+                        checksum_type,
+                        verify_checksums,
+                        path.to_owned(),
+                    )
+                    .await?
+                    else {
+                        break;
+                    };
+                    let block = Arc::new(block);
+                    if let (Some(cache), true) = (cache, verify_checksums) {
+                        cache.insert(path, total_bytes_read, block.clone(), bytes_read);
+                    }
+                    (block, bytes_read)
+                }
+            };
             total_bytes_read += bytes_read;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
+            for entry in block.iter() {
+                let value = SkipMapValue::new(entry.location(), entry.creation_date, entry.is_tombstone)
+                    .with_written_at(entry.written_at)
+                    .with_expiry(entry.expires_at)
+                    .with_inline(entry.inline.to_owned());
+                entries.insert(entry.key.to_owned(), value);
             }
-
-            let created_at = u64::from_le_bytes(created_at_bytes);
-            let value_offset = u32::from_le_bytes(val_offset_bytes);
-            let is_tombstone = is_tombstone_byte[0] == 1;
-            entries.insert(key, SkipMapValue::new(value_offset as usize, created_at, is_tombstone));
         }
         return Ok((entries, total_bytes_read));
     }
@@ -271,130 +312,83 @@ impl DataFs for DataFileNode {
         &self,
         offset: u32,
         searched_key: &[u8],
-    ) -> Result<Option<(ValOffset, CreationTime, IsTombStone)>, Error> {
-        let path = &self.node.file_path;
-        let mut file = self.node.file.write().await;
-        file.seek(std::io::SeekFrom::Start(offset.into()))
-            .await
-            .map_err(|err| FileSeekError(err))?;
-
-        loop {
-            let mut key_len_bytes = [0; SIZE_OF_U32];
-            let mut bytes_read = load_buffer!(file, &mut key_len_bytes, path.to_owned())?;
-
-            if bytes_read == 0 {
-                return Ok(None);
-            }
-
-            let key_len = u32::from_le_bytes(key_len_bytes);
-            let mut key = vec![0; key_len as usize];
-            bytes_read = load_buffer!(file, &mut key, path.to_owned())?;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
-            }
-
-            let mut val_offset_bytes = [0; SIZE_OF_U32];
-            bytes_read = load_buffer!(file, &mut val_offset_bytes, path.to_owned())?;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
-            }
-
-            let mut created_at_bytes = [0; SIZE_OF_U64];
-            bytes_read = load_buffer!(file, &mut created_at_bytes, path.to_owned())?;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
-            }
-
-            let mut is_tombstone_byte = [0; SIZE_OF_U8];
-            bytes_read = load_buffer!(file, &mut is_tombstone_byte, path.to_owned())?;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
-            }
-
-            let created_at = u64::from_le_bytes(created_at_bytes);
-            let value_offset = u32::from_le_bytes(val_offset_bytes);
-            let is_tombstone = is_tombstone_byte[0] == 1;
-            if key == searched_key {
-                return Ok(Some((value_offset as usize, created_at, is_tombstone)));
-            }
-        }
-    }
-
-    async fn load_entries_within_range(&self, range_offset: RangeOffset) -> Result<Vec<Entry<Vec<u8>, usize>>, Error> {
-        let mut entries = Vec::new();
-        let mut total_bytes_read = 0;
-        let path = &self.node.file_path;
-        let mut file = self.node.file.write().await;
-        file.seek(std::io::SeekFrom::Start((range_offset.start_offset) as u64))
-            .await
-            .map_err(|err| FileSeekError(err))?;
-
-        loop {
-            let mut key_len_bytes = [0; SIZE_OF_U32];
-            let mut bytes_read = load_buffer!(file, &mut key_len_bytes, path.to_owned())?;
-            total_bytes_read += bytes_read;
-            if bytes_read == 0 {
-                return Ok(entries);
-            }
-
-            let key_len = u32::from_le_bytes(key_len_bytes);
-            let mut key = vec![0; key_len as usize];
-            bytes_read = load_buffer!(file, &mut key, path.to_owned())?;
-            total_bytes_read += bytes_read;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
-            }
-
-            let mut val_offset_bytes = [0; SIZE_OF_U32];
-            bytes_read = load_buffer!(file, &mut val_offset_bytes, path.to_owned())?;
-            total_bytes_read += bytes_read;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
-            }
-
-            let mut created_at_bytes = [0; SIZE_OF_U64];
-            bytes_read = load_buffer!(file, &mut created_at_bytes, path.to_owned())?;
-            total_bytes_read += bytes_read;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
-            }
-
-            let mut is_tombstone_byte = [0; SIZE_OF_U8];
-            bytes_read = load_buffer!(file, &mut is_tombstone_byte, path.to_owned())?;
-            total_bytes_read += bytes_read;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
-            }
-
-            let created_at = u64::from_le_bytes(created_at_bytes);
-            let value_offset = u32::from_le_bytes(val_offset_bytes) as usize;
-            let is_tombstone = is_tombstone_byte[0] == 1;
-            entries.push(Entry::new(key, value_offset, created_at, is_tombstone));
-
-            if total_bytes_read as u32 >= range_offset.end_offset {
-                return Ok(entries);
-            }
-        }
+        verify_checksums: bool,
+        cache: Option<&BlockCache>,
+    ) -> Result<Option<FoundEntry>, Error> {
+        // The index points to the only block that can hold the key
+        let Some(block) = self.load_blocks(&[offset], verify_checksums, cache).await?.pop() else {
+            return Ok(None);
+        };
+        let entry = block.iter().find(|entry| entry.key == searched_key).cloned();
+        // An expired entry is reported as deleted so it shadows older versions of the key
+        Ok(entry.map(|entry| {
+            (
+                entry.location(),
+                entry.creation_date,
+                entry.written_at,
+                entry.is_tombstone || is_expired(entry.expires_at),
+                entry.inline,
+            )
+        }))
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct VLogFileNode {
     pub node: FileNode,
+
+    /// Layout of the entries of the file, read from its header when it is opened
+    pub format: ValueLogFormat,
+
+    /// Offset of the first byte of the file, shared by the clones of the node
+    ///
+    /// Offsets of the entries are counted from the start of the log as it was first written, copy-and-truncate
+    /// garbage collection drops the start of the file without moving the entries it keeps.
+    base: Arc<AtomicUsize>,
+
+    /// Bytes of disk space allocated at once past the end of the file ahead of the appends, 0 allocates nothing
+    /// ahead, see `preallocate`. Shared by the clones of the node
+    preallocation: Arc<AtomicUsize>,
+
+    /// Length of the file up to which disk space is allocated, shared by the clones of the node
+    allocated: Arc<AtomicUsize>,
 }
 
 #[async_trait]
 impl VLogFs for VLogFileNode {
     async fn new(path: PathBuf, file_type: FileType) -> Result<VLogFileNode, Error> {
         let node = FileNode::new(path, file_type).await?;
-        Ok(VLogFileNode { node })
+        let (format, base) = {
+            let mut file = node.file.write().await;
+            VLogFileNode::load_header(&mut file, node.file_path.to_owned()).await?
+        };
+        Ok(VLogFileNode {
+            node,
+            format,
+            base: Arc::new(AtomicUsize::new(base)),
+            preallocation: Arc::new(AtomicUsize::new(0)),
+            allocated: Arc::new(AtomicUsize::new(0)),
+        })
     }
     async fn get(&self, start_offset: usize) -> Result<Option<(Vec<u8>, bool)>, Error> {
         let path = &self.node.file_path;
         let mut file = self.node.file.write().await;
-        file.seek(std::io::SeekFrom::Start((start_offset) as u64))
+        let base = self.base();
+        // The value was dropped along with the start of the file
+        if start_offset < base {
+            return Ok(None);
+        }
+        file.seek(std::io::SeekFrom::Start((start_offset - base) as u64))
             .await
             .map_err(|err| FileSeekError(err))?;
+        if self.format == ValueLogFormat::V1 {
+            let file_len = base + file.metadata().await.map_err(GetFileMetaDataError)?.len() as usize;
+            let Some((entry, _)) = FileNode::load_record(&mut file, start_offset, file_len, path.to_owned()).await?
+            else {
+                return Ok(None);
+            };
+            return Ok(Some(FileNode::found_value(entry, start_offset)?));
+        }
 
         let mut key_len_bytes = [0; SIZE_OF_U32];
         let mut bytes_read = load_buffer!(file, &mut key_len_bytes, path.to_owned())?;
@@ -423,7 +417,8 @@ impl VLogFs for VLogFileNode {
             return Err(FileNode::unexpected_eof());
         }
 
-        let is_tombstone = istombstone_bytes[0] == 1;
+        let (expires_at, expiry_len) = FileNode::load_expiry(&mut file, istombstone_bytes[0], path.to_owned()).await?;
+        let is_tombstone = istombstone_bytes[0] & TOMBSTONE_FLAG != 0 || is_expired(expires_at);
         let mut key = vec![0; key_len as usize];
         bytes_read = load_buffer!(file, &mut key, path.to_owned())?;
         if bytes_read == 0 {
@@ -432,68 +427,217 @@ impl VLogFs for VLogFileNode {
 
         let mut value = vec![0; val_len as usize];
         bytes_read = load_buffer!(file, &mut value, path.to_owned())?;
+        if bytes_read == 0 && val_len != 0 {
+            return Err(FileNode::unexpected_eof());
+        }
+        let expiry_bytes = expires_at.unwrap_or_default().to_le_bytes();
+        let fields = [
+            &key_len_bytes[..],
+            &val_len_bytes,
+            &creation_date_bytes,
+            &istombstone_bytes,
+            &expiry_bytes[..expiry_len],
+            &key,
+            &value,
+        ];
+        let checksum_type = FileNode::entry_checksum_type(istombstone_bytes[0]);
+        FileNode::verify_checksum(&mut file, &fields, checksum_type, start_offset, path.to_owned()).await?;
+        let is_compressed = istombstone_bytes[0] & COMPRESSED_FLAG != 0;
+        Ok(Some((
+            FileNode::decode_value(value, is_compressed, start_offset)?,
+            is_tombstone,
+        )))
+    }
+
+    /// Returns the values stored at `start_offsets`, sorted and distinct, along with their tombstone flags
+    ///
+    /// The offsets are read in a single sweep: each run of records lying within `VLOG_READ_COALESCE_GAP` of each
+    /// other takes one seek and one read up to its last record, which is then read in place.
+    async fn get_many(&self, start_offsets: &[usize]) -> Result<Vec<Option<(Vec<u8>, bool)>>, Error> {
+        let mut values = Vec::with_capacity(start_offsets.len());
+        if self.format != ValueLogFormat::V1 {
+            for offset in start_offsets {
+                values.push(self.get(*offset).await?);
+            }
+            return Ok(values);
+        }
+        let path = &self.node.file_path;
+        let mut file = self.node.file.write().await;
+        let base = self.base();
+        let file_len = base + file.metadata().await.map_err(GetFileMetaDataError)?.len() as usize;
+        let mut run_start = 0;
+        while run_start < start_offsets.len() {
+            let first = start_offsets[run_start];
+            // The value was dropped along with the start of the file, or lies past its end
+            if first < base || first >= file_len {
+                values.push(None);
+                run_start += 1;
+                continue;
+            }
+            let mut run_end = run_start + 1;
+            while run_end < start_offsets.len()
+                && start_offsets[run_end] < file_len
+                && start_offsets[run_end] - start_offsets[run_end - 1] <= VLOG_READ_COALESCE_GAP
+            {
+                run_end += 1;
+            }
+            let last = start_offsets[run_end - 1];
+            file.seek(std::io::SeekFrom::Start((first - base) as u64))
+                .await
+                .map_err(FileSeekError)?;
+            let mut span = vec![0; last - first];
+            file.read_exact(&mut span).await.map_err(|error| FileReadError {
+                path: path.to_owned(),
+                error,
+            })?;
+            for offset in &start_offsets[run_start..run_end - 1] {
+                let entry = FileNode::record_within(&span, offset - first, *offset)?;
+                values.push(Some(FileNode::found_value(entry, *offset)?));
+            }
+            // The file is positioned at the last record of the run
+            let found = FileNode::load_record(&mut file, last, file_len, path.to_owned()).await?;
+            values.push(match found {
+                Some((entry, _)) => Some(FileNode::found_value(entry, last)?),
+                None => None,
+            });
+            run_start = run_end;
+        }
+        Ok(values)
+    }
+
+    /// Returns a reader over the value stored at `start_offset` along with its tombstone flag
+    ///
+    /// The value log is opened again so the value is read without holding the shared file lock.
+    /// The checksum of the entry is not verified since the value is not read up front, unless it is compressed
+    async fn get_stream(&self, start_offset: usize) -> Result<Option<(ValueReader, bool)>, Error> {
+        let path = &self.node.file_path;
+        let mut file = FileNode::open(path.to_owned()).await?;
+        // Read from the file opened rather than from the node, garbage collection may have replaced it since
+        let (_, base) = VLogFileNode::load_header(&mut file, path.to_owned()).await?;
+        if start_offset < base {
+            return Ok(None);
+        }
+        file.seek(std::io::SeekFrom::Start((start_offset - base) as u64))
+            .await
+            .map_err(FileSeekError)?;
+        if self.format == ValueLogFormat::V1 {
+            return FileNode::load_record_value(file, start_offset, path.to_owned()).await;
+        }
+
+        let mut key_len_bytes = [0; SIZE_OF_U32];
+        let mut bytes_read = load_buffer!(file, &mut key_len_bytes, path.to_owned())?;
+        if bytes_read == 0 {
+            return Ok(None);
+        }
+
+        let key_len = u32::from_le_bytes(key_len_bytes);
+        let mut val_len_bytes = [0; SIZE_OF_U32];
+        bytes_read = load_buffer!(file, &mut val_len_bytes, path.to_owned())?;
+        if bytes_read == 0 {
+            return Err(FileNode::unexpected_eof());
+        }
+
+        let val_len = u32::from_le_bytes(val_len_bytes);
+        let mut creation_date_bytes = [0; SIZE_OF_U64];
+        bytes_read = load_buffer!(file, &mut creation_date_bytes, path.to_owned())?;
+        if bytes_read == 0 {
+            return Err(FileNode::unexpected_eof());
+        }
+
+        let mut istombstone_bytes = [0; SIZE_OF_U8];
+        bytes_read = load_buffer!(file, &mut istombstone_bytes, path.to_owned())?;
         if bytes_read == 0 {
             return Err(FileNode::unexpected_eof());
         }
-        Ok(Some((value, is_tombstone)))
+
+        let (expires_at, _) = FileNode::load_expiry(&mut file, istombstone_bytes[0], path.to_owned()).await?;
+        let is_tombstone = istombstone_bytes[0] & TOMBSTONE_FLAG != 0 || is_expired(expires_at);
+        // Skip the key, the reader starts at the first byte of the value
+        file.seek(std::io::SeekFrom::Current(key_len as i64))
+            .await
+            .map_err(FileSeekError)?;
+        let is_compressed = istombstone_bytes[0] & COMPRESSED_FLAG != 0;
+        Ok(Some((
+            FileNode::value_reader(file, val_len, is_compressed, start_offset).await?,
+            is_tombstone,
+        )))
     }
 
-    async fn recover(&self, start_offset: usize) -> Result<Vec<ValueLogEntry>, Error> {
+    /// Replays the entries stored from `start_offset`
+    ///
+    /// A torn or corrupted entry ends the replay: if `truncate` is set the log is truncated to the end of the
+    /// last valid entry so that the entries appended next are not written after it
+    async fn recover(&self, start_offset: usize, truncate: bool) -> Result<Vec<ValueLogEntry>, Error> {
         let path = &self.node.file_path;
         let mut entries = Vec::new();
         let mut file = self.node.file.write().await;
-        file.seek(std::io::SeekFrom::Start((start_offset) as u64))
+        let base = self.base();
+        let start_offset = start_offset.max(self.first_offset());
+        let file_len = base + file.metadata().await.map_err(GetFileMetaDataError)?.len() as usize;
+        file.seek(std::io::SeekFrom::Start((start_offset - base) as u64))
             .await
             .map_err(|err| FileSeekError(err))?;
 
+        let mut offset = start_offset;
         loop {
-            let mut key_len_bytes = [0; SIZE_OF_U32];
-            let mut bytes_read = load_buffer!(file, &mut key_len_bytes, path.to_owned())?;
-            if bytes_read == 0 {
-                return Ok(entries);
-            }
-
-            let key_len = u32::from_le_bytes(key_len_bytes);
-            let mut val_len_bytes = [0; SIZE_OF_U32];
-            bytes_read = load_buffer!(file, &mut val_len_bytes, path.to_owned())?;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
-            }
-
-            let val_len = u32::from_le_bytes(val_len_bytes);
-            let mut creation_date_bytes = [0; SIZE_OF_U64];
-            bytes_read = load_buffer!(file, &mut creation_date_bytes, path.to_owned())?;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
-            }
-
-            let created_at = u64::from_le_bytes(creation_date_bytes);
-            let mut istombstone_bytes = [0; SIZE_OF_U8];
-            let mut bytes_read = load_buffer!(file, &mut istombstone_bytes, path.to_owned())?;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
+            match FileNode::load_entry(&mut file, self.format, offset, file_len, path.to_owned()).await {
+                Ok(Some((entry, entry_len))) => {
+                    offset += entry_len;
+                    entries.push(entry);
+                }
+                Ok(None) => return Ok(entries),
+                Err(UnexpectedEOF(_) | CorruptedValueLogEntry { .. }) if !truncate => return Ok(entries),
+                Err(err @ (UnexpectedEOF(_) | CorruptedValueLogEntry { .. })) => {
+                    log::warn!(
+                        "Truncating {:?} from offset {} to {}, {} bytes are lost: {}",
+                        path,
+                        offset,
+                        file_len,
+                        file_len - offset,
+                        err
+                    );
+                    file.set_len((offset - base) as u64)
+                        .await
+                        .map_err(|error| FileWriteError {
+                            path: path.to_owned(),
+                            error,
+                        })?;
+                    file.sync_all().await.map_err(|error| FileSyncError { error })?;
+                    return Ok(entries);
+                }
+                Err(err) => return Err(err),
             }
+        }
+    }
 
-            let is_tombstone = istombstone_bytes[0] == 1;
-            let mut key = vec![0; key_len as usize];
-            bytes_read = load_buffer!(file, &mut key, path.to_owned())?;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
-            }
+    /// Reads the entries stored from `start_offset` and verifies their checksums, the log is left untouched
+    ///
+    /// Returns the offset and key of every valid entry along with the offset of the entry that ended the walk
+    /// and why, unless the walk reached the end of the file
+    async fn verify(&self, start_offset: usize) -> Result<(Vec<(ValOffset, Key)>, Option<(ValOffset, Error)>), Error> {
+        let path = &self.node.file_path;
+        let mut entries = Vec::new();
+        let mut file = self.node.file.write().await;
+        let base = self.base();
+        let start_offset = start_offset.max(self.first_offset());
+        let file_len = base + file.metadata().await.map_err(GetFileMetaDataError)?.len() as usize;
+        file.seek(std::io::SeekFrom::Start((start_offset - base) as u64))
+            .await
+            .map_err(FileSeekError)?;
 
-            let mut value = vec![0; val_len as usize];
-            bytes_read = load_buffer!(file, &mut value, path.to_owned())?;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
+        let mut offset = start_offset;
+        loop {
+            match FileNode::load_entry(&mut file, self.format, offset, file_len, path.to_owned()).await {
+                Ok(Some((entry, entry_len))) => {
+                    entries.push((offset, entry.key));
+                    offset += entry_len;
+                }
+                Ok(None) => return Ok((entries, None)),
+                Err(err @ (UnexpectedEOF(_) | CorruptedValueLogEntry { .. })) => {
+                    return Ok((entries, Some((offset, err))))
+                }
+                Err(err) => return Err(err),
             }
-            entries.push(ValueLogEntry {
-                ksize: key_len as usize,
-                vsize: val_len as usize,
-                key,
-                value,
-                created_at,
-                is_tombstone,
-            })
         }
     }
 
@@ -505,11 +649,32 @@ impl VLogFs for VLogFileNode {
         let path = &self.node.file_path;
         let mut entries = Vec::new();
         let mut file = self.node.file.write().await;
-        file.seek(std::io::SeekFrom::Start((offset) as u64))
+        let base = self.base();
+        // The tail never precedes the first byte of the file, it is where the file is truncated
+        let Some(physical_offset) = (offset as usize).checked_sub(base) else {
+            return Ok((entries, 0));
+        };
+        file.seek(std::io::SeekFrom::Start(physical_offset as u64))
             .await
             .map_err(|err| FileSeekError(err))?;
         let mut total_bytes_read: usize = 0;
+        if self.format == ValueLogFormat::V1 {
+            let file_len = base + file.metadata().await.map_err(GetFileMetaDataError)?.len() as usize;
+            let start_offset = offset as usize;
+            while let Some((entry, record_len)) =
+                FileNode::load_record(&mut file, start_offset + total_bytes_read, file_len, path.to_owned()).await?
+            {
+                total_bytes_read += record_len;
+                entries.push(entry);
+                // Ensure the size read from value log is approximately bytes expected to be garbage collected
+                if total_bytes_read >= bytes_to_collect {
+                    break;
+                }
+            }
+            return Ok((entries, total_bytes_read));
+        }
         loop {
+            let entry_offset = offset as usize + total_bytes_read;
             let mut key_len_bytes = [0; SIZE_OF_U32];
             let mut bytes_read = load_buffer!(file, &mut key_len_bytes, path.to_owned())?;
             total_bytes_read += bytes_read;
@@ -541,7 +706,10 @@ impl VLogFs for VLogFileNode {
                 return Err(FileNode::unexpected_eof());
             }
 
-            let is_tombstone = istombstone_bytes[0] == 1;
+            let (expires_at, expiry_bytes_read) =
+                FileNode::load_expiry(&mut file, istombstone_bytes[0], path.to_owned()).await?;
+            total_bytes_read += expiry_bytes_read;
+            let is_tombstone = istombstone_bytes[0] & TOMBSTONE_FLAG != 0;
             let mut key = vec![0; key_len as usize];
             bytes_read = load_buffer!(file, &mut key, path.to_owned())?;
             total_bytes_read += bytes_read;
@@ -552,16 +720,36 @@ impl VLogFs for VLogFileNode {
             let mut value = vec![0; val_len as usize];
             bytes_read = load_buffer!(file, &mut value, path.to_owned())?;
             total_bytes_read += bytes_read;
-            if bytes_read == 0 {
+            if bytes_read == 0 && val_len != 0 {
                 return Err(FileNode::unexpected_eof());
             }
+            let expiry_bytes = expires_at.unwrap_or_default().to_le_bytes();
+            let fields = [
+                &key_len_bytes[..],
+                &val_len_bytes,
+                &creation_date_bytes,
+                &istombstone_bytes,
+                &expiry_bytes[..expiry_bytes_read],
+                &key,
+                &value,
+            ];
+            let checksum_type = FileNode::entry_checksum_type(istombstone_bytes[0]);
+            total_bytes_read +=
+                FileNode::verify_checksum(&mut file, &fields, checksum_type, entry_offset, path.to_owned()).await?;
             entries.push(ValueLogEntry {
                 ksize: key_len as usize,
                 vsize: val_len as usize,
                 key,
                 value,
                 created_at,
+                // Legacy entries were created at a wall-clock time
+                written_at: created_at,
                 is_tombstone,
+                expires_at,
+                checksum_type,
+                is_rewritten: istombstone_bytes[0] & REWRITTEN_FLAG != 0,
+                is_compressed: istombstone_bytes[0] & COMPRESSED_FLAG != 0,
+                is_redirect: istombstone_bytes[0] & REDIRECT_FLAG != 0,
             });
 
             // Ensure the size read from value log is approximately bytes expected to be garbage collected
@@ -572,100 +760,597 @@ impl VLogFs for VLogFileNode {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct IndexFileNode {
-    pub node: FileNode,
-}
-#[async_trait]
-impl IndexFs for IndexFileNode {
-    async fn new(path: PathBuf, file_type: FileType) -> Result<IndexFileNode, Error> {
-        let node = FileNode::new(path, file_type).await?;
-        Ok(IndexFileNode { node })
+impl VLogFileNode {
+    /// Returns the offset of the first byte of the file
+    pub fn base(&self) -> usize {
+        self.base.load(Ordering::SeqCst)
     }
-    async fn get_from_index(&self, searched_key: &[u8]) -> Result<Option<u32>, Error> {
-        let path = &self.node.file_path;
-        let block_offset: i32 = -1;
+
+    /// Allocates `size` bytes of disk space at once past the end of the file ahead of the appends, 0 allocates
+    /// nothing ahead
+    pub(crate) fn set_preallocation(&self, size: usize) {
+        self.preallocation.store(size, Ordering::SeqCst);
+    }
+
+    /// Makes sure the disk space of `len` bytes appended to `file`, `file_len` bytes long, is allocated
+    ///
+    /// Space is allocated `preallocation` bytes at a time rather than by every append, which means fewer file
+    /// system metadata updates on the append path and a less fragmented file. The length of the file is kept so
+    /// appends still start at its end and reads never see the space allocated. Allocation is best effort, it stops
+    /// once the file system refuses it and is skipped outside Linux
+    pub(crate) fn preallocate(&self, file: &File, file_len: u64, len: usize) {
+        let size = self.preallocation.load(Ordering::SeqCst);
+        let end = file_len as usize + len;
+        if size == 0 || end <= self.allocated.load(Ordering::SeqCst) {
+            return;
+        }
+        let len = len.max(size);
+        #[cfg(target_os = "linux")]
+        {
+            let res = unsafe {
+                libc::fallocate(
+                    file.as_raw_fd(),
+                    libc::FALLOC_FL_KEEP_SIZE,
+                    file_len as libc::off_t,
+                    len as libc::off_t,
+                )
+            };
+            if res != 0 {
+                log::warn!(
+                    "Space could not be allocated ahead in {:?}, reason {}",
+                    self.node.file_path,
+                    std::io::Error::last_os_error()
+                );
+                self.preallocation.store(0, Ordering::SeqCst);
+                return;
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = file;
+        self.allocated.store(file_len as usize + len, Ordering::SeqCst);
+    }
+
+    /// Returns the offset of the first entry of the file
+    pub fn first_offset(&self) -> usize {
+        VLogFileNode::first_offset_of(self.format, self.base())
+    }
+
+    /// Returns the offset of the first entry of a file of format `format` whose first byte is at `base`
+    pub(crate) fn first_offset_of(format: ValueLogFormat, base: usize) -> usize {
+        if base == 0 {
+            return format.header_len();
+        }
+        base + VLOG_SEGMENT_HEADER_SIZE
+    }
+
+    /// Reads the header of the value log `file` to find the layout of its entries and the offset of its first byte
+    ///
+    /// An empty file is given the current format, its header is written along with its first entry. So is a file
+    /// too short to hold a header, it can only hold a torn first append
+    pub(crate) async fn load_header(file: &mut File, path: PathBuf) -> Result<(ValueLogFormat, usize), Error> {
+        let file_len = file.metadata().await.map_err(GetFileMetaDataError)?.len() as usize;
+        if file_len < VLOG_HEADER_SIZE {
+            return Ok((ValueLogFormat::V1, 0));
+        }
+        file.seek(SeekFrom::Start(0)).await.map_err(FileSeekError)?;
+        let mut header = [0; VLOG_HEADER_SIZE];
+        file.read_exact(&mut header).await.map_err(|error| FileReadError {
+            path: path.to_owned(),
+            error,
+        })?;
+        // Files written before the header existed start with the key length of their first entry, which is never
+        // as large as `VLOG_MAGIC` read as a number
+        if header[..VLOG_MAGIC.len()] != VLOG_MAGIC[..] {
+            return Ok((ValueLogFormat::Legacy, 0));
+        }
+        let version = u32::from_le_bytes(header[VLOG_MAGIC.len()..].try_into().unwrap());
+        match version {
+            VLOG_FORMAT_VERSION => Ok((ValueLogFormat::V1, 0)),
+            // The file is renamed into place once complete, its header is never torn
+            VLOG_SEGMENT_FORMAT_VERSION => {
+                let mut base = [0; SIZE_OF_U64];
+                file.read_exact(&mut base)
+                    .await
+                    .map_err(|error| FileReadError { path, error })?;
+                Ok((ValueLogFormat::V1, u64::from_le_bytes(base) as usize))
+            }
+            _ => Err(UnsupportedValueLogVersion { path, version }),
+        }
+    }
+
+    /// Returns the length of the entry stored at `offset` from the fields preceding its key, the entry is not
+    /// verified. `None` if the entry was dropped along with the start of the file or `offset` is the end of the file
+    pub(crate) async fn entry_len(&self, offset: usize) -> Result<Option<usize>, Error> {
+        let path = self.node.file_path.to_owned();
         let mut file = self.node.file.write().await;
-        file.seek(std::io::SeekFrom::Start((0) as u64))
+        if offset < self.first_offset() {
+            return Ok(None);
+        }
+        file.seek(SeekFrom::Start((offset - self.base()) as u64))
             .await
-            .map_err(|err| FileSeekError(err))?;
-
-        loop {
-            let mut key_len_bytes = [0; SIZE_OF_U32];
-            let mut bytes_read = load_buffer!(file, &mut key_len_bytes, path.to_owned())?;
-            if bytes_read == 0 {
-                if block_offset == -1 {
+            .map_err(FileSeekError)?;
+        match self.format {
+            ValueLogFormat::V1 => {
+                let mut prefix = [0; SIZE_OF_U8 + SIZE_OF_U32];
+                let bytes_read = load_buffer!(file, &mut prefix, path)?;
+                if bytes_read == 0 {
+                    return Ok(None);
+                }
+                if prefix[0] != VLOG_RECORD_MAGIC || bytes_read < prefix.len() {
+                    return Err(CorruptedValueLogEntry { offset });
+                }
+                let record_len = u32::from_le_bytes(prefix[SIZE_OF_U8..].try_into().unwrap()) as usize;
+                Ok(Some(prefix.len() + record_len))
+            }
+            ValueLogFormat::Legacy => {
+                // Key size, value size, creation time and flags
+                let mut prefix = [0; SIZE_OF_U32 + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8];
+                let bytes_read = load_buffer!(file, &mut prefix, path)?;
+                if bytes_read == 0 {
                     return Ok(None);
                 }
-                return Ok(Some(block_offset as u32));
+                if bytes_read < prefix.len() {
+                    return Err(FileNode::unexpected_eof());
+                }
+                let key_len = u32::from_le_bytes(prefix[..SIZE_OF_U32].try_into().unwrap()) as usize;
+                let val_len = u32::from_le_bytes(prefix[SIZE_OF_U32..SIZE_OF_U32 * 2].try_into().unwrap()) as usize;
+                let flags = prefix[prefix.len() - SIZE_OF_U8];
+                let expiry_len = if flags & EXPIRY_FLAG != 0 { SIZE_OF_U64 } else { 0 };
+                let checksum_len = FileNode::entry_checksum_type(flags).size();
+                Ok(Some(prefix.len() + expiry_len + key_len + val_len + checksum_len))
             }
+        }
+    }
 
-            let key_len = u32::from_le_bytes(key_len_bytes);
-            let mut key = vec![0; key_len as usize];
-            bytes_read = load_buffer!(file, &mut key, path.to_owned())?;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
+    /// Drops the start of the log up to `offset`, the entries from `offset` on are copied to a new file that
+    /// replaces it
+    ///
+    /// The new file records the offset of its first byte in its header so that the entries it holds keep their
+    /// offsets. Appends and reads wait for the copy. Returns the number of bytes dropped, nothing is dropped from a
+    /// legacy log, which has no header to record it in, nor if `offset` does not follow the first entry of the file
+    pub(crate) async fn truncate_before(&self, offset: usize) -> Result<usize, Error> {
+        if self.format == ValueLogFormat::Legacy {
+            return Ok(0);
+        }
+        let path = self.node.file_path.to_owned();
+        let mut file = self.node.file.write().await;
+        let base = self.base();
+        let first_offset = self.first_offset();
+        if offset <= first_offset.max(VLOG_SEGMENT_HEADER_SIZE) {
+            return Ok(0);
+        }
+        let new_base = offset - VLOG_SEGMENT_HEADER_SIZE;
+        // Written to a temporary file first so that a crash leaves the log untouched
+        let tmp_path = path.with_extension(TEMP_EXTENSION);
+        let mut tmp_file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp_path)
+            .await
+            .map_err(|error| FileOpenError {
+                path: tmp_path.to_owned(),
+                error,
+            })?;
+        let header = [
+            &VLOG_MAGIC[..],
+            &VLOG_SEGMENT_FORMAT_VERSION.to_le_bytes(),
+            &(new_base as u64).to_le_bytes(),
+        ]
+        .concat();
+        // The entries kept are copied to space allocated at once, along with the space the next appends take
+        let file_len = file.metadata().await.map_err(GetFileMetaDataError)?.len() as usize;
+        self.allocated.store(0, Ordering::SeqCst);
+        self.preallocate(&tmp_file, 0, header.len() + (base + file_len).saturating_sub(offset));
+        tmp_file.write_all(&header).await.map_err(|error| FileWriteError {
+            path: tmp_path.to_owned(),
+            error,
+        })?;
+        file.seek(SeekFrom::Start((offset - base) as u64))
+            .await
+            .map_err(FileSeekError)?;
+        io::copy(&mut *file, &mut tmp_file)
+            .await
+            .map_err(|error| FileWriteError {
+                path: tmp_path.to_owned(),
+                error,
+            })?;
+        tmp_file.sync_all().await.map_err(|error| FileSyncError { error })?;
+        fs::rename(&tmp_path, &path).await.map_err(|error| FileWriteError {
+            path: path.to_owned(),
+            error,
+        })?;
+        if let Some(dir) = path.parent() {
+            sync_dir(dir).await?;
+        }
+        *file = FileNode::create(path).await?;
+        self.base.store(new_base, Ordering::SeqCst);
+        Ok(offset - first_offset)
+    }
+
+    /// Returns where the records stored from `start_offset` and their values start, their values are neither read
+    /// nor verified so a value whose space was reclaimed in place is walked over. The walk ends at the end of the
+    /// file or at the first record torn, whose offset is returned along with the records
+    ///
+    /// Nothing is returned for a legacy log, which holds no class of values
+    pub(crate) async fn spans(&self, start_offset: usize) -> Result<(Vec<RecordSpan>, usize), Error> {
+        let path = self.node.file_path.to_owned();
+        let mut spans = Vec::new();
+        let mut offset = start_offset.max(self.first_offset());
+        if self.format == ValueLogFormat::Legacy {
+            return Ok((spans, offset));
+        }
+        let mut file = self.node.file.write().await;
+        let base = self.base();
+        let file_len = base + file.metadata().await.map_err(GetFileMetaDataError)?.len() as usize;
+        // Record magic, record length, flags and creation time
+        let mut prefix = [0; SIZE_OF_U8 + SIZE_OF_U32 + SIZE_OF_U8 + SIZE_OF_U64];
+        while offset + prefix.len() <= file_len {
+            file.seek(SeekFrom::Start((offset - base) as u64))
+                .await
+                .map_err(FileSeekError)?;
+            file.read_exact(&mut prefix).await.map_err(|error| FileReadError {
+                path: path.to_owned(),
+                error,
+            })?;
+            let record_len = u32::from_le_bytes(prefix[SIZE_OF_U8..SIZE_OF_U8 + SIZE_OF_U32].try_into().unwrap());
+            let len = SIZE_OF_U8 + SIZE_OF_U32 + record_len as usize;
+            if prefix[0] != VLOG_RECORD_MAGIC || offset + len > file_len {
+                break;
+            }
+            let flags = prefix[SIZE_OF_U8 + SIZE_OF_U32];
+            let expiry_len = if flags & EXPIRY_FLAG != 0 { SIZE_OF_U64 } else { 0 };
+            let mut fields = vec![0; expiry_len + SIZE_OF_U32 + SIZE_OF_U32];
+            file.read_exact(&mut fields).await.map_err(|error| FileReadError {
+                path: path.to_owned(),
+                error,
+            })?;
+            let key_len = u32::from_le_bytes(fields[expiry_len..expiry_len + SIZE_OF_U32].try_into().unwrap());
+            let value_len = u32::from_le_bytes(fields[expiry_len + SIZE_OF_U32..].try_into().unwrap());
+            let value_offset = offset + prefix.len() + fields.len() + key_len as usize;
+            if value_offset + value_len as usize > offset + len {
+                break;
             }
+            let mut key = vec![0; key_len as usize];
+            file.read_exact(&mut key).await.map_err(|error| FileReadError {
+                path: path.to_owned(),
+                error,
+            })?;
+            spans.push(RecordSpan {
+                offset,
+                flags,
+                key,
+                value_offset,
+                value_len: value_len as usize,
+            });
+            offset += len;
+        }
+        Ok((spans, offset))
+    }
 
-            let mut key_offset_bytes = [0; SIZE_OF_U32];
-            bytes_read = load_buffer!(file, &mut key_offset_bytes, path.to_owned())?;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
+    /// Marks the record of `span` a tombstone once the space of its value was reclaimed, see
+    /// `RecordSpan::is_reclaimed`. The record no longer matches its checksum
+    pub(crate) async fn mark_reclaimed(&self, span: &RecordSpan) -> Result<(), Error> {
+        let path = self.node.file_path.to_owned();
+        // Appends go to the end of the file whatever its position, the flags are written through a handle of its own
+        let _file = self.node.file.write().await;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .await
+            .map_err(|error| FileOpenError {
+                path: path.to_owned(),
+                error,
+            })?;
+        let flags_offset = span.offset - self.base() + SIZE_OF_U8 + SIZE_OF_U32;
+        file.seek(SeekFrom::Start(flags_offset as u64))
+            .await
+            .map_err(FileSeekError)?;
+        file.write_all(&[span.flags | TOMBSTONE_FLAG])
+            .await
+            .map_err(|error| FileWriteError { path, error })
+    }
+
+    /// Truncates the file at the first record torn, so that the records appended next are not written after it
+    pub(crate) async fn truncate_torn(&self) -> Result<(), Error> {
+        let (_, end) = self.spans(self.first_offset()).await?;
+        let file = self.node.file.write().await;
+        let file_len = self.base() + file.metadata().await.map_err(GetFileMetaDataError)?.len() as usize;
+        if end >= file_len {
+            return Ok(());
+        }
+        log::warn!(
+            "Truncating {:?} from offset {} to {}, {} bytes are lost",
+            self.node.file_path,
+            end,
+            file_len,
+            file_len - end
+        );
+        let path = self.node.file_path.to_owned();
+        file.set_len((end - self.base()) as u64)
+            .await
+            .map_err(|error| FileWriteError { path, error })?;
+        file.sync_all().await.map_err(|error| FileSyncError { error })
+    }
+}
+
+/// Where a record of the value log and its value start, see `VLogFileNode::spans`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RecordSpan {
+    pub offset: ValOffset,
+    pub flags: u8,
+    pub key: Key,
+    pub value_offset: ValOffset,
+    pub value_len: usize,
+}
+
+impl RecordSpan {
+    /// Returns true if the space of the value was reclaimed, the log of a class holds no tombstones otherwise
+    pub(crate) fn is_reclaimed(&self) -> bool {
+        self.flags & TOMBSTONE_FLAG != 0
+    }
+}
+
+impl DataFileNode {
+    /// Reads the blocks at `offsets`, in order, verifying their checksum if `verify_checksums` is set
+    ///
+    /// The blocks are looked up in `cache` first and cached once read from the file. Reading stops at the end of the
+    /// file.
+    pub(crate) async fn load_blocks(
+        &self,
+        offsets: &[u32],
+        verify_checksums: bool,
+        cache: Option<&BlockCache>,
+    ) -> Result<Vec<Arc<Vec<BlockEntry>>>, Error> {
+        let path = &self.node.file_path;
+        let mut blocks = Vec::with_capacity(offsets.len());
+        // The header is read along with the first block missing from the cache
+        let mut checksum_type = None;
+        for offset in offsets.iter().map(|offset| *offset as usize) {
+            if let Some((block, _)) = cache.and_then(|cache| cache.get(path, offset)) {
+                blocks.push(block);
+                continue;
+            }
+            let mut file = self.node.file.write().await;
+            let block_checksum_type = match checksum_type {
+                Some(checksum_type) => checksum_type,
+                None => {
+                    file.seek(std::io::SeekFrom::Start(0)).await.map_err(FileSeekError)?;
+                    let Some(header) = FileNode::load_checksum_type(&mut file, path.to_owned()).await? else {
+                        break;
+                    };
+                    *checksum_type.insert(header)
+                }
+            };
+            file.seek(std::io::SeekFrom::Start(offset as u64))
+                .await
+                .map_err(FileSeekError)?;
+            let Some((block, bytes_read)) = FileNode::load_block(
+                &mut file,
+                offset,
+                block_checksum_type,
+                verify_checksums,
+                path.to_owned(),
+            )
+            .await?
+            else {
+                break;
+            };
+            let block = Arc::new(block);
+            if let (Some(cache), true) = (cache, verify_checksums) {
+                cache.insert(path, offset, block.clone(), bytes_read);
             }
-            let offset = u32::from_le_bytes(key_offset_bytes);
-            match key.cmp(&searched_key.to_vec()) {
-                std::cmp::Ordering::Less => {
-                    continue;
+            blocks.push(block);
+        }
+        Ok(blocks)
+    }
+
+    /// Reads the block at `offset` and verifies its checksum
+    ///
+    /// Returns the entries of the block along with its length, `None` if `offset` is the end of the file
+    pub(crate) async fn load_block_at(&self, offset: usize) -> Result<Option<(Vec<BlockEntry>, NoBytesRead)>, Error> {
+        let path = &self.node.file_path;
+        let mut file = self.node.file.write().await;
+        file.seek(std::io::SeekFrom::Start(0)).await.map_err(FileSeekError)?;
+        let Some(checksum_type) = FileNode::load_checksum_type(&mut file, path.to_owned()).await? else {
+            return Ok(None);
+        };
+        file.seek(std::io::SeekFrom::Start(offset as u64))
+            .await
+            .map_err(FileSeekError)?;
+        FileNode::load_block(&mut file, offset, checksum_type, true, path.to_owned()).await
+    }
+
+    /// Reads the entries of every block that can still be read, blocks whose checksum does not match are skipped
+    ///
+    /// Returns the entries along with the number of blocks dropped. Reading stops at a block extending past the
+    /// end of the file since where the next block starts is unknown.
+    pub(crate) async fn salvage_entries(&self) -> Result<(SkipMapEntries<Key>, usize), Error> {
+        let entries = Arc::new(SkipMap::new());
+        let path = &self.node.file_path;
+        let mut file = self.node.file.write().await;
+        file.seek(std::io::SeekFrom::Start(0)).await.map_err(FileSeekError)?;
+        let Some(checksum_type) = FileNode::load_checksum_type(&mut file, path.to_owned()).await? else {
+            return Ok((entries, 0));
+        };
+        let mut offset = SIZE_OF_U8;
+        let mut dropped_blocks = 0;
+        loop {
+            match FileNode::load_block(&mut file, offset, checksum_type, true, path.to_owned()).await {
+                Ok(Some((block, bytes_read))) => {
+                    offset += bytes_read;
+                    for entry in block {
+                        let value = SkipMapValue::new(entry.location(), entry.creation_date, entry.is_tombstone)
+                            .with_written_at(entry.written_at)
+                            .with_expiry(entry.expires_at)
+                            .with_inline(entry.inline);
+                        entries.insert(entry.key, value);
+                    }
                 }
-                std::cmp::Ordering::Equal => {
-                    return Ok(Some(offset));
+                Ok(None) => break,
+                // The whole block was read so the next one starts where the file now is
+                Err(ChecksumMismatch { .. }) | Err(SerializationError(_)) => {
+                    dropped_blocks += 1;
+                    offset = file.stream_position().await.map_err(FileSeekError)? as usize;
                 }
-                std::cmp::Ordering::Greater => {
-                    return Ok(Some(offset));
+                Err(_) => {
+                    dropped_blocks += 1;
+                    break;
                 }
             }
         }
+        Ok((entries, dropped_blocks))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexFileNode {
+    pub node: FileNode,
+}
+#[async_trait]
+impl IndexFs for IndexFileNode {
+    async fn new(path: PathBuf, file_type: FileType) -> Result<IndexFileNode, Error> {
+        let node = FileNode::new(path, file_type).await?;
+        Ok(IndexFileNode { node })
+    }
+    async fn get_from_index(&self, searched_key: &[u8]) -> Result<Option<u32>, Error> {
+        let entries = self.load_entries().await?;
+        // The first block whose last key is not smaller than the searched key is the only one that can hold it
+        Ok(entries
+            .into_iter()
+            .find(|(key, _)| key.as_slice() >= searched_key)
+            .map(|(_, offset)| offset))
+    }
+
+    async fn load_keys(&self) -> Result<Vec<Key>, Error> {
+        let entries = self.load_entries().await?;
+        Ok(entries.into_iter().map(|(key, _)| key).collect())
     }
+}
 
-    async fn get_block_range(&self, start_key: &[u8], end_key: &[u8]) -> Result<RangeOffset, Error> {
+impl IndexFileNode {
+    /// Reads the whole index file and verifies the checksum in its footer with the algorithm recorded in its header
+    ///
+    /// Returns the last key of every block along with the offset of the block, in key order
+    pub(crate) async fn load_entries(&self) -> Result<Vec<(Key, u32)>, Error> {
         let path = &self.node.file_path;
-        let mut range_offset = RangeOffset::new(0, 0);
         let mut file = self.node.file.write().await;
-        file.seek(std::io::SeekFrom::Start((0) as u64))
+        file.seek(std::io::SeekFrom::Start(0))
             .await
             .map_err(|err| FileSeekError(err))?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).await.map_err(|error| FileReadError {
+            path: path.to_owned(),
+            error,
+        })?;
+        drop(file);
+        let Some(header) = buf.first() else {
+            return Ok(Vec::new());
+        };
+        let checksum_type = ChecksumType::from_byte(*header).ok_or(UnknownChecksumType {
+            path: path.to_owned(),
+            checksum_type: *header,
+        })?;
+        if buf.len() < SIZE_OF_U8 + checksum_type.size() {
+            return Err(FileNode::unexpected_eof());
+        }
 
-        loop {
-            let mut key_len_bytes = [0; SIZE_OF_U32];
-            let mut bytes_read = load_buffer!(file, &mut key_len_bytes, path.to_owned())?;
-            if bytes_read == 0 {
-                return Ok(range_offset);
-            }
+        let (buf, checksum) = buf.split_at(buf.len() - checksum_type.size());
+        if checksum_type.checksum(buf) != checksum {
+            return Err(ChecksumMismatch {
+                path: path.to_owned(),
+                offset: buf.len(),
+            });
+        }
+        let buf = &buf[SIZE_OF_U8..];
+        let mut entries = Vec::new();
+        let mut read = 0;
+        while read < buf.len() {
+            let key_len_bytes = buf.get(read..read + SIZE_OF_U32).ok_or_else(FileNode::unexpected_eof)?;
+            let key_len = u32::from_le_bytes(key_len_bytes.try_into().unwrap()) as usize;
+            read += SIZE_OF_U32;
+            let key = buf.get(read..read + key_len).ok_or_else(FileNode::unexpected_eof)?;
+            read += key_len;
+            let offset_bytes = buf.get(read..read + SIZE_OF_U32).ok_or_else(FileNode::unexpected_eof)?;
+            read += SIZE_OF_U32;
+            entries.push((key.to_vec(), u32::from_le_bytes(offset_bytes.try_into().unwrap())));
+        }
+        Ok(entries)
+    }
+}
 
-            let key_len = u32::from_le_bytes(key_len_bytes);
-            let mut key = vec![0; key_len as usize];
-            bytes_read = load_buffer!(file, &mut key, path.to_owned())?;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
-            }
+/// Exclusive advisory lock on the `LOCK` file of a store directory
+///
+/// The storage engine is single-process: background compaction, flushes and garbage collection assume
+/// they are the only writers of the buckets and the value log. The lock is held for as long as the
+/// `DataStore` is alive and released when it is closed or dropped. The holder writes its process id to the
+/// file so that a failed attempt can tell who holds the lock.
+#[derive(Debug)]
+pub struct LockFile {
+    pub path: PathBuf,
+    file: std::fs::File,
+}
 
-            let mut key_offset_bytes = [0; SIZE_OF_U32];
-            bytes_read = load_buffer!(file, &mut key_offset_bytes, path.to_owned())?;
-            if bytes_read == 0 {
-                return Err(FileNode::unexpected_eof());
-            }
-            let offset = u32::from_le_bytes(key_offset_bytes);
-            match key.cmp(&start_key.to_vec()) {
-                std::cmp::Ordering::Greater => match key.cmp(&end_key.to_vec()) {
-                    std::cmp::Ordering::Greater => {
-                        range_offset.end_offset = offset;
-                        return Ok(range_offset);
-                    }
-                    _ => range_offset.end_offset = offset,
-                },
-                _ => range_offset.start_offset = offset,
+impl LockFile {
+    pub fn acquire(path: PathBuf) -> Result<Self, Error> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|err| FileCreationError {
+                path: path.to_owned(),
+                error: err,
+            })?;
+        #[cfg(unix)]
+        {
+            // LOCK_NB makes flock fail immediately instead of blocking if another process holds the lock
+            let result = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+            if result != 0 {
+                let error = std::io::Error::last_os_error();
+                return Err(FileLockError {
+                    holder: Self::holder(&file),
+                    path,
+                    error,
+                });
             }
         }
+        let lock = Self { path, file };
+        lock.write_holder(Some(std::process::id()))?;
+        Ok(lock)
+    }
+
+    /// Returns the process id written by the holder of the lock, `None` if it has not written it yet
+    fn holder(mut file: &std::fs::File) -> Option<u32> {
+        let mut holder = String::new();
+        std::io::Read::read_to_string(&mut file, &mut holder).ok()?;
+        holder.trim().parse().ok()
+    }
+
+    // Replaces the content of the file with the process id of the holder, or empties it when released
+    fn write_holder(&self, holder: Option<u32>) -> Result<(), Error> {
+        let mut file = &self.file;
+        file.set_len(0)
+            .and_then(|_| std::io::Seek::seek(&mut file, SeekFrom::Start(0)))
+            .and_then(|_| match holder {
+                Some(pid) => std::io::Write::write_all(&mut file, format!("{}\n", pid).as_bytes()),
+                None => Ok(()),
+            })
+            .map_err(|err| FileWriteError {
+                path: self.path.to_owned(),
+                error: err,
+            })
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = self.write_holder(None);
+        #[cfg(unix)]
+        unsafe {
+            libc::flock(self.file.as_raw_fd(), libc::LOCK_UN);
+        }
     }
 }
 
@@ -673,4 +1358,470 @@ impl FileNode {
     fn unexpected_eof() -> Error {
         return UnexpectedEOF(io::Error::new(io::ErrorKind::UnexpectedEof, EOF));
     }
+
+    /// Reads the header of an SSTable file, the algorithm checksumming its blocks, `None` if the file is empty
+    async fn load_checksum_type(file: &mut File, path: PathBuf) -> Result<Option<ChecksumType>, Error> {
+        let mut header = [0; SIZE_OF_U8];
+        let bytes_read = load_buffer!(file, &mut header, path.to_owned())?;
+        if bytes_read == 0 {
+            return Ok(None);
+        }
+        match ChecksumType::from_byte(header[0]) {
+            Some(checksum_type) => Ok(Some(checksum_type)),
+            None => Err(UnknownChecksumType {
+                path,
+                checksum_type: header[0],
+            }),
+        }
+    }
+
+    /// Reads the block at `offset`, the current position of `file`, and verifies its `checksum_type` checksum
+    /// if `verify_checksums` is set
+    ///
+    /// Returns the entries of the block along with its length, `None` at the end of the file
+    async fn load_block(
+        file: &mut File,
+        offset: usize,
+        checksum_type: ChecksumType,
+        verify_checksums: bool,
+        path: PathBuf,
+    ) -> Result<Option<(Vec<BlockEntry>, NoBytesRead)>, Error> {
+        let mut len_bytes = [0; SIZE_OF_U32];
+        let bytes_read = load_buffer!(file, &mut len_bytes, path.to_owned())?;
+        if bytes_read == 0 {
+            return Ok(None);
+        }
+
+        let (stored_len, is_compressed) = Block::stored_len(len_bytes);
+        let mut block = vec![0; stored_len];
+        file.read_exact(&mut block)
+            .await
+            .map_err(|_| FileNode::unexpected_eof())?;
+        let mut checksum_bytes = vec![0; checksum_type.size()];
+        file.read_exact(&mut checksum_bytes)
+            .await
+            .map_err(|_| FileNode::unexpected_eof())?;
+        if verify_checksums {
+            let mut checksum = Checksum::new(checksum_type);
+            checksum.update(&len_bytes);
+            checksum.update(&block);
+            if checksum.finish() != checksum_bytes {
+                return Err(ChecksumMismatch { path, offset });
+            }
+        }
+        let entries = Block::decode(&block, is_compressed)?;
+        Ok(Some((entries, SIZE_OF_U32 + block.len() + checksum_bytes.len())))
+    }
+
+    /// Reads the value log entry of format `format` at `offset`, the current position of `file`, and verifies its
+    /// checksum
+    ///
+    /// Returns the entry along with its length, `None` at the end of the file
+    pub(crate) async fn load_entry(
+        file: &mut File,
+        format: ValueLogFormat,
+        offset: usize,
+        file_len: usize,
+        path: PathBuf,
+    ) -> Result<Option<(ValueLogEntry, NoBytesRead)>, Error> {
+        match format {
+            ValueLogFormat::Legacy => FileNode::load_legacy_entry(file, offset, file_len, path).await,
+            ValueLogFormat::V1 => FileNode::load_record(file, offset, file_len, path).await,
+        }
+    }
+
+    /// Reads the record at `offset`, the current position of `file`, and verifies its checksum
+    ///
+    /// Returns the entry along with the length of the record, `None` at the end of the file. What a torn write
+    /// leaves behind is detected before anything else is read: a record that does not start with
+    /// `VLOG_RECORD_MAGIC` is reported as corrupted and one whose length extends past `file_len` as an unexpected
+    /// end of file
+    async fn load_record(
+        file: &mut File,
+        offset: usize,
+        file_len: usize,
+        path: PathBuf,
+    ) -> Result<Option<(ValueLogEntry, NoBytesRead)>, Error> {
+        let mut prefix = [0; SIZE_OF_U8 + SIZE_OF_U32];
+        let bytes_read = load_buffer!(file, &mut prefix, path.to_owned())?;
+        if bytes_read == 0 {
+            return Ok(None);
+        }
+        if prefix[0] != VLOG_RECORD_MAGIC {
+            return Err(CorruptedValueLogEntry { offset });
+        }
+        if bytes_read < prefix.len() {
+            return Err(FileNode::unexpected_eof());
+        }
+        let record_len = u32::from_le_bytes(prefix[SIZE_OF_U8..].try_into().unwrap()) as usize;
+        if offset + prefix.len() + record_len > file_len {
+            return Err(FileNode::unexpected_eof());
+        }
+        let mut record = vec![0; record_len];
+        file.read_exact(&mut record).await.map_err(|error| FileReadError {
+            path: path.to_owned(),
+            error,
+        })?;
+        let entry = FileNode::decode_record(&prefix, &record, offset)?;
+        Ok(Some((entry, prefix.len() + record_len)))
+    }
+
+    // Decodes the record at `offset` made of `prefix`, its magic and length, and of `record`, the bytes the length
+    // counts, once its checksum is verified
+    fn decode_record(prefix: &[u8], record: &[u8], offset: usize) -> Result<ValueLogEntry, Error> {
+        let flags = *record.first().ok_or(CorruptedValueLogEntry { offset })?;
+        let checksum_type = FileNode::entry_checksum_type(flags);
+        let fields_len = record
+            .len()
+            .checked_sub(checksum_type.size())
+            .ok_or(CorruptedValueLogEntry { offset })?;
+        let (mut fields, checksum_bytes) = record.split_at(fields_len);
+        let mut checksum = Checksum::new(checksum_type);
+        checksum.update(prefix);
+        checksum.update(fields);
+        if checksum.finish() != checksum_bytes {
+            return Err(CorruptedValueLogEntry { offset });
+        }
+
+        FileNode::take_field(&mut fields, SIZE_OF_U8, offset)?;
+        let created_at = u64::from_le_bytes(
+            FileNode::take_field(&mut fields, SIZE_OF_U64, offset)?
+                .try_into()
+                .unwrap(),
+        );
+        let expires_at = if flags & EXPIRY_FLAG != 0 {
+            let expires_at = FileNode::take_field(&mut fields, SIZE_OF_U64, offset)?;
+            Some(u64::from_le_bytes(expires_at.try_into().unwrap()))
+        } else {
+            None
+        };
+        let key_len = u32::from_le_bytes(
+            FileNode::take_field(&mut fields, SIZE_OF_U32, offset)?
+                .try_into()
+                .unwrap(),
+        );
+        let val_len = u32::from_le_bytes(
+            FileNode::take_field(&mut fields, SIZE_OF_U32, offset)?
+                .try_into()
+                .unwrap(),
+        );
+        let key = FileNode::take_field(&mut fields, key_len as usize, offset)?.to_vec();
+        let value = FileNode::take_field(&mut fields, val_len as usize, offset)?.to_vec();
+        // Records written before the write time was recorded end with their value
+        let written_at = match fields.get(..SIZE_OF_U64) {
+            Some(written_at) => u64::from_le_bytes(written_at.try_into().unwrap()),
+            None => created_at,
+        };
+        // Fields a later version appends after the write time are skipped
+        Ok(ValueLogEntry {
+            ksize: key_len as usize,
+            vsize: val_len as usize,
+            key,
+            value,
+            created_at,
+            written_at,
+            is_tombstone: flags & TOMBSTONE_FLAG != 0,
+            expires_at,
+            checksum_type,
+            is_rewritten: flags & REWRITTEN_FLAG != 0,
+            is_compressed: flags & COMPRESSED_FLAG != 0,
+            is_redirect: flags & REDIRECT_FLAG != 0,
+        })
+    }
+
+    // Splits the first `len` bytes off `fields`, the fields of the record at `offset`
+    fn take_field<'a>(fields: &mut &'a [u8], len: usize, offset: usize) -> Result<&'a [u8], Error> {
+        if fields.len() < len {
+            return Err(CorruptedValueLogEntry { offset });
+        }
+        let (field, rest) = fields.split_at(len);
+        *fields = rest;
+        Ok(field)
+    }
+
+    /// Returns a reader over the value of the record at `offset`, the current position of `file`, along with its
+    /// tombstone flag, `None` at the end of the file. The checksum of the record is not verified
+    async fn load_record_value(
+        mut file: File,
+        offset: usize,
+        path: PathBuf,
+    ) -> Result<Option<(ValueReader, bool)>, Error> {
+        let mut prefix = [0; SIZE_OF_U8 + SIZE_OF_U32 + SIZE_OF_U8 + SIZE_OF_U64];
+        let bytes_read = load_buffer!(file, &mut prefix, path.to_owned())?;
+        if bytes_read == 0 {
+            return Ok(None);
+        }
+        if prefix[0] != VLOG_RECORD_MAGIC {
+            return Err(CorruptedValueLogEntry { offset });
+        }
+        if bytes_read < prefix.len() {
+            return Err(FileNode::unexpected_eof());
+        }
+        let flags = prefix[SIZE_OF_U8 + SIZE_OF_U32];
+        let (expires_at, _) = FileNode::load_expiry(&mut file, flags, path).await?;
+        let is_tombstone = flags & TOMBSTONE_FLAG != 0 || is_expired(expires_at);
+        let mut lens = [0; SIZE_OF_U32 + SIZE_OF_U32];
+        file.read_exact(&mut lens)
+            .await
+            .map_err(|_| FileNode::unexpected_eof())?;
+        let key_len = u32::from_le_bytes(lens[..SIZE_OF_U32].try_into().unwrap());
+        let val_len = u32::from_le_bytes(lens[SIZE_OF_U32..].try_into().unwrap());
+        // Skip the key, the reader starts at the first byte of the value
+        file.seek(std::io::SeekFrom::Current(key_len as i64))
+            .await
+            .map_err(FileSeekError)?;
+        let is_compressed = flags & COMPRESSED_FLAG != 0;
+        Ok(Some((
+            FileNode::value_reader(file, val_len, is_compressed, offset).await?,
+            is_tombstone,
+        )))
+    }
+
+    // Returns a reader over the `len` bytes of the value of the entry at `offset`, the current position of `file`.
+    // A compressed value is read and decompressed up front
+    async fn value_reader(file: File, len: u32, is_compressed: bool, offset: usize) -> Result<ValueReader, Error> {
+        let mut reader = file.take(len as u64);
+        if !is_compressed {
+            return Ok(Box::new(reader));
+        }
+        let mut value = Vec::with_capacity(len as usize);
+        reader
+            .read_to_end(&mut value)
+            .await
+            .map_err(|_| FileNode::unexpected_eof())?;
+        if value.len() < len as usize {
+            return Err(FileNode::unexpected_eof());
+        }
+        let value = FileNode::decode_value(value, true, offset)?;
+        Ok(Box::new(std::io::Cursor::new(value)))
+    }
+
+    // Returns the value of the entry at `offset` as it was appended, `value` is decompressed if `is_compressed`
+    // Decodes the record at `start`, the position of `offset` in `span`, which must hold the whole record
+    fn record_within(span: &[u8], start: usize, offset: usize) -> Result<ValueLogEntry, Error> {
+        let prefix_len = SIZE_OF_U8 + SIZE_OF_U32;
+        let prefix = span
+            .get(start..start + prefix_len)
+            .ok_or(CorruptedValueLogEntry { offset })?;
+        if prefix[0] != VLOG_RECORD_MAGIC {
+            return Err(CorruptedValueLogEntry { offset });
+        }
+        let record_len = u32::from_le_bytes(prefix[SIZE_OF_U8..].try_into().unwrap()) as usize;
+        let record = span
+            .get(start + prefix_len..start + prefix_len + record_len)
+            .ok_or(CorruptedValueLogEntry { offset })?;
+        FileNode::decode_record(prefix, record, offset)
+    }
+
+    // Returns the value of the entry at `offset` along with its tombstone flag, an expired entry counts as deleted
+    fn found_value(entry: ValueLogEntry, offset: usize) -> Result<(Vec<u8>, bool), Error> {
+        let is_tombstone = entry.is_tombstone || is_expired(entry.expires_at);
+        Ok((
+            FileNode::decode_value(entry.value, entry.is_compressed, offset)?,
+            is_tombstone,
+        ))
+    }
+
+    fn decode_value(value: Vec<u8>, is_compressed: bool, offset: usize) -> Result<Vec<u8>, Error> {
+        if !is_compressed {
+            return Ok(value);
+        }
+        CompressionType::decompress(&value).ok_or(CorruptedValueLogEntry { offset })
+    }
+
+    /// Reads the entry at `offset` of a value log written before records were framed, the current position of
+    /// `file`, and verifies its checksum
+    ///
+    /// Returns the entry along with its length, `None` at the end of the file. An entry that would extend
+    /// past `file_len` is reported as an unexpected end of file before its key and value are allocated
+    async fn load_legacy_entry(
+        file: &mut File,
+        offset: usize,
+        file_len: usize,
+        path: PathBuf,
+    ) -> Result<Option<(ValueLogEntry, NoBytesRead)>, Error> {
+        let mut key_len_bytes = [0; SIZE_OF_U32];
+        let mut bytes_read = load_buffer!(file, &mut key_len_bytes, path.to_owned())?;
+        if bytes_read == 0 {
+            return Ok(None);
+        }
+
+        let key_len = u32::from_le_bytes(key_len_bytes);
+        let mut val_len_bytes = [0; SIZE_OF_U32];
+        bytes_read = load_buffer!(file, &mut val_len_bytes, path.to_owned())?;
+        if bytes_read == 0 {
+            return Err(FileNode::unexpected_eof());
+        }
+
+        let val_len = u32::from_le_bytes(val_len_bytes);
+        let mut creation_date_bytes = [0; SIZE_OF_U64];
+        bytes_read = load_buffer!(file, &mut creation_date_bytes, path.to_owned())?;
+        if bytes_read == 0 {
+            return Err(FileNode::unexpected_eof());
+        }
+
+        let created_at = u64::from_le_bytes(creation_date_bytes);
+        let mut istombstone_bytes = [0; SIZE_OF_U8];
+        bytes_read = load_buffer!(file, &mut istombstone_bytes, path.to_owned())?;
+        if bytes_read == 0 {
+            return Err(FileNode::unexpected_eof());
+        }
+
+        let (expires_at, expiry_len) = FileNode::load_expiry(file, istombstone_bytes[0], path.to_owned()).await?;
+        let is_tombstone = istombstone_bytes[0] & TOMBSTONE_FLAG != 0;
+        let checksum_type = FileNode::entry_checksum_type(istombstone_bytes[0]);
+        let entry_len = SIZE_OF_U32
+            + SIZE_OF_U32
+            + SIZE_OF_U64
+            + SIZE_OF_U8
+            + expiry_len
+            + key_len as usize
+            + val_len as usize
+            + checksum_type.size();
+        if offset + entry_len > file_len {
+            return Err(FileNode::unexpected_eof());
+        }
+
+        let mut key = vec![0; key_len as usize];
+        file.read_exact(&mut key).await.map_err(|error| FileReadError {
+            path: path.to_owned(),
+            error,
+        })?;
+        let mut value = vec![0; val_len as usize];
+        file.read_exact(&mut value).await.map_err(|error| FileReadError {
+            path: path.to_owned(),
+            error,
+        })?;
+        let expiry_bytes = expires_at.unwrap_or_default().to_le_bytes();
+        let fields = [
+            &key_len_bytes[..],
+            &val_len_bytes,
+            &creation_date_bytes,
+            &istombstone_bytes,
+            &expiry_bytes[..expiry_len],
+            &key,
+            &value,
+        ];
+        FileNode::verify_checksum(file, &fields, checksum_type, offset, path).await?;
+        let entry = ValueLogEntry {
+            ksize: key_len as usize,
+            vsize: val_len as usize,
+            key,
+            value,
+            created_at,
+            // Legacy entries were created at a wall-clock time
+            written_at: created_at,
+            is_tombstone,
+            expires_at,
+            checksum_type,
+            is_rewritten: istombstone_bytes[0] & REWRITTEN_FLAG != 0,
+            is_compressed: istombstone_bytes[0] & COMPRESSED_FLAG != 0,
+            is_redirect: istombstone_bytes[0] & REDIRECT_FLAG != 0,
+        };
+        Ok(Some((entry, entry_len)))
+    }
+
+    /// Reads the checksum that ends the entry at `offset` and compares it with the checksum of `fields`,
+    /// the bytes of the entry that precede it
+    ///
+    /// Returns the number of bytes read
+    async fn verify_checksum(
+        file: &mut File,
+        fields: &[&[u8]],
+        checksum_type: ChecksumType,
+        offset: usize,
+        path: PathBuf,
+    ) -> Result<NoBytesRead, Error> {
+        let mut checksum_bytes = vec![0; checksum_type.size()];
+        let bytes_read = load_buffer!(file, &mut checksum_bytes, path)?;
+        if bytes_read < checksum_bytes.len() {
+            return Err(FileNode::unexpected_eof());
+        }
+        let mut checksum = Checksum::new(checksum_type);
+        fields.iter().for_each(|field| checksum.update(field));
+        if checksum.finish() != checksum_bytes {
+            return Err(CorruptedValueLogEntry { offset });
+        }
+        Ok(bytes_read)
+    }
+
+    // Returns the algorithm of the checksum ending a value log entry with the flags byte `flags`
+    fn entry_checksum_type(flags: u8) -> ChecksumType {
+        if flags & XXHASH64_FLAG != 0 {
+            ChecksumType::XxHash64
+        } else {
+            ChecksumType::Crc32c
+        }
+    }
+
+    /// Reads the expiry time that follows the flags byte of an entry if `EXPIRY_FLAG` is set
+    ///
+    /// Returns the expiry time along with the number of bytes read
+    async fn load_expiry(file: &mut File, flags: u8, path: PathBuf) -> Result<(ExpiresAt, NoBytesRead), Error> {
+        if flags & EXPIRY_FLAG == 0 {
+            return Ok((None, 0));
+        }
+        let mut expires_at_bytes = [0; SIZE_OF_U64];
+        let bytes_read = load_buffer!(file, &mut expires_at_bytes, path)?;
+        if bytes_read == 0 {
+            return Err(FileNode::unexpected_eof());
+        }
+        Ok((Some(u64::from_le_bytes(expires_at_bytes)), bytes_read))
+    }
+}
+
+/// Encodes the flags byte stored after the creation date of an entry, followed by the expiry time if set
+pub(crate) fn encode_flags(is_tombstone: IsTombStone, expires_at: ExpiresAt) -> Vec<u8> {
+    let mut flags = if is_tombstone { TOMBSTONE_FLAG } else { 0 };
+    if expires_at.is_some() {
+        flags |= EXPIRY_FLAG;
+    }
+    let mut encoded = Vec::with_capacity(flags_len(expires_at));
+    encoded.push(flags);
+    if let Some(expires_at) = expires_at {
+        encoded.extend_from_slice(&expires_at.to_le_bytes());
+    }
+    encoded
+}
+
+/// Returns the number of bytes `encode_flags` writes for an entry
+pub(crate) fn flags_len(expires_at: ExpiresAt) -> usize {
+    SIZE_OF_U8 + expires_at.map_or(0, |_| SIZE_OF_U64)
+}
+
+/// Syncs the directory `dir` so that the files created, renamed or removed in it survive a machine crash
+pub(crate) async fn sync_dir(dir: &Path) -> Result<(), Error> {
+    // Directories cannot be opened as files on every platform, elsewhere this is left to the file system
+    #[cfg(unix)]
+    {
+        let dir_file = File::open(dir).await.map_err(|error| FileOpenError {
+            path: dir.to_path_buf(),
+            error,
+        })?;
+        dir_file.sync_all().await.map_err(|error| FileSyncError { error })?;
+    }
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Returns the number of bytes that can still be written to the file system holding `path`
+pub(crate) fn available_space(path: &Path) -> Result<u64, Error> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+            .map_err(|err| GetFileMetaDataError(std::io::Error::new(std::io::ErrorKind::InvalidInput, err)))?;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+            return Err(GetFileMetaDataError(std::io::Error::last_os_error()));
+        }
+        // Blocks reserved for the superuser are not counted
+        Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        Ok(u64::MAX)
+    }
 }
@@ -0,0 +1,100 @@
+//! # Dead bytes
+//!
+//! Garbage collection runs every `Config::online_gc_interval` whatever the share of the value log that is garbage.
+//! `DeadBytes` estimates how many bytes of the value log no entry points to anymore: the value an overwrite or a
+//! delete replaces in the active memtable, and the values of the versions compaction drops once the SSTables they
+//! were merged from are removed. Each garbage collection pass takes off the bytes it reclaimed.
+//!
+//! Garbage collection is woken up whenever dead bytes are recorded and collects as soon as they exceed
+//! `Config::gc_garbage_ratio` of the entries from the tail of the value log on. The estimate is not persisted, it
+//! starts from 0 when the store is opened.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// Shared by the writes, the compactor and the garbage collector of a store, clones share the same estimate
+#[derive(Debug, Clone, Default)]
+pub struct DeadBytes {
+    bytes: Arc<AtomicU64>,
+
+    // Share of the value log from which garbage collection is woken up, 0 disables the estimate
+    ratio: f64,
+
+    // Wakes garbage collection up once dead bytes are recorded
+    recorded: Arc<Notify>,
+}
+
+impl DeadBytes {
+    pub fn new(ratio: f64) -> Self {
+        Self {
+            ratio,
+            ..Self::default()
+        }
+    }
+
+    /// Returns true if dead bytes are estimated, they are not recorded otherwise
+    pub fn is_tracked(&self) -> bool {
+        self.ratio > 0.0
+    }
+
+    /// Records `bytes` of the value log as dead
+    pub(crate) fn record(&self, bytes: usize) {
+        if !self.is_tracked() || bytes == 0 {
+            return;
+        }
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.recorded.notify_one();
+    }
+
+    /// Takes off `bytes` garbage collection reclaimed
+    pub(crate) fn reclaimed(&self, bytes: usize) {
+        let _ = self.bytes.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |dead| {
+            Some(dead.saturating_sub(bytes as u64))
+        });
+    }
+
+    /// Forgets every dead byte recorded, garbage collection went through the whole value log
+    pub(crate) fn reset(&self) {
+        self.bytes.store(0, Ordering::Relaxed);
+    }
+
+    /// Returns the estimated number of dead bytes in the value log
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Returns true if the dead bytes exceed the ratio of `len`, the bytes from the tail of the value log on
+    pub(crate) fn exceeds(&self, len: usize) -> bool {
+        self.is_tracked() && len > 0 && self.bytes() as f64 >= self.ratio * len as f64
+    }
+
+    /// Resolves once dead bytes are recorded
+    pub(crate) async fn woken(&self) {
+        self.recorded.notified().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dead_bytes_ratio() {
+        let dead_bytes = DeadBytes::new(0.5);
+        dead_bytes.record(40);
+        dead_bytes.woken().await;
+        assert!(!dead_bytes.exceeds(100));
+        dead_bytes.record(10);
+        assert!(dead_bytes.exceeds(100));
+        dead_bytes.reclaimed(30);
+        assert_eq!(dead_bytes.bytes(), 20);
+        dead_bytes.reclaimed(30);
+        assert_eq!(dead_bytes.bytes(), 0);
+
+        let untracked = DeadBytes::new(0.0);
+        untracked.record(100);
+        assert_eq!(untracked.bytes(), 0);
+        assert!(!untracked.exceeds(100));
+    }
+}
@@ -0,0 +1,94 @@
+//! # Values freed by compaction
+//!
+//! Garbage collection reads the value log from its tail and looks every key up to find out whether the value read
+//! is still live, values of entries dropped by compaction are only reclaimed once the tail reaches them. When
+//! compaction drops expired entries it records the offsets of their values in `FreedValues` once the SSTables they
+//! were merged from are removed, no SSTable points to these values anymore.
+//!
+//! Garbage collection is woken up as soon as values are recorded and keeps collecting until its tail passes every
+//! recorded value, the values are then removed from the disk without waiting for `Config::online_gc_interval`.
+//! Recorded values are garbage without being looked up.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+use crate::types::{Key, ValOffset};
+use crate::value_log::ValueClass;
+
+/// Shared by the compactor and the garbage collector of a store, clones share the same values
+#[derive(Debug, Clone, Default)]
+pub struct FreedValues {
+    // Keys of the values freed, by offset in the value log
+    values: Arc<Mutex<BTreeMap<ValOffset, Key>>>,
+
+    // Wakes garbage collection up once values are recorded
+    recorded: Arc<Notify>,
+}
+
+impl FreedValues {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the values of `freed` as garbage, values stored in the log of their class are reclaimed where they
+    /// are and are left out
+    pub(crate) fn record(&self, mut freed: Vec<(ValOffset, Key)>) {
+        freed.retain(|(offset, _)| ValueClass::of(*offset) == ValueClass::Small);
+        if freed.is_empty() {
+            return;
+        }
+        self.values.lock().unwrap().extend(freed);
+        self.recorded.notify_one();
+    }
+
+    /// Returns true if the value of `key` stored at `offset` was recorded
+    pub(crate) fn contains(&self, offset: ValOffset, key: &[u8]) -> bool {
+        self.values
+            .lock()
+            .unwrap()
+            .get(&offset)
+            .is_some_and(|freed| freed.as_slice() == key)
+    }
+
+    /// Forgets the values stored before `tail`, garbage collection reclaimed them
+    pub(crate) fn reclaimed(&self, tail: usize) {
+        let mut values = self.values.lock().unwrap();
+        *values = values.split_off(&tail);
+    }
+
+    /// Returns the number of values recorded and not reclaimed yet
+    pub fn len(&self) -> usize {
+        self.values.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Resolves once values are recorded
+    pub(crate) async fn woken(&self) {
+        self.recorded.notified().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_record_and_reclaim_freed_values() {
+        let freed = FreedValues::new();
+        freed.record(vec![(10, b"a".to_vec()), (30, b"b".to_vec())]);
+        freed.woken().await;
+        assert!(freed.contains(10, b"a"));
+        assert!(!freed.contains(10, b"b"));
+        assert!(!freed.contains(20, b"a"));
+
+        freed.reclaimed(20);
+        assert_eq!(freed.len(), 1);
+        assert!(freed.contains(30, b"b"));
+        freed.reclaimed(31);
+        assert!(freed.is_empty());
+    }
+}
@@ -1,130 +1,408 @@
-// NOTE: GarbageCollector is only supported on Linux based OS for now because File Systems for other OS does not
-// support the FILE_PUNCH_HOLE command which is crucial for reclaiming unused spaces on the disk
+// NOTE: Punching holes is only supported on Linux based OS because File Systems for other OS does not support the
+// FILE_PUNCH_HOLE command, elsewhere reclaimed space is returned to the disk by copying the live values, see
+// `GCStrategy`
 
 extern crate libc;
 extern crate nix;
-use crate::consts::{TAIL_ENTRY_KEY, TOMB_STONE_MARKER};
+use crate::cfg::ReadOptions;
+use crate::compactors::RateLimiter;
+use crate::consts::TAIL_ENTRY_KEY;
+use crate::events::EventListener;
 use crate::filter::BloomFilter;
-use crate::fs::{FileAsync, FileNode};
+use crate::fs::{FileAsync, FileNode, FsCapabilities};
+use crate::gc::{DeadBytes, FreedValues, GCRun, GCStats, ReadPins};
 use crate::index::Index;
 use crate::memtable::{Entry, MemTable, SkipMapValue};
+use crate::meta::{Meta, Sequence};
+use crate::snapshot::{ReadHorizon, Snapshots};
 use crate::types::{
-    BloomFilterHandle, CreationTime, GCUpdatedEntries, ImmutableMemTable, IsTombStone, Key, KeyRangeHandle,
-    SkipMapEntries, ValOffset, Value,
+    BloomFilterHandle, CreationTime, ExpiresAt, GCUpdatedEntries, ImmutableMemTable, IsTombStone, Key, KeyRangeHandle,
+    RangeTombstonesHandle, SkipMapEntries, ValOffset, Value, WriteTime,
 };
-use crate::value_log::{ValueLog, ValueLogEntry};
+use crate::value_log::{ValueClass, ValueLog, ValueLogEntry};
 use crate::{err, types};
 use crate::{err::Error, storage::*};
 use chrono::Utc;
 use crossbeam_skiplist::SkipMap;
 use err::Error::*;
 use futures::future::join_all;
+#[cfg(target_os = "linux")]
 use nix::libc::{c_int, off_t};
+use std::collections::HashSet;
+use std::future::Future;
+#[cfg(target_os = "linux")]
 use std::os::unix::io::AsRawFd;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{broadcast, RwLock};
+use tokio::sync::{broadcast, Mutex, RwLock};
 use tokio::time::sleep;
 type K = types::Key;
 //type V = types::Value;
 
+#[cfg(target_os = "linux")]
 extern "C" {
     fn fallocate(fd: libc::c_int, mode: c_int, offset: off_t, len: off_t) -> c_int;
 }
 
+#[cfg(target_os = "linux")]
 const FALLOC_FL_PUNCH_HOLE: c_int = 0x2;
+#[cfg(target_os = "linux")]
 const FALLOC_FL_KEEP_SIZE: c_int = 0x1;
 
 type GCTable = Arc<RwLock<MemTable<Key>>>;
 type GCLog = Arc<RwLock<ValueLog>>;
-type ValidEntries = Arc<RwLock<Vec<(Key, Value, ValOffset)>>>;
+type ValidEntries = Arc<RwLock<Vec<(Key, Value, ValOffset, ExpiresAt, CreationTime, WriteTime)>>>;
 type InvalidEntries = Arc<RwLock<Vec<ValueLogEntry>>>;
-type SyncedEntries = Arc<RwLock<Vec<(Key, Value, ValOffset)>>>;
+type LiveEntries = Arc<RwLock<Vec<(Key, Value, ExpiresAt, Option<ValOffset>)>>>;
+type SyncedEntries = Arc<RwLock<Vec<(Key, Value, ValOffset, ExpiresAt, CreationTime, WriteTime)>>>;
+type WrittenKeys = Arc<std::sync::Mutex<Option<HashSet<Key>>>>;
+
+/// Handles of the store a garbage collection pass looks up the entries of the value log in and inserts the
+/// relocated entries into
+#[derive(Debug, Clone)]
+pub struct GCContext {
+    pub memtable: GCTable,
+    pub filters: BloomFilterHandle,
+    pub key_range: KeyRangeHandle,
+    pub read_only_memtables: ImmutableMemTable<K>,
+    pub gc_updated_entries: GCUpdatedEntries<K>,
+    pub range_tombstones: RangeTombstonesHandle,
+
+    /// Values still readable by a snapshot are not relocated
+    pub snapshots: Snapshots,
+}
 
 #[derive(Debug)]
 pub struct GC {
     pub table: GCTable,
     pub vlog: GCLog,
     pub config: Config,
+
+    /// Relocated entries get a new sequence number from the same allocator as writes, the new tail of the
+    /// value log is persisted in it
+    pub meta: Meta,
+
+    /// Failed garbage collections are recorded in it, garbage collection is skipped while an error is recorded
+    pub background_errors: BackgroundErrors,
 }
 #[derive(Clone, Debug)]
 pub struct Config {
     pub online_gc_interval: u64,
     pub gc_chunk_size: usize,
+
+    /// How long overwritten versions stay readable (in milliseconds)
+    pub version_retention: u64,
+
+    /// Values of the entries dropped by compaction, collected without waiting for `online_gc_interval`
+    pub freed_values: FreedValues,
+
+    /// How reclaimed values are removed from the disk, never `GCStrategy::Auto`
+    pub strategy: GCStrategy,
+
+    /// Estimate of the garbage in the value log, collected once it exceeds its ratio of the log
+    pub dead_bytes: DeadBytes,
+
+    /// How long the background task sleeps between the passes of a run (in milliseconds)
+    pub chunk_interval: u64,
+
+    /// Paces the live values passes rewrite at the head of the value log
+    pub rate_limiter: RateLimiter,
+
+    /// Tails pinned by reads, a pass reclaims its chunk once the reads that may still read it are done
+    pub read_pins: ReadPins,
+
+    /// Bytes holes were punched over, time the last pass completed and the last runs, see
+    /// `DataStore::value_log_stats` and `DataStore::gc_history`
+    pub stats: GCStats,
+
+    /// Notified of every completed run
+    pub event_listener: Option<Arc<dyn EventListener>>,
+
+    /// Held by a pass until it is done, passes run by `DataStore::run_gc` and in the background would otherwise
+    /// read the same entries from the tail
+    pub(crate) pass: Arc<Mutex<()>>,
+
+    /// Keys written while a pass runs, `None` between passes. The pass read the values it relocates before these
+    /// writes, relocating them would bring the overwritten values back
+    pub(crate) written_keys: WrittenKeys,
+
+    /// Held by writes from the append of their value until it is in the memtable, and by a pass while it appends
+    /// and inserts the values it relocates, so that a write and a relocation of the same key never interleave
+    pub(crate) write_gate: Arc<Mutex<()>>,
+
+    /// Set by `DataStore::close`, the background task stops and no pass starts anymore
+    pub(crate) shut_down: Arc<AtomicBool>,
+}
+
+/// What a garbage collection pass did, returned by `DataStore::run_gc`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GCReport {
+    /// Bytes of the value log read from its tail
+    pub bytes_scanned: usize,
+
+    /// Live entries read, they are appended again at the head of the value log
+    pub live_entries_rewritten: usize,
+
+    /// Bytes of the live entries read, they are appended again at the head of the value log
+    pub live_bytes_rewritten: usize,
+
+    /// Bytes of the garbage entries read, they are removed from the disk as `GCStrategy` says
+    pub bytes_reclaimed: usize,
+
+    /// Bytes of the values reclaimed from the logs of the other classes, see `ValueClass`
+    pub class_bytes_reclaimed: usize,
+
+    /// Tail of the value log once the pass is done, unchanged if a value read is still readable by a snapshot or
+    /// its key was written while the pass ran
+    pub tail_offset: usize,
+}
+
+/// How garbage collection removes the values it reclaimed from the value log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GCStrategy {
+    /// `PunchHole` if the file system holding the value log can punch holes, `CopyAndTruncate` otherwise. Probed
+    /// when the store is opened
+    #[default]
+    Auto,
+
+    /// Punches a hole over the values reclaimed, the file keeps its length but not the disk space. Only Linux file
+    /// systems supporting `FALLOC_FL_PUNCH_HOLE` can
+    PunchHole,
+
+    /// Copies the entries from the tail on to a new file that replaces the value log once the values reclaimed
+    /// take at least as much space as them, so that no more is copied than reclaimed. Works on every platform
+    CopyAndTruncate,
+}
+
+impl GCStrategy {
+    /// Resolves `Auto` with the `capabilities` of the file system holding the value log
+    ///
+    /// `PunchHole` falls back to `CopyAndTruncate` where holes cannot be punched, so garbage collection does not
+    /// fail on every pass
+    pub fn resolve(self, capabilities: &FsCapabilities) -> GCStrategy {
+        match self {
+            GCStrategy::CopyAndTruncate => self,
+            _ if capabilities.punch_hole => GCStrategy::PunchHole,
+            GCStrategy::Auto => {
+                log::info!("File system cannot punch holes, garbage collection copies live values");
+                GCStrategy::CopyAndTruncate
+            }
+            GCStrategy::PunchHole => {
+                log::warn!("File system cannot punch holes, garbage collection copies live values instead");
+                GCStrategy::CopyAndTruncate
+            }
+        }
+    }
 }
 
 impl GC {
-    pub fn new(online_gc_interval: u64, gc_chunk_size: usize, table: GCTable, vlog: GCLog) -> Self {
+    pub fn new(
+        online_gc_interval: u64,
+        gc_chunk_size: usize,
+        version_retention: u64,
+        table: GCTable,
+        vlog: GCLog,
+        meta: Meta,
+        background_errors: BackgroundErrors,
+    ) -> Self {
         Self {
             table,
             vlog,
             config: Config {
                 online_gc_interval,
                 gc_chunk_size,
+                version_retention,
+                freed_values: FreedValues::new(),
+                strategy: GCStrategy::PunchHole,
+                dead_bytes: DeadBytes::default(),
+                chunk_interval: 0,
+                rate_limiter: RateLimiter::default(),
+                read_pins: ReadPins::default(),
+                stats: GCStats::default(),
+                event_listener: None,
+                pass: Arc::new(Mutex::new(())),
+                written_keys: Arc::new(std::sync::Mutex::new(None)),
+                write_gate: Arc::new(Mutex::new(())),
+                shut_down: Arc::new(AtomicBool::new(false)),
             },
+            meta,
+            background_errors,
         }
     }
-    pub fn start_background_gc_task(
-        &self,
-        filters: BloomFilterHandle,
-        key_range: KeyRangeHandle,
-        read_only_memtables: ImmutableMemTable<K>,
-        gc_updated_entries: GCUpdatedEntries<K>,
-    ) {
+    /// Shares `freed_values` with the compactor
+    pub fn with_freed_values(mut self, freed_values: FreedValues) -> Self {
+        self.config.freed_values = freed_values;
+        self
+    }
+
+    /// Shares `dead_bytes` with the writes and the compactor
+    pub fn with_dead_bytes(mut self, dead_bytes: DeadBytes) -> Self {
+        self.config.dead_bytes = dead_bytes;
+        self
+    }
+
+    /// Sleeps `chunk_interval` milliseconds between the passes of a background run
+    pub fn with_chunk_interval(mut self, chunk_interval: u64) -> Self {
+        self.config.chunk_interval = chunk_interval;
+        self
+    }
+
+    /// Paces the live values rewritten with `rate_limiter`
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.config.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// Notifies `event_listener` of every completed run
+    pub fn with_event_listener(mut self, event_listener: Option<Arc<dyn EventListener>>) -> Self {
+        self.config.event_listener = event_listener;
+        self
+    }
+
+    /// Removes reclaimed values with `strategy`, resolve `GCStrategy::Auto` first
+    pub fn with_strategy(mut self, strategy: GCStrategy) -> Self {
+        self.config.strategy = strategy;
+        self
+    }
+
+    pub fn start_background_gc_task(&self, ctx: GCContext) {
         let cfg = self.config.to_owned();
-        let memtable = self.table.clone();
         let vlog = self.vlog.clone();
-        let table_ref = Arc::clone(&memtable);
-        let vlog_ref = Arc::clone(&vlog);
-        let filters_ref = Arc::clone(&filters);
-        let key_range_ref = Arc::clone(&key_range);
-        let read_only_memtables_ref = Arc::clone(&read_only_memtables);
-        let gc_updated_entries_ref = Arc::clone(&gc_updated_entries);
+        let meta = self.meta.clone();
+        let background_errors = self.background_errors.clone();
         tokio::spawn(async move {
             loop {
-                sleep_gc_task(cfg.online_gc_interval).await;
-                let res = GC::gc_handler(
-                    &cfg,
-                    Arc::clone(&table_ref),
-                    Arc::clone(&vlog_ref),
-                    Arc::clone(&filters_ref),
-                    Arc::clone(&key_range_ref),
-                    Arc::clone(&read_only_memtables_ref),
-                    Arc::clone(&gc_updated_entries_ref),
-                )
-                .await;
-                match res {
-                    Ok(_) => {
-                        log::info!("GC successful, tail shifted {}", vlog.read().await.tail_offset)
+                // Values freed by compaction and dead bytes past the ratio are reclaimed without waiting for the
+                // interval
+                let woken_by_dead_bytes = tokio::select! {
+                    _ = sleep_gc_task(cfg.online_gc_interval) => false,
+                    _ = cfg.freed_values.woken() => false,
+                    _ = cfg.dead_bytes.woken() => true,
+                };
+                if cfg.shut_down.load(Ordering::SeqCst) {
+                    return;
+                }
+                if woken_by_dead_bytes && !GC::exceeds_garbage_ratio(&cfg, &vlog).await {
+                    continue;
+                }
+                // Dead bytes are collected up to the end of the log as it is now, so that a wrong estimate does
+                // not keep relocating the same live values
+                let sweep_end = GC::end_offset(&vlog).await;
+                let mut run = GCRun::start();
+                // Collects until the tail passes the values freed by compaction and the dead bytes fall below the
+                // ratio, a collection that does not shift the tail is not repeated
+                loop {
+                    if background_errors.get().is_some() || cfg.shut_down.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let tail = vlog.read().await.tail_offset;
+                    let res = GC::gc_handler(&cfg, ctx.clone(), Arc::clone(&vlog), meta.clone()).await;
+                    match res {
+                        Ok(report) => {
+                            run.add_pass(&report);
+                            log::info!("GC successful, tail shifted {}", vlog.read().await.tail_offset)
+                        }
+                        Err(err) => {
+                            log::error!("{}", GCError(err.to_string()));
+                            background_errors.record(err);
+                            break;
+                        }
                     }
-                    Err(err) => {
-                        log::error!("{}", GCError(err.to_string()))
+                    let new_tail = vlog.read().await.tail_offset;
+                    cfg.freed_values.reclaimed(new_tail);
+                    if new_tail >= sweep_end {
+                        cfg.dead_bytes.reset();
                     }
+                    let collects_dead_bytes = new_tail < sweep_end && GC::exceeds_garbage_ratio(&cfg, &vlog).await;
+                    if new_tail == tail || (cfg.freed_values.is_empty() && !collects_dead_bytes) {
+                        break;
+                    }
+                    if cfg.chunk_interval > 0 {
+                        sleep_gc_task(cfg.chunk_interval).await;
+                    }
+                }
+                if run.chunks > 0 {
+                    GC::run_completed(&cfg, run);
                 }
             }
         });
     }
 
-    pub async fn gc_handler(
-        cfg: &Config,
-        memtable: GCTable,
-        vlog: GCLog,
-        filters: BloomFilterHandle,
-        key_range: KeyRangeHandle,
-        read_only_memtables: ImmutableMemTable<K>,
-        gc_updated_entries: GCUpdatedEntries<Key>,
-    ) -> Result<(), Error> {
+    /// Keeps `run` in the history and notifies the event listener
+    pub(crate) fn run_completed(cfg: &Config, mut run: GCRun) {
+        run.finish();
+        cfg.stats.run_completed(run);
+        if let Some(listener) = &cfg.event_listener {
+            listener.on_gc_completed(&run);
+        }
+    }
+
+    pub async fn gc_handler(cfg: &Config, ctx: GCContext, vlog: GCLog, mut meta: Meta) -> Result<GCReport, Error> {
+        let GCContext {
+            memtable,
+            filters,
+            key_range,
+            read_only_memtables,
+            gc_updated_entries,
+            range_tombstones,
+            snapshots,
+        } = ctx;
+        let _pass = cfg.pass.lock().await;
+        // The store was closed while the pass waited for the one before it
+        if cfg.shut_down.load(Ordering::SeqCst) {
+            return Ok(GCReport {
+                tail_offset: vlog.read().await.tail_offset,
+                ..Default::default()
+            });
+        }
+        let _writes = TrackedWrites::start(&cfg.written_keys);
+        let sequence = meta.sequence.clone();
+        // Values still readable through a live snapshot or within the retention window must stay where they are,
+        // relocated values get a new sequence number and are no longer found at older points in time
+        let oldest_readable = snapshots.oldest_readable(cfg.version_retention);
+        let visible = oldest_readable
+            .map(|horizon| horizon.read_options())
+            .unwrap_or_default();
+        let locate = |key: Key| {
+            let (memtable, filters, key_range, read_only_memtables) = (
+                Arc::clone(&memtable),
+                Arc::clone(&filters),
+                Arc::clone(&key_range),
+                Arc::clone(&read_only_memtables),
+            );
+            async move { GC::locate(&key, memtable, filters, key_range, read_only_memtables).await }
+        };
+        let class_bytes_reclaimed = GC::reclaim_class_values(
+            cfg,
+            Arc::clone(&vlog),
+            &range_tombstones,
+            oldest_readable,
+            &visible,
+            locate,
+        )
+        .await
+        .map_err(|err| GCError(err.to_string()))?;
+        let holds_readable_values = Arc::new(AtomicBool::new(false));
+        let live_bytes = Arc::new(AtomicUsize::new(0));
         let invalid_entries = Arc::new(RwLock::new(Vec::new()));
         let valid_entries = Arc::new(RwLock::new(Vec::new()));
         let synced_entries = Arc::new(RwLock::new(Vec::new()));
         let vlog_reader = vlog.read().await;
         let punch_hole_start_offset = vlog_reader.tail_offset.to_owned();
+        let format = vlog_reader.format();
         let chunk_res = vlog_reader.read_chunk_to_garbage_collect(cfg.gc_chunk_size).await;
         drop(vlog_reader);
-        Ok(match chunk_res {
+        match chunk_res {
             Ok((entries, total_bytes_read)) => {
+                let chunk_keys: HashSet<Key> = entries.iter().map(|entry| entry.key.to_owned()).collect();
+                let mut next_offset = punch_hole_start_offset;
                 let tasks = entries.into_iter().map(|entry| {
+                    let entry_offset = next_offset;
+                    next_offset += entry.serialized_len(format);
+                    let entry_len = next_offset - entry_offset;
+                    let live_bytes_ref = Arc::clone(&live_bytes);
                     let invalid_entries_ref = Arc::clone(&invalid_entries);
                     let valid_entries_ref = Arc::clone(&valid_entries);
                     let table_ref = Arc::clone(&memtable);
@@ -132,22 +410,66 @@ impl GC {
                     let filters_ref = Arc::clone(&filters);
                     let key_range_ref = Arc::clone(&key_range);
                     let read_only_memtables_ref = Arc::clone(&read_only_memtables);
+                    let range_tombstones_ref = Arc::clone(&range_tombstones);
+                    let holds_readable_values_ref = Arc::clone(&holds_readable_values);
+                    let freed_values = cfg.freed_values.clone();
+                    let visible = visible.clone();
                     tokio::spawn(async move {
-                        let most_recent_value = GC::get(
-                            std::str::from_utf8(&entry.key).unwrap(),
+                        // Compaction dropped the entry pointing to the value, no other entry points to it
+                        if freed_values.contains(entry_offset, &entry.key) {
+                            invalid_entries_ref.write().await.push(entry);
+                            return Ok(());
+                        }
+                        let located = GC::locate(
+                            &entry.key,
                             Arc::clone(&table_ref),
                             Arc::clone(&filters_ref),
                             Arc::clone(&key_range_ref),
-                            Arc::clone(&vlog_ref),
                             Arc::clone(&read_only_memtables_ref),
                         )
                         .await;
+                        // Only the pointer to a value stored in the log of its class is relocated and a value an
+                        // SSTable holds a copy of is never relocated, neither is read
+                        let most_recent_value = match located {
+                            Ok((location, creation_time, written_at, is_inline))
+                                if is_inline || entry.redirect().is_some() =>
+                            {
+                                Ok((entry.value.to_owned(), creation_time, written_at, location, is_inline))
+                            }
+                            Ok((location, creation_time, written_at, _)) => {
+                                GC::get_value_from_vlog(Arc::clone(&vlog_ref), location, creation_time)
+                                    .await
+                                    .map(|(value, creation_time, location)| {
+                                        (value, creation_time, written_at, location, false)
+                                    })
+                            }
+                            Err(err) => Err(err),
+                        };
                         match most_recent_value {
-                            Ok((value, creation_time)) => {
-                                if entry.created_at != creation_time || value == TOMB_STONE_MARKER.as_bytes().to_vec() {
+                            Ok((value, creation_time, written_at, val_offset, is_inline)) => {
+                                // Entries deleted by a range tombstone are garbage as well
+                                let range_tombstones = range_tombstones_ref.read().await;
+                                let range_deleted = range_tombstones.covers(&entry.key, creation_time, &visible);
+                                drop(range_tombstones);
+                                // A value rewritten by a compaction filter keeps the creation time of the version
+                                let overwritten = entry.created_at != creation_time
+                                    || entry.redirect().unwrap_or(entry_offset) != val_offset;
+                                let is_garbage = overwritten || is_inline || entry.is_tombstone || range_deleted;
+                                // An overwritten value is readable until the version replacing it was created
+                                if oldest_readable.is_some_and(|horizon| {
+                                    !is_garbage || (overwritten && horizon.reads_replaced(creation_time, written_at))
+                                }) {
+                                    holds_readable_values_ref.store(true, Ordering::SeqCst);
+                                }
+                                if is_garbage {
                                     invalid_entries_ref.write().await.push(entry);
                                 } else {
-                                    valid_entries_ref.write().await.push((entry.key, value));
+                                    live_bytes_ref.fetch_add(entry_len, Ordering::SeqCst);
+                                    let pointed = entry.redirect();
+                                    valid_entries_ref
+                                        .write()
+                                        .await
+                                        .push((entry.key, value, entry.expires_at, pointed));
                                 }
                                 Ok(())
                             }
@@ -169,65 +491,135 @@ impl GC {
                         }
                     }
                 }
+                // Waits before the write gate is taken so that writes are not held up meanwhile
+                cfg.rate_limiter.acquire(live_bytes.load(Ordering::SeqCst)).await;
+                // A write in flight while the entries were looked up may not have been seen, its key is left to a
+                // later pass
+                let write_gate = cfg.write_gate.lock().await;
+                let written_during_pass = cfg
+                    .written_keys
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .is_some_and(|keys| keys.iter().any(|key| chunk_keys.contains(key)));
+                if holds_readable_values.load(Ordering::SeqCst) || written_during_pass {
+                    cfg.stats.pass_completed();
+                    return Ok(GCReport {
+                        bytes_scanned: total_bytes_read,
+                        class_bytes_reclaimed,
+                        tail_offset: punch_hole_start_offset,
+                        ..Default::default()
+                    });
+                }
+                let live_entries = valid_entries.read().await.len();
                 let new_tail_offset = vlog.read().await.tail_offset + total_bytes_read;
-                let append_res = GC::update_tail(Arc::clone(&vlog), new_tail_offset).await;
+                let tail_created_at = sequence.next();
+                let tail_written_at = Utc::now().timestamp_millis() as u64;
+                let append_res = GC::update_tail(Arc::clone(&vlog), new_tail_offset, tail_created_at).await;
                 match append_res {
                     Ok(v_offset) => {
                         synced_entries.write().await.push((
                             TAIL_ENTRY_KEY.to_vec(),
                             new_tail_offset.to_le_bytes().to_vec(),
                             v_offset,
+                            None,
+                            tail_created_at,
+                            tail_written_at,
                         ));
-                        if let Err(err) =
-                            GC::write_valid_entries_to_vlog(valid_entries, synced_entries.to_owned(), Arc::clone(&vlog))
-                                .await
+                        if let Err(err) = GC::write_valid_entries_to_vlog(
+                            valid_entries,
+                            synced_entries.to_owned(),
+                            Arc::clone(&vlog),
+                            &sequence,
+                        )
+                        .await
                         {
                             return Err(GCError(err.to_string()));
                         }
+                        drop(write_gate);
                         // call fsync on vlog to guarantee persistence to disk
                         let sync_res = vlog.write().await.sync_to_disk().await;
                         match sync_res {
                             Ok(_) => {
                                 vlog.write().await.set_tail(new_tail_offset);
+                                if let Err(err) = meta.advance_v_log_tail(new_tail_offset).await {
+                                    return Err(GCError(err.to_string()));
+                                }
+                                let write_gate = cfg.write_gate.lock().await;
                                 if let Err(err) = GC::write_valid_entries_to_store(
                                     synced_entries.to_owned(),
                                     Arc::clone(&memtable),
                                     gc_updated_entries,
+                                    &cfg.written_keys,
                                     Arc::clone(&vlog),
                                 )
                                 .await
                                 {
                                     return Err(GCError(err.to_string()));
                                 }
+                                drop(write_gate);
 
-                                if let Err(err) = GC::remove_unsed(
+                                // Reads that looked up an offset before the relocated values were inserted may
+                                // still read the chunk
+                                cfg.read_pins.advance(new_tail_offset);
+                                if !cfg.read_pins.released_before(new_tail_offset).await {
+                                    log::warn!("Store closed while reads held the chunk, its space is not reclaimed");
+                                } else if let Err(err) = GC::remove_unsed(
                                     Arc::clone(&vlog),
                                     invalid_entries,
+                                    cfg.strategy,
                                     punch_hole_start_offset,
                                     total_bytes_read,
                                 )
                                 .await
                                 {
                                     return Err(GCError(err.to_string()));
-                                };
+                                } else if cfg.strategy == GCStrategy::PunchHole {
+                                    cfg.stats.punched(total_bytes_read);
+                                }
+                                let live_bytes = live_bytes.load(Ordering::SeqCst);
+                                cfg.dead_bytes.reclaimed(total_bytes_read - live_bytes);
+                                cfg.stats.pass_completed();
+                                Ok(GCReport {
+                                    bytes_scanned: total_bytes_read,
+                                    live_entries_rewritten: live_entries,
+                                    live_bytes_rewritten: live_bytes,
+                                    bytes_reclaimed: total_bytes_read - live_bytes,
+                                    class_bytes_reclaimed,
+                                    tail_offset: new_tail_offset,
+                                })
                             }
-                            Err(err) => return Err(GCError(err.to_string())),
+                            Err(err) => Err(GCError(err.to_string())),
                         }
                     }
-                    Err(err) => return Err(GCError(err.to_string())),
+                    Err(err) => Err(GCError(err.to_string())),
                 }
             }
-            Err(err) => return Err(GCError(err.to_string())),
-        })
+            Err(err) => Err(GCError(err.to_string())),
+        }
+    }
+
+    // Returns true if the dead bytes exceed their ratio of the entries from the tail of the value log on
+    async fn exceeds_garbage_ratio(cfg: &Config, vlog: &GCLog) -> bool {
+        if !cfg.dead_bytes.is_tracked() {
+            return false;
+        }
+        let tail = vlog.read().await.tail_offset;
+        cfg.dead_bytes.exceeds(GC::end_offset(vlog).await.saturating_sub(tail))
+    }
+
+    // Returns the offset the next entry of the value log is appended at
+    async fn end_offset(vlog: &GCLog) -> usize {
+        vlog.read().await.end_offset().await
     }
 
-    pub async fn update_tail(vlog: GCLog, new_tail_offset: usize) -> Result<usize, Error> {
+    pub async fn update_tail(vlog: GCLog, new_tail_offset: usize, created_at: CreationTime) -> Result<usize, Error> {
         vlog.write()
             .await
             .append(
                 &TAIL_ENTRY_KEY.to_vec(),
                 &new_tail_offset.to_le_bytes().to_vec(),
-                Utc::now().timestamp_millis() as u64,
+                created_at,
                 false,
             )
             .await
@@ -237,22 +629,25 @@ impl GC {
         valid_entries: ValidEntries,
         table: GCTable,
         gc_updated_entries: GCUpdatedEntries<Key>,
+        written_keys: &WrittenKeys,
         vlog: GCLog,
     ) -> Result<(), Error> {
-        gc_updated_entries.write().await.clear();
-        for (key, value, existing_v_offset) in valid_entries.to_owned().read().await.iter() {
-            if let Err(err) = GC::put(
-                std::str::from_utf8(&key).unwrap(),
-                std::str::from_utf8(&value).unwrap(),
-                *existing_v_offset,
-                Arc::clone(&table),
-                gc_updated_entries.clone(),
-            )
-            .await
-            {
+        // Entries relocated by earlier passes are applied by the next write, their former values were reclaimed.
+        // An entry keeps the creation time of its relocated value, which later passes compare with it
+        for (key, _, existing_v_offset, expires_at, created_at, written_at) in
+            valid_entries.to_owned().read().await.iter()
+        {
+            // Only live entries are relocated, a relocated empty value is not a tombstone
+            let entry = Entry::new(key.to_owned(), *existing_v_offset, *created_at, false)
+                .with_written_at(*written_at)
+                .with_expiry(*expires_at);
+            if let Err(err) = GC::put(&entry, Arc::clone(&table), gc_updated_entries.clone(), written_keys).await {
                 return Err(err);
             };
-            if existing_v_offset > &vlog.read().await.head_offset {
+            // The head is not moved past the pointer of a value stored in the log of its class, which is replayed
+            if ValueClass::of(*existing_v_offset) == ValueClass::Small
+                && existing_v_offset > &vlog.read().await.head_offset
+            {
                 vlog.write().await.set_head(*existing_v_offset)
             }
         }
@@ -260,23 +655,40 @@ impl GC {
     }
 
     pub async fn write_valid_entries_to_vlog(
-        valid_entries: Arc<RwLock<Vec<(Key, Value)>>>,
+        valid_entries: LiveEntries,
         synced_entries: SyncedEntries,
         vlog: GCLog,
+        sequence: &Sequence,
     ) -> Result<(), Error> {
-        for (key, value) in valid_entries.to_owned().read().await.iter() {
-            let append_res = vlog
-                .write()
-                .await
-                .append(&key, &value, Utc::now().timestamp_millis() as u64, false)
-                .await;
+        for (key, value, expires_at, pointed) in valid_entries.to_owned().read().await.iter() {
+            let created_at = sequence.next();
+            let written_at = Utc::now().timestamp_millis() as u64;
+            // A pointer is appended again, the value it points to stays where it is
+            let append_res = match pointed {
+                Some(location) => vlog
+                    .write()
+                    .await
+                    .append_redirect(key, *location, created_at, written_at, *expires_at)
+                    .await
+                    .map(|_| *location),
+                None => {
+                    vlog.write()
+                        .await
+                        .append_with_expiry(key, value, created_at, written_at, false, *expires_at)
+                        .await
+                }
+            };
 
             match append_res {
                 Ok(v_offset) => {
-                    synced_entries
-                        .write()
-                        .await
-                        .push((key.to_owned(), value.to_owned(), v_offset));
+                    synced_entries.write().await.push((
+                        key.to_owned(),
+                        value.to_owned(),
+                        v_offset,
+                        *expires_at,
+                        created_at,
+                        written_at,
+                    ));
                 }
                 Err(err) => {
                     return Err(err);
@@ -289,24 +701,148 @@ impl GC {
     pub async fn remove_unsed(
         vlog: GCLog,
         invalid_entries: InvalidEntries,
+        strategy: GCStrategy,
         punch_hole_start_offset: usize,
         punch_hole_length: usize,
     ) -> std::result::Result<(), Error> {
-        let vlog_path = vlog.read().await.content.file.node.file_path.to_owned();
+        if strategy == GCStrategy::CopyAndTruncate {
+            return GC::truncate_reclaimed(vlog).await;
+        }
+        let vlog = vlog.read().await;
+        let vlog_path = vlog.content.file.node.file_path.to_owned();
+        // Holes are punched in the file, whose first byte may not be the start of the log
+        let punch_hole_start_offset = punch_hole_start_offset - vlog.base();
+        drop(vlog);
         #[cfg(target_os = "linux")]
         {
             GC::punch_holes(vlog_path, punch_hole_start_offset as i64, punch_hole_length as i64).await
         }
         #[cfg(not(target_os = "linux"))]
         {
+            let _ = (vlog_path, punch_hole_start_offset, punch_hole_length);
             return Err(Error::GCErrorUnsupportedPlatform(String::from(
                 "File system does not support file punch hole",
             )));
         }
     }
 
+    // Reclaims the values of the logs of the other classes that are no longer looked up, except those still readable
+    // through a snapshot. Keys are looked up with `locate`, returns the bytes reclaimed
+    //
+    // A value is reclaimed where it is, its hole is punched and its record marked, or with `CopyAndTruncate` once
+    // the values preceding the first live one take at least as much space as the values that follow
+    async fn reclaim_class_values<F: Future<Output = Result<(ValOffset, CreationTime, WriteTime, bool), Error>>>(
+        cfg: &Config,
+        vlog: GCLog,
+        range_tombstones: &RangeTombstonesHandle,
+        oldest_readable: Option<ReadHorizon>,
+        visible: &ReadOptions,
+        locate: impl Fn(Key) -> F,
+    ) -> Result<usize, Error> {
+        let mut reclaimed = 0;
+        for class in ValueClass::SEPARATE {
+            // Values appended before the end are in the memtable, a value appended later may not be found yet
+            let write_gate = cfg.write_gate.lock().await;
+            let vlog_reader = vlog.read().await;
+            let Some(log) = vlog_reader.class_log(class) else {
+                continue;
+            };
+            let end = log.end_offset().await;
+            drop(write_gate);
+            let spans = log.spans(log.first_offset()).await?;
+            drop(vlog_reader);
+
+            let mut dead = Vec::new();
+            let mut first_live = None;
+            for span in spans.into_iter().filter(|span| span.offset < end) {
+                if span.is_reclaimed() {
+                    continue;
+                }
+                let location = class.location(span.offset);
+                let is_dead = match locate(span.key.to_owned()).await {
+                    Ok((located, creation_time, written_at, _)) => {
+                        let overwritten = located != location;
+                        let range_deleted = range_tombstones.read().await.covers(&span.key, creation_time, visible);
+                        // An overwritten value is readable until the version replacing it was created
+                        let readable = oldest_readable
+                            .is_some_and(|horizon| overwritten && horizon.reads_replaced(creation_time, written_at));
+                        (overwritten || range_deleted) && !readable
+                    }
+                    Err(err) if GC::is_deleted(&err) => true,
+                    Err(err) => return Err(err),
+                };
+                if is_dead {
+                    dead.push(span);
+                } else if first_live.is_none() {
+                    first_live = Some(span.offset);
+                }
+            }
+            if dead.is_empty() {
+                continue;
+            }
+            // Reads that looked a dead value up before it was replaced may still read it
+            let epoch = cfg.read_pins.next_epoch();
+            if !cfg.read_pins.released_before_epoch(epoch).await {
+                log::warn!("Store closed while reads held values of the {:?} value log", class);
+                return Ok(reclaimed);
+            }
+            let vlog = vlog.read().await;
+            let Some(log) = vlog.class_log(class) else {
+                continue;
+            };
+            if cfg.strategy == GCStrategy::CopyAndTruncate {
+                let live_start = first_live.unwrap_or(end);
+                let live = log.end_offset().await.saturating_sub(live_start);
+                if live_start > log.first_offset() && live_start - log.first_offset() >= live {
+                    reclaimed += log.truncate_before(live_start).await?;
+                }
+                continue;
+            }
+            for span in dead.iter().filter(|span| span.value_len > 0) {
+                // Holes are punched in the file, whose first byte may not be the start of the log
+                let value_start = span.value_offset - log.base();
+                #[cfg(target_os = "linux")]
+                GC::punch_holes(
+                    log.content.path.to_owned(),
+                    value_start as off_t,
+                    span.value_len as off_t,
+                )
+                .await?;
+                #[cfg(not(target_os = "linux"))]
+                {
+                    let _ = value_start;
+                    return Err(Error::GCErrorUnsupportedPlatform(String::from(
+                        "File system does not support file punch hole",
+                    )));
+                }
+                log.mark_reclaimed(span).await?;
+                cfg.stats.punched(span.value_len);
+                reclaimed += span.value_len;
+            }
+        }
+        Ok(reclaimed)
+    }
+
+    // Drops the entries preceding the tail of the value log once they take at least as much space as the entries
+    // that follow, the copy of the live entries is paid for by the space reclaimed
+    async fn truncate_reclaimed(vlog: GCLog) -> std::result::Result<(), Error> {
+        let vlog = vlog.read().await;
+        vlog.write_buffer().await?;
+        let file_len = vlog.content.file.node.metadata().await?.len() as usize;
+        let reclaimed = vlog.tail_offset.saturating_sub(vlog.first_offset());
+        let live = (vlog.base() + file_len).saturating_sub(vlog.tail_offset);
+        if reclaimed == 0 || reclaimed < live {
+            return Ok(());
+        }
+        let dropped = vlog.truncate_before(vlog.tail_offset).await?;
+        log::info!("GC dropped {} bytes from the start of the value log", dropped);
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
     pub async fn punch_holes(file_path: PathBuf, offset: off_t, length: off_t) -> std::result::Result<(), Error> {
-        let file = FileNode::open(file_path).await?;
+        // Punching holes requires a file opened for writing
+        let file = FileNode::create(file_path).await?;
         let fd = file.as_raw_fd();
         unsafe {
             let result = fallocate(fd, FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE, offset, length);
@@ -322,42 +858,61 @@ impl GC {
     }
 
     pub async fn put(
-        key: &str,
-        value: &str,
-        val_offset: ValOffset,
+        entry: &Entry<Key, ValOffset>,
         memtable: GCTable,
         gc_updated_entries: GCUpdatedEntries<Key>,
+        written_keys: &WrittenKeys,
     ) -> Result<bool, Error> {
-        let is_tombstone = value.len() == 0;
-        let key = &key.as_bytes().to_vec();
-        let created_at = Utc::now().timestamp_millis() as u64;
-        let v_offset = val_offset;
-        let entry = Entry::new(key.to_vec(), v_offset, created_at, is_tombstone);
-        memtable.write().await.insert(&entry)?;
-        gc_updated_entries
-            .write()
-            .await
-            .insert(key.to_vec(), SkipMapValue::new(v_offset, created_at, is_tombstone));
+        // The key was written after the pass read the value, the write holds a more recent one
+        if written_keys
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|keys| keys.contains(&entry.key))
+        {
+            return Ok(false);
+        }
+        memtable.write().await.insert(entry)?;
+        gc_updated_entries.write().await.insert(
+            entry.key.to_owned(),
+            SkipMapValue::new(entry.val_offset, entry.created_at, entry.is_tombstone)
+                .with_written_at(entry.written_at)
+                .with_expiry(entry.expires_at),
+        );
         Ok(true)
     }
 
     pub async fn get(
-        key: &str,
+        key: &[u8],
         memtable: GCTable,
         filters: BloomFilterHandle,
         key_range: KeyRangeHandle,
         vlog: Arc<RwLock<ValueLog>>,
         read_only_memtables: ImmutableMemTable<K>,
-    ) -> Result<(Value, CreationTime), Error> {
-        let key = key.as_bytes().to_vec();
+    ) -> Result<(Value, CreationTime, ValOffset), Error> {
+        let (offset, creation_time, _, _) = GC::locate(key, memtable, filters, key_range, read_only_memtables).await?;
+        GC::get_value_from_vlog(vlog, offset, creation_time).await
+    }
+
+    // Returns the location of the most recent value of `key`, its creation time, its write time and whether an SSTable
+    // holds a copy of the value, the value log no longer has to keep it then
+    async fn locate(
+        key: &[u8],
+        memtable: GCTable,
+        filters: BloomFilterHandle,
+        key_range: KeyRangeHandle,
+        read_only_memtables: ImmutableMemTable<K>,
+    ) -> Result<(ValOffset, CreationTime, WriteTime, bool), Error> {
+        let key = key.to_vec();
         let mut offset = 0;
         let mut most_recent_insert_time = 0;
+        let mut most_recent_write_time = 0;
         // Step 1: Check the active memtable
         if let Some(value) = memtable.read().await.get(&key) {
-            if value.is_tombstone {
+            if value.is_deleted() {
                 return Err(NotFoundInDB);
             }
-            return GC::get_value_from_vlog(vlog, value.val_offset, value.created_at).await;
+            return Ok((value.val_offset, value.created_at, value.written_at, false));
         } else {
             // Step 2: Check the read-only memtables
             let mut is_deleted = false;
@@ -366,7 +921,8 @@ impl GC {
                     if value.created_at > most_recent_insert_time {
                         offset = value.val_offset;
                         most_recent_insert_time = value.created_at;
-                        is_deleted = value.is_tombstone
+                        most_recent_write_time = value.written_at;
+                        is_deleted = value.is_deleted()
                     }
                 }
             }
@@ -374,9 +930,10 @@ impl GC {
                 if is_deleted {
                     return Err(NotFoundInDB);
                 }
-                return GC::get_value_from_vlog(vlog, offset, most_recent_insert_time).await;
+                return Ok((offset, most_recent_insert_time, most_recent_write_time, false));
             } else {
                 // Step 3: Check sstables
+                let mut is_inline = false;
                 let key_range = &key_range.read().await;
                 let mut ssts = key_range.filter_sstables_by_biggest_key(&key);
                 if ssts.is_empty() {
@@ -394,15 +951,18 @@ impl GC {
                         Ok(None) => continue,
                         Ok(result) => {
                             if let Some(block_offset) = result {
-                                let sst_res = sst.get(block_offset, &key).await;
+                                let sst_res = sst.get(block_offset, &key, true, None).await;
                                 match sst_res {
                                     Ok(None) => continue,
                                     Ok(result) => {
-                                        if let Some((val_offset, created_at, is_tombstone)) = result {
+                                        if let Some((val_offset, created_at, written_at, is_tombstone, value)) = result
+                                        {
                                             if created_at > most_recent_insert_time {
                                                 offset = val_offset;
                                                 most_recent_insert_time = created_at;
+                                                most_recent_write_time = written_at;
                                                 is_deleted = is_tombstone;
+                                                is_inline = value.is_some();
                                             }
                                         }
                                     }
@@ -417,8 +977,7 @@ impl GC {
                     if is_deleted {
                         return Err(NotFoundInDB);
                     }
-                    // Step 5: Read value from value log based on offset
-                    return GC::get_value_from_vlog(vlog, offset, most_recent_insert_time).await;
+                    return Ok((offset, most_recent_insert_time, most_recent_write_time, is_inline));
                 }
             }
         }
@@ -429,14 +988,14 @@ impl GC {
         val_log: Arc<RwLock<ValueLog>>,
         offset: usize,
         creation_time: CreationTime,
-    ) -> Result<(Value, CreationTime), Error> {
+    ) -> Result<(Value, CreationTime, ValOffset), Error> {
         let res = val_log.read().await.get(offset).await?;
         match res {
             Some((value, is_tombstone)) => {
                 if is_tombstone {
                     return Err(KeyFoundAsTombstoneInValueLogError);
                 }
-                return Ok((value, creation_time));
+                return Ok((value, creation_time, offset));
             }
             None => return Err(KeyNotFoundInValueLogError),
         };
@@ -447,42 +1006,46 @@ impl GC {
         entry: ValueLogEntry,
         err: Error,
     ) -> std::result::Result<(), Error> {
-        match err {
-            KeyFoundAsTombstoneInMemtableError => {
-                invalid_entries_ref.write().await.push(entry);
-                Ok(())
-            }
-            KeyNotFoundInAnySSTableError => {
-                invalid_entries_ref.write().await.push(entry);
-                Ok(())
-            }
-            KeyNotFoundByAnyBloomFilterError => {
-                invalid_entries_ref.write().await.push(entry);
-                Ok(())
-            }
-            KeyFoundAsTombstoneInSSTableError => {
-                invalid_entries_ref.write().await.push(entry);
-                Ok(())
-            }
-            KeyFoundAsTombstoneInValueLogError => {
-                invalid_entries_ref.write().await.push(entry);
-                Ok(())
-            }
-            err::Error::KeyNotFoundInValueLogError => {
-                invalid_entries_ref.write().await.push(entry);
-                Ok(())
-            }
-            NotFoundInDB => {
-                invalid_entries_ref.write().await.push(entry);
-                Ok(())
-            }
-            _ => return Err(err),
+        if !GC::is_deleted(&err) {
+            return Err(err);
         }
+        invalid_entries_ref.write().await.push(entry);
+        Ok(())
+    }
+
+    // Returns true if `err` tells that the key looked up was deleted or is not found
+    fn is_deleted(err: &Error) -> bool {
+        matches!(
+            err,
+            KeyFoundAsTombstoneInMemtableError
+                | KeyNotFoundInAnySSTableError
+                | KeyNotFoundByAnyBloomFilterError
+                | KeyFoundAsTombstoneInSSTableError
+                | KeyFoundAsTombstoneInValueLogError
+                | KeyNotFoundInValueLogError
+                | NotFoundInDB
+        )
     }
 }
 
 impl DataStore<'static, Key> {}
 
+// Records the keys written from the start of a pass until it returns
+struct TrackedWrites<'a>(&'a WrittenKeys);
+
+impl<'a> TrackedWrites<'a> {
+    fn start(written_keys: &'a WrittenKeys) -> Self {
+        *written_keys.lock().unwrap() = Some(HashSet::new());
+        Self(written_keys)
+    }
+}
+
+impl Drop for TrackedWrites<'_> {
+    fn drop(&mut self) {
+        *self.0.lock().unwrap() = None;
+    }
+}
+
 async fn sleep_gc_task(duration: u64) {
     sleep(Duration::from_millis(duration)).await;
 }
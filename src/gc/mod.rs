@@ -1 +1,11 @@
+pub(crate) mod dead;
+pub(crate) mod freed;
 pub(crate) mod gc;
+pub(crate) mod pins;
+pub(crate) mod stats;
+
+pub use dead::DeadBytes;
+pub use freed::FreedValues;
+pub use gc::{GCContext, GCReport, GCStrategy};
+pub use pins::{ReadPin, ReadPins};
+pub use stats::{GCRun, GCStats, ValueLogStats};
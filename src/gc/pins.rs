@@ -0,0 +1,178 @@
+//! # Read pins
+//!
+//! A read looks up the offset of a value before it reads it from the value log, a garbage collection pass running
+//! in between could punch a hole over the value or drop it from the file. Reads pin the tail of the value log from
+//! their lookup until their values are read, the entries from the pinned tail on are not reclaimed while the pin
+//! is held.
+//!
+//! A pass moves the tail past the chunk it collected once the values it relocated can be looked up, then waits for
+//! the pins taken on an older tail to be released before the space of the chunk is reclaimed. Reads pinning the
+//! new tail find the relocated values.
+//!
+//! Values stored in the log of their class are reclaimed where they are rather than from a tail, see `ValueClass`.
+//! A pass starts a new epoch once it found which of them are dead and waits for the pins taken in older epochs,
+//! reads pinning the new epoch look up the values that replaced them.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// Shared by the reads and the garbage collector of a store, clones share the same pins
+#[derive(Debug, Clone, Default)]
+pub struct ReadPins {
+    state: Arc<Mutex<PinState>>,
+
+    // Wakes passes up once pins are released
+    released: Arc<Notify>,
+}
+
+#[derive(Debug, Default)]
+struct PinState {
+    // Tail pinned by reads starting now
+    tail: usize,
+
+    // Epoch of the pins taken now
+    epoch: usize,
+
+    // Number of reads holding each tail pinned, by tail and epoch
+    pinned: BTreeMap<(usize, usize), usize>,
+
+    // Set once the store is closed, passes stop waiting
+    shut_down: bool,
+}
+
+/// Keeps the entries of the value log from the tail it pinned on from being reclaimed until it is dropped
+#[derive(Debug)]
+pub struct ReadPin {
+    pins: ReadPins,
+    tail: usize,
+    epoch: usize,
+}
+
+impl ReadPins {
+    /// Pins the current tail until the pin returned is dropped
+    pub fn pin(&self) -> ReadPin {
+        let mut state = self.state.lock().unwrap();
+        let (tail, epoch) = (state.tail, state.epoch);
+        *state.pinned.entry((tail, epoch)).or_default() += 1;
+        ReadPin {
+            pins: self.clone(),
+            tail,
+            epoch,
+        }
+    }
+
+    /// Returns the number of reads holding a pin
+    pub fn pinned(&self) -> usize {
+        self.state.lock().unwrap().pinned.values().sum()
+    }
+
+    /// Moves the tail pinned by later reads to `tail`
+    pub(crate) fn advance(&self, tail: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.tail = state.tail.max(tail);
+    }
+
+    /// Starts a new epoch of pins, returns the epoch started
+    pub(crate) fn next_epoch(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.epoch += 1;
+        state.epoch
+    }
+
+    /// Waits until no read holds a tail before `tail` pinned, returns false if the store was closed meanwhile
+    pub(crate) async fn released_before(&self, tail: usize) -> bool {
+        self.released(|pinned| pinned.range(..(tail, 0)).next().is_some()).await
+    }
+
+    /// Waits until no read holds a pin taken before `epoch`, returns false if the store was closed meanwhile
+    pub(crate) async fn released_before_epoch(&self, epoch: usize) -> bool {
+        self.released(|pinned| pinned.keys().any(|(_, pinned_epoch)| *pinned_epoch < epoch))
+            .await
+    }
+
+    // Waits until `held` returns false for the pins held, returns false if the store was closed meanwhile
+    async fn released(&self, held: impl Fn(&BTreeMap<(usize, usize), usize>) -> bool) -> bool {
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+            {
+                let state = self.state.lock().unwrap();
+                if state.shut_down {
+                    return false;
+                }
+                if !held(&state.pinned) {
+                    return true;
+                }
+            }
+            released.await;
+        }
+    }
+
+    /// Stops passes waiting for pins, reads may outlive the store
+    pub(crate) fn shut_down(&self) {
+        self.state.lock().unwrap().shut_down = true;
+        self.released.notify_waiters();
+    }
+}
+
+impl Drop for ReadPin {
+    fn drop(&mut self) {
+        let mut state = self.pins.state.lock().unwrap();
+        let key = (self.tail, self.epoch);
+        if let Some(count) = state.pinned.get_mut(&key) {
+            *count -= 1;
+            if *count == 0 {
+                state.pinned.remove(&key);
+            }
+        }
+        drop(state);
+        self.pins.released.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn test_pins_older_than_tail() {
+        let pins = ReadPins::default();
+        let old = pins.pin();
+        pins.advance(100);
+        let new = pins.pin();
+        assert_eq!(pins.pinned(), 2);
+
+        // Only pins taken on an older tail are waited for
+        assert!(timeout(Duration::from_millis(50), pins.released_before(100))
+            .await
+            .is_err());
+        let waiter = tokio::spawn({
+            let pins = pins.clone();
+            async move { pins.released_before(100).await }
+        });
+        drop(old);
+        assert!(waiter.await.unwrap());
+        drop(new);
+        assert_eq!(pins.pinned(), 0);
+
+        // Only pins taken in an older epoch are waited for
+        let old = pins.pin();
+        let epoch = pins.next_epoch();
+        let new = pins.pin();
+        assert!(timeout(Duration::from_millis(50), pins.released_before_epoch(epoch))
+            .await
+            .is_err());
+        drop(old);
+        assert!(pins.released_before_epoch(epoch).await);
+        drop(new);
+
+        let _held = pins.pin();
+        pins.advance(200);
+        pins.shut_down();
+        assert!(!pins.released_before(200).await);
+    }
+}
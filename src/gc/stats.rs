@@ -0,0 +1,210 @@
+//! # GC stats
+//!
+//! `DataStore::value_log_stats` reports the size of the value log along with what garbage collection reclaimed of
+//! it, so that operators can tell whether garbage collection keeps up with the writes. `GCStats` counts the bytes
+//! holes were punched over and records when the last pass completed. Like `DeadBytes` the counters are not
+//! persisted, they start over when the store is opened.
+//!
+//! Each run, the chunks a background run collects back to back or the single pass of `DataStore::run_gc`, is kept
+//! as a `GCRun` in a ring buffer of the last `GC_RUN_HISTORY_LEN` runs returned by `DataStore::gc_history` and is
+//! passed to `EventListener::on_gc_completed`. Runs reclaiming less for the same time spent tell that garbage
+//! collection became less effective.
+
+use crate::consts::GC_RUN_HISTORY_LEN;
+use crate::gc::GCReport;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Shared by the garbage collection passes of a store, clones share the same counters
+#[derive(Debug, Clone, Default)]
+pub struct GCStats {
+    // Bytes of the value logs holes were punched over
+    punched: Arc<AtomicU64>,
+
+    // Time the last pass completed
+    last_pass: Arc<Mutex<Option<SystemTime>>>,
+
+    // Last runs, oldest first
+    runs: Arc<Mutex<VecDeque<GCRun>>>,
+}
+
+/// What a garbage collection run did, see `DataStore::gc_history`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GCRun {
+    /// Time the run started
+    pub started_at: SystemTime,
+
+    /// Time the run took, the sleeps between its chunks included
+    pub duration: Duration,
+
+    /// Chunks collected from the tail of the value log
+    pub chunks: usize,
+
+    /// Live entries appended again at the head of the value log
+    pub live_entries_rewritten: usize,
+
+    /// Bytes of the live entries appended again at the head of the value log
+    pub live_bytes_rewritten: usize,
+
+    /// Bytes of garbage reclaimed, from the logs of the other classes included
+    pub bytes_reclaimed: usize,
+}
+
+/// Size of the value log and what garbage collection reclaimed of it, returned by `DataStore::value_log_stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ValueLogStats {
+    /// Bytes of the value log files, those of the other classes included, holes punched in them count
+    pub total_bytes: u64,
+
+    /// Bytes from the tail of the value log to its head, garbage collection has yet to go through them
+    pub live_bytes: u64,
+
+    /// Estimate of the bytes no entry points to anymore, see `DeadBytes`, 0 unless `Config::gc_garbage_ratio` is set
+    pub garbage_bytes: u64,
+
+    /// Bytes holes were punched over since the store was opened, they no longer take space on the disk
+    pub punched_bytes: u64,
+
+    /// Time the last garbage collection pass completed, `None` if none did since the store was opened
+    pub last_gc: Option<SystemTime>,
+}
+
+impl GCStats {
+    /// Records a hole punched over `bytes`
+    pub(crate) fn punched(&self, bytes: usize) {
+        self.punched.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Records that a pass completed now
+    pub(crate) fn pass_completed(&self) {
+        *self.last_pass.lock().unwrap() = Some(SystemTime::now());
+    }
+
+    /// Records a completed run, the oldest run is dropped once `GC_RUN_HISTORY_LEN` are kept
+    pub(crate) fn run_completed(&self, run: GCRun) {
+        let mut runs = self.runs.lock().unwrap();
+        if runs.len() == GC_RUN_HISTORY_LEN {
+            runs.pop_front();
+        }
+        runs.push_back(run);
+    }
+
+    /// Returns the last runs, oldest first
+    pub fn runs(&self) -> Vec<GCRun> {
+        self.runs.lock().unwrap().iter().copied().collect()
+    }
+
+    /// Returns the bytes holes were punched over since the store was opened
+    pub fn punched_bytes(&self) -> u64 {
+        self.punched.load(Ordering::Relaxed)
+    }
+
+    /// Returns the time the last pass completed
+    pub fn last_pass(&self) -> Option<SystemTime> {
+        *self.last_pass.lock().unwrap()
+    }
+}
+
+impl GCRun {
+    /// Returns a run starting now, it has collected no chunk yet
+    pub(crate) fn start() -> GCRun {
+        GCRun {
+            started_at: SystemTime::now(),
+            duration: Duration::ZERO,
+            chunks: 0,
+            live_entries_rewritten: 0,
+            live_bytes_rewritten: 0,
+            bytes_reclaimed: 0,
+        }
+    }
+
+    /// Adds what the pass collecting a chunk did
+    pub(crate) fn add_pass(&mut self, report: &GCReport) {
+        self.chunks += 1;
+        self.live_entries_rewritten += report.live_entries_rewritten;
+        self.live_bytes_rewritten += report.live_bytes_rewritten;
+        self.bytes_reclaimed += report.bytes_reclaimed + report.class_bytes_reclaimed;
+    }
+
+    /// Ends the run now
+    pub(crate) fn finish(&mut self) {
+        self.duration = self.started_at.elapsed().unwrap_or_default();
+    }
+
+    /// Returns the bytes of garbage reclaimed per second the run took, 0 if it took no time
+    pub fn reclaim_rate(&self) -> f64 {
+        let secs = self.duration.as_secs_f64();
+        if secs == 0.0 {
+            return 0.0;
+        }
+        self.bytes_reclaimed as f64 / secs
+    }
+}
+
+impl ValueLogStats {
+    /// Returns the bytes the value log takes on the disk for each byte of live values, 0 if it holds none
+    pub fn space_amplification(&self) -> f64 {
+        let live = self.live_bytes.saturating_sub(self.garbage_bytes);
+        if live == 0 {
+            return 0.0;
+        }
+        self.total_bytes.saturating_sub(self.punched_bytes) as f64 / live as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_space_amplification() {
+        let stats = ValueLogStats {
+            total_bytes: 1000,
+            live_bytes: 600,
+            garbage_bytes: 100,
+            punched_bytes: 250,
+            last_gc: None,
+        };
+        assert_eq!(stats.space_amplification(), 1.5);
+        assert_eq!(ValueLogStats::default().space_amplification(), 0.0);
+
+        let gc_stats = GCStats::default();
+        let shared = gc_stats.clone();
+        shared.punched(4096);
+        shared.pass_completed();
+        assert_eq!(gc_stats.punched_bytes(), 4096);
+        assert!(gc_stats.last_pass().is_some());
+    }
+
+    #[test]
+    fn test_gc_run_history() {
+        let gc_stats = GCStats::default();
+        let mut run = GCRun::start();
+        let report = GCReport {
+            bytes_scanned: 300,
+            live_entries_rewritten: 2,
+            live_bytes_rewritten: 100,
+            bytes_reclaimed: 200,
+            class_bytes_reclaimed: 50,
+            tail_offset: 300,
+        };
+        run.add_pass(&report);
+        run.add_pass(&report);
+        run.finish();
+        assert_eq!(run.chunks, 2);
+        assert_eq!(run.live_entries_rewritten, 4);
+        assert_eq!(run.live_bytes_rewritten, 200);
+        assert_eq!(run.bytes_reclaimed, 500);
+
+        // Only the last runs are kept
+        for chunks in 0..GC_RUN_HISTORY_LEN + 2 {
+            gc_stats.run_completed(GCRun { chunks, ..run });
+        }
+        let runs = gc_stats.clone().runs();
+        assert_eq!(runs.len(), GC_RUN_HISTORY_LEN);
+        assert_eq!(runs.first().unwrap().chunks, 2);
+        assert_eq!(runs.last().unwrap().chunks, GC_RUN_HISTORY_LEN + 1);
+    }
+}
@@ -0,0 +1,2 @@
+mod tokens;
+pub use tokens::IdempotencyTokens;
@@ -0,0 +1,242 @@
+//! # Idempotency tokens
+//!
+//! A write can carry an idempotency token in its `WriteOptions`. Once the write is applied its token is recorded,
+//! a later write with the same token is skipped, so a client can safely retry a write that it does not know the
+//! outcome of.
+//!
+//! Tokens are appended to a file in the meta directory once the value log is synced, the file is synced too when
+//! the write asks for it. Tokens are forgotten once they outlive the idempotency token TTL, and the file is rewritten
+//! without them once enough have expired.
+
+use crate::consts::{IDEMPOTENCY_TOKENS_REWRITE_THRESHOLD, SIZE_OF_U32, SIZE_OF_U64};
+use crate::err::Error;
+use crate::err::Error::*;
+use crate::types::CreationTime;
+use chrono::Utc;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+/// Recently seen idempotency tokens with the time they were recorded, persisted in `path`
+#[derive(Debug, Clone)]
+pub struct IdempotencyTokens {
+    path: PathBuf,
+    tokens: HashMap<String, CreationTime>,
+
+    /// Tokens in the order they were recorded, expired ones are dropped from the front
+    recorded: VecDeque<(CreationTime, String)>,
+
+    /// Number of tokens in the file, expired ones included
+    in_file: usize,
+
+    /// How long a token is remembered (in milliseconds)
+    ttl: u64,
+}
+
+impl IdempotencyTokens {
+    /// Loads the tokens stored in `path` that are younger than `ttl`, the file is created on the first insertion
+    pub async fn open(path: PathBuf, ttl: u64) -> Result<Self, Error> {
+        Self::load(path, ttl, true).await
+    }
+
+    /// Same as `open` but the file is not rewritten without the expired tokens
+    pub async fn open_read_only(path: PathBuf, ttl: u64) -> Result<Self, Error> {
+        Self::load(path, ttl, false).await
+    }
+
+    async fn load(path: PathBuf, ttl: u64, rewrite: bool) -> Result<Self, Error> {
+        let mut store = Self {
+            path,
+            tokens: HashMap::new(),
+            recorded: VecDeque::new(),
+            in_file: 0,
+            ttl,
+        };
+        if !store.path.exists() {
+            return Ok(store);
+        }
+        let buf = fs::read(&store.path).await.map_err(|error| FileReadError {
+            path: store.path.to_owned(),
+            error,
+        })?;
+        let mut offset = 0;
+        let mut expired = 0;
+        while offset < buf.len() {
+            match Self::deserialize(&buf[offset..]) {
+                Some((token, created_at, read)) => {
+                    if store.has_expired(created_at) {
+                        expired += 1;
+                    } else {
+                        store.tokens.insert(token.to_owned(), created_at);
+                        store.recorded.push_back((created_at, token));
+                    }
+                    store.in_file += 1;
+                    offset += read;
+                }
+                None => {
+                    // A write interrupted by a crash, the token was never acknowledged
+                    log::warn!("Ignoring incomplete idempotency token at the end of {:?}", store.path);
+                    break;
+                }
+            }
+        }
+        if rewrite && expired > 0 {
+            store.rewrite().await?;
+        }
+        Ok(store)
+    }
+
+    /// Returns true if a write with `token` was already applied
+    pub fn contains(&self, token: &str) -> bool {
+        self.tokens
+            .get(token)
+            .is_some_and(|created_at| !self.has_expired(*created_at))
+    }
+
+    /// Persists `token` so that later writes carrying it are skipped, the file is synced if `sync` is set
+    ///
+    /// Expired tokens are forgotten along the way.
+    pub async fn insert(&mut self, token: &str, sync: bool) -> Result<(), Error> {
+        let created_at = Utc::now().timestamp_millis() as u64;
+        Self::append(&self.path, &Self::serialize(token, created_at), sync).await?;
+        self.tokens.insert(token.to_owned(), created_at);
+        self.recorded.push_back((created_at, token.to_owned()));
+        self.in_file += 1;
+        self.remove_expired().await
+    }
+
+    // Forgets the expired tokens, the file is rewritten once its expired tokens reach the threshold and outnumber
+    // the live ones
+    async fn remove_expired(&mut self) -> Result<(), Error> {
+        while let Some((created_at, _)) = self.recorded.front() {
+            if !self.has_expired(*created_at) {
+                break;
+            }
+            let (created_at, token) = self.recorded.pop_front().unwrap();
+            // The token was recorded again since
+            if self.tokens.get(&token) == Some(&created_at) {
+                self.tokens.remove(&token);
+            }
+        }
+        let expired = self.in_file - self.tokens.len();
+        if expired >= IDEMPOTENCY_TOKENS_REWRITE_THRESHOLD && expired > self.tokens.len() {
+            self.rewrite().await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of tokens remembered
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Returns true if no token is remembered
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    fn has_expired(&self, created_at: CreationTime) -> bool {
+        let current_timestamp = Utc::now().timestamp_millis() as u64;
+        current_timestamp > (created_at + self.ttl)
+    }
+
+    fn serialize(token: &str, created_at: CreationTime) -> Vec<u8> {
+        let mut entry = Vec::with_capacity(SIZE_OF_U32 + token.len() + SIZE_OF_U64);
+        entry.extend_from_slice(&(token.len() as u32).to_le_bytes());
+        entry.extend_from_slice(token.as_bytes());
+        entry.extend_from_slice(&created_at.to_le_bytes());
+        entry
+    }
+
+    // Parses the token at the start of `buf` and returns it with its creation time and the number of bytes read,
+    // `None` if `buf` ends before the token does
+    fn deserialize(buf: &[u8]) -> Option<(String, CreationTime, usize)> {
+        let token_len = u32::from_le_bytes(buf.get(..SIZE_OF_U32)?.try_into().ok()?) as usize;
+        let token_end = SIZE_OF_U32 + token_len;
+        let token = String::from_utf8(buf.get(SIZE_OF_U32..token_end)?.to_vec()).ok()?;
+        let created_at = u64::from_le_bytes(buf.get(token_end..token_end + SIZE_OF_U64)?.try_into().ok()?);
+        Some((token, created_at, token_end + SIZE_OF_U64))
+    }
+
+    // Replaces the file with the tokens currently held in memory, in the order they were recorded
+    async fn rewrite(&mut self) -> Result<(), Error> {
+        self.recorded
+            .retain(|(created_at, token)| self.tokens.get(token) == Some(created_at));
+        let buf: Vec<u8> = self
+            .recorded
+            .iter()
+            .flat_map(|(created_at, token)| Self::serialize(token, *created_at))
+            .collect();
+        // Written to a temporary file first so that a crash never leaves a partially written file
+        let tmp_path = self.path.with_extension("tmp");
+        if tmp_path.exists() {
+            fs::remove_file(&tmp_path).await.map_err(FileDeleteError)?;
+        }
+        Self::append(&tmp_path, &buf, true).await?;
+        fs::rename(&tmp_path, &self.path)
+            .await
+            .map_err(|error| FileWriteError {
+                path: self.path.to_owned(),
+                error,
+            })?;
+        self.in_file = self.recorded.len();
+        Ok(())
+    }
+
+    async fn append(path: &Path, buf: &[u8], sync: bool) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await.map_err(|error| DirCreationError {
+                path: dir.to_path_buf(),
+                error,
+            })?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|error| FileOpenError {
+                path: path.to_path_buf(),
+                error,
+            })?;
+        file.write_all(buf).await.map_err(|error| FileWriteError {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        if sync {
+            file.sync_all().await.map_err(|error| FileSyncError { error })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_expired_tokens_removed_on_insert() {
+        let root = tempdir().unwrap();
+        let path = root.path().join("idempotency_tokens.bin");
+        let mut tokens = IdempotencyTokens::open(path.to_owned(), 1000).await.unwrap();
+        for i in 0..IDEMPOTENCY_TOKENS_REWRITE_THRESHOLD {
+            tokens.insert(&format!("token_{}", i), false).await.unwrap();
+        }
+        assert!(tokens.contains("token_0"));
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+
+        // The expired tokens are forgotten and the file holding them is rewritten with the live token only
+        tokens.insert("token_live", true).await.unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert!(!tokens.contains("token_0"));
+        assert!(tokens.contains("token_live"));
+        let file_len = fs::metadata(&path).await.unwrap().len() as usize;
+        assert_eq!(file_len, IdempotencyTokens::serialize("token_live", 0).len());
+
+        let tokens = IdempotencyTokens::open(path, 1000).await.unwrap();
+        assert!(tokens.contains("token_live"));
+    }
+}
@@ -1,7 +1,10 @@
+use crate::checksum::ChecksumType;
 use crate::consts::SIZE_OF_U32;
 use crate::err::Error;
 use crate::fs::{FileAsync, IndexFileNode, IndexFs};
 use crate::types::Key;
+use std::cmp;
+use std::ops::{Bound, RangeBounds};
 use std::path::PathBuf;
 
 use Error::*;
@@ -35,20 +38,6 @@ pub struct Index {
     entries: Vec<IndexEntry>,
     file: IndexFile<IndexFileNode>,
 }
-#[derive(Debug, Clone)]
-pub struct RangeOffset {
-    pub start_offset: Offset,
-    pub end_offset: Offset,
-}
-
-impl RangeOffset {
-    pub fn new(start: Offset, end: Offset) -> Self {
-        Self {
-            start_offset: start,
-            end_offset: end,
-        }
-    }
-}
 
 impl Index {
     pub fn new(path: PathBuf, file: IndexFileNode) -> Self {
@@ -65,12 +54,15 @@ impl Index {
         })
     }
 
-    pub async fn write_to_file(&self) -> Result<(), Error> {
+    /// Writes a header recording `checksum_type` and the entries, followed by a footer holding their checksum
+    pub async fn write_to_file(&self, checksum_type: ChecksumType) -> Result<(), Error> {
+        let mut serialized_entries = vec![checksum_type.as_byte()];
         for e in &self.entries {
-            let serialized_input = self.serialize_entry(e)?;
-            self.file.file.node.write_all(&serialized_input).await?;
+            serialized_entries.extend_from_slice(&self.serialize_entry(e)?);
         }
-        Ok(())
+        let checksum = checksum_type.checksum(&serialized_entries);
+        serialized_entries.extend_from_slice(&checksum);
+        self.file.file.node.write_all(&serialized_entries).await
     }
 
     fn serialize_entry(&self, e: &IndexEntry) -> Result<Vec<u8>, Error> {
@@ -96,7 +88,33 @@ impl Index {
         self.file.file.get_from_index(searched_key).await
     }
 
-    // pub(crate) async fn get_block_offset_range(&self, start_key: &[u8], end_key: &[u8]) -> Result<RangeOffset, Error> {
-    //     self.file.file.get_block_range(start_key, end_key).await
-    // }
+    // Returns the last key of every block, in key order
+    pub(crate) async fn keys(&self) -> Result<Vec<Key>, Error> {
+        self.file.file.load_keys().await
+    }
+
+    /// Returns the offsets of the blocks that can hold keys within `range`, in key order
+    ///
+    /// A block holds the keys following the last key of the previous block up to its own last key.
+    pub(crate) async fn block_offsets_within<R: RangeBounds<Key>>(&self, range: &R) -> Result<Vec<Offset>, Error> {
+        let blocks = self.file.file.load_entries().await?;
+        let first = blocks.partition_point(|(last_key, _)| match range.start_bound() {
+            Bound::Included(start) => last_key < start,
+            Bound::Excluded(start) => last_key <= start,
+            Bound::Unbounded => false,
+        });
+        // The first block whose last key is not smaller than the end is the last one that can hold keys before it
+        let end = match range.end_bound() {
+            Bound::Included(end) | Bound::Excluded(end) => {
+                cmp::min(blocks.partition_point(|(last_key, _)| last_key < end) + 1, blocks.len())
+            }
+            Bound::Unbounded => blocks.len(),
+        };
+        Ok(blocks
+            .get(first..end)
+            .unwrap_or_default()
+            .iter()
+            .map(|(_, offset)| *offset)
+            .collect())
+    }
 }
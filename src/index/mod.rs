@@ -1,4 +1,3 @@
 mod index;
 pub use index::Index;
 pub use index::IndexFile;
-pub use index::RangeOffset;
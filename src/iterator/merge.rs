@@ -0,0 +1,161 @@
+//! # Merge iterator
+//!
+//! K-way merge of sorted entry sources (memtables and SSTables) into a single sorted stream holding one
+//! version per key. Sources are passed from the most recent to the oldest: the version with the highest
+//! creation time wins and on equal creation time the version from the most recent source wins.
+
+use crate::memtable::Entry;
+use crate::types::{Key, SkipMapEntries, ValOffset};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Source of entries sorted by key with at most one entry per key
+pub type EntryIterator<'a> = Box<dyn Iterator<Item = Entry<Key, ValOffset>> + Send + 'a>;
+
+pub struct MergeIterator<'a> {
+    sources: Vec<EntryIterator<'a>>,
+
+    /// Next entry of every source, `None` once the source is exhausted
+    heads: Vec<Option<Entry<Key, ValOffset>>>,
+
+    /// Min-heap of (key, source index) for every source that is not exhausted
+    heap: BinaryHeap<Reverse<(Key, usize)>>,
+
+    skip_tombstones: bool,
+}
+
+impl<'a> MergeIterator<'a> {
+    /// Creates an iterator merging `sources`, ordered from the most recent to the oldest
+    pub fn new(sources: Vec<EntryIterator<'a>>) -> Self {
+        let mut iterator = Self {
+            heads: Vec::with_capacity(sources.len()),
+            heap: BinaryHeap::with_capacity(sources.len()),
+            sources,
+            skip_tombstones: false,
+        };
+        for idx in 0..iterator.sources.len() {
+            iterator.heads.push(None);
+            iterator.advance(idx);
+        }
+        iterator
+    }
+
+    /// Drops keys whose most recent version is a tombstone or has expired instead of returning it
+    pub fn skip_tombstones(mut self) -> Self {
+        self.skip_tombstones = true;
+        self
+    }
+
+    /// Returns a source over the entries of a memtable or an SSTable
+    pub fn source_from_entries(entries: &'a SkipMapEntries<Key>) -> EntryIterator<'a> {
+        Box::new(entries.iter().map(|e| {
+            Entry::new(
+                e.key().to_vec(),
+                e.value().val_offset,
+                e.value().created_at,
+                e.value().is_tombstone,
+            )
+            .with_expiry(e.value().expires_at)
+            .with_written_at(e.value().written_at)
+            .with_inline(e.value().inline.to_owned())
+        }))
+    }
+
+    // Moves source `idx` to its next entry
+    fn advance(&mut self, idx: usize) -> Option<Entry<Key, ValOffset>> {
+        let next = self.sources[idx].next();
+        if let Some(entry) = &next {
+            self.heap.push(Reverse((entry.key.to_owned(), idx)));
+        }
+        std::mem::replace(&mut self.heads[idx], next)
+    }
+}
+
+impl Iterator for MergeIterator<'_> {
+    type Item = Entry<Key, ValOffset>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Reverse((key, idx)) = self.heap.pop()?;
+            let mut most_recent = self.advance(idx)?;
+            // Sources holding the same key are popped in source order
+            while self.heap.peek().is_some_and(|Reverse((k, _))| *k == key) {
+                let Reverse((_, idx)) = self.heap.pop()?;
+                if let Some(entry) = self.advance(idx) {
+                    if entry.created_at > most_recent.created_at {
+                        most_recent = entry;
+                    }
+                }
+            }
+            if self.skip_tombstones && most_recent.is_deleted() {
+                continue;
+            }
+            return Some(most_recent);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(entries: Vec<(&str, ValOffset, u64, bool)>) -> EntryIterator<'static> {
+        Box::new(
+            entries
+                .into_iter()
+                .map(|(key, offset, created_at, is_tombstone)| {
+                    Entry::new(key.as_bytes().to_vec(), offset, created_at, is_tombstone)
+                })
+                .collect::<Vec<_>>()
+                .into_iter(),
+        )
+    }
+
+    fn keys_and_offsets(iterator: MergeIterator) -> Vec<(String, ValOffset)> {
+        iterator
+            .map(|e| (String::from_utf8(e.key).unwrap(), e.val_offset))
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_sorted_sources() {
+        let iterator = MergeIterator::new(vec![
+            source(vec![("b", 1, 1, false), ("e", 2, 1, false)]),
+            source(vec![("a", 3, 1, false), ("d", 4, 1, false)]),
+            source(vec![]),
+            source(vec![("c", 5, 1, false), ("f", 6, 1, false)]),
+        ]);
+        let merged = keys_and_offsets(iterator);
+        let keys: Vec<&str> = merged.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, vec!["a", "b", "c", "d", "e", "f"]);
+    }
+
+    #[test]
+    fn test_newest_version_wins() {
+        let iterator = MergeIterator::new(vec![
+            source(vec![("a", 1, 5, false), ("b", 2, 1, false)]),
+            source(vec![("a", 3, 2, false), ("b", 4, 9, false)]),
+            // Same creation time as the most recent source, the most recent source wins
+            source(vec![("a", 5, 5, false)]),
+        ]);
+        assert_eq!(
+            keys_and_offsets(iterator),
+            vec![("a".to_string(), 1), ("b".to_string(), 4)]
+        );
+    }
+
+    #[test]
+    fn test_tombstones() {
+        let sources = || {
+            vec![
+                source(vec![("a", 1, 5, true), ("c", 2, 5, false)]),
+                source(vec![("a", 3, 1, false), ("b", 4, 1, true), ("c", 5, 9, true)]),
+            ]
+        };
+        let tombstones: Vec<bool> = MergeIterator::new(sources()).map(|e| e.is_tombstone).collect();
+        assert_eq!(tombstones, vec![true, true, true]);
+
+        let iterator = MergeIterator::new(sources()).skip_tombstones();
+        assert!(keys_and_offsets(iterator).is_empty());
+    }
+}
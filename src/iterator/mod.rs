@@ -0,0 +1,3 @@
+mod merge;
+pub use merge::EntryIterator;
+pub use merge::MergeIterator;
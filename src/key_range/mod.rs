@@ -1,2 +1,3 @@
 mod range;
 pub use range::KeyRange;
+pub(crate) use range::Range;
@@ -2,7 +2,12 @@ use crate::{
     sst::Table,
     types::{self, Key},
 };
-use std::{cmp::Ordering, collections::HashMap, path::PathBuf};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    ops::{Bound, RangeBounds},
+    path::PathBuf,
+};
 
 type LargestKey = types::Key;
 type SmallestKey = types::Key;
@@ -25,6 +30,21 @@ impl Range {
             sst,
         }
     }
+
+    // Returns true if at least one key between the smallest and biggest key is within `bounds`
+    pub fn overlaps<R: RangeBounds<Key>>(&self, bounds: &R) -> bool {
+        let starts_before_end = match bounds.end_bound() {
+            Bound::Included(end) => self.smallest_key <= *end,
+            Bound::Excluded(end) => self.smallest_key < *end,
+            Bound::Unbounded => true,
+        };
+        let ends_after_start = match bounds.start_bound() {
+            Bound::Included(start) => self.biggest_key >= *start,
+            Bound::Excluded(start) => self.biggest_key > *start,
+            Bound::Unbounded => true,
+        };
+        starts_before_end && ends_after_start
+    }
 }
 impl KeyRange {
     pub fn new() -> Self {
@@ -55,19 +75,16 @@ impl KeyRange {
             .collect()
     }
 
-    // Returns SSTables whose keys overlap with the key range supplied
+    // Returns SSTables whose keys overlap with the key range supplied, `end_key` is exclusive
     pub fn range_scan(&self, start_key: &SmallestKey, end_key: &LargestKey) -> Vec<&Range> {
+        self.overlapping(&(start_key.to_owned()..end_key.to_owned()))
+    }
+
+    // Returns SSTables with at least one key within `bounds`
+    pub fn overlapping<R: RangeBounds<Key>>(&self, bounds: &R) -> Vec<&Range> {
         self.key_ranges
             .iter()
-            .filter(|(_, range)| {
-                // Check minimum range
-                (range.smallest_key.as_slice().cmp(start_key) == Ordering::Less
-                    || range.smallest_key.as_slice().cmp(start_key) == Ordering::Equal)
-
-                    // Check maximum range
-                    || (range.biggest_key.as_slice().cmp(end_key) == Ordering::Greater
-                        || range.biggest_key.as_slice().cmp(end_key) == Ordering::Equal)
-            })
+            .filter(|(_, range)| range.overlaps(bounds))
             .map(|(_, path)| path)
             .collect()
     }
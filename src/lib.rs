@@ -1,23 +1,34 @@
+mod batch;
 mod block;
 mod bucket;
 mod cfg;
+mod changes;
+mod checksum;
 mod compactors;
+mod compression;
 mod consts;
 mod db;
 mod err;
+mod events;
 mod filter;
 mod flusher;
 mod fs;
 mod gc;
 mod helpers;
+mod idempotency;
 mod index;
+mod iterator;
 mod key_range;
+mod lock;
 mod mac;
 mod memtable;
 mod meta;
 mod range;
+mod range_tombstone;
+mod snapshot;
 mod sst;
 pub mod storage;
 mod tests;
+mod transaction;
 mod types;
 mod value_log;
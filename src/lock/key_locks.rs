@@ -0,0 +1,136 @@
+//! # Key locks
+//!
+//! Advisory locks that application tasks sharing a store take on keys to coordinate with each other. The store
+//! never takes them itself, writes to a locked key made without the lock go through.
+//!
+//! Tasks waiting on the same key get the lock in the order they asked for it. Locks are held in memory only and
+//! a key's entry is removed once nobody holds or waits for its lock. The entry of a key whose last waiter was
+//! cancelled is removed by the next `lock` or `len` call.
+
+use crate::types::Key;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+type LockTable = Arc<Mutex<HashMap<Key, Arc<AsyncMutex<()>>>>>;
+
+/// Lock table of the store, shared by every clone
+#[derive(Debug, Clone, Default)]
+pub struct KeyLocks {
+    locks: LockTable,
+}
+
+impl KeyLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Waits until the lock of `key` is free and takes it
+    pub async fn lock(&self, key: &[u8]) -> KeyLockGuard {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            Self::remove_unused(&mut locks);
+            Arc::clone(locks.entry(key.to_vec()).or_default())
+        };
+        let guard = lock.lock_owned().await;
+        KeyLockGuard {
+            key: key.to_vec(),
+            locks: Arc::clone(&self.locks),
+            guard: Some(guard),
+        }
+    }
+
+    /// Number of keys whose lock is held or waited for
+    pub fn len(&self) -> usize {
+        let mut locks = self.locks.lock().unwrap();
+        Self::remove_unused(&mut locks);
+        locks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Removes the entries only the table holds, left behind by `lock` calls cancelled while waiting
+    fn remove_unused(locks: &mut HashMap<Key, Arc<AsyncMutex<()>>>) {
+        locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+    }
+}
+
+/// Lock on a key returned by `DataStore::lock`, released when dropped
+#[derive(Debug)]
+pub struct KeyLockGuard {
+    key: Key,
+    locks: LockTable,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl KeyLockGuard {
+    pub fn key(&self) -> &Key {
+        &self.key
+    }
+}
+
+impl Drop for KeyLockGuard {
+    fn drop(&mut self) {
+        let mut locks = self.locks.lock().unwrap();
+        // The table and this guard are the only owners when nobody else waits for the lock
+        if locks.get(&self.key).is_some_and(|lock| Arc::strong_count(lock) == 2) {
+            locks.remove(&self.key);
+        }
+        self.guard.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_lock_is_exclusive_and_fair() {
+        let locks = KeyLocks::new();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let guard = locks.lock(b"key").await;
+        let mut waiters = Vec::new();
+        for i in 0..3 {
+            let locks = locks.clone();
+            let order = Arc::clone(&order);
+            waiters.push(tokio::spawn(async move {
+                let _guard = locks.lock(b"key").await;
+                order.lock().unwrap().push(i);
+            }));
+            // Lets the waiter queue up before the next one
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // Other keys are not affected
+        drop(locks.lock(b"other").await);
+        assert!(order.lock().unwrap().is_empty());
+
+        drop(guard);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec![0, 1, 2]);
+        assert!(locks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_lock_leaves_no_entry() {
+        let locks = KeyLocks::new();
+        let guard = locks.lock(b"key").await;
+        let waiter = tokio::time::timeout(Duration::from_millis(10), locks.lock(b"key"));
+        assert!(waiter.await.is_err());
+        assert_eq!(locks.len(), 1);
+        drop(guard);
+        assert!(locks.is_empty());
+
+        // A waiter cancelled while the lock was free to take
+        let guard = locks.lock(b"key").await;
+        let mut waiter = Box::pin(locks.lock(b"key"));
+        assert!(futures::poll!(waiter.as_mut()).is_pending());
+        drop(guard);
+        drop(waiter);
+        assert!(locks.is_empty());
+    }
+}
@@ -0,0 +1,3 @@
+mod key_locks;
+pub use key_locks::KeyLockGuard;
+pub use key_locks::KeyLocks;
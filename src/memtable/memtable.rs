@@ -6,32 +6,51 @@
 //! Once the read-only memtable vector exceeds the `max_buffer_write_number` all memtable in the vector is flushed to to the disk concurrently
 
 use crate::bucket::InsertableToBucket;
-use crate::consts::{DEFAULT_FALSE_POSITIVE_RATE, SIZE_OF_U32, SIZE_OF_U64, SIZE_OF_U8, WRITE_BUFFER_SIZE};
+use crate::consts::{
+    DEFAULT_AVG_ENTRY_SIZE, DEFAULT_FALSE_POSITIVE_RATE, SIZE_OF_U32, SIZE_OF_U64, SIZE_OF_U8, WRITE_BUFFER_SIZE,
+};
 use crate::err::Error;
 use crate::filter::BloomFilter;
+use crate::range::entries_within;
 use crate::storage::SizeUnit;
-use crate::types::{CreationTime, IsTombStone, Key, SkipMapEntries, ValOffset, Value};
+use crate::types::{CreationTime, ExpiresAt, IsTombStone, Key, SkipMapEntries, ValOffset, Value, WriteTime};
+use crate::value_log::ValueClass;
 use chrono::{DateTime, Utc};
 use crossbeam_skiplist::SkipMap;
 use rand::distributions::Alphanumeric;
 use rand::Rng;
 use std::cmp::{self, Ordering};
+use std::ops::RangeBounds;
 use Error::*;
 
 use std::{hash::Hash, sync::Arc};
 
-#[derive(PartialOrd, PartialEq, Copy, Clone, Debug)]
+#[derive(PartialOrd, PartialEq, Clone, Debug)]
 pub struct Entry<K: Hash, V> {
     pub key: K,
     pub val_offset: V,
     pub created_at: u64,
+
+    /// Wall-clock time the version was written at, `created_at` is its sequence number
+    pub written_at: WriteTime,
     pub is_tombstone: bool,
+    pub expires_at: ExpiresAt,
+
+    /// Value shorter than `Config::inline_value_threshold`, SSTables store it along with its offset
+    pub inline: Option<Value>,
 }
 #[derive(Clone, Debug, PartialEq)]
 pub struct SkipMapValue<V: Ord> {
     pub val_offset: V,
     pub created_at: CreationTime,
+
+    /// Wall-clock time the version was written at, see `Entry::written_at`
+    pub written_at: WriteTime,
     pub is_tombstone: IsTombStone,
+    pub expires_at: ExpiresAt,
+
+    /// Value stored along with its offset, see `Entry::inline`
+    pub inline: Option<Value>,
 }
 
 impl<V: Ord> SkipMapValue<V> {
@@ -39,9 +58,40 @@ impl<V: Ord> SkipMapValue<V> {
         SkipMapValue {
             val_offset,
             created_at,
+            written_at: Utc::now().timestamp_millis() as u64,
             is_tombstone,
+            expires_at: None,
+            inline: None,
         }
     }
+
+    /// Sets the wall-clock time the version was written at, it defaults to now
+    pub(crate) fn with_written_at(mut self, written_at: WriteTime) -> Self {
+        self.written_at = written_at;
+        self
+    }
+
+    /// Sets the time after which the entry is treated as deleted
+    pub(crate) fn with_expiry(mut self, expires_at: ExpiresAt) -> Self {
+        self.expires_at = expires_at;
+        self
+    }
+
+    /// Stores the value along with its offset
+    pub(crate) fn with_inline(mut self, inline: Option<Value>) -> Self {
+        self.inline = inline;
+        self
+    }
+
+    /// Returns true if the entry is a tombstone or its expiry time has passed
+    pub(crate) fn is_deleted(&self) -> bool {
+        self.is_tombstone || is_expired(self.expires_at)
+    }
+}
+
+/// Returns true if `expires_at` is set and lies in the past
+pub(crate) fn is_expired(expires_at: ExpiresAt) -> bool {
+    expires_at.is_some_and(|expires_at| Utc::now().timestamp_millis() as u64 >= expires_at)
 }
 
 #[derive(Clone, Debug)]
@@ -55,6 +105,9 @@ pub struct MemTable<K: Hash + cmp::Ord> {
     pub created_at: DateTime<Utc>,
     pub read_only: bool,
     pub most_recent_entry: Entry<K, ValOffset>,
+
+    /// Number of entries inserted so far, used to derive the observed average entry size
+    pub inserted_entries: usize,
 }
 
 #[allow(dead_code)]
@@ -101,14 +154,40 @@ impl Entry<Key, ValOffset> {
             key,
             val_offset,
             created_at,
+            written_at: Utc::now().timestamp_millis() as u64,
             is_tombstone,
+            expires_at: None,
+            inline: None,
         }
     }
 
+    /// Sets the wall-clock time the version was written at, it defaults to now
+    pub(crate) fn with_written_at(mut self, written_at: WriteTime) -> Self {
+        self.written_at = written_at;
+        self
+    }
+
+    /// Sets the time after which the entry is treated as deleted
+    pub(crate) fn with_expiry(mut self, expires_at: ExpiresAt) -> Self {
+        self.expires_at = expires_at;
+        self
+    }
+
+    /// Stores the value along with its offset, see `Entry::inline`
+    pub(crate) fn with_inline(mut self, inline: Option<Value>) -> Self {
+        self.inline = inline;
+        self
+    }
+
+    /// Returns true if the entry is a tombstone or its expiry time has passed
+    pub(crate) fn is_deleted(&self) -> bool {
+        self.is_tombstone || is_expired(self.expires_at)
+    }
+
     pub(crate) fn has_expired(&self, ttl: u64) -> bool {
         let current_time = Utc::now();
         let current_timestamp = current_time.timestamp_millis() as u64;
-        current_timestamp > (self.created_at + ttl)
+        current_timestamp > (self.written_at + ttl)
     }
 }
 
@@ -118,6 +197,24 @@ impl MemTable<Key> {
     }
 
     pub fn with_specified_capacity_and_rate(size_unit: SizeUnit, capacity: usize, false_positive_rate: f64) -> Self {
+        Self::with_specified_capacity_rate_and_entry_size(
+            size_unit,
+            capacity,
+            false_positive_rate,
+            DEFAULT_AVG_ENTRY_SIZE,
+        )
+    }
+
+    /// Creates a memtable whose bloom filter is pre-sized for entries of `avg_entry_size` bytes
+    ///
+    /// This is used to carry the average entry size observed by a full memtable over to its successor
+    /// so the bloom filter is neither oversized for large keys nor undersized for small ones
+    pub fn with_specified_capacity_rate_and_entry_size(
+        size_unit: SizeUnit,
+        capacity: usize,
+        false_positive_rate: f64,
+        avg_entry_size: usize,
+    ) -> Self {
         assert!(
             false_positive_rate >= 0.0,
             "False positive rate can not be les than or equal to zero"
@@ -125,8 +222,7 @@ impl MemTable<Key> {
         assert!(capacity > 0, "Capacity should be greater than 0");
 
         let capacity_to_bytes = size_unit.to_bytes(capacity);
-        let avg_entry_size = 100;
-        let max_no_of_entries = capacity_to_bytes / avg_entry_size as usize;
+        let max_no_of_entries = Self::expected_no_of_entries(capacity_to_bytes, avg_entry_size);
         let bf = BloomFilter::new(false_positive_rate, max_no_of_entries);
         let entries = SkipMap::new();
         let now: DateTime<Utc> = Utc::now();
@@ -140,18 +236,26 @@ impl MemTable<Key> {
             false_positive_rate,
             read_only: false,
             most_recent_entry: Entry::new(vec![], 0, Utc::now().timestamp_millis() as u64, false),
+            inserted_entries: 0,
         }
     }
 
     pub fn insert(&mut self, entry: &Entry<Key, ValOffset>) -> Result<(), Error> {
-        let entry_length_byte = entry.key.len() + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8;
+        let entry_length_byte = Self::inserted_size(entry);
+        self.inserted_entries += 1;
         if !self.bloom_filter.contains(&entry.key) {
             self.bloom_filter.set(&entry.key.clone());
             self.entries.insert(
                 entry.key.to_owned(),
-                SkipMapValue::new(entry.val_offset, entry.created_at, entry.is_tombstone),
+                SkipMapValue::new(entry.val_offset, entry.created_at, entry.is_tombstone)
+                    .with_written_at(entry.written_at)
+                    .with_expiry(entry.expires_at)
+                    .with_inline(entry.inline.to_owned()),
             );
-            if entry.val_offset > self.most_recent_entry.val_offset {
+            // The location of a value stored in the log of its class is not an offset of the main log
+            if ValueClass::of(entry.val_offset) == ValueClass::Small
+                && entry.val_offset > self.most_recent_entry.val_offset
+            {
                 self.most_recent_entry = entry.to_owned();
             }
             self.size += entry_length_byte;
@@ -160,12 +264,25 @@ impl MemTable<Key> {
 
         self.entries.insert(
             entry.key.to_owned(),
-            SkipMapValue::new(entry.val_offset, entry.created_at, entry.is_tombstone),
+            SkipMapValue::new(entry.val_offset, entry.created_at, entry.is_tombstone)
+                .with_written_at(entry.written_at)
+                .with_expiry(entry.expires_at)
+                .with_inline(entry.inline.to_owned()),
         );
         self.size += entry_length_byte;
         Ok(())
     }
 
+    /// Records that the pointer to the value of `entry`, stored in the log of its class, was appended at `offset` of
+    /// the main value log, so that the head set from this memtable covers the pointer
+    pub fn track_offset(&mut self, entry: &Entry<Key, ValOffset>, offset: ValOffset) {
+        if offset > self.most_recent_entry.val_offset {
+            let mut entry = entry.to_owned();
+            entry.val_offset = offset;
+            self.most_recent_entry = entry;
+        }
+    }
+
     pub fn get(&self, key: &Vec<u8>) -> Option<SkipMapValue<ValOffset>> {
         if self.bloom_filter.contains(key) {
             if let Some(entry) = self.entries.get(key) {
@@ -181,7 +298,10 @@ impl MemTable<Key> {
         }
         self.entries.insert(
             entry.key.to_vec(),
-            SkipMapValue::new(entry.val_offset, entry.created_at, entry.is_tombstone),
+            SkipMapValue::new(entry.val_offset, entry.created_at, entry.is_tombstone)
+                .with_written_at(entry.written_at)
+                .with_expiry(entry.expires_at)
+                .with_inline(entry.inline.to_owned()),
         );
         Ok(())
     }
@@ -204,13 +324,36 @@ impl MemTable<Key> {
         // Insert thumb stone to indicate deletion
         self.entries.insert(
             entry.key.to_vec(),
-            SkipMapValue::new(entry.val_offset, created_at, entry.is_tombstone),
+            SkipMapValue::new(entry.val_offset, created_at, entry.is_tombstone).with_expiry(entry.expires_at),
         );
         Ok(())
     }
 
-    pub fn is_full(&mut self, key_len: usize) -> bool {
-        self.size + key_len + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8 >= self.capacity()
+    /// Returns true if inserting `entry` would exceed the memtable capacity, its inline value counts towards it
+    pub fn is_full(&mut self, entry: &Entry<Key, ValOffset>) -> bool {
+        self.size + Self::inserted_size(entry) > self.capacity()
+    }
+
+    /// Returns the number of bytes an entry with a key of `key_len` bytes occupies in the memtable
+    pub fn entry_size(key_len: usize) -> usize {
+        key_len + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8
+    }
+
+    // Returns the number of bytes `entry` occupies in the memtable along with its inline value
+    fn inserted_size(entry: &Entry<Key, ValOffset>) -> usize {
+        Self::entry_size(entry.key.len()) + entry.inline.as_ref().map_or(0, Vec::len)
+    }
+
+    /// Returns the average size of the entries inserted so far, or `DEFAULT_AVG_ENTRY_SIZE` if none was inserted
+    pub fn avg_entry_size(&self) -> usize {
+        if self.inserted_entries == 0 {
+            return DEFAULT_AVG_ENTRY_SIZE;
+        }
+        cmp::max(self.size / self.inserted_entries, 1)
+    }
+
+    fn expected_no_of_entries(capacity: usize, avg_entry_size: usize) -> usize {
+        cmp::max(capacity / cmp::max(avg_entry_size, 1), 1)
     }
 
     pub fn is_entry_within_range<'a>(
@@ -243,16 +386,19 @@ impl MemTable<Key> {
         self.size_unit
     }
 
-    pub fn range() {}
+    /// Returns the entries whose key falls within `range` in sorted order
+    pub fn range<R: RangeBounds<Key>>(&self, range: &R) -> Vec<Entry<Key, ValOffset>> {
+        entries_within(&self.entries, range)
+    }
 
     /// Clears all key-value entries in the MemTable.
     pub fn clear(&mut self) {
         let capacity_to_bytes = self.size_unit.to_bytes(self.capacity);
-        let avg_entry_size = 100;
-        let max_no_of_entries = capacity_to_bytes / avg_entry_size as usize;
+        let max_no_of_entries = Self::expected_no_of_entries(capacity_to_bytes, self.avg_entry_size());
 
         self.entries.clear();
         self.size = 0;
+        self.inserted_entries = 0;
         self.bloom_filter = BloomFilter::new(self.false_positive_rate, max_no_of_entries);
     }
 }
@@ -363,7 +509,8 @@ mod tests {
             let keys_clone = keys.clone();
             let m = mem_table.clone();
             let handler = thread::spawn(move || {
-                let entry = Entry::new(keys_clone[i].to_owned(), i, created_at, is_tombstone);
+                let entry =
+                    Entry::new(keys_clone[i].to_owned(), i, created_at, is_tombstone).with_written_at(created_at);
                 m.lock().unwrap().insert(&entry).unwrap();
             });
             handlers.push(handler)
@@ -377,7 +524,10 @@ mod tests {
             SkipMapValue {
                 val_offset: 0,
                 created_at,
-                is_tombstone
+                written_at: created_at,
+                is_tombstone,
+                expires_at: None,
+                inline: None
             }
         );
         assert_eq!(
@@ -385,7 +535,10 @@ mod tests {
             SkipMapValue {
                 val_offset: 1,
                 created_at,
-                is_tombstone
+                written_at: created_at,
+                is_tombstone,
+                expires_at: None,
+                inline: None
             }
         );
         assert_eq!(
@@ -393,7 +546,10 @@ mod tests {
             SkipMapValue {
                 val_offset: 2,
                 created_at,
-                is_tombstone
+                written_at: created_at,
+                is_tombstone,
+                expires_at: None,
+                inline: None
             }
         );
         assert_eq!(
@@ -401,7 +557,10 @@ mod tests {
             SkipMapValue {
                 val_offset: 3,
                 created_at,
-                is_tombstone
+                written_at: created_at,
+                is_tombstone,
+                expires_at: None,
+                inline: None
             }
         );
         assert_eq!(
@@ -409,7 +568,10 @@ mod tests {
             SkipMapValue {
                 val_offset: 4,
                 created_at,
-                is_tombstone
+                written_at: created_at,
+                is_tombstone,
+                expires_at: None,
+                inline: None
             }
         );
     }
@@ -581,10 +743,57 @@ mod tests {
         let buffer_size = 51200;
         let false_pos_rate = 1e-300;
         let mut mem_table = MemTable::with_specified_capacity_and_rate(SizeUnit::Bytes, buffer_size, false_pos_rate);
-        let key = vec![1, 2, 3, 4];
-        let is_full = mem_table
-            .to_owned()
-            .is_full(key.len() + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8 + mem_table.capacity());
+        let key = vec![1; mem_table.capacity()];
+        let is_full = mem_table.to_owned().is_full(&Entry::new(key, 0, 0, false));
         assert_eq!(is_full, true);
     }
+
+    #[test]
+    fn test_is_full_with_mixed_entry_sizes() {
+        let buffer_size = 1024;
+        let false_pos_rate = 1e-4;
+        let mut mem_table =
+            MemTable::with_specified_capacity_and_rate(SizeUnit::Kilobytes, buffer_size, false_pos_rate);
+        let capacity = mem_table.capacity();
+        let small_key_len = 10;
+        let large_key_len = 10 * 1024;
+        let inline_len = 4 * 1024;
+        let created_at = Utc::now().timestamp_millis() as u64;
+        let mut i: usize = 0;
+        loop {
+            let key_len = [large_key_len, small_key_len, small_key_len][i % 3];
+            let mut key = vec![0; key_len];
+            key[..SIZE_OF_U64].copy_from_slice(&i.to_le_bytes());
+            // Inline values count towards the capacity as much as keys do
+            let inline = (i % 3 == 2).then(|| vec![0; inline_len]);
+            let entry = Entry::new(key, i, created_at, false).with_inline(inline);
+            if mem_table.is_full(&entry) {
+                break;
+            }
+            mem_table.insert(&entry).unwrap();
+            i += 1;
+        }
+        // flush must happen near the configured capacity regardless of entry size skew
+        assert!(mem_table.size() <= capacity);
+        assert!(capacity - mem_table.size() < MemTable::entry_size(large_key_len));
+
+        let expected_avg = mem_table.size() / i;
+        assert_eq!(mem_table.avg_entry_size(), expected_avg);
+
+        // next memtable bloom filter is sized from the observed average instead of the default
+        let default_sized =
+            MemTable::with_specified_capacity_and_rate(SizeUnit::Kilobytes, buffer_size, false_pos_rate);
+        let next = MemTable::with_specified_capacity_rate_and_entry_size(
+            SizeUnit::Kilobytes,
+            buffer_size,
+            false_pos_rate,
+            mem_table.avg_entry_size(),
+        );
+        assert!(next.bloom_filter.num_bits() < default_sized.bloom_filter.num_bits());
+
+        mem_table.clear();
+        assert_eq!(mem_table.size(), 0);
+        assert_eq!(mem_table.bloom_filter.num_bits(), next.bloom_filter.num_bits());
+        assert_eq!(mem_table.avg_entry_size(), DEFAULT_AVG_ENTRY_SIZE);
+    }
 }
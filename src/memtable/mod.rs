@@ -1,4 +1,5 @@
 mod memtable;
+pub(crate) use memtable::is_expired;
 pub use memtable::Entry;
 pub use memtable::MemTable;
 pub use memtable::SkipMapValue;
@@ -0,0 +1,58 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Allocates the numbers that name bucket and SSTable directories and the files inside them
+///
+/// Numbers only go up so names sort in creation order and are never reused. The last number handed out is
+/// persisted in the meta file, numbers handed out since the last write are recovered from the names listed in
+/// the manifest.
+#[derive(Debug, Clone, Default)]
+pub struct FileNumbers {
+    last: Arc<AtomicU64>,
+}
+
+impl FileNumbers {
+    /// Creates an allocator whose next number is bigger than `last`
+    pub fn new(last: u64) -> Self {
+        Self {
+            last: Arc::new(AtomicU64::new(last)),
+        }
+    }
+
+    /// Returns a number bigger than every one returned before
+    pub fn next(&self) -> u64 {
+        self.last.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Returns the last number handed out
+    pub fn last(&self) -> u64 {
+        self.last.load(Ordering::SeqCst)
+    }
+
+    /// Makes sure later numbers are bigger than `number`, used when recovering names
+    pub(crate) fn advance_to(&self, number: u64) {
+        self.last.fetch_max(number, Ordering::SeqCst);
+    }
+
+    /// Returns the number a name ends with, `sstable_000012` ends with 12
+    pub(crate) fn parse(name: &str) -> Option<u64> {
+        name.rsplit('_').next()?.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_after_advance() {
+        let numbers = FileNumbers::default();
+        assert_eq!(numbers.next(), 1);
+        assert_eq!(numbers.next(), 2);
+        numbers.advance_to(FileNumbers::parse("sstable_000012").unwrap());
+        numbers.advance_to(1);
+        assert_eq!(numbers.next(), 13);
+        assert_eq!(numbers.last(), 13);
+        assert_eq!(FileNumbers::parse("bucket6a0e5f4c"), None);
+    }
+}
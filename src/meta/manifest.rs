@@ -0,0 +1,281 @@
+//! # Manifest
+//!
+//! The manifest lists the live SSTables of the store: the bucket each one belongs to, its data and index files,
+//! its smallest and biggest keys and what is needed to rebuild its bloom filter. Recovery opens the SSTables it
+//! lists instead of walking the buckets directory, so files it does not list are never mistaken for live ones.
+//!
+//! The manifest is kept by the bucket map and rewritten whenever a flush or a compaction adds or removes
+//! SSTables. Each rewrite goes to a temporary file that is synced and renamed over the previous manifest, so a
+//! crash leaves either the old or the new list of SSTables, never a partial one. Paths are stored relative to the
+//! buckets directory.
+
+use crate::bucket::BucketID;
+use crate::consts::{SIZE_OF_U32, SIZE_OF_U64};
+use crate::err::Error;
+use crate::err::Error::*;
+use crate::sst::Table;
+use crate::types::Key;
+use indexmap::IndexMap;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+const SIZE_OF_UUID: usize = 16;
+
+/// An SSTable listed in the manifest
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableRecord {
+    pub bucket_id: BucketID,
+    pub bucket_dir: PathBuf,
+    pub dir: PathBuf,
+    pub data_file: PathBuf,
+    pub index_file: PathBuf,
+    pub smallest_key: Key,
+    pub biggest_key: Key,
+
+    /// Number of keys added to the bloom filter of the SSTable
+    pub filter_entries: u64,
+
+    /// False positive rate the bloom filter of the SSTable was built with
+    pub false_positive_rate: f64,
+}
+
+impl TableRecord {
+    fn serialize(&self, buckets_dir: &Path) -> Vec<u8> {
+        let mut record = Vec::new();
+        record.extend_from_slice(self.bucket_id.as_bytes());
+        for path in [&self.bucket_dir, &self.dir, &self.data_file, &self.index_file] {
+            let path = path.strip_prefix(buckets_dir).unwrap_or(path);
+            let path = path.to_string_lossy();
+            record.extend_from_slice(&(path.len() as u32).to_le_bytes());
+            record.extend_from_slice(path.as_bytes());
+        }
+        for key in [&self.smallest_key, &self.biggest_key] {
+            record.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            record.extend_from_slice(key);
+        }
+        record.extend_from_slice(&self.filter_entries.to_le_bytes());
+        record.extend_from_slice(&self.false_positive_rate.to_le_bytes());
+        record
+    }
+
+    // Parses the record at the start of `buf` and returns it with the number of bytes read,
+    // `None` if `buf` ends before the record does
+    fn deserialize(buf: &[u8], buckets_dir: &Path) -> Option<(Self, usize)> {
+        let mut read = 0;
+        let mut next = |len: usize| -> Option<&[u8]> {
+            let bytes = buf.get(read..read + len)?;
+            read += len;
+            Some(bytes)
+        };
+        let bucket_id = Uuid::from_slice(next(SIZE_OF_UUID)?).ok()?;
+        let mut paths = Vec::with_capacity(4);
+        for _ in 0..4 {
+            let len = u32::from_le_bytes(next(SIZE_OF_U32)?.try_into().ok()?) as usize;
+            let path = String::from_utf8(next(len)?.to_vec()).ok()?;
+            paths.push(buckets_dir.join(path));
+        }
+        let smallest_len = u32::from_le_bytes(next(SIZE_OF_U32)?.try_into().ok()?) as usize;
+        let smallest_key = next(smallest_len)?.to_vec();
+        let biggest_len = u32::from_le_bytes(next(SIZE_OF_U32)?.try_into().ok()?) as usize;
+        let biggest_key = next(biggest_len)?.to_vec();
+        let filter_entries = u64::from_le_bytes(next(SIZE_OF_U64)?.try_into().ok()?);
+        let false_positive_rate = f64::from_le_bytes(next(SIZE_OF_U64)?.try_into().ok()?);
+        let index_file = paths.pop()?;
+        let data_file = paths.pop()?;
+        let dir = paths.pop()?;
+        let bucket_dir = paths.pop()?;
+        let record = Self {
+            bucket_id,
+            bucket_dir,
+            dir,
+            data_file,
+            index_file,
+            smallest_key,
+            biggest_key,
+            filter_entries,
+            false_positive_rate,
+        };
+        Some((record, read))
+    }
+}
+
+/// Live SSTables of the store, persisted in `path`
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    path: PathBuf,
+    buckets_dir: PathBuf,
+    false_positive_rate: f64,
+
+    /// Records keyed by the directory of their SSTable, in the order the SSTables were added
+    tables: IndexMap<PathBuf, TableRecord>,
+}
+
+impl Manifest {
+    /// Loads the manifest stored in `path`, the file is created on the first write
+    ///
+    /// SSTables recorded from now on are given `false_positive_rate` as the rate of their bloom filter
+    pub async fn open(path: PathBuf, buckets_dir: PathBuf, false_positive_rate: f64) -> Result<Self, Error> {
+        let mut manifest = Self {
+            path,
+            buckets_dir,
+            false_positive_rate,
+            tables: IndexMap::new(),
+        };
+        if !manifest.path.exists() {
+            return Ok(manifest);
+        }
+        let buf = fs::read(&manifest.path).await.map_err(|error| FileReadError {
+            path: manifest.path.to_owned(),
+            error,
+        })?;
+        let mut offset = 0;
+        while offset < buf.len() {
+            match TableRecord::deserialize(&buf[offset..], &manifest.buckets_dir) {
+                Some((record, read)) => {
+                    manifest.tables.insert(record.dir.to_owned(), record);
+                    offset += read;
+                }
+                // Rewrites are atomic so this can only be caused by a corrupted file
+                None => {
+                    return Err(InvalidSSTableDirectoryError {
+                        input_string: manifest.path.to_string_lossy().to_string(),
+                    })
+                }
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Returns the records of the live SSTables, oldest first
+    pub fn tables(&self) -> impl Iterator<Item = &TableRecord> {
+        self.tables.values()
+    }
+
+    /// Returns the record of the SSTable stored in `dir`
+    pub fn get(&self, dir: &Path) -> Option<&TableRecord> {
+        self.tables.get(dir)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// Adds `sst` of bucket `bucket_id` stored in `bucket_dir`, the change is persisted by the next `write`
+    pub fn record(&mut self, bucket_id: BucketID, bucket_dir: &Path, sst: &Table) -> Result<(), Error> {
+        let smallest_key = sst
+            .entries
+            .front()
+            .map(|e| e.key().to_vec())
+            .ok_or(LowestKeyIndexError)?;
+        let biggest_key = sst
+            .entries
+            .back()
+            .map(|e| e.key().to_vec())
+            .ok_or(BiggestKeyIndexError)?;
+        let record = TableRecord {
+            bucket_id,
+            bucket_dir: bucket_dir.to_path_buf(),
+            dir: sst.dir.to_owned(),
+            data_file: sst.data_file.path.to_owned(),
+            index_file: sst.index_file.path.to_owned(),
+            smallest_key,
+            biggest_key,
+            filter_entries: sst.entries.len() as u64,
+            false_positive_rate: self.false_positive_rate,
+        };
+        self.tables.insert(record.dir.to_owned(), record);
+        Ok(())
+    }
+
+    /// Removes the SSTable stored in `dir`, the change is persisted by the next `write`
+    pub fn forget(&mut self, dir: &Path) -> bool {
+        self.tables.shift_remove(dir).is_some()
+    }
+
+    /// Replaces the manifest file with the current list of SSTables
+    pub async fn write(&self) -> Result<(), Error> {
+        let buf: Vec<u8> = self
+            .tables
+            .values()
+            .flat_map(|record| record.serialize(&self.buckets_dir))
+            .collect();
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).await.map_err(|error| DirCreationError {
+                path: dir.to_path_buf(),
+                error,
+            })?;
+        }
+        // Written to a temporary file first so that a crash never leaves a partially written file
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp_path)
+            .await
+            .map_err(|error| FileOpenError {
+                path: tmp_path.to_owned(),
+                error,
+            })?;
+        file.write_all(&buf).await.map_err(|error| FileWriteError {
+            path: tmp_path.to_owned(),
+            error,
+        })?;
+        file.sync_all().await.map_err(|error| FileSyncError { error })?;
+        fs::rename(&tmp_path, &self.path).await.map_err(|error| FileWriteError {
+            path: self.path.to_owned(),
+            error,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn record(buckets_dir: &Path, bucket_id: BucketID, name: &str) -> TableRecord {
+        let bucket_dir = buckets_dir.join(format!("bucket{}", bucket_id));
+        let dir = bucket_dir.join(name);
+        TableRecord {
+            bucket_id,
+            bucket_dir,
+            data_file: dir.join("data.db"),
+            index_file: dir.join("index.db"),
+            dir,
+            smallest_key: b"a".to_vec(),
+            biggest_key: b"z".to_vec(),
+            filter_entries: 10,
+            false_positive_rate: 0.01,
+        }
+    }
+
+    #[tokio::test]
+    async fn manifest_survives_reopen() {
+        let root = tempdir().unwrap();
+        let buckets_dir = root.path().join("buckets");
+        let path = root.path().join("meta").join("manifest.bin");
+        let mut manifest = Manifest::open(path.to_owned(), buckets_dir.to_owned(), 0.01)
+            .await
+            .unwrap();
+        assert!(manifest.is_empty());
+
+        let bucket_id = Uuid::new_v4();
+        let first = record(&buckets_dir, bucket_id, "sstable_1");
+        let second = record(&buckets_dir, bucket_id, "sstable_2");
+        manifest.tables.insert(first.dir.to_owned(), first.to_owned());
+        manifest.tables.insert(second.dir.to_owned(), second.to_owned());
+        assert!(manifest.forget(&first.dir));
+        assert!(!manifest.forget(&first.dir));
+        manifest.write().await.unwrap();
+
+        let manifest = Manifest::open(path, buckets_dir, 0.01).await.unwrap();
+        assert_eq!(manifest.tables().collect::<Vec<_>>(), vec![&second]);
+    }
+}
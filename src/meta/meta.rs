@@ -1,29 +1,202 @@
-use std::fs::OpenOptions;
-use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use std::sync::Arc;
 
 use chrono::{DateTime, Utc};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use super::{FileNumbers, Sequence};
+use crate::consts::{META_FILE_NAME, SIZE_OF_U64};
+use crate::err::Error;
+use crate::err::Error::*;
+
+/// Head and tail of the value log
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ValueLogOffsets {
+    /// Entries from the head on are replayed on recovery
+    pub head: usize,
+
+    /// Space before the tail was reclaimed by garbage collection
+    pub tail: usize,
+}
 
 // For now this struct doesn't do much but as the project evolve it will store details about the storage engine
 #[derive(Debug, Clone)]
 pub struct Meta {
     pub path: PathBuf,
-    pub v_log_tail: u32,
-    pub v_log_head: u32,
     pub created_at: DateTime<Utc>,
     pub last_modified: DateTime<Utc>,
+
+    /// Allocator of the sequence numbers of new entries, its last value is persisted in the meta file
+    pub sequence: Sequence,
+
+    /// Allocator of the numbers naming new files, its last value is persisted in the meta file
+    pub file_numbers: FileNumbers,
+
+    /// Offsets of the value log persisted in the meta file, `None` if it was written before they were.
+    /// Shared by the clones of the meta, the lock is held while the file is written so writes never interleave
+    v_log_offsets: Arc<Mutex<Option<ValueLogOffsets>>>,
 }
 
 impl Meta {
-    pub fn new(path: &PathBuf) -> Self {
+    pub fn new(path: &Path) -> Self {
         let created_at = Utc::now();
         let last_modified = Utc::now();
         Self {
             path: PathBuf::from(path),
-            v_log_tail: 0,
-            v_log_head: 0,
             created_at,
             last_modified,
+            sequence: Sequence::default(),
+            file_numbers: FileNumbers::default(),
+            v_log_offsets: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Loads the meta file stored in the `path` directory, the file is created on the first write
+    pub async fn open(path: &Path) -> Result<Self, Error> {
+        let meta = Self::new(path);
+        let file_path = meta.file_path();
+        if !file_path.exists() {
+            return Ok(meta);
+        }
+        let buf = fs::read(&file_path).await.map_err(|error| FileReadError {
+            path: file_path.to_owned(),
+            error,
+        })?;
+        match buf.get(..SIZE_OF_U64).and_then(|bytes| bytes.try_into().ok()) {
+            Some(bytes) => meta.sequence.advance_to(u64::from_le_bytes(bytes)),
+            // Sequence numbers are recovered from the entries replayed from the value log instead
+            None => log::warn!("Ignoring incomplete meta file {:?}", file_path),
+        }
+        // Missing from meta files written before file numbers existed, they are recovered from the manifest
+        if let Some(bytes) = buf
+            .get(SIZE_OF_U64..SIZE_OF_U64 * 2)
+            .and_then(|bytes| bytes.try_into().ok())
+        {
+            meta.file_numbers.advance_to(u64::from_le_bytes(bytes));
+        }
+        // Missing from meta files written before the offsets of the value log were, they are recovered from
+        // the head and tail entries of the SSTables instead
+        let offsets = buf.get(SIZE_OF_U64 * 2..SIZE_OF_U64 * 4).map(|bytes| ValueLogOffsets {
+            head: u64::from_le_bytes(bytes[..SIZE_OF_U64].try_into().unwrap()) as usize,
+            tail: u64::from_le_bytes(bytes[SIZE_OF_U64..].try_into().unwrap()) as usize,
+        });
+        *meta.v_log_offsets.lock().await = offsets;
+        Ok(meta)
+    }
+
+    /// Returns the offsets of the value log persisted in the meta file, `None` if none were
+    pub async fn v_log_offsets(&self) -> Option<ValueLogOffsets> {
+        *self.v_log_offsets.lock().await
+    }
+
+    /// Replaces the offsets of the value log and persists them
+    pub async fn set_v_log_offsets(&mut self, offsets: ValueLogOffsets) -> Result<(), Error> {
+        let lock = Arc::clone(&self.v_log_offsets);
+        let mut v_log_offsets = lock.lock().await;
+        *v_log_offsets = Some(offsets);
+        self.write_file(*v_log_offsets).await
+    }
+
+    /// Moves the head of the value log to `head` unless it is already past it, and persists it
+    ///
+    /// Must only be called once the entries before `head` are stored in SSTables
+    pub async fn advance_v_log_head(&mut self, head: usize) -> Result<(), Error> {
+        let lock = Arc::clone(&self.v_log_offsets);
+        let mut v_log_offsets = lock.lock().await;
+        let offsets = v_log_offsets.get_or_insert_with(ValueLogOffsets::default);
+        offsets.head = offsets.head.max(head);
+        self.write_file(*v_log_offsets).await
+    }
+
+    /// Moves the tail of the value log to `tail` unless it is already past it, and persists it
+    pub async fn advance_v_log_tail(&mut self, tail: usize) -> Result<(), Error> {
+        let lock = Arc::clone(&self.v_log_offsets);
+        let mut v_log_offsets = lock.lock().await;
+        let offsets = v_log_offsets.get_or_insert_with(ValueLogOffsets::default);
+        offsets.tail = offsets.tail.max(tail);
+        self.write_file(*v_log_offsets).await
+    }
+
+    /// Persists the last sequence number and file number handed out along with the offsets of the value log
+    ///
+    /// Must be called before entries leave the part of the value log replayed on recovery so that their
+    /// sequence numbers are never handed out again
+    pub async fn write(&mut self) -> Result<(), Error> {
+        let lock = Arc::clone(&self.v_log_offsets);
+        let v_log_offsets = lock.lock().await;
+        self.write_file(*v_log_offsets).await
+    }
+
+    // Callers hold the lock on the offsets of the value log
+    async fn write_file(&mut self, v_log_offsets: Option<ValueLogOffsets>) -> Result<(), Error> {
+        let file_path = self.file_path();
+        fs::create_dir_all(&self.path).await.map_err(|error| DirCreationError {
+            path: self.path.to_owned(),
+            error,
+        })?;
+        // Written to a temporary file first so that a crash never leaves a partially written file
+        let tmp_path = file_path.with_extension("tmp");
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp_path)
+            .await
+            .map_err(|error| FileOpenError {
+                path: tmp_path.to_owned(),
+                error,
+            })?;
+        let mut buf = Vec::with_capacity(SIZE_OF_U64 * 4);
+        buf.extend_from_slice(&self.sequence.last().to_le_bytes());
+        buf.extend_from_slice(&self.file_numbers.last().to_le_bytes());
+        if let Some(offsets) = v_log_offsets {
+            buf.extend_from_slice(&(offsets.head as u64).to_le_bytes());
+            buf.extend_from_slice(&(offsets.tail as u64).to_le_bytes());
         }
+        file.write_all(&buf).await.map_err(|error| FileWriteError {
+            path: tmp_path.to_owned(),
+            error,
+        })?;
+        file.sync_all().await.map_err(|error| FileSyncError { error })?;
+        fs::rename(&tmp_path, &file_path)
+            .await
+            .map_err(|error| FileWriteError {
+                path: file_path.to_owned(),
+                error,
+            })?;
+        self.last_modified = Utc::now();
+        Ok(())
+    }
+
+    fn file_path(&self) -> PathBuf {
+        self.path.join(META_FILE_NAME)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn v_log_offsets_survive_reopen() {
+        let root = tempdir().unwrap();
+        let mut meta = Meta::open(root.path()).await.unwrap();
+        assert_eq!(meta.v_log_offsets().await, None);
+        meta.write().await.unwrap();
+        assert_eq!(Meta::open(root.path()).await.unwrap().v_log_offsets().await, None);
+
+        let mut other = meta.clone();
+        meta.advance_v_log_head(100).await.unwrap();
+        other.advance_v_log_tail(40).await.unwrap();
+        // A head recorded out of order does not move the head back
+        other.advance_v_log_head(60).await.unwrap();
+
+        let meta = Meta::open(root.path()).await.unwrap();
+        let offsets = ValueLogOffsets { head: 100, tail: 40 };
+        assert_eq!(meta.v_log_offsets().await, Some(offsets));
     }
 }
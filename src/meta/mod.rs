@@ -1,2 +1,9 @@
+mod file_numbers;
+mod manifest;
 mod meta;
+mod sequence;
+pub use file_numbers::FileNumbers;
+pub use manifest::Manifest;
 pub use meta::Meta;
+pub use meta::ValueLogOffsets;
+pub use sequence::Sequence;
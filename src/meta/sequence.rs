@@ -0,0 +1,64 @@
+use crate::types::CreationTime;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Allocates the sequence numbers that order the versions of a key, shared by writes and garbage collection
+///
+/// Sequence numbers are stored where entries used to store their creation time. They never repeat nor go backwards,
+/// two writes made within the same millisecond or across a clock adjustment are still ordered. Stores written before
+/// sequence numbers were counted hold creation times, the counter carries on from the last one.
+#[derive(Debug, Clone, Default)]
+pub struct Sequence {
+    last: Arc<AtomicU64>,
+}
+
+impl Sequence {
+    /// Creates an allocator whose next sequence number is bigger than `last`
+    pub fn new(last: CreationTime) -> Self {
+        Self {
+            last: Arc::new(AtomicU64::new(last)),
+        }
+    }
+
+    /// Returns a sequence number bigger than every one returned before
+    pub fn next(&self) -> CreationTime {
+        self.last.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Returns the last sequence number handed out
+    pub fn last(&self) -> CreationTime {
+        self.last.load(Ordering::SeqCst)
+    }
+
+    /// Makes sure later sequence numbers are bigger than `sequence`, used when recovering entries
+    pub(crate) fn advance_to(&self, sequence: CreationTime) {
+        self.last.fetch_max(sequence, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_is_strictly_increasing() {
+        let sequence = Sequence::default();
+        let mut previous = sequence.next();
+        for _ in 0..1000 {
+            let next = sequence.next();
+            assert!(next > previous);
+            previous = next;
+        }
+        assert_eq!(sequence.last(), previous);
+    }
+
+    #[test]
+    fn test_advance_to() {
+        let sequence = Sequence::new(5);
+        assert_eq!(sequence.next(), 6);
+        sequence.advance_to(100);
+        assert_eq!(sequence.next(), 101);
+        sequence.advance_to(50);
+        assert_eq!(sequence.next(), 102);
+    }
+}
@@ -1,2 +1,4 @@
 mod range;
-pub use range::RangeIterator;
+mod super_version;
+pub(crate) use range::entries_within;
+pub use range::{ContinuationToken, FetchedEntry, KeyEntry, KeyIterator, RangeIterator, ScanPage};
@@ -3,32 +3,46 @@
 // each identified SSTable might still contain data outside your desired range. For heavily range query-focused workloads, LCS or TWSC should be considered
 // Although this stratedy is not available for now, It will be implmented in the future
 
-use crate::consts::{DEFAULT_ALLOW_PREFETCH, DEFAULT_PREFETCH_SIZE, HEAD_ENTRY_KEY};
+use crate::cfg::ReadOptions;
+use crate::consts::{DEFAULT_ALLOW_PREFETCH, DEFAULT_PREFETCH_SIZE, HEAD_ENTRY_KEY, TAIL_ENTRY_KEY};
 use crate::err::Error;
+use crate::gc::ReadPin;
 use crate::index::Index;
-use crate::memtable::{Entry, MemTable};
+use crate::iterator::{EntryIterator, MergeIterator};
+use crate::memtable::{Entry, SkipMapValue};
+use crate::range::super_version::SuperVersion;
+use crate::range_tombstone::RangeTombstones;
+use crate::sst::BlockCursor;
 use crate::storage::DataStore;
-use crate::types::{Key, ValOffset, Value};
+use crate::types::{CreationTime, Key, ValOffset, Value};
 use crate::value_log::ValueLog;
 use async_trait::async_trait;
-use futures::future::join_all;
-use futures::stream::StreamExt;
-use log::error;
-use std::collections::BTreeMap;
+use crossbeam_skiplist::SkipMap;
+use futures::future::try_join_all;
+use futures::stream::{self, BoxStream, Stream, StreamExt, TryStreamExt};
+use futures::Future;
+use std::cmp;
+use std::collections::VecDeque;
+use std::iter::Peekable;
+use std::ops::{Bound, RangeBounds, RangeFull};
 use std::path::PathBuf;
 use std::pin::Pin;
-use std::sync::Arc;
 use std::task::{Context, Poll};
-use std::{cmp::Ordering, collections::HashMap};
 use tokio::fs::{File, OpenOptions};
-use tokio::sync::RwLock;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
 #[derive(Debug, Clone)]
 pub struct FetchedEntry {
     pub key: Key,
     pub val: Value,
 }
 
-#[derive(Debug, Clone)]
+/// Streams the entries of a range query in key order
+///
+/// Implements `futures::Stream` so it composes with `StreamExt` and `tokio::select!`. When prefetch is
+/// allowed, values of the next batch of keys are read from the value log in the background while the
+/// current batch is being consumed.
+#[derive(Debug)]
 pub struct RangeIterator<'a> {
     pub start: &'a [u8],
     pub current: usize,
@@ -38,6 +52,15 @@ pub struct RangeIterator<'a> {
     pub prefetch_entries: Vec<FetchedEntry>,
     pub keys: Vec<Entry<Key, ValOffset>>,
     pub v_log: ValueLog,
+
+    /// Number of keys whose values have already been requested from the value log
+    pub fetched: usize,
+
+    /// Batch of values being read from the value log
+    pending_fetch: Option<JoinHandle<Result<Vec<FetchedEntry>, Error>>>,
+
+    /// Keeps garbage collection from reclaiming the values of `keys` until the iterator is dropped
+    _pin: ReadPin,
 }
 
 impl<'a> RangeIterator<'a> {
@@ -48,6 +71,7 @@ impl<'a> RangeIterator<'a> {
         prefetch_entries_size: usize,
         keys: Vec<Entry<Key, ValOffset>>,
         v_log: ValueLog,
+        pin: ReadPin,
     ) -> Self {
         Self {
             start,
@@ -58,30 +82,12 @@ impl<'a> RangeIterator<'a> {
             prefetch_entries: Vec::new(),
             keys,
             v_log,
+            fetched: 0,
+            pending_fetch: None,
+            _pin: pin,
         }
     }
 
-    pub async fn next(&mut self) -> Option<FetchedEntry> {
-        if self.allow_prefetch {
-            if self.current_is_at_end_prefetched_keys() {
-                if self.current_is_at_last_key() {
-                    return None;
-                }
-                match self.prefetch_entries().await {
-                    Err(err) => {
-                        error!("{}", Error::RangeScanError(Box::new(err)));
-                        return None;
-                    }
-                    _ => {}
-                }
-            }
-            let entry = self.current_entry();
-            self.current += 1;
-            return Some(entry);
-        }
-        //TODO: handle not allow prefetch option
-        return None;
-    }
     pub fn prev(&mut self) -> Option<FetchedEntry> {
         None
     }
@@ -98,253 +104,531 @@ impl<'a> RangeIterator<'a> {
         None
     }
 
-    pub async fn prefetch_entries(&mut self) -> Result<(), Error> {
-        let keys: Vec<Entry<Key, ValOffset>>;
-        if self.current + self.prefetch_entries_size <= self.keys.len() {
-            keys = (&self.keys[self.current..self.current + self.prefetch_entries_size]).to_vec();
+    // Starts reading the values of the next batch of keys in the background
+    fn prefetch_entries(&mut self) {
+        // Without prefetch, values are fetched from the value log one at a time
+        let batch_size = if self.allow_prefetch {
+            cmp::max(self.prefetch_entries_size, 1)
         } else {
-            keys = (&self.keys[self.current..]).to_vec();
-        }
-        let entries = self.fetch_entries_in_parralel(&keys).await;
-        match entries {
-            Ok(e) => Ok(self.prefetch_entries.extend(e)),
-            Err(err) => Err(err),
-        }
-    }
-    pub fn current_entry(&self) -> FetchedEntry {
-        self.prefetch_entries[self.current].to_owned()
+            1
+        };
+        let batch_end = cmp::min(self.fetched + batch_size, self.keys.len());
+        let keys = self.keys[self.fetched..batch_end].to_vec();
+        self.fetched = batch_end;
+        self.pending_fetch = Some(tokio::spawn(fetch_entries(self.v_log.to_owned(), keys)));
     }
     pub fn current_is_at_end_prefetched_keys(&self) -> bool {
         self.current >= self.prefetch_entries.len()
     }
     pub fn current_is_at_last_key(&self) -> bool {
-        self.current >= self.keys.len()
+        self.fetched >= self.keys.len()
     }
     pub fn reset_current(&mut self) {
         self.current = 0;
     }
-    pub async fn fetch_entries_in_parralel(
-        &self,
-        keys: &'a Vec<Entry<Key, ValOffset>>,
-    ) -> Result<Vec<FetchedEntry>, Error> {
-        let mut entries_map: BTreeMap<Key, Value> = BTreeMap::new();
-        let tokio_owned_keys = keys.to_owned();
-        let tokio_owned_v_log = Arc::new(self.v_log.to_owned());
-        let tasks = tokio_owned_keys.into_iter().map(|entry| {
-            let v_log = Arc::clone(&tokio_owned_v_log);
-            tokio::spawn(async move {
-                // We only use the snapshot of vlog to prevent modification while transaction is ongoing
-                let entry_from_vlog = v_log.get(entry.val_offset).await;
-                match entry_from_vlog {
-                    Ok(val_opt) => match val_opt {
-                        Some((val, is_deleted)) => return Ok((entry.key, val, is_deleted)),
-                        None => {
-                            return Err(Error::KeyNotFoundInValueLogError);
-                        }
-                    },
-                    Err(err) => return Err(err),
-                };
-            })
-        });
+}
+
+impl Stream for RangeIterator<'_> {
+    type Item = Result<(Key, Value), Error>;
 
-        let all_results = join_all(tasks).await;
-        for tokio_response in all_results {
-            match tokio_response {
-                Ok(entry) => match entry {
-                    Ok((key, val, is_deleted)) => {
-                        if !is_deleted {
-                            entries_map.insert(key, val);
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if !self.current_is_at_end_prefetched_keys() {
+                let current = self.current;
+                let entry = std::mem::replace(
+                    &mut self.prefetch_entries[current],
+                    FetchedEntry {
+                        key: Vec::new(),
+                        val: Vec::new(),
+                    },
+                );
+                self.current += 1;
+                return Poll::Ready(Some(Ok((entry.key, entry.val))));
+            }
+            match self.pending_fetch.as_mut() {
+                Some(pending_fetch) => match Pin::new(pending_fetch).poll(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(result) => {
+                        self.pending_fetch = None;
+                        match result.map_err(|_| Error::TokioJoinError).and_then(|r| r) {
+                            Ok(entries) => {
+                                self.prefetch_entries = entries;
+                                self.reset_current();
+                                // Pipeline the value log reads of the next batch with the consumption of this one
+                                if self.allow_prefetch && !self.current_is_at_last_key() {
+                                    self.prefetch_entries();
+                                }
+                            }
+                            Err(err) => {
+                                // End the stream after reporting the error
+                                self.fetched = self.keys.len();
+                                return Poll::Ready(Some(Err(Error::RangeScanError(Box::new(err)))));
+                            }
                         }
                     }
-                    Err(err) => {
-                        error!("{:?}", err)
-                    }
                 },
-                Err(err) => error!("{}", err),
+                None => {
+                    if self.current_is_at_last_key() {
+                        return Poll::Ready(None);
+                    }
+                    self.prefetch_entries();
+                }
             }
         }
-        let mut prefetched_entries = Vec::new();
-        for (key, val) in entries_map {
-            prefetched_entries.push(FetchedEntry { key, val })
+    }
+}
+
+impl Drop for RangeIterator<'_> {
+    fn drop(&mut self) {
+        if let Some(pending_fetch) = self.pending_fetch.take() {
+            pending_fetch.abort();
+        }
+    }
+}
+
+// Reads values of `keys` with a single sweep over the value log sorted by offset, entries deleted in the value log
+// are skipped
+async fn fetch_entries(v_log: ValueLog, keys: Vec<Entry<Key, ValOffset>>) -> Result<Vec<FetchedEntry>, Error> {
+    // We only use the snapshot of vlog to prevent modification while transaction is ongoing
+    let locations: Vec<ValOffset> = keys
+        .iter()
+        .filter(|entry| entry.inline.is_none())
+        .map(|entry| entry.val_offset)
+        .collect();
+    let mut values = v_log.get_many(&locations).await?.into_iter();
+    let mut prefetched_entries = Vec::new();
+    for entry in keys {
+        let (val, is_deleted) = match entry.inline {
+            Some(inline) => (inline, false),
+            None => values.next().flatten().ok_or(Error::KeyNotFoundInValueLogError)?,
+        };
+        if !is_deleted {
+            prefetched_entries.push(FetchedEntry { key: entry.key, val })
         }
-        Ok(prefetched_entries)
     }
+    Ok(prefetched_entries)
 }
 
 impl<'a> DataStore<'a, Key> {
-    // Start if the range query
-    pub async fn seek(&self, start: &'a [u8], end: &'a [u8]) -> Result<RangeIterator, Error> {
-        let mut merger = Merger::new();
-        // check entries within active memtable
-        // if !self.active_memtable.index.is_empty() {
-        //     if self
-        //         .active_memtable
-        //         .index
-        //         .lower_bound(std::ops::Bound::Included(start))
-        //         .is_some()
-        //         || self
-        //             .active_memtable
-        //             .index
-        //             .upper_bound(std::ops::Bound::Included(end))
-        //             .is_some()
-        //     {
-        //         merger.merge_entries(
-        //             self.active_memtable
-        //                 .clone()
-        //                 .index
-        //                 .iter()
-        //                 .filter(|e| InMemoryTable::is_entry_within_range(e, start, end))
-        //                 .map(|e| {
-        //                     Entry::new(e.key().to_vec(), e.value().0, e.value().1, e.value().2)
-        //                 })
-        //                 .collect::<Vec<Entry<Key, ValOffset>>>(),
-        //         );
-        //     }
-        // }
-        // // check inactive memtable
-        // if !self.read_only_memtables.read().await.is_empty() {
-        //     let read_only_memtables = self.read_only_memtables.read().await.clone();
-
-        //     for (_, memtable) in read_only_memtables {
-        //         let memtable_ref = memtable.read().await;
-        //         if memtable_ref
-        //             .index
-        //             .lower_bound(std::ops::Bound::Included(start))
-        //             .is_some()
-        //             || memtable_ref
-        //                 .index
-        //                 .upper_bound(std::ops::Bound::Included(end))
-        //                 .is_some()
-        //         {
-        //             merger.merge_entries(
-        //                 memtable_ref
-        //                     .clone()
-        //                     .index
-        //                     .iter()
-        //                     .filter(|e| InMemoryTable::is_entry_within_range(e, start, end))
-        //                     .map(|e| {
-        //                         Entry::new(e.key().to_vec(), e.value().0, e.value().1, e.value().2)
-        //                     })
-        //                     .collect::<Vec<Entry<Key, ValOffset>>>(),
-        //             );
-        //         }
-        //     }
-        // }
-
-        let sstables_within_range = {
-            let mut sstable_path = HashMap::new();
-
-            for b in self.filters.read().await.to_owned().into_iter() {
-                let bf_inner = b.to_owned();
-                let bf_sstable = bf_inner.sst.to_owned().unwrap();
-                let data_path = bf_sstable.data_file.path.to_str().unwrap();
-                if bf_inner.contains(&start.to_vec()) || bf_inner.contains(&end.to_vec()) {
-                    // add to sstable path
-                    sstable_path.insert(data_path.to_owned(), bf_sstable.to_owned());
-                }
-            }
+    /// Returns an iterator over the live entries whose key falls within `range`, `range.end` is exclusive
+    ///
+    /// The bounds can hold arbitrary bytes (`&str` or `&[u8]`). Entries from the active memtable, read-only
+    /// memtables and SSTables overlapping the range are merged, the most recent version of each key wins and
+    /// deleted keys are skipped. Values are resolved through the value log while iterating.
+    pub async fn range<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        range: std::ops::Range<&'a K>,
+    ) -> Result<RangeIterator<'a>, Error> {
+        self.range_with_options(range, &ReadOptions::default()).await
+    }
 
-            let key_range = self.key_range.read().await;
-            let paths_from_key_range = key_range.range_scan(&start.to_vec(), &end.to_vec());
-            if !paths_from_key_range.is_empty() {
-                for range in paths_from_key_range.iter() {
-                    if !sstable_path.contains_key(range.sst.data_file.path.to_str().unwrap()) {
-                        sstable_path.insert(
-                            range.sst.data_file.path.to_str().unwrap().to_owned(),
-                            range.sst.to_owned(),
-                        );
-                    }
-                }
-            }
-            sstable_path
-        };
+    /// Same as `range` but with per-call read options
+    pub async fn range_with_options<K: AsRef<[u8]> + ?Sized>(
+        &self,
+        range: std::ops::Range<&'a K>,
+        options: &ReadOptions,
+    ) -> Result<RangeIterator<'a>, Error> {
+        self.seek_with_options(range.start.as_ref(), range.end.as_ref(), options)
+            .await
+    }
 
-        // for (_, sst) in sstables_within_range {
-        //     let sparse_index = SparseIndex::new(sst.index_file_path.clone(), sst).await;
-        //     match sparse_index.get_block_offset_range(&start, &end).await {
-        //         Ok(range_offset) => {
-        //             let sst = SSTable::new_with_exisiting_file_path(
-        //                 sst.dir.to_owned(),
-        //                 sst.data_file_path.to_owned(),
-        //                 sst.index_file_path.to_owned(),
-        //             ).await;
-        //             match sst.range(range_offset).await {
-        //                 Ok(sstable_entries) => merger.merge_entries(sstable_entries),
-        //                 Err(err) => return Err(err),
-        //             }
-        //         }
-        //         Err(err) => return Err(Error::RangeScanError(Box::new(err))),
-        //     }
-        // }
+    // Start of the range query, `end` is exclusive
+    pub async fn seek(&self, start: &'a [u8], end: &'a [u8]) -> Result<RangeIterator<'a>, Error> {
+        self.seek_with_options(start, end, &ReadOptions::default()).await
+    }
+
+    async fn seek_with_options(
+        &self,
+        start: &'a [u8],
+        end: &'a [u8],
+        options: &ReadOptions,
+    ) -> Result<RangeIterator<'a>, Error> {
+        let pin = self.gc.config.read_pins.pin();
+        let entries = if start < end {
+            self.live_entries_within(start.to_vec()..end.to_vec(), options)
+                .await?
+                .collect()
+                .await?
+        } else {
+            Vec::new()
+        };
 
         let range_iterator = RangeIterator::<'a>::new(
             start,
             end,
             self.config.allow_prefetch,
             self.config.prefetch_size,
-            merger.entries,
+            entries,
             self.val_log.clone(),
+            pin,
         );
         Ok(range_iterator)
     }
-}
 
-pub struct Merger {
-    entries: Vec<Entry<Key, ValOffset>>,
+    /// Returns a stream over all the live keys in the store
+    ///
+    /// Only memtables and SSTables are read, the value log is never accessed which makes key-only scans
+    /// much cheaper than a range scan. SSTables are read one block at a time as the stream advances
+    pub async fn keys(&self) -> Result<KeyIterator, Error> {
+        let entries = self
+            .live_entries_within::<RangeFull>(.., &ReadOptions::default())
+            .await?;
+        let keys = stream::try_unfold(entries, |mut entries| async move {
+            let key = entries.next().await?.map(|e| KeyEntry {
+                key: e.key,
+                created_at: e.created_at,
+            });
+            Ok(key.map(|key| (key, entries)))
+        });
+        Ok(KeyIterator { keys: Box::pin(keys) })
+    }
+
+    // Reads the live entries within `[start, end)` visible to `options` along with their values
+    pub(crate) async fn fetch_range(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        options: &ReadOptions,
+    ) -> Result<Vec<FetchedEntry>, Error> {
+        if start >= end {
+            return Ok(Vec::new());
+        }
+        let _pin = self.gc.config.read_pins.pin();
+        let keys = self
+            .live_entries_within(start.to_vec()..end.to_vec(), options)
+            .await?
+            .collect()
+            .await?;
+        fetch_entries(self.val_log.to_owned(), keys).await
+    }
+
+    // Returns the live entries within `range` from every source visible to `options`, keeping only the most recent
+    // version of each key and dropping deleted keys. Memtables are read upfront, SSTables one block at a time as the
+    // entries are consumed
+    async fn live_entries_within<R: RangeBounds<Key>>(
+        &self,
+        range: R,
+        options: &ReadOptions,
+    ) -> Result<LiveEntries, Error> {
+        let visible = |entries: Vec<Entry<Key, ValOffset>>| -> EntryIterator<'static> {
+            let options = options.to_owned();
+            Box::new(
+                entries
+                    .into_iter()
+                    .filter(move |e| options.is_visible(e.created_at, e.written_at)),
+            )
+        };
+        // Pin the memtables and sstables to read so concurrent flushes and compactions
+        // cannot make the scan see a key twice or miss it
+        let super_version = self.super_version().await;
+        // Sources are ordered from the most recent to the oldest so that on equal
+        // creation time the entry from the most recent source is kept
+        let mut memtables = vec![
+            visible(entries_within(&*self.gc_updated_entries.read().await, &range)),
+            visible(self.active_memtable.range(&range)),
+        ];
+        for memtable in super_version.memtables.iter().rev() {
+            memtables.push(visible(memtable.read().await.range(&range)));
+        }
+
+        let ssts = if options.reads_sstables() {
+            super_version.ranges_overlapping(&range)
+        } else {
+            Vec::new()
+        };
+        // Only the index of the SSTables is read here, bounded by `max_parallel_sstable_reads`
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        let block_cache = options.fill_cache.then(|| self.block_cache.clone());
+        let sstables = stream::iter(ssts)
+            .map(|key_range| {
+                BlockCursor::new(
+                    key_range.sst.to_owned(),
+                    bounds.to_owned(),
+                    options.verify_checksums,
+                    block_cache.to_owned(),
+                )
+            })
+            .buffered(self.max_parallel_sstable_reads())
+            .try_collect()
+            .await
+            .map_err(|err| Error::RangeScanError(Box::new(err)))?;
+        Ok(LiveEntries {
+            memtables: MergeIterator::new(memtables).peekable(),
+            sstables,
+            max_parallel_reads: self.max_parallel_sstable_reads(),
+            range_tombstones: self.range_tombstones.read().await.to_owned(),
+            options: options.to_owned(),
+            merged: VecDeque::new(),
+            is_done: false,
+            _super_version: super_version,
+        })
+    }
+
+    fn max_parallel_sstable_reads(&self) -> usize {
+        cmp::max(self.config.max_parallel_sstable_reads, 1)
+    }
 }
 
-impl Merger {
-    fn new() -> Self {
-        Self { entries: Vec::new() }
-    }
-    // merge entries in sorted order
-    fn merge_entries(&mut self, entries_to_merge: Vec<Entry<Key, ValOffset>>) {
-        let mut merged_indexes = Vec::new();
-        let e1 = &self.entries;
-        let e2 = entries_to_merge;
-
-        let (mut i, mut j) = (0, 0);
-        // Compare elements from both arrays and merge them
-        while i < e1.len() && j < e2.len() {
-            match e1[i].key.cmp(&e2[j].key) {
-                Ordering::Less => {
-                    self.head_entry_checker(&e1[i], &mut merged_indexes);
-                    i += 1;
-                }
-                Ordering::Equal => {
-                    if e1[i].created_at > e2[j].created_at {
-                        self.head_entry_checker(&e1[i], &mut merged_indexes);
-                    } else {
-                        self.head_entry_checker(&e2[j], &mut merged_indexes);
-                    }
-                    i += 1;
-                    j += 1;
+impl<'a> DataStore<'a, Key> {
+    /// Returns up to `limit` live entries starting at `start` in key order
+    ///
+    /// The returned page holds a continuation token when more entries might follow, passing it back to
+    /// `scan_page` resumes the scan right after the last entry of the page. No iterator is kept open
+    /// between calls so the token can be handed to a client, e.g. for keyset pagination over HTTP.
+    pub async fn scan_page(&self, start: &ContinuationToken, limit: usize) -> Result<ScanPage, Error> {
+        if limit == 0 {
+            return Ok(ScanPage {
+                entries: Vec::new(),
+                next: Some(start.to_owned()),
+            });
+        }
+        let _pin = self.gc.config.read_pins.pin();
+        // The merge stops once the page is full and the next live key is known, the blocks past it are not read
+        let mut keys = self
+            .live_entries_within(
+                (Bound::Included(start.0.to_owned()), Bound::Unbounded),
+                &ReadOptions::default(),
+            )
+            .await?;
+        let mut entries: Vec<FetchedEntry> = Vec::new();
+        let mut next = None;
+        loop {
+            // Values are read in sweeps just big enough to fill the page unless some were deleted in the value log
+            let batch = keys.take(limit - entries.len()).await?;
+            if batch.is_empty() {
+                break;
+            }
+            let locations: Vec<ValOffset> = batch
+                .iter()
+                .filter(|entry| entry.inline.is_none())
+                .map(|entry| entry.val_offset)
+                .collect();
+            let mut values = self.val_log.get_many(&locations).await?.into_iter();
+            for entry in batch {
+                let (val, is_deleted) = match entry.inline {
+                    Some(inline) => (inline, false),
+                    None => values.next().flatten().ok_or(Error::KeyNotFoundInValueLogError)?,
+                };
+                if !is_deleted {
+                    entries.push(FetchedEntry { key: entry.key, val });
                 }
-                Ordering::Greater => {
-                    self.head_entry_checker(&e2[j], &mut merged_indexes);
-                    j += 1;
+            }
+            if entries.len() == limit {
+                if !keys.is_empty().await? {
+                    next = Some(ContinuationToken::after(&entries[limit - 1].key));
                 }
+                break;
             }
         }
+        Ok(ScanPage { entries, next })
+    }
+}
+
+/// Live entries within a range merged from the memtables and SSTables of a super version
+///
+/// The merge advances in rounds. Every SSTable holds its entries up to the last key it read, the entries up to the
+/// smallest of these keys are merged, then the SSTables that have nothing left below it read their next block.
+pub(crate) struct LiveEntries {
+    /// Entries of the memtables, already merged from the most recent to the oldest
+    memtables: Peekable<MergeIterator<'static>>,
+
+    sstables: Vec<BlockCursor>,
 
-        // If there are any remaining entries in e1, append them
-        while i < e1.len() {
-            self.head_entry_checker(&e1[i], &mut merged_indexes);
-            i += 1;
+    max_parallel_reads: usize,
+
+    range_tombstones: RangeTombstones,
+
+    options: ReadOptions,
+
+    /// Live entries of the last round that were not returned yet
+    merged: VecDeque<Entry<Key, ValOffset>>,
+
+    /// Set once the last round merged every remaining entry
+    is_done: bool,
+
+    /// Keeps the SSTables being read from being deleted by compaction
+    _super_version: SuperVersion,
+}
+
+impl LiveEntries {
+    /// Returns the next live entry, `None` once every entry was returned
+    pub(crate) async fn next(&mut self) -> Result<Option<Entry<Key, ValOffset>>, Error> {
+        while self.merged.is_empty() && !self.is_done {
+            self.merge_next_round().await?;
+        }
+        Ok(self.merged.pop_front())
+    }
+
+    /// Returns the next `n` live entries, fewer once every entry was returned
+    pub(crate) async fn take(&mut self, n: usize) -> Result<Vec<Entry<Key, ValOffset>>, Error> {
+        let mut entries = Vec::with_capacity(n);
+        while entries.len() < n {
+            let Some(entry) = self.next().await? else {
+                break;
+            };
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    /// Returns true if every live entry was returned
+    pub(crate) async fn is_empty(&mut self) -> Result<bool, Error> {
+        while self.merged.is_empty() && !self.is_done {
+            self.merge_next_round().await?;
         }
+        Ok(self.merged.is_empty())
+    }
 
-        // If there are any remaining entries in e2, append them
-        while j < e2.len() {
-            self.head_entry_checker(&e2[j], &mut merged_indexes);
-            j += 1;
+    /// Returns every live entry that was not returned yet
+    pub(crate) async fn collect(mut self) -> Result<Vec<Entry<Key, ValOffset>>, Error> {
+        let mut entries = Vec::new();
+        while let Some(entry) = self.next().await? {
+            entries.push(entry);
         }
-        self.entries = merged_indexes;
+        Ok(entries)
     }
 
-    fn head_entry_checker(&self, entry: &Entry<Key, ValOffset>, merged_indexes: &mut Vec<Entry<Key, ValOffset>>) {
-        if entry.key != HEAD_ENTRY_KEY {
-            merged_indexes.push(entry.to_owned());
+    async fn merge_next_round(&mut self) -> Result<(), Error> {
+        // SSTables are read concurrently, bounded by a semaphore
+        let semaphore = Semaphore::new(self.max_parallel_reads);
+        let mut reads = Vec::with_capacity(self.sstables.len());
+        for sst in self.sstables.iter_mut() {
+            let semaphore = &semaphore;
+            reads.push(async move {
+                let _permit = semaphore.acquire().await.ok();
+                sst.fill().await
+            });
+        }
+        try_join_all(reads)
+            .await
+            .map_err(|err| Error::RangeScanError(Box::new(err)))?;
+        // No SSTable holds an entry up to `bound` that it did not read yet
+        let bound = self.sstables.iter().filter_map(|sst| sst.read_until()).min().cloned();
+        let mut memtables = Vec::new();
+        while let Some(entry) = self
+            .memtables
+            .next_if(|e| bound.as_ref().is_none_or(|bound| e.key <= *bound))
+        {
+            memtables.push(entry);
+        }
+        let mut sources: Vec<EntryIterator<'static>> = vec![Box::new(memtables.into_iter())];
+        for sst in self.sstables.iter_mut() {
+            let options = self.options.to_owned();
+            sources.push(Box::new(
+                sst.take_until(bound.as_ref())
+                    .into_iter()
+                    .filter(move |e| options.is_visible(e.created_at, e.written_at)),
+            ));
         }
+        let range_tombstones = &self.range_tombstones;
+        let options = &self.options;
+        self.merged = MergeIterator::new(sources)
+            .skip_tombstones()
+            .filter(|e| e.key != HEAD_ENTRY_KEY && e.key != TAIL_ENTRY_KEY)
+            .filter(|e| !range_tombstones.covers(&e.key, e.created_at, options))
+            .collect();
+        self.is_done = bound.is_none();
+        Ok(())
+    }
+}
+
+/// A page of entries returned by `DataStore::scan_page`
+#[derive(Debug, Clone)]
+pub struct ScanPage {
+    pub entries: Vec<FetchedEntry>,
+
+    /// Token to fetch the next page, `None` once the end of the keyspace is reached
+    pub next: Option<ContinuationToken>,
+}
+
+/// Opaque position in the keyspace from which a paginated scan starts
+///
+/// It can be serialized with `to_string` and parsed back with `str::parse` to travel across requests
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ContinuationToken(Key);
+
+impl ContinuationToken {
+    /// Starts the scan at the smallest key in the store
+    pub fn start() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Starts the scan at `key` (inclusive), it can hold arbitrary bytes (`&str`, `String`, `&[u8]` or `Vec<u8>`)
+    pub fn from_key(key: impl AsRef<[u8]>) -> Self {
+        Self(key.as_ref().to_vec())
+    }
+
+    // The smallest key strictly bigger than `key` is `key` followed by a zero byte
+    fn after(key: &[u8]) -> Self {
+        let mut next_key = key.to_vec();
+        next_key.push(0);
+        Self(next_key)
+    }
+}
+
+impl std::fmt::Display for ContinuationToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for ContinuationToken {
+    type Err = Error;
+
+    fn from_str(token: &str) -> Result<Self, Error> {
+        if !token.len().is_multiple_of(2) || !token.is_ascii() {
+            return Err(Error::InvalidContinuationToken(token.to_owned()));
+        }
+        (0..token.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&token[i..i + 2], 16))
+            .collect::<Result<Key, _>>()
+            .map(ContinuationToken)
+            .map_err(|_| Error::InvalidContinuationToken(token.to_owned()))
+    }
+}
+
+/// Returns the entries of `entries` whose key falls within `range` in sorted order
+pub(crate) fn entries_within<R: RangeBounds<Key>>(
+    entries: &SkipMap<Key, SkipMapValue<ValOffset>>,
+    range: &R,
+) -> Vec<Entry<Key, ValOffset>> {
+    entries
+        .range::<Key, _>((range.start_bound(), range.end_bound()))
+        .map(|e| {
+            Entry::new(
+                e.key().to_vec(),
+                e.value().val_offset,
+                e.value().created_at,
+                e.value().is_tombstone,
+            )
+            .with_expiry(e.value().expires_at)
+            .with_written_at(e.value().written_at)
+            .with_inline(e.value().inline.to_owned())
+        })
+        .collect()
+}
+
+/// Key and metadata of a live entry returned by `KeyIterator`
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyEntry {
+    pub key: Key,
+    pub created_at: CreationTime,
+}
+
+/// Streams live keys in sorted order without reading values from the value log
+pub struct KeyIterator {
+    keys: BoxStream<'static, Result<KeyEntry, Error>>,
+}
+
+impl Stream for KeyIterator {
+    type Item = Result<KeyEntry, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.keys.as_mut().poll_next(cx)
     }
 }
 
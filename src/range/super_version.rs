@@ -0,0 +1,51 @@
+//! # Super version
+//!
+//! A super version is a consistent view of the read-only memtables and SSTables at a point in time.
+//! Read-only memtables and key ranges are captured while both locks are held, so a concurrent flush that
+//! moves a memtable into an SSTable is either not visible yet or fully visible, and compaction always adds
+//! merged SSTables before removing the obsolete ones. SSTables in the view are pinned so their files
+//! outlive compaction until the super version is dropped.
+
+use crate::key_range::Range;
+use crate::memtable::MemTable;
+use crate::sst::PinGuard;
+use crate::storage::DataStore;
+use crate::types::Key;
+use std::ops::RangeBounds;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+#[derive(Debug)]
+pub struct SuperVersion {
+    /// Read-only memtables, in insertion order
+    pub(crate) memtables: Vec<Arc<RwLock<MemTable<Key>>>>,
+
+    /// Key ranges of the SSTables
+    pub(crate) ranges: Vec<Range>,
+
+    _pins: PinGuard,
+}
+
+impl SuperVersion {
+    /// Returns the key ranges of the SSTables with at least one key within `bounds`
+    pub(crate) fn ranges_overlapping<R: RangeBounds<Key>>(&self, bounds: &R) -> Vec<&Range> {
+        self.ranges.iter().filter(|range| range.overlaps(bounds)).collect()
+    }
+}
+
+impl<'a> DataStore<'a, Key> {
+    /// Captures the read-only memtables and SSTables currently visible and pins them
+    pub(crate) async fn super_version(&self) -> SuperVersion {
+        // Acquired first as the flusher holds the bucket map while updating key ranges
+        let pins = self.buckets.read().await.pins.clone();
+        let read_only_memtables = self.read_only_memtables.read().await;
+        let key_range = self.key_range.read().await;
+        let ranges: Vec<Range> = key_range.key_ranges.values().cloned().collect();
+        let pin_guard = pins.pin(ranges.iter().map(|r| &r.sst));
+        SuperVersion {
+            memtables: read_only_memtables.values().cloned().collect(),
+            ranges,
+            _pins: pin_guard,
+        }
+    }
+}
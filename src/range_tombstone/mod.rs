@@ -0,0 +1,3 @@
+mod tombstones;
+pub use tombstones::RangeTombstone;
+pub use tombstones::RangeTombstones;
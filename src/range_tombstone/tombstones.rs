@@ -0,0 +1,200 @@
+//! # Range tombstones
+//!
+//! `delete_range` records a single range tombstone instead of one tombstone per key. A range tombstone hides
+//! every version of the keys in `[start, end)` created at or before its sequence number: reads and scans skip the
+//! versions it covers and compaction drops them so that garbage collection can reclaim their value log space.
+//!
+//! Range tombstones are appended to a file in the meta directory and synced before `delete_range` returns, they
+//! are removed once they outlive the tombstone TTL like regular tombstones. The length of the start key has
+//! `WRITTEN_AT_BIT` set when the wall-clock time the tombstone was written at follows its sequence number, the
+//! sequence number of tombstones written before it was recorded was a wall-clock time and stands in for it.
+
+use crate::cfg::ReadOptions;
+use crate::consts::{HEAD_ENTRY_KEY, SIZE_OF_U32, SIZE_OF_U64, TAIL_ENTRY_KEY};
+use crate::err::Error;
+use crate::err::Error::*;
+use crate::types::{CreationTime, Key, WriteTime};
+use chrono::Utc;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+// Set on the length of the start key when the write time follows the sequence number
+const WRITTEN_AT_BIT: u32 = 1 << 31;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeTombstone {
+    pub start: Key,
+
+    /// Exclusive end of the deleted range
+    pub end: Key,
+
+    pub created_at: CreationTime,
+
+    /// Wall-clock time the tombstone was written at, `created_at` is its sequence number
+    pub written_at: WriteTime,
+}
+
+impl RangeTombstone {
+    pub fn new(start: Key, end: Key, created_at: CreationTime, written_at: WriteTime) -> Self {
+        Self {
+            start,
+            end,
+            created_at,
+            written_at,
+        }
+    }
+
+    /// Returns true if the version of `key` created at `created_at` is deleted by this tombstone
+    pub fn covers(&self, key: &[u8], created_at: CreationTime) -> bool {
+        self.start.as_slice() <= key && key < self.end.as_slice() && created_at <= self.created_at
+    }
+
+    pub(crate) fn has_expired(&self, ttl: u64) -> bool {
+        let current_timestamp = Utc::now().timestamp_millis() as u64;
+        current_timestamp > (self.written_at + ttl)
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut entry = Vec::with_capacity(SIZE_OF_U32 * 2 + self.start.len() + self.end.len() + SIZE_OF_U64 * 2);
+        entry.extend_from_slice(&(self.start.len() as u32 | WRITTEN_AT_BIT).to_le_bytes());
+        entry.extend_from_slice(&self.start);
+        entry.extend_from_slice(&(self.end.len() as u32).to_le_bytes());
+        entry.extend_from_slice(&self.end);
+        entry.extend_from_slice(&self.created_at.to_le_bytes());
+        entry.extend_from_slice(&self.written_at.to_le_bytes());
+        entry
+    }
+
+    // Parses the tombstone at the start of `buf` and returns it with the number of bytes read,
+    // `None` if `buf` ends before the tombstone does
+    fn deserialize(buf: &[u8]) -> Option<(Self, usize)> {
+        let mut read = 0;
+        let mut next = |len: usize| -> Option<&[u8]> {
+            let bytes = buf.get(read..read + len)?;
+            read += len;
+            Some(bytes)
+        };
+        let start_len = u32::from_le_bytes(next(SIZE_OF_U32)?.try_into().ok()?);
+        let start = next((start_len & !WRITTEN_AT_BIT) as usize)?.to_vec();
+        let end_len = u32::from_le_bytes(next(SIZE_OF_U32)?.try_into().ok()?) as usize;
+        let end = next(end_len)?.to_vec();
+        let created_at = u64::from_le_bytes(next(SIZE_OF_U64)?.try_into().ok()?);
+        let written_at = match start_len & WRITTEN_AT_BIT {
+            0 => created_at,
+            _ => u64::from_le_bytes(next(SIZE_OF_U64)?.try_into().ok()?),
+        };
+        Some((Self::new(start, end, created_at, written_at), read))
+    }
+}
+
+/// Range tombstones of the store, persisted in `path`
+#[derive(Debug, Clone)]
+pub struct RangeTombstones {
+    path: PathBuf,
+    tombstones: Vec<RangeTombstone>,
+}
+
+impl RangeTombstones {
+    /// Loads the range tombstones stored in `path`, the file is created on the first insertion
+    pub async fn open(path: PathBuf) -> Result<Self, Error> {
+        let mut tombstones = Vec::new();
+        if path.exists() {
+            let buf = fs::read(&path).await.map_err(|error| FileReadError {
+                path: path.to_owned(),
+                error,
+            })?;
+            let mut offset = 0;
+            while offset < buf.len() {
+                match RangeTombstone::deserialize(&buf[offset..]) {
+                    Some((tombstone, read)) => {
+                        tombstones.push(tombstone);
+                        offset += read;
+                    }
+                    None => {
+                        // A write interrupted by a crash, `delete_range` never returned for this tombstone
+                        log::warn!("Ignoring incomplete range tombstone at the end of {:?}", path);
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(Self { path, tombstones })
+    }
+
+    /// Persists `tombstone` and makes it visible to reads
+    pub async fn insert(&mut self, tombstone: RangeTombstone) -> Result<(), Error> {
+        Self::append(&self.path, &tombstone.serialize()).await?;
+        self.tombstones.push(tombstone);
+        Ok(())
+    }
+
+    /// Returns true if the version of `key` created at `created_at` is deleted by a range tombstone,
+    /// tombstones not visible to `options` are ignored
+    ///
+    /// Internal head and tail entries are never covered since recovery depends on them
+    pub fn covers(&self, key: &[u8], created_at: CreationTime, options: &ReadOptions) -> bool {
+        if key == HEAD_ENTRY_KEY || key == TAIL_ENTRY_KEY {
+            return false;
+        }
+        self.tombstones
+            .iter()
+            .filter(|t| options.is_visible(t.created_at, t.written_at))
+            .any(|t| t.covers(key, created_at))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tombstones.is_empty()
+    }
+
+    /// Removes the range tombstones older than `ttl`
+    pub async fn remove_expired(&mut self, ttl: u64) -> Result<(), Error> {
+        if !self.tombstones.iter().any(|t| t.has_expired(ttl)) {
+            return Ok(());
+        }
+        let tombstones: Vec<RangeTombstone> = self
+            .tombstones
+            .iter()
+            .filter(|t| !t.has_expired(ttl))
+            .cloned()
+            .collect();
+        let buf: Vec<u8> = tombstones.iter().flat_map(|t| t.serialize()).collect();
+        // Written to a temporary file first so that a crash never leaves a partially written file
+        let tmp_path = self.path.with_extension("tmp");
+        if tmp_path.exists() {
+            fs::remove_file(&tmp_path).await.map_err(FileDeleteError)?;
+        }
+        Self::append(&tmp_path, &buf).await?;
+        fs::rename(&tmp_path, &self.path)
+            .await
+            .map_err(|error| FileWriteError {
+                path: self.path.to_owned(),
+                error,
+            })?;
+        self.tombstones = tombstones;
+        Ok(())
+    }
+
+    async fn append(path: &Path, buf: &[u8]) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await.map_err(|error| DirCreationError {
+                path: dir.to_path_buf(),
+                error,
+            })?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|error| FileOpenError {
+                path: path.to_path_buf(),
+                error,
+            })?;
+        file.write_all(buf).await.map_err(|error| FileWriteError {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        file.sync_all().await.map_err(|error| FileSyncError { error })
+    }
+}
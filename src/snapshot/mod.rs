@@ -0,0 +1,4 @@
+mod snapshots;
+pub use snapshots::ReadHorizon;
+pub use snapshots::Snapshot;
+pub use snapshots::Snapshots;
@@ -0,0 +1,204 @@
+//! # Snapshots
+//!
+//! A snapshot pins the state of the store at a sequence number, reads made with its `ReadOptions` ignore every
+//! write made after it was taken.
+//!
+//! While a snapshot is alive compaction keeps the versions it can read along with the latest ones, and garbage
+//! collection does not reclaim value log space it could read. The version retention window extends the same
+//! protection to every point in time within the window.
+//!
+//! Versions are ordered by their sequence number while SSTables and the retention window are stamped with the
+//! wall clock, so a snapshot records both its sequence number and the time it was taken at.
+
+use crate::cfg::ReadOptions;
+use crate::types::{CreationTime, WriteTime};
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// Registry of the live snapshots, shared by the store, the compactor and the garbage collector
+///
+/// Uses a blocking mutex so that snapshots can be released from `Drop`
+#[derive(Debug, Clone, Default)]
+pub struct Snapshots {
+    /// Time the first live snapshot handle pinned at each sequence number was taken at and the number of handles
+    pinned: Arc<Mutex<BTreeMap<CreationTime, (WriteTime, usize)>>>,
+}
+
+/// Oldest point reads can still be made at, see `Snapshots::oldest_readable`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadHorizon {
+    /// Sequence number of the oldest live snapshot, `None` if no snapshot is alive
+    pub sequence: Option<CreationTime>,
+
+    /// Wall-clock time the oldest live snapshot was taken at or the start of the retention window, the oldest
+    pub time: WriteTime,
+}
+
+impl ReadHorizon {
+    /// Returns read options seeing what every read still possible sees, e.g. the range tombstones that can be
+    /// applied for good
+    pub fn read_options(&self) -> ReadOptions {
+        ReadOptions {
+            snapshot: self.sequence,
+            as_of: Some(self.time),
+            ..ReadOptions::default()
+        }
+    }
+
+    /// Returns true if a version replaced by the one created at `created_at` and written at `written_at` can still
+    /// be read
+    pub fn reads_replaced(&self, created_at: CreationTime, written_at: WriteTime) -> bool {
+        self.sequence.is_some_and(|sequence| created_at > sequence) || written_at > self.time
+    }
+}
+
+impl Snapshots {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pins a new snapshot at `sequence`, taken now
+    pub(crate) fn pin(&self, sequence: CreationTime) -> Snapshot {
+        self.pin_taken_at(sequence, Utc::now().timestamp_millis() as u64)
+    }
+
+    fn pin_taken_at(&self, sequence: CreationTime, taken_at: WriteTime) -> Snapshot {
+        let mut pinned = self.pinned.lock().unwrap();
+        let (first_taken_at, count) = pinned.entry(sequence).or_insert((taken_at, 0));
+        *count += 1;
+        Snapshot {
+            sequence,
+            taken_at: *first_taken_at,
+            snapshots: self.clone(),
+        }
+    }
+
+    fn unpin(&self, sequence: CreationTime) {
+        let mut pinned = self.pinned.lock().unwrap();
+        if let Some((_, count)) = pinned.get_mut(&sequence) {
+            *count -= 1;
+            if *count == 0 {
+                pinned.remove(&sequence);
+            }
+        }
+    }
+
+    /// Returns the sequence number of the oldest live snapshot
+    pub fn oldest(&self) -> Option<CreationTime> {
+        self.pinned.lock().unwrap().keys().next().copied()
+    }
+
+    /// Returns the oldest point reads can still be made at, the oldest live snapshot or the start of the
+    /// `retention` window (in milliseconds), `None` if only the latest versions are read
+    pub fn oldest_readable(&self, retention: u64) -> Option<ReadHorizon> {
+        let window_start = (retention > 0).then(|| (Utc::now().timestamp_millis() as u64).saturating_sub(retention));
+        let pinned = self.pinned.lock().unwrap();
+        // The wall clock can go back, the snapshot taken first is not always the oldest pinned
+        let taken_at = pinned.values().map(|(taken_at, _)| *taken_at).min();
+        let time = match (taken_at, window_start) {
+            (Some(taken_at), Some(window_start)) => taken_at.min(window_start),
+            (taken_at, window_start) => taken_at.or(window_start)?,
+        };
+        Some(ReadHorizon {
+            sequence: pinned.keys().next().copied(),
+            time,
+        })
+    }
+
+    /// Returns true if no snapshot is alive
+    pub fn is_empty(&self) -> bool {
+        self.pinned.lock().unwrap().is_empty()
+    }
+}
+
+/// Handle to a consistent view of the store, returned by `DataStore::snapshot`
+///
+/// The snapshot is released when the handle and all its clones are dropped.
+#[derive(Debug)]
+pub struct Snapshot {
+    sequence: CreationTime,
+    taken_at: WriteTime,
+    snapshots: Snapshots,
+}
+
+impl Snapshot {
+    /// Sequence number the snapshot is pinned to, versions created after it are not visible
+    pub fn sequence(&self) -> CreationTime {
+        self.sequence
+    }
+
+    /// Wall-clock time the snapshot was taken at
+    pub fn taken_at(&self) -> WriteTime {
+        self.taken_at
+    }
+
+    /// Returns read options for `get_with_options`, `multi_get_with_options` and `range_with_options`
+    /// that read the store as of this snapshot
+    pub fn read_options(&self) -> ReadOptions {
+        ReadOptions {
+            snapshot: Some(self.sequence),
+            ..ReadOptions::default()
+        }
+    }
+}
+
+impl Clone for Snapshot {
+    fn clone(&self) -> Self {
+        self.snapshots.pin_taken_at(self.sequence, self.taken_at)
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.snapshots.unpin(self.sequence);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pin_and_release() {
+        let snapshots = Snapshots::new();
+        assert!(snapshots.oldest().is_none());
+        let first = snapshots.pin(10);
+        let second = snapshots.pin(20);
+        let copy = first.clone();
+        assert_eq!(snapshots.oldest(), Some(10));
+        assert_eq!(copy.read_options().snapshot, Some(10));
+
+        drop(first);
+        assert_eq!(snapshots.oldest(), Some(10));
+        drop(copy);
+        assert_eq!(snapshots.oldest(), Some(20));
+        drop(second);
+        assert!(snapshots.is_empty());
+    }
+
+    #[test]
+    fn test_oldest_readable() {
+        let snapshots = Snapshots::new();
+        assert!(snapshots.oldest_readable(0).is_none());
+        let window = snapshots.oldest_readable(60000).unwrap();
+        assert!(window.sequence.is_none());
+        assert!(window.time <= Utc::now().timestamp_millis() as u64 - 60000);
+        assert!(window.reads_replaced(10, Utc::now().timestamp_millis() as u64));
+        assert!(!window.reads_replaced(10, window.time));
+
+        let snapshot = snapshots.pin(10);
+        let horizon = snapshots.oldest_readable(0).unwrap();
+        assert_eq!(horizon.sequence, Some(10));
+        assert_eq!(horizon.time, snapshot.taken_at());
+        assert!(horizon.reads_replaced(11, snapshot.taken_at()));
+        assert!(!horizon.reads_replaced(10, snapshot.taken_at()));
+        assert_eq!(horizon.read_options().snapshot, Some(10));
+
+        // The window starts before the snapshot was taken
+        let horizon = snapshots.oldest_readable(60000).unwrap();
+        assert_eq!(horizon.sequence, Some(10));
+        assert!(horizon.time < snapshot.taken_at());
+        drop(snapshot);
+    }
+}
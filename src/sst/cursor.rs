@@ -0,0 +1,106 @@
+//! # Block cursor
+//!
+//! Reads the entries of an SSTable within a range one block at a time. A scan that stops early, e.g. once a page is
+//! full, only reads the blocks holding the keys it returned instead of every block up to the end of the range.
+
+use crate::block::BlockCache;
+use crate::err::Error;
+use crate::index::Index;
+use crate::memtable::Entry;
+use crate::sst::Table;
+use crate::types::{Key, ValOffset};
+use std::collections::VecDeque;
+use std::ops::{Bound, RangeBounds};
+
+#[derive(Debug)]
+pub(crate) struct BlockCursor {
+    table: Table,
+
+    bounds: (Bound<Key>, Bound<Key>),
+
+    /// Offsets of the blocks within the range that were not read yet, in key order
+    offsets: VecDeque<u32>,
+
+    /// Entries within the range that were read but not taken yet, in key order
+    entries: VecDeque<Entry<Key, ValOffset>>,
+
+    verify_checksums: bool,
+
+    cache: Option<BlockCache>,
+}
+
+impl BlockCursor {
+    /// Creates a cursor over the entries of `table` within `bounds`, only the index is read
+    ///
+    /// Blocks are looked up in `cache` first and cached once read from the file.
+    pub(crate) async fn new(
+        table: Table,
+        bounds: (Bound<Key>, Bound<Key>),
+        verify_checksums: bool,
+        cache: Option<BlockCache>,
+    ) -> Result<Self, Error> {
+        let index = Index::new(table.index_file.path.to_owned(), table.index_file.file.to_owned());
+        let offsets = index.block_offsets_within(&bounds).await?;
+        Ok(Self {
+            table,
+            bounds,
+            offsets: offsets.into(),
+            entries: VecDeque::new(),
+            verify_checksums,
+            cache,
+        })
+    }
+
+    /// Reads blocks until an entry within the range is buffered or every block was read
+    pub(crate) async fn fill(&mut self) -> Result<(), Error> {
+        while self.entries.is_empty() {
+            let Some(offset) = self.offsets.pop_front() else {
+                return Ok(());
+            };
+            let blocks = self
+                .table
+                .data_file
+                .file
+                .load_blocks(&[offset], self.verify_checksums, self.cache.as_ref())
+                .await?;
+            let bounds = &self.bounds;
+            self.entries.extend(
+                blocks
+                    .iter()
+                    .flat_map(|block| block.iter())
+                    .filter(|entry| bounds.contains(&entry.key))
+                    .map(|entry| {
+                        Entry::new(
+                            entry.key.to_owned(),
+                            entry.location(),
+                            entry.creation_date,
+                            entry.is_tombstone,
+                        )
+                        .with_written_at(entry.written_at)
+                        .with_expiry(entry.expires_at)
+                        .with_inline(entry.inline.to_owned())
+                    }),
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the last key read, every entry of the SSTable up to it is buffered or was taken
+    ///
+    /// `None` once every block within the range was read, no entry is left to read.
+    pub(crate) fn read_until(&self) -> Option<&Key> {
+        if self.offsets.is_empty() {
+            return None;
+        }
+        self.entries.back().map(|entry| &entry.key)
+    }
+
+    /// Takes the buffered entries up to and including `bound`, every buffered entry if `bound` is `None`
+    pub(crate) fn take_until(&mut self, bound: Option<&Key>) -> Vec<Entry<Key, ValOffset>> {
+        let len = match bound {
+            Some(bound) => self.entries.partition_point(|entry| entry.key <= *bound),
+            None => self.entries.len(),
+        };
+        self.entries.drain(..len).collect()
+    }
+}
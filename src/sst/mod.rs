@@ -1,3 +1,10 @@
+mod cursor;
+mod pins;
+mod properties;
 mod table;
+pub(crate) use cursor::BlockCursor;
+pub(crate) use pins::PinGuard;
+pub(crate) use pins::TablePins;
+pub use properties::TableProperties;
 pub(crate) use table::DataFile;
 pub(crate) use table::Table;
@@ -0,0 +1,100 @@
+//! # SSTable pins
+//!
+//! Readers pin the SSTables they are about to read so that compaction does not delete their files underneath them.
+//! Directories that become obsolete while one of their SSTables is pinned are only removed once the last pin is released.
+
+use crate::err::Error;
+use crate::sst::Table;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Default)]
+pub struct TablePins {
+    inner: Arc<Mutex<PinnedDirs>>,
+}
+
+#[derive(Debug, Default)]
+struct PinnedDirs {
+    /// Number of readers holding each SSTable directory
+    counts: HashMap<PathBuf, usize>,
+
+    /// Directories to remove once none of the SSTables they contain is pinned
+    obsolete: Vec<PathBuf>,
+}
+
+/// Keeps SSTables pinned until dropped
+#[derive(Debug)]
+pub struct PinGuard {
+    pins: TablePins,
+    dirs: Vec<PathBuf>,
+}
+
+impl TablePins {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pins `tables` until the returned guard is dropped
+    pub fn pin<'a>(&self, tables: impl IntoIterator<Item = &'a Table>) -> PinGuard {
+        let dirs: Vec<PathBuf> = tables.into_iter().map(|t| t.dir.to_owned()).collect();
+        let mut inner = self.inner.lock().expect("Failed to lock pinned tables");
+        for dir in dirs.iter() {
+            *inner.counts.entry(dir.to_owned()).or_insert(0) += 1;
+        }
+        PinGuard {
+            pins: self.clone(),
+            dirs,
+        }
+    }
+
+    /// Removes `dir` now, or once the SSTables it contains are no longer pinned
+    ///
+    /// Returns true if the directory was removed immediately
+    pub(crate) async fn remove_dir_all(&self, dir: &Path) -> Result<bool, Error> {
+        {
+            let mut inner = self.inner.lock().expect("Failed to lock pinned tables");
+            if inner.is_pinned(dir) {
+                inner.obsolete.push(dir.to_path_buf());
+                return Ok(false);
+            }
+        }
+        tokio::fs::remove_dir_all(dir).await.map_err(Error::DirDeleteError)?;
+        Ok(true)
+    }
+}
+
+impl PinnedDirs {
+    fn is_pinned(&self, dir: &Path) -> bool {
+        self.counts.keys().any(|pinned| pinned.starts_with(dir))
+    }
+}
+
+impl Drop for PinGuard {
+    fn drop(&mut self) {
+        let mut inner = match self.pins.inner.lock() {
+            Ok(inner) => inner,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        for dir in self.dirs.iter() {
+            if let Some(count) = inner.counts.get_mut(dir) {
+                *count -= 1;
+                if *count == 0 {
+                    inner.counts.remove(dir);
+                }
+            }
+        }
+        let obsolete = std::mem::take(&mut inner.obsolete);
+        for dir in obsolete {
+            if inner.is_pinned(&dir) {
+                inner.obsolete.push(dir);
+                continue;
+            }
+            if dir.exists() {
+                if let Err(err) = std::fs::remove_dir_all(&dir) {
+                    log::error!("{}", Error::DirDeleteError(err));
+                }
+            }
+        }
+    }
+}
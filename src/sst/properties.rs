@@ -0,0 +1,53 @@
+//! # SSTable properties
+//!
+//! Counts of the entries of an SSTable, taken from its entries when it is written and when the store is opened.
+//! Compaction uses them to find the SSTables holding the most dead entries: tombstones and entries whose TTL
+//! ran out. They are not persisted, opening the store reads every entry anyway to rebuild the bloom filters.
+
+use crate::memtable::is_expired;
+use crate::types::{ExpiresAt, Key, SkipMapEntries};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TableProperties {
+    /// Number of entries
+    pub entries: usize,
+
+    /// Number of tombstones
+    pub tombstones: usize,
+
+    /// Number of entries whose TTL had run out when they were counted
+    pub expired: usize,
+
+    /// Number of entries written with a TTL that had not run out when they were counted
+    pub expiring: usize,
+
+    /// Time the last of the `expiring` entries expires at
+    pub expires_by: ExpiresAt,
+}
+
+impl TableProperties {
+    pub(crate) fn from_entries(entries: &SkipMapEntries<Key>) -> Self {
+        let mut properties = Self {
+            entries: entries.len(),
+            ..Default::default()
+        };
+        for entry in entries.iter() {
+            let value = entry.value();
+            if value.is_tombstone {
+                properties.tombstones += 1;
+            } else if is_expired(value.expires_at) {
+                properties.expired += 1;
+            } else if value.expires_at.is_some() {
+                properties.expiring += 1;
+                properties.expires_by = properties.expires_by.max(value.expires_at);
+            }
+        }
+        properties
+    }
+
+    /// Returns the number of dead entries, entries written with a TTL are only counted once all of them expired
+    pub fn dead_entries(&self) -> usize {
+        let expiring = if is_expired(self.expires_by) { self.expiring } else { 0 };
+        self.tombstones + self.expired + expiring
+    }
+}
@@ -6,17 +6,21 @@ use std::{
     sync::Arc,
     time::SystemTime,
 };
+use tokio::fs;
 
 use crate::{
-    block::Block,
+    block::{Block, BlockCache},
     bucket::InsertableToBucket,
-    consts::{SIZE_OF_U32, SIZE_OF_U64, SIZE_OF_U8, SIZE_OF_USIZE},
+    checksum::ChecksumType,
+    compression::CompressionType,
+    consts::{SIZE_OF_U64, SIZE_OF_U8, SIZE_OF_USIZE, TEMP_EXTENSION},
     err::Error,
     filter::BloomFilter,
-    fs::{DataFileNode, DataFs, FileAsync, FileNode, IndexFileNode, IndexFs},
-    index::{Index, IndexFile, RangeOffset},
+    fs::{sync_dir, DataFileNode, DataFs, FileAsync, FileNode, IndexFileNode, IndexFs},
+    index::{Index, IndexFile},
     memtable::{Entry, SkipMapValue},
-    types::{CreationTime, IsTombStone, Key, SkipMapEntries, ValOffset},
+    sst::TableProperties,
+    types::{CreationTime, FoundEntry, Key, SkipMapEntries, ValOffset},
 };
 
 use Error::*;
@@ -48,6 +52,9 @@ pub struct Table {
     pub(crate) data_file: DataFile<DataFileNode>,
     pub(crate) index_file: IndexFile<IndexFileNode>,
     pub(crate) entries: SkipMapEntries<Key>,
+
+    /// Counts of the entries, used by compaction to find the SSTables holding the most dead entries
+    pub(crate) properties: TableProperties,
 }
 
 impl InsertableToBucket for Table {
@@ -75,8 +82,9 @@ impl InsertableToBucket for Table {
 }
 
 impl Table {
-    pub async fn new(dir: PathBuf) -> Result<Table, Error> {
-        let (data_file_path, index_file_path, creation_time) = Table::generate_file_path(dir.to_owned()).await?;
+    pub async fn new(dir: PathBuf, file_number: u64) -> Result<Table, Error> {
+        let (data_file_path, index_file_path, creation_time) =
+            Table::generate_file_path(dir.to_owned(), file_number).await?;
         let data_file = DataFileNode::new(data_file_path.to_owned(), crate::fs::FileType::Data)
             .await
             .unwrap();
@@ -92,11 +100,55 @@ impl Table {
             created_at: creation_time.timestamp_millis() as u64,
             entries: Arc::new(SkipMap::new()),
             size: 0,
+            properties: TableProperties::default(),
         })
     }
+
+    /// Writes `entries` to a new SSTable stored in `dir`, its files are named after `file_number` and checksummed
+    /// with `checksum_type`, its blocks are compressed with `compression`
+    ///
+    /// The files are written to a temporary directory that is renamed to `dir` once they are synced, so a crash
+    /// never leaves a partially written SSTable in `dir`
+    pub(crate) async fn write_new(
+        dir: PathBuf,
+        file_number: u64,
+        entries: SkipMapEntries<Key>,
+        checksum_type: ChecksumType,
+        compression: CompressionType,
+    ) -> Result<Table, Error> {
+        let tmp_dir = dir.with_extension(TEMP_EXTENSION);
+        let mut sst = Table::new(tmp_dir.to_owned(), file_number).await?;
+        sst.properties = TableProperties::from_entries(&entries);
+        sst.set_entries(entries);
+        sst.write_to_file(checksum_type, compression).await?;
+        sst.data_file.file.node.sync_all().await?;
+        sst.index_file.file.node.sync_all().await?;
+        fs::rename(&tmp_dir, &dir).await.map_err(|error| FileWriteError {
+            path: dir.to_owned(),
+            error,
+        })?;
+        if let Some(bucket_dir) = dir.parent() {
+            sync_dir(bucket_dir).await?;
+        }
+        // Open file handles follow the rename, only the paths change
+        let data_file_path = dir.join(sst.data_file.path.file_name().unwrap_or_default());
+        let index_file_path = dir.join(sst.index_file.path.file_name().unwrap_or_default());
+        sst.data_file.file.node.file_path = data_file_path.to_owned();
+        sst.data_file.path = data_file_path;
+        sst.index_file.file.node.file_path = index_file_path.to_owned();
+        sst.index_file.path = index_file_path;
+        sst.dir = dir;
+        Ok(sst)
+    }
+
     pub fn increase_hotness(&mut self) {
         self.hotness += 1;
     }
+
+    /// Halves the hotness
+    pub(crate) fn decay_hotness(&mut self) {
+        self.hotness /= 2;
+    }
     pub fn get_data_file_path(&self) -> PathBuf {
         self.data_file.path.clone()
     }
@@ -105,28 +157,50 @@ impl Table {
         self.hotness
     }
 
-    pub async fn generate_file_path(dir: PathBuf) -> Result<(PathBuf, PathBuf, DateTime<Utc>), Error> {
+    pub async fn generate_file_path(
+        dir: PathBuf,
+        file_number: u64,
+    ) -> Result<(PathBuf, PathBuf, DateTime<Utc>), Error> {
         let created_at = Utc::now();
         let _ = FileNode::create_dir_all(dir.to_owned()).await?;
-        let data_file_name = format!("data_{}_.db", created_at.timestamp_millis());
-        let index_file_name = format!("index_{}_.db", created_at.timestamp_millis());
+        let data_file_name = format!("data_{:06}.db", file_number);
+        let index_file_name = format!("index_{:06}.db", file_number);
 
         let data_file_path = dir.join(data_file_name.to_owned());
         let index_file_path = dir.join(index_file_name.to_owned());
         Ok((data_file_path, index_file_path, created_at))
     }
 
+    /// Looks up `searched_key` in the block starting at `start_offset`, the checksum of the block is
+    /// verified if `verify_checksums` is set
+    ///
+    /// The block is looked up in `cache` first and cached once read from the file.
     pub(crate) async fn get(
         &self,
         start_offset: u32,
         searched_key: &[u8],
-    ) -> Result<Option<(ValOffset, CreationTime, IsTombStone)>, Error> {
-        self.data_file.file.find_entry(start_offset, searched_key).await
+        verify_checksums: bool,
+        cache: Option<&BlockCache>,
+    ) -> Result<Option<FoundEntry>, Error> {
+        self.data_file
+            .file
+            .find_entry(start_offset, searched_key, verify_checksums, cache)
+            .await
+    }
+
+    pub(crate) async fn load_entries_from_file(&self, verify_checksums: bool) -> Result<Table, Error> {
+        self.load_entries_cached(verify_checksums, None).await
     }
 
-    pub(crate) async fn load_entries_from_file(&self) -> Result<Table, Error> {
-        let (entries, bytes_read) = self.data_file.file.load_entries().await?;
+    /// Same as `load_entries_from_file`, the blocks are looked up in `cache` first and cached once read from the file
+    pub(crate) async fn load_entries_cached(
+        &self,
+        verify_checksums: bool,
+        cache: Option<&BlockCache>,
+    ) -> Result<Table, Error> {
+        let (entries, bytes_read) = self.data_file.file.load_entries(verify_checksums, cache).await?;
         Ok(Table {
+            properties: TableProperties::from_entries(&entries),
             entries,
             size: bytes_read,
             dir: self.dir.clone(),
@@ -156,6 +230,7 @@ impl Table {
             },
             size: 0,
             entries: Arc::new(SkipMap::new()),
+            properties: TableProperties::default(),
         };
         table.size = table.data_file.file.node.size().await;
         let modified_time = table.data_file.file.node.metadata().await.unwrap().modified().unwrap();
@@ -165,7 +240,13 @@ impl Table {
         return table;
     }
 
-    pub(crate) async fn write_to_file(&mut self) -> Result<(), Error> {
+    /// Writes the entries to the data and index files, both start with a header recording `checksum_type`, the
+    /// blocks of the data file are compressed with `compression`
+    pub(crate) async fn write_to_file(
+        &mut self,
+        checksum_type: ChecksumType,
+        compression: CompressionType,
+    ) -> Result<(), Error> {
         let index_file = &self.index_file;
         let mut blocks: Vec<Block> = Vec::new();
         let mut table_index = Index::new(self.index_file.path.clone(), index_file.file.clone());
@@ -173,54 +254,58 @@ impl Table {
         if self.size > 0 {
             self.reset_size();
         }
+        self.data_file.file.node.write_all(&[checksum_type.as_byte()]).await?;
+        self.size += SIZE_OF_U8;
         for e in self.entries.iter() {
             let entry = Entry::new(
                 e.key().clone(),
                 e.value().val_offset,
                 e.value().created_at,
                 e.value().is_tombstone,
-            );
-            // key len(variable) +  key length(used during fetch) + value length(4 bytes) + date in milliseconds(8 bytes)
-            let entry_size = entry.key.len() + SIZE_OF_U32 + SIZE_OF_U32 + SIZE_OF_U64 + SIZE_OF_U8;
+            )
+            .with_expiry(e.value().expires_at)
+            .with_written_at(e.value().written_at)
+            .with_inline(e.value().inline.to_owned());
+            let entry_size = Block::entry_size(entry.key.len(), entry.expires_at, entry.inline.as_ref());
             if current_block.is_full(entry_size) {
                 blocks.push(current_block);
                 current_block = Block::new();
             }
 
-            current_block.set_entry(
-                entry.key.len() as u32,
-                entry.key,
-                entry.val_offset as u32,
-                entry.created_at,
-                entry.is_tombstone,
-            )?;
+            current_block.set_entry(entry)?;
         }
 
         for block in blocks.iter() {
-            self.write_block(block, &mut table_index).await?;
+            self.write_block(block, &mut table_index, checksum_type, compression)
+                .await?;
         }
 
         // Incase we have some entries left in current block, write them to disk
         if current_block.entries.len() > 0 {
-            self.write_block(&current_block, &mut table_index).await?;
+            self.write_block(&current_block, &mut table_index, checksum_type, compression)
+                .await?;
         }
-        table_index.write_to_file().await?;
+        table_index.write_to_file(checksum_type).await?;
         Ok(())
     }
 
-    async fn write_block(&mut self, block: &Block, table_index: &mut Index) -> Result<(), Error> {
+    async fn write_block(
+        &mut self,
+        block: &Block,
+        table_index: &mut Index,
+        checksum_type: ChecksumType,
+        compression: CompressionType,
+    ) -> Result<(), Error> {
         let offset = self.size;
         let last_entry = block.get_last_entry();
         table_index.insert(last_entry.key_prefix, last_entry.key, offset as u32);
-        let bytes_written = block.write_to_file(self.data_file.file.node.clone()).await?;
+        let bytes_written = block
+            .write_to_file(self.data_file.file.node.clone(), checksum_type, compression)
+            .await?;
         self.size += bytes_written;
         Ok(())
     }
 
-    pub(crate) async fn range(&self, range_offset: RangeOffset) -> Result<Vec<Entry<Vec<u8>, usize>>, Error> {
-        self.data_file.file.load_entries_within_range(range_offset).await
-    }
-
     pub(crate) fn reset_size(&mut self) {
         self.size = 0;
     }
@@ -0,0 +1,106 @@
+//! # Background errors
+//!
+//! Flushes, compactions, garbage collection and interval syncs of the value log run in background tasks that
+//! have nobody to return their errors to. An I/O error hit by one of them means the store may no longer be able to
+//! persist what it is given, so it is recorded and later writes fail with `BackgroundError` instead of piling up
+//! in memory. Background tasks skip their work while an error is recorded.
+//!
+//! A write that cannot append to the value log because the disk is full halts the store the same way, the
+//! store stays readable rather than accepting writes it cannot persist. Every error that halts writes is sent to
+//! the receivers returned by `DataStore::subscribe_background_errors`.
+//!
+//! Once the cause is addressed, e.g. disk space was freed, `DataStore::resume` clears the error. Memtables whose
+//! flush failed are kept and flushed again with the next ones.
+
+use crate::consts::BACKGROUND_ERROR_CHANNEL_SIZE;
+use crate::err::Error;
+use crate::err::Error::*;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+/// Error that halted the writes of the store, shared by the store, the flusher, the compactor and the garbage
+/// collector
+#[derive(Debug, Clone)]
+pub struct BackgroundErrors {
+    last: Arc<Mutex<Option<Arc<Error>>>>,
+    tx: Sender<Arc<Error>>,
+}
+
+impl Default for BackgroundErrors {
+    fn default() -> Self {
+        let (tx, _) = broadcast::channel(BACKGROUND_ERROR_CHANNEL_SIZE);
+        Self {
+            last: Arc::new(Mutex::new(None)),
+            tx,
+        }
+    }
+}
+
+impl BackgroundErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a receiver of every error that halts writes
+    pub fn subscribe(&self) -> Receiver<Arc<Error>> {
+        self.tx.subscribe()
+    }
+
+    /// Records `err` if it was caused by an I/O error, errors recorded later do not replace the first one
+    pub(crate) fn record(&self, err: Error) {
+        if !Self::is_fatal(&err) {
+            return;
+        }
+        let mut last = self.last.lock().unwrap();
+        if last.is_none() {
+            log::error!("Halting writes after a background error: {}", err);
+            let err = Arc::new(err);
+            *last = Some(Arc::clone(&err));
+            // Fails only if nobody subscribed
+            let _ = self.tx.send(err);
+        }
+    }
+
+    /// Halts writes if `err`, returned by a write, was caused by the disk being full. Returns the error to
+    /// report to the caller of the write
+    pub(crate) fn halt_if_disk_full(&self, err: Error) -> Error {
+        if !err.is_disk_full() {
+            return err;
+        }
+        let message = err.to_string();
+        self.record(err);
+        BackgroundError(message)
+    }
+
+    /// Returns the recorded error, `None` if writes are not halted
+    pub fn get(&self) -> Option<Arc<Error>> {
+        self.last.lock().unwrap().clone()
+    }
+
+    /// Clears the recorded error and returns it
+    pub(crate) fn take(&self) -> Option<Arc<Error>> {
+        self.last.lock().unwrap().take()
+    }
+
+    // Returns true if `err` was caused by a failure to read or write the files of the store. Failing to remove a
+    // file loses nothing, it only leaves the file behind
+    fn is_fatal(err: &Error) -> bool {
+        match err {
+            FileSyncError { .. }
+            | FileCreationError { .. }
+            | FileSeekError(_)
+            | FileOpenError { .. }
+            | GetFileMetaDataError(_)
+            | DirCreationError { .. }
+            | FileReadError { .. }
+            | FileWriteError { .. }
+            | DirectoryOpenError { .. }
+            | GCErrorFailedToPunchHoleInVlogFile(_) => true,
+            FlushToDiskError { error } => Self::is_fatal(error),
+            FlushError(err) | CompactionFailed(err) | CompactionPartiallyFailed(err) | CompactionCleanupError(err) => {
+                Self::is_fatal(err)
+            }
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! # Integrity
+//!
+//! `verify_integrity` checks what is stored on disk rather than the in-memory read structures checked by `verify`.
+//!
+//! For every SSTable it:
+//! - verifies the checksum of the index file and of every block the index points to
+//! - checks that the blocks the index points to follow each other from the header to the end of the data file,
+//!   and that the key the index records for each block is the last key of the block
+//! - checks that the value offset of every entry is the start of a valid value log entry for the same key
+//!
+//! The value log is walked from its tail and the checksum of every entry is verified. Space before the tail
+//! has been reclaimed by garbage collection, entries still pointing there are stale versions and are only counted.
+//!
+//! Every problem found is recorded in the report, a corrupted block does not stop the walk of the next ones.
+
+use super::DataStore;
+use crate::consts::{HEAD_ENTRY_KEY, SIZE_OF_U8, TAIL_ENTRY_KEY};
+use crate::err::Error;
+use crate::fs::FileAsync;
+use crate::sst::Table;
+use crate::types::{Key, ValOffset};
+use crate::value_log::ValueClass;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Result of running `DataStore::verify_integrity`
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityReport {
+    /// Per SSTable integrity result
+    pub tables: Vec<TableIntegrity>,
+
+    pub value_log: ValueLogIntegrity,
+}
+
+/// Integrity result for a single SSTable
+#[derive(Debug, Clone)]
+pub struct TableIntegrity {
+    pub data_file_path: PathBuf,
+
+    pub index_file_path: PathBuf,
+
+    /// Number of blocks read from the data file
+    pub blocks_checked: usize,
+
+    /// Number of entries whose value offset was checked
+    pub entries_checked: usize,
+
+    /// Number of entries whose value offset lies before the value log tail
+    pub reclaimed_values: usize,
+
+    pub issues: Vec<IntegrityIssue>,
+}
+
+/// Integrity result for the value log
+#[derive(Debug, Clone, Default)]
+pub struct ValueLogIntegrity {
+    pub path: PathBuf,
+
+    /// Offset the walk started from, the tail of the value log
+    pub start_offset: usize,
+
+    /// Number of entries whose checksum was verified
+    pub entries_checked: usize,
+
+    pub issues: Vec<IntegrityIssue>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntegrityIssue {
+    /// Index file could not be read or its checksum does not match
+    UnreadableIndex(String),
+
+    /// Block the index points to could not be read or its checksum does not match
+    UnreadableBlock { offset: usize, error: String },
+
+    /// Block the index points to does not start where the previous block ends
+    MisplacedBlock { offset: usize, expected: usize },
+
+    /// Blocks the index points to end before or after the data file
+    DataFileLengthMismatch { blocks_end: usize, file_len: usize },
+
+    /// Key recorded in the index for a block differs from the last key of the block
+    IndexKeyMismatch { offset: usize, indexed: Key, actual: Key },
+
+    /// Value offset of the entry is not the start of a valid value log entry
+    DanglingValueOffset { key: Key, value_offset: usize },
+
+    /// Value offset of the entry is the start of a value log entry for another key
+    ValueKeyMismatch { key: Key, value_offset: usize, found: Key },
+
+    /// Value log entry that ended the walk of the value log, entries after it could not be checked
+    CorruptedValueLogEntry { offset: usize, error: String },
+}
+
+impl IntegrityReport {
+    /// Returns true if no issue was found in any SSTable nor in the value log
+    pub fn is_intact(&self) -> bool {
+        self.value_log.issues.is_empty() && self.tables.iter().all(|t| t.issues.is_empty())
+    }
+
+    /// Returns only reports of SSTables with at least one issue
+    pub fn damaged_tables(&self) -> Vec<&TableIntegrity> {
+        self.tables.iter().filter(|t| !t.issues.is_empty()).collect()
+    }
+}
+
+impl<'a> DataStore<'a, Key> {
+    /// Verifies the checksums and the consistency of the SSTables and of the value log
+    pub async fn verify_integrity(&self) -> Result<IntegrityReport, Error> {
+        // SSTables are listed before the value log is walked so every offset they hold was written before the walk
+        let tables = {
+            let buckets = self.buckets.read().await;
+            let mut tables = Vec::new();
+            for (_, bucket) in buckets.buckets.iter() {
+                tables.extend(bucket.sstables.read().await.iter().cloned());
+            }
+            tables
+        };
+        let tail_offset = self.val_log.tail_offset.max(self.gc_log.read().await.tail_offset);
+        let (entries, end) = self.val_log.verify(tail_offset).await?;
+        let mut report = IntegrityReport {
+            tables: Vec::new(),
+            value_log: ValueLogIntegrity {
+                path: self.val_log.content.path.to_owned(),
+                start_offset: tail_offset,
+                entries_checked: entries.len(),
+                issues: Vec::new(),
+            },
+        };
+        if let Some((offset, err)) = end {
+            report.value_log.issues.push(IntegrityIssue::CorruptedValueLogEntry {
+                offset,
+                error: err.to_string(),
+            });
+        }
+        let value_log_keys: HashMap<ValOffset, Key> = entries.into_iter().collect();
+        for table in tables.iter() {
+            report
+                .tables
+                .push(Self::verify_table_integrity(table, &value_log_keys, tail_offset).await?);
+        }
+        Ok(report)
+    }
+
+    pub(super) async fn verify_table_integrity(
+        table: &Table,
+        value_log_keys: &HashMap<ValOffset, Key>,
+        tail_offset: usize,
+    ) -> Result<TableIntegrity, Error> {
+        let mut table_report = TableIntegrity {
+            data_file_path: table.data_file.path.to_owned(),
+            index_file_path: table.index_file.path.to_owned(),
+            blocks_checked: 0,
+            entries_checked: 0,
+            reclaimed_values: 0,
+            issues: Vec::new(),
+        };
+        let index = match table.index_file.file.load_entries().await {
+            Ok(index) => index,
+            Err(err) => {
+                table_report
+                    .issues
+                    .push(IntegrityIssue::UnreadableIndex(err.to_string()));
+                return Ok(table_report);
+            }
+        };
+        let file_len = table.data_file.file.node.metadata().await?.len() as usize;
+        // Blocks follow the checksum type header, where a block ends is unknown once one could not be read
+        let mut expected_offset = Some(if file_len > 0 { SIZE_OF_U8 } else { 0 });
+        for (indexed_key, offset) in index {
+            let offset = offset as usize;
+            if let Some(expected) = expected_offset.filter(|expected| *expected != offset) {
+                table_report
+                    .issues
+                    .push(IntegrityIssue::MisplacedBlock { offset, expected });
+            }
+            expected_offset = None;
+            let block = match table.data_file.file.load_block_at(offset).await {
+                Ok(Some((block, block_len))) => {
+                    expected_offset = Some(offset + block_len);
+                    block
+                }
+                Ok(None) => {
+                    let error = format!("no block at offset {}", offset);
+                    table_report
+                        .issues
+                        .push(IntegrityIssue::UnreadableBlock { offset, error });
+                    continue;
+                }
+                Err(err) => {
+                    table_report.issues.push(IntegrityIssue::UnreadableBlock {
+                        offset,
+                        error: err.to_string(),
+                    });
+                    continue;
+                }
+            };
+            table_report.blocks_checked += 1;
+            if let Some(last_entry) = block.last() {
+                if last_entry.key != indexed_key {
+                    table_report.issues.push(IntegrityIssue::IndexKeyMismatch {
+                        offset,
+                        indexed: indexed_key,
+                        actual: last_entry.key.to_owned(),
+                    });
+                }
+            }
+            for entry in block {
+                // The head and tail entries hold offsets into the value log rather than the offset of a value
+                if entry.key == HEAD_ENTRY_KEY || entry.key == TAIL_ENTRY_KEY {
+                    continue;
+                }
+                table_report.entries_checked += 1;
+                // Only the main value log is walked, values stored in the log of their class are not checked.
+                // Garbage collection may have reclaimed the record of an inline value
+                if entry.class != ValueClass::Small || entry.inline.is_some() {
+                    continue;
+                }
+                let value_offset = entry.location();
+                if value_offset < tail_offset {
+                    table_report.reclaimed_values += 1;
+                    continue;
+                }
+                match value_log_keys.get(&value_offset) {
+                    Some(found) if *found == entry.key => {}
+                    Some(found) => table_report.issues.push(IntegrityIssue::ValueKeyMismatch {
+                        key: entry.key,
+                        value_offset,
+                        found: found.to_owned(),
+                    }),
+                    None => table_report.issues.push(IntegrityIssue::DanglingValueOffset {
+                        key: entry.key,
+                        value_offset,
+                    }),
+                }
+            }
+        }
+        if let Some(blocks_end) = expected_offset.filter(|blocks_end| *blocks_end != file_len) {
+            table_report
+                .issues
+                .push(IntegrityIssue::DataFileLengthMismatch { blocks_end, file_len });
+        }
+        Ok(table_report)
+    }
+}
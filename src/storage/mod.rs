@@ -1,4 +1,61 @@
+mod background;
+mod integrity;
+mod plan;
 mod recover;
+mod repair;
+mod sample;
 mod storage;
+mod verify;
+pub use crate::batch::BatchOp;
+pub use crate::batch::BatchProgress;
+pub use crate::batch::GroupCommit;
+pub use crate::batch::WriteBatch;
+pub use crate::block::BlockCache;
+pub use crate::cfg::ReadOptions;
+pub use crate::cfg::ReadTier;
+pub use crate::cfg::WriteOptions;
+pub use crate::changes::Change;
+pub use crate::changes::ChangeEvent;
+pub use crate::changes::ChangeIterator;
+pub use crate::checksum::ChecksumType;
+pub use crate::compactors::CompactionDecision;
+pub use crate::compactors::CompactionFilter;
+pub use crate::compression::CompressionType;
+pub use crate::events::EventListener;
+pub use crate::fs::FsCapabilities;
+pub use crate::gc::GCReport;
+pub use crate::gc::GCRun;
+pub use crate::gc::GCStrategy;
+pub use crate::gc::ValueLogStats;
+pub use crate::lock::KeyLockGuard;
+pub use crate::lock::KeyLocks;
+pub use crate::range::ContinuationToken;
+pub use crate::range::FetchedEntry;
+pub use crate::range::KeyEntry;
+pub use crate::range::KeyIterator;
+pub use crate::range::RangeIterator;
+pub use crate::range::ScanPage;
+pub use crate::snapshot::Snapshot;
+pub use crate::transaction::PreparedToken;
+pub use crate::transaction::Transaction;
+pub use crate::value_log::ValueLog;
+pub use crate::value_log::ValueLogEntry;
+pub use crate::value_log::ValueLogIter;
+pub use background::BackgroundErrors;
+pub use integrity::IntegrityIssue;
+pub use integrity::IntegrityReport;
+pub use integrity::TableIntegrity;
+pub use integrity::ValueLogIntegrity;
+pub use plan::BucketPlan;
+pub use plan::CompactionBlocker;
+pub use plan::CompactionPlan;
+pub use recover::QuarantinedTable;
+pub use recover::RecoveryProgress;
+pub use recover::RecoveryReport;
+pub use repair::RebuiltTable;
+pub use repair::RepairReport;
 pub use storage::DataStore;
 pub use storage::SizeUnit;
+pub use verify::Inconsistency;
+pub use verify::TableReport;
+pub use verify::VerifyReport;
@@ -0,0 +1,146 @@
+//! # Compaction plan
+//!
+//! `plan_compaction` picks the SSTables the next compaction would merge, the same way compaction does, without
+//! merging them. For every bucket to compact it reports the SSTables picked, their size and an estimate of the
+//! size of the merged SSTables. It also reports why compaction would not run at all: a background error halting
+//! writes, a strategy that is not supported or too little free disk space.
+//!
+//! The estimate takes out the dead entries of each SSTable, tombstones and expired entries, in proportion to its
+//! size. Versions of a key overwritten in another SSTable of the bucket are not known before the merge so the space
+//! reclaimed is usually higher than estimated, unless a snapshot or a read within the retention window can still
+//! read them. Such versions are kept by the merge, a bucket they would not let shrink is not merged at all. A
+//! compaction keeps merging until no bucket is left to compact, the plan only covers its first round.
+
+use super::DataStore;
+use crate::bucket::BucketID;
+use crate::compactors::Strategy;
+use crate::err::Error;
+use crate::fs::available_space;
+use crate::types::Key;
+use std::path::PathBuf;
+
+/// Result of `DataStore::plan_compaction`
+#[derive(Debug, Clone, Default)]
+pub struct CompactionPlan {
+    /// Buckets to compact, in the order compaction merges them
+    pub buckets: Vec<BucketPlan>,
+
+    /// Reason compaction would not run, `None` if it would merge `buckets`
+    pub blocker: Option<CompactionBlocker>,
+}
+
+/// SSTables of a bucket picked for compaction
+#[derive(Debug, Clone)]
+pub struct BucketPlan {
+    pub bucket_id: BucketID,
+
+    /// Data files of the SSTables to merge
+    pub sstables: Vec<PathBuf>,
+
+    /// Size of the SSTables to merge (in bytes)
+    pub input_size: usize,
+
+    /// Number of entries in the SSTables to merge
+    pub entries: usize,
+
+    /// Number of tombstones and expired entries in the SSTables to merge
+    pub dead_entries: usize,
+
+    /// Estimated size of the merged SSTables (in bytes)
+    pub estimated_output_size: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum CompactionBlocker {
+    /// Writes and background work are halted by this error until `DataStore::resume` is called
+    BackgroundError(String),
+
+    /// Only the size-tiered strategy compacts
+    UnsupportedStrategy(Strategy),
+
+    /// Free disk space is below `Config::reserved_disk_space`
+    NotEnoughDiskSpace { available: u64, reserved: u64 },
+}
+
+impl CompactionPlan {
+    /// Returns true if compaction would merge SSTables
+    pub fn will_compact(&self) -> bool {
+        self.blocker.is_none() && !self.buckets.is_empty()
+    }
+
+    /// Returns the size of the SSTables to merge (in bytes)
+    pub fn input_size(&self) -> usize {
+        self.buckets.iter().map(|bucket| bucket.input_size).sum()
+    }
+
+    /// Returns the estimated size of the merged SSTables (in bytes)
+    pub fn estimated_output_size(&self) -> usize {
+        self.buckets.iter().map(|bucket| bucket.estimated_output_size).sum()
+    }
+
+    /// Returns the estimated disk space freed by the compaction (in bytes)
+    pub fn estimated_reclaimed(&self) -> usize {
+        self.buckets.iter().map(|bucket| bucket.estimated_reclaimed()).sum()
+    }
+}
+
+impl BucketPlan {
+    /// Returns the estimated disk space freed by merging the SSTables of the bucket (in bytes)
+    pub fn estimated_reclaimed(&self) -> usize {
+        self.input_size.saturating_sub(self.estimated_output_size)
+    }
+}
+
+impl<'a> DataStore<'a, Key> {
+    /// Returns the SSTables the next compaction would merge and the space it would reclaim, nothing is merged
+    pub async fn plan_compaction(&self) -> Result<CompactionPlan, Error> {
+        let cfg = &self.compactor.config;
+        let buckets = self.buckets.read().await;
+        let (to_compact, _) = buckets.extract_imbalanced_buckets().await?;
+        let mut plan = CompactionPlan::default();
+        for bucket in to_compact.iter() {
+            let sstables = bucket.sstables.read().await;
+            let mut bucket_plan = BucketPlan {
+                bucket_id: bucket.id,
+                sstables: Vec::with_capacity(sstables.len()),
+                input_size: 0,
+                entries: 0,
+                dead_entries: 0,
+                estimated_output_size: 0,
+            };
+            for sst in sstables.iter() {
+                let entries = sst.properties.entries;
+                let dead_entries = sst.properties.dead_entries().min(entries);
+                bucket_plan.sstables.push(sst.get_data_file_path());
+                bucket_plan.input_size += sst.size;
+                bucket_plan.entries += entries;
+                bucket_plan.dead_entries += dead_entries;
+                bucket_plan.estimated_output_size += if entries == 0 {
+                    sst.size
+                } else {
+                    (sst.size as f64 * (entries - dead_entries) as f64 / entries as f64) as usize
+                };
+            }
+            plan.buckets.push(bucket_plan);
+        }
+
+        let available = match cfg.reserved_disk_space {
+            0 => None,
+            _ => Some(available_space(&buckets.dir)?),
+        };
+        // Checked in the order compaction checks them
+        plan.blocker = if let Some(err) = self.background_errors.get() {
+            Some(CompactionBlocker::BackgroundError(err.to_string()))
+        } else if !matches!(cfg.strategy, Strategy::STCS) {
+            Some(CompactionBlocker::UnsupportedStrategy(cfg.strategy))
+        } else {
+            available
+                .filter(|available| *available < cfg.reserved_disk_space)
+                .map(|available| CompactionBlocker::NotEnoughDiskSpace {
+                    available,
+                    reserved: cfg.reserved_disk_space,
+                })
+        };
+        Ok(plan)
+    }
+}
@@ -1,135 +1,186 @@
-use std::path::PathBuf;
+use std::cmp;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 
-use super::{storage::DirPath, DataStore, SizeUnit};
+use super::{storage::DirPath, BackgroundErrors, DataStore, SizeUnit};
 
-use crate::bucket::bucket::InsertableToBucket;
+use crate::block::BlockCache;
 use crate::bucket::{Bucket, BucketID, BucketMap};
 use crate::cfg::Config;
-use crate::compactors::{self, Compactor};
+use crate::changes::Subscriptions;
+use crate::compactors::{self, CompactionProgress, Compactor, RateLimiter};
 use crate::consts::{
-    DEFAULT_FLUSH_SIGNAL_CHANNEL_SIZE, HEAD_ENTRY_KEY, HEAD_ENTRY_VALUE, SIZE_OF_U32, SIZE_OF_U64, SIZE_OF_U8,
-    TAIL_ENTRY_KEY, TAIL_ENTRY_VALUE,
+    BUCKET_DIRECTORY_PREFIX, DEFAULT_FLUSH_SIGNAL_CHANNEL_SIZE, HEAD_ENTRY_KEY, HEAD_ENTRY_VALUE, TAIL_ENTRY_KEY,
+    TAIL_ENTRY_VALUE, TEMP_EXTENSION,
 };
 use crate::err::Error;
 use crate::err::Error::*;
 use crate::filter::BloomFilter;
 use crate::flusher::Flusher;
+use crate::fs::{FsCapabilities, LockFile};
 use crate::gc::gc::GC;
+use crate::gc::{DeadBytes, FreedValues};
+use crate::idempotency::IdempotencyTokens;
 use crate::key_range::KeyRange;
+use crate::lock::KeyLocks;
 use crate::memtable::{Entry, MemTable};
-use crate::meta::Meta;
+use crate::meta::{FileNumbers, Manifest, Meta, Sequence, ValueLogOffsets};
+use crate::range_tombstone::RangeTombstones;
+use crate::snapshot::Snapshots;
 use crate::sst::Table;
+use crate::transaction::PreparedBatches;
 use crate::types::{self, Key, MemtableId};
 use crate::value_log::ValueLog;
 use async_broadcast::broadcast;
-use chrono::Utc;
 use crossbeam_skiplist::SkipMap;
+use futures::stream::{self, StreamExt};
 use indexmap::IndexMap;
 use std::sync::Arc;
-use tokio::fs::read_dir;
+use tokio::fs::{self, read_dir};
 use tokio::sync::RwLock;
+use uuid::Uuid;
+
+/// What was found and cleaned up while opening the store, returned by `DataStore::recovery_report`
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryReport {
+    /// Files and directories left behind by a crash that were deleted: SSTables and buckets the manifest does
+    /// not list and temporary files
+    pub removed: Vec<PathBuf>,
+
+    /// SSTables that could not be opened, they were moved out of the store
+    pub quarantined: Vec<QuarantinedTable>,
+}
+
+/// An SSTable left out of the store because it is corrupted
+#[derive(Debug, Clone)]
+pub struct QuarantinedTable {
+    /// Directory the SSTable was stored in
+    pub dir: PathBuf,
+
+    /// Directory of the quarantine the SSTable was moved to, `None` if its directory was missing or the store
+    /// was opened read-only
+    pub moved_to: Option<PathBuf>,
+
+    /// Why the SSTable could not be opened
+    pub reason: String,
+}
+
+/// Progress of opening the store, reported by `DataStore::open_with_progress`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RecoveryProgress {
+    /// Number of SSTables found in the manifest, or in the buckets directory if there is no manifest yet
+    pub tables_discovered: usize,
+
+    /// Number of SSTables loaded or quarantined so far
+    pub tables_loaded: usize,
+
+    /// Number of value log bytes replayed so far
+    pub bytes_replayed: usize,
+
+    /// Number of value log bytes from the head to the end of the value log
+    pub bytes_to_replay: usize,
+
+    /// Number of read-only memtables rebuilt from the replayed entries so far
+    pub memtables_rebuilt: usize,
+}
+
+// SSTable found while opening the store along with its bucket, `table` is an error if its files are missing
+struct FoundTable {
+    bucket_id: BucketID,
+    bucket_dir: PathBuf,
+    dir: PathBuf,
+    table: Result<Table, Error>,
+}
 
 impl DataStore<'static, Key> {
     pub async fn recover(
         dir: DirPath,
-        buckets_path: PathBuf,
         mut vlog: ValueLog,
-        mut key_range: KeyRange,
         config: &Config,
         size_unit: SizeUnit,
-        meta: Meta,
+        lock: Option<LockFile>,
+        fs_capabilities: FsCapabilities,
+        on_progress: &mut (dyn FnMut(&RecoveryProgress) + Send),
     ) -> Result<DataStore<'static, Key>, Error> {
-        let mut recovered_buckets: IndexMap<BucketID, Bucket> = IndexMap::new();
+        // Without the lock another process may be writing to the directory, nothing is changed in it
+        let read_only = lock.is_none();
+        let buckets_path = dir.buckets.to_owned();
+        let mut key_range = KeyRange::new();
+        let mut meta = Meta::open(&dir.meta).await?;
+        let v_log_offsets = meta.v_log_offsets().await;
+        let manifest_exists = dir.manifest.exists();
+        let mut manifest = Manifest::open(
+            dir.manifest.to_owned(),
+            buckets_path.to_owned(),
+            config.false_positive_rate,
+        )
+        .await?;
+        let mut recovered_tables: IndexMap<BucketID, (PathBuf, Vec<Table>)> = IndexMap::new();
         let mut filters: Vec<BloomFilter> = Vec::new();
         let mut most_recent_head_timestamp = 0;
         let mut most_recent_head_offset = 0;
         let mut most_recent_tail_timestamp = 0;
         let mut most_recent_tail_offset = 0;
 
-        // Get bucket diretories streams
-        let mut buckets_stream = read_dir(buckets_path.to_owned())
-            .await
-            .map_err(|err| DirectoryOpenError {
-                path: buckets_path.to_owned(),
-                error: err,
-            })?;
-        // for each bucket directory
-        while let Some(bucket_dir) = buckets_stream.next_entry().await.map_err(|err| DirectoryOpenError {
-            path: buckets_path.to_owned(),
-            error: err,
-        })? {
-            // get read stream for sstable directories stream in the bucket
-            let mut sst_directories_stream =
-                read_dir(bucket_dir.path().to_owned())
-                    .await
-                    .map_err(|err| DirectoryOpenError {
-                        path: buckets_path.to_owned(),
-                        error: err,
-                    })?;
-            // iterate over each sstable directory
-            while let Some(sst_dir) = sst_directories_stream
-                .next_entry()
-                .await
-                .map_err(|err| DirectoryOpenError {
-                    path: buckets_path.to_owned(),
-                    error: err,
-                })?
-            {
-                // get read stream for files in the sstable directory
-                let files_read_stream = read_dir(sst_dir.path()).await.map_err(|err| FileOpenError {
-                    path: sst_dir.path(),
-                    error: err,
-                });
-                let mut sst_files = Vec::new();
-                let mut reader = files_read_stream.unwrap();
-                // iterate over each file
-                while let Some(file) = reader.next_entry().await.map_err(|err| DirectoryOpenError {
-                    path: buckets_path.to_owned(),
-                    error: err,
-                })? {
-                    let file_path = file.path();
-                    if file_path.is_file() {
-                        sst_files.push(file_path);
-                    }
+        let tables = if manifest_exists {
+            Self::open_tables_in_manifest(&manifest).await?
+        } else {
+            // Stores written before the manifest existed are walked once, their SSTables are recorded below
+            Self::find_tables_in_buckets(&buckets_path).await?
+        };
+        let mut progress = RecoveryProgress {
+            tables_discovered: tables.len(),
+            ..Default::default()
+        };
+        on_progress(&progress);
+        let mut quarantined = Vec::new();
+        // SSTables are read and their filters built concurrently, bounded by `max_parallel_sstable_reads`, and
+        // merged in the order they were found
+        let loads: Vec<(FoundTable, f64)> = tables
+            .into_iter()
+            .map(|found| {
+                let false_positive_rate = manifest
+                    .get(&found.dir)
+                    .map_or(config.false_positive_rate, |record| record.false_positive_rate);
+                (found, false_positive_rate)
+            })
+            .collect();
+        let mut loads = stream::iter(loads)
+            .map(|(found, false_positive_rate)| async move {
+                let loaded = match found.table {
+                    Ok(table) => tokio::spawn(Self::load_table(table, false_positive_rate))
+                        .await
+                        .map_err(|_| TokioJoinError)
+                        .and_then(|loaded| loaded),
+                    Err(err) => Err(err),
+                };
+                (found.bucket_id, found.bucket_dir, found.dir, loaded)
+            })
+            .buffered(cmp::max(config.max_parallel_sstable_reads, 1));
+        while let Some((bucket_id, bucket_dir, table_dir, loaded)) = loads.next().await {
+            progress.tables_loaded += 1;
+            let (table, sstable, filter) = match loaded {
+                Ok(loaded) => loaded,
+                // A corrupted SSTable is set aside so that the rest of the store can be opened
+                Err(err) if Self::is_corrupted(&err) => {
+                    let table =
+                        Self::quarantine_table(&table_dir, &dir.quarantine, &mut manifest, err, !read_only).await?;
+                    quarantined.push(table);
+                    on_progress(&progress);
+                    continue;
                 }
-                // Sort to make order deterministic
-                sst_files.sort();
-                let bucket_id = Self::get_bucket_id_from_full_bucket_path(sst_dir.path());
-                if sst_files.len() < 2 {
-                    return Err(InvalidSSTableDirectoryError {
-                        input_string: sst_dir.path().to_owned().to_string_lossy().to_string(),
-                    });
-                }
-                let data_file_path = sst_files[0].to_owned();
-                let index_file_path = sst_files[1].to_owned();
-                let table = Table::build_from(
-                    sst_dir.path().to_owned(),
-                    data_file_path.to_owned(),
-                    index_file_path.to_owned(),
-                )
-                .await;
-                let bucket_uuid = uuid::Uuid::parse_str(&bucket_id).map_err(|err| InvaidUUIDParseString {
-                    input_string: bucket_id,
-                    error: err,
-                })?;
-
-                if let Some(b) = recovered_buckets.get(&bucket_uuid) {
-                    let temp_sstables = b.sstables.clone();
-                    temp_sstables.write().await.push(table.clone());
-                    let updated_bucket =
-                        Bucket::from(bucket_dir.path(), bucket_uuid, temp_sstables.read().await.clone(), 0).await?;
-                    recovered_buckets.insert(bucket_uuid, updated_bucket);
-                } else {
-                    // Create new bucket
-                    let updated_bucket = Bucket::from(bucket_dir.path(), bucket_uuid, vec![table.clone()], 0).await?;
-                    recovered_buckets.insert(bucket_uuid, updated_bucket);
-                }
-
-                let sstable = table.load_entries_from_file().await?;
+                Err(err) => return Err(err),
+            };
+            if !manifest_exists {
+                manifest.record(bucket_id, &bucket_dir, &sstable)?;
+            }
+            let record = manifest.get(&table.dir).ok_or(InvalidSSTableDirectoryError {
+                input_string: table.dir.to_string_lossy().to_string(),
+            })?;
+            // Meta files written before the offsets of the value log were only find them in the SSTables
+            if v_log_offsets.is_none() {
                 let head_entry = sstable.get_value_from_entries(HEAD_ENTRY_KEY);
                 let tail_entry = sstable.get_value_from_entries(TAIL_ENTRY_KEY);
-                let biggest_key = sstable.find_biggest_key().unwrap();
-                let smallest_key = sstable.find_smallest_key().unwrap();
                 // update head
                 if let Some(value) = head_entry {
                     if value.created_at > most_recent_head_timestamp {
@@ -144,29 +195,104 @@ impl DataStore<'static, Key> {
                         most_recent_tail_timestamp = value.created_at;
                     }
                 }
-                let mut filter = Table::build_filter_from_sstable(&sstable.entries, config.false_positive_rate);
-                table.entries.clear();
-                filter.set_sstable(table.clone());
-                filters.push(filter);
-                key_range.set(data_file_path.to_owned(), smallest_key, biggest_key, table);
             }
+            filters.push(filter);
+            key_range.set(
+                table.data_file.path.to_owned(),
+                record.smallest_key.to_owned(),
+                record.biggest_key.to_owned(),
+                table.clone(),
+            );
+            recovered_tables
+                .entry(bucket_id)
+                .or_insert_with(|| (bucket_dir, Vec::new()))
+                .1
+                .push(table);
+            on_progress(&progress);
+        }
+        // Buckets are built once all their SSTables are known, building one reads the size of every SSTable it holds
+        let mut recovered_buckets: IndexMap<BucketID, Bucket> = IndexMap::new();
+        for (bucket_id, (bucket_dir, sstables)) in recovered_tables {
+            recovered_buckets.insert(bucket_id, Bucket::from(bucket_dir, bucket_id, sstables, 0).await?);
         }
+        if !read_only && (!manifest_exists || !quarantined.is_empty()) {
+            manifest.write().await?;
+        }
+        let removed = if read_only {
+            Vec::new()
+        } else {
+            Self::remove_unlisted_files(&manifest, &buckets_path, &dir.meta).await?
+        };
+        let recovery_report = RecoveryReport { removed, quarantined };
         let mut buckets_map = BucketMap::new(buckets_path.clone()).await;
         for (bucket_id, bucket) in recovered_buckets.iter() {
             buckets_map.buckets.insert(*bucket_id, bucket.clone());
         }
-        vlog.set_head(most_recent_head_offset);
-        vlog.set_tail(most_recent_tail_offset);
+        // Numbers handed out since the meta file was last written are only found in the names they gave
+        for record in manifest.tables() {
+            for path in [&record.bucket_dir, &record.dir] {
+                if let Some(number) = path
+                    .file_name()
+                    .and_then(|name| FileNumbers::parse(&name.to_string_lossy()))
+                {
+                    meta.file_numbers.advance_to(number);
+                }
+            }
+        }
+        buckets_map.set_manifest(manifest);
+        buckets_map.set_file_numbers(meta.file_numbers.clone());
+        buckets_map.set_checksum_type(config.checksum_type);
+        buckets_map.set_compression(config.compression_policy());
+        buckets_map.set_tombstone_compaction_ratio(config.tombstone_compaction_ratio);
+        buckets_map.set_policy(config.bucket_policy());
+        meta.sequence
+            .advance_to(most_recent_head_timestamp.max(most_recent_tail_timestamp));
+        let v_log_offsets = match v_log_offsets {
+            Some(offsets) => offsets,
+            None => {
+                let offsets = ValueLogOffsets {
+                    head: most_recent_head_offset,
+                    tail: most_recent_tail_offset,
+                };
+                if !read_only {
+                    meta.set_v_log_offsets(offsets).await?;
+                }
+                offsets
+            }
+        };
+        vlog.set_head(v_log_offsets.head);
+        vlog.set_tail(v_log_offsets.tail);
 
         let recover_res = DataStore::recover_memtable(
             size_unit,
-            config.write_buffer_size,
-            config.false_positive_rate,
+            config,
             &dir.val_log,
-            most_recent_head_offset,
+            v_log_offsets,
+            &meta.sequence,
+            !read_only,
+            &mut |replay: &RecoveryProgress| {
+                progress.bytes_replayed = replay.bytes_replayed;
+                progress.bytes_to_replay = replay.bytes_to_replay;
+                progress.memtables_rebuilt = replay.memtables_rebuilt;
+                on_progress(&progress);
+            },
         )
         .await;
         let (flush_signal_tx, flush_signal_rx) = broadcast(DEFAULT_FLUSH_SIGNAL_CHANNEL_SIZE);
+        let range_tombstones = RangeTombstones::open(dir.range_tombstones.to_owned()).await?;
+        let range_tombstones = Arc::new(RwLock::new(range_tombstones));
+        let (idempotency_tokens, prepared_batches) = if read_only {
+            (
+                IdempotencyTokens::open_read_only(dir.idempotency_tokens.to_owned(), config.idempotency_token_ttl)
+                    .await?,
+                PreparedBatches::open_read_only(dir.prepared_batches.to_owned()).await?,
+            )
+        } else {
+            (
+                IdempotencyTokens::open(dir.idempotency_tokens.to_owned(), config.idempotency_token_ttl).await?,
+                PreparedBatches::open(dir.prepared_batches.to_owned()).await?,
+            )
+        };
         match recover_res {
             Ok((active_memtable, read_only_memtables)) => {
                 let buckets = Arc::new(RwLock::new(buckets_map.to_owned()));
@@ -175,39 +301,59 @@ impl DataStore<'static, Key> {
                 let read_only_memtables = Arc::new(RwLock::new(read_only_memtables));
                 let gc_table = Arc::new(RwLock::new(active_memtable.to_owned()));
                 let gc_log = Arc::new(RwLock::new(vlog.to_owned()));
+                let background_errors = BackgroundErrors::new();
+                let compaction_progress = CompactionProgress::new();
                 let flusher = Flusher::new(
                     read_only_memtables.clone(),
                     buckets.clone(),
                     filters.clone(),
                     key_range.clone(),
-                );
+                    meta.clone(),
+                    background_errors.clone(),
+                )
+                .with_compaction_progress(compaction_progress.clone());
+                let freed_values = FreedValues::new();
+                let dead_bytes = DeadBytes::new(config.gc_garbage_ratio);
+                // Read-only stores do not collect garbage, the file system is left untouched
+                let gc_strategy = if read_only {
+                    config.gc_strategy
+                } else {
+                    config.gc_strategy.resolve(&fs_capabilities)
+                };
                 Ok(DataStore {
                     active_memtable: active_memtable.to_owned(),
-                    val_log: vlog,
+                    val_log: vlog.clone(),
                     dir,
                     buckets,
                     filters,
                     key_range,
-                    meta,
+                    meta: meta.clone(),
                     flusher,
-                    compactor: Compactor::new(
-                        config.enable_ttl,
-                        config.entry_ttl_millis,
-                        config.tombstone_ttl,
-                        config.background_compaction_interval,
-                        config.compactor_flush_listener_interval,
-                        config.tombstone_compaction_interval,
-                        config.compaction_strategy,
-                        compactors::CompactionReason::MaxSize,
-                        config.false_positive_rate,
-                    ),
+                    compactor: Compactor::new(config, compactors::CompactionReason::MaxSize)
+                        .with_background_errors(background_errors.clone())
+                        .with_value_log(vlog.clone())
+                        .with_rate_limiter(RateLimiter::new(config.compaction_rate_limit))
+                        .with_compaction_progress(compaction_progress)
+                        .with_hotness_half_life(config.hotness_half_life)
+                        .with_read_amp_compaction_threshold(config.read_amp_compaction_threshold)
+                        .with_freed_values(freed_values.clone())
+                        .with_dead_bytes(dead_bytes.clone()),
                     config: config.clone(),
                     gc: GC::new(
                         config.online_gc_interval,
                         config.gc_chunk_size,
+                        config.version_retention,
                         gc_table.clone(),
                         gc_log.clone(),
-                    ),
+                        meta.clone(),
+                        background_errors.clone(),
+                    )
+                    .with_freed_values(freed_values)
+                    .with_dead_bytes(dead_bytes)
+                    .with_strategy(gc_strategy)
+                    .with_chunk_interval(config.gc_chunk_interval)
+                    .with_rate_limiter(RateLimiter::new(config.gc_rewrite_rate_limit))
+                    .with_event_listener(config.event_listener.clone()),
                     read_only_memtables,
                     range_iterator: None,
                     flush_signal_tx,
@@ -215,6 +361,17 @@ impl DataStore<'static, Key> {
                     gc_log,
                     gc_table,
                     gc_updated_entries: Arc::new(RwLock::new(SkipMap::new())),
+                    range_tombstones,
+                    idempotency_tokens,
+                    snapshots: Snapshots::new(),
+                    prepared_batches,
+                    key_locks: KeyLocks::new(),
+                    subscriptions: Subscriptions::new(),
+                    background_errors,
+                    recovery_report,
+                    fs_capabilities,
+                    block_cache: BlockCache::new(config.block_cache_size),
+                    lock,
                 })
             }
             Err(err) => Err(MemTableRecoveryError(Box::new(err))),
@@ -223,24 +380,57 @@ impl DataStore<'static, Key> {
 
     pub async fn recover_memtable(
         size_unit: SizeUnit,
-        capacity: usize,
-        false_positive_rate: f64,
+        config: &Config,
         vlog_path: &PathBuf,
-        head_offset: usize,
+        v_log_offsets: ValueLogOffsets,
+        sequence: &Sequence,
+        truncate: bool,
+        on_progress: &mut (dyn FnMut(&RecoveryProgress) + Send),
     ) -> Result<(MemTable<Key>, IndexMap<MemtableId, Arc<RwLock<MemTable<Key>>>>), Error> {
+        let (capacity, false_positive_rate) = (config.write_buffer_size, config.false_positive_rate);
         let mut read_only_memtables: IndexMap<MemtableId, Arc<RwLock<MemTable<Key>>>> = IndexMap::new();
         let mut active_memtable = MemTable::with_specified_capacity_and_rate(size_unit, capacity, false_positive_rate);
-        let mut vlog = ValueLog::new(&vlog_path.clone()).await?;
-        let mut most_recent_offset = head_offset;
-        let entries = vlog.recover(head_offset).await?;
+        let vlog = ValueLog::new(&vlog_path.clone()).await?;
+        // Entries start after the header of the file
+        let head_offset = v_log_offsets.head.max(vlog.first_offset());
+        // Garbage collection can move the tail past entries not flushed yet, their live values were appended after
+        // the tail and the space before it may have been reclaimed
+        let start_offset = head_offset.max(v_log_offsets.tail);
+        let mut most_recent_offset = start_offset;
+        let entries = vlog.recover(start_offset, truncate).await?;
+        let vlog_len = vlog.base()
+            + fs::metadata(&vlog.content.path)
+                .await
+                .map_err(GetFileMetaDataError)?
+                .len() as usize;
+        let mut progress = RecoveryProgress {
+            bytes_to_replay: vlog_len.saturating_sub(start_offset),
+            ..Default::default()
+        };
+        on_progress(&progress);
 
         for e in entries {
-            let entry = Entry::new(e.key.to_owned(), most_recent_offset, e.created_at, e.is_tombstone);
+            // Entries replayed from the value log can be newer than the persisted sequence number
+            sequence.advance_to(e.created_at);
+            // A pointer is replayed as the value it points to in the log of another class
+            let location = e.redirect().unwrap_or(most_recent_offset);
+            // The value of a compressed record is not decoded here, it is read from the value log instead
+            let inline = if e.is_compressed {
+                None
+            } else {
+                config.inline_value(&e.value, e.is_tombstone, location)
+            };
+            let entry = Entry::new(e.key.to_owned(), location, e.created_at, e.is_tombstone)
+                .with_written_at(e.written_at)
+                .with_expiry(e.expires_at)
+                .with_inline(inline);
             // Since the most recent offset is the offset we start reading entries from in value log
             // and we retrieved this from the sstable, therefore should not re-write the initial entry in
             // memtable since it's already in the sstable
-            if most_recent_offset != head_offset {
-                if active_memtable.is_full(e.key.len()) {
+            // Values rewritten by a compaction filter are held by the merged SSTable along with the version they
+            // belong to, a newer version of the key may have been replayed already
+            if most_recent_offset != head_offset && !e.is_rewritten {
+                if active_memtable.is_full(&entry) {
                     // Make memtable read only
                     active_memtable.read_only = true;
                     read_only_memtables.insert(
@@ -249,32 +439,36 @@ impl DataStore<'static, Key> {
                     );
                     active_memtable =
                         MemTable::with_specified_capacity_and_rate(size_unit, capacity, false_positive_rate);
+                    progress.memtables_rebuilt += 1;
+                    progress.bytes_replayed = most_recent_offset - start_offset;
+                    on_progress(&progress);
                 }
                 active_memtable.insert(&entry)?;
+                if location != most_recent_offset {
+                    active_memtable.track_offset(&entry, most_recent_offset);
+                }
             }
-            most_recent_offset += SIZE_OF_U32   // Key Size(for fetching key length)
-                        +SIZE_OF_U32            // Value Length(for fetching value length)
-                        + SIZE_OF_U64           // Date Length
-                        + SIZE_OF_U8            // tombstone marker
-                        + e.key.len()           // Key Length
-                        + e.value.len(); // Value Length
+            most_recent_offset += e.serialized_len(vlog.format());
         }
+        progress.bytes_replayed = most_recent_offset - start_offset;
+        on_progress(&progress);
         Ok((active_memtable, read_only_memtables))
     }
 
     pub async fn handle_empty_vlog(
         dir: DirPath,
-        buckets_path: PathBuf,
         mut vlog: ValueLog,
-        key_range: KeyRange,
         config: &Config,
         size_unit: SizeUnit,
-        meta: Meta,
+        lock: LockFile,
+        fs_capabilities: FsCapabilities,
     ) -> Result<DataStore<'static, types::Key>, Error> {
+        let buckets_path = dir.buckets.to_owned();
+        let mut meta = Meta::open(&dir.meta).await?;
         let mut active_memtable =
             MemTable::with_specified_capacity_and_rate(size_unit, config.write_buffer_size, config.false_positive_rate);
         // if ValueLog is empty then we want to insert both tail and head
-        let created_at = Utc::now().timestamp_millis() as u64;
+        let created_at = meta.sequence.next();
         let tail_offset = vlog
             .append(&TAIL_ENTRY_KEY.to_vec(), &TAIL_ENTRY_VALUE.to_vec(), created_at, false)
             .await?;
@@ -285,46 +479,74 @@ impl DataStore<'static, Key> {
         let head_entry = Entry::new(HEAD_ENTRY_KEY.to_vec(), head_offset, created_at, false);
         vlog.set_head(head_offset);
         vlog.set_tail(tail_offset);
+        meta.set_v_log_offsets(ValueLogOffsets {
+            head: head_offset,
+            tail: tail_offset,
+        })
+        .await?;
 
         // insert tail and head to memtable
         active_memtable.insert(&tail_entry.to_owned())?;
         active_memtable.insert(&head_entry.to_owned())?;
-        let buckets = BucketMap::new(buckets_path).await;
+        let manifest = Manifest::open(
+            dir.manifest.to_owned(),
+            buckets_path.to_owned(),
+            config.false_positive_rate,
+        )
+        .await?;
+        let mut buckets = BucketMap::new(buckets_path).await;
+        buckets.set_manifest(manifest);
+        buckets.set_file_numbers(meta.file_numbers.clone());
+        buckets.set_checksum_type(config.checksum_type);
+        buckets.set_compression(config.compression_policy());
+        buckets.set_tombstone_compaction_ratio(config.tombstone_compaction_ratio);
+        buckets.set_policy(config.bucket_policy());
         let (flush_signal_tx, flush_signal_rx) = broadcast(DEFAULT_FLUSH_SIGNAL_CHANNEL_SIZE);
+        let range_tombstones = RangeTombstones::open(dir.range_tombstones.to_owned()).await?;
+        let range_tombstones = Arc::new(RwLock::new(range_tombstones));
+        let idempotency_tokens =
+            IdempotencyTokens::open(dir.idempotency_tokens.to_owned(), config.idempotency_token_ttl).await?;
+        let prepared_batches = PreparedBatches::open(dir.prepared_batches.to_owned()).await?;
         let read_only_memtables = IndexMap::new();
         let filters = Arc::new(RwLock::new(Vec::new()));
         let buckets = Arc::new(RwLock::new(buckets.to_owned()));
-        let key_range = Arc::new(RwLock::new(key_range));
+        let key_range = Arc::new(RwLock::new(KeyRange::new()));
         let read_only_memtables = Arc::new(RwLock::new(read_only_memtables));
         let gc_table = Arc::new(RwLock::new(active_memtable.to_owned()));
         let gc_log = Arc::new(RwLock::new(vlog.to_owned()));
+        let background_errors = BackgroundErrors::new();
+        let compaction_progress = CompactionProgress::new();
         let flusher = Flusher::new(
             read_only_memtables.clone(),
             buckets.clone(),
             filters.clone(),
             key_range.clone(),
-        );
+            meta.clone(),
+            background_errors.clone(),
+        )
+        .with_compaction_progress(compaction_progress.clone());
+        let freed_values = FreedValues::new();
+        let dead_bytes = DeadBytes::new(config.gc_garbage_ratio);
+        let gc_strategy = config.gc_strategy.resolve(&fs_capabilities);
 
         return Ok(DataStore {
             active_memtable,
-            val_log: vlog,
+            val_log: vlog.clone(),
             filters,
             buckets,
             dir,
             key_range,
-            compactor: Compactor::new(
-                config.enable_ttl,
-                config.entry_ttl_millis,
-                config.tombstone_ttl,
-                config.background_compaction_interval,
-                config.compactor_flush_listener_interval,
-                config.tombstone_compaction_interval,
-                config.compaction_strategy,
-                compactors::CompactionReason::MaxSize,
-                config.false_positive_rate,
-            ),
+            compactor: Compactor::new(config, compactors::CompactionReason::MaxSize)
+                .with_background_errors(background_errors.clone())
+                .with_value_log(vlog.clone())
+                .with_rate_limiter(RateLimiter::new(config.compaction_rate_limit))
+                .with_compaction_progress(compaction_progress)
+                .with_hotness_half_life(config.hotness_half_life)
+                .with_read_amp_compaction_threshold(config.read_amp_compaction_threshold)
+                .with_freed_values(freed_values.clone())
+                .with_dead_bytes(dead_bytes.clone()),
             config: config.clone(),
-            meta,
+            meta: meta.clone(),
             flusher,
             read_only_memtables,
             range_iterator: None,
@@ -333,28 +555,274 @@ impl DataStore<'static, Key> {
             gc: GC::new(
                 config.online_gc_interval,
                 config.gc_chunk_size,
+                config.version_retention,
                 gc_table.clone(),
                 gc_log.clone(),
-            ),
+                meta.clone(),
+                background_errors.clone(),
+            )
+            .with_freed_values(freed_values)
+            .with_dead_bytes(dead_bytes)
+            .with_strategy(gc_strategy)
+            .with_chunk_interval(config.gc_chunk_interval)
+            .with_rate_limiter(RateLimiter::new(config.gc_rewrite_rate_limit))
+            .with_event_listener(config.event_listener.clone()),
             gc_log,
             gc_table,
             gc_updated_entries: Arc::new(RwLock::new(SkipMap::new())),
+            range_tombstones,
+            idempotency_tokens,
+            snapshots: Snapshots::new(),
+            prepared_batches,
+            key_locks: KeyLocks::new(),
+            subscriptions: Subscriptions::new(),
+            background_errors,
+            recovery_report: RecoveryReport::default(),
+            fs_capabilities,
+            block_cache: BlockCache::new(config.block_cache_size),
+            lock: Some(lock),
         });
     }
 
-    fn get_bucket_id_from_full_bucket_path(full_path: PathBuf) -> String {
-        let full_path_as_str = full_path.to_string_lossy().to_string();
-        let mut bucket_id = String::new();
-        // Find the last occurrence of "bucket" in the file path
-        if let Some(idx) = full_path_as_str.rfind("bucket") {
-            // Extract the substring starting from the index after the last occurrence of "bucket"
-            let uuid_part = &full_path_as_str[idx + "bucket".len()..];
-            if let Some(end_idx) = uuid_part.find('/') {
-                // Extract the UUID
-                let uuid = &uuid_part[..end_idx];
-                bucket_id = uuid.to_string();
+    // Opens the SSTables listed in the manifest along with the id and directory of their bucket
+    async fn open_tables_in_manifest(manifest: &Manifest) -> Result<Vec<FoundTable>, Error> {
+        let mut tables = Vec::with_capacity(manifest.len());
+        for record in manifest.tables() {
+            let table = if !record.data_file.is_file() || !record.index_file.is_file() {
+                Err(InvalidSSTableDirectoryError {
+                    input_string: record.dir.to_string_lossy().to_string(),
+                })
+            } else {
+                Ok(Table::build_from(
+                    record.dir.to_owned(),
+                    record.data_file.to_owned(),
+                    record.index_file.to_owned(),
+                )
+                .await)
+            };
+            tables.push(FoundTable {
+                bucket_id: record.bucket_id,
+                bucket_dir: record.bucket_dir.to_owned(),
+                dir: record.dir.to_owned(),
+                table,
+            });
+        }
+        Ok(tables)
+    }
+
+    // Reads the entries of an SSTable and builds its filter, the entries are returned along with the table
+    async fn load_table(mut table: Table, false_positive_rate: f64) -> Result<(Table, Table, BloomFilter), Error> {
+        let sstable = table.load_entries_from_file(true).await?;
+        let mut filter = Table::build_filter_from_sstable(&sstable.entries, false_positive_rate);
+        table.entries.clear();
+        table.properties = sstable.properties;
+        filter.set_sstable(table.clone());
+        Ok((table, sstable, filter))
+    }
+
+    // Walks the buckets directory and opens every SSTable found along with the id and directory of its bucket,
+    // only used to build the manifest of a store that does not have one yet
+    async fn find_tables_in_buckets(buckets_path: &PathBuf) -> Result<Vec<FoundTable>, Error> {
+        let mut tables = Vec::new();
+
+        // Get bucket diretories streams
+        let mut buckets_stream = read_dir(buckets_path.to_owned())
+            .await
+            .map_err(|err| DirectoryOpenError {
+                path: buckets_path.to_owned(),
+                error: err,
+            })?;
+        // for each bucket directory
+        while let Some(bucket_dir) = buckets_stream.next_entry().await.map_err(|err| DirectoryOpenError {
+            path: buckets_path.to_owned(),
+            error: err,
+        })? {
+            let bucket_id = Self::bucket_id_from_dir(&bucket_dir.path());
+            // get read stream for sstable directories stream in the bucket
+            let mut sst_directories_stream =
+                read_dir(bucket_dir.path().to_owned())
+                    .await
+                    .map_err(|err| DirectoryOpenError {
+                        path: buckets_path.to_owned(),
+                        error: err,
+                    })?;
+            // iterate over each sstable directory
+            while let Some(sst_dir) = sst_directories_stream
+                .next_entry()
+                .await
+                .map_err(|err| DirectoryOpenError {
+                    path: buckets_path.to_owned(),
+                    error: err,
+                })?
+            {
+                // SSTables still being written when the store crashed
+                if sst_dir.path().extension().is_some_and(|ext| ext == TEMP_EXTENSION) {
+                    continue;
+                }
+                // get read stream for files in the sstable directory
+                let files_read_stream = read_dir(sst_dir.path()).await.map_err(|err| FileOpenError {
+                    path: sst_dir.path(),
+                    error: err,
+                });
+                let mut sst_files = Vec::new();
+                let mut reader = files_read_stream.unwrap();
+                // iterate over each file
+                while let Some(file) = reader.next_entry().await.map_err(|err| DirectoryOpenError {
+                    path: buckets_path.to_owned(),
+                    error: err,
+                })? {
+                    let file_path = file.path();
+                    if file_path.is_file() {
+                        sst_files.push(file_path);
+                    }
+                }
+                // Sort to make order deterministic
+                sst_files.sort();
+                let table = if sst_files.len() < 2 {
+                    Err(InvalidSSTableDirectoryError {
+                        input_string: sst_dir.path().to_owned().to_string_lossy().to_string(),
+                    })
+                } else {
+                    let data_file_path = sst_files[0].to_owned();
+                    let index_file_path = sst_files[1].to_owned();
+                    Ok(Table::build_from(
+                        sst_dir.path().to_owned(),
+                        data_file_path.to_owned(),
+                        index_file_path.to_owned(),
+                    )
+                    .await)
+                };
+                tables.push(FoundTable {
+                    bucket_id,
+                    bucket_dir: bucket_dir.path(),
+                    dir: sst_dir.path(),
+                    table,
+                });
             }
         }
-        bucket_id
+        Ok(tables)
+    }
+
+    // Returns true if `err` means the files of an SSTable are missing or do not hold what was written
+    pub(super) fn is_corrupted(err: &Error) -> bool {
+        matches!(
+            err,
+            InvalidSSTableDirectoryError { .. }
+                | ChecksumMismatch { .. }
+                | UnknownChecksumType { .. }
+                | UnexpectedEOF(_)
+                | SerializationError(_)
+        )
+    }
+
+    // Moves the SSTable stored in `sst_dir` to the `quarantine_path` directory and removes it from the manifest,
+    // `reason` is why it could not be opened
+    pub(super) async fn quarantine_table(
+        sst_dir: &Path,
+        quarantine_path: &Path,
+        manifest: &mut Manifest,
+        reason: Error,
+        move_table: bool,
+    ) -> Result<QuarantinedTable, Error> {
+        manifest.forget(sst_dir);
+        let mut moved_to = None;
+        if move_table && sst_dir.exists() {
+            fs::create_dir_all(quarantine_path)
+                .await
+                .map_err(|error| DirCreationError {
+                    path: quarantine_path.to_path_buf(),
+                    error,
+                })?;
+            let name = sst_dir.file_name().unwrap_or_default().to_string_lossy().to_string();
+            let mut path = quarantine_path.join(&name);
+            let mut attempt = 1;
+            while path.exists() {
+                path = quarantine_path.join(format!("{}.{}", name, attempt));
+                attempt += 1;
+            }
+            fs::rename(sst_dir, &path).await.map_err(|error| FileWriteError {
+                path: path.to_owned(),
+                error,
+            })?;
+            moved_to = Some(path);
+        }
+        log::error!(
+            "Quarantined SSTable {:?} to {:?}, it could not be opened: {}",
+            sst_dir,
+            moved_to,
+            reason
+        );
+        Ok(QuarantinedTable {
+            dir: sst_dir.to_path_buf(),
+            moved_to,
+            reason: reason.to_string(),
+        })
+    }
+
+    // Deletes what a crash can leave behind: SSTables and buckets the manifest does not list, such as the
+    // output of an interrupted flush or compaction, and temporary files in the meta directory
+    //
+    // Returns the deleted paths
+    async fn remove_unlisted_files(
+        manifest: &Manifest,
+        buckets_path: &Path,
+        meta_path: &Path,
+    ) -> Result<Vec<PathBuf>, Error> {
+        let live_buckets: HashSet<&PathBuf> = manifest.tables().map(|record| &record.bucket_dir).collect();
+        let mut unlisted = Vec::new();
+        for bucket_dir in Self::list_dir(buckets_path).await? {
+            if !live_buckets.contains(&bucket_dir) {
+                unlisted.push(bucket_dir);
+                continue;
+            }
+            for sst_dir in Self::list_dir(&bucket_dir).await? {
+                if manifest.get(&sst_dir).is_none() {
+                    unlisted.push(sst_dir);
+                }
+            }
+        }
+        for file in Self::list_dir(meta_path).await? {
+            if file.extension().is_some_and(|ext| ext == TEMP_EXTENSION) {
+                unlisted.push(file);
+            }
+        }
+        for path in unlisted.iter() {
+            log::warn!("Removing {:?} left behind by a crash", path);
+            if path.is_dir() {
+                fs::remove_dir_all(path).await.map_err(DirDeleteError)?;
+            } else {
+                fs::remove_file(path).await.map_err(FileDeleteError)?;
+            }
+        }
+        Ok(unlisted)
+    }
+
+    // Returns the paths of the entries of `dir`, none if it does not exist
+    pub(super) async fn list_dir(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+        let mut paths = Vec::new();
+        if !dir.exists() {
+            return Ok(paths);
+        }
+        let mut entries = read_dir(dir).await.map_err(|error| DirectoryOpenError {
+            path: dir.to_path_buf(),
+            error,
+        })?;
+        while let Some(entry) = entries.next_entry().await.map_err(|error| DirectoryOpenError {
+            path: dir.to_path_buf(),
+            error,
+        })? {
+            paths.push(entry.path());
+        }
+        Ok(paths)
+    }
+
+    // Buckets named before file numbers existed carry their id in their name, others are given a new id
+    pub(super) fn bucket_id_from_dir(bucket_dir: &Path) -> BucketID {
+        bucket_dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(BUCKET_DIRECTORY_PREFIX))
+            .and_then(|id| Uuid::parse_str(id).ok())
+            .unwrap_or_else(Uuid::new_v4)
     }
 }
@@ -0,0 +1,297 @@
+//! # Repair
+//!
+//! `repair` rebuilds what can be derived from the files of a damaged store, in the spirit of RocksDB's `RepairDB`:
+//! - the value log is truncated at the first entry from its tail on that cannot be read
+//! - every SSTable is read block by block. Blocks that cannot be read are dropped along with the entries whose
+//!   value was in the truncated part of the value log. An SSTable that lost entries, has no index or whose index
+//!   does not match its blocks is rewritten, which rebuilds its index. One left with no entry is quarantined
+//! - the manifest is rebuilt from the SSTables that remain
+//!
+//! Bloom filters are not stored, they are rebuilt from the SSTables when the store is opened.
+//!
+//! Repairing loses what was dropped, and an older version of a key held by a dropped entry can become visible again.
+
+use super::{storage::DirPath, DataStore, QuarantinedTable};
+use crate::bucket::bucket::SST_PREFIX;
+use crate::bucket::BucketID;
+use crate::cfg::Config;
+use crate::consts::{HEAD_ENTRY_KEY, TAIL_ENTRY_KEY, TEMP_EXTENSION, VLOG_FILE_NAME};
+use crate::err::Error;
+use crate::err::Error::*;
+use crate::fs::{DataFileNode, DataFs, FileType, LockFile};
+use crate::meta::{FileNumbers, Manifest, Meta};
+use crate::sst::Table;
+use crate::types::Key;
+use crate::value_log::ValueLog;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+/// Result of running `DataStore::repair`
+#[derive(Debug, Clone, Default)]
+pub struct RepairReport {
+    /// Number of SSTables kept as they were
+    pub intact_tables: usize,
+
+    /// SSTables rewritten from the entries that could still be read
+    pub rebuilt: Vec<RebuiltTable>,
+
+    /// SSTables left with no entry, they were moved out of the store
+    pub quarantined: Vec<QuarantinedTable>,
+
+    /// Offset the value log was truncated at, `None` if every entry from its tail on could be read
+    pub value_log_truncated_at: Option<usize>,
+}
+
+/// An SSTable rewritten by `DataStore::repair`
+#[derive(Debug, Clone)]
+pub struct RebuiltTable {
+    /// Directory the SSTable was stored in, it was removed
+    pub dir: PathBuf,
+
+    /// Directory of the rewritten SSTable
+    pub rebuilt_as: PathBuf,
+
+    /// Number of entries kept
+    pub entries: usize,
+
+    /// Number of blocks that could not be read
+    pub dropped_blocks: usize,
+
+    /// Number of entries dropped because their value was in the truncated part of the value log
+    pub dropped_entries: usize,
+}
+
+// What `repair_table` found an SSTable to be
+enum TableRepair {
+    Intact(Table),
+    Rebuilt(Table, RebuiltTable),
+    Unreadable(Error),
+}
+
+impl DataStore<'static, Key> {
+    /// Repairs the store in `dir`, see the module documentation
+    ///
+    /// The store must not be opened while it is repaired, the directory lock is taken for the duration of the repair.
+    pub async fn repair(dir: PathBuf) -> Result<RepairReport, Error> {
+        let dir = DirPath::build(dir);
+        if !dir.root.is_dir() {
+            return Err(DirectoryOpenError {
+                path: dir.root,
+                error: std::io::Error::from(std::io::ErrorKind::NotFound),
+            });
+        }
+        let _lock = LockFile::acquire(dir.lock.to_owned())?;
+        let config = Config::default();
+        let mut meta = Meta::open(&dir.meta).await?;
+        let mut report = RepairReport {
+            value_log_truncated_at: Self::repair_value_log(&dir, &mut meta).await?,
+            ..Default::default()
+        };
+
+        let mut manifest = match Manifest::open(
+            dir.manifest.to_owned(),
+            dir.buckets.to_owned(),
+            config.false_positive_rate,
+        )
+        .await
+        {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                log::warn!("Rebuilding unreadable manifest {:?}: {}", dir.manifest, err);
+                fs::remove_file(&dir.manifest).await.map_err(FileDeleteError)?;
+                Manifest::open(
+                    dir.manifest.to_owned(),
+                    dir.buckets.to_owned(),
+                    config.false_positive_rate,
+                )
+                .await?
+            }
+        };
+        // The manifest is rebuilt from the SSTables found, bucket ids are kept for the buckets it listed
+        let bucket_ids: HashMap<PathBuf, BucketID> = manifest
+            .tables()
+            .map(|record| (record.bucket_dir.to_owned(), record.bucket_id))
+            .collect();
+        let listed: Vec<PathBuf> = manifest.tables().map(|record| record.dir.to_owned()).collect();
+        for table_dir in listed.iter() {
+            manifest.forget(table_dir);
+        }
+
+        let bucket_dirs = Self::list_dir(&dir.buckets).await?;
+        // SSTables rewritten below must not be named like one that was found
+        for bucket_dir in bucket_dirs.iter().filter(|path| path.is_dir()) {
+            for path in Self::list_dir(bucket_dir).await?.iter().chain([bucket_dir]) {
+                if let Some(number) = path
+                    .file_name()
+                    .and_then(|name| FileNumbers::parse(&name.to_string_lossy()))
+                {
+                    meta.file_numbers.advance_to(number);
+                }
+            }
+        }
+        let mut replaced = Vec::new();
+        for bucket_dir in bucket_dirs.iter().filter(|path| path.is_dir()) {
+            let bucket_id = bucket_ids
+                .get(bucket_dir)
+                .copied()
+                .unwrap_or_else(|| Self::bucket_id_from_dir(bucket_dir));
+            for table_dir in Self::list_dir(bucket_dir).await? {
+                // Written by an interrupted flush or compaction, removed when the store is opened
+                if table_dir.extension().is_some_and(|ext| ext == TEMP_EXTENSION) {
+                    continue;
+                }
+                let truncated_at = report.value_log_truncated_at;
+                match Self::repair_table(&table_dir, bucket_dir, &meta.file_numbers, truncated_at).await? {
+                    TableRepair::Intact(table) => {
+                        manifest.record(bucket_id, bucket_dir, &table)?;
+                        report.intact_tables += 1;
+                    }
+                    TableRepair::Rebuilt(table, rebuilt) => {
+                        manifest.record(bucket_id, bucket_dir, &table)?;
+                        replaced.push(table_dir);
+                        report.rebuilt.push(rebuilt);
+                    }
+                    TableRepair::Unreadable(reason) => {
+                        let quarantined =
+                            Self::quarantine_table(&table_dir, &dir.quarantine, &mut manifest, reason, true).await?;
+                        report.quarantined.push(quarantined);
+                    }
+                }
+            }
+        }
+        // Replaced SSTables are only removed once the manifest lists what replaced them
+        manifest.write().await?;
+        for table_dir in replaced {
+            fs::remove_dir_all(&table_dir).await.map_err(DirDeleteError)?;
+        }
+        meta.write().await?;
+        Ok(report)
+    }
+
+    // Truncates the value log at the first entry from its tail on that cannot be read, returns where it was
+    // truncated
+    async fn repair_value_log(dir: &DirPath, meta: &mut Meta) -> Result<Option<usize>, Error> {
+        // Without the tail, space reclaimed by garbage collection cannot be told apart from corrupted entries
+        let Some(mut offsets) = meta.v_log_offsets().await else {
+            return Ok(None);
+        };
+        if !dir.val_log.join(VLOG_FILE_NAME).is_file() {
+            return Ok(None);
+        }
+        let vlog = ValueLog::new(&dir.val_log).await?;
+        let (entries, end) = vlog.verify(offsets.tail).await?;
+        let Some((truncated_at, err)) = end else {
+            return Ok(None);
+        };
+        let path = vlog.content.path.to_owned();
+        log::warn!(
+            "Truncating {:?} at offset {}, the entry there cannot be read: {}",
+            path,
+            truncated_at,
+            err
+        );
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .await
+            .map_err(|error| FileOpenError {
+                path: path.to_owned(),
+                error,
+            })?;
+        // Offsets are counted from the start of the log, which may have been dropped by garbage collection
+        file.set_len((truncated_at - vlog.base()) as u64)
+            .await
+            .map_err(|error| FileWriteError { path, error })?;
+        file.sync_all().await.map_err(|error| FileSyncError { error })?;
+        // The head must remain the offset of an entry, entries after it are replayed on recovery
+        if offsets.head >= truncated_at {
+            offsets.head = entries.last().map_or(offsets.tail, |(offset, _)| *offset);
+            meta.set_v_log_offsets(offsets).await?;
+        }
+        Ok(Some(truncated_at))
+    }
+
+    // Salvages the entries of the SSTable stored in `table_dir` and rewrites it in `bucket_dir` if some were lost
+    // or its index does not match its blocks
+    async fn repair_table(
+        table_dir: &Path,
+        bucket_dir: &Path,
+        file_numbers: &FileNumbers,
+        truncated_at: Option<usize>,
+    ) -> Result<TableRepair, Error> {
+        let files = Self::list_dir(table_dir).await?;
+        let find_file = |prefix: &str| {
+            files
+                .iter()
+                .find(|path| {
+                    path.file_name()
+                        .is_some_and(|name| name.to_string_lossy().starts_with(prefix))
+                })
+                .cloned()
+        };
+        let invalid_dir = || InvalidSSTableDirectoryError {
+            input_string: table_dir.to_string_lossy().to_string(),
+        };
+        let Some(data_file_path) = find_file("data_") else {
+            return Ok(TableRepair::Unreadable(invalid_dir()));
+        };
+        let data_file = DataFileNode::new(data_file_path.to_owned(), FileType::Data).await?;
+        let (entries, dropped_blocks) = match data_file.salvage_entries().await {
+            Ok(salvaged) => salvaged,
+            Err(err) if Self::is_corrupted(&err) => return Ok(TableRepair::Unreadable(err)),
+            Err(err) => return Err(err),
+        };
+        let mut dropped_entries = 0;
+        if let Some(truncated_at) = truncated_at {
+            for entry in entries.iter() {
+                // The head and tail entries hold offsets into the value log rather than the offset of a value
+                let is_offset = entry.key() == HEAD_ENTRY_KEY || entry.key() == TAIL_ENTRY_KEY;
+                if !is_offset && entry.value().val_offset >= truncated_at {
+                    entry.remove();
+                    dropped_entries += 1;
+                }
+            }
+        }
+        if entries.is_empty() {
+            return Ok(TableRepair::Unreadable(invalid_dir()));
+        }
+
+        if dropped_blocks == 0 && dropped_entries == 0 {
+            if let Some(index_file_path) = find_file("index_") {
+                let mut table = Table::build_from(table_dir.to_path_buf(), data_file_path, index_file_path).await;
+                // Values are not checked, only whether the index matches the blocks
+                let integrity = Self::verify_table_integrity(&table, &HashMap::new(), usize::MAX).await?;
+                if integrity.issues.is_empty() {
+                    table.set_entries(entries);
+                    return Ok(TableRepair::Intact(table));
+                }
+            }
+        }
+        let file_number = file_numbers.next();
+        let rebuilt_as = bucket_dir.join(format!("{}_{:06}", SST_PREFIX, file_number));
+        let table = Table::write_new(
+            rebuilt_as.to_owned(),
+            file_number,
+            entries,
+            Config::default().checksum_type,
+            Config::default().sstable_compression,
+        )
+        .await?;
+        log::warn!(
+            "Rebuilt SSTable {:?} as {:?}, {} blocks and {} entries were dropped",
+            table_dir,
+            rebuilt_as,
+            dropped_blocks,
+            dropped_entries
+        );
+        let rebuilt = RebuiltTable {
+            dir: table_dir.to_path_buf(),
+            rebuilt_as,
+            entries: table.entries.len(),
+            dropped_blocks,
+            dropped_entries,
+        };
+        Ok(TableRepair::Rebuilt(table, rebuilt))
+    }
+}
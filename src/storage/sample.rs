@@ -0,0 +1,127 @@
+//! # Sample
+//!
+//! `sample_keys` returns approximately uniformly distributed keys without scanning SSTables. Memtable keys are
+//! sampled directly while every SSTable only contributes the last key of each of its blocks, read from the index
+//! and weighted by the estimated number of keys in the block.
+
+use super::DataStore;
+use crate::consts::{HEAD_ENTRY_KEY, TAIL_ENTRY_KEY};
+use crate::err::Error;
+use crate::index::Index;
+use crate::types::{Key, SkipMapEntries};
+use rand::Rng;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::path::PathBuf;
+
+impl<'a> DataStore<'a, Key> {
+    /// Returns up to `n` keys sampled approximately uniformly from the store, in key order
+    ///
+    /// Keys sampled from SSTables are block boundaries, a key that was deleted or overwritten in a more
+    /// recent table can be returned
+    pub async fn sample_keys(&self, n: usize) -> Result<Vec<Key>, Error> {
+        let mut sampler = Sampler::new(n);
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        let super_version = self.super_version().await;
+        sampler.offer_entries(&self.active_memtable.entries);
+        for memtable in super_version.memtables.iter() {
+            sampler.offer_entries(&memtable.read().await.entries);
+        }
+
+        let keys_per_table: HashMap<PathBuf, usize> = self
+            .filters
+            .read()
+            .await
+            .iter()
+            .map(|f| (f.get_sst().dir.to_owned(), f.num_elements()))
+            .collect();
+        for range in super_version.ranges.iter() {
+            let sst = &range.sst;
+            let index = Index::new(sst.index_file.path.to_owned(), sst.index_file.file.to_owned());
+            let block_keys = index.keys().await?;
+            if block_keys.is_empty() {
+                continue;
+            }
+            let num_keys = keys_per_table.get(&sst.dir).copied().unwrap_or(block_keys.len());
+            let weight = f64::max(num_keys as f64 / block_keys.len() as f64, 1.0);
+            for key in block_keys {
+                sampler.offer(key, weight);
+            }
+        }
+        Ok(sampler.into_keys())
+    }
+}
+
+// Weighted reservoir sampling (Efraimidis-Spirakis): every key gets the priority u^(1/weight) with u
+// uniform in (0, 1) and the `n` keys with the highest priority are kept
+struct Sampler {
+    n: usize,
+    reservoir: BinaryHeap<Reverse<Candidate>>,
+}
+
+struct Candidate {
+    // ln(u) / weight, same order as u^(1/weight) without underflowing for big weights
+    priority: f64,
+    key: Key,
+}
+
+impl Sampler {
+    fn new(n: usize) -> Self {
+        Self {
+            n,
+            reservoir: BinaryHeap::with_capacity(n),
+        }
+    }
+
+    fn offer_entries(&mut self, entries: &SkipMapEntries<Key>) {
+        for entry in entries.iter().filter(|e| !e.value().is_deleted()) {
+            self.offer(entry.key().to_owned(), 1.0);
+        }
+    }
+
+    fn offer(&mut self, key: Key, weight: f64) {
+        if key == HEAD_ENTRY_KEY || key == TAIL_ENTRY_KEY {
+            return;
+        }
+        let u: f64 = rand::thread_rng().gen_range(f64::MIN_POSITIVE..1.0);
+        let candidate = Candidate {
+            priority: u.ln() / weight,
+            key,
+        };
+        if self.reservoir.len() < self.n {
+            self.reservoir.push(Reverse(candidate));
+        } else if self.reservoir.peek().is_some_and(|Reverse(min)| candidate > *min) {
+            self.reservoir.pop();
+            self.reservoir.push(Reverse(candidate));
+        }
+    }
+
+    fn into_keys(self) -> Vec<Key> {
+        let mut keys: Vec<Key> = self.reservoir.into_iter().map(|Reverse(c)| c.key).collect();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority.total_cmp(&other.priority)
+    }
+}
@@ -1,30 +1,51 @@
+use crate::batch::{BatchProgress, WriteBatch};
+use crate::block::BlockCache;
 use crate::bucket::bucket::InsertableToBucket;
-use crate::cfg::Config;
-use crate::compactors::Compactor;
+use crate::bucket::SSTablesToRemove;
+use crate::cfg::{Config, ReadOptions, WriteOptions};
+use crate::changes::{Change, ChangeEvent, Subscriptions};
+use crate::compactors::{Compactor, ReadAmpStats, ReadCost};
 use crate::consts::{
-    BUCKETS_DIRECTORY_NAME, HEAD_ENTRY_KEY, KB, META_DIRECTORY_NAME, TOMB_STONE_MARKER, VALUE_LOG_DIRECTORY_NAME,
+    BUCKETS_DIRECTORY_NAME, DEFAULT_SUBSCRIPTION_CHANNEL_SIZE, HEAD_ENTRY_KEY, IDEMPOTENCY_TOKENS_FILE_NAME, KB,
+    LOCK_FILE_NAME, MANIFEST_FILE_NAME, META_DIRECTORY_NAME, PREPARED_BATCHES_FILE_NAME, QUARANTINE_DIRECTORY_NAME,
+    RANGE_TOMBSTONES_FILE_NAME, TAIL_ENTRY_KEY, TOMB_STONE_MARKER, VALUE_LOG_DIRECTORY_NAME, VLOG_FILE_NAME,
 };
 use crate::err::Error;
 use crate::err::Error::*;
 use crate::filter::BloomFilter;
 use crate::flusher::Flusher;
+use crate::fs::{FileAsync, FileNode, FsCapabilities, LockFile};
 use crate::gc::gc::GC;
+use crate::gc::{GCContext, GCReport, GCRun, ValueLogStats};
+use crate::idempotency::IdempotencyTokens;
 use crate::index::Index;
-use crate::key_range::KeyRange;
-use crate::memtable::{Entry, MemTable};
+use crate::key_range::{KeyRange, Range};
+use crate::lock::{KeyLockGuard, KeyLocks};
+use crate::memtable::{Entry, MemTable, SkipMapValue};
 use crate::meta::Meta;
 use crate::range::RangeIterator;
+use crate::range_tombstone::RangeTombstone;
+use crate::snapshot::{Snapshot, Snapshots};
+use crate::storage::{BackgroundErrors, RecoveryProgress, RecoveryReport};
+use crate::sst::Table;
+use crate::transaction::{PreparedBatches, PreparedToken};
 use crate::types::{
-    self, BloomFilterHandle, Bool, BucketMapHandle, CreationTime, FlushSignal, GCUpdatedEntries, ImmutableMemTable,
-    Key, KeyRangeHandle, Value,
+    self, BloomFilterHandle, Bool, BucketMapHandle, CreationTime, Duration, FlushSignal, FoundEntry, GCUpdatedEntries,
+    ImmutableMemTable, Key, KeyRangeHandle, RangeTombstonesHandle, SkipMapEntries, ValOffset, Value, ValueReader,
+    WriteTime,
 };
-use crate::value_log::ValueLog;
+use crate::value_log::{ValueClass, ValueLog};
 use chrono::Utc;
 use indexmap::IndexMap;
 use std::path::PathBuf;
+use std::sync::atomic::Ordering;
 use std::{hash::Hash, sync::Arc};
 use tokio::fs::{self};
+use tokio::io::AsyncRead;
 use tokio::sync::RwLock;
+/// The storage engine is single-process, opening a directory acquires an exclusive lock on its `LOCK` file
+/// and a second open of the same directory fails with `FileLockError` until the first store is closed or dropped.
+/// A store opened with `open_read_only` does not take the lock so it can inspect a directory opened elsewhere.
 pub struct DataStore<'a, K>
 where
     K: Hash + Ord + Send + Sync + Clone,
@@ -47,6 +68,20 @@ where
     pub gc_updated_entries: GCUpdatedEntries<K>,
     pub gc_table: Arc<RwLock<MemTable<Key>>>,
     pub gc_log: Arc<RwLock<ValueLog>>,
+    pub range_tombstones: RangeTombstonesHandle,
+    pub idempotency_tokens: IdempotencyTokens,
+    pub snapshots: Snapshots,
+    pub prepared_batches: PreparedBatches,
+    pub key_locks: KeyLocks,
+    pub subscriptions: Subscriptions,
+    pub background_errors: BackgroundErrors,
+    pub recovery_report: RecoveryReport,
+    /// Probed when the store is opened, nothing is supported by a store opened read-only
+    pub fs_capabilities: FsCapabilities,
+    /// Blocks of the SSTables last read by gets and range queries
+    pub block_cache: BlockCache,
+    /// `None` if the store was opened read-only
+    pub lock: Option<LockFile>,
 }
 
 #[derive(Clone, Debug)]
@@ -55,6 +90,12 @@ pub struct DirPath {
     pub val_log: PathBuf,
     pub buckets: PathBuf,
     pub meta: PathBuf,
+    pub quarantine: PathBuf,
+    pub manifest: PathBuf,
+    pub lock: PathBuf,
+    pub range_tombstones: PathBuf,
+    pub idempotency_tokens: PathBuf,
+    pub prepared_batches: PathBuf,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -80,23 +121,106 @@ impl<'a> DataStore<'a, Key> {
     pub async fn new(dir: PathBuf) -> Result<DataStore<'a, Vec<u8>>, Error> {
         let dir = DirPath::build(dir);
         let default_config = Config::default();
-        let store = DataStore::with_default_config(dir.to_owned(), SizeUnit::Bytes, default_config).await?;
+        let store =
+            DataStore::with_default_config(dir.to_owned(), SizeUnit::Bytes, default_config, &mut |_| {}).await?;
         store.start_background_jobs();
         return Ok(store);
     }
 
     pub async fn new_with_custom_config(dir: PathBuf, config: Config) -> Result<DataStore<'a, Key>, Error> {
+        Self::open_with_progress(dir, config, |_| {}).await
+    }
+
+    /// Same as `new_with_custom_config` but calls `on_progress` as SSTables are loaded and the value log is replayed
+    ///
+    /// Progress is reported once the SSTables are discovered, after each SSTable is loaded, when the replay of the
+    /// value log starts, after each memtable it fills and when it ends.
+    pub async fn open_with_progress<F: FnMut(&RecoveryProgress) + Send>(
+        dir: PathBuf,
+        config: Config,
+        mut on_progress: F,
+    ) -> Result<DataStore<'a, Key>, Error> {
         let dir = DirPath::build(dir);
-        let store = DataStore::with_default_config(dir.clone(), SizeUnit::Bytes, config).await?;
+        let store = DataStore::with_default_config(dir.clone(), SizeUnit::Bytes, config, &mut on_progress).await?;
         store.start_background_jobs();
         return Ok(store);
     }
 
+    /// Opens an existing store for reads only, e.g. to inspect a store opened by another process
+    ///
+    /// The directory lock is not taken and nothing is written to the directory: crash leftovers are not removed,
+    /// corrupted SSTables are left in place and no flush, compaction or garbage collection is started. Writes
+    /// fail with `ReadOnlyStore`. The store sees what was written to disk up to the time it was opened.
+    pub async fn open_read_only(dir: PathBuf) -> Result<DataStore<'a, Key>, Error> {
+        let dir = DirPath::build(dir);
+        let vlog_path = dir.val_log.join(VLOG_FILE_NAME);
+        if !vlog_path.is_file() {
+            return Err(FileOpenError {
+                path: vlog_path,
+                error: std::io::Error::from(std::io::ErrorKind::NotFound),
+            });
+        }
+        let mut vlog = ValueLog::new(&dir.val_log).await?;
+        let config = Config::default();
+        vlog.set_checksum_type(config.checksum_type);
+        DataStore::recover(
+            dir,
+            vlog,
+            &config,
+            SizeUnit::Bytes,
+            None,
+            FsCapabilities::default(),
+            &mut |_| {},
+        )
+        .await
+    }
+
+    /// Returns true if the store was opened with `open_read_only`
+    pub fn is_read_only(&self) -> bool {
+        self.lock.is_none()
+    }
+
+    // Rejects writes to a store opened read-only or halted by a background error
+    fn check_writable(&self) -> Result<(), Error> {
+        if self.is_read_only() {
+            return Err(ReadOnlyStore);
+        }
+        if let Some(err) = self.background_errors.get() {
+            return Err(BackgroundError(err.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Returns the I/O error a background flush, compaction or garbage collection hit, or the error of a write
+    /// that found the disk full. Writes fail with `BackgroundError` until `resume` is called
+    pub fn last_background_error(&self) -> Option<Arc<Error>> {
+        self.background_errors.get()
+    }
+
+    /// Returns a receiver of every error that halts writes, be it hit by a background task or by a write that
+    /// found the disk full
+    pub fn subscribe_background_errors(&self) -> tokio::sync::broadcast::Receiver<Arc<Error>> {
+        self.background_errors.subscribe()
+    }
+
+    /// Resumes writes and background work halted by a background error, once its cause is addressed
+    ///
+    /// Returns the error that was cleared, `None` if writes were not halted
+    pub fn resume(&self) -> Option<Arc<Error>> {
+        let err = self.background_errors.take();
+        if let Some(err) = &err {
+            log::info!("Resuming writes halted by: {}", err);
+        }
+        err
+    }
+
     pub fn start_background_jobs(&self) {
         self.compactor.start_periodic_background_compaction(
             Arc::clone(&self.buckets),
             Arc::clone(&self.filters),
             Arc::clone(&self.key_range),
+            Arc::clone(&self.range_tombstones),
+            self.snapshots.clone(),
         );
 
         self.compactor.start_flush_listener(
@@ -104,178 +228,1094 @@ impl<'a> DataStore<'a, Key> {
             Arc::clone(&self.buckets),
             Arc::clone(&self.filters),
             Arc::clone(&self.key_range),
+            Arc::clone(&self.range_tombstones),
+            self.snapshots.clone(),
         );
 
-        self.gc.start_background_gc_task(
-            Arc::clone(&self.filters),
-            Arc::clone(&self.key_range),
-            Arc::clone(&self.read_only_memtables),
-            Arc::clone(&self.gc_updated_entries),
-        );
+        self.compactor
+            .start_hotness_decay(Arc::clone(&self.buckets), Arc::clone(&self.filters));
+
+        self.gc.start_background_gc_task(self.gc_context());
+
+        if self.config.value_log_buffer_size > 0 {
+            self.val_log
+                .start_buffer_flush_task(self.config.value_log_buffer_interval);
+        }
+
+        if self.config.value_log_sync_interval > 0 {
+            self.val_log.start_sync_task(
+                self.config.value_log_sync_interval,
+                Arc::clone(&self.gc.config.shut_down),
+                self.background_errors.clone(),
+            );
+        }
+    }
+
+    /// Inserts `key` with `val`, both can hold arbitrary bytes (`&str`, `String`, `&[u8]` or `Vec<u8>`)
+    pub async fn put(&mut self, key: impl AsRef<[u8]>, val: impl AsRef<[u8]>) -> Result<Bool, Error> {
+        self.put_with_options(key, val, &WriteOptions::default()).await
+    }
+
+    /// Same as `put` but with per-call write options
+    ///
+    /// Returns false without writing if `options` carries an idempotency token that was already applied
+    pub async fn put_with_options(
+        &mut self,
+        key: impl AsRef<[u8]>,
+        val: impl AsRef<[u8]>,
+        options: &WriteOptions,
+    ) -> Result<Bool, Error> {
+        self.write_with_options(key.as_ref(), val.as_ref(), false, options)
+            .await
+    }
+
+    // Writes a value or, if `is_tombstone` is set, a tombstone unless `options` carries an idempotency token that
+    // was already applied
+    async fn write_with_options(
+        &mut self,
+        key: &[u8],
+        val: &[u8],
+        is_tombstone: bool,
+        options: &WriteOptions,
+    ) -> Result<Bool, Error> {
+        if self.is_duplicate_write(options) {
+            return Ok(false);
+        }
+        self.write_entry(key, val, is_tombstone, None, options).await?;
+        self.record_idempotency_token(options).await?;
+        Ok(true)
+    }
+
+    fn is_duplicate_write(&self, options: &WriteOptions) -> bool {
+        options
+            .idempotency_token
+            .as_ref()
+            .is_some_and(|token| self.idempotency_tokens.contains(token))
+    }
+
+    // The value log is synced before the token is persisted, a token is never recorded for a write
+    // that a crash could lose. A crash between the two, or before the token file is synced for a write
+    // that did not ask for it, leaves the write applied without its token so a retry applies it again.
+    async fn record_idempotency_token(&mut self, options: &WriteOptions) -> Result<(), Error> {
+        if let Some(token) = &options.idempotency_token {
+            self.val_log.sync_to_disk().await?;
+            self.idempotency_tokens.insert(token, options.sync).await?;
+        }
+        Ok(())
+    }
+
+    /// Inserts `key` like `put` and returns its previous value, or `None` if the key was absent or deleted
+    ///
+    /// The previous value is read while `&mut self` is held so no other write can happen in between
+    pub async fn insert(&mut self, key: impl AsRef<[u8]>, val: impl AsRef<[u8]>) -> Result<Option<Value>, Error> {
+        let key = key.as_ref();
+        let previous = self.get(key).await?;
+        self.put(key, val).await?;
+        Ok(previous)
+    }
+
+    /// Returns the value of `key`, inserting the value computed by `f` first if the key is absent
+    ///
+    /// `f` is only called when the key is absent, the lookup and the write happen while `&mut self` is held
+    /// so concurrent callers sharing the store behind a lock never both insert
+    pub async fn get_or_insert_with<F, V>(&mut self, key: impl AsRef<[u8]>, f: F) -> Result<Value, Error>
+    where
+        F: FnOnce() -> V,
+        V: AsRef<[u8]>,
+    {
+        let key = key.as_ref();
+        if let Some(value) = self.get(key).await? {
+            return Ok(value);
+        }
+        let value = f();
+        self.put(key, value.as_ref()).await?;
+        Ok(value.as_ref().to_vec())
+    }
+
+    /// Adds `delta` to the counter stored at `key` and returns the new count, an absent key counts from 0
+    ///
+    /// Counters are stored as 8 byte little-endian integers. The read and the write happen while `&mut self`
+    /// is held so concurrent increments are never lost
+    pub async fn increment(&mut self, key: impl AsRef<[u8]>, delta: i64) -> Result<i64, Error> {
+        let key = key.as_ref();
+        let count = match self.get(key).await? {
+            Some(value) => {
+                let bytes = value
+                    .try_into()
+                    .map_err(|_| InvalidCounterValue(String::from_utf8_lossy(key).to_string()))?;
+                i64::from_le_bytes(bytes)
+            }
+            None => 0,
+        };
+        let count = count
+            .checked_add(delta)
+            .ok_or_else(|| CounterOverflow(String::from_utf8_lossy(key).to_string()))?;
+        self.write_entry(key, &count.to_le_bytes(), false, None, &WriteOptions::default())
+            .await?;
+        Ok(count)
+    }
+
+    /// Appends `bytes` to the value of `key`, an absent key is inserted with `bytes` as its value
+    ///
+    /// The concatenated value is written as a new version so reads never have to resolve a chain of
+    /// records, the read and the write happen while `&mut self` is held so concurrent appends are never lost
+    ///
+    /// Every call reads and rewrites the whole value: building a value of N bytes with appends of k bytes
+    /// writes about N²/2k bytes to the value log, all of it garbage except the last copy until garbage
+    /// collection reclaims it. Values grown by many small appends are better split over several keys.
+    pub async fn append(&mut self, key: impl AsRef<[u8]>, bytes: &[u8]) -> Result<Bool, Error> {
+        let key = key.as_ref();
+        let mut value = self.get(key).await?.unwrap_or_default();
+        value.extend_from_slice(bytes);
+        self.write_entry(key, &value, false, None, &WriteOptions::default())
+            .await
+    }
+
+    /// Inserts `key` with a value that expires `ttl` milliseconds from now
+    ///
+    /// Once expired the entry is treated as deleted by reads, it is dropped by compaction after
+    /// `tombstone_ttl` and its value log space is reclaimed by garbage collection
+    pub async fn put_with_ttl(
+        &mut self,
+        key: impl AsRef<[u8]>,
+        val: impl AsRef<[u8]>,
+        ttl: Duration,
+    ) -> Result<Bool, Error> {
+        self.write_entry(key.as_ref(), val.as_ref(), false, Some(ttl), &WriteOptions::default())
+            .await
+    }
+
+    // Tombstones are written with `is_tombstone` set, their value is the tombstone marker but a value equal to the
+    // marker is not a tombstone
+    async fn write_entry(
+        &mut self,
+        key: &[u8],
+        val: &[u8],
+        is_tombstone: bool,
+        ttl: Option<Duration>,
+        options: &WriteOptions,
+    ) -> Result<Bool, Error> {
+        self.check_writable()?;
+        self.check_entry_size(key.len(), val.len())?;
+        let _write_gate = Arc::clone(&self.gc.config.write_gate).lock_owned().await;
+        self.apply_gc_updates().await?;
+        let key = &key.to_vec();
+        let val = &val.to_vec();
+        let created_at = self.meta.sequence.next();
+        let written_at = Utc::now().timestamp_millis() as u64;
+        let expires_at = ttl.map(|ttl| written_at + ttl);
+        let v_offset = self
+            .val_log
+            .append_with_expiry(key, val, created_at, written_at, is_tombstone, expires_at)
+            .await
+            .map_err(|err| self.background_errors.halt_if_disk_full(err))?;
+        if options.sync {
+            self.val_log
+                .sync_to_disk()
+                .await
+                .map_err(|err| self.background_errors.halt_if_disk_full(err))?;
+        }
+        let entry = Entry::new(key.to_vec(), v_offset, created_at, is_tombstone)
+            .with_written_at(written_at)
+            .with_expiry(expires_at)
+            .with_inline(self.config.inline_value(val, is_tombstone, v_offset));
+        self.insert_entry(entry).await?;
+        let change = || {
+            if is_tombstone {
+                Change::Delete {
+                    sequence: created_at,
+                    key: key.to_vec(),
+                }
+            } else {
+                Change::Put {
+                    sequence: created_at,
+                    key: key.to_vec(),
+                    value: val.to_vec(),
+                }
+            }
+        };
+        self.subscriptions.notify(change, key);
+        Ok(true)
+    }
+
+    /// Inserts `key` with a value of `len` bytes read from `reader`
+    ///
+    /// The value is copied into the value log chunk by chunk so it never has to be held in memory, only the key
+    /// and the value offset are kept in the memtable. Fails with `UnexpectedEOF` if `reader` ends before `len`
+    /// bytes were read, in which case nothing is written.
+    pub async fn put_stream<R: AsyncRead + Unpin>(
+        &mut self,
+        key: impl AsRef<[u8]>,
+        reader: R,
+        len: usize,
+    ) -> Result<Bool, Error> {
+        self.check_writable()?;
+        let key = key.as_ref().to_vec();
+        self.check_entry_size(key.len(), len)?;
+        let _write_gate = Arc::clone(&self.gc.config.write_gate).lock_owned().await;
+        self.apply_gc_updates().await?;
+        let created_at = self.meta.sequence.next();
+        let written_at = Utc::now().timestamp_millis() as u64;
+        let v_offset = self
+            .val_log
+            .append_stream(&key, reader, len, created_at, written_at)
+            .await
+            .map_err(|err| self.background_errors.halt_if_disk_full(err))?;
+        self.insert_entry(Entry::new(key, v_offset, created_at, false).with_written_at(written_at))
+            .await
+    }
+
+    // Rejects keys and values bigger than `max_key_size` and `max_value_size`
+    pub(crate) fn check_entry_size(&self, key_len: usize, val_len: usize) -> Result<(), Error> {
+        if key_len > self.config.max_key_size {
+            return Err(KeyTooLarge {
+                size: key_len,
+                max: self.config.max_key_size,
+            });
+        }
+        if val_len > self.config.max_value_size {
+            return Err(ValueTooLarge {
+                size: val_len,
+                max: self.config.max_value_size,
+            });
+        }
+        Ok(())
     }
 
-    pub async fn put(&mut self, key: &str, val: &str) -> Result<Bool, Error> {
+    // Moves entries relocated by garbage collection into the active memtable
+    async fn apply_gc_updates(&mut self) -> Result<(), Error> {
         let gc_entries_reader = self.gc_updated_entries.read().await;
         if !gc_entries_reader.is_empty() {
             for e in gc_entries_reader.iter() {
-                self.active_memtable.insert(&Entry::new(
-                    e.key().to_vec(),
-                    e.value().val_offset,
-                    e.value().created_at,
-                    e.value().is_tombstone,
-                ))?;
+                self.active_memtable.insert(
+                    &Entry::new(
+                        e.key().to_vec(),
+                        e.value().val_offset,
+                        e.value().created_at,
+                        e.value().is_tombstone,
+                    )
+                    .with_written_at(e.value().written_at)
+                    .with_expiry(e.value().expires_at),
+                )?;
             }
             gc_entries_reader.clear();
         }
-        drop(gc_entries_reader);
-        let is_tombstone = val == TOMB_STONE_MARKER;
-        let key = &key.as_bytes().to_vec();
-        let val = &val.as_bytes().to_vec();
-        let created_at = Utc::now().timestamp_millis() as u64;
-        let v_offset = self.val_log.append(key, val, created_at, is_tombstone).await?;
-
-        let entry = Entry::new(key.to_vec(), v_offset, created_at, is_tombstone);
-        if self.active_memtable.is_full(HEAD_ENTRY_KEY.len()) {
-            let capacity = self.active_memtable.capacity();
-            let size_unit = self.active_memtable.size_unit();
-            let false_pos = self.active_memtable.false_positive_rate();
-            let head_offset = self.active_memtable.most_recent_entry.val_offset;
-
-            // reset head in vLog
-            self.val_log.set_head(head_offset as usize);
-            let head_entry = Entry::new(
-                HEAD_ENTRY_KEY.to_vec(),
-                head_offset,
-                Utc::now().timestamp_millis() as u64,
-                false,
-            );
-            self.active_memtable.insert(&head_entry)?;
-            self.active_memtable.read_only = true;
-            self.read_only_memtables.write().await.insert(
-                MemTable::generate_table_id(),
-                Arc::new(RwLock::new(self.active_memtable.to_owned())),
-            );
+        Ok(())
+    }
 
-            if self.read_only_memtables.read().await.len() >= self.config.max_buffer_write_number {
-                let immutable_tables = self.read_only_memtables.read().await;
-                for (table_id, table) in immutable_tables.iter() {
-                    let table_inner = Arc::clone(table);
-                    let id = table_id.clone();
-                    let mut flusher = self.flusher.clone();
-                    let tx = self.flush_signal_tx.clone();
-                    // NOTE: If the put method returns before the code inside tokio::spawn finishes executing,
-                    // the tokio::spawn task will continue to run independently of the original function call.
-                    // This is because tokio::spawn creates a new asynchronous task that is managed by the Tokio runtime.
-                    // The spawned task is executed concurrently and its lifecycle is not tied to the function that spawned it.
-                    tokio::spawn(async move {
-                        flusher.flush_handler(id, table_inner, tx);
-                    });
+    // Inserts an entry whose value was appended to the value log, the active memtable is
+    // frozen first if it cannot hold the entry. Callers hold the write gate of garbage collection
+    async fn insert_entry(&mut self, entry: Entry<Key, ValOffset>) -> Result<Bool, Error> {
+        if self.active_memtable.is_full(&entry) {
+            self.freeze_active_memtable().await?;
+        }
+        // The value the entry replaces never reaches an sstable
+        if self.gc.config.dead_bytes.is_tracked() {
+            if let Some(replaced) = self.active_memtable.get(&entry.key) {
+                match self.val_log.entry_len(replaced.val_offset).await {
+                    Ok(entry_len) => self.gc.config.dead_bytes.record(entry_len.unwrap_or_default()),
+                    Err(err) => log::warn!("Length of the value replaced by a write is unknown: {}", err),
                 }
             }
-            self.active_memtable = MemTable::with_specified_capacity_and_rate(size_unit, capacity, false_pos);
-            self.gc_table = Arc::new(RwLock::new(MemTable::with_specified_capacity_and_rate(
-                size_unit, capacity, false_pos,
-            )));
+        }
+        // A garbage collection pass running now must not relocate the value it read before this write
+        if let Some(keys) = self.gc.config.written_keys.lock().unwrap().as_mut() {
+            keys.insert(entry.key.to_owned());
         }
         self.active_memtable.insert(&entry)?;
-        let gc_table = Arc::clone(&self.gc_table);
-        tokio::spawn(async move { gc_table.write().await.insert(&entry) });
+        if ValueClass::of(entry.val_offset) != ValueClass::Small {
+            // The head must cover the pointer appended to the main log
+            self.active_memtable.track_offset(&entry, self.val_log.last_offset);
+        }
+        self.gc_table.write().await.insert(&entry)?;
         Ok(true)
     }
 
-    pub async fn delete(&mut self, key: &str) -> Result<bool, Error> {
-        self.get(key).await?;
-        let value = TOMB_STONE_MARKER;
-        self.put(key, value).await
+    // Makes the active memtable read-only and starts a new one, read-only memtables are flushed
+    // once there are `max_buffer_write_number` of them
+    async fn freeze_active_memtable(&mut self) -> Result<(), Error> {
+        let capacity = self.active_memtable.capacity();
+        let size_unit = self.active_memtable.size_unit();
+        let false_pos = self.active_memtable.false_positive_rate();
+        let avg_entry_size = self.active_memtable.avg_entry_size();
+        let head_offset = self.active_memtable.most_recent_entry.val_offset;
+
+        let head_entry = Entry::new(HEAD_ENTRY_KEY.to_vec(), head_offset, self.meta.sequence.next(), false);
+        // The SSTable written from the memtable must not point to records still buffered
+        self.val_log.write_buffer().await?;
+        // Entries before the new head are no longer replayed on recovery
+        self.meta.write().await?;
+        // reset head in vLog
+        self.val_log.set_head(head_offset as usize);
+        self.active_memtable.insert(&head_entry)?;
+        self.active_memtable.read_only = true;
+        self.read_only_memtables.write().await.insert(
+            MemTable::generate_table_id(),
+            Arc::new(RwLock::new(self.active_memtable.to_owned())),
+        );
+
+        if self.read_only_memtables.read().await.len() >= self.config.max_buffer_write_number {
+            let immutable_tables = self.read_only_memtables.read().await;
+            for (table_id, table) in immutable_tables.iter() {
+                let table_inner = Arc::clone(table);
+                let id = table_id.clone();
+                let mut flusher = self.flusher.clone();
+                let tx = self.flush_signal_tx.clone();
+                // NOTE: If the put method returns before the code inside tokio::spawn finishes executing,
+                // the tokio::spawn task will continue to run independently of the original function call.
+                // This is because tokio::spawn creates a new asynchronous task that is managed by the Tokio runtime.
+                // The spawned task is executed concurrently and its lifecycle is not tied to the function that spawned it.
+                tokio::spawn(async move {
+                    flusher.flush_handler(id, table_inner, tx);
+                });
+            }
+        }
+        self.active_memtable =
+            MemTable::with_specified_capacity_rate_and_entry_size(size_unit, capacity, false_pos, avg_entry_size);
+        // Garbage collection holds the same table
+        *self.gc_table.write().await = MemTable::with_specified_capacity_and_rate(size_unit, capacity, false_pos);
+        Ok(())
+    }
+
+    /// Deletes `key` by writing a tombstone, whether the key exists or not
+    ///
+    /// The store is not read before the write, use `delete_checked` to fail when the key is absent
+    pub async fn delete(&mut self, key: impl AsRef<[u8]>) -> Result<bool, Error> {
+        self.delete_with_options(key, &WriteOptions::default()).await
+    }
+
+    /// Same as `delete` but with per-call write options
+    pub async fn delete_with_options(&mut self, key: impl AsRef<[u8]>, options: &WriteOptions) -> Result<bool, Error> {
+        self.write_with_options(key.as_ref(), TOMB_STONE_MARKER.as_bytes(), true, options)
+            .await
+    }
+
+    /// Applies every operation of `batch` in order
+    ///
+    /// Batches bigger than `max_batch_size` are written chunk by chunk and other tasks, such as flushes, can run
+    /// between chunks. The batch is not atomic, chunks written before a failure are kept.
+    pub async fn write_batch(&mut self, batch: &WriteBatch, options: &WriteOptions) -> Result<(), Error> {
+        self.write_batch_with_progress(batch, options, |_| {}).await
+    }
+
+    /// Same as `write_batch` but calls `on_progress` after each chunk is written
+    ///
+    /// A batch carrying an idempotency token that was already applied is skipped entirely
+    pub async fn write_batch_with_progress<F: FnMut(&BatchProgress)>(
+        &mut self,
+        batch: &WriteBatch,
+        options: &WriteOptions,
+        mut on_progress: F,
+    ) -> Result<(), Error> {
+        self.check_writable()?;
+        if self.is_duplicate_write(options) {
+            return Ok(());
+        }
+        // Validated up front so an oversized operation does not leave the batch half written
+        for op in batch.ops() {
+            self.check_entry_size(op.key().len(), op.value().len())?;
+        }
+        let chunks = batch.chunks(self.config.max_batch_size);
+        let mut progress = BatchProgress {
+            chunks_written: 0,
+            total_chunks: chunks.len(),
+            ops_written: 0,
+            bytes_written: 0,
+        };
+        // The value log is synced once per chunk instead of once per operation
+        let chunk_options = WriteOptions::new(false);
+        for chunk in chunks {
+            for op in chunk {
+                self.write_entry(op.key(), op.value(), op.is_tombstone(), None, &chunk_options)
+                    .await?;
+                progress.bytes_written += op.size();
+            }
+            if options.sync {
+                self.val_log.sync_to_disk().await?;
+            }
+            progress.chunks_written += 1;
+            progress.ops_written += chunk.len();
+            on_progress(&progress);
+            tokio::task::yield_now().await;
+        }
+        self.record_idempotency_token(options).await
+    }
+
+    /// Persists `batch` without applying it and returns the token to `commit` or `rollback` it with
+    ///
+    /// This is the first phase of a two-phase commit. The batch survives a restart until it is resolved,
+    /// `prepared` lists the batches waiting for a decision. Keys written by the batch are not locked,
+    /// writes made before the commit are overwritten by it.
+    pub async fn prepare(&mut self, batch: &WriteBatch) -> Result<PreparedToken, Error> {
+        self.check_writable()?;
+        for op in batch.ops() {
+            self.check_entry_size(op.key().len(), op.value().len())?;
+        }
+        // Sequence numbers are never handed out twice so neither are tokens
+        let token = PreparedToken::new(self.meta.sequence.next());
+        self.prepared_batches.insert(token, batch).await?;
+        Ok(token)
+    }
+
+    /// Applies the batch prepared under `token`, returns `UnknownPreparedToken` if there is none
+    ///
+    /// The batch is written with an idempotency token, committing again after a crash that happened before
+    /// the batch was resolved does not apply it twice.
+    pub async fn commit(&mut self, token: PreparedToken) -> Result<(), Error> {
+        let batch = self
+            .prepared_batches
+            .get(token)
+            .cloned()
+            .ok_or(UnknownPreparedToken(token.id()))?;
+        let options = WriteOptions::new(true).with_idempotency_token(format!("prepared_{}", token.id()));
+        self.write_batch(&batch, &options).await?;
+        self.prepared_batches.remove(token).await?;
+        Ok(())
+    }
+
+    /// Discards the batch prepared under `token`, returns `UnknownPreparedToken` if there is none
+    pub async fn rollback(&mut self, token: PreparedToken) -> Result<(), Error> {
+        self.check_writable()?;
+        if !self.prepared_batches.remove(token).await? {
+            return Err(UnknownPreparedToken(token.id()));
+        }
+        Ok(())
+    }
+
+    /// Returns the tokens of the batches prepared but not yet committed or rolled back, oldest first
+    pub fn prepared(&self) -> Vec<PreparedToken> {
+        self.prepared_batches.tokens()
+    }
+
+    /// Deletes `key` if it exists, returns `NotFoundInDB` otherwise
+    pub async fn delete_checked(&mut self, key: impl AsRef<[u8]>) -> Result<bool, Error> {
+        let key = key.as_ref();
+        if self.get(key).await?.is_none() {
+            return Err(NotFoundInDB);
+        }
+        self.delete(key).await
+    }
+
+    /// Deletes `key` only if its current value equals `expected`
+    ///
+    /// Returns true if the tombstone was written. The comparison and the write happen while `&mut self`
+    /// is held so no other write can change the value in between
+    pub async fn delete_if(&mut self, key: impl AsRef<[u8]>, expected: &[u8]) -> Result<bool, Error> {
+        let key = key.as_ref();
+        match self.get(key).await? {
+            Some(value) if value == expected => self.delete(key).await,
+            _ => Ok(false),
+        }
+    }
+
+    /// Deletes every key in `[start, end)` with a single range tombstone instead of one tombstone per key
+    ///
+    /// Keys written after `delete_range` returns are not affected. Deleted versions are dropped from SSTables
+    /// when they are compacted, their value log space is then reclaimed by garbage collection.
+    pub async fn delete_range(&mut self, start: impl AsRef<[u8]>, end: impl AsRef<[u8]>) -> Result<(), Error> {
+        self.check_writable()?;
+        let (start, end) = (start.as_ref(), end.as_ref());
+        if start >= end {
+            return Ok(());
+        }
+        // Writes made after this call get a bigger sequence number and are never covered
+        let created_at = self.meta.sequence.next();
+        let written_at = Utc::now().timestamp_millis() as u64;
+        let tombstone = RangeTombstone::new(start.to_vec(), end.to_vec(), created_at, written_at);
+        self.range_tombstones.write().await.insert(tombstone).await
+    }
+
+    /// Deletes every key in `[start, end)` like `delete_range` and removes the SSTables whose keys all lie in the
+    /// range right away, returns the number of SSTables removed
+    ///
+    /// The SSTables are removed from their buckets, the manifest and the disk without being rewritten. SSTables
+    /// overlapping the edges of the range and keys still held by memtables are dropped when they are compacted.
+    /// No SSTable is removed while a snapshot or a read within the retention window can still read the deleted
+    /// versions.
+    pub async fn delete_files_in_range(
+        &mut self,
+        start: impl AsRef<[u8]>,
+        end: impl AsRef<[u8]>,
+    ) -> Result<usize, Error> {
+        let (start, end) = (start.as_ref().to_vec(), end.as_ref().to_vec());
+        // The range tombstone hides the versions of the SSTables overlapping the edges, these SSTables might
+        // otherwise hold older versions that the removed SSTables shadowed
+        self.delete_range(&start, &end).await?;
+        if start >= end
+            || self
+                .snapshots
+                .oldest_readable(self.compactor.config.version_retention)
+                .is_some()
+        {
+            return Ok(0);
+        }
+        let contained = self.sstables_in_range(&start, &end).await?;
+        if contained.is_empty() {
+            return Ok(0);
+        }
+        // Readers either pinned the SSTables already or no longer see them
+        let mut key_range = self.key_range.write().await;
+        for sst in contained.iter() {
+            key_range.remove(sst.get_data_file_path());
+        }
+        drop(key_range);
+
+        let mut buckets = self.buckets.write().await;
+        let mut ssts_to_remove: SSTablesToRemove = Vec::new();
+        for sst in contained.iter() {
+            let Some(bucket_id) = buckets.bucket_id_of(sst) else {
+                continue;
+            };
+            match ssts_to_remove.iter_mut().find(|(id, _)| *id == bucket_id) {
+                Some((_, ssts)) => ssts.push(sst.to_owned()),
+                None => ssts_to_remove.push((bucket_id, vec![sst.to_owned()])),
+            }
+        }
+        if !buckets.delete_ssts(&ssts_to_remove).await? {
+            log::error!("{}", CannotRemoveObsoleteSSTError);
+        }
+        drop(buckets);
+        self.filters
+            .write()
+            .await
+            .retain(|filter| !contained.iter().any(|sst| sst.dir == filter.get_sst().dir));
+        Ok(contained.len())
+    }
+
+    // Returns the SSTables whose keys all lie in `[start, end)`, the head and tail entries of the value log are
+    // left out since their offsets are persisted in the meta file
+    async fn sstables_in_range(&self, start: &Key, end: &Key) -> Result<Vec<Table>, Error> {
+        let internal_keys = [HEAD_ENTRY_KEY.to_vec(), TAIL_ENTRY_KEY.to_vec()];
+        let is_contained = |key: &Key| internal_keys.contains(key) || (start <= key && key < end);
+        let candidates: Vec<Range> = self
+            .key_range
+            .read()
+            .await
+            .overlapping(&(start.to_owned()..end.to_owned()))
+            .into_iter()
+            .cloned()
+            .collect();
+        let mut contained = Vec::new();
+        for range in candidates {
+            if start <= &range.smallest_key && range.biggest_key < *end {
+                contained.push(range.sst);
+                continue;
+            }
+            // Every SSTable written by a flush holds the head entry, its bounds say nothing of the other keys
+            if !internal_keys
+                .iter()
+                .any(|key| range.overlaps(&(key.to_owned()..=key.to_owned())))
+            {
+                continue;
+            }
+            let table = range.sst.load_entries_from_file(true).await?;
+            if table.entries.iter().all(|entry| is_contained(entry.key())) {
+                contained.push(range.sst);
+            }
+        }
+        Ok(contained)
+    }
+
+    /// Returns the value of `key`, or `None` if the key was never inserted or has been deleted
+    ///
+    /// An error is only returned when the store could not be read
+    pub async fn get(&self, key: impl AsRef<[u8]>) -> Result<Option<Value>, Error> {
+        self.get_with_options(key, &ReadOptions::default()).await
+    }
+
+    /// Same as `get` but with per-call read options
+    pub async fn get_with_options(&self, key: impl AsRef<[u8]>, options: &ReadOptions) -> Result<Option<Value>, Error> {
+        let _pin = self.gc.config.read_pins.pin();
+        let key = key.as_ref().to_vec();
+        match self.lookup(&key, options).await? {
+            Some((_, created_at, _, false, _)) if self.is_range_deleted(&key, created_at, options).await => Ok(None),
+            Some((_, _, _, false, Some(inline))) => Ok(Some(inline)),
+            Some((offset, _, _, false, None)) => match self.val_log.get(offset).await? {
+                Some((value, false)) => Ok(Some(value)),
+                Some((_, true)) => Ok(None),
+                None => Err(KeyNotFoundInValueLogError),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns a reader over the value of `key`, or `None` if the key was never inserted or has been deleted
+    ///
+    /// The value is read from the value log as the reader is polled instead of being loaded in memory,
+    /// suitable for values written with `put_stream`. The reader should be drained promptly, garbage
+    /// collection can reclaim the space of a value that was overwritten or deleted in the meantime.
+    pub async fn get_stream(&self, key: impl AsRef<[u8]>) -> Result<Option<ValueReader>, Error> {
+        let _pin = self.gc.config.read_pins.pin();
+        let options = ReadOptions::default();
+        let key = key.as_ref().to_vec();
+        match self.lookup(&key, &options).await? {
+            Some((_, created_at, _, false, _)) if self.is_range_deleted(&key, created_at, &options).await => Ok(None),
+            Some((_, _, _, false, Some(inline))) => Ok(Some(Box::new(std::io::Cursor::new(inline)))),
+            Some((offset, _, _, false, None)) => match self.val_log.get_stream(offset).await? {
+                Some((reader, false)) => Ok(Some(reader)),
+                Some((_, true)) => Ok(None),
+                None => Err(KeyNotFoundInValueLogError),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the newest version of `key` written at or before `timestamp`, a wall-clock time in milliseconds
+    ///
+    /// Older versions are only found while they are still held by another memtable or SSTable than the newer
+    /// ones, a version overwritten within the same memtable or discarded by compaction returns `None`
+    pub async fn get_at(&self, key: impl AsRef<[u8]>, timestamp: WriteTime) -> Result<Option<Value>, Error> {
+        let options = ReadOptions {
+            as_of: Some(timestamp),
+            ..ReadOptions::default()
+        };
+        self.get_with_options(key, &options).await
+    }
+
+    /// Returns a snapshot of the current state of the store
+    ///
+    /// Reads made with `snapshot.read_options()` ignore every later write. The active memtable is frozen so that
+    /// later writes to a key do not replace the version the snapshot reads, compaction and garbage collection
+    /// hold back while the snapshot is alive, it should be dropped as soon as it is no longer needed.
+    pub async fn snapshot(&mut self) -> Result<Snapshot, Error> {
+        // A read-only store receives no writes so its active memtable never has to be frozen
+        if self.active_memtable.size() > 0 && !self.is_read_only() {
+            self.freeze_active_memtable().await?;
+        }
+        // Writes made after this call get a bigger sequence number and are never visible
+        let sequence = self.meta.sequence.last();
+        Ok(self.snapshots.pin(sequence))
+    }
+
+    /// Waits until the advisory lock of `key` is free and takes it, the lock is released when the guard is dropped
+    ///
+    /// Locks let tasks sharing the store coordinate on keys, they are granted in the order they were asked for.
+    /// The store does not check them, a write to a locked key made without its lock goes through. Tasks sharing
+    /// the store behind a lock can wait on a clone of `key_locks` instead so they do not hold the store meanwhile.
+    pub async fn lock(&self, key: impl AsRef<[u8]>) -> KeyLockGuard {
+        self.key_locks.lock(key.as_ref()).await
+    }
+
+    /// Returns a receiver of an event for every put and delete of a key starting with `prefix`, an empty prefix
+    /// watches every key
+    ///
+    /// Events are sent once the write is in the memtable. A receiver lagging more than 1024 events behind misses
+    /// the oldest ones and gets `RecvError::Lagged`. Values written with `put_stream`, range deletions and values
+    /// relocated by garbage collection are not sent.
+    pub fn subscribe(&self, prefix: impl AsRef<[u8]>) -> tokio::sync::broadcast::Receiver<ChangeEvent> {
+        self.subscriptions
+            .subscribe(prefix.as_ref(), DEFAULT_SUBSCRIPTION_CHANNEL_SIZE)
+    }
+
+    /// Returns what was found and cleaned up while opening the store
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery_report
+    }
+
+    /// Returns what the file system holding the value log supports, garbage collection and the value log only use
+    /// what it does
+    pub fn fs_capabilities(&self) -> FsCapabilities {
+        self.fs_capabilities
     }
 
-    pub async fn get(&self, key: &str) -> Result<(Value, CreationTime), Error> {
-        let key = key.as_bytes().to_vec();
+    /// Returns the cache of the SSTable blocks, its hits and misses tell how often gets and range queries read
+    /// blocks from disk
+    pub fn block_cache(&self) -> &BlockCache {
+        &self.block_cache
+    }
+
+    /// Returns true if `key` exists in the store
+    ///
+    /// Memtables and SSTables are searched but the value is never read from the value log
+    pub async fn contains_key(&self, key: impl AsRef<[u8]>) -> bool {
+        let key = key.as_ref().to_vec();
+        let options = ReadOptions::default();
+        match self.lookup(&key, &options).await {
+            Ok(Some((_, created_at, _, false, _))) => !self.is_range_deleted(&key, created_at, &options).await,
+            Err(err) => {
+                log::error!("{}", err);
+                false
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns false if `key` is definitely not in the store
+    ///
+    /// Only memtables and bloom filters are consulted, no file is read, so `true` might be a false positive
+    pub async fn key_may_exist(&self, key: impl AsRef<[u8]>) -> bool {
+        let key = key.as_ref().to_vec();
+        if let Some(e) = self.gc_updated_entries.read().await.get(&key) {
+            return !e.value().is_deleted();
+        }
+        if let Some(value) = self.active_memtable.get(&key) {
+            return !value.is_deleted();
+        }
+        let mut most_recent: Option<SkipMapValue<ValOffset>> = None;
+        for (_, table) in self.read_only_memtables.read().await.iter() {
+            if let Some(value) = table.read().await.get(&key) {
+                if most_recent.as_ref().is_none_or(|m| value.created_at > m.created_at) {
+                    most_recent = Some(value);
+                }
+            }
+        }
+        if let Some(value) = most_recent {
+            return !value.is_deleted();
+        }
+        let ssts = self.key_range.read().await.filter_sstables_by_biggest_key(&key);
+        if ssts.is_empty() {
+            return false;
+        }
+        !BloomFilter::ssts_within_key_range(&key, &*self.filters.read().await, &ssts).is_empty()
+    }
+
+    /// Returns an estimate of the number of keys in the store without scanning it
+    ///
+    /// The estimate adds up the entries held by memtables and the number of keys recorded in the bloom filter of
+    /// each SSTable when it was written. Keys overwritten or deleted across tables are counted once per table.
+    pub async fn estimate_num_keys(&self) -> usize {
+        let internal_keys = [HEAD_ENTRY_KEY.to_vec(), TAIL_ENTRY_KEY.to_vec()];
+        let memtable_keys = |entries: &SkipMapEntries<Key>| {
+            entries.len() - internal_keys.iter().filter(|k| entries.contains_key(*k)).count()
+        };
+        let mut num_keys = memtable_keys(&self.active_memtable.entries);
+        for (_, table) in self.read_only_memtables.read().await.iter() {
+            num_keys += memtable_keys(&table.read().await.entries);
+        }
+        for filter in self.filters.read().await.iter() {
+            let internal_keys_in_filter = internal_keys.iter().filter(|k| filter.contains(*k)).count();
+            num_keys += filter.num_elements().saturating_sub(internal_keys_in_filter);
+        }
+        num_keys
+    }
+
+    // Returns the value offset, sequence number, write time, deleted flag and inline value of the most recent version
+    // of `key` visible to `options`, expired versions are reported as deleted
+    //
+    // Errors reading an SSTable are logged and the SSTable skipped, except for checksum mismatches
+    pub(crate) async fn lookup(&self, key: &Key, options: &ReadOptions) -> Result<Option<FoundEntry>, Error> {
+        let mut cost = ReadCost::default();
+        let res = self.lookup_with_cost(key, options, &mut cost).await;
+        self.compactor.config.read_amp.record(&cost);
+        res
+    }
+
+    // Same as `lookup`, counts the memtables, bloom filters and SSTables consulted in `cost`
+    async fn lookup_with_cost(
+        &self,
+        key: &Key,
+        options: &ReadOptions,
+        cost: &mut ReadCost,
+    ) -> Result<Option<FoundEntry>, Error> {
         let gc_entries_reader = self.gc_updated_entries.read().await;
         if !gc_entries_reader.is_empty() {
-            let res = gc_entries_reader.get(&key);
-            if res.is_some() {
-                let value = res.to_owned().unwrap().value().to_owned();
-                if value.is_tombstone {
-                    return Err(NotFoundInDB);
-                }
-                return self.get_value_from_vlog(value.val_offset, value.created_at).await;
+            let res = gc_entries_reader.get(key);
+            if let Some(entry) = res.filter(|e| options.is_visible(e.value().created_at, e.value().written_at)) {
+                let value = entry.value().to_owned();
+                let is_deleted = value.is_deleted();
+                return Ok(Some((
+                    value.val_offset,
+                    value.created_at,
+                    value.written_at,
+                    is_deleted,
+                    value.inline,
+                )));
             }
         }
         drop(gc_entries_reader);
         let mut offset = 0;
         let mut most_recent_insert_time = 0;
+        let mut most_recent_write_time = 0;
+        let mut inline = None;
         // Step 1: Check the active memtable
-        if let Some(value) = self.active_memtable.get(&key) {
-            if value.is_tombstone {
-                return Err(NotFoundInDB);
-            }
-            return self.get_value_from_vlog(value.val_offset, value.created_at).await;
+        cost.memtables += 1;
+        if let Some(value) = self
+            .active_memtable
+            .get(key)
+            .filter(|v| options.is_visible(v.created_at, v.written_at))
+        {
+            let is_deleted = value.is_deleted();
+            return Ok(Some((
+                value.val_offset,
+                value.created_at,
+                value.written_at,
+                is_deleted,
+                value.inline,
+            )));
         } else {
             // Step 2: Check the read-only memtables
             let mut is_deleted = false;
             for (_, table) in self.read_only_memtables.read().await.iter() {
-                if let Some(value) = table.read().await.get(&key) {
-                    if value.created_at > most_recent_insert_time {
+                cost.memtables += 1;
+                if let Some(value) = table.read().await.get(key) {
+                    if value.created_at > most_recent_insert_time
+                        && options.is_visible(value.created_at, value.written_at)
+                    {
                         offset = value.val_offset;
                         most_recent_insert_time = value.created_at;
-                        is_deleted = value.is_tombstone
+                        most_recent_write_time = value.written_at;
+                        is_deleted = value.is_deleted();
+                        inline = value.inline;
                     }
                 }
             }
-            if self.found_in_table(most_recent_insert_time)  {
-                if is_deleted {
-                    return Err(NotFoundInDB);
-                }
-                return self.get_value_from_vlog(offset, most_recent_insert_time).await;
+            if self.found_in_table(most_recent_insert_time) {
+                return Ok(Some((
+                    offset,
+                    most_recent_insert_time,
+                    most_recent_write_time,
+                    is_deleted,
+                    inline,
+                )));
+            } else if !options.reads_sstables() {
+                return Ok(None);
             } else {
                 // Step 3: Check sstables
                 let key_range = &self.key_range.read().await;
-                let mut ssts = key_range.filter_sstables_by_biggest_key(&key);
+                let mut ssts = key_range.filter_sstables_by_biggest_key(key);
                 if ssts.is_empty() {
-                    return Err(NotFoundInDB);
+                    return Ok(None);
                 }
                 let filters = &self.filters.read().await;
-                ssts = BloomFilter::ssts_within_key_range(&key, filters, &ssts);
+                cost.filters += ssts.len() as u64;
+                ssts = BloomFilter::ssts_within_key_range(key, filters, &ssts);
                 if ssts.is_empty() {
-                    return Err(NotFoundInDB);
+                    return Ok(None);
                 }
                 for sst in ssts.iter() {
+                    cost.sstables += 1;
                     let index = Index::new(sst.index_file.path.to_owned(), sst.index_file.file.to_owned());
-                    let block_handle = index.get(&key).await;
+                    let block_handle = index.get(key).await;
                     match block_handle {
                         Ok(None) => continue,
                         Ok(result) => {
                             if let Some(block_offset) = result {
-                                let sst_res = sst.get(block_offset, &key).await;
+                                let sst_res = sst
+                                    .get(
+                                        block_offset,
+                                        key,
+                                        options.verify_checksums,
+                                        options.fill_cache.then_some(&self.block_cache),
+                                    )
+                                    .await;
                                 match sst_res {
                                     Ok(None) => continue,
                                     Ok(result) => {
-                                        if let Some((val_offset, created_at, is_tombstone)) = result {
-                                            if created_at > most_recent_insert_time {
+                                        if let Some((val_offset, created_at, written_at, is_tombstone, value)) = result
+                                        {
+                                            if created_at > most_recent_insert_time
+                                                && options.is_visible(created_at, written_at)
+                                            {
                                                 offset = val_offset;
                                                 most_recent_insert_time = created_at;
+                                                most_recent_write_time = written_at;
                                                 is_deleted = is_tombstone;
+                                                inline = value;
                                             }
                                         }
                                     }
+                                    // A corrupted block may hide the most recent version of the key
+                                    Err(err @ ChecksumMismatch { .. }) => return Err(err),
                                     Err(err) => log::error!("{}", err),
                                 }
                             }
                         }
+                        Err(err @ ChecksumMismatch { .. }) => return Err(err),
                         Err(err) => log::error!("{}", err),
                     }
                 }
                 if self.found_in_table(most_recent_insert_time) {
-                    if is_deleted {
-                        return Err(NotFoundInDB);
+                    return Ok(Some((
+                        offset,
+                        most_recent_insert_time,
+                        most_recent_write_time,
+                        is_deleted,
+                        inline,
+                    )));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Retrieves the values of several keys at once, the result at index `i` is the value of `keys[i]`
+    ///
+    /// Memtables and bloom filters are checked for every key first, then each SSTable index is opened once
+    /// for all the keys it may contain and values are read from the value log in offset order
+    pub async fn multi_get<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<Vec<Option<Value>>, Error> {
+        self.multi_get_with_options(keys, &ReadOptions::default()).await
+    }
+
+    /// Same as `multi_get` but with per-call read options
+    pub async fn multi_get_with_options<K: AsRef<[u8]>>(
+        &self,
+        keys: &[K],
+        options: &ReadOptions,
+    ) -> Result<Vec<Option<Value>>, Error> {
+        let _pin = self.gc.config.read_pins.pin();
+        let keys: Vec<Key> = keys.iter().map(|k| k.as_ref().to_vec()).collect();
+        // (value offset, sequence number, write time, is deleted, inline value) of the most recent version found for
+        // each key
+        let mut found: Vec<Option<FoundEntry>> = vec![None; keys.len()];
+
+        // Step 1: Check GC updated entries and memtables
+        let gc_entries_reader = self.gc_updated_entries.read().await;
+        let read_only_memtables = self.read_only_memtables.read().await;
+        for (i, key) in keys.iter().enumerate() {
+            if let Some(e) = gc_entries_reader
+                .get(key)
+                .filter(|e| options.is_visible(e.value().created_at, e.value().written_at))
+            {
+                let value = e.value().to_owned();
+                let is_deleted = value.is_deleted();
+                found[i] = Some((
+                    value.val_offset,
+                    value.created_at,
+                    value.written_at,
+                    is_deleted,
+                    value.inline,
+                ));
+                continue;
+            }
+            if let Some(value) = self
+                .active_memtable
+                .get(key)
+                .filter(|v| options.is_visible(v.created_at, v.written_at))
+            {
+                let is_deleted = value.is_deleted();
+                found[i] = Some((
+                    value.val_offset,
+                    value.created_at,
+                    value.written_at,
+                    is_deleted,
+                    value.inline,
+                ));
+                continue;
+            }
+            for (_, table) in read_only_memtables.iter() {
+                if let Some(value) = table.read().await.get(key) {
+                    if !options.is_visible(value.created_at, value.written_at) {
+                        continue;
+                    }
+                    if found[i]
+                        .as_ref()
+                        .is_none_or(|(_, created_at, _, _, _)| value.created_at > *created_at)
+                    {
+                        let is_deleted = value.is_deleted();
+                        found[i] = Some((
+                            value.val_offset,
+                            value.created_at,
+                            value.written_at,
+                            is_deleted,
+                            value.inline,
+                        ));
+                    }
+                }
+            }
+        }
+        drop(read_only_memtables);
+        drop(gc_entries_reader);
+
+        // Step 2: Group the remaining keys by the SSTables that may contain them
+        let mut ssts_to_keys: IndexMap<PathBuf, (Table, Vec<usize>)> = IndexMap::new();
+        if options.reads_sstables() {
+            let key_range = self.key_range.read().await;
+            let filters = self.filters.read().await;
+            for (i, key) in keys.iter().enumerate() {
+                if found[i].is_some() {
+                    continue;
+                }
+                let ssts = key_range.filter_sstables_by_biggest_key(key);
+                if ssts.is_empty() {
+                    continue;
+                }
+                for sst in BloomFilter::ssts_within_key_range(key, &filters, &ssts) {
+                    ssts_to_keys
+                        .entry(sst.get_data_file_path())
+                        .or_insert_with(|| (sst.to_owned(), Vec::new()))
+                        .1
+                        .push(i);
+                }
+            }
+        }
+
+        // Step 3: Open every SSTable index once and look up all its keys
+        for (_, (sst, key_indexes)) in ssts_to_keys.iter() {
+            let index = Index::new(sst.index_file.path.to_owned(), sst.index_file.file.to_owned());
+            for i in key_indexes.iter() {
+                let key = &keys[*i];
+                let block_offset = match index.get(key).await {
+                    Ok(Some(block_offset)) => block_offset,
+                    Ok(None) => continue,
+                    Err(err @ ChecksumMismatch { .. }) => return Err(err),
+                    Err(err) => {
+                        log::error!("{}", err);
+                        continue;
                     }
-                    // Step 5: Read value from value log based on offset
-                    return self.get_value_from_vlog(offset, most_recent_insert_time).await;
+                };
+                match sst
+                    .get(
+                        block_offset,
+                        key,
+                        options.verify_checksums,
+                        options.fill_cache.then_some(&self.block_cache),
+                    )
+                    .await
+                {
+                    Ok(Some((val_offset, created_at, written_at, is_tombstone, inline))) => {
+                        if options.is_visible(created_at, written_at)
+                            && found[*i]
+                                .as_ref()
+                                .is_none_or(|(_, most_recent, _, _, _)| created_at > *most_recent)
+                        {
+                            found[*i] = Some((val_offset, created_at, written_at, is_tombstone, inline));
+                        }
+                    }
+                    Ok(None) => continue,
+                    Err(err @ ChecksumMismatch { .. }) => return Err(err),
+                    Err(err) => log::error!("{}", err),
                 }
             }
         }
-        Err(NotFoundInDB)
+
+        let range_tombstones = self.range_tombstones.read().await;
+        for (i, key) in keys.iter().enumerate() {
+            if let Some((_, created_at, _, false, _)) = found[i] {
+                if range_tombstones.covers(key, created_at, options) {
+                    found[i] = None;
+                }
+            }
+        }
+        drop(range_tombstones);
+
+        // Step 4: Read values from the value log in a single sweep sorted by offset to avoid random seeks, inline
+        // values are already known
+        let mut values: Vec<Option<Value>> = vec![None; keys.len()];
+        let mut to_read: Vec<usize> = Vec::new();
+        let mut locations: Vec<ValOffset> = Vec::new();
+        for (i, f) in found.into_iter().enumerate() {
+            match f {
+                Some((_, _, _, false, Some(inline))) => values[i] = Some(inline),
+                Some((val_offset, _, _, false, None)) => {
+                    to_read.push(i);
+                    locations.push(val_offset);
+                }
+                _ => (),
+            }
+        }
+        for (i, value) in to_read.into_iter().zip(self.val_log.get_many(&locations).await?) {
+            match value {
+                Some((value, false)) => values[i] = Some(value),
+                Some((_, true)) => continue,
+                None => return Err(KeyNotFoundInValueLogError),
+            }
+        }
+        Ok(values)
+    }
+
+    // Returns true if the version of `key` created at `created_at` is deleted by a range tombstone visible to `options`
+    pub(crate) async fn is_range_deleted(&self, key: &[u8], created_at: CreationTime, options: &ReadOptions) -> bool {
+        self.range_tombstones.read().await.covers(key, created_at, options)
     }
 
     pub fn found_in_table(&self, most_recent_insert_time: u64)-> bool {
@@ -299,12 +1339,17 @@ impl<'a> DataStore<'a, Key> {
         };
     }
 
-    pub async fn update(&mut self, key: &str, value: &str) -> Result<bool, Error> {
-        self.get(key).await?;
+    pub async fn update(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Result<bool, Error> {
+        let key = key.as_ref();
+        if self.get(key).await?.is_none() {
+            return Err(NotFoundInDB);
+        }
         self.put(key, value).await
     }
     // Flush all memtables
     pub async fn flush_all_memtables(&mut self) -> Result<(), Error> {
+        self.check_writable()?;
+        self.val_log.write_buffer().await?;
         self.active_memtable.read_only = true;
         self.read_only_memtables.write().await.insert(
             MemTable::generate_table_id(),
@@ -316,7 +1361,10 @@ impl<'a> DataStore<'a, Key> {
             Arc::clone(&self.buckets),
             Arc::clone(&self.filters),
             Arc::clone(&self.key_range),
-        );
+            self.meta.clone(),
+            self.background_errors.clone(),
+        )
+        .with_compaction_progress(self.compactor.progress.clone());
         for (_, table) in immutable_tables.iter() {
             let table_inner = Arc::clone(table);
             flusher.flush(table_inner).await?;
@@ -330,36 +1378,179 @@ impl<'a> DataStore<'a, Key> {
         dir: DirPath,
         size_unit: SizeUnit,
         config: Config,
+        on_progress: &mut (dyn FnMut(&RecoveryProgress) + Send),
     ) -> Result<DataStore<'a, types::Key>, Error> {
-        Self::with_capacity_and_rate(dir, size_unit, config).await
+        Self::with_capacity_and_rate(dir, size_unit, config, on_progress).await
     }
 
     async fn with_capacity_and_rate(
         dir: DirPath,
         size_unit: SizeUnit,
         config: Config,
+        on_progress: &mut (dyn FnMut(&RecoveryProgress) + Send),
     ) -> Result<DataStore<'a, types::Key>, Error> {
+        FileNode::create_dir_all(dir.root.to_owned()).await?;
+        let lock = LockFile::acquire(dir.lock.to_owned())?;
         let vlog_path = &dir.clone().val_log;
-        let buckets_path = dir.buckets.clone();
         let vlog_exit = vlog_path.exists();
         let vlog_empty = !vlog_exit || fs::metadata(vlog_path).await.map_err(GetFileMetaDataError)?.len() == 0;
-        let key_range = KeyRange::new();
-        let vlog = ValueLog::new(vlog_path).await?;
-        let meta = Meta::new(&dir.meta);
+        let mut vlog = ValueLog::new(vlog_path).await?;
+        vlog.set_checksum_type(config.checksum_type);
+        vlog.set_compression(config.value_compression, config.value_compression_threshold);
+        vlog.set_class_thresholds(config.medium_value_threshold, config.large_value_threshold)
+            .await?;
+        vlog.set_write_buffer(config.value_log_buffer_size, config.value_log_buffer_interval)
+            .await?;
+        let fs_capabilities = FsCapabilities::probe(vlog_path).await;
+        if fs_capabilities.fallocate {
+            vlog.set_preallocation(config.value_log_preallocation_size);
+        } else if config.value_log_preallocation_size > 0 {
+            log::warn!("File system cannot allocate space ahead, value log preallocation is disabled");
+        }
         if vlog_empty {
-            return DataStore::handle_empty_vlog(dir, buckets_path, vlog, key_range, &config, size_unit, meta).await;
+            // Nothing to load nor replay, the store is reported as opened straight away
+            on_progress(&RecoveryProgress::default());
+            return DataStore::handle_empty_vlog(dir, vlog, &config, size_unit, lock, fs_capabilities).await;
+        }
+        return DataStore::recover(dir, vlog, &config, size_unit, Some(lock), fs_capabilities, on_progress).await;
+    }
+
+    /// Syncs the value log and the meta file to disk and releases the directory lock
+    ///
+    /// Background compaction is stopped, a running compaction is cancelled and waited for.
+    pub async fn close(mut self) -> Result<(), Error> {
+        if self.is_read_only() {
+            return Ok(());
         }
-        return DataStore::recover(dir, buckets_path, vlog, key_range, &config, size_unit, meta).await;
+        self.compactor.config.cancel.shut_down();
+        self.compactor.progress.idle().await;
+        // A garbage collection pass running in the background would go on appending to the value log
+        self.gc.config.shut_down.store(true, Ordering::SeqCst);
+        self.gc.config.read_pins.shut_down();
+        let _pass = self.gc.config.pass.lock().await;
+        self.val_log.sync_to_disk().await?;
+        self.meta.write().await
+    }
+
+    /// Limits compaction reads and writes to `bytes_per_sec`, 0 removes the limit
+    ///
+    /// Applies to running compactions as well as later ones.
+    pub fn set_compaction_rate_limit(&mut self, bytes_per_sec: u64) {
+        self.config.compaction_rate_limit = bytes_per_sec;
+        self.compactor.config.rate_limiter.set_rate(bytes_per_sec);
+    }
+
+    /// Waits until background compaction covered every memtable flushed before the call
+    ///
+    /// The flush listener is woken up rather than waited for, the future resolves once a background compaction
+    /// round that started after the flushes is done and none is running. Memtables flushed in the background
+    /// after the call are not waited for. Returns the background error that halted writes if one is recorded, no
+    /// compaction runs until `resume` is called.
+    pub async fn wait_for_compaction(&self) -> Result<(), Error> {
+        self.compactor.progress.wait(&self.background_errors).await
+    }
+
+    /// Cancels the compactions running now, background or started by `run_compaction`, later ones run
+    ///
+    /// A cancelled compaction discards the SSTables it wrote and keeps the ones it was merging, it returns
+    /// `Error::CompactionCancelled`.
+    pub fn cancel_compaction(&self) {
+        self.compactor.config.cancel.cancel();
+    }
+
+    /// Returns how many memtables, bloom filters and SSTables the gets made since the store was opened consulted
+    ///
+    /// Compaction also merges buckets of two SSTables once gets read `Config::read_amp_compaction_threshold`
+    /// SSTables on average.
+    pub fn read_amplification(&self) -> ReadAmpStats {
+        self.compactor.config.read_amp.stats()
+    }
+
+    /// Returns the size of the value log, the estimate of its garbage and what garbage collection reclaimed of it
+    /// since the store was opened
+    ///
+    /// Live bytes growing along with garbage bytes while the last garbage collection gets older means garbage
+    /// collection does not keep up with the writes.
+    pub async fn value_log_stats(&self) -> ValueLogStats {
+        // Garbage collection moves the tail of its own handle on the value log
+        let vlog = self.gc_log.read().await;
+        let end = vlog.end_offset().await;
+        let mut total_bytes = end - vlog.base();
+        for class in ValueClass::SEPARATE {
+            if let Some(log) = vlog.class_log(class) {
+                total_bytes += log.end_offset().await - log.base();
+            }
+        }
+        ValueLogStats {
+            total_bytes: total_bytes as u64,
+            live_bytes: end.saturating_sub(vlog.tail_offset) as u64,
+            garbage_bytes: self.gc.config.dead_bytes.bytes(),
+            punched_bytes: self.gc.config.stats.punched_bytes(),
+            last_gc: self.gc.config.stats.last_pass(),
+        }
+    }
+
+    /// Returns what the last garbage collection runs did since the store was opened, oldest first
+    ///
+    /// A run is the chunks background garbage collection collects back to back once woken up, or the single pass
+    /// of `DataStore::run_gc`. Only the last 64 runs are kept, `Config::event_listener` is notified of every run.
+    pub fn gc_history(&self) -> Vec<GCRun> {
+        self.gc.config.stats.runs()
     }
 
     pub async fn run_compaction(&mut self) -> Result<(), Error> {
-        Compactor::handle_compaction(
+        self.check_writable()?;
+        let res = Compactor::handle_compaction(
             Arc::clone(&self.buckets),
             Arc::clone(&self.filters.clone()),
             Arc::clone(&self.key_range),
+            Arc::clone(&self.range_tombstones),
+            self.snapshots.clone(),
+            Some(self.val_log.clone()),
             &self.compactor.config,
         )
-        .await
+        .await;
+        match res {
+            Err(err) if err.is_cancelled() => Err(CompactionCancelled),
+            res => res,
+        }
+    }
+
+    /// Runs a garbage collection pass over `Config::gc_chunk_size` bytes from the tail of the value log and returns
+    /// what it did
+    ///
+    /// Passes otherwise run in the background every `Config::online_gc_interval`, a pass running there is waited
+    /// for.
+    ///
+    /// The space collected is only reclaimed once the reads that started before the pass are done, a range iterator
+    /// created before the call and still held by the caller keeps the pass waiting.
+    pub async fn run_gc(&self) -> Result<GCReport, Error> {
+        self.check_writable()?;
+        let mut run = GCRun::start();
+        let report = GC::gc_handler(
+            &self.gc.config,
+            self.gc_context(),
+            Arc::clone(&self.gc_log),
+            self.meta.clone(),
+        )
+        .await?;
+        self.gc.config.freed_values.reclaimed(report.tail_offset);
+        run.add_pass(&report);
+        GC::run_completed(&self.gc.config, run);
+        Ok(report)
+    }
+
+    /// Returns the handles garbage collection passes look entries up in
+    pub(crate) fn gc_context(&self) -> GCContext {
+        GCContext {
+            memtable: Arc::clone(&self.gc_table),
+            filters: Arc::clone(&self.filters),
+            key_range: Arc::clone(&self.key_range),
+            read_only_memtables: Arc::clone(&self.read_only_memtables),
+            gc_updated_entries: Arc::clone(&self.gc_updated_entries),
+            range_tombstones: Arc::clone(&self.range_tombstones),
+            snapshots: self.snapshots.clone(),
+        }
     }
 }
 impl DirPath {
@@ -368,11 +1559,23 @@ impl DirPath {
         let val_log = root.join(VALUE_LOG_DIRECTORY_NAME);
         let buckets = root.join(BUCKETS_DIRECTORY_NAME);
         let meta = root.join(META_DIRECTORY_NAME);
+        let quarantine = root.join(QUARANTINE_DIRECTORY_NAME);
+        let lock = root.join(LOCK_FILE_NAME);
+        let manifest = meta.join(MANIFEST_FILE_NAME);
+        let range_tombstones = meta.join(RANGE_TOMBSTONES_FILE_NAME);
+        let idempotency_tokens = meta.join(IDEMPOTENCY_TOKENS_FILE_NAME);
+        let prepared_batches = meta.join(PREPARED_BATCHES_FILE_NAME);
         Self {
             root,
             val_log,
             buckets,
             meta,
+            quarantine,
+            manifest,
+            lock,
+            range_tombstones,
+            idempotency_tokens,
+            prepared_batches,
         }
     }
 }
@@ -0,0 +1,199 @@
+//! # Verify
+//!
+//! `verify` is a diagnostic self-check that cross-validates the in-memory read structures (bloom filters and key ranges)
+//! against what is actually stored in each SSTable on disk.
+//!
+//! For every SSTable in the bucket map it:
+//! - loads the entries from the data file and asserts that every key is reported as present by its bloom filter
+//!   (a false negative is a correctness bug because `get` would skip the table)
+//! - probes the filter with keys that are not in the table to estimate the realized false positive rate
+//! - checks that the smallest and biggest key recorded in the `KeyRange` match the table contents
+
+use super::DataStore;
+use crate::bucket::InsertableToBucket;
+use crate::consts::{VERIFY_ABSENT_KEY_LENGTH, VERIFY_ABSENT_KEY_SAMPLE_SIZE};
+use crate::err::Error;
+use crate::filter::BloomFilter;
+use crate::helpers;
+use crate::types::{Key, SkipMapEntries};
+use std::path::PathBuf;
+
+/// Result of running `DataStore::verify`
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// Per SSTable verification result
+    pub tables: Vec<TableReport>,
+
+    /// Bloom filters whose SSTable is no longer tracked by any bucket
+    pub orphaned_filters: Vec<PathBuf>,
+}
+
+/// Verification result for a single SSTable
+#[derive(Debug, Clone)]
+pub struct TableReport {
+    pub data_file_path: PathBuf,
+
+    /// Number of keys read from the data file
+    pub keys_checked: usize,
+
+    /// Number of absent keys used to estimate the false positive rate
+    pub absent_keys_sampled: usize,
+
+    /// Ratio of sampled absent keys reported as present by the bloom filter
+    pub false_positive_rate: f64,
+
+    pub inconsistencies: Vec<Inconsistency>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inconsistency {
+    /// Key stored in the SSTable that its bloom filter does not contain
+    FilterFalseNegative { key: Key },
+
+    /// No bloom filter is associated with the SSTable
+    MissingFilter,
+
+    /// No key range is associated with the SSTable
+    MissingKeyRange,
+
+    /// Smallest key in the key range differs from the smallest key in the SSTable
+    SmallestKeyMismatch { recorded: Key, actual: Key },
+
+    /// Biggest key in the key range differs from the biggest key in the SSTable
+    BiggestKeyMismatch { recorded: Key, actual: Key },
+
+    /// SSTable does not contain any entry
+    EmptyTable,
+
+    /// SSTable data file could not be read
+    Unreadable(String),
+}
+
+impl VerifyReport {
+    /// Returns true if no inconsistency was found in any SSTable
+    pub fn is_consistent(&self) -> bool {
+        self.orphaned_filters.is_empty() && self.tables.iter().all(|t| t.inconsistencies.is_empty())
+    }
+
+    /// Returns only reports of SSTables with at least one inconsistency
+    pub fn inconsistent_tables(&self) -> Vec<&TableReport> {
+        self.tables.iter().filter(|t| !t.inconsistencies.is_empty()).collect()
+    }
+}
+
+impl<'a> DataStore<'a, Key> {
+    /// Cross-validates bloom filters and key ranges against the actual SSTable contents
+    pub async fn verify(&self) -> Result<VerifyReport, Error> {
+        let mut report = VerifyReport::default();
+        let tables = {
+            let buckets = self.buckets.read().await;
+            let mut tables = Vec::new();
+            for (_, bucket) in buckets.buckets.iter() {
+                tables.extend(bucket.sstables.read().await.iter().cloned());
+            }
+            tables
+        };
+        let filters = self.filters.read().await.clone();
+        let key_range = self.key_range.read().await.clone();
+
+        for table in tables.iter() {
+            let data_file_path = table.get_data_file_path();
+            let mut table_report = TableReport {
+                data_file_path: data_file_path.to_owned(),
+                keys_checked: 0,
+                absent_keys_sampled: 0,
+                false_positive_rate: 0.0,
+                inconsistencies: Vec::new(),
+            };
+            let filter = filters
+                .iter()
+                .find(|f| f.sst.as_ref().map(|s| s.get_data_file_path()) == Some(data_file_path.to_owned()));
+            let sstable = match table.load_entries_from_file(true).await {
+                Ok(sstable) => sstable,
+                Err(err) => {
+                    table_report
+                        .inconsistencies
+                        .push(Inconsistency::Unreadable(err.to_string()));
+                    report.tables.push(table_report);
+                    continue;
+                }
+            };
+            if sstable.entries.is_empty() {
+                table_report.inconsistencies.push(Inconsistency::EmptyTable);
+            }
+            table_report.keys_checked = sstable.entries.len();
+
+            match filter {
+                Some(filter) => {
+                    for e in sstable.entries.iter() {
+                        if !filter.contains(e.key()) {
+                            table_report
+                                .inconsistencies
+                                .push(Inconsistency::FilterFalseNegative { key: e.key().to_vec() });
+                        }
+                    }
+                    let (sampled, false_positives) = Self::sample_absent_keys(filter, &sstable.entries);
+                    table_report.absent_keys_sampled = sampled;
+                    if sampled > 0 {
+                        table_report.false_positive_rate = false_positives as f64 / sampled as f64;
+                    }
+                }
+                None => table_report.inconsistencies.push(Inconsistency::MissingFilter),
+            }
+
+            match key_range.key_ranges.get(&data_file_path) {
+                Some(range) => {
+                    if let Ok(smallest_key) = sstable.find_smallest_key() {
+                        if range.smallest_key != smallest_key {
+                            table_report.inconsistencies.push(Inconsistency::SmallestKeyMismatch {
+                                recorded: range.smallest_key.to_owned(),
+                                actual: smallest_key,
+                            });
+                        }
+                    }
+                    if let Ok(biggest_key) = sstable.find_biggest_key() {
+                        if range.biggest_key != biggest_key {
+                            table_report.inconsistencies.push(Inconsistency::BiggestKeyMismatch {
+                                recorded: range.biggest_key.to_owned(),
+                                actual: biggest_key,
+                            });
+                        }
+                    }
+                }
+                None => table_report.inconsistencies.push(Inconsistency::MissingKeyRange),
+            }
+            report.tables.push(table_report);
+        }
+
+        for filter in filters.iter() {
+            if let Some(sst) = &filter.sst {
+                let path = sst.get_data_file_path();
+                if !tables.iter().any(|t| t.get_data_file_path() == path) {
+                    report.orphaned_filters.push(path);
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Probes `filter` with random keys that are not in `entries`
+    ///
+    /// Returns the number of keys sampled and how many of them were reported as present
+    fn sample_absent_keys(filter: &BloomFilter, entries: &SkipMapEntries<Key>) -> (usize, usize) {
+        let mut sampled = 0;
+        let mut false_positives = 0;
+        for _ in 0..VERIFY_ABSENT_KEY_SAMPLE_SIZE {
+            let key = helpers::generate_random_id(VERIFY_ABSENT_KEY_LENGTH)
+                .as_bytes()
+                .to_vec();
+            if entries.contains_key(&key) {
+                continue;
+            }
+            sampled += 1;
+            if filter.contains(&key) {
+                false_positives += 1;
+            }
+        }
+        (sampled, false_positives)
+    }
+}
@@ -1,7 +1,7 @@
 #[cfg(test)]
 mod tests {
     use crate::{
-        bucket::{Bucket, BucketMap, InsertableToBucket},
+        bucket::{Bucket, BucketFallback, BucketMap, BucketPolicy, InsertableToBucket},
         consts::{BUCKET_HIGH, MIN_TRESHOLD},
         err::Error,
         tests::fixtures::{self, sst::generate_ssts},
@@ -15,7 +15,7 @@ mod tests {
     async fn test_bucket_new() {
         let root = tempdir().unwrap();
         let path = PathBuf::from(root.path().join("."));
-        let new_bucket = Bucket::new(path.to_owned()).await;
+        let new_bucket = Bucket::new(path.to_owned(), 1).await;
         let new_dir = new_bucket.dir.to_str().unwrap();
         let prefix = new_dir.rfind("bucket").unwrap();
 
@@ -94,24 +94,34 @@ mod tests {
     async fn test_sstcount_exceed_threshold() {
         let root = tempdir().unwrap();
         let path = PathBuf::from(root.path().join("."));
-        let new_bucket = Bucket::new(path.to_owned()).await;
+        let new_bucket = Bucket::new(path.to_owned(), 1).await;
         let sst_count = 5;
         let sst_samples = fixtures::sst::generate_ssts(sst_count).await;
         for s in sst_samples {
             new_bucket.sstables.write().await.push(s)
         }
-        assert_eq!(new_bucket.sstable_count_exceeds_threshhold().await, true);
+        assert_eq!(
+            new_bucket
+                .sstable_count_exceeds_threshhold(&BucketPolicy::default())
+                .await,
+            true
+        );
 
         new_bucket.sstables.write().await.clear();
 
-        assert_eq!(new_bucket.sstable_count_exceeds_threshhold().await, false);
+        assert_eq!(
+            new_bucket
+                .sstable_count_exceeds_threshhold(&BucketPolicy::default())
+                .await,
+            false
+        );
     }
 
     #[tokio::test]
     async fn test_extract_sstable_to_compact() {
         let root = tempdir().unwrap();
         let path = PathBuf::from(root.path().join("."));
-        let new_bucket = Bucket::new(path.to_owned()).await;
+        let new_bucket = Bucket::new(path.to_owned(), 1).await;
         let sst_count = 5;
         let sst_samples = fixtures::sst::generate_ssts(sst_count).await;
         let sst_meta = sst_samples
@@ -129,7 +139,7 @@ mod tests {
             new_bucket.sstables.write().await.push(s)
         }
         let expected_avg = all_sstable_size / sst_count as usize;
-        let extracted_ssts = new_bucket.extract_sstables().await;
+        let extracted_ssts = new_bucket.extract_sstables(&BucketPolicy::default()).await;
         assert!(extracted_ssts.is_ok());
         let (ssts, avg) = extracted_ssts.unwrap();
         assert_eq!(avg, expected_avg);
@@ -140,24 +150,33 @@ mod tests {
     async fn table_fits_into_bucket() {
         let root = tempdir().unwrap();
         let path = PathBuf::from(root.path().join("."));
-        let mut new_bucket = Bucket::new(path.to_owned()).await;
+        let mut new_bucket = Bucket::new(path.to_owned(), 1).await;
         let sst_sample = fixtures::sst::generate_ssts(2).await;
         for s in sst_sample {
             new_bucket.sstables.write().await.push(s)
         }
         let mut sst_within_size_range = generate_ssts(1).await[0].to_owned();
         new_bucket.avarage_size = sst_within_size_range.size();
-        let fits_into_bucket = new_bucket.fits_into_bucket(Arc::new(Box::new(sst_within_size_range.to_owned())));
+        let fits_into_bucket = new_bucket.fits_into_bucket(
+            Arc::new(Box::new(sst_within_size_range.to_owned())),
+            &BucketPolicy::default(),
+        );
         // size of sstable is not less than bucket low
         assert_eq!(fits_into_bucket, true);
         // increase sstable size to be greater than bucket high range
         sst_within_size_range.size = ((new_bucket.avarage_size as f64 * BUCKET_HIGH) * 2.0) as usize;
-        let fits_into_bucket = new_bucket.fits_into_bucket(Arc::new(Box::new(sst_within_size_range.to_owned())));
+        let fits_into_bucket = new_bucket.fits_into_bucket(
+            Arc::new(Box::new(sst_within_size_range.to_owned())),
+            &BucketPolicy::default(),
+        );
         // sstable size is greater than bucket high range
         assert_eq!(fits_into_bucket, false);
         // increase bucket average
         new_bucket.avarage_size = ((new_bucket.avarage_size as f64 * BUCKET_HIGH) * 2.0) as usize;
-        let fits_into_bucket = new_bucket.fits_into_bucket(Arc::new(Box::new(sst_within_size_range.to_owned())));
+        let fits_into_bucket = new_bucket.fits_into_bucket(
+            Arc::new(Box::new(sst_within_size_range.to_owned())),
+            &BucketPolicy::default(),
+        );
         // sstable size is within bucket range
         assert_eq!(fits_into_bucket, true);
     }
@@ -176,24 +195,24 @@ mod tests {
     async fn test_bucket_map_extract_imbalanced_buckets() {
         let root = tempdir().unwrap();
         let path = PathBuf::from(root.path().join("."));
-        let new_bucket1 = Bucket::new(path.to_owned()).await;
+        let new_bucket1 = Bucket::new(path.to_owned(), 1).await;
         let sst_count = 6;
         let sst_samples = fixtures::sst::generate_ssts(sst_count).await;
         for s in sst_samples.to_owned() {
             new_bucket1.sstables.write().await.push(s)
         }
 
-        let new_bucket2 = Bucket::new(path.to_owned()).await;
+        let new_bucket2 = Bucket::new(path.to_owned(), 2).await;
         for s in sst_samples.to_owned() {
             new_bucket2.sstables.write().await.push(s)
         }
 
-        let new_bucket3 = Bucket::new(path.to_owned()).await;
+        let new_bucket3 = Bucket::new(path.to_owned(), 3).await;
         for s in sst_samples.to_owned() {
             new_bucket3.sstables.write().await.push(s)
         }
 
-        let new_bucket4 = Bucket::new(path.to_owned()).await;
+        let new_bucket4 = Bucket::new(path.to_owned(), 4).await;
         for s in sst_samples.to_owned() {
             new_bucket4.sstables.write().await.push(s)
         }
@@ -246,24 +265,24 @@ mod tests {
     async fn test_bucket_map_is_balanced() {
         let root = tempdir().unwrap();
         let path = PathBuf::from(root.path().join("."));
-        let new_bucket1 = Bucket::new(path.to_owned()).await;
+        let new_bucket1 = Bucket::new(path.to_owned(), 1).await;
         let sst_count = 6;
         let sst_samples = fixtures::sst::generate_ssts(sst_count).await;
         for s in sst_samples.to_owned() {
             new_bucket1.sstables.write().await.push(s)
         }
 
-        let new_bucket2 = Bucket::new(path.to_owned()).await;
+        let new_bucket2 = Bucket::new(path.to_owned(), 2).await;
         for s in sst_samples.to_owned() {
             new_bucket2.sstables.write().await.push(s)
         }
 
-        let new_bucket3 = Bucket::new(path.to_owned()).await;
+        let new_bucket3 = Bucket::new(path.to_owned(), 3).await;
         for s in sst_samples.to_owned() {
             new_bucket3.sstables.write().await.push(s)
         }
 
-        let new_bucket4 = Bucket::new(path.to_owned()).await;
+        let new_bucket4 = Bucket::new(path.to_owned(), 4).await;
         for s in sst_samples.to_owned() {
             new_bucket4.sstables.write().await.push(s)
         }
@@ -299,7 +318,7 @@ mod tests {
         let mut bucket_map = BucketMap::new(path.to_owned()).await;
 
         let sst_within_size_range = generate_ssts(1).await[0].to_owned();
-        let mut sst_with_entries = sst_within_size_range.load_entries_from_file().await.unwrap();
+        let mut sst_with_entries = sst_within_size_range.load_entries_from_file(true).await.unwrap();
         // bucket insertion is succeeds
         let insert_res = bucket_map
             .insert_to_appropriate_bucket(Arc::new(Box::new(sst_with_entries.to_owned())))
@@ -321,33 +340,63 @@ mod tests {
         assert_eq!(bucket_map.buckets.len(), 2);
     }
 
+    #[tokio::test]
+    async fn table_insert_to_closest_bucket() {
+        let root = tempdir().unwrap();
+        let path = root.path().join(".");
+        let mut bucket_map = BucketMap::new(path.to_owned()).await;
+        bucket_map.set_policy(BucketPolicy {
+            min_sstables_per_merge: 1,
+            bucket_fallback: BucketFallback::ClosestBucket,
+            ..BucketPolicy::default()
+        });
+        // A merge takes at least two sstables
+        assert_eq!(bucket_map.policy.min_sstables_per_merge, 2);
+
+        let sst_within_size_range = generate_ssts(1).await[0].to_owned();
+        let mut sst_with_entries = sst_within_size_range.load_entries_from_file(true).await.unwrap();
+        let insert_res = bucket_map
+            .insert_to_appropriate_bucket(Arc::new(Box::new(sst_with_entries.to_owned())))
+            .await;
+        assert!(insert_res.is_ok());
+        assert!(bucket_map.is_balanced().await);
+        sst_with_entries.size = ((sst_with_entries.size as f64 * BUCKET_HIGH) * 2.0) as usize;
+        let insert_res = bucket_map
+            .insert_to_appropriate_bucket(Arc::new(Box::new(sst_with_entries.to_owned())))
+            .await;
+        assert!(insert_res.is_ok());
+        // SST size is not within the bucket size range but it joins the closest bucket
+        assert_eq!(bucket_map.buckets.len(), 1);
+        assert!(!bucket_map.is_balanced().await);
+    }
+
     #[tokio::test]
     async fn test_delete_sstables() {
         let root = tempdir().unwrap();
         let path = PathBuf::from(root.path().join("."));
-        let new_bucket1 = Bucket::new(path.to_owned()).await;
+        let new_bucket1 = Bucket::new(path.to_owned(), 1).await;
         let sst_count = 6;
         let sst_samples = fixtures::sst::generate_ssts(sst_count).await;
         for s in sst_samples.to_owned() {
             new_bucket1.sstables.write().await.push(s)
         }
 
-        let new_bucket2 = Bucket::new(path.to_owned()).await;
+        let new_bucket2 = Bucket::new(path.to_owned(), 2).await;
         for s in sst_samples.to_owned() {
             new_bucket2.sstables.write().await.push(s)
         }
 
-        let new_bucket3 = Bucket::new(path.to_owned()).await;
+        let new_bucket3 = Bucket::new(path.to_owned(), 3).await;
         for s in sst_samples.to_owned() {
             new_bucket3.sstables.write().await.push(s)
         }
 
-        let new_bucket4 = Bucket::new(path.to_owned()).await;
+        let new_bucket4 = Bucket::new(path.to_owned(), 4).await;
         for s in sst_samples.to_owned() {
             new_bucket4.sstables.write().await.push(s)
         }
 
-        let new_bucket5 = Bucket::new(path.to_owned()).await;
+        let new_bucket5 = Bucket::new(path.to_owned(), 5).await;
         for s in sst_samples.to_owned() {
             new_bucket5.sstables.write().await.push(s)
         }
@@ -6,7 +6,7 @@ use tokio::{fs::File, sync::RwLock};
 use crate::{
     fs::{DataFileNode, FileNode, FileType, IndexFileNode},
     index::IndexFile,
-    sst::{DataFile, Table},
+    sst::{DataFile, Table, TableProperties},
 };
 
 struct SSTContructor {
@@ -106,6 +106,7 @@ pub async fn generate_ssts(number: u32) -> Vec<Table> {
             hotness: 100,
             size: 4096,
             created_at: 1655580700,
+            properties: TableProperties::default(),
             data_file: DataFile {
                 file: DataFileNode {
                     node: FileNode {
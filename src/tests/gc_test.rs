@@ -1,8 +1,11 @@
 #[cfg(test)]
 mod tests {
-    use crate::consts::{SIZE_OF_U32, SIZE_OF_U64, SIZE_OF_U8};
+    use crate::cfg::Config;
+    use crate::consts::{FS_PROBE_FILE_NAME, SIZE_OF_U32, SIZE_OF_U64, SIZE_OF_U8};
     use crate::err::Error;
+    use crate::fs::FsCapabilities;
     use crate::gc::gc::GC;
+    use crate::gc::GCStrategy;
     use crate::storage::{DataStore, SizeUnit};
     use crate::tests::workload::Workload;
     use std::path::PathBuf;
@@ -36,12 +39,9 @@ mod tests {
         let config = storage_reader.gc.config.clone();
         let res = GC::gc_handler(
             &config,
-            Arc::clone(&storage_reader.gc_table),
+            storage_reader.gc_context(),
             Arc::clone(&storage_reader.gc_log),
-            Arc::clone(&storage_reader.filters),
-            Arc::clone(&storage_reader.key_range),
-            Arc::clone(&storage_reader.read_only_memtables),
-            Arc::clone(&storage_reader.gc_updated_entries),
+            storage_reader.meta.clone(),
         )
         .await;
 
@@ -67,15 +67,14 @@ mod tests {
             return;
         }
         let storage_reader = store.read().await;
-        let config = storage_reader.gc.config.clone();
+        let mut config = storage_reader.gc.config.clone();
+        // Other platforms pick copy-and-truncate when the store is opened
+        config.strategy = GCStrategy::PunchHole;
         let res = GC::gc_handler(
             &config,
-            Arc::clone(&storage_reader.gc_table),
+            storage_reader.gc_context(),
             Arc::clone(&storage_reader.gc_log),
-            Arc::clone(&storage_reader.filters),
-            Arc::clone(&storage_reader.key_range),
-            Arc::clone(&storage_reader.read_only_memtables),
-            Arc::clone(&storage_reader.gc_updated_entries),
+            storage_reader.meta.clone(),
         )
         .await;
 
@@ -114,12 +113,9 @@ mod tests {
        
         let _ = GC::gc_handler(
             &config,
-            Arc::clone(&storage_reader.gc_table),
+            storage_reader.gc_context(),
             Arc::clone(&storage_reader.gc_log),
-            Arc::clone(&storage_reader.filters),
-            Arc::clone(&storage_reader.key_range),
-            Arc::clone(&storage_reader.read_only_memtables),
-            Arc::clone(&storage_reader.gc_updated_entries),
+            storage_reader.meta.clone(),
         )
         .await;
         assert!(storage_reader.gc.vlog.read().await.tail_offset != initial_tail_offset);
@@ -150,12 +146,9 @@ mod tests {
         config.gc_chunk_size = bytes_to_scan_for_garbage_colection;
         let _ = GC::gc_handler(
             &config,
-            Arc::clone(&storage_reader.gc_table),
+            storage_reader.gc_context(),
             Arc::clone(&storage_reader.gc_log),
-            Arc::clone(&storage_reader.filters),
-            Arc::clone(&storage_reader.key_range),
-            Arc::clone(&storage_reader.read_only_memtables),
-            Arc::clone(&storage_reader.gc_updated_entries),
+            storage_reader.meta.clone(),
         )
         .await;
         let max_extention_length = SIZE_OF_U32   // Key Size(for fetching key length)
@@ -163,7 +156,8 @@ mod tests {
         + SIZE_OF_U64           // Date Length
         + SIZE_OF_U8            // Tombstone marker len
         + string_length         // Key Len
-        + vaue_len; // Value Len
+        + vaue_len              // Value Len
+        + SIZE_OF_U32; // Checksum
         assert!(
             storage_reader.gc.vlog.read().await.tail_offset
                 <= initial_tail_offset + bytes_to_scan_for_garbage_colection + max_extention_length
@@ -189,15 +183,138 @@ mod tests {
         let initial_head_offset = storage_reader.gc_log.read().await.head_offset;
         let _ = GC::gc_handler(
             &storage_reader.gc.config.clone(),
-            Arc::clone(&storage_reader.gc_table),
+            storage_reader.gc_context(),
             Arc::clone(&storage_reader.gc_log),
-            Arc::clone(&storage_reader.filters),
-            Arc::clone(&storage_reader.key_range),
-            Arc::clone(&storage_reader.read_only_memtables),
-            Arc::clone(&storage_reader.gc_updated_entries),
+            storage_reader.meta.clone(),
         )
         .await;
 
         assert!(storage_reader.gc.vlog.read().await.head_offset != initial_head_offset);
     }
+
+    #[tokio::test]
+    async fn datastore_gc_test_empty_value_relocated() {
+        let root = tempdir().unwrap();
+        let path = root.path().join("gc_test_6");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        // An empty value must not be relocated as a tombstone
+        let res = store.put("key_1", "").await;
+        assert!(res.is_ok());
+        // Let the gc table catch up with the write
+        tokio::task::yield_now().await;
+        let _ = GC::gc_handler(
+            &store.gc.config.clone(),
+            store.gc_context(),
+            Arc::clone(&store.gc_log),
+            store.meta.clone(),
+        )
+        .await;
+        let relocated = store
+            .gc_updated_entries
+            .read()
+            .await
+            .get(b"key_1".as_slice())
+            .map(|e| e.value().to_owned());
+        assert!(relocated.is_some_and(|value| !value.is_tombstone));
+    }
+
+    #[tokio::test]
+    async fn datastore_gc_test_copy_and_truncate() {
+        let root = tempdir().unwrap();
+        let path = root.path().join("gc_test_7");
+        let config = Config {
+            gc_strategy: GCStrategy::CopyAndTruncate,
+            online_gc_interval: 60 * 60 * 1000,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        assert_eq!(store.gc.config.strategy, GCStrategy::CopyAndTruncate);
+        for value in ["old_value", "new_value"] {
+            for i in 0..50 {
+                let res = store.put(format!("key_{}", i), value).await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        let vlog_path = store.gc_log.read().await.content.path.to_owned();
+        let len_before = fs::metadata(&vlog_path).await.unwrap().len();
+
+        // Collects until the overwritten values outweigh the live ones and the start of the log is dropped
+        for _ in 0..20 {
+            let res = GC::gc_handler(
+                &store.gc.config.clone(),
+                store.gc_context(),
+                Arc::clone(&store.gc_log),
+                store.meta.clone(),
+            )
+            .await;
+            assert!(res.is_ok());
+            if store.gc_log.read().await.base() > 0 {
+                break;
+            }
+        }
+        let gc_log = store.gc_log.read().await;
+        assert!(gc_log.base() > 0);
+        assert_eq!(gc_log.first_offset(), gc_log.tail_offset);
+        drop(gc_log);
+        assert!(fs::metadata(&vlog_path).await.unwrap().len() < len_before);
+        for i in 0..50 {
+            let res = store.get(format!("key_{}", i)).await;
+            assert_eq!(res.unwrap(), Some(b"new_value".to_vec()));
+        }
+        // Values appended after the copy keep counting from the start of the log
+        let res = store.put("key_50", "new_value").await;
+        assert!(res.is_ok());
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        let store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        for i in 0..51 {
+            let res = store.get(format!("key_{}", i)).await;
+            assert_eq!(res.unwrap(), Some(b"new_value".to_vec()));
+        }
+    }
+
+    #[tokio::test]
+    async fn datastore_gc_strategy_follows_fs_capabilities() {
+        let root = tempdir().unwrap();
+        let path = root.path().join("gc_test_8");
+        let config = Config {
+            gc_strategy: GCStrategy::PunchHole,
+            ..Config::default()
+        };
+        let store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        let capabilities = store.fs_capabilities();
+        assert_eq!(capabilities, FsCapabilities::probe(&store.dir.val_log).await);
+        // The probe file is removed once the file system was probed
+        assert!(!store.dir.val_log.join(FS_PROBE_FILE_NAME).exists());
+        let expected = if capabilities.punch_hole {
+            GCStrategy::PunchHole
+        } else {
+            GCStrategy::CopyAndTruncate
+        };
+        assert_eq!(store.gc.config.strategy, expected);
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        // Nothing is probed on a store opened read-only
+        let store = DataStore::open_read_only(path).await.unwrap();
+        assert_eq!(store.fs_capabilities(), FsCapabilities::default());
+
+        let unsupported = FsCapabilities::default();
+        assert_eq!(GCStrategy::Auto.resolve(&unsupported), GCStrategy::CopyAndTruncate);
+        assert_eq!(GCStrategy::PunchHole.resolve(&unsupported), GCStrategy::CopyAndTruncate);
+        let supported = FsCapabilities {
+            punch_hole: true,
+            ..FsCapabilities::default()
+        };
+        assert_eq!(GCStrategy::Auto.resolve(&supported), GCStrategy::PunchHole);
+        assert_eq!(
+            GCStrategy::CopyAndTruncate.resolve(&supported),
+            GCStrategy::CopyAndTruncate
+        );
+    }
 }
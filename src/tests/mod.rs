@@ -1,5 +1,7 @@
 mod bucket_test;
 mod fixtures;
 mod gc_test;
+mod range_test;
 mod store_test;
+mod verify_test;
 mod workload;
@@ -0,0 +1,480 @@
+#[cfg(test)]
+mod tests {
+    use crate::cfg::{Config, ReadOptions};
+    use crate::err::Error;
+    use crate::storage::{ContinuationToken, DataStore, KeyEntry};
+    use crate::types::Key;
+    use futures::stream::{StreamExt, TryStreamExt};
+    use tempfile::tempdir;
+
+    fn setup() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    async fn collect_range(store: &DataStore<'_, Key>, start: &str, end: &str) -> Vec<(String, String)> {
+        let mut iterator = store.range(start..end).await.unwrap();
+        let mut entries = Vec::new();
+        while let Some(entry) = iterator.next().await {
+            let (key, val) = entry.unwrap();
+            entries.push((String::from_utf8(key).unwrap(), String::from_utf8(val).unwrap()));
+        }
+        entries
+    }
+
+    #[tokio::test]
+    async fn datastore_range_memtable() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("range_test_1");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..20 {
+            let key = format!("key_{:02}", i);
+            store.put(&key, &format!("val_{}", i)).await.unwrap();
+        }
+
+        let entries = collect_range(&store, "key_05", "key_10").await;
+        let expected: Vec<(String, String)> = (5..10)
+            .map(|i| (format!("key_{:02}", i), format!("val_{}", i)))
+            .collect();
+        assert_eq!(entries, expected);
+
+        let entries = collect_range(&store, "key_10", "key_05").await;
+        assert!(entries.is_empty());
+
+        let entries = collect_range(&store, "zzz", "zzzz").await;
+        assert!(entries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn datastore_range_overlapping_tables() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("range_test_2");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..30 {
+            store.put(&format!("key_{:02}", i), "old").await.unwrap();
+        }
+        store.flush_all_memtables().await.unwrap();
+
+        // Second SSTable overlaps the first one and overrides part of its keys
+        for i in 10..40 {
+            store.put(&format!("key_{:02}", i), "mid").await.unwrap();
+        }
+        store.flush_all_memtables().await.unwrap();
+
+        // Most recent versions are still in the memtable
+        for i in 20..25 {
+            store.put(&format!("key_{:02}", i), "new").await.unwrap();
+        }
+
+        let entries = collect_range(&store, "key_00", "key_99").await;
+        assert_eq!(entries.len(), 40);
+        for (key, val) in entries.iter() {
+            let i: usize = key.trim_start_matches("key_").parse().unwrap();
+            let expected = match i {
+                0..=9 => "old",
+                20..=24 => "new",
+                _ => "mid",
+            };
+            assert_eq!(val, expected, "unexpected value for {}", key);
+        }
+        let keys: Vec<&String> = entries.iter().map(|(k, _)| k).collect();
+        let mut sorted_keys = keys.clone();
+        sorted_keys.sort();
+        assert_eq!(keys, sorted_keys);
+    }
+
+    #[tokio::test]
+    async fn datastore_range_skips_tombstones() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("range_test_3");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..20 {
+            store.put(&format!("key_{:02}", i), "val").await.unwrap();
+        }
+        // Tombstones flushed to an SSTable
+        for i in 0..5 {
+            store.delete(&format!("key_{:02}", i)).await.unwrap();
+        }
+        store.flush_all_memtables().await.unwrap();
+
+        // Tombstones still in the memtable shadow entries stored in SSTables
+        for i in 10..15 {
+            store.delete(&format!("key_{:02}", i)).await.unwrap();
+        }
+        // Key deleted then inserted again is visible
+        store.put("key_03", "revived").await.unwrap();
+
+        let entries = collect_range(&store, "key_00", "key_20").await;
+        let keys: Vec<&str> = entries.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "key_03", "key_05", "key_06", "key_07", "key_08", "key_09", "key_15", "key_16", "key_17", "key_18",
+                "key_19"
+            ]
+        );
+        assert_eq!(entries[0].1, "revived");
+    }
+
+    #[tokio::test]
+    async fn datastore_keys() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("range_test_4");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..20 {
+            store.put(&format!("key_{:02}", i), "val").await.unwrap();
+        }
+        store.flush_all_memtables().await.unwrap();
+        for i in 15..25 {
+            store.put(&format!("key_{:02}", i), "val").await.unwrap();
+        }
+        store.delete("key_00").await.unwrap();
+        store.delete("key_20").await.unwrap();
+
+        let keys: Vec<KeyEntry> = store.keys().await.unwrap().try_collect().await.unwrap();
+        let expected: Vec<Key> = (1..25)
+            .filter(|i| *i != 20)
+            .map(|i| format!("key_{:02}", i).into_bytes())
+            .collect();
+        assert_eq!(keys.iter().map(|e| e.key.to_owned()).collect::<Vec<Key>>(), expected);
+        assert!(keys.iter().all(|e| e.created_at > 0));
+
+        let empty_path = root.path().join("range_test_5");
+        let empty_store = DataStore::new(empty_path.clone()).await.unwrap();
+        assert_eq!(empty_store.keys().await.unwrap().count().await, 0);
+
+        // The sources are captured when the stream is created, writes made while it is consumed are not seen
+        let mut keys = store.keys().await.unwrap();
+        assert_eq!(keys.next().await.unwrap().unwrap().key, b"key_01".to_vec());
+        store.delete("key_02").await.unwrap();
+        store.put("key_30", "val").await.unwrap();
+        store.flush_all_memtables().await.unwrap();
+        assert_eq!(keys.next().await.unwrap().unwrap().key, b"key_02".to_vec());
+        assert_eq!(keys.count().await, 21);
+    }
+
+    #[tokio::test]
+    async fn datastore_scan_page() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("range_test_6");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..15 {
+            store
+                .put(&format!("key_{:02}", i), &format!("val_{}", i))
+                .await
+                .unwrap();
+        }
+        store.flush_all_memtables().await.unwrap();
+        for i in 15..25 {
+            store
+                .put(&format!("key_{:02}", i), &format!("val_{}", i))
+                .await
+                .unwrap();
+        }
+        store.delete("key_07").await.unwrap();
+
+        let mut token = ContinuationToken::start();
+        let mut keys = Vec::new();
+        let mut pages = 0;
+        loop {
+            let page = store.scan_page(&token, 10).await.unwrap();
+            assert!(page.entries.len() <= 10);
+            keys.extend(
+                page.entries
+                    .iter()
+                    .map(|e| String::from_utf8(e.key.to_owned()).unwrap()),
+            );
+            pages += 1;
+            match page.next {
+                // Token survives a round trip through its string form
+                Some(next) => token = next.to_string().parse().unwrap(),
+                None => break,
+            }
+        }
+        assert_eq!(pages, 3);
+        let expected: Vec<String> = (0..25).filter(|i| *i != 7).map(|i| format!("key_{:02}", i)).collect();
+        assert_eq!(keys, expected);
+
+        let page = store
+            .scan_page(&ContinuationToken::from_key("key_20"), 2)
+            .await
+            .unwrap();
+        let keys: Vec<&[u8]> = page.entries.iter().map(|e| e.key.as_slice()).collect();
+        assert_eq!(keys, vec![b"key_20".as_slice(), b"key_21".as_slice()]);
+        assert_eq!(page.entries[0].val, b"val_20".to_vec());
+        assert!(page.next.is_some());
+
+        // The page ends at the last key, no entry follows it
+        let page = store
+            .scan_page(&ContinuationToken::from_key("key_23"), 2)
+            .await
+            .unwrap();
+        assert_eq!(page.entries.len(), 2);
+        assert!(page.next.is_none());
+
+        let res = "not a token".parse::<ContinuationToken>();
+        assert!(matches!(res, Err(Error::InvalidContinuationToken(_))));
+    }
+
+    #[tokio::test]
+    async fn datastore_range_stream_combinators() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("range_test_7");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        // More keys than the prefetch size so several value log batches are pipelined
+        for i in 0..50 {
+            store
+                .put(&format!("key_{:02}", i), &format!("val_{}", i))
+                .await
+                .unwrap();
+        }
+        store.flush_all_memtables().await.unwrap();
+
+        let keys: Vec<Key> = store
+            .range("key_10".."key_40")
+            .await
+            .unwrap()
+            .map_ok(|(key, _)| key)
+            .try_collect()
+            .await
+            .unwrap();
+        let expected: Vec<Key> = (10..40).map(|i| format!("key_{:02}", i).into_bytes()).collect();
+        assert_eq!(keys, expected);
+
+        // Dropping the stream early stops the scan
+        let first: Vec<(Key, Key)> = store
+            .range("key_00".."key_99")
+            .await
+            .unwrap()
+            .take(3)
+            .map(|e| e.unwrap())
+            .collect()
+            .await;
+        assert_eq!(first.len(), 3);
+        assert_eq!(first[2], (b"key_02".to_vec(), b"val_2".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_super_version_pins_tables() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("range_test_8");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        // Enough similarly sized sstables to land in one bucket and be compacted
+        for flush in 0..4 {
+            for i in 0..300 {
+                store
+                    .put(&format!("key_{:04}", i), &format!("val_{}", flush))
+                    .await
+                    .unwrap();
+            }
+            store.flush_all_memtables().await.unwrap();
+        }
+
+        let super_version = store.super_version().await;
+        let pinned: Vec<_> = super_version.ranges.iter().map(|r| r.sst.dir.to_owned()).collect();
+        assert!(!pinned.is_empty());
+        store.run_compaction().await.unwrap();
+
+        // Compacted tables are gone from the store but their files outlive compaction
+        let current = store.super_version().await;
+        assert!(current.ranges.iter().all(|r| !pinned.contains(&r.sst.dir)));
+        for range in super_version.ranges.iter() {
+            assert!(range.sst.dir.exists());
+            assert!(range.sst.load_entries_from_file(true).await.is_ok());
+        }
+        assert_eq!(collect_range(&store, "key_0000", "key_0002").await.len(), 2);
+
+        drop(super_version);
+        assert!(pinned.iter().all(|dir| !dir.exists()));
+    }
+
+    #[tokio::test]
+    async fn datastore_range_parallel_sstable_reads() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("range_test_9");
+        let config = Config {
+            max_parallel_sstable_reads: 2,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        // Every flush overrides the keys written by the previous one
+        for flush in 0..10 {
+            for i in 0..20 {
+                store
+                    .put(&format!("key_{:02}", i), &format!("val_{}", flush))
+                    .await
+                    .unwrap();
+            }
+            store.flush_all_memtables().await.unwrap();
+        }
+
+        let entries = collect_range(&store, "key_05", "key_15").await;
+        let expected: Vec<(String, String)> = (5..15)
+            .map(|i| (format!("key_{:02}", i), "val_9".to_string()))
+            .collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[tokio::test]
+    async fn datastore_range_reads_overlapping_blocks() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("range_test_10");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..500 {
+            store
+                .put(&format!("key_{:04}", i), &format!("val_{}", i))
+                .await
+                .unwrap();
+        }
+        store.flush_all_memtables().await.unwrap();
+        let cache = store.block_cache().clone();
+
+        // The index locates the blocks holding the range, the rest of the SSTable is not read
+        let entries = collect_range(&store, "key_0100", "key_0110").await;
+        let expected: Vec<(String, String)> = (100..110)
+            .map(|i| (format!("key_{:04}", i), format!("val_{}", i)))
+            .collect();
+        assert_eq!(entries, expected);
+        let misses = cache.misses();
+        assert!(misses <= 2);
+
+        let entries = collect_range(&store, "key_0000", "key_9999").await;
+        assert_eq!(entries.len(), 500);
+        assert!(cache.misses() > misses + 2);
+
+        // Ranges at the edges of the SSTable
+        let entries = collect_range(&store, "key_0499", "key_9999").await;
+        assert_eq!(entries, vec![("key_0499".to_string(), "val_499".to_string())]);
+        let entries = collect_range(&store, "a", "key_0001").await;
+        assert_eq!(entries, vec![("key_0000".to_string(), "val_0".to_string())]);
+        assert!(collect_range(&store, "key_0500", "key_9999").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn datastore_range_without_filling_cache() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("range_test_11");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..100 {
+            store
+                .put(&format!("key_{:03}", i), &format!("val_{}", i))
+                .await
+                .unwrap();
+        }
+        store.flush_all_memtables().await.unwrap();
+        let cache = store.block_cache().clone();
+        let options = ReadOptions {
+            fill_cache: false,
+            ..ReadOptions::default()
+        };
+
+        // Scans and gets that do not fill the cache leave it unchanged
+        let mut iterator = store.range_with_options("key_000".."key_999", &options).await.unwrap();
+        let mut count = 0;
+        while let Some(entry) = iterator.next().await {
+            entry.unwrap();
+            count += 1;
+        }
+        assert_eq!(count, 100);
+        assert_eq!(
+            store.get_with_options("key_050", &options).await.unwrap(),
+            Some(b"val_50".to_vec())
+        );
+        assert_eq!(cache.size(), 0);
+        assert_eq!(cache.hits() + cache.misses(), 0);
+
+        // The same scan with the default options fills it
+        assert_eq!(collect_range(&store, "key_000", "key_999").await.len(), 100);
+        assert!(cache.size() > 0);
+    }
+
+    #[tokio::test]
+    async fn datastore_scan_page_reads_blocks_lazily() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("range_test_12");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..2000 {
+            store
+                .put(&format!("key_{:04}", i), &format!("val_{}", i))
+                .await
+                .unwrap();
+        }
+        store.flush_all_memtables().await.unwrap();
+        let cache = store.block_cache().clone();
+
+        // A page only reads the blocks holding its entries and the key following them
+        let page = store
+            .scan_page(&ContinuationToken::from_key("key_0250"), 10)
+            .await
+            .unwrap();
+        assert_eq!(page.entries.len(), 10);
+        assert!(page.next.is_some());
+        assert!(cache.misses() <= 2);
+    }
+
+    #[tokio::test]
+    async fn datastore_keys_reads_blocks_lazily() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("range_test_13");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..2000 {
+            store
+                .put(&format!("key_{:04}", i), &format!("val_{}", i))
+                .await
+                .unwrap();
+        }
+        store.flush_all_memtables().await.unwrap();
+        let cache = store.block_cache().clone();
+
+        // Keys are read one block at a time as the stream advances
+        let keys: Vec<KeyEntry> = store.keys().await.unwrap().take(10).try_collect().await.unwrap();
+        assert_eq!(keys.len(), 10);
+        assert!(cache.misses() <= 1);
+        let keys: Vec<KeyEntry> = store.keys().await.unwrap().try_collect().await.unwrap();
+        assert_eq!(keys.len(), 2000);
+        assert!(cache.misses() > 10);
+    }
+
+    #[tokio::test]
+    async fn datastore_range_over_byte_keys() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("range_test_14");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        // Keys that are not valid UTF-8
+        for i in 0..10u8 {
+            store.put([0xff, i], [i]).await.unwrap();
+        }
+        store.flush_all_memtables().await.unwrap();
+
+        let (start, end) = ([0xff, 2], [0xff, 5]);
+        let keys: Vec<Key> = store
+            .range(start.as_slice()..end.as_slice())
+            .await
+            .unwrap()
+            .map_ok(|(key, _)| key)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(keys, vec![vec![0xff, 2], vec![0xff, 3], vec![0xff, 4]]);
+
+        let page = store
+            .scan_page(&ContinuationToken::from_key([0xff, 8]), 5)
+            .await
+            .unwrap();
+        let keys: Vec<Key> = page.entries.into_iter().map(|e| e.key).collect();
+        assert_eq!(keys, vec![vec![0xff, 8], vec![0xff, 9]]);
+        assert!(page.next.is_none());
+    }
+}
@@ -1,14 +1,24 @@
 #[cfg(test)]
 mod tests {
+    use crate::cfg::Config;
+    use crate::consts::{BLOCK_COMPRESSED_FLAG, HEAD_ENTRY_KEY, TAIL_ENTRY_KEY};
     use crate::err::Error;
-    use crate::storage::DataStore;
+    use crate::storage::{
+        Change, ChecksumType, CompactionDecision, CompactionFilter, CompressionType, ContinuationToken, DataStore,
+        EventListener, GCRun, GroupCommit, ReadOptions, ReadTier, WriteBatch, WriteOptions,
+    };
     use crate::tests::workload::Workload;
+    use crate::value_log::ValueLogFormat;
+    use chrono::Utc;
     use futures::future::join_all;
-    use std::path::PathBuf;
+    use futures::stream::{StreamExt, TryStreamExt};
+    use std::path::{Path, PathBuf};
     use std::sync::Arc;
     use tempfile::tempdir;
     use tokio::fs::{self};
+    use tokio::io::AsyncReadExt;
     use tokio::sync::RwLock;
+    use tokio::time::{sleep, timeout, Duration, Instant};
 
     fn setup() {
         let _ = env_logger::builder().is_test(true).try_init();
@@ -22,6 +32,28 @@ mod tests {
         assert!(store.is_ok())
     }
 
+    #[tokio::test]
+    async fn datastore_open_locked_directory() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_11");
+        let store = DataStore::new(path.clone()).await;
+        assert!(store.is_ok());
+
+        let second_store = DataStore::new(path.clone()).await;
+        assert!(second_store.is_err());
+        match second_store.err().unwrap() {
+            Error::FileLockError { holder, .. } => assert_eq!(holder, Some(std::process::id())),
+            err => panic!("unexpected error: {}", err),
+        }
+
+        // Lock is released once the first store is closed
+        let res = store.unwrap().close().await;
+        assert!(res.is_ok());
+        let reopened_store = DataStore::new(path.clone()).await;
+        assert!(reopened_store.is_ok());
+    }
+
     #[tokio::test]
     async fn datastore_put_test() {
         setup();
@@ -94,7 +126,8 @@ mod tests {
             tokio::spawn(async move {
                 let key_str = std::str::from_utf8(&key).unwrap();
                 match store_inner.read().await.get(key_str).await {
-                    Ok((value, _)) => Ok((key_str.as_bytes().to_vec(), value)),
+                    Ok(Some(value)) => Ok((key_str.as_bytes().to_vec(), value)),
+                    Ok(None) => Err(Error::NotFoundInDB),
                     Err(err) => return Err(err),
                 }
             })
@@ -158,7 +191,7 @@ mod tests {
         let res = store_ref.read().await.get(std::str::from_utf8(&key).unwrap()).await;
         assert!(res.is_ok());
         // Even though the write of thesame key happened concurrently, we expect the last entry to reflect
-        assert_eq!(res.unwrap().0, entry5.val);
+        assert_eq!(res.unwrap().unwrap(), entry5.val);
     }
 
     #[tokio::test]
@@ -209,7 +242,7 @@ mod tests {
             let res = store.get(&key_str).await;
             assert!(res.is_ok());
             let w = write_workload.iter().find(|e1| e1.key == e.key).unwrap();
-            assert_eq!(res.unwrap().0, w.val);
+            assert_eq!(res.unwrap().unwrap(), w.val);
         }
     }
 
@@ -234,8 +267,8 @@ mod tests {
 
         let not_found_key = "**_not_found_**";
         let res = store_ref.read().await.get(not_found_key).await;
-        assert!(res.is_err());
-        assert_eq!(res.err().unwrap().to_string(), Error::NotFoundInDB.to_string());
+        assert!(res.is_ok());
+        assert!(res.unwrap().is_none());
     }
 
     #[tokio::test]
@@ -269,33 +302,33 @@ mod tests {
         assert!(res2.is_ok());
         assert!(res3.is_ok());
         assert!(res4.is_ok());
-        assert_eq!(res1.unwrap().0, write_workload[0].val);
-        assert_eq!(res2.unwrap().0, write_workload[1].val);
-        assert_eq!(res3.unwrap().0, write_workload[2].val);
-        assert_eq!(res4.unwrap().0, write_workload[3].val);
+        assert_eq!(res1.unwrap().unwrap(), write_workload[0].val);
+        assert_eq!(res2.unwrap().unwrap(), write_workload[1].val);
+        assert_eq!(res3.unwrap().unwrap(), write_workload[2].val);
+        assert_eq!(res4.unwrap().unwrap(), write_workload[3].val);
 
         let res = store_ref.write().await.delete(key1).await;
         assert!(res.is_ok());
         assert_eq!(res.unwrap(), true);
 
         let res = store_ref.read().await.get(key1).await;
-        assert!(res.is_err());
-        assert_eq!(Error::NotFoundInDB.to_string(), res.err().unwrap().to_string());
+        assert!(res.is_ok());
+        assert!(res.unwrap().is_none());
 
         let _ = store_ref.write().await.flush_all_memtables().await;
 
         // We expect tombstone to be flushed to an sstable at this point
         let res = store_ref.read().await.get(key1).await;
-        assert!(res.is_err());
-        assert_eq!(Error::NotFoundInDB.to_string(), res.err().unwrap().to_string());
+        assert!(res.is_ok());
+        assert!(res.unwrap().is_none());
 
         let comp_res = store_ref.write().await.run_compaction().await;
         assert!(comp_res.is_ok());
 
         // Fetch after compaction
         let res = store_ref.read().await.get(key1).await;
-        assert!(res.is_err());
-        assert_eq!(Error::NotFoundInDB.to_string(), res.err().unwrap().to_string());
+        assert!(res.is_ok());
+        assert!(res.unwrap().is_none());
     }
 
     #[tokio::test]
@@ -322,7 +355,7 @@ mod tests {
 
         let res = store_ref.read().await.get(key1).await;
         assert!(res.is_ok());
-        assert_eq!(res.unwrap().0, write_workload[0].val);
+        assert_eq!(res.unwrap().unwrap(), write_workload[0].val);
 
         let res = store_ref.write().await.update(key1, updated_value).await;
         assert!(res.is_ok());
@@ -330,8 +363,8 @@ mod tests {
 
         let res = store_ref.read().await.get(key1).await;
         assert!(res.is_ok());
-        assert_ne!(res.as_ref().unwrap().0, write_workload[0].val);
-        assert_eq!(res.as_ref().unwrap().0, updated_value.as_bytes().to_vec());
+        assert_ne!(res.as_ref().unwrap(), &Some(write_workload[0].val.to_owned()));
+        assert_eq!(res.as_ref().unwrap(), &Some(updated_value.as_bytes().to_vec()));
 
         let res = store_ref.write().await.flush_all_memtables().await;
         assert!(res.is_ok());
@@ -339,7 +372,7 @@ mod tests {
         let res = store_ref.read().await.get(key1).await;
         println!("RESPONSE {:?}", res);
         assert!(res.is_ok());
-        assert_eq!(res.unwrap().0, updated_value.as_bytes().to_vec());
+        assert_eq!(res.unwrap().unwrap(), updated_value.as_bytes().to_vec());
 
         // // Run compaction
         let comp_res = store_ref.write().await.run_compaction().await;
@@ -347,7 +380,7 @@ mod tests {
 
         let res = store_ref.read().await.get(key1).await;
         assert!(res.is_ok());
-        assert_eq!(res.unwrap().0, updated_value.as_bytes().to_vec());
+        assert_eq!(res.unwrap().unwrap(), updated_value.as_bytes().to_vec());
     }
 
     #[tokio::test]
@@ -372,7 +405,7 @@ mod tests {
 
         let res = store_ref.read().await.get(key1).await;
         assert!(res.is_ok());
-        assert_eq!(res.unwrap().0, write_workload[0].val);
+        assert_eq!(res.unwrap().unwrap(), write_workload[0].val);
 
         let res = store_ref.write().await.delete(key1).await;
         assert!(res.is_ok());
@@ -382,15 +415,3697 @@ mod tests {
         assert!(res.is_ok());
 
         let res = store_ref.read().await.get(key1).await;
-        assert!(res.is_err());
-        assert_eq!(Error::NotFoundInDB.to_string(), res.err().unwrap().to_string());
+        assert!(res.is_ok());
+        assert!(res.unwrap().is_none());
 
         let comp_opt = store_ref.write().await.run_compaction().await;
         assert!(comp_opt.is_ok());
 
         let res = store_ref.read().await.get(key1).await;
-        assert!(res.is_err());
-        assert_eq!(Error::NotFoundInDB.to_string(), res.err().unwrap().to_string());
+        assert!(res.is_ok());
+        assert!(res.unwrap().is_none());
         let _ = fs::remove_dir_all(path.clone()).await;
     }
+
+    #[tokio::test]
+    async fn datastore_multi_get() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_12");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..20 {
+            let res = store.put(&format!("key_{}", i), &format!("old_{}", i)).await;
+            assert!(res.is_ok());
+        }
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+
+        // Newer versions in the memtable shadow the ones flushed to the SSTable
+        for i in 0..5 {
+            let res = store.put(&format!("key_{}", i), &format!("new_{}", i)).await;
+            assert!(res.is_ok());
+        }
+        let res = store.delete("key_10").await;
+        assert!(res.is_ok());
+
+        let keys = vec!["key_3", "key_15", "key_10", "missing", "key_0"];
+        let res = store.multi_get(&keys).await;
+        assert!(res.is_ok());
+        assert_eq!(
+            res.unwrap(),
+            vec![
+                Some(b"new_3".to_vec()),
+                Some(b"old_15".to_vec()),
+                None,
+                None,
+                Some(b"new_0".to_vec())
+            ]
+        );
+
+        let res = store.multi_get::<&str>(&[]).await;
+        assert!(res.is_ok());
+        assert!(res.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn datastore_contains_key() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_13");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..10 {
+            let res = store.put(&format!("key_{}", i), "val").await;
+            assert!(res.is_ok());
+        }
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let res = store.put("key_10", "val").await;
+        assert!(res.is_ok());
+        let res = store.delete("key_0").await;
+        assert!(res.is_ok());
+
+        // Keys in SSTables and in the active memtable
+        assert!(store.contains_key("key_5").await);
+        assert!(store.key_may_exist("key_5").await);
+        assert!(store.contains_key("key_10").await);
+        assert!(store.key_may_exist("key_10").await);
+
+        // Deleted key is shadowed by its tombstone
+        assert!(!store.contains_key("key_0").await);
+        assert!(!store.key_may_exist("key_0").await);
+
+        // Key bigger than every key in the store is ruled out by the key range
+        assert!(!store.contains_key("missing").await);
+        assert!(!store.key_may_exist("missing").await);
+    }
+
+    #[tokio::test]
+    async fn datastore_estimate_num_keys() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_14");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.estimate_num_keys().await, 0);
+        for i in 0..100 {
+            let res = store.put(&format!("key_{}", i), "val").await;
+            assert!(res.is_ok());
+        }
+        assert_eq!(store.estimate_num_keys().await, 100);
+
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        assert_eq!(store.estimate_num_keys().await, 100);
+
+        for i in 100..150 {
+            let res = store.put(&format!("key_{}", i), "val").await;
+            assert!(res.is_ok());
+        }
+        assert_eq!(store.estimate_num_keys().await, 150);
+    }
+
+    #[tokio::test]
+    async fn datastore_read_options() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_15");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "old").await;
+        assert!(res.is_ok());
+        let res = store.put("key_2", "val").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let snapshot = store.meta.sequence.last();
+        let res = store.put("key_1", "new").await;
+        assert!(res.is_ok());
+        let res = store.put("key_3", "val").await;
+        assert!(res.is_ok());
+
+        let options = ReadOptions::default();
+        assert_eq!(
+            store.get_with_options("key_1", &options).await.unwrap(),
+            Some(b"new".to_vec())
+        );
+
+        // Versions written after the snapshot are ignored
+        let options = ReadOptions {
+            snapshot: Some(snapshot),
+            ..ReadOptions::default()
+        };
+        assert_eq!(
+            store.get_with_options("key_1", &options).await.unwrap(),
+            Some(b"old".to_vec())
+        );
+        assert_eq!(store.get_with_options("key_3", &options).await.unwrap(), None);
+        let values = store
+            .multi_get_with_options(&["key_1", "key_2", "key_3"], &options)
+            .await;
+        assert_eq!(
+            values.unwrap(),
+            vec![Some(b"old".to_vec()), Some(b"val".to_vec()), None]
+        );
+        let mut iterator = store.range_with_options("key_1".."key_4", &options).await.unwrap();
+        let mut entries = Vec::new();
+        while let Some(entry) = iterator.next().await {
+            entries.push(entry.unwrap());
+        }
+        assert_eq!(
+            entries,
+            vec![
+                (b"key_1".to_vec(), b"old".to_vec()),
+                (b"key_2".to_vec(), b"val".to_vec())
+            ]
+        );
+
+        // Keys only present in SSTables are not reachable from the memtable tier
+        let options = ReadOptions {
+            read_tier: ReadTier::MemtableOnly,
+            ..ReadOptions::default()
+        };
+        assert_eq!(
+            store.get_with_options("key_1", &options).await.unwrap(),
+            Some(b"new".to_vec())
+        );
+        assert_eq!(store.get_with_options("key_2", &options).await.unwrap(), None);
+        let values = store.multi_get_with_options(&["key_2", "key_3"], &options).await;
+        assert_eq!(values.unwrap(), vec![None, Some(b"val".to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn datastore_get_at() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_16");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let before_insert = Utc::now().timestamp_millis() as u64;
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        let res = store.put("key_1", "v1").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        let after_v1 = Utc::now().timestamp_millis() as u64;
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        let res = store.put("key_1", "v2").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        let after_v2 = Utc::now().timestamp_millis() as u64;
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        let res = store.delete("key_1").await;
+        assert!(res.is_ok());
+
+        assert_eq!(store.get_at("key_1", before_insert).await.unwrap(), None);
+        assert_eq!(store.get_at("key_1", after_v1).await.unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(store.get_at("key_1", after_v2).await.unwrap(), Some(b"v2".to_vec()));
+        assert_eq!(store.get("key_1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn datastore_sample_keys() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_17");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        assert!(store.sample_keys(10).await.unwrap().is_empty());
+        for i in 0..900 {
+            let res = store.put(&format!("key_{:04}", i), "val").await;
+            assert!(res.is_ok());
+            if i % 300 == 299 {
+                let res = store.flush_all_memtables().await;
+                assert!(res.is_ok());
+            }
+        }
+        for i in 900..1000 {
+            let res = store.put(&format!("key_{:04}", i), "val").await;
+            assert!(res.is_ok());
+        }
+
+        let inserted: Vec<Vec<u8>> = (0..1000).map(|i| format!("key_{:04}", i).into_bytes()).collect();
+        let sample = store.sample_keys(20).await.unwrap();
+        assert!(!sample.is_empty() && sample.len() <= 20);
+        assert!(sample.windows(2).all(|w| w[0] < w[1]));
+        assert!(sample.iter().all(|k| inserted.contains(k)));
+        assert!(store.sample_keys(0).await.unwrap().is_empty());
+
+        // Every memtable key and one key per SSTable block are candidates
+        let sample = store.sample_keys(10000).await.unwrap();
+        assert!(sample.len() > 100 && sample.len() < 1000);
+        assert!(sample.iter().any(|k| k < &b"key_0900".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_delete_range() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_18");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..20 {
+            let res = store.put(&format!("key_{:02}", i), "val").await;
+            assert!(res.is_ok());
+        }
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        for i in 20..30 {
+            let res = store.put(&format!("key_{:02}", i), "val").await;
+            assert!(res.is_ok());
+        }
+
+        let res = store.delete_range("key_05", "key_25").await;
+        assert!(res.is_ok());
+        assert!(store.get("key_04").await.unwrap().is_some());
+        assert!(store.get("key_05").await.unwrap().is_none());
+        assert!(store.get("key_24").await.unwrap().is_none());
+        assert!(store.get("key_25").await.unwrap().is_some());
+        assert!(!store.contains_key("key_10").await);
+        let values = store.multi_get(&["key_04", "key_10", "key_22"]).await.unwrap();
+        assert_eq!(values, vec![Some(b"val".to_vec()), None, None]);
+        let keys: Vec<Vec<u8>> = store
+            .keys()
+            .await
+            .unwrap()
+            .map_ok(|e| e.key)
+            .try_collect()
+            .await
+            .unwrap();
+        let expected: Vec<Vec<u8>> = (0..5)
+            .chain(25..30)
+            .map(|i| format!("key_{:02}", i).into_bytes())
+            .collect();
+        assert_eq!(keys, expected);
+
+        // Writes made after the range deletion are visible
+        let res = store.put("key_10", "new_val").await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("key_10").await.unwrap(), Some(b"new_val".to_vec()));
+
+        // Range tombstones survive a restart
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert!(store.get("key_05").await.unwrap().is_none());
+        assert!(store.get("key_04").await.unwrap().is_some());
+        assert_eq!(store.get("key_10").await.unwrap(), Some(b"new_val".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_delete_range_compaction() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_19");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        // Enough similarly sized sstables to land in one bucket and be compacted
+        for flush in 0..4 {
+            for i in 0..300 {
+                let res = store.put(&format!("key_{:04}", i), &format!("val_{}", flush)).await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        let res = store.delete_range("key_0100", "key_0200").await;
+        assert!(res.is_ok());
+        let res = store.run_compaction().await;
+        assert!(res.is_ok());
+
+        // Covered versions are no longer stored in any sstable
+        let ranges: Vec<_> = store.key_range.read().await.key_ranges.values().cloned().collect();
+        for range in ranges {
+            let sstable = range.sst.load_entries_from_file(true).await.unwrap();
+            assert!(sstable
+                .entries
+                .iter()
+                .all(|e| e.key() < &b"key_0100".to_vec() || e.key() >= &b"key_0200".to_vec()));
+        }
+        assert!(store.get("key_0150").await.unwrap().is_none());
+        assert_eq!(store.get("key_0250").await.unwrap(), Some(b"val_3".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_put_with_ttl() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_20");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "old_val").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        // Expiring entries both in a sstable and in the active memtable
+        let res = store.put_with_ttl("key_1", "val", 200).await;
+        assert!(res.is_ok());
+        let res = store.put_with_ttl("key_2", "val", 200).await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let res = store.put_with_ttl("key_3", "val", 200).await;
+        assert!(res.is_ok());
+        let res = store.put_with_ttl("key_4", "val", 60 * 60 * 1000).await;
+        assert!(res.is_ok());
+        let res = store.put("key_5", "val").await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"val".to_vec()));
+        assert_eq!(store.get("key_3").await.unwrap(), Some(b"val".to_vec()));
+
+        sleep(Duration::from_millis(300)).await;
+        // Expired entries are not found and do not expose older versions
+        assert!(store.get("key_1").await.unwrap().is_none());
+        assert!(store.get("key_2").await.unwrap().is_none());
+        assert!(store.get("key_3").await.unwrap().is_none());
+        assert!(!store.contains_key("key_3").await);
+        let values = store.multi_get(&["key_1", "key_3", "key_4"]).await.unwrap();
+        assert_eq!(values, vec![None, None, Some(b"val".to_vec())]);
+        let keys: Vec<Vec<u8>> = store
+            .keys()
+            .await
+            .unwrap()
+            .map_ok(|e| e.key)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(keys, vec![b"key_4".to_vec(), b"key_5".to_vec()]);
+
+        // Expiry times survive a restart
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert!(store.get("key_1").await.unwrap().is_none());
+        assert!(store.get("key_3").await.unwrap().is_none());
+        assert_eq!(store.get("key_4").await.unwrap(), Some(b"val".to_vec()));
+        assert_eq!(store.get("key_5").await.unwrap(), Some(b"val".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_blind_delete() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_21");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "val").await;
+        assert!(res.is_ok());
+
+        // Deleting an absent key only writes a tombstone
+        let res = store.delete("key_2").await;
+        assert!(res.is_ok());
+        assert!(store.get("key_2").await.unwrap().is_none());
+
+        let res = store.delete_checked("key_2").await;
+        assert!(matches!(res.err().unwrap(), Error::NotFoundInDB));
+        let res = store.delete_checked("key_1").await;
+        assert!(res.is_ok());
+        assert!(store.get("key_1").await.unwrap().is_none());
+
+        // A key deleted blindly can be inserted again
+        let res = store.put("key_2", "val").await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("key_2").await.unwrap(), Some(b"val".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_insert_returns_previous_value() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_22");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.insert("key_1", "val_1").await.unwrap(), None);
+        assert_eq!(store.insert("key_1", "val_2").await.unwrap(), Some(b"val_1".to_vec()));
+
+        // The previous value is also resolved from sstables
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        assert_eq!(store.insert("key_1", "val_3").await.unwrap(), Some(b"val_2".to_vec()));
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"val_3".to_vec()));
+
+        let res = store.delete("key_1").await;
+        assert!(res.is_ok());
+        assert_eq!(store.insert("key_1", "val_4").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn datastore_get_or_insert_with() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_23");
+        let store = Arc::new(RwLock::new(DataStore::new(path.clone()).await.unwrap()));
+
+        // Only one of the concurrent callers inserts its value
+        let tasks = (0..5).map(|i| {
+            let store = Arc::clone(&store);
+            tokio::spawn(async move {
+                store
+                    .write()
+                    .await
+                    .get_or_insert_with("key_1", || format!("val_{}", i))
+                    .await
+            })
+        });
+        let values: Vec<Vec<u8>> = join_all(tasks).await.into_iter().map(|v| v.unwrap().unwrap()).collect();
+        assert!(values.iter().all(|v| *v == values[0]));
+        assert_eq!(
+            store.read().await.get("key_1").await.unwrap(),
+            Some(values[0].to_owned())
+        );
+
+        let value = store
+            .write()
+            .await
+            .get_or_insert_with("key_1", || -> String { panic!("key_1 exists") })
+            .await;
+        assert_eq!(value.unwrap(), values[0]);
+    }
+
+    #[tokio::test]
+    async fn datastore_delete_if() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_24");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("session_1", "token_1").await;
+        assert!(res.is_ok());
+
+        // The key is kept when its value changed
+        assert!(!store.delete_if("session_1", b"token_0").await.unwrap());
+        assert_eq!(store.get("session_1").await.unwrap(), Some(b"token_1".to_vec()));
+        assert!(!store.delete_if("session_2", b"token_1").await.unwrap());
+
+        assert!(store.delete_if("session_1", b"token_1").await.unwrap());
+        assert!(store.get("session_1").await.unwrap().is_none());
+        assert!(!store.delete_if("session_1", b"token_1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn datastore_increment() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_25");
+        let store = Arc::new(RwLock::new(DataStore::new(path.clone()).await.unwrap()));
+        assert_eq!(store.write().await.increment("counter", 5).await.unwrap(), 5);
+        assert_eq!(store.write().await.increment("counter", -2).await.unwrap(), 3);
+        assert_eq!(
+            store.read().await.get("counter").await.unwrap(),
+            Some(3i64.to_le_bytes().to_vec())
+        );
+
+        // Concurrent increments are never lost
+        let tasks = (0..10).map(|_| {
+            let store = Arc::clone(&store);
+            tokio::spawn(async move { store.write().await.increment("counter", 1).await })
+        });
+        for res in join_all(tasks).await {
+            assert!(res.unwrap().is_ok());
+        }
+        let res = store.write().await.flush_all_memtables().await;
+        assert!(res.is_ok());
+        assert_eq!(store.write().await.increment("counter", 0).await.unwrap(), 13);
+
+        let mut store = store.write().await;
+        let res = store.increment("counter", i64::MAX).await;
+        assert!(matches!(res.err().unwrap(), Error::CounterOverflow(_)));
+        let res = store.put("not_a_counter", "val").await;
+        assert!(res.is_ok());
+        let res = store.increment("not_a_counter", 1).await;
+        assert!(matches!(res.err().unwrap(), Error::InvalidCounterValue(_)));
+    }
+
+    #[tokio::test]
+    async fn datastore_append() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_26");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.append("log", b"a").await;
+        assert!(res.is_ok());
+        let res = store.append("log", b"b").await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("log").await.unwrap(), Some(b"ab".to_vec()));
+
+        // Appending onto a value stored in a sstable
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let res = store.append("log", b"c").await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("log").await.unwrap(), Some(b"abc".to_vec()));
+
+        let res = store.delete("log").await;
+        assert!(res.is_ok());
+        let res = store.append("log", b"d").await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("log").await.unwrap(), Some(b"d".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_write_options() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_27");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let sync = WriteOptions::new(true);
+        let res = store.put_with_options("key_1", "val", &sync).await;
+        assert!(res.is_ok());
+        let res = store.put_with_options("key_2", "val", &WriteOptions::default()).await;
+        assert!(res.is_ok());
+        let res = store.delete_with_options("key_2", &sync).await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"val".to_vec()));
+        assert!(store.get("key_2").await.unwrap().is_none());
+
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"val".to_vec()));
+        assert!(store.get("key_2").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn datastore_write_batch_chunking() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_28");
+        let config = Config {
+            max_batch_size: 100,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        let res = store.put("key_000", "old_val").await;
+        assert!(res.is_ok());
+        let mut batch = WriteBatch::new();
+        // Each operation writes 10 bytes
+        for i in 0..50 {
+            batch.put(format!("key_{:03}", i), "val");
+        }
+        batch.delete("key_010");
+        assert_eq!(batch.len(), 51);
+
+        let mut reports = Vec::new();
+        let res = store
+            .write_batch_with_progress(&batch, &WriteOptions::new(true), |p| reports.push(p.to_owned()))
+            .await;
+        assert!(res.is_ok());
+        assert_eq!(reports.len(), 6);
+        assert!(reports.iter().all(|p| p.total_chunks == 6));
+        assert_eq!(reports[0].ops_written, 10);
+        let last = reports.last().unwrap();
+        assert_eq!(
+            (last.chunks_written, last.ops_written, last.bytes_written),
+            (6, 51, batch.size())
+        );
+
+        assert_eq!(store.get("key_000").await.unwrap(), Some(b"val".to_vec()));
+        assert_eq!(store.get("key_049").await.unwrap(), Some(b"val".to_vec()));
+        assert!(store.get("key_010").await.unwrap().is_none());
+
+        let mut batch = WriteBatch::new();
+        batch.put("key_050", "val");
+        let res = store.write_batch(&batch, &WriteOptions::default()).await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("key_050").await.unwrap(), Some(b"val".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_idempotent_writes() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_29");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let options = WriteOptions::default().with_idempotency_token("request_1");
+        let res = store.put_with_options("key_1", "val_1", &options).await;
+        assert!(res.unwrap());
+        // A retry with the same token is skipped even if it carries a different value
+        let res = store.put_with_options("key_1", "val_2", &options).await;
+        assert!(!res.unwrap());
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"val_1".to_vec()));
+
+        let mut batch = WriteBatch::new();
+        batch.delete("key_1");
+        let res = store.write_batch(&batch, &options).await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"val_1".to_vec()));
+
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.delete_with_options("key_1", &options).await;
+        assert!(!res.unwrap());
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"val_1".to_vec()));
+        let options = WriteOptions::default().with_idempotency_token("request_2");
+        let res = store.delete_with_options("key_1", &options).await;
+        assert!(res.unwrap());
+        assert!(store.get("key_1").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn datastore_put_stream() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_30");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        // Spans several stream chunks
+        let value: Vec<u8> = (0..200 * 1024).map(|i| (i % 251) as u8).collect();
+        let res = store.put_stream("blob", value.as_slice(), value.len()).await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("blob").await.unwrap(), Some(value.to_owned()));
+
+        // A reader that ends early leaves nothing behind
+        let res = store.put_stream("short", &value[..10], 20).await;
+        assert!(res.is_err());
+        assert!(store.get("short").await.unwrap().is_none());
+        let res = store.put("key_1", "val_1").await;
+        assert!(res.is_ok());
+
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.get("blob").await.unwrap(), Some(value));
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"val_1".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_get_stream() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_31");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let value: Vec<u8> = (0..200 * 1024).map(|i| (i % 251) as u8).collect();
+        let res = store.put_stream("blob", value.as_slice(), value.len()).await;
+        assert!(res.is_ok());
+        let res = store.put("key_1", "val_1").await;
+        assert!(res.is_ok());
+
+        let mut reader = store.get_stream("blob").await.unwrap().unwrap();
+        let mut chunk = vec![0; 4096];
+        let mut streamed = Vec::new();
+        loop {
+            let bytes_read = reader.read(&mut chunk).await.unwrap();
+            if bytes_read == 0 {
+                break;
+            }
+            streamed.extend_from_slice(&chunk[..bytes_read]);
+        }
+        assert_eq!(streamed, value);
+
+        // The reader stops at the end of the value
+        let mut streamed = Vec::new();
+        let mut reader = store.get_stream("key_1").await.unwrap().unwrap();
+        reader.read_to_end(&mut streamed).await.unwrap();
+        assert_eq!(streamed, b"val_1".to_vec());
+
+        let res = store.delete("key_1").await;
+        assert!(res.is_ok());
+        assert!(store.get_stream("key_1").await.unwrap().is_none());
+        assert!(store.get_stream("unknown").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn datastore_entry_size_limits() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_32");
+        let config = Config {
+            max_key_size: 8,
+            max_value_size: 16,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        let res = store.put("key_1", &"v".repeat(16)).await;
+        assert!(res.is_ok());
+        let res = store.put("key_long_1", "val").await;
+        assert!(matches!(res, Err(Error::KeyTooLarge { size: 10, max: 8 })));
+        let res = store.put("key_2", &"v".repeat(17)).await;
+        assert!(matches!(res, Err(Error::ValueTooLarge { size: 17, max: 16 })));
+        let value = vec![0; 17];
+        let res = store.put_stream("key_2", value.as_slice(), value.len()).await;
+        assert!(matches!(res, Err(Error::ValueTooLarge { .. })));
+        assert!(store.get("key_2").await.unwrap().is_none());
+
+        // Nothing is written from a batch holding an oversized operation
+        let mut batch = WriteBatch::new();
+        batch.put("key_3", "val");
+        batch.put("key_long_2", "val");
+        let res = store.write_batch(&batch, &WriteOptions::default()).await;
+        assert!(matches!(res, Err(Error::KeyTooLarge { .. })));
+        assert!(store.get("key_3").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn datastore_empty_values() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_33");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "").await;
+        assert!(res.is_ok());
+        let res = store.put("key_2", "").await;
+        assert!(res.is_ok());
+        let res = store.delete("key_2").await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("key_1").await.unwrap(), Some(vec![]));
+        assert!(store.contains_key("key_1").await);
+        assert!(store.get("key_2").await.unwrap().is_none());
+
+        // Still distinguishable once flushed to an SSTable and after recovery
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("key_1").await.unwrap(), Some(vec![]));
+        assert!(store.get("key_2").await.unwrap().is_none());
+        let res = store.put("key_3", "").await;
+        assert!(res.is_ok());
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.get("key_1").await.unwrap(), Some(vec![]));
+        assert!(store.get("key_2").await.unwrap().is_none());
+        assert_eq!(store.get("key_3").await.unwrap(), Some(vec![]));
+    }
+
+    #[tokio::test]
+    async fn datastore_binary_keys_and_values() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_34");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        // Neither the keys nor the values are valid UTF-8
+        let key_1 = vec![0xff, 0x00, 0xfe];
+        let key_2 = [0xc3, 0x28];
+        let res = store.put(&key_1, [0x80, 0x81]).await;
+        assert!(res.is_ok());
+        let res = store.put(key_2, vec![0xf0, 0x28, 0x8c]).await;
+        assert!(res.is_ok());
+        let res = store.put("key_3", "val").await;
+        assert!(res.is_ok());
+        assert_eq!(store.get(&key_1).await.unwrap(), Some(vec![0x80, 0x81]));
+        assert_eq!(store.get("key_3").await.unwrap(), Some(b"val".to_vec()));
+
+        let res = store.delete(key_2).await;
+        assert!(res.is_ok());
+        assert!(store.get(key_2).await.unwrap().is_none());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.get(key_1).await.unwrap(), Some(vec![0x80, 0x81]));
+        assert!(store.get(key_2).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn datastore_snapshot() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_35");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for key in ["key_1", "key_2"] {
+            let res = store.put(key, "v1").await;
+            assert!(res.is_ok());
+        }
+        let snapshot = store.snapshot().await.unwrap();
+        let res = store.put("key_1", "v2").await;
+        assert!(res.is_ok());
+        let res = store.delete("key_2").await;
+        assert!(res.is_ok());
+        let res = store.put("key_3", "v1").await;
+        assert!(res.is_ok());
+
+        let options = snapshot.read_options();
+        assert_eq!(
+            store.get_with_options("key_1", &options).await.unwrap(),
+            Some(b"v1".to_vec())
+        );
+        assert_eq!(
+            store.get_with_options("key_2", &options).await.unwrap(),
+            Some(b"v1".to_vec())
+        );
+        assert!(store.get_with_options("key_3", &options).await.unwrap().is_none());
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"v2".to_vec()));
+        assert!(store.get("key_2").await.unwrap().is_none());
+        let mut iterator = store.range_with_options("key_1".."key_4", &options).await.unwrap();
+        let mut keys = Vec::new();
+        while let Some(entry) = iterator.next().await {
+            keys.push(entry.unwrap().0);
+        }
+        assert_eq!(keys, vec![b"key_1".to_vec(), b"key_2".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn datastore_snapshot_holds_back_compaction() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_36");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "v0").await;
+        assert!(res.is_ok());
+        let snapshot = store.snapshot().await.unwrap();
+        // Enough similarly sized sstables to land in one bucket and be compacted
+        for flush in 1..5 {
+            let res = store.put("key_1", format!("v{}", flush)).await;
+            assert!(res.is_ok());
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        let sstables = store.key_range.read().await.key_ranges.len();
+        let res = store.run_compaction().await;
+        assert!(res.is_ok());
+        assert_eq!(store.key_range.read().await.key_ranges.len(), sstables);
+        let options = snapshot.read_options();
+        assert_eq!(
+            store.get_with_options("key_1", &options).await.unwrap(),
+            Some(b"v0".to_vec())
+        );
+
+        drop(snapshot);
+        let res = store.run_compaction().await;
+        assert!(res.is_ok());
+        assert!(store.key_range.read().await.key_ranges.len() < sstables);
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"v4".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_sequence_numbers() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_37");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        // Most of these writes land within the same millisecond, they are still ordered
+        for i in 0..100 {
+            let res = store.put("key_1", format!("v{}", i)).await;
+            assert!(res.is_ok());
+        }
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"v99".to_vec()));
+
+        // Sequence numbers carry on from the last one handed out after a restart
+        let ahead = store.meta.sequence.last() + 3600000;
+        store.meta.sequence.advance_to(ahead);
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.meta.sequence.last(), ahead);
+        assert!(store.meta.sequence.next() > ahead);
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"v99".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_version_retention() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_38");
+        let config = Config {
+            version_retention: 500,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        let mut written_at = Vec::new();
+        // Enough similarly sized sstables to land in one bucket and be compacted
+        for flush in 0..4 {
+            let res = store.put("key_1", format!("v{}", flush)).await;
+            assert!(res.is_ok());
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+            tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+            written_at.push(Utc::now().timestamp_millis() as u64);
+            tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        }
+        let sstables = store.key_range.read().await.key_ranges.len();
+
+        // Older versions are kept within the window
+        let res = store.run_compaction().await;
+        assert!(res.is_ok());
+        assert_eq!(store.key_range.read().await.key_ranges.len(), sstables);
+        for (i, timestamp) in written_at.iter().enumerate() {
+            let value = store.get_at("key_1", *timestamp).await.unwrap();
+            assert_eq!(value, Some(format!("v{}", i).into_bytes()));
+        }
+
+        // And pruned once they fall out of it
+        tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+        let res = store.run_compaction().await;
+        assert!(res.is_ok());
+        assert!(store.key_range.read().await.key_ranges.len() < sstables);
+        assert!(store.get_at("key_1", written_at[0]).await.unwrap().is_none());
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"v3".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_two_phase_commit() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_39");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let mut committed = WriteBatch::new();
+        committed.put("key_1", "val_1");
+        committed.delete("key_2");
+        let mut rolled_back = WriteBatch::new();
+        rolled_back.put("key_3", "val_3");
+        let res = store.put("key_2", "val_2").await;
+        assert!(res.is_ok());
+
+        let first = store.prepare(&committed).await.unwrap();
+        let second = store.prepare(&rolled_back).await.unwrap();
+        assert_eq!(store.prepared(), vec![first, second]);
+        // Prepared batches are not applied
+        assert!(store.get("key_1").await.unwrap().is_none());
+        assert!(store.get("key_2").await.unwrap().is_some());
+
+        let res = store.commit(first).await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"val_1".to_vec()));
+        assert!(store.get("key_2").await.unwrap().is_none());
+        let res = store.rollback(second).await;
+        assert!(res.is_ok());
+        assert!(store.get("key_3").await.unwrap().is_none());
+        assert!(store.prepared().is_empty());
+        assert!(matches!(store.commit(first).await, Err(Error::UnknownPreparedToken(_))));
+        assert!(matches!(
+            store.rollback(second).await,
+            Err(Error::UnknownPreparedToken(_))
+        ));
+
+        // Batches waiting for a decision survive a restart
+        let pending = store.prepare(&rolled_back).await.unwrap();
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.prepared(), vec![pending]);
+        let res = store.rollback(pending).await;
+        assert!(res.is_ok());
+        assert!(store.prepared().is_empty());
+    }
+
+    #[tokio::test]
+    async fn datastore_transaction_reads_its_writes() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_40");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for key in ["key_1", "key_2", "key_3"] {
+            let res = store.put(key, "v1").await;
+            assert!(res.is_ok());
+        }
+
+        let mut txn = store.transaction().await.unwrap();
+        txn.put("key_1", "v2");
+        txn.delete("key_2");
+        txn.put("key_4", "v2");
+        assert_eq!(txn.get("key_1").await.unwrap(), Some(b"v2".to_vec()));
+        assert!(txn.get("key_2").await.unwrap().is_none());
+        assert_eq!(txn.get("key_3").await.unwrap(), Some(b"v1".to_vec()));
+        let entries: Vec<(Vec<u8>, Vec<u8>)> = txn
+            .scan("key_1", "key_5")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.key, e.val))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (b"key_1".to_vec(), b"v2".to_vec()),
+                (b"key_3".to_vec(), b"v1".to_vec()),
+                (b"key_4".to_vec(), b"v2".to_vec()),
+            ]
+        );
+        // Dropping the transaction discards its writes
+        drop(txn);
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"v1".to_vec()));
+
+        let mut txn = store.transaction().await.unwrap();
+        txn.put("key_1", "v3");
+        txn.delete("key_3");
+        let res = txn.commit().await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"v3".to_vec()));
+        assert!(store.get("key_3").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn datastore_key_locks() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_41");
+        let store = DataStore::new(path.clone()).await.unwrap();
+        let locks = store.key_locks.clone();
+        let store = Arc::new(tokio::sync::RwLock::new(store));
+        // Read-modify-write cycles on the same key would lose updates without the lock
+        let tasks = (0..10).map(|_| {
+            let store = Arc::clone(&store);
+            let locks = locks.clone();
+            tokio::spawn(async move {
+                let _guard = locks.lock(b"counter").await;
+                let count = match store.read().await.get("counter").await.unwrap() {
+                    Some(value) => u64::from_le_bytes(value.try_into().unwrap()),
+                    None => 0,
+                };
+                tokio::task::yield_now().await;
+                store.write().await.put("counter", (count + 1).to_le_bytes()).await
+            })
+        });
+        for res in join_all(tasks).await {
+            assert!(res.unwrap().is_ok());
+        }
+        let store = store.read().await;
+        assert_eq!(store.get("counter").await.unwrap(), Some(10u64.to_le_bytes().to_vec()));
+        let guard = store.lock("counter").await;
+        assert_eq!(guard.key(), &b"counter".to_vec());
+        drop(guard);
+        assert!(locks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn datastore_changes_since() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_42");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "v1").await;
+        assert!(res.is_ok());
+        let checkpoint = store.meta.sequence.last();
+        let res = store.put("key_2", "v1").await;
+        assert!(res.is_ok());
+        let res = store.delete("key_1").await;
+        assert!(res.is_ok());
+
+        let changes: Vec<Change> = store.changes_since(checkpoint).await.unwrap().collect();
+        assert_eq!(changes.len(), 2);
+        assert!(matches!(&changes[0], Change::Put { key, value, .. } if key == b"key_2" && value == b"v1"));
+        assert!(matches!(&changes[1], Change::Delete { key, .. } if key == b"key_1"));
+        assert!(changes[0].sequence() > checkpoint);
+        assert!(changes[1].sequence() > changes[0].sequence());
+
+        // Resuming after the last change returns nothing new
+        let last = changes[1].sequence();
+        assert_eq!(store.changes_since(last).await.unwrap().count(), 0);
+        assert_eq!(store.changes_since(0).await.unwrap().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn datastore_subscribe() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_43");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let mut users = store.subscribe("user_");
+        let mut everything = store.subscribe("");
+        let res = store.put("user_1", "alice").await;
+        assert!(res.is_ok());
+        let res = store.put("order_1", "book").await;
+        assert!(res.is_ok());
+        let res = store.delete("user_1").await;
+        assert!(res.is_ok());
+
+        assert!(
+            matches!(users.try_recv(), Ok(Change::Put { key, value, .. }) if key == b"user_1" && value == b"alice")
+        );
+        assert!(matches!(users.try_recv(), Ok(Change::Delete { key, .. }) if key == b"user_1"));
+        assert!(users.try_recv().is_err());
+        let keys: Vec<Vec<u8>> = std::iter::from_fn(|| everything.try_recv().ok())
+            .map(|e| e.key().to_owned())
+            .collect();
+        assert_eq!(keys, vec![b"user_1".to_vec(), b"order_1".to_vec(), b"user_1".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn datastore_group_commit() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_44");
+        let config = Config {
+            max_key_size: 8,
+            ..Config::default()
+        };
+        let store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        let store = Arc::new(RwLock::new(store));
+        let group = GroupCommit::new(store.clone(), WriteOptions::new(true));
+        let tasks = (0..50).map(|i| {
+            let group = group.clone();
+            tokio::spawn(async move { group.put(format!("key_{}", i), format!("val_{}", i)).await })
+        });
+        for res in join_all(tasks).await {
+            assert!(res.unwrap().is_ok());
+        }
+        let res = group.delete("key_0").await;
+        assert!(res.is_ok());
+
+        // An invalid write fails alone with its own error, the writes grouped with it succeed
+        let tasks = (50..60).map(|i| {
+            let group = group.clone();
+            let key = match i {
+                55 => "key_long_1".to_owned(),
+                _ => format!("key_{}", i),
+            };
+            tokio::spawn(async move { group.put(key, "val").await })
+        });
+        for (i, res) in (50..60).zip(join_all(tasks).await) {
+            match i {
+                55 => assert!(matches!(res.unwrap(), Err(Error::KeyTooLarge { size: 10, max: 8 }))),
+                _ => assert!(res.unwrap().is_ok()),
+            }
+        }
+
+        let store = store.read().await;
+        assert!(store.get("key_0").await.unwrap().is_none());
+        for i in 1..50 {
+            let val = store.get(format!("key_{}", i)).await.unwrap();
+            assert_eq!(val, Some(format!("val_{}", i).into_bytes()));
+        }
+    }
+
+    #[tokio::test]
+    async fn datastore_manifest() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_45");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for flush in 0..2 {
+            let res = store.put(format!("key_{}", flush), "val").await;
+            assert!(res.is_ok());
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        let sstables = store.key_range.read().await.key_ranges.len();
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let manifest = path.join("meta").join("manifest.bin");
+        assert!(manifest.exists());
+
+        // Directories the manifest does not list, such as a crashed flush, are not opened
+        let partial = path.join("buckets").join(format!("bucket{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(partial.join("sstable_1")).await.unwrap();
+        fs::write(partial.join("sstable_1").join("data.db"), b"partial")
+            .await
+            .unwrap();
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.key_range.read().await.key_ranges.len(), sstables);
+        assert_eq!(store.get("key_0").await.unwrap(), Some(b"val".to_vec()));
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"val".to_vec()));
+        drop(store);
+
+        // A store without a manifest is walked once and gets one
+        fs::remove_file(&manifest).await.unwrap();
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert!(manifest.exists());
+        assert_eq!(store.key_range.read().await.key_ranges.len(), sstables);
+        assert_eq!(store.get("key_0").await.unwrap(), Some(b"val".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_sstables_written_atomically() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_46");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "val").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let sst = store
+            .key_range
+            .read()
+            .await
+            .key_ranges
+            .values()
+            .next()
+            .unwrap()
+            .sst
+            .clone();
+        assert!(sst.data_file.path.starts_with(&sst.dir));
+        assert!(sst.data_file.path.is_file());
+        assert!(sst.index_file.path.is_file());
+        assert!(!sst.dir.with_extension("tmp").exists());
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        // A flush interrupted by a crash leaves a temporary directory that is never opened as an SSTable
+        let partial = sst.dir.with_file_name("sstable_1.tmp");
+        fs::create_dir_all(&partial).await.unwrap();
+        fs::write(partial.join("data.db"), b"partial").await.unwrap();
+        fs::remove_file(path.join("meta").join("manifest.bin")).await.unwrap();
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.key_range.read().await.key_ranges.len(), 1);
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"val".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_startup_cleanup() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_47");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "val").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let sst = store
+            .key_range
+            .read()
+            .await
+            .key_ranges
+            .values()
+            .next()
+            .unwrap()
+            .sst
+            .clone();
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert!(store.recovery_report().removed.is_empty());
+        drop(store);
+
+        // Leftovers of a crash in the middle of a compaction and of a meta file rewrite
+        let unlisted_sst = sst.dir.with_file_name("sstable_1");
+        let partial_sst = sst.dir.with_file_name("sstable_2.tmp");
+        let unlisted_bucket = path.join("buckets").join(format!("bucket{}", uuid::Uuid::new_v4()));
+        let tmp_file = path.join("meta").join("manifest.tmp");
+        for dir in [&unlisted_sst, &partial_sst, &unlisted_bucket.join("sstable_3")] {
+            fs::create_dir_all(dir).await.unwrap();
+            fs::write(dir.join("data.db"), b"partial").await.unwrap();
+        }
+        fs::write(&tmp_file, b"partial").await.unwrap();
+
+        let store = DataStore::new(path.clone()).await.unwrap();
+        let mut removed = store.recovery_report().removed.clone();
+        removed.sort();
+        let mut expected = vec![unlisted_sst, partial_sst, unlisted_bucket, tmp_file];
+        expected.sort();
+        assert_eq!(removed, expected);
+        assert!(removed.iter().all(|path| !path.exists()));
+        assert!(sst.data_file.path.exists());
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"val".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_file_numbers() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_48");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "val").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let sst = store
+            .key_range
+            .read()
+            .await
+            .key_ranges
+            .values()
+            .next()
+            .unwrap()
+            .sst
+            .clone();
+        let bucket_dir = sst.dir.parent().unwrap().to_owned();
+        assert_eq!(bucket_dir.file_name().unwrap(), "bucket_000001");
+        assert_eq!(sst.dir.file_name().unwrap(), "sstable_000002");
+        assert_eq!(sst.data_file.path, sst.dir.join("data_000002.db"));
+        assert_eq!(sst.index_file.path, sst.dir.join("index_000002.db"));
+        drop(store);
+
+        // Numbers handed out after the meta file was last written are recovered from the manifest
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.meta.file_numbers.last(), 2);
+        assert_eq!(store.buckets.read().await.file_numbers.next(), 3);
+        assert_eq!(store.meta.file_numbers.last(), 3);
+    }
+
+    #[tokio::test]
+    async fn datastore_value_log_checksums() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_49");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "value_1").await;
+        assert!(res.is_ok());
+        let res = store.put("key_2", "value_2").await;
+        assert!(res.is_ok());
+        store.val_log.sync_to_disk().await.unwrap();
+
+        // Flip the last byte of the value of `key_2`, just before the checksum ending the log
+        let vlog_path = store.val_log.content.path.to_owned();
+        let mut bytes = fs::read(&vlog_path).await.unwrap();
+        let last_value_byte = bytes.len() - 5;
+        bytes[last_value_byte] ^= 0xFF;
+        fs::write(&vlog_path, &bytes).await.unwrap();
+
+        assert_eq!(store.get("key_1").await.unwrap().unwrap(), b"value_1".to_vec());
+        let res = store.get("key_2").await;
+        assert!(matches!(res, Err(Error::CorruptedValueLogEntry { .. })));
+        drop(store);
+
+        // Replaying the log stops at the corrupted entry and drops it
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.get("key_1").await.unwrap().unwrap(), b"value_1".to_vec());
+        assert!(store.get("key_2").await.unwrap().is_none());
+        assert!(fs::metadata(&vlog_path).await.unwrap().len() < bytes.len() as u64);
+    }
+
+    #[tokio::test]
+    async fn datastore_truncate_torn_value_log_tail() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_50");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "value_1").await;
+        assert!(res.is_ok());
+        store.val_log.sync_to_disk().await.unwrap();
+        let vlog_path = store.val_log.content.path.to_owned();
+        drop(store);
+
+        // A crash in the middle of an append leaves the start of an entry at the end of the log
+        let mut bytes = fs::read(&vlog_path).await.unwrap();
+        let valid_len = bytes.len() as u64;
+        bytes.extend_from_slice(&5u32.to_le_bytes());
+        bytes.extend_from_slice(&1024u32.to_le_bytes());
+        bytes.extend_from_slice(b"torn");
+        fs::write(&vlog_path, &bytes).await.unwrap();
+
+        // The torn entry could be an append still in progress in the process holding the store
+        let read_only = DataStore::open_read_only(path.clone()).await.unwrap();
+        assert_eq!(read_only.get("key_1").await.unwrap().unwrap(), b"value_1".to_vec());
+        assert_eq!(fs::metadata(&vlog_path).await.unwrap().len(), bytes.len() as u64);
+        drop(read_only);
+
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.get("key_1").await.unwrap().unwrap(), b"value_1".to_vec());
+        assert_eq!(fs::metadata(&vlog_path).await.unwrap().len(), valid_len);
+    }
+
+    #[tokio::test]
+    async fn datastore_block_checksums() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_51");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "value_1").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let sst = store
+            .key_range
+            .read()
+            .await
+            .key_ranges
+            .values()
+            .next()
+            .unwrap()
+            .sst
+            .clone();
+
+        // Flip the first byte of the creation date of `key_1`, the first entry of the first block,
+        // which follows the checksum type header
+        let mut bytes = fs::read(&sst.data_file.path).await.unwrap();
+        let created_at_first_byte = 1 + 4 + 4 + "key_1".len() + 4;
+        bytes[created_at_first_byte] ^= 0x01;
+        fs::write(&sst.data_file.path, &bytes).await.unwrap();
+
+        match store.get("key_1").await {
+            Err(Error::ChecksumMismatch { path, offset }) => {
+                assert_eq!(path, sst.data_file.path);
+                assert_eq!(offset, 1);
+            }
+            res => panic!("expected a checksum mismatch, got {:?}", res),
+        }
+        let options = ReadOptions {
+            verify_checksums: false,
+            ..Default::default()
+        };
+        let res = store.get_with_options("key_1", &options).await;
+        assert_eq!(res.unwrap(), Some(b"value_1".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_index_checksum() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_52");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "value_1").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let sst = store
+            .key_range
+            .read()
+            .await
+            .key_ranges
+            .values()
+            .next()
+            .unwrap()
+            .sst
+            .clone();
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"value_1".to_vec()));
+
+        // Point the only block of the index to another offset
+        let mut bytes = fs::read(&sst.index_file.path).await.unwrap();
+        let footer = bytes.len() - 4;
+        bytes[footer - 4] ^= 0x01;
+        fs::write(&sst.index_file.path, &bytes).await.unwrap();
+
+        match store.get("key_1").await {
+            Err(Error::ChecksumMismatch { path, offset }) => {
+                assert_eq!(path, sst.index_file.path);
+                assert_eq!(offset, footer);
+            }
+            res => panic!("expected a checksum mismatch, got {:?}", res),
+        }
+        let res = store.multi_get(&["key_1"]).await;
+        assert!(matches!(res, Err(Error::ChecksumMismatch { .. })));
+    }
+
+    #[tokio::test]
+    async fn datastore_checksum_type_change() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_53");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "value_1").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let res = store.put("key_2", "value_2").await;
+        assert!(res.is_ok());
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        // Files written with CRC32C stay readable once the config selects XXH64
+        let config = Config {
+            checksum_type: ChecksumType::XxHash64,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"value_1".to_vec()));
+        assert_eq!(store.get("key_2").await.unwrap(), Some(b"value_2".to_vec()));
+        let res = store.put("key_3", "value_3").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let mut headers = Vec::new();
+        for range in store.key_range.read().await.key_ranges.values() {
+            let bytes = fs::read(&range.sst.data_file.path).await.unwrap();
+            headers.push(bytes[0]);
+        }
+        headers.sort();
+        assert_eq!(headers, vec![0, 1]);
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        // And the other way around
+        let store = DataStore::new(path.clone()).await.unwrap();
+        for (key, value) in [("key_1", "value_1"), ("key_2", "value_2"), ("key_3", "value_3")] {
+            assert_eq!(store.get(key).await.unwrap(), Some(value.as_bytes().to_vec()));
+        }
+    }
+
+    #[tokio::test]
+    async fn datastore_persist_value_log_offsets() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_54");
+        let config = Config {
+            write_buffer_size: 1024,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        for i in 0..100 {
+            let res = store.put(format!("key_{:03}", i), format!("value_{:03}", i)).await;
+            assert!(res.is_ok());
+        }
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let offsets = store.meta.v_log_offsets().await.unwrap();
+        assert!(offsets.head > 0);
+        assert!(offsets.head <= store.val_log.head_offset);
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        let store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        assert_eq!(store.val_log.head_offset, offsets.head);
+        assert_eq!(store.val_log.tail_offset, offsets.tail);
+        for i in 0..100 {
+            let value = store.get(format!("key_{:03}", i)).await.unwrap();
+            assert_eq!(value, Some(format!("value_{:03}", i).into_bytes()));
+        }
+        let meta_file = store.dir.meta.join("meta.bin");
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        // Meta files written before the offsets were persisted fall back to the SSTables
+        let bytes = fs::read(&meta_file).await.unwrap();
+        fs::write(&meta_file, &bytes[..16]).await.unwrap();
+        let store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        assert!(store.meta.v_log_offsets().await.is_some());
+        for i in 0..100 {
+            let value = store.get(format!("key_{:03}", i)).await.unwrap();
+            assert_eq!(value, Some(format!("value_{:03}", i).into_bytes()));
+        }
+    }
+
+    #[tokio::test]
+    async fn datastore_key_ranges_survive_reopen() {
+        async fn sorted_key_ranges(store: &DataStore<'_, Vec<u8>>) -> Vec<(PathBuf, Vec<u8>, Vec<u8>)> {
+            let key_range = store.key_range.read().await;
+            let mut ranges: Vec<_> = key_range
+                .key_ranges
+                .iter()
+                .map(|(path, range)| {
+                    (
+                        path.to_owned(),
+                        range.smallest_key.to_owned(),
+                        range.biggest_key.to_owned(),
+                    )
+                })
+                .collect();
+            ranges.sort();
+            ranges
+        }
+
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_55");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for flush in 0..4 {
+            for i in 0..300 {
+                let res = store.put(&format!("key_{:04}", flush * 300 + i), "val").await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        let res = store.run_compaction().await;
+        assert!(res.is_ok());
+        let ranges = sorted_key_ranges(&store).await;
+        assert!(!ranges.is_empty());
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        // Key ranges are rebuilt from the manifest, pruning works without rewriting any SSTable
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(sorted_key_ranges(&store).await, ranges);
+        let candidates = store
+            .key_range
+            .read()
+            .await
+            .filter_sstables_by_biggest_key(&b"key_1199".to_vec());
+        assert!(!candidates.is_empty());
+        assert_eq!(store.get("key_0000").await.unwrap(), Some(b"val".to_vec()));
+        assert_eq!(store.get("key_1199").await.unwrap(), Some(b"val".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_quarantine_corrupted_sstable() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_56");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "value_1").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let corrupted = store
+            .key_range
+            .read()
+            .await
+            .key_ranges
+            .values()
+            .next()
+            .unwrap()
+            .sst
+            .clone();
+        let res = store.put("key_2", "value_2").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        // Flip a byte of the only block of the first SSTable
+        let mut bytes = fs::read(&corrupted.data_file.path).await.unwrap();
+        bytes[1 + 4] ^= 0x01;
+        fs::write(&corrupted.data_file.path, &bytes).await.unwrap();
+
+        let store = DataStore::new(path.clone()).await.unwrap();
+        let quarantined = store.recovery_report().quarantined.clone();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].dir, corrupted.dir);
+        let moved_to = quarantined[0].moved_to.clone().unwrap();
+        assert!(moved_to.starts_with(path.join("quarantine")));
+        assert!(moved_to.exists());
+        assert!(!corrupted.dir.exists());
+        assert!(!store
+            .key_range
+            .read()
+            .await
+            .key_ranges
+            .contains_key(&corrupted.data_file.path));
+        assert_eq!(store.get("key_2").await.unwrap(), Some(b"value_2".to_vec()));
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        // The quarantined SSTable is no longer part of the store
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert!(store.recovery_report().quarantined.is_empty());
+        assert!(moved_to.exists());
+        assert_eq!(store.get("key_2").await.unwrap(), Some(b"value_2".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_open_with_progress() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_57");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for flush in 0..2 {
+            for i in 0..100 {
+                let res = store.put(&format!("key_{:04}", flush * 100 + i), "val").await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        // Entries written after the last flush are only in the value log and are replayed on open
+        for i in 200..500 {
+            let res = store.put(&format!("key_{:04}", i), "val").await;
+            assert!(res.is_ok());
+        }
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        let config = Config {
+            write_buffer_size: 1024,
+            ..Config::default()
+        };
+        let mut reported = Vec::new();
+        let store = DataStore::open_with_progress(path.clone(), config, |progress| reported.push(progress.clone()))
+            .await
+            .unwrap();
+        let first = reported.first().unwrap();
+        assert!(first.tables_discovered > 0);
+        assert_eq!(first.tables_loaded, 0);
+        for pair in reported.windows(2) {
+            assert!(pair[1].tables_loaded >= pair[0].tables_loaded);
+            assert!(pair[1].bytes_replayed >= pair[0].bytes_replayed);
+            assert!(pair[1].memtables_rebuilt >= pair[0].memtables_rebuilt);
+        }
+        let last = reported.last().unwrap();
+        assert_eq!(last.tables_loaded, last.tables_discovered);
+        assert!(last.bytes_replayed > 0);
+        assert_eq!(last.bytes_replayed, last.bytes_to_replay);
+        assert!(last.memtables_rebuilt > 0);
+        assert_eq!(store.get("key_0000").await.unwrap(), Some(b"val".to_vec()));
+        assert_eq!(store.get("key_0499").await.unwrap(), Some(b"val".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_open_loads_sstables_in_parallel() {
+        async fn opened_tables(path: &Path, max_parallel_sstable_reads: usize) -> (Vec<PathBuf>, usize) {
+            let config = Config {
+                max_parallel_sstable_reads,
+                ..Config::default()
+            };
+            let store = DataStore::new_with_custom_config(path.to_path_buf(), config)
+                .await
+                .unwrap();
+            for i in 0..600 {
+                let value = store.get(&format!("key_{:04}", i)).await.unwrap();
+                assert_eq!(value, Some(b"val".to_vec()));
+            }
+            let mut tables: Vec<PathBuf> = store.key_range.read().await.key_ranges.keys().cloned().collect();
+            tables.sort();
+            let filters = store.filters.read().await.len();
+            let res = store.close().await;
+            assert!(res.is_ok());
+            (tables, filters)
+        }
+
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_58");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for flush in 0..6 {
+            for i in 0..100 {
+                let res = store.put(&format!("key_{:04}", flush * 100 + i), "val").await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        // Loading SSTables one at a time or concurrently opens the same store
+        let (sequential, sequential_filters) = opened_tables(&path, 1).await;
+        let (parallel, parallel_filters) = opened_tables(&path, 8).await;
+        assert!(sequential.len() > 1);
+        assert_eq!(sequential, parallel);
+        assert_eq!(sequential_filters, parallel_filters);
+    }
+
+    #[tokio::test]
+    async fn datastore_open_read_only() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_59");
+        let res = DataStore::open_read_only(path.clone()).await;
+        assert!(res.is_err());
+        assert!(!path.exists());
+
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..100 {
+            let res = store.put(&format!("key_{:03}", i), "flushed").await;
+            assert!(res.is_ok());
+        }
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let res = store.put("key_100", "in_value_log").await;
+        assert!(res.is_ok());
+
+        // The directory is still locked by the store above
+        let mut read_only = DataStore::open_read_only(path.clone()).await.unwrap();
+        assert!(read_only.is_read_only());
+        assert!(!store.is_read_only());
+        assert_eq!(read_only.get("key_000").await.unwrap(), Some(b"flushed".to_vec()));
+        assert_eq!(read_only.get("key_100").await.unwrap(), Some(b"in_value_log".to_vec()));
+        assert!(matches!(
+            read_only.put("key_101", "value").await,
+            Err(Error::ReadOnlyStore)
+        ));
+        assert!(matches!(read_only.delete("key_000").await, Err(Error::ReadOnlyStore)));
+        assert!(matches!(
+            read_only.flush_all_memtables().await,
+            Err(Error::ReadOnlyStore)
+        ));
+        assert_eq!(read_only.get("key_101").await.unwrap(), None);
+        let res = read_only.close().await;
+        assert!(res.is_ok());
+
+        // The read-only store left the directory as the writable store expects it
+        let res = store.put("key_101", "value").await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("key_000").await.unwrap(), Some(b"flushed".to_vec()));
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_restore_to() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_60");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "v1").await;
+        assert!(res.is_ok());
+        let res = store.put("key_2", "v1").await;
+        assert!(res.is_ok());
+        let res = store.put_with_ttl("key_3", "v1", 60 * 60 * 1000).await;
+        assert!(res.is_ok());
+        let res = store.put_with_ttl("key_4", "v1", 1).await;
+        assert!(res.is_ok());
+        let checkpoint = store.meta.sequence.last();
+        let res = store.put("key_1", "v2").await;
+        assert!(res.is_ok());
+        let res = store.delete("key_2").await;
+        assert!(res.is_ok());
+        let res = store.put("key_5", "v2").await;
+        assert!(res.is_ok());
+
+        let restored_path = root.path().join("store_test_60_restored");
+        let restored = store.restore_to(checkpoint, restored_path.clone()).await.unwrap();
+        assert_eq!(restored.get("key_1").await.unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(restored.get("key_2").await.unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(restored.get("key_3").await.unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(restored.get("key_4").await.unwrap(), None);
+        assert_eq!(restored.get("key_5").await.unwrap(), None);
+        // The store restored from is left as it is
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"v2".to_vec()));
+        assert_eq!(store.get("key_2").await.unwrap(), None);
+        let res = restored.close().await;
+        assert!(res.is_ok());
+
+        // Restoring into a directory that is not empty fails
+        let res = store.restore_to(checkpoint, restored_path).await;
+        assert!(matches!(res, Err(Error::DirectoryOpenError { .. })));
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_halts_writes_after_background_error() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_61");
+        let config = Config {
+            write_buffer_size: 1024,
+            max_buffer_write_number: 1,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        assert!(store.last_background_error().is_none());
+        assert!(store.resume().is_none());
+
+        // Background flushes fail to create the directory of their bucket
+        let buckets = store.dir.buckets.to_owned();
+        let _ = fs::remove_dir_all(&buckets).await;
+        fs::write(&buckets, b"").await.unwrap();
+        let mut written = 0;
+        let halted = loop {
+            match store.put(format!("key_{:04}", written), "value").await {
+                Ok(_) => written += 1,
+                Err(err) => break err,
+            }
+            assert!(written < 10_000, "writes were not halted");
+            sleep(Duration::from_millis(1)).await;
+        };
+        assert!(matches!(halted, Error::BackgroundError(_)));
+        let err = store.last_background_error().unwrap();
+        assert!(matches!(*err, Error::FlushError(_)));
+        assert!(matches!(store.delete("key_0000").await, Err(Error::BackgroundError(_))));
+        assert!(matches!(
+            store.flush_all_memtables().await,
+            Err(Error::BackgroundError(_))
+        ));
+        // Reads are still served
+        assert_eq!(store.get("key_0000").await.unwrap(), Some(b"value".to_vec()));
+
+        fs::remove_file(&buckets).await.unwrap();
+        assert!(store.resume().is_some());
+        assert!(store.last_background_error().is_none());
+        let res = store.put("resumed", "value").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        for i in 0..written {
+            let value = store.get(format!("key_{:04}", i)).await.unwrap();
+            assert_eq!(value, Some(b"value".to_vec()));
+        }
+        assert_eq!(store.get("resumed").await.unwrap(), Some(b"value".to_vec()));
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_halts_writes_when_disk_is_full() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_62");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let mut events = store.subscribe_background_errors();
+        let res = store.put("key_1", "value").await;
+        assert!(res.is_ok());
+
+        // Other write errors are returned as they are
+        let err = Error::FileWriteError {
+            path: path.clone(),
+            error: std::io::Error::from(std::io::ErrorKind::PermissionDenied),
+        };
+        let err = store.background_errors.halt_if_disk_full(err);
+        assert!(matches!(err, Error::FileWriteError { .. }));
+        assert!(store.last_background_error().is_none());
+
+        let err = Error::FileWriteError {
+            path: path.clone(),
+            error: std::io::Error::from(std::io::ErrorKind::StorageFull),
+        };
+        let err = store.background_errors.halt_if_disk_full(err);
+        assert!(matches!(err, Error::BackgroundError(_)));
+        assert!(store.last_background_error().unwrap().is_disk_full());
+        assert!(events.try_recv().unwrap().is_disk_full());
+        assert!(matches!(
+            store.put("key_2", "value").await,
+            Err(Error::BackgroundError(_))
+        ));
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"value".to_vec()));
+
+        assert!(store.resume().is_some());
+        let res = store.put("key_2", "value").await;
+        assert!(res.is_ok());
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_compaction_keeps_reserved_disk_space() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_63");
+        let config = Config {
+            reserved_disk_space: u64::MAX,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        let res = store.put("key_1", "value").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        assert!(matches!(
+            store.run_compaction().await,
+            Err(Error::NotEnoughDiskSpace { .. })
+        ));
+        // Flushes and writes are not held back by the reserve
+        assert!(store.last_background_error().is_none());
+        let res = store.put("key_2", "value").await;
+        assert!(res.is_ok());
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_value_log_record_framing() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_64");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.val_log.format(), ValueLogFormat::V1);
+        let res = store.put("key_1", "value_1").await;
+        assert!(res.is_ok());
+        let vlog_path = store.val_log.content.path.to_owned();
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        let bytes = fs::read(&vlog_path).await.unwrap();
+        assert_eq!(&bytes[..4], b"VLOG");
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()), 1);
+        assert_eq!(bytes[8], 0xA5);
+
+        // A record whose length runs past the end of the file was torn
+        let mut torn = bytes.clone();
+        torn.push(0xA5);
+        torn.extend_from_slice(&1024u32.to_le_bytes());
+        torn.extend_from_slice(b"torn");
+        fs::write(&vlog_path, &torn).await.unwrap();
+
+        // Entries appended after reopening are found at the offsets they were given
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(fs::metadata(&vlog_path).await.unwrap().len(), bytes.len() as u64);
+        let res = store.put("key_2", "value_2").await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("key_1").await.unwrap().unwrap(), b"value_1".to_vec());
+        assert_eq!(store.get("key_2").await.unwrap().unwrap(), b"value_2".to_vec());
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.get("key_2").await.unwrap().unwrap(), b"value_2".to_vec());
+    }
+
+    // Encodes an entry the way value logs were written before records were framed
+    fn legacy_value_log_entry(key: &[u8], value: &[u8], created_at: u64) -> Vec<u8> {
+        let mut entry = Vec::new();
+        entry.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        entry.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        entry.extend_from_slice(&created_at.to_le_bytes());
+        entry.push(0);
+        entry.extend_from_slice(key);
+        entry.extend_from_slice(value);
+        let checksum = ChecksumType::Crc32c.checksum(&entry);
+        entry.extend_from_slice(&checksum);
+        entry
+    }
+
+    #[tokio::test]
+    async fn datastore_legacy_value_log() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_65");
+        let vlog_dir = path.join("v_log");
+        fs::create_dir_all(&vlog_dir).await.unwrap();
+        fs::create_dir_all(path.join("buckets")).await.unwrap();
+        let created_at = Utc::now().timestamp_millis() as u64;
+        let bytes = [
+            legacy_value_log_entry(b"tail", b"tail", created_at),
+            legacy_value_log_entry(b"head", b"head", created_at),
+            legacy_value_log_entry(b"key_1", b"value_1", created_at + 1),
+        ]
+        .concat();
+        fs::write(vlog_dir.join("val_log.bin"), &bytes).await.unwrap();
+
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.val_log.format(), ValueLogFormat::Legacy);
+        assert_eq!(store.get("key_1").await.unwrap().unwrap(), b"value_1".to_vec());
+        let res = store.put("key_2", "value_2").await;
+        assert!(res.is_ok());
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        // Entries keep the layout of the file they are appended to
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.val_log.format(), ValueLogFormat::Legacy);
+        assert_eq!(store.get("key_1").await.unwrap().unwrap(), b"value_1".to_vec());
+        assert_eq!(store.get("key_2").await.unwrap().unwrap(), b"value_2".to_vec());
+    }
+
+    #[tokio::test]
+    async fn datastore_unsupported_value_log_version() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_66");
+        let vlog_dir = path.join("v_log");
+        fs::create_dir_all(&vlog_dir).await.unwrap();
+        // Version 2 is written by copy-and-truncate garbage collection
+        let header = [&b"VLOG"[..], &3u32.to_le_bytes()].concat();
+        fs::write(vlog_dir.join("val_log.bin"), &header).await.unwrap();
+
+        let res = DataStore::new(path.clone()).await;
+        assert!(matches!(res, Err(Error::UnsupportedValueLogVersion { version: 3, .. })));
+    }
+
+    // Removes `expired_` keys and uppercases the values of `migrate_` keys
+    #[derive(Debug)]
+    struct PrefixFilter;
+
+    impl CompactionFilter for PrefixFilter {
+        fn filter(&self, key: &[u8], value: &[u8], _written_at: u64) -> CompactionDecision {
+            if key.starts_with(b"expired_") {
+                CompactionDecision::Remove
+            } else if key.starts_with(b"migrate_") {
+                CompactionDecision::ChangeValue(value.to_ascii_uppercase())
+            } else {
+                CompactionDecision::Keep
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn datastore_compaction_filter() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_67");
+        let config = Config {
+            compaction_filter: Some(Arc::new(PrefixFilter)),
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        // Enough similarly sized sstables to land in one bucket and be compacted
+        for flush in 0..4 {
+            for key in ["expired", "migrate", "kept"] {
+                let res = store.put(format!("{}_{}", key, flush), "value").await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        let sstables = store.key_range.read().await.key_ranges.len();
+        let res = store.run_compaction().await;
+        assert!(res.is_ok());
+        assert!(store.key_range.read().await.key_ranges.len() < sstables);
+        for flush in 0..4 {
+            assert_eq!(store.get(format!("expired_{}", flush)).await.unwrap(), None);
+            assert_eq!(
+                store.get(format!("migrate_{}", flush)).await.unwrap(),
+                Some(b"VALUE".to_vec())
+            );
+            assert_eq!(
+                store.get(format!("kept_{}", flush)).await.unwrap(),
+                Some(b"value".to_vec())
+            );
+        }
+
+        // Rewritten values are not replayed over versions written after them
+        let res = store.put("migrate_1", "newer").await;
+        assert!(res.is_ok());
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        assert_eq!(store.get("migrate_1").await.unwrap(), Some(b"newer".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_subcompactions() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_68");
+        let config = Config {
+            max_subcompactions: 2,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        // Enough entries for two sub-compactions
+        for flush in 0..4 {
+            for i in 0..700 {
+                let res = store.put(format!("key_{:04}_{}", i, flush), "value").await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        let res = store.run_compaction().await;
+        assert!(res.is_ok());
+        let key_range = store.key_range.read().await;
+        let mut ranges: Vec<_> = key_range.key_ranges.values().collect();
+        assert_eq!(ranges.len(), 2);
+        ranges.sort_by(|a, b| a.smallest_key.cmp(&b.smallest_key));
+        assert!(ranges[0].biggest_key < ranges[1].smallest_key);
+        drop(key_range);
+        for i in (0..700).step_by(50) {
+            for flush in 0..4 {
+                assert_eq!(
+                    store.get(format!("key_{:04}_{}", i, flush)).await.unwrap(),
+                    Some(b"value".to_vec())
+                );
+            }
+        }
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_compaction_rate_limit() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_69");
+        let config = Config {
+            compaction_rate_limit: 1024,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        assert_eq!(store.compactor.config.rate_limiter.rate(), 1024);
+        store.set_compaction_rate_limit(1024 * 1024);
+        assert_eq!(store.config.compaction_rate_limit, 1024 * 1024);
+        assert_eq!(store.compactor.config.rate_limiter.rate(), 1024 * 1024);
+        for flush in 0..4 {
+            let res = store.put(format!("key_{}", flush), "value").await;
+            assert!(res.is_ok());
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        let res = store.run_compaction().await;
+        assert!(res.is_ok());
+        for flush in 0..4 {
+            assert_eq!(
+                store.get(format!("key_{}", flush)).await.unwrap(),
+                Some(b"value".to_vec())
+            );
+        }
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_tombstone_dropped_by_bottom_most_compaction() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_70");
+        let config = Config {
+            tombstone_ttl: 0,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        // A large sstable lands in a bucket of its own and is left out of the compaction below
+        for i in 0..40 {
+            let res = store.put(format!("key_{:04}_{}", i, "k".repeat(200)), "value").await;
+            assert!(res.is_ok());
+        }
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+
+        let res = store.put("a_small_key", "value").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let key = format!("key_{:04}_{}", 1, "k".repeat(200));
+        for key in [key.as_str(), "a_small_key"] {
+            let res = store.delete(key).await;
+            assert!(res.is_ok());
+        }
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        for flush in 0..2 {
+            let res = store.put(format!("filler_{}", flush), "value").await;
+            assert!(res.is_ok());
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        // Lets the tombstones outlive `tombstone_ttl`, sequence numbers can run ahead of the clock
+        sleep(Duration::from_millis(200)).await;
+        let res = store.run_compaction().await;
+        assert!(res.is_ok());
+
+        let mut tombstones = Vec::new();
+        for range in store.key_range.read().await.key_ranges.values() {
+            let table = range.sst.load_entries_from_file(true).await.unwrap();
+            for entry in table.entries.iter().filter(|e| e.value().is_tombstone) {
+                tombstones.push(entry.key().to_owned());
+            }
+        }
+        // The tombstone shadowing a version held by the large sstable is kept past `tombstone_ttl`
+        assert_eq!(tombstones, vec![key.as_bytes().to_vec()]);
+        assert_eq!(store.get(&key).await.unwrap(), None);
+        assert_eq!(store.get("a_small_key").await.unwrap(), None);
+        let key = format!("key_{:04}_{}", 2, "k".repeat(200));
+        assert_eq!(store.get(&key).await.unwrap(), Some(b"value".to_vec()));
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_tombstone_density_compaction() {
+        setup();
+        let root = tempdir().unwrap();
+        for (ratio, tables_left) in [(0.0, 2), (0.4, 1)] {
+            let path = root.path().join(format!("store_test_71_{}", tables_left));
+            let config = Config {
+                tombstone_compaction_ratio: ratio,
+                ..Config::default()
+            };
+            let mut store = DataStore::new_with_custom_config(path, config).await.unwrap();
+            for i in 0..10 {
+                let res = store.put(format!("key_{}", i), "value").await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+            for i in 0..10 {
+                let res = store.delete(format!("key_{}", i)).await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+            let tombstones: Vec<usize> = store
+                .key_range
+                .read()
+                .await
+                .key_ranges
+                .values()
+                .map(|range| range.sst.properties.tombstones)
+                .collect();
+            assert!(tombstones.contains(&10));
+
+            // Two sstables are not enough to be compacted unless they hold enough dead entries
+            let res = store.run_compaction().await;
+            assert!(res.is_ok());
+            assert_eq!(store.key_range.read().await.key_ranges.len(), tables_left);
+            for i in 0..10 {
+                assert_eq!(store.get(format!("key_{}", i)).await.unwrap(), None);
+            }
+            let res = store.close().await;
+            assert!(res.is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn datastore_wait_for_compaction() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_72");
+        let mut store = DataStore::new_with_custom_config(path.clone(), Config::default())
+            .await
+            .unwrap();
+        // Nothing was flushed
+        let res = timeout(Duration::from_secs(1), store.wait_for_compaction()).await;
+        assert!(res.unwrap().is_ok());
+
+        for flush in 0..4 {
+            for i in 0..10 {
+                let res = store.put(format!("key_{}_{}", i, flush), "value").await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        assert_eq!(store.key_range.read().await.key_ranges.len(), 4);
+
+        // Resolves well before the flush listener interval elapsed
+        let res = timeout(Duration::from_secs(10), store.wait_for_compaction()).await;
+        assert!(res.unwrap().is_ok());
+        assert_eq!(store.key_range.read().await.key_ranges.len(), 1);
+        assert_eq!(store.get("key_3_3").await.unwrap(), Some(b"value".to_vec()));
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_plan_compaction() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_73");
+        let mut store = DataStore::new_with_custom_config(path.clone(), Config::default())
+            .await
+            .unwrap();
+        let plan = store.plan_compaction().await.unwrap();
+        assert!(plan.buckets.is_empty());
+        assert!(!plan.will_compact());
+
+        for flush in 0..4 {
+            for i in 0..10 {
+                let res = match flush {
+                    3 => store.delete(format!("key_{}", i)).await,
+                    _ => store.put(format!("key_{}", i), "value").await,
+                };
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        let plan = store.plan_compaction().await.unwrap();
+        assert!(plan.will_compact());
+        assert_eq!(plan.buckets.len(), 1);
+        assert_eq!(plan.buckets[0].dead_entries, 10);
+        let key_range = store.key_range.read().await;
+        let tables = key_range.key_ranges.len();
+        assert!(tables >= 4);
+        assert_eq!(plan.buckets[0].sstables.len(), tables);
+        assert!(plan.buckets[0]
+            .sstables
+            .iter()
+            .all(|path| key_range.key_ranges.contains_key(path)));
+        let input_size: usize = key_range.key_ranges.values().map(|range| range.sst.size).sum();
+        drop(key_range);
+        assert_eq!(plan.input_size(), input_size);
+        assert!(plan.estimated_reclaimed() > 0);
+        assert_eq!(plan.estimated_output_size() + plan.estimated_reclaimed(), input_size);
+
+        // Nothing was merged
+        assert_eq!(store.key_range.read().await.key_ranges.len(), tables);
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_cancel_compaction() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_74");
+        // Compaction waits for the rate limiter long enough to be cancelled
+        let config = Config {
+            compaction_rate_limit: 1,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        for flush in 0..4 {
+            for i in 0..10 {
+                let res = store.put(format!("key_{}_{}", i, flush), "value").await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        let tables = store.key_range.read().await.key_ranges.len();
+        let cancel = store.compactor.config.cancel.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(100)).await;
+            cancel.cancel();
+        });
+        let res = timeout(Duration::from_secs(10), store.run_compaction()).await.unwrap();
+        assert!(matches!(res, Err(Error::CompactionCancelled)));
+        assert_eq!(store.key_range.read().await.key_ranges.len(), tables);
+        let mut sst_dirs = 0;
+        let mut buckets = fs::read_dir(&store.dir.buckets).await.unwrap();
+        while let Some(bucket) = buckets.next_entry().await.unwrap() {
+            let mut ssts = fs::read_dir(bucket.path()).await.unwrap();
+            while ssts.next_entry().await.unwrap().is_some() {
+                sst_dirs += 1;
+            }
+        }
+        assert_eq!(sst_dirs, tables);
+        assert_eq!(store.get("key_9_3").await.unwrap(), Some(b"value".to_vec()));
+
+        // Closing cancels the background compaction woken up by the wait
+        let res = timeout(Duration::from_millis(200), store.wait_for_compaction()).await;
+        assert!(res.is_err());
+        let res = timeout(Duration::from_secs(10), store.close()).await.unwrap();
+        assert!(res.is_ok());
+
+        let store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        assert_eq!(store.key_range.read().await.key_ranges.len(), tables);
+        assert_eq!(store.get("key_0_0").await.unwrap(), Some(b"value".to_vec()));
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_hotness_decay() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_75");
+        let config = Config {
+            hotness_half_life: 100,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        for flush in 0..3 {
+            for i in 0..10 {
+                let res = store.put(format!("key_{}_{}", i, flush), "value").await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        let mut hotness = Vec::new();
+        for (_, bucket) in store.buckets.read().await.buckets.iter() {
+            hotness.extend(bucket.sstables.read().await.iter().map(|sst| sst.get_hotness()));
+        }
+        assert!(hotness.iter().any(|hotness| *hotness >= 2));
+
+        // Halved at least twice
+        sleep(Duration::from_millis(600)).await;
+        for (_, bucket) in store.buckets.read().await.buckets.iter() {
+            assert!(bucket.sstables.read().await.iter().all(|sst| sst.get_hotness() == 0));
+        }
+        assert_eq!(store.get("key_0_0").await.unwrap(), Some(b"value".to_vec()));
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_expired_values_reclaimed_after_compaction() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_76");
+        let config = Config {
+            tombstone_ttl: 0,
+            online_gc_interval: 60 * 60 * 1000,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        for flush in 0..4 {
+            for i in 0..10 {
+                let res = store.put_with_ttl(format!("key_{}_{}", i, flush), "value", 100).await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        let res = store.put("live_key", "value").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let tail = store.gc_log.read().await.tail_offset;
+
+        // Lets the entries expire, sequence numbers can run ahead of the clock
+        sleep(Duration::from_millis(300)).await;
+        let res = store.run_compaction().await;
+        assert!(res.is_ok());
+
+        // Garbage collection is woken up by the compaction rather than by its interval
+        let res = timeout(Duration::from_secs(10), async {
+            while !store.gc.config.freed_values.is_empty() || store.gc_log.read().await.tail_offset == tail {
+                sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await;
+        assert!(res.is_ok());
+        assert!(store.background_errors.get().is_none());
+        assert_eq!(store.get("key_0_0").await.unwrap(), None);
+        assert_eq!(store.get("live_key").await.unwrap(), Some(b"value".to_vec()));
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_delete_files_in_range() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_77");
+        let mut store = DataStore::new_with_custom_config(path.clone(), Config::default())
+            .await
+            .unwrap();
+        for flush in 0..2 {
+            for i in 0..10 {
+                let res = store.put(format!("tenant_a_{}_{}", flush, i), "value").await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        // Overlaps the edges of the range
+        for key in ["tenant_a_9", "tenant_b"] {
+            let res = store.put(key, "value").await;
+            assert!(res.is_ok());
+        }
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        assert_eq!(store.key_range.read().await.key_ranges.len(), 3);
+
+        let res = store.delete_files_in_range("tenant_a", "tenant_b").await;
+        assert_eq!(res.unwrap(), 2);
+        assert_eq!(store.key_range.read().await.key_ranges.len(), 1);
+        let mut sstables = 0;
+        for (_, bucket) in store.buckets.read().await.buckets.iter() {
+            sstables += bucket.sstables.read().await.len();
+        }
+        assert_eq!(sstables, 1);
+        assert_eq!(store.get("tenant_a_0_0").await.unwrap(), None);
+        assert_eq!(store.get("tenant_a_9").await.unwrap(), None);
+        assert_eq!(store.get("tenant_b").await.unwrap(), Some(b"value".to_vec()));
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        // The manifest no longer lists the removed SSTables
+        let store = DataStore::new_with_custom_config(path.clone(), Config::default())
+            .await
+            .unwrap();
+        assert_eq!(store.key_range.read().await.key_ranges.len(), 1);
+        assert_eq!(store.get("tenant_a_1_0").await.unwrap(), None);
+        assert_eq!(store.get("tenant_b").await.unwrap(), Some(b"value".to_vec()));
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_read_amplification_compaction() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_78");
+        let config = Config {
+            read_amp_compaction_threshold: 2.0,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        for flush in 0..3 {
+            for i in 0..10 {
+                let res = store.put(format!("key_{}", i), format!("value_{}", flush)).await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        // Fewer SSTables than `min_sstables_per_merge`
+        let res = store.run_compaction().await;
+        assert!(res.is_ok());
+        assert_eq!(store.key_range.read().await.key_ranges.len(), 3);
+
+        for _ in 0..10 {
+            for i in 0..10 {
+                let res = store.get(format!("key_{}", i)).await;
+                assert_eq!(res.unwrap(), Some(b"value_2".to_vec()));
+            }
+        }
+        let stats = store.read_amplification();
+        assert_eq!(stats.gets, 100);
+        assert!(stats.memtables >= 100);
+        assert_eq!(stats.filters, 300);
+        assert_eq!(stats.sstables_per_get(), 3.0);
+
+        let res = store.run_compaction().await;
+        assert!(res.is_ok());
+        assert_eq!(store.key_range.read().await.key_ranges.len(), 1);
+        assert_eq!(store.get("key_0").await.unwrap(), Some(b"value_2".to_vec()));
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_run_gc_report() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_79");
+        let config = Config {
+            online_gc_interval: 60 * 60 * 1000,
+            gc_chunk_size: 64 * 1024,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        for value in ["old_value", "new_value"] {
+            for i in 0..20 {
+                let res = store.put(format!("key_{}", i), value).await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        let tail = store.gc_log.read().await.tail_offset;
+
+        let report = store.run_gc().await.unwrap();
+        assert!(report.bytes_reclaimed > 0);
+        assert!(report.live_bytes_rewritten > 0);
+        assert_eq!(
+            report.bytes_scanned,
+            report.bytes_reclaimed + report.live_bytes_rewritten
+        );
+        assert_eq!(report.tail_offset, tail + report.bytes_scanned);
+        assert_eq!(store.gc_log.read().await.tail_offset, report.tail_offset);
+        for i in 0..20 {
+            let res = store.get(format!("key_{}", i)).await;
+            assert_eq!(res.unwrap(), Some(b"new_value".to_vec()));
+        }
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_gc_triggered_by_garbage_ratio() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_80");
+        let config = Config {
+            online_gc_interval: 60 * 60 * 1000,
+            gc_garbage_ratio: 0.3,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        let tail = store.gc_log.read().await.tail_offset;
+        for round in 0..5 {
+            for i in 0..20 {
+                let res = store.put(format!("key_{}", i), format!("value_{}", round)).await;
+                assert!(res.is_ok());
+            }
+        }
+        // Garbage collection is woken up by the overwrites rather than by its interval
+        let res = timeout(Duration::from_secs(10), async {
+            while store.gc_log.read().await.tail_offset == tail {
+                sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await;
+        assert!(res.is_ok());
+        assert!(store.background_errors.get().is_none());
+        for i in 0..20 {
+            let res = store.get(format!("key_{}", i)).await;
+            assert_eq!(res.unwrap(), Some(b"value_4".to_vec()));
+        }
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        // The tail moved past entries that were never flushed, they are replayed from the tail on
+        let store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..20 {
+            let res = store.get(format!("key_{}", i)).await;
+            assert_eq!(res.unwrap(), Some(b"value_4".to_vec()));
+        }
+    }
+
+    #[tokio::test]
+    async fn datastore_gc_rewrite_rate_limit() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_81");
+        let config = Config {
+            online_gc_interval: 60 * 60 * 1000,
+            gc_chunk_size: 64 * 1024,
+            gc_rewrite_rate_limit: 1000,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        let value = vec![b'v'; 100];
+        for _ in 0..2 {
+            for i in 0..20 {
+                let res = store.put(format!("key_{}", i), value.clone()).await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+
+        // The bucket holds one second worth of bytes, the rest of the live values wait for it to refill
+        let started = Instant::now();
+        let report = store.run_gc().await.unwrap();
+        assert!(report.live_bytes_rewritten > 2000);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        for i in 0..20 {
+            let res = store.get(format!("key_{}", i)).await;
+            assert_eq!(res.unwrap(), Some(value.clone()));
+        }
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_gc_waits_for_read_pins() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_82");
+        let config = Config {
+            online_gc_interval: 60 * 60 * 1000,
+            gc_chunk_size: 64 * 1024,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        for value in ["old_value", "new_value"] {
+            for i in 0..20 {
+                let res = store.put(format!("key_{}", i), value).await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+
+        // A read that looked up its offsets before the pass keeps the chunk from being reclaimed
+        let pin = store.gc.config.read_pins.pin();
+        let started = Instant::now();
+        let (report, _) = tokio::join!(store.run_gc(), async {
+            sleep(Duration::from_millis(200)).await;
+            drop(pin);
+        });
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(report.unwrap().bytes_reclaimed > 0);
+        assert_eq!(store.gc.config.read_pins.pinned(), 0);
+        for i in 0..20 {
+            let res = store.get(format!("key_{}", i)).await;
+            assert_eq!(res.unwrap(), Some(b"new_value".to_vec()));
+        }
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_value_log_compression() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_83");
+        let value = b"compressible_value_".repeat(100);
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("uncompressed", value.clone()).await;
+        assert!(res.is_ok());
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        let config = Config {
+            value_compression: CompressionType::Lz4,
+            value_compression_threshold: 1024,
+            online_gc_interval: 60 * 60 * 1000,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        let vlog_path = path.join("v_log").join("val_log.bin");
+        let size = fs::metadata(&vlog_path).await.unwrap().len() as usize;
+        let res = store.put("compressed", value.clone()).await;
+        assert!(res.is_ok());
+        let grown = fs::metadata(&vlog_path).await.unwrap().len() as usize - size;
+        assert!(grown < value.len() / 4);
+        let size = size + grown;
+        let res = store.put("short", b"short_value_".repeat(10)).await;
+        assert!(res.is_ok());
+        assert!(fs::metadata(&vlog_path).await.unwrap().len() as usize - size > 120);
+        for key in ["uncompressed", "compressed"] {
+            assert_eq!(store.get(key).await.unwrap(), Some(value.clone()));
+            let mut reader = store.get_stream(key).await.unwrap().unwrap();
+            let mut streamed = Vec::new();
+            reader.read_to_end(&mut streamed).await.unwrap();
+            assert_eq!(streamed, value);
+        }
+
+        // Values relocated by garbage collection are decompressed when they are read back
+        let res = store.put("overwritten", value.clone()).await;
+        assert!(res.is_ok());
+        let res = store.put("overwritten", b"new_value".to_vec()).await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let res = store.run_gc().await;
+        assert!(res.is_ok());
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        // Compressed values stay readable once compression is turned off
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.get("compressed").await.unwrap(), Some(value.clone()));
+        assert_eq!(store.get("uncompressed").await.unwrap(), Some(value.clone()));
+        assert_eq!(store.get("short").await.unwrap(), Some(b"short_value_".repeat(10)));
+        assert_eq!(store.get("overwritten").await.unwrap(), Some(b"new_value".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_value_classes() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_84");
+        let config = Config {
+            medium_value_threshold: 256,
+            large_value_threshold: 4096,
+            online_gc_interval: 60 * 60 * 1000,
+            ..Config::default()
+        };
+        let values = [
+            ("small", b"small_value".to_vec()),
+            ("medium", b"medium_value_".repeat(50)),
+            ("large", b"large_value_".repeat(1000)),
+        ];
+        let mut store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        for (key, value) in values.iter() {
+            let res = store.put(key, value.clone()).await;
+            assert!(res.is_ok());
+        }
+        let streamed = b"streamed_value_".repeat(500);
+        let res = store.put_stream("streamed", &streamed[..], streamed.len()).await;
+        assert!(res.is_ok());
+        let vlog_dir = path.join("v_log");
+        let medium_len = fs::metadata(vlog_dir.join("val_log_medium.bin")).await.unwrap().len() as usize;
+        assert!(medium_len >= values[1].1.len());
+        let large_len = fs::metadata(vlog_dir.join("val_log_large.bin")).await.unwrap().len() as usize;
+        assert!(large_len >= values[2].1.len() + streamed.len());
+        for (key, value) in values.iter() {
+            assert_eq!(store.get(key).await.unwrap(), Some(value.clone()));
+            let mut reader = store.get_stream(key).await.unwrap().unwrap();
+            let mut read = Vec::new();
+            reader.read_to_end(&mut read).await.unwrap();
+            assert_eq!(&read, value);
+        }
+        assert_eq!(store.get("streamed").await.unwrap(), Some(streamed.clone()));
+
+        // The class of a value is kept by SSTables
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        for (key, value) in values.iter() {
+            assert_eq!(store.get(key).await.unwrap(), Some(value.clone()));
+        }
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        // Values not flushed are found through their pointers once the store is reopened
+        let mut store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        let unflushed = b"unflushed_value_".repeat(400);
+        let res = store.put("unflushed", unflushed.clone()).await;
+        assert!(res.is_ok());
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let mut store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        assert_eq!(store.get("unflushed").await.unwrap(), Some(unflushed.clone()));
+
+        // Overwritten values are reclaimed from the log of their class
+        let res = store.put("large", b"new_value".to_vec()).await;
+        assert!(res.is_ok());
+        let res = store.delete("streamed").await;
+        assert!(res.is_ok());
+        let report = store.run_gc().await.unwrap();
+        assert!(report.class_bytes_reclaimed >= values[2].1.len() + streamed.len());
+        assert_eq!(store.get("large").await.unwrap(), Some(b"new_value".to_vec()));
+        assert_eq!(store.get("streamed").await.unwrap(), None);
+        assert_eq!(store.get("medium").await.unwrap(), Some(values[1].1.clone()));
+        assert_eq!(store.get("unflushed").await.unwrap(), Some(unflushed.clone()));
+        let report = store.run_gc().await.unwrap();
+        assert_eq!(report.class_bytes_reclaimed, 0);
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        // Values stay readable once the thresholds are unset
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.get("medium").await.unwrap(), Some(values[1].1.clone()));
+        assert_eq!(store.get("unflushed").await.unwrap(), Some(unflushed));
+        assert_eq!(store.get("small").await.unwrap(), Some(values[0].1.clone()));
+    }
+
+    #[tokio::test]
+    async fn datastore_inline_values() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_85");
+        let config = Config {
+            inline_value_threshold: 64,
+            online_gc_interval: 60 * 60 * 1000,
+            // A single pass reaches the big value
+            gc_chunk_size: 4096,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        let keys: Vec<String> = (0..20).map(|i| format!("key_{:02}", i)).collect();
+        for key in keys.iter() {
+            let res = store.put(key, format!("value_of_{}", key)).await;
+            assert!(res.is_ok());
+        }
+        let big_value = b"big_value_".repeat(20);
+        let res = store.put("big", big_value.clone()).await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+
+        // The value log records of flushed inline values are garbage, only the big value is relocated
+        let report = store.run_gc().await.unwrap();
+        assert!(report.bytes_reclaimed > 0);
+        assert!(report.live_bytes_rewritten >= big_value.len());
+        for key in keys.iter() {
+            assert_eq!(
+                store.get(key).await.unwrap(),
+                Some(format!("value_of_{}", key).into_bytes())
+            );
+        }
+        assert_eq!(store.get("big").await.unwrap(), Some(big_value.clone()));
+        let mut reader = store.get_stream("key_07").await.unwrap().unwrap();
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, b"value_of_key_07".to_vec());
+        let values = store.multi_get(&["key_03", "big", "missing"]).await.unwrap();
+        assert_eq!(
+            values,
+            vec![Some(b"value_of_key_03".to_vec()), Some(big_value.clone()), None]
+        );
+        let page = store.scan_page(&ContinuationToken::start(), 5).await.unwrap();
+        let page_keys: Vec<Vec<u8>> = page.entries.iter().map(|e| e.key.to_owned()).collect();
+        assert_eq!(page_keys[0], b"big".to_vec());
+        assert_eq!(page.entries[1].val, b"value_of_key_00".to_vec());
+
+        // Inline values are kept by compaction and replayed values are inlined again
+        let res = store.put("key_05", "new_value").await;
+        assert!(res.is_ok());
+        let res = store.delete("key_06").await;
+        assert!(res.is_ok());
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        assert_eq!(store.get("key_05").await.unwrap(), Some(b"new_value".to_vec()));
+        assert_eq!(store.get("key_06").await.unwrap(), None);
+        assert_eq!(store.get("key_19").await.unwrap(), Some(b"value_of_key_19".to_vec()));
+        assert_eq!(store.get("big").await.unwrap(), Some(big_value));
+        let report = store.verify_integrity().await.unwrap();
+        assert!(report.is_intact());
+    }
+
+    #[tokio::test]
+    async fn datastore_value_log_buffer() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_86");
+        let config = Config {
+            value_log_buffer_size: 16 * 1024,
+            value_log_buffer_interval: 60 * 60 * 1000,
+            online_gc_interval: 60 * 60 * 1000,
+            ..Config::default()
+        };
+        let vlog_path = path.join("v_log").join("val_log.bin");
+        let mut store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        let file_len = fs::metadata(&vlog_path).await.unwrap().len();
+        for i in 0..20 {
+            let res = store.put(format!("key_{:02}", i), format!("value_{:02}", i)).await;
+            assert!(res.is_ok());
+        }
+        // Records are buffered until the buffer is full, reads find them all the same
+        assert_eq!(fs::metadata(&vlog_path).await.unwrap().len(), file_len);
+        assert_eq!(store.get("key_07").await.unwrap(), Some(b"value_07".to_vec()));
+        let buffered_len = fs::metadata(&vlog_path).await.unwrap().len();
+        assert!(buffered_len > file_len);
+        let value = vec![7; 20 * 1024];
+        let res = store.put("big", value.clone()).await;
+        assert!(res.is_ok());
+        assert!(fs::metadata(&vlog_path).await.unwrap().len() >= buffered_len + value.len() as u64);
+
+        // Offsets given to buffered records are where they are written
+        for i in 20..40 {
+            let res = store.put(format!("key_{:02}", i), format!("value_{:02}", i)).await;
+            assert!(res.is_ok());
+        }
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let res = store.put("unflushed", "unflushed_value").await;
+        assert!(res.is_ok());
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        for i in 0..40 {
+            let value = store.get(format!("key_{:02}", i)).await.unwrap();
+            assert_eq!(value, Some(format!("value_{:02}", i).into_bytes()));
+        }
+        assert_eq!(store.get("big").await.unwrap(), Some(value));
+        assert_eq!(store.get("unflushed").await.unwrap(), Some(b"unflushed_value".to_vec()));
+        drop(store);
+
+        // A buffer no append comes to is written once its records waited for the interval
+        let config = Config {
+            value_log_buffer_interval: 50,
+            ..config
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        let file_len = fs::metadata(&vlog_path).await.unwrap().len();
+        let res = store.put("late", "late_value").await;
+        assert!(res.is_ok());
+        assert_eq!(fs::metadata(&vlog_path).await.unwrap().len(), file_len);
+        sleep(Duration::from_millis(500)).await;
+        assert!(fs::metadata(&vlog_path).await.unwrap().len() > file_len);
+    }
+
+    #[tokio::test]
+    async fn datastore_batched_value_log_reads() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_87");
+        let config = Config {
+            medium_value_threshold: 256,
+            large_value_threshold: 64 * 1024,
+            online_gc_interval: 60 * 60 * 1000,
+            ..Config::default()
+        };
+        // Big values split the small records into several runs, some values go to the medium log
+        let value_of = |i: usize| match i {
+            i if i % 10 == 0 => vec![i as u8; 40 * 1024],
+            i if i % 7 == 0 => format!("medium_{:02}_", i).repeat(40).into_bytes(),
+            i => format!("value_{:02}", i).into_bytes(),
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        for i in 0..50 {
+            let res = store.put(format!("key_{:02}", i), value_of(i)).await;
+            assert!(res.is_ok());
+        }
+        let res = store.delete("key_13").await;
+        assert!(res.is_ok());
+
+        // Values come back in the order of the keys whatever the order of their offsets
+        let keys = [
+            "key_42", "key_03", "missing", "key_13", "key_20", "key_03", "key_14", "key_01",
+        ];
+        let expected: Vec<Option<Vec<u8>>> = vec![
+            Some(value_of(42)),
+            Some(value_of(3)),
+            None,
+            None,
+            Some(value_of(20)),
+            Some(value_of(3)),
+            Some(value_of(14)),
+            Some(value_of(1)),
+        ];
+        assert_eq!(store.multi_get(&keys).await.unwrap(), expected);
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        assert_eq!(store.multi_get(&keys).await.unwrap(), expected);
+
+        let live: Vec<(Vec<u8>, Vec<u8>)> = (0..50)
+            .filter(|i| *i != 13)
+            .map(|i| (format!("key_{:02}", i).into_bytes(), value_of(i)))
+            .collect();
+        let scanned: Vec<(Vec<u8>, Vec<u8>)> = store
+            .range("key_00".."key_99")
+            .await
+            .unwrap()
+            .map(|e| e.unwrap())
+            .collect()
+            .await;
+        assert_eq!(scanned, live);
+
+        let mut paged = Vec::new();
+        let mut token = Some(ContinuationToken::start());
+        while let Some(start) = token {
+            let page = store.scan_page(&start, 7).await.unwrap();
+            assert!(page.entries.len() <= 7);
+            paged.extend(page.entries.into_iter().map(|e| (e.key, e.val)));
+            token = page.next;
+        }
+        assert_eq!(paged, live);
+    }
+
+    #[tokio::test]
+    async fn datastore_value_log_stats() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_88");
+        let config = Config {
+            online_gc_interval: 60 * 60 * 1000,
+            gc_chunk_size: 64 * 1024,
+            gc_garbage_ratio: 0.99,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        for value in ["old_value", "new_value"] {
+            for i in 0..20 {
+                let res = store.put(format!("key_{}", i), value).await;
+                assert!(res.is_ok());
+            }
+        }
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let before = store.value_log_stats().await;
+        assert!(before.live_bytes > 0);
+        assert!(before.total_bytes >= before.live_bytes);
+        // Overwrites in the active memtable are counted as garbage
+        assert!(before.garbage_bytes > 0 && before.garbage_bytes < before.live_bytes);
+        assert_eq!(before.punched_bytes, 0);
+        assert_eq!(before.last_gc, None);
+        assert!(before.space_amplification() > 1.0);
+
+        let report = store.run_gc().await.unwrap();
+        assert!(report.bytes_reclaimed > 0);
+        let after = store.value_log_stats().await;
+        assert!(after.last_gc.is_some());
+        assert_eq!(
+            after.garbage_bytes,
+            before.garbage_bytes.saturating_sub(report.bytes_reclaimed as u64)
+        );
+        let end = store.gc_log.read().await.end_offset().await as u64;
+        assert_eq!(after.live_bytes, end - report.tail_offset as u64);
+        if store.gc.config.strategy == crate::gc::GCStrategy::PunchHole {
+            assert_eq!(after.punched_bytes, report.bytes_scanned as u64);
+        } else {
+            assert_eq!(after.punched_bytes, 0);
+        }
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_value_log_preallocation() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_89");
+        let config = Config {
+            value_log_preallocation_size: 1024 * 1024,
+            online_gc_interval: 60 * 60 * 1000,
+            ..Config::default()
+        };
+        let vlog_path = path.join("v_log").join("val_log.bin");
+        let mut store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        for i in 0..20 {
+            let res = store.put(format!("key_{:02}", i), format!("value_{:02}", i)).await;
+            assert!(res.is_ok());
+        }
+        // Space is allocated past the end of the file, whose length only counts the records
+        let metadata = fs::metadata(&vlog_path).await.unwrap();
+        assert!(metadata.len() < 64 * 1024);
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert!(metadata.blocks() * 512 >= 1024 * 1024);
+        }
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        let mut store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        let res = store.put("key_20", "value_20").await;
+        assert!(res.is_ok());
+        for i in 0..21 {
+            let value = store.get(format!("key_{:02}", i)).await.unwrap();
+            assert_eq!(value, Some(format!("value_{:02}", i).into_bytes()));
+        }
+    }
+
+    #[tokio::test]
+    async fn datastore_value_log_sync_interval() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_90");
+        let config = Config {
+            value_log_sync_interval: 20,
+            value_log_buffer_size: 16 * 1024,
+            value_log_buffer_interval: 60 * 60 * 1000,
+            online_gc_interval: 60 * 60 * 1000,
+            ..Config::default()
+        };
+        let vlog_path = path.join("v_log").join("val_log.bin");
+        let mut store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        let file_len = fs::metadata(&vlog_path).await.unwrap().len();
+        for i in 0..10 {
+            let res = store.put(format!("key_{}", i), format!("value_{}", i)).await;
+            assert!(res.is_ok());
+        }
+        // The sync writes the buffered records first, writes are on the disk without asking for a sync
+        assert_eq!(fs::metadata(&vlog_path).await.unwrap().len(), file_len);
+        sleep(Duration::from_millis(200)).await;
+        assert!(fs::metadata(&vlog_path).await.unwrap().len() > file_len);
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        let store = DataStore::new_with_custom_config(path.clone(), config.clone())
+            .await
+            .unwrap();
+        for i in 0..10 {
+            let value = store.get(format!("key_{}", i)).await.unwrap();
+            assert_eq!(value, Some(format!("value_{}", i).into_bytes()));
+        }
+    }
+
+    // Keeps the garbage collection runs it is notified of
+    #[derive(Debug, Default)]
+    struct GCRunRecorder {
+        runs: std::sync::Mutex<Vec<GCRun>>,
+    }
+
+    impl EventListener for GCRunRecorder {
+        fn on_gc_completed(&self, run: &GCRun) {
+            self.runs.lock().unwrap().push(*run);
+        }
+    }
+
+    #[tokio::test]
+    async fn datastore_gc_history() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_91");
+        let recorder = Arc::new(GCRunRecorder::default());
+        let config = Config {
+            online_gc_interval: 60 * 60 * 1000,
+            gc_chunk_size: 64 * 1024,
+            event_listener: Some(recorder.clone()),
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        assert!(store.gc_history().is_empty());
+        for value in ["old_value", "new_value"] {
+            for i in 0..20 {
+                let res = store.put(format!("key_{}", i), value).await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+
+        let report = store.run_gc().await.unwrap();
+        let history = store.gc_history();
+        assert_eq!(history.len(), 1);
+        let run = history[0];
+        assert_eq!(run.chunks, 1);
+        assert_eq!(run.live_entries_rewritten, report.live_entries_rewritten);
+        assert_eq!(run.live_bytes_rewritten, report.live_bytes_rewritten);
+        assert_eq!(
+            run.bytes_reclaimed,
+            report.bytes_reclaimed + report.class_bytes_reclaimed
+        );
+        // The new values are live, the old ones are reclaimed
+        assert!(run.live_entries_rewritten >= 20);
+        assert!(run.bytes_reclaimed > 0);
+        assert_eq!(*recorder.runs.lock().unwrap(), history);
+
+        let res = store.run_gc().await;
+        assert!(res.is_ok());
+        assert_eq!(store.gc_history().len(), 2);
+        assert_eq!(recorder.runs.lock().unwrap().len(), 2);
+        let res = store.close().await;
+        assert!(res.is_ok());
+    }
+
+    #[tokio::test]
+    async fn datastore_value_log_iter_from() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_92");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..10 {
+            let res = store.put(format!("key_{}", i), format!("value_{}", i)).await;
+            assert!(res.is_ok());
+        }
+        let res = store.delete("key_3").await;
+        assert!(res.is_ok());
+
+        let mut iter = store.val_log.iter_from(0).await.unwrap();
+        let mut records = Vec::new();
+        while let Some((offset, entry)) = iter.next().await.unwrap() {
+            records.push((offset, entry));
+        }
+        assert_eq!(iter.offset(), store.val_log.end_offset().await);
+        assert!(iter.next().await.unwrap().is_none());
+        // The head and tail entries written when the store was created come first
+        let markers = records
+            .iter()
+            .take_while(|(_, entry)| entry.key == HEAD_ENTRY_KEY || entry.key == TAIL_ENTRY_KEY)
+            .count();
+        assert_eq!(markers, 2);
+        let records = records.split_off(markers);
+        assert_eq!(records.len(), 11);
+        for (i, (offset, entry)) in records.iter().take(10).enumerate() {
+            assert_eq!(entry.key, format!("key_{}", i).into_bytes());
+            assert_eq!(entry.value, format!("value_{}", i).into_bytes());
+            let value = store.val_log.get(*offset).await.unwrap();
+            assert_eq!(value, Some((entry.value.to_owned(), false)));
+        }
+        let (_, delete) = &records[10];
+        assert_eq!(delete.key, b"key_3".to_vec());
+        assert!(delete.is_tombstone);
+
+        // Iterating resumes from the offset of any record
+        let (offset, _) = records[5];
+        let mut iter = store.val_log.iter_from(offset).await.unwrap();
+        let (resumed_offset, entry) = iter.next().await.unwrap().unwrap();
+        assert_eq!(resumed_offset, offset);
+        assert_eq!(entry.key, b"key_5".to_vec());
+
+        // A corrupted record ends the iteration with an error
+        store.val_log.sync_to_disk().await.unwrap();
+        let vlog_path = store.val_log.content.path.to_owned();
+        let mut bytes = fs::read(&vlog_path).await.unwrap();
+        let last_value_byte = records[9].0 + 5;
+        bytes[last_value_byte] ^= 0xFF;
+        fs::write(&vlog_path, &bytes).await.unwrap();
+        let mut iter = store.val_log.iter_from(offset).await.unwrap();
+        for _ in 5..9 {
+            assert!(iter.next().await.unwrap().is_some());
+        }
+        let res = iter.next().await;
+        assert!(matches!(res, Err(Error::CorruptedValueLogEntry { offset }) if offset == records[9].0));
+        assert!(iter.next().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn datastore_sstable_compression() {
+        setup();
+        let root = tempdir().unwrap();
+        let mut data_sizes = Vec::new();
+        for compression in [CompressionType::None, CompressionType::Lz4, CompressionType::Snappy] {
+            let path = root.path().join(format!("store_test_93_{}", compression.as_byte()));
+            let config = Config {
+                sstable_compression: compression,
+                ..Config::default()
+            };
+            let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+            for i in 0..500 {
+                let res = store.put(format!("key_{:04}", i), format!("value_{}", i)).await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+            let mut data_size = 0;
+            for range in store.key_range.read().await.key_ranges.values() {
+                data_size += fs::metadata(&range.sst.data_file.path).await.unwrap().len();
+            }
+            data_sizes.push(data_size);
+            for i in (0..500).step_by(7) {
+                let value = store.get(format!("key_{:04}", i)).await.unwrap();
+                assert_eq!(value, Some(format!("value_{}", i).into_bytes()));
+            }
+            let keys: Vec<Vec<u8>> = store
+                .keys()
+                .await
+                .unwrap()
+                .map_ok(|e| e.key)
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(keys.len(), 500);
+            assert_eq!(keys[499], b"key_0499".to_vec());
+            let res = store.close().await;
+            assert!(res.is_ok());
+
+            // Compressed blocks stay readable once compression is turned off
+            let store = DataStore::new(path.clone()).await.unwrap();
+            assert_eq!(store.get("key_0123").await.unwrap(), Some(b"value_123".to_vec()));
+        }
+        assert!(data_sizes[1] < data_sizes[0] / 2);
+        assert!(data_sizes[2] < data_sizes[0] / 2);
+    }
+
+    #[tokio::test]
+    async fn datastore_cold_sstable_compression() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_94");
+        let config = Config {
+            sstable_compression: CompressionType::None,
+            cold_sstable_compression: CompressionType::Lz4,
+            cold_sstable_size: 8 * 1024,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        for (start, end) in [(0, 20), (20, 1020)] {
+            for i in start..end {
+                let res = store.put(format!("key_{:04}", i), format!("value_{}", i)).await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+
+        // The flag on the length of the first block, after the checksum type header, tells if it is compressed
+        let mut first_blocks_compressed = Vec::new();
+        for range in store.key_range.read().await.key_ranges.values() {
+            let bytes = fs::read(&range.sst.data_file.path).await.unwrap();
+            let len = u32::from_le_bytes(bytes[1..5].try_into().unwrap());
+            first_blocks_compressed.push((bytes.len(), len & BLOCK_COMPRESSED_FLAG != 0));
+        }
+        first_blocks_compressed.sort();
+        assert_eq!(first_blocks_compressed.len(), 2);
+        assert!(!first_blocks_compressed[0].1);
+        assert!(first_blocks_compressed[1].1);
+        for i in [0, 19, 20, 1019] {
+            let value = store.get(format!("key_{:04}", i)).await.unwrap();
+            assert_eq!(value, Some(format!("value_{}", i).into_bytes()));
+        }
+    }
+
+    #[tokio::test]
+    async fn datastore_block_cache() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_95");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..500 {
+            let res = store.put(format!("key_{:04}", i), format!("value_{}", i)).await;
+            assert!(res.is_ok());
+        }
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let cache = store.block_cache().clone();
+        assert_eq!(cache.capacity(), Config::default().block_cache_size);
+        assert_eq!(cache.size(), 0);
+
+        // The first get reads the block from the file, the next ones find it in the cache
+        for _ in 0..3 {
+            assert_eq!(store.get("key_0042").await.unwrap(), Some(b"value_42".to_vec()));
+        }
+        assert_eq!(cache.misses(), 1);
+        assert_eq!(cache.hits(), 2);
+        assert!(cache.size() > 0);
+
+        // Scans read the blocks they did not cache yet and reuse them afterwards
+        let keys: Vec<Vec<u8>> = store
+            .keys()
+            .await
+            .unwrap()
+            .map_ok(|e| e.key)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(keys.len(), 500);
+        let (hits, misses) = (cache.hits(), cache.misses());
+        assert!(misses > 1);
+        let keys: Vec<Vec<u8>> = store
+            .keys()
+            .await
+            .unwrap()
+            .map_ok(|e| e.key)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(keys.len(), 500);
+        assert_eq!(cache.misses(), misses);
+        assert!(cache.hits() > hits);
+        assert_eq!(store.get("key_0499").await.unwrap(), Some(b"value_499".to_vec()));
+        assert_eq!(cache.misses(), misses);
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        let config = Config {
+            block_cache_size: 0,
+            ..Config::default()
+        };
+        let store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        for _ in 0..3 {
+            assert_eq!(store.get("key_0042").await.unwrap(), Some(b"value_42".to_vec()));
+        }
+        assert_eq!(store.block_cache().hits(), 0);
+        assert_eq!(store.block_cache().size(), 0);
+    }
+
+    #[tokio::test]
+    async fn datastore_write_times() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_96");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let before_insert = Utc::now().timestamp_millis() as u64;
+        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        // Sequence numbers run far ahead of the clock when writes come in faster than one per millisecond
+        for i in 0..5000 {
+            let res = store.put(format!("key_{}", i), "val").await;
+            assert!(res.is_ok());
+        }
+        let started = std::time::Instant::now();
+        let snapshot = store.snapshot().await.unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_millis(100));
+        assert!(snapshot.taken_at() <= Utc::now().timestamp_millis() as u64);
+
+        // TTLs are counted from the time of the write
+        let res = store.put_with_ttl("key_ttl", "val", 60000).await;
+        assert!(res.is_ok());
+        let expires_at = store
+            .active_memtable
+            .get(&b"key_ttl".to_vec())
+            .unwrap()
+            .expires_at
+            .unwrap();
+        assert!(expires_at <= Utc::now().timestamp_millis() as u64 + 60000);
+        drop(snapshot);
+
+        // Write times are recovered from the value log
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let store = DataStore::new(path.clone()).await.unwrap();
+        let now = Utc::now().timestamp_millis() as u64;
+        assert_eq!(store.get_at("key_4999", before_insert).await.unwrap(), None);
+        assert_eq!(store.get_at("key_4999", now).await.unwrap(), Some(b"val".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_value_equal_to_tombstone_marker() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_97");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "*").await;
+        assert!(res.is_ok());
+        let mut batch = WriteBatch::new();
+        batch.put("key_2", "*");
+        let res = store.write_batch(&batch, &WriteOptions::default()).await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"*".to_vec()));
+        assert_eq!(store.get("key_2").await.unwrap(), Some(b"*".to_vec()));
+
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"*".to_vec()));
+        let res = store.delete("key_2").await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("key_2").await.unwrap(), None);
+
+        // Replayed from the value log
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"*".to_vec()));
+        assert_eq!(store.get("key_2").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn datastore_compaction_keeps_readable_versions() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_98");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        let res = store.put("key_1", "v0").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let snapshot = store.snapshot().await.unwrap();
+        // Enough similarly sized sstables to land in one bucket and be compacted, only the first one holds a
+        // version the snapshot reads
+        for flush in 1..5 {
+            for i in 0..10 {
+                let res = store.put(format!("key_{}_{}", flush, i), "value").await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        let res = store.put("key_1", "v1").await;
+        assert!(res.is_ok());
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let sstables = store.key_range.read().await.key_ranges.len();
+        let res = store.run_compaction().await;
+        assert!(res.is_ok());
+        assert!(store.key_range.read().await.key_ranges.len() < sstables);
+
+        // The version replaced after the snapshot was taken is kept for it
+        let options = snapshot.read_options();
+        assert_eq!(
+            store.get_with_options("key_1", &options).await.unwrap(),
+            Some(b"v0".to_vec())
+        );
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"v1".to_vec()));
+        assert_eq!(store.get_with_options("key_4_9", &options).await.unwrap(), None);
+        assert_eq!(store.get("key_4_9").await.unwrap(), Some(b"value".to_vec()));
+        drop(snapshot);
+
+        let res = store.run_compaction().await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("key_1").await.unwrap(), Some(b"v1".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn datastore_group_commit_failed_group() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_99");
+        let config = Config {
+            write_buffer_size: 1024,
+            ..Config::default()
+        };
+        let store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        let store = Arc::new(RwLock::new(store));
+        // Every group would carry the token, it is ignored
+        let options = WriteOptions::new(false).with_idempotency_token("request_1");
+        let group = GroupCommit::new(store.clone(), options);
+        assert!(group.put("key_a", "val").await.is_ok());
+        assert!(group.put("key_b", "val").await.is_ok());
+        assert_eq!(store.read().await.get("key_b").await.unwrap(), Some(b"val".to_vec()));
+
+        // Freezing the memtable part way through the group fails since the meta file can no longer be written
+        let meta = path.join("meta");
+        fs::remove_dir_all(&meta).await.unwrap();
+        fs::write(&meta, b"").await.unwrap();
+        // Submitted from one task so that they all join the same group
+        let puts = (0..100).map(|i| group.put(format!("key_{:02}", i), "val"));
+        let results = join_all(puts).await;
+        // Each waiter gets the error of its own write, the memtable is still full when it is retried
+        assert!(results
+            .iter()
+            .all(|res| matches!(res, Err(Error::DirCreationError { .. }))));
+        // The writes the group applied before failing stay applied, the rest were never applied
+        assert_eq!(store.read().await.get("key_00").await.unwrap(), Some(b"val".to_vec()));
+        assert_eq!(store.read().await.get("key_99").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn datastore_compaction_filter_dead_bytes() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("store_test_100");
+        let config = Config {
+            compaction_filter: Some(Arc::new(PrefixFilter)),
+            online_gc_interval: 60 * 60 * 1000,
+            gc_garbage_ratio: 0.99,
+            tombstone_ttl: 0,
+            ..Config::default()
+        };
+        let mut store = DataStore::new_with_custom_config(path.clone(), config).await.unwrap();
+        for flush in 0..4 {
+            for key in ["expired", "kept"] {
+                let res = store.put(format!("{}_{}", key, flush), "value").await;
+                assert!(res.is_ok());
+            }
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        let before = store.value_log_stats().await;
+        let res = store.run_compaction().await;
+        assert!(res.is_ok());
+
+        // Values removed by the filter are dead even though the tombstones replacing them point to them
+        let removed = store.value_log_stats().await;
+        assert!(removed.garbage_bytes > before.garbage_bytes);
+
+        for flush in 0..4 {
+            let res = store.put(format!("migrate_{}", flush), "value").await;
+            assert!(res.is_ok());
+            let res = store.flush_all_memtables().await;
+            assert!(res.is_ok());
+        }
+        // Lets the tombstones left by the filter outlive `tombstone_ttl`, they are dropped by the next compaction
+        sleep(Duration::from_millis(200)).await;
+        let res = store.run_compaction().await;
+        assert!(res.is_ok());
+        assert_eq!(store.get("migrate_0").await.unwrap(), Some(b"VALUE".to_vec()));
+        let mut tombstones = 0;
+        for range in store.key_range.read().await.key_ranges.values() {
+            let table = range.sst.load_entries_from_file(true).await.unwrap();
+            tombstones += table.entries.iter().filter(|e| e.value().is_tombstone).count();
+        }
+        assert_eq!(tombstones, 0);
+        // The values the filter rewrote are dead, the removed values are not counted again along with the tombstones
+        // pointing to them. Every value is stored in a record of the same length
+        let rewritten = store.value_log_stats().await;
+        assert_eq!(rewritten.garbage_bytes, 2 * removed.garbage_bytes);
+    }
 }
@@ -0,0 +1,250 @@
+#[cfg(test)]
+mod tests {
+    use crate::storage::{DataStore, Inconsistency, IntegrityIssue};
+    use crate::tests::workload::Workload;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+    use tokio::fs;
+    use tokio::sync::RwLock;
+
+    fn setup() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[tokio::test]
+    async fn datastore_verify_consistent() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("verify_test_1");
+        let store = DataStore::new(path.clone()).await.unwrap();
+        let workload_size = 5000;
+        let key_len = 5;
+        let val_len = 5;
+        let write_read_ratio = 0.5;
+        let workload = Workload::new(workload_size, key_len, val_len, write_read_ratio);
+        let (_, write_workload) = workload.generate_workload_data_as_vec();
+        let store_ref = Arc::new(RwLock::new(store));
+        let res = workload.insert_parallel(&write_workload, store_ref.clone()).await;
+        assert!(res.is_ok());
+        let res = store_ref.write().await.flush_all_memtables().await;
+        assert!(res.is_ok());
+
+        let report = store_ref.read().await.verify().await;
+        assert!(report.is_ok());
+        let report = report.unwrap();
+        assert!(!report.tables.is_empty());
+        assert!(report.is_consistent(), "{:?}", report.inconsistent_tables());
+        for table in report.tables.iter() {
+            assert!(table.keys_checked > 0);
+            assert!(table.absent_keys_sampled > 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn datastore_verify_detects_key_range_mismatch() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("verify_test_2");
+        let store = DataStore::new(path.clone()).await.unwrap();
+        let workload_size = 5000;
+        let key_len = 5;
+        let val_len = 5;
+        let write_read_ratio = 0.5;
+        let workload = Workload::new(workload_size, key_len, val_len, write_read_ratio);
+        let (_, write_workload) = workload.generate_workload_data_as_vec();
+        let store_ref = Arc::new(RwLock::new(store));
+        let res = workload.insert_parallel(&write_workload, store_ref.clone()).await;
+        assert!(res.is_ok());
+        let res = store_ref.write().await.flush_all_memtables().await;
+        assert!(res.is_ok());
+
+        let store = store_ref.read().await;
+        let corrupted_path = {
+            let mut key_range = store.key_range.write().await;
+            let (path, range) = key_range.key_ranges.iter_mut().next().unwrap();
+            range.biggest_key = b"~~corrupted~~".to_vec();
+            path.to_owned()
+        };
+        let report = store.verify().await.unwrap();
+        assert!(!report.is_consistent());
+        let inconsistent = report.inconsistent_tables();
+        assert_eq!(inconsistent.len(), 1);
+        assert_eq!(inconsistent[0].data_file_path, corrupted_path);
+        assert!(matches!(
+            inconsistent[0].inconsistencies[0],
+            Inconsistency::BiggestKeyMismatch { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn datastore_verify_integrity_intact() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("verify_test_3");
+        let store = DataStore::new(path.clone()).await.unwrap();
+        let workload_size = 5000;
+        let key_len = 5;
+        let val_len = 5;
+        let write_read_ratio = 0.5;
+        let workload = Workload::new(workload_size, key_len, val_len, write_read_ratio);
+        let (_, write_workload) = workload.generate_workload_data_as_vec();
+        let store_ref = Arc::new(RwLock::new(store));
+        let res = workload.insert_parallel(&write_workload, store_ref.clone()).await;
+        assert!(res.is_ok());
+        let res = store_ref.write().await.flush_all_memtables().await;
+        assert!(res.is_ok());
+
+        let report = store_ref.read().await.verify_integrity().await.unwrap();
+        assert!(!report.tables.is_empty());
+        assert!(
+            report.is_intact(),
+            "{:?} {:?}",
+            report.damaged_tables(),
+            report.value_log
+        );
+        assert!(report.value_log.entries_checked >= workload_size);
+        for table in report.tables.iter() {
+            assert!(table.blocks_checked > 0);
+            assert!(table.entries_checked > 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn datastore_verify_integrity_reports_corruption() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("verify_test_4");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..10 {
+            let res = store.put(format!("key_{}", i), format!("value_{}", i)).await;
+            assert!(res.is_ok());
+        }
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let data_file_path = store.verify_integrity().await.unwrap().tables[0]
+            .data_file_path
+            .to_owned();
+
+        // Flip a byte of the first block, right after the checksum type header and the block length
+        let mut bytes = fs::read(&data_file_path).await.unwrap();
+        bytes[1 + 4] ^= 0x01;
+        fs::write(&data_file_path, &bytes).await.unwrap();
+
+        // Leave a torn entry at the end of the value log
+        let vlog_path = store.val_log.content.path.to_owned();
+        let mut bytes = fs::read(&vlog_path).await.unwrap();
+        let torn_offset = bytes.len();
+        bytes.extend_from_slice(&5u32.to_le_bytes());
+        fs::write(&vlog_path, &bytes).await.unwrap();
+
+        let report = store.verify_integrity().await.unwrap();
+        assert!(!report.is_intact());
+        let damaged = report.damaged_tables();
+        assert_eq!(damaged.len(), 1);
+        assert_eq!(damaged[0].data_file_path, data_file_path);
+        assert!(matches!(
+            damaged[0].issues[0],
+            IntegrityIssue::UnreadableBlock { offset: 1, .. }
+        ));
+        assert_eq!(report.value_log.issues.len(), 1);
+        assert!(matches!(
+            report.value_log.issues[0],
+            IntegrityIssue::CorruptedValueLogEntry { offset, .. } if offset == torn_offset
+        ));
+    }
+
+    #[tokio::test]
+    async fn datastore_repair_rebuilds_index() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("verify_test_5");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..300 {
+            let res = store.put(format!("key_{:03}", i), format!("value_{:03}", i)).await;
+            assert!(res.is_ok());
+        }
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let index_file_path = store.verify_integrity().await.unwrap().tables[0]
+            .index_file_path
+            .to_owned();
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        let mut bytes = fs::read(&index_file_path).await.unwrap();
+        bytes[1 + 4] ^= 0x01;
+        fs::write(&index_file_path, &bytes).await.unwrap();
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert!(!store.verify_integrity().await.unwrap().is_intact());
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        let report = DataStore::repair(path.clone()).await.unwrap();
+        assert_eq!(report.intact_tables, 0);
+        assert_eq!(report.rebuilt.len(), 1);
+        assert_eq!(report.rebuilt[0].dropped_blocks, 0);
+        assert_eq!(report.rebuilt[0].dropped_entries, 0);
+        assert!(!report.rebuilt[0].dir.exists());
+        assert!(report.quarantined.is_empty());
+        assert_eq!(report.value_log_truncated_at, None);
+
+        let store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..300 {
+            let value = store.get(format!("key_{:03}", i)).await.unwrap();
+            assert_eq!(value, Some(format!("value_{:03}", i).into_bytes()));
+        }
+        assert!(store.verify_integrity().await.unwrap().is_intact());
+        assert!(store.recovery_report().removed.is_empty());
+
+        // A store without damage is left as it is
+        let res = store.close().await;
+        assert!(res.is_ok());
+        let report = DataStore::repair(path.clone()).await.unwrap();
+        assert_eq!(report.intact_tables, 1);
+        assert!(report.rebuilt.is_empty());
+    }
+
+    #[tokio::test]
+    async fn datastore_repair_drops_unreadable_records() {
+        setup();
+        let root = tempdir().unwrap();
+        let path = root.path().join("verify_test_6");
+        let mut store = DataStore::new(path.clone()).await.unwrap();
+        for i in 0..300 {
+            let res = store.put(format!("key_{:03}", i), format!("value_{:03}", i)).await;
+            assert!(res.is_ok());
+        }
+        let res = store.flush_all_memtables().await;
+        assert!(res.is_ok());
+        let res = store.put("unflushed", "value").await;
+        assert!(res.is_ok());
+        let data_file_path = store.verify_integrity().await.unwrap().tables[0]
+            .data_file_path
+            .to_owned();
+        let vlog_path = store.val_log.content.path.to_owned();
+        let res = store.close().await;
+        assert!(res.is_ok());
+
+        // Flip a byte of the first block and leave a torn entry at the end of the value log
+        let mut bytes = fs::read(&data_file_path).await.unwrap();
+        bytes[1 + 4] ^= 0x01;
+        fs::write(&data_file_path, &bytes).await.unwrap();
+        let mut bytes = fs::read(&vlog_path).await.unwrap();
+        let torn_offset = bytes.len();
+        bytes.extend_from_slice(&5u32.to_le_bytes());
+        fs::write(&vlog_path, &bytes).await.unwrap();
+
+        let report = DataStore::repair(path.clone()).await.unwrap();
+        assert_eq!(report.value_log_truncated_at, Some(torn_offset));
+        assert_eq!(fs::metadata(&vlog_path).await.unwrap().len() as usize, torn_offset);
+        assert_eq!(report.rebuilt.len(), 1);
+        assert_eq!(report.rebuilt[0].dropped_blocks, 1);
+        assert!(report.rebuilt[0].entries > 0);
+
+        let store = DataStore::new(path.clone()).await.unwrap();
+        assert!(store.recovery_report().quarantined.is_empty());
+        assert_eq!(store.get("key_299").await.unwrap(), Some(b"value_299".to_vec()));
+        assert_eq!(store.get("unflushed").await.unwrap(), Some(b"value".to_vec()));
+        assert!(store.verify_integrity().await.unwrap().is_intact());
+    }
+}
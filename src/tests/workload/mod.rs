@@ -91,10 +91,8 @@ impl Workload {
             let key = e.key.clone();
             let val = e.val.clone();
             tokio::spawn(async move {
-                let key_str = std::str::from_utf8(&key).unwrap();
-                let val_str = std::str::from_utf8(&val).unwrap();
                 let mut value = s_engine.write().await;
-                value.put(key_str, val_str).await
+                value.put(key, val).await
             })
         });
 
@@ -0,0 +1,113 @@
+//! # Transactions
+//!
+//! A transaction buffers its writes in memory and reads the store as of the snapshot it was started at, with
+//! its own pending writes laid on top so that application code reads what it wrote before committing it.
+//! `commit` applies the pending writes as a single write batch, dropping the transaction discards them.
+
+use crate::batch::WriteBatch;
+use crate::cfg::WriteOptions;
+use crate::err::Error;
+use crate::range::FetchedEntry;
+use crate::snapshot::Snapshot;
+use crate::storage::DataStore;
+use crate::types::{Key, Value};
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+/// Handle to a transaction started with `DataStore::transaction`
+///
+/// The store stays borrowed until the transaction is committed or dropped
+pub struct Transaction<'s, 'a> {
+    store: &'s mut DataStore<'a, Key>,
+    snapshot: Snapshot,
+
+    /// Pending writes by key, `None` for deletes
+    writes: BTreeMap<Key, Option<Value>>,
+}
+
+impl<'s, 'a> Transaction<'s, 'a> {
+    /// Buffers a write of `key`, it is visible to this transaction only until it is committed
+    pub fn put(&mut self, key: impl AsRef<[u8]>, val: impl AsRef<[u8]>) {
+        self.writes.insert(key.as_ref().to_vec(), Some(val.as_ref().to_vec()));
+    }
+
+    /// Buffers a delete of `key`, it is visible to this transaction only until it is committed
+    pub fn delete(&mut self, key: impl AsRef<[u8]>) {
+        self.writes.insert(key.as_ref().to_vec(), None);
+    }
+
+    /// Returns the value of `key` written by this transaction, or as of its snapshot if it did not write it
+    pub async fn get(&self, key: impl AsRef<[u8]>) -> Result<Option<Value>, Error> {
+        let key = key.as_ref();
+        if let Some(pending) = self.writes.get(key) {
+            return Ok(pending.to_owned());
+        }
+        self.store.get_with_options(key, &self.snapshot.read_options()).await
+    }
+
+    /// Returns the live entries whose key falls within `[start, end)` in key order, pending writes of this
+    /// transaction replace the entries read as of its snapshot
+    pub async fn scan(&self, start: impl AsRef<[u8]>, end: impl AsRef<[u8]>) -> Result<Vec<FetchedEntry>, Error> {
+        let (start, end) = (start.as_ref(), end.as_ref());
+        if start >= end {
+            return Ok(Vec::new());
+        }
+        let mut entries: BTreeMap<Key, Value> = self
+            .store
+            .fetch_range(start, end, &self.snapshot.read_options())
+            .await?
+            .into_iter()
+            .map(|e| (e.key, e.val))
+            .collect();
+        let pending = self
+            .writes
+            .range::<[u8], _>((Bound::Included(start), Bound::Excluded(end)));
+        for (key, val) in pending {
+            match val {
+                Some(val) => entries.insert(key.to_owned(), val.to_owned()),
+                None => entries.remove(key),
+            };
+        }
+        Ok(entries
+            .into_iter()
+            .map(|(key, val)| FetchedEntry { key, val })
+            .collect())
+    }
+
+    /// Snapshot the transaction reads the store at
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+
+    /// Applies the pending writes with `write_batch`
+    pub async fn commit(self) -> Result<(), Error> {
+        self.commit_with_options(&WriteOptions::default()).await
+    }
+
+    /// Same as `commit` but with per-call write options
+    pub async fn commit_with_options(self, options: &WriteOptions) -> Result<(), Error> {
+        let mut batch = WriteBatch::new();
+        for (key, val) in self.writes {
+            match val {
+                Some(val) => batch.put(key, val),
+                None => batch.delete(key),
+            }
+        }
+        self.store.write_batch(&batch, options).await
+    }
+}
+
+impl<'a> DataStore<'a, Key> {
+    /// Starts a transaction reading the store as of now
+    ///
+    /// Writes are buffered in the transaction until it is committed. The transaction holds a snapshot, it
+    /// should be committed or dropped promptly so that compaction and garbage collection can proceed.
+    pub async fn transaction(&mut self) -> Result<Transaction<'_, 'a>, Error> {
+        let snapshot = self.snapshot().await?;
+        Ok(Transaction {
+            store: self,
+            snapshot,
+            writes: BTreeMap::new(),
+        })
+    }
+}
@@ -0,0 +1,5 @@
+mod handle;
+mod prepared;
+pub use handle::Transaction;
+pub use prepared::PreparedBatches;
+pub use prepared::PreparedToken;
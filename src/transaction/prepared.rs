@@ -0,0 +1,244 @@
+//! # Prepared batches
+//!
+//! The store can take part in a distributed transaction as a two-phase commit participant. `prepare` persists a
+//! batch without applying it and returns a token, the coordinator then either `commit`s the batch, which applies
+//! it, or `rollback`s it, which forgets it.
+//!
+//! Prepared batches are appended to a file in the meta directory and synced before `prepare` returns, a batch
+//! that was neither committed nor rolled back is still prepared after a restart. Resolving a batch appends a
+//! record for its token, resolved batches are dropped from the file the next time it is opened.
+
+use crate::batch::{BatchOp, WriteBatch};
+use crate::consts::{SIZE_OF_U32, SIZE_OF_U64, SIZE_OF_U8};
+use crate::err::Error;
+use crate::err::Error::*;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+const PREPARE_RECORD: u8 = 1;
+const RESOLVE_RECORD: u8 = 2;
+const PUT_OP: u8 = 1;
+const DELETE_OP: u8 = 2;
+
+/// Identifies a prepared batch, returned by `DataStore::prepare`
+///
+/// Tokens are never reused, a coordinator can persist `id` and rebuild the token with `new` after a crash
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PreparedToken(u64);
+
+impl PreparedToken {
+    pub fn new(id: u64) -> Self {
+        Self(id)
+    }
+
+    pub fn id(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Batches prepared but not yet committed or rolled back, persisted in `path`
+#[derive(Debug, Clone)]
+pub struct PreparedBatches {
+    path: PathBuf,
+    batches: BTreeMap<PreparedToken, WriteBatch>,
+}
+
+impl PreparedBatches {
+    /// Loads the batches still prepared in `path`, the file is created on the first insertion
+    pub async fn open(path: PathBuf) -> Result<Self, Error> {
+        Self::load(path, true).await
+    }
+
+    /// Same as `open` but the file is not rewritten without the resolved batches
+    pub async fn open_read_only(path: PathBuf) -> Result<Self, Error> {
+        Self::load(path, false).await
+    }
+
+    async fn load(path: PathBuf, rewrite: bool) -> Result<Self, Error> {
+        let mut prepared = Self {
+            path,
+            batches: BTreeMap::new(),
+        };
+        if !prepared.path.exists() {
+            return Ok(prepared);
+        }
+        let buf = fs::read(&prepared.path).await.map_err(|error| FileReadError {
+            path: prepared.path.to_owned(),
+            error,
+        })?;
+        let mut offset = 0;
+        let mut resolved = 0;
+        while offset < buf.len() {
+            match Self::deserialize(&buf[offset..]) {
+                Some((token, Some(batch), read)) => {
+                    prepared.batches.insert(token, batch);
+                    offset += read;
+                }
+                Some((token, None, read)) => {
+                    prepared.batches.remove(&token);
+                    resolved += 1;
+                    offset += read;
+                }
+                None => {
+                    // A record interrupted by a crash, the batch was never reported as prepared
+                    log::warn!("Ignoring incomplete prepared batch at the end of {:?}", prepared.path);
+                    break;
+                }
+            }
+        }
+        if rewrite && resolved > 0 {
+            prepared.rewrite().await?;
+        }
+        Ok(prepared)
+    }
+
+    /// Persists `batch` under `token`
+    pub async fn insert(&mut self, token: PreparedToken, batch: &WriteBatch) -> Result<(), Error> {
+        Self::append(&self.path, &Self::serialize(token, Some(batch))).await?;
+        self.batches.insert(token, batch.to_owned());
+        Ok(())
+    }
+
+    /// Forgets the batch prepared under `token`, returns false if there is none
+    pub async fn remove(&mut self, token: PreparedToken) -> Result<bool, Error> {
+        if !self.batches.contains_key(&token) {
+            return Ok(false);
+        }
+        Self::append(&self.path, &Self::serialize(token, None)).await?;
+        self.batches.remove(&token);
+        Ok(true)
+    }
+
+    pub fn get(&self, token: PreparedToken) -> Option<&WriteBatch> {
+        self.batches.get(&token)
+    }
+
+    /// Returns the tokens of every prepared batch, oldest first
+    pub fn tokens(&self) -> Vec<PreparedToken> {
+        self.batches.keys().copied().collect()
+    }
+
+    // A prepare record holds the batch operations, a resolve record only the token
+    fn serialize(token: PreparedToken, batch: Option<&WriteBatch>) -> Vec<u8> {
+        let mut record = Vec::new();
+        let Some(batch) = batch else {
+            record.push(RESOLVE_RECORD);
+            record.extend_from_slice(&token.id().to_le_bytes());
+            return record;
+        };
+        record.push(PREPARE_RECORD);
+        record.extend_from_slice(&token.id().to_le_bytes());
+        record.extend_from_slice(&(batch.len() as u32).to_le_bytes());
+        for op in batch.ops() {
+            let (kind, value): (u8, &[u8]) = match op {
+                BatchOp::Put { value, .. } => (PUT_OP, value),
+                BatchOp::Delete { .. } => (DELETE_OP, &[]),
+            };
+            record.push(kind);
+            record.extend_from_slice(&(op.key().len() as u32).to_le_bytes());
+            record.extend_from_slice(op.key());
+            record.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            record.extend_from_slice(value);
+        }
+        record
+    }
+
+    // Parses the record at the start of `buf` and returns its token, its batch for prepare records and the number
+    // of bytes read, `None` if `buf` ends before the record does
+    fn deserialize(buf: &[u8]) -> Option<(PreparedToken, Option<WriteBatch>, usize)> {
+        let mut read = 0;
+        let mut next = |len: usize| -> Option<&[u8]> {
+            let bytes = buf.get(read..read + len)?;
+            read += len;
+            Some(bytes)
+        };
+        let kind = next(SIZE_OF_U8)?[0];
+        let token = PreparedToken::new(u64::from_le_bytes(next(SIZE_OF_U64)?.try_into().ok()?));
+        if kind == RESOLVE_RECORD {
+            return Some((token, None, read));
+        }
+        let mut batch = WriteBatch::new();
+        let ops = u32::from_le_bytes(next(SIZE_OF_U32)?.try_into().ok()?);
+        for _ in 0..ops {
+            let op = next(SIZE_OF_U8)?[0];
+            let key_len = u32::from_le_bytes(next(SIZE_OF_U32)?.try_into().ok()?) as usize;
+            let key = next(key_len)?.to_vec();
+            let value_len = u32::from_le_bytes(next(SIZE_OF_U32)?.try_into().ok()?) as usize;
+            let value = next(value_len)?;
+            match op {
+                PUT_OP => batch.put(key, value),
+                _ => batch.delete(key),
+            }
+        }
+        Some((token, Some(batch), read))
+    }
+
+    // Replaces the file with the batches still prepared
+    async fn rewrite(&self) -> Result<(), Error> {
+        let buf: Vec<u8> = self
+            .batches
+            .iter()
+            .flat_map(|(token, batch)| Self::serialize(*token, Some(batch)))
+            .collect();
+        // Written to a temporary file first so that a crash never leaves a partially written file
+        let tmp_path = self.path.with_extension("tmp");
+        if tmp_path.exists() {
+            fs::remove_file(&tmp_path).await.map_err(FileDeleteError)?;
+        }
+        Self::append(&tmp_path, &buf).await?;
+        fs::rename(&tmp_path, &self.path).await.map_err(|error| FileWriteError {
+            path: self.path.to_owned(),
+            error,
+        })
+    }
+
+    async fn append(path: &Path, buf: &[u8]) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).await.map_err(|error| DirCreationError {
+                path: dir.to_path_buf(),
+                error,
+            })?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|error| FileOpenError {
+                path: path.to_path_buf(),
+                error,
+            })?;
+        file.write_all(buf).await.map_err(|error| FileWriteError {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        file.sync_all().await.map_err(|error| FileSyncError { error })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let mut batch = WriteBatch::new();
+        batch.put("a", "1");
+        batch.delete("b");
+        batch.put("c", "");
+        let token = PreparedToken::new(42);
+        let record = PreparedBatches::serialize(token, Some(&batch));
+        let (read_token, read_batch, read) = PreparedBatches::deserialize(&record).unwrap();
+        assert_eq!(read_token, token);
+        assert_eq!(read_batch.unwrap().ops(), batch.ops());
+        assert_eq!(read, record.len());
+        assert!(PreparedBatches::deserialize(&record[..record.len() - 1]).is_none());
+
+        let record = PreparedBatches::serialize(token, None);
+        let (read_token, read_batch, _) = PreparedBatches::deserialize(&record).unwrap();
+        assert_eq!(read_token, token);
+        assert!(read_batch.is_none());
+    }
+}
@@ -9,12 +9,22 @@ use crate::{
     filter::BloomFilter,
     key_range::KeyRange,
     memtable::{MemTable, SkipMapValue},
+    range_tombstone::RangeTombstones,
 };
 pub type Key = Vec<u8>;
 pub type Value = Vec<u8>;
 pub type ValOffset = usize;
 pub type CreationTime = u64;
+/// Wall-clock time in milliseconds a version was written at, TTLs and reads as of a point in time rely on it while
+/// versions are ordered by their sequence number (`CreationTime`)
+pub type WriteTime = u64;
 pub type IsTombStone = bool;
+/// Absolute time in milliseconds after which an entry is treated as deleted, `None` if it never expires
+pub type ExpiresAt = Option<u64>;
+/// Value offset, sequence number, write time, deleted flag and inline value of the version of a key found by a lookup
+pub type FoundEntry = (ValOffset, CreationTime, WriteTime, IsTombStone, Option<Value>);
+/// Reader over a value of the value log, a compressed value is decompressed before it is read
+pub type ValueReader = Box<dyn tokio::io::AsyncRead + Send + Unpin>;
 pub type FlushSignal = u8;
 pub type NoBytesRead = usize;
 pub type SkipMapEntries<K> = Arc<SkipMap<K, SkipMapValue<ValOffset>>>; // TODO: mention reason for our choice for this data structure in docs
@@ -22,6 +32,7 @@ pub type FlushReceiver = async_broadcast::Receiver<FlushSignal>;
 pub type BucketMapHandle = Arc<RwLock<BucketMap>>;
 pub type BloomFilterHandle = Arc<RwLock<Vec<BloomFilter>>>; // TODO: Explain why we used RwLock and not Mutex in docmentation
 pub type KeyRangeHandle = Arc<RwLock<KeyRange>>;
+pub type RangeTombstonesHandle = Arc<RwLock<RangeTombstones>>;
 pub type ImmutableMemTable<K> = Arc<RwLock<IndexMap<K, Arc<RwLock<MemTable<K>>>>>>;
 pub type Duration = u64;
 pub type Bool = bool;
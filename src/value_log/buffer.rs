@@ -0,0 +1,156 @@
+//! # Append buffer
+//!
+//! Records appended to the value log are grouped in memory and written to the file with a single write, instead of
+//! one write per record. A record gets its offset when it is buffered: the end of the file plus the bytes buffered
+//! before it. The buffer is written once it holds `Config::value_log_buffer_size` bytes or once its oldest record
+//! waited `Config::value_log_buffer_interval` milliseconds, an append past the interval writes it and a background
+//! task writes a buffer no append comes to.
+//!
+//! Reads of a buffered record, syncs and garbage collection write the buffer first so they never miss a record. The
+//! clones of a value log share its buffer, an append made by a clone with no buffer set writes the buffer before
+//! its record so offsets never overlap. Records still buffered when the process stops are lost like records
+//! written but not synced, a write made with `WriteOptions::sync` is never lost.
+
+use crate::{
+    err::Error::{self, *},
+    fs::{FileAsync, VLogFileNode},
+    value_log::ValueLogFormat,
+};
+use std::{
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+
+/// Records appended to a value log but not written to its file yet
+#[derive(Debug, Default)]
+pub(crate) struct AppendBuffer {
+    /// Records are written once the buffer holds this many bytes, 0 writes every record right away
+    capacity: usize,
+
+    /// Milliseconds a record waits in the buffer at most
+    interval: u64,
+
+    /// Records buffered, preceded by the header of the file if it is empty
+    bytes: Vec<u8>,
+
+    /// Offset of the first buffered byte
+    start: usize,
+
+    /// Time the first record was buffered
+    since: Option<Instant>,
+}
+
+/// Handle on the buffer shared by the clones of a value log
+pub(crate) type SharedAppendBuffer = Arc<Mutex<AppendBuffer>>;
+
+impl AppendBuffer {
+    /// Buffers records until they take `capacity` bytes or wait `interval` milliseconds, a capacity of 0 disables
+    /// the buffer
+    pub(crate) fn set_limits(&mut self, capacity: usize, interval: u64) {
+        self.capacity = capacity;
+        self.interval = interval;
+    }
+
+    /// Returns true if records are buffered rather than written right away
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Returns true if the record at `offset` may still be buffered
+    pub(crate) fn holds(&self, offset: usize) -> bool {
+        !self.bytes.is_empty() && offset >= self.start
+    }
+
+    /// Returns the offset the next record is appended at, `None` if nothing is buffered
+    pub(crate) fn end(&self) -> Option<usize> {
+        (!self.bytes.is_empty()).then(|| self.start + self.bytes.len())
+    }
+
+    // Returns true if the oldest record waited for the interval
+    fn is_due(&self) -> bool {
+        self.since
+            .is_some_and(|since| since.elapsed() >= Duration::from_millis(self.interval))
+    }
+
+    /// Buffers `record`, a record of a log of format `format` stored in `file`, returns its offset
+    ///
+    /// The buffer is written if it is full or due, the record is dropped from it if that write fails so the append
+    /// fails as a whole.
+    pub(crate) async fn append(
+        &mut self,
+        file: &VLogFileNode,
+        format: ValueLogFormat,
+        record: &[u8],
+    ) -> Result<usize, Error> {
+        if self.bytes.is_empty() {
+            let file_len = file.node.metadata().await?.len() as usize;
+            self.start = file.base() + file_len;
+            if file_len == 0 {
+                self.bytes.extend_from_slice(&format.header());
+            }
+            self.since = Some(Instant::now());
+        }
+        let buffered = self.bytes.len();
+        let offset = self.start + buffered;
+        self.bytes.extend_from_slice(record);
+        if self.bytes.len() >= self.capacity || self.is_due() {
+            if let Err(err) = self.write_to(file).await {
+                self.bytes.truncate(buffered);
+                return Err(err);
+            }
+        }
+        Ok(offset)
+    }
+
+    /// Writes the buffered records at the end of the file of `node`
+    ///
+    /// The part written is truncated away if the write fails, the records stay buffered for the next write.
+    pub(crate) async fn write_to(&mut self, node: &VLogFileNode) -> Result<(), Error> {
+        if self.bytes.is_empty() {
+            return Ok(());
+        }
+        let path = node.node.file_path.to_owned();
+        let mut file = node.node.w_lock().await;
+        let start = file.metadata().await.map_err(GetFileMetaDataError)?.len();
+        node.preallocate(&file, start, self.bytes.len());
+        let res = match file.write_all(&self.bytes).await {
+            Ok(()) => file.flush().await,
+            Err(error) => Err(error),
+        };
+        if let Err(error) = res {
+            file.set_len(start).await.map_err(|error| FileWriteError {
+                path: path.to_owned(),
+                error,
+            })?;
+            return Err(FileWriteError { path, error });
+        }
+        self.clear();
+        Ok(())
+    }
+
+    /// Drops the buffered records
+    pub(crate) fn clear(&mut self) {
+        self.bytes.clear();
+        self.since = None;
+    }
+
+    /// Writes the records of `buffer` to `file` once they are due, until the buffer is dropped
+    pub(crate) fn start_flush_task(buffer: &SharedAppendBuffer, file: VLogFileNode, interval: u64) {
+        let buffer: Weak<Mutex<AppendBuffer>> = Arc::downgrade(buffer);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(interval.max(1))).await;
+                let Some(buffer) = buffer.upgrade() else {
+                    return;
+                };
+                let mut buffer = buffer.lock().await;
+                if buffer.is_due() {
+                    if let Err(err) = buffer.write_to(&file).await {
+                        log::error!("Value log buffer could not be written: {}", err);
+                    }
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,111 @@
+//! # Value classes
+//!
+//! Values are appended to one of three value logs by size, so that garbage collection of the churn of small values
+//! never copies large ones and reads of large values do not evict small ones from the page cache. Small values and
+//! every pointer go to `val_log.bin`, values of at least `Config::medium_value_threshold` bytes to
+//! `val_log_medium.bin` and values of at least `Config::large_value_threshold` bytes to `val_log_large.bin`.
+//!
+//! A value stored in the medium or large log is found at a location holding the class of its log above
+//! `CLASS_SHIFT` and its offset in that log below, the offset of a small value is its location. SSTable entries
+//! record the class in their flags byte next to the 4-byte offset. The main log holds a pointer to the location of
+//! every value appended to another log, recovery replays the main log only.
+
+use crate::{
+    consts::{VLOG_FILE_NAME, VLOG_LARGE_FILE_NAME, VLOG_MEDIUM_FILE_NAME},
+    types::ValOffset,
+};
+
+// Bits of a location below the class of its value log
+const CLASS_SHIFT: u32 = 48;
+
+/// Value log a value is appended to, by size
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ValueClass {
+    /// Values shorter than the medium threshold, along with tombstones and pointers
+    #[default]
+    Small,
+
+    /// Values shorter than the large threshold
+    Medium,
+
+    /// Values of at least the large threshold
+    Large,
+}
+
+impl ValueClass {
+    /// Classes stored in a value log of their own
+    pub const SEPARATE: [ValueClass; 2] = [ValueClass::Medium, ValueClass::Large];
+
+    /// Returns the class of a value of `len` bytes
+    pub fn of_len(len: usize, medium_threshold: usize, large_threshold: usize) -> Self {
+        if len >= large_threshold {
+            ValueClass::Large
+        } else if len >= medium_threshold {
+            ValueClass::Medium
+        } else {
+            ValueClass::Small
+        }
+    }
+
+    /// Returns the class of the value log holding the value at `location`
+    pub fn of(location: ValOffset) -> Self {
+        ValueClass::from_bits((location >> CLASS_SHIFT) as u8).unwrap_or_default()
+    }
+
+    /// Returns the offset in its value log of the value at `location`
+    pub fn offset(location: ValOffset) -> ValOffset {
+        location & ((1 << CLASS_SHIFT) - 1)
+    }
+
+    /// Returns the location of the value at `offset` in the value log of this class
+    pub fn location(&self, offset: ValOffset) -> ValOffset {
+        ((self.bits() as usize) << CLASS_SHIFT) | offset
+    }
+
+    /// Returns the bits identifying the class in SSTable entries
+    pub(crate) fn bits(&self) -> u8 {
+        match self {
+            ValueClass::Small => 0,
+            ValueClass::Medium => 1,
+            ValueClass::Large => 2,
+        }
+    }
+
+    pub(crate) fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0 => Some(ValueClass::Small),
+            1 => Some(ValueClass::Medium),
+            2 => Some(ValueClass::Large),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the file of the value log of this class
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            ValueClass::Small => VLOG_FILE_NAME,
+            ValueClass::Medium => VLOG_MEDIUM_FILE_NAME,
+            ValueClass::Large => VLOG_LARGE_FILE_NAME,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_class_location() {
+        assert_eq!(ValueClass::of_len(10, 100, 1000), ValueClass::Small);
+        assert_eq!(ValueClass::of_len(100, 100, 1000), ValueClass::Medium);
+        assert_eq!(ValueClass::of_len(5000, 100, 1000), ValueClass::Large);
+        assert_eq!(ValueClass::of_len(5000, usize::MAX, usize::MAX), ValueClass::Small);
+
+        // The location of a small value is its offset
+        assert_eq!(ValueClass::Small.location(1234), 1234);
+        let location = ValueClass::Large.location(1234);
+        assert_eq!(ValueClass::of(location), ValueClass::Large);
+        assert_eq!(ValueClass::offset(location), 1234);
+        assert_eq!(ValueClass::of(1234), ValueClass::Small);
+    }
+}
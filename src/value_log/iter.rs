@@ -0,0 +1,96 @@
+//! # Value log iterator
+//!
+//! `ValueLog::iter_from` decodes the records of the value log one after the other from an offset, with the same
+//! decoding recovery replays the log with, so that tools can inspect, export or audit the raw log. The records are
+//! returned as they are stored: values stay compressed, pointers to the logs of other classes are not followed
+//! and the head and tail entries garbage collection writes are returned along with the others.
+//!
+//! The iterator reads its own handle on the file, up to its end when the iterator was created. Records appended
+//! afterwards are not returned and a file garbage collection replaces meanwhile is still read as it was.
+
+use crate::{
+    err::Error::{self, *},
+    fs::{FileAsync, FileNode, VLogFileNode},
+    types::ValOffset,
+    value_log::{ValueLogEntry, ValueLogFormat},
+};
+use std::{io::SeekFrom, path::PathBuf};
+use tokio::{fs::File, io::AsyncSeekExt};
+
+/// Decodes the records of a value log file in the order they were appended, see `ValueLog::iter_from`
+#[derive(Debug)]
+pub struct ValueLogIter {
+    file: File,
+    path: PathBuf,
+    format: ValueLogFormat,
+
+    // Offset of the next record
+    offset: usize,
+
+    // Offset of the end of the file when the iterator was created
+    end_offset: usize,
+
+    // Set once the end of the file or a record that cannot be decoded is reached
+    done: bool,
+}
+
+impl ValueLogIter {
+    /// Opens the value log file at `path` positioned on the record at `start_offset`, or on its first record if
+    /// `start_offset` precedes it
+    pub(crate) async fn open(path: PathBuf, start_offset: usize) -> Result<ValueLogIter, Error> {
+        let mut file = FileNode::open(path.to_owned()).await?;
+        let (format, base) = VLogFileNode::load_header(&mut file, path.to_owned()).await?;
+        let offset = start_offset.max(VLogFileNode::first_offset_of(format, base));
+        let end_offset = base + file.metadata().await.map_err(GetFileMetaDataError)?.len() as usize;
+        file.seek(SeekFrom::Start((offset - base) as u64))
+            .await
+            .map_err(FileSeekError)?;
+        Ok(ValueLogIter {
+            file,
+            path,
+            format,
+            offset,
+            end_offset,
+            done: false,
+        })
+    }
+
+    /// Returns the offset of the next record, the offset to resume from with `ValueLog::iter_from`
+    pub fn offset(&self) -> ValOffset {
+        self.offset
+    }
+
+    /// Returns the next record along with its offset, `None` once the end of the file is reached
+    ///
+    /// A torn or corrupted record is returned as an error, `UnexpectedEOF` or `CorruptedValueLogEntry` with its
+    /// offset, and ends the iteration since the records that follow cannot be located.
+    pub async fn next(&mut self) -> Result<Option<(ValOffset, ValueLogEntry)>, Error> {
+        if self.done || self.offset >= self.end_offset {
+            self.done = true;
+            return Ok(None);
+        }
+        let res = FileNode::load_entry(
+            &mut self.file,
+            self.format,
+            self.offset,
+            self.end_offset,
+            self.path.to_owned(),
+        )
+        .await;
+        match res {
+            Ok(Some((entry, entry_len))) => {
+                let offset = self.offset;
+                self.offset += entry_len;
+                Ok(Some((offset, entry)))
+            }
+            Ok(None) => {
+                self.done = true;
+                Ok(None)
+            }
+            Err(err) => {
+                self.done = true;
+                Err(err)
+            }
+        }
+    }
+}
@@ -1,3 +1,9 @@
+mod buffer;
+mod class;
+mod iter;
 mod v_log;
+pub use class::ValueClass;
+pub use iter::ValueLogIter;
 pub use v_log::ValueLog;
 pub use v_log::ValueLogEntry;
+pub use v_log::ValueLogFormat;
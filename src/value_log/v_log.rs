@@ -35,57 +35,109 @@
 //!
 //! ## Log File Structure Diagram
 //!
-//! The `log_file` is structured as follows:
+//! The `log_file` starts with a header made of the magic `VLOG` and the version of the record format, followed
+//! by the records:
 //!
 //! ```text
 //! +-------------------+
-//! |    Key Size       |   (4 bytes)
+//! |      Magic        |   (4 bytes, "VLOG")
 //! +-------------------+
-//! |   Value Size      |   (4 byte)
+//! |     Version       |   (4 bytes)
 //! +-------------------+
-//! |      Key          |   (variable)
+//! |   Record Magic    |   (1 byte, 0xA5)
 //! +-------------------+
-//! |     Value         |   (variable)
+//! |  Record Length    |   (4 bytes)
+//! +-------------------+
+//! |      Flags        |   (1 byte)
 //! +-------------------+
 //! |   Created At      |   (8 bytes)
-//! |                   |
 //! +-------------------+
-//! |  Is Tombstone     |   (1 byte)
-//! |                   |
-//! |                   |
+//! |   Expires At      |   (8 bytes, optional)
 //! +-------------------+
 //! |    Key Size       |   (4 bytes)
 //! +-------------------+
-//! |   Value Size      |   (4 byte)
+//! |   Value Size      |   (4 bytes)
 //! +-------------------+
 //! |      Key          |   (variable)
 //! +-------------------+
 //! |     Value         |   (variable)
 //! +-------------------+
-//! |   Created At      |   (8 bytes)
-//! |                   |
+//! |   Written At      |   (8 bytes)
+//! +-------------------+
+//! |    Checksum       |   (4 or 8 bytes)
 //! +-------------------+
-//! |  Is Tombstone     |   (1 byte)
-//! |                   |
-//! |                   |
+//! |   Record Magic    |   (1 byte, 0xA5)
+//! +-------------------+
+//! |       ...         |
 //! +-------------------+
 //! ```
 //!
-//! - **Key Size**: A 4-byte field representing the length of the key in bytes.
-//! - **Value Size**: A 4-byte field representing the length of the value in bytes.
-//! - **Key**: The actual key data, which can vary in size.
-//! - **Value**: The actual value data, which can vary in size.
-//! - **Created At**: A 8-byte field representing the time of insertion in bytes.
-//! - **Is Tombstone**: A 1 byte field representing a boolean of deleted or not deleted entry
+//! - **Magic**, **Version**: Identify the file and the layout of its records, a version this build does not know
+//!   is reported as `UnsupportedValueLogVersion`
+//! - **Record Magic**: A record that does not start with it was not fully written
+//! - **Record Length**: A 4-byte field counting the bytes of the record that follow it, checksum included. A record
+//!   extending past the end of the file was not fully written
+//! - **Flags**: Bit 0 marks a deleted entry, bit 1 marks that an expiry time follows the creation time and bit 2
+//!   selects the checksum algorithm, bit 4 marks a compressed value, see `CompressionType`, and bit 5 a value
+//!   holding the location of a value stored in the log of another class, see `ValueClass`. New bits can announce
+//!   new fields
+//! - **Created At**: A 8-byte field holding the sequence number of the insertion
+//! - **Expires At**: An optional 8-byte field representing the time after which the entry is treated as deleted
+//! - **Key Size**, **Value Size**: 4-byte fields representing the length of the key and of the value in bytes
+//! - **Key**, **Value**: The actual key and value data, which can vary in size. The value size of a compressed
+//!   value is the size it is stored with
+//! - **Written At**: A 8-byte field holding the wall-clock time of the insertion, records written before it was
+//!   recorded end with their value and their creation time stands in for it. Fields a later version appends after
+//!   it are skipped
+//! - **Checksum**: A 4-byte CRC32C, or an 8-byte XXH64 if bit 2 of the flags is set, of every preceding field of
+//!   the record, a mismatch on read or recovery is reported as `CorruptedValueLogEntry`
+//!
+//! Torn writes are found by the record magic, the record length or the checksum, recovery truncates the log at
+//! the first record found torn.
+//!
+//! Copy-and-truncate garbage collection rewrites the file without the entries preceding the tail, see
+//! `GCStrategy`. The rewritten file has version 2 and its header holds 8 more bytes: the offset of the first byte of
+//! the file, so that the entries it keeps are still found at the offsets the SSTables hold.
+//!
+//! Files written before the header existed hold `ValueLogFormat::Legacy` entries, they are still read and
+//! appended to in that layout: key size, value size, created at, flags, optional expires at, key, value and
+//! checksum, with no header nor framing.
 
+use super::{
+    buffer::{AppendBuffer, SharedAppendBuffer},
+    ValueClass, ValueLogIter,
+};
 use crate::{
-    consts::{EOF, SIZE_OF_U32, SIZE_OF_U64, SIZE_OF_U8, VLOG_FILE_NAME},
+    checksum::{Checksum, ChecksumType},
+    compression::CompressionType,
+    consts::{
+        COMPRESSED_FLAG, DEFAULT_LARGE_VALUE_THRESHOLD, DEFAULT_MEDIUM_VALUE_THRESHOLD,
+        DEFAULT_VALUE_COMPRESSION_THRESHOLD, EOF, REDIRECT_FLAG, REWRITTEN_FLAG, SIZE_OF_U32, SIZE_OF_U64, SIZE_OF_U8,
+        VLOG_FILE_NAME, VLOG_FORMAT_VERSION, VLOG_HEADER_SIZE, VLOG_MAGIC, VLOG_RECORD_MAGIC, VLOG_STREAM_CHUNK_SIZE,
+        XXHASH64_FLAG,
+    },
     err::Error,
-    fs::{FileAsync, FileNode, VLogFileNode, VLogFs},
+    err::Error::*,
+    fs::{encode_flags, flags_len, FileAsync, FileNode, RecordSpan, VLogFileNode, VLogFs},
+    storage::BackgroundErrors,
+    types::{ExpiresAt, Key, ValOffset, ValueReader, WriteTime},
 };
+use chrono::Utc;
 use log::error;
-use std::{mem, path::PathBuf};
-use tokio::io::{self};
+use std::{
+    io::{IoSlice, Write},
+    mem,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt, AsyncWriteExt},
+    sync::Mutex,
+};
 
 type TotalBytesRead = usize;
 
@@ -113,6 +165,42 @@ pub struct ValueLog {
     pub head_offset: usize,
     pub tail_offset: usize,
     pub size: usize,
+
+    /// Algorithm checksumming the entries appended from now on
+    pub checksum_type: ChecksumType,
+
+    /// Algorithm compressing the values appended from now on
+    pub compression: CompressionType,
+
+    /// Values shorter than this are appended uncompressed
+    pub compression_threshold: usize,
+
+    /// Logs of the values stored apart from the small ones, see `ValueClass`. A log is opened if its file exists or
+    /// its threshold is set
+    pub classes: Vec<(ValueClass, ValueLog)>,
+
+    /// Values at least this long are appended to the log of `ValueClass::Medium`
+    pub medium_threshold: usize,
+
+    /// Values at least this long are appended to the log of `ValueClass::Large`
+    pub large_threshold: usize,
+
+    /// Offset of the last entry appended, the pointer of a value appended to the log of another class
+    pub last_offset: usize,
+
+    /// Records appended but not written to the file yet, shared by the clones of the log, see `AppendBuffer`
+    pub(crate) buffer: SharedAppendBuffer,
+}
+
+/// Layout of the entries of a value log file, detected when the file is opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueLogFormat {
+    /// Entries written before records were framed, the file has no header. Entries appended to such a file keep
+    /// this layout
+    Legacy,
+
+    /// Records framed by `VLOG_RECORD_MAGIC` and their length, the file starts with `VLOG_MAGIC` and version 1
+    V1,
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -122,25 +210,101 @@ pub struct ValueLogEntry {
     pub key: Vec<u8>,
     pub value: Vec<u8>,
     pub created_at: u64,
+
+    /// Wall-clock time the entry was written at, `created_at` is its sequence number. Legacy logs do not record
+    /// it, their entries were created at a wall-clock time which stands in for it
+    pub written_at: WriteTime,
     pub is_tombstone: bool,
+    pub expires_at: ExpiresAt,
+    pub checksum_type: ChecksumType,
+
+    /// Set on values rewritten by a compaction filter, see `REWRITTEN_FLAG`
+    pub is_rewritten: bool,
+
+    /// Set on values stored compressed, see `COMPRESSED_FLAG`
+    pub is_compressed: bool,
+
+    /// Set on pointers to a value appended to the log of another class, see `REDIRECT_FLAG`
+    pub is_redirect: bool,
 }
 
 impl ValueLog {
     pub async fn new(dir: &PathBuf) -> Result<Self, Error> {
         let dir_path = PathBuf::from(dir);
         FileNode::create_dir_all(dir_path.to_owned()).await?;
-        let file_path = dir_path.join(VLOG_FILE_NAME);
-        let file = VLogFileNode::new(file_path.to_owned(), crate::fs::FileType::ValueLog)
-            .await
-            .unwrap();
+        let mut vlog = ValueLog::open(dir_path.join(VLOG_FILE_NAME)).await?;
+        // Values appended to the logs of other classes stay readable once their threshold is unset
+        for class in ValueClass::SEPARATE {
+            let file_path = dir_path.join(class.file_name());
+            if file_path.is_file() {
+                vlog.classes.push((class, ValueLog::open(file_path).await?));
+            }
+        }
+        Ok(vlog)
+    }
+
+    // Opens the value log stored at `file_path`, the file is created if it does not exist
+    async fn open(file_path: PathBuf) -> Result<Self, Error> {
+        let file = VLogFileNode::new(file_path.to_owned(), crate::fs::FileType::ValueLog).await?;
         Ok(Self {
             head_offset: 0,
             tail_offset: 0,
             content: VFile::new(file_path, file),
             size: 0,
+            checksum_type: ChecksumType::default(),
+            compression: CompressionType::default(),
+            compression_threshold: DEFAULT_VALUE_COMPRESSION_THRESHOLD,
+            classes: Vec::new(),
+            medium_threshold: DEFAULT_MEDIUM_VALUE_THRESHOLD,
+            large_threshold: DEFAULT_LARGE_VALUE_THRESHOLD,
+            last_offset: 0,
+            buffer: Arc::new(Mutex::new(AppendBuffer::default())),
         })
     }
 
+    /// Appends values at least `medium_threshold` bytes long to the log of `ValueClass::Medium` and values at least
+    /// `large_threshold` bytes long to the log of `ValueClass::Large`, `usize::MAX` keeps them in this log
+    ///
+    /// The logs are created if needed, an entry torn at the end of a log opened is truncated
+    pub async fn set_class_thresholds(&mut self, medium_threshold: usize, large_threshold: usize) -> Result<(), Error> {
+        self.medium_threshold = medium_threshold;
+        self.large_threshold = large_threshold;
+        let dir = self.content.path.parent().map(PathBuf::from).unwrap_or_default();
+        for (class, threshold) in [
+            (ValueClass::Medium, medium_threshold),
+            (ValueClass::Large, large_threshold),
+        ] {
+            if threshold != usize::MAX && self.class_log(class).is_none() {
+                let mut log = ValueLog::open(dir.join(class.file_name())).await?;
+                log.checksum_type = self.checksum_type;
+                log.compression = self.compression;
+                log.compression_threshold = self.compression_threshold;
+                self.classes.push((class, log));
+            }
+        }
+        for (_, log) in self.classes.iter() {
+            log.content.file.truncate_torn().await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the log of the values of `class`, `None` if it is not opened or `class` is `ValueClass::Small`
+    pub fn class_log(&self, class: ValueClass) -> Option<&ValueLog> {
+        self.classes.iter().find(|(c, _)| *c == class).map(|(_, log)| log)
+    }
+
+    fn class_log_mut(&mut self, class: ValueClass) -> Option<&mut ValueLog> {
+        self.classes.iter_mut().find(|(c, _)| *c == class).map(|(_, log)| log)
+    }
+
+    // Returns the class of the log a value of `len` bytes is appended to, the log is opened
+    fn class_of_len(&self, len: usize) -> ValueClass {
+        match ValueClass::of_len(len, self.medium_threshold, self.large_threshold) {
+            class if self.class_log(class).is_some() => class,
+            _ => ValueClass::Small,
+        }
+    }
+
     pub async fn append(
         &mut self,
         key: &Vec<u8>,
@@ -148,7 +312,22 @@ impl ValueLog {
         created_at: u64,
         is_tombstone: bool,
     ) -> Result<usize, Error> {
-        let v_log_entry = ValueLogEntry::new(
+        let written_at = Utc::now().timestamp_millis() as u64;
+        self.append_with_expiry(key, value, created_at, written_at, is_tombstone, None)
+            .await
+    }
+
+    /// Appends an entry written at `written_at` that is treated as deleted once `expires_at` has passed
+    pub async fn append_with_expiry(
+        &mut self,
+        key: &Vec<u8>,
+        value: &Vec<u8>,
+        created_at: u64,
+        written_at: WriteTime,
+        is_tombstone: bool,
+        expires_at: ExpiresAt,
+    ) -> Result<usize, Error> {
+        let mut v_log_entry = ValueLogEntry::new(
             key.len(),
             value.len(),
             key.to_vec(),
@@ -156,31 +335,351 @@ impl ValueLog {
             created_at,
             is_tombstone,
         );
-        let serialized_data = v_log_entry.serialize();
-        // Get the current offset before writing(this will be the offset of the value stored in the memtable)
-        let last_offset = self.size;
-        let data_file = &self.content;
-        let _ = data_file.file.node.write_all(&serialized_data).await;
-        self.size += serialized_data.len();
-        Ok(last_offset as usize)
+        v_log_entry.written_at = written_at;
+        v_log_entry.expires_at = expires_at;
+        self.append_entry(v_log_entry).await
+    }
+
+    /// Appends the value a compaction filter rewrote the version of `key` created at `created_at` and written at
+    /// `written_at` to, the entry is not replayed on recovery, see `REWRITTEN_FLAG`
+    pub async fn append_rewritten(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        created_at: u64,
+        written_at: WriteTime,
+        expires_at: ExpiresAt,
+    ) -> Result<usize, Error> {
+        let mut v_log_entry =
+            ValueLogEntry::new(key.len(), value.len(), key.to_vec(), value.to_vec(), created_at, false);
+        v_log_entry.written_at = written_at;
+        v_log_entry.expires_at = expires_at;
+        v_log_entry.is_rewritten = true;
+        self.append_entry(v_log_entry).await
+    }
+
+    /// Appends a pointer to the value of `key` stored at `location` in the log of another class, returns the
+    /// offset of the pointer
+    pub async fn append_redirect(
+        &mut self,
+        key: &[u8],
+        location: ValOffset,
+        created_at: u64,
+        written_at: WriteTime,
+        expires_at: ExpiresAt,
+    ) -> Result<usize, Error> {
+        let value = (location as u64).to_le_bytes().to_vec();
+        let mut pointer = ValueLogEntry::new(key.len(), value.len(), key.to_vec(), value, created_at, false);
+        pointer.written_at = written_at;
+        pointer.expires_at = expires_at;
+        pointer.is_redirect = true;
+        self.append_record(pointer).await
+    }
+
+    // Appends `v_log_entry` to the log of the class of its value, returns the location of the value. A value
+    // appended to the log of another class is followed by its pointer in this log so that recovery replays it.
+    // Tombstones and values rewritten by a compaction filter, which are not replayed, stay in this log
+    async fn append_entry(&mut self, v_log_entry: ValueLogEntry) -> Result<usize, Error> {
+        let class = match v_log_entry.is_tombstone || v_log_entry.is_rewritten {
+            true => ValueClass::Small,
+            false => self.class_of_len(v_log_entry.value.len()),
+        };
+        let Some(log) = self.class_log_mut(class) else {
+            return self.append_record(v_log_entry).await;
+        };
+        let (key, created_at, written_at, expires_at) = (
+            v_log_entry.key.to_owned(),
+            v_log_entry.created_at,
+            v_log_entry.written_at,
+            v_log_entry.expires_at,
+        );
+        let location = class.location(log.append_record(v_log_entry).await?);
+        self.append_redirect(&key, location, created_at, written_at, expires_at)
+            .await?;
+        Ok(location)
+    }
+
+    // Appends `v_log_entry` to this log checksummed with `checksum_type`, its value compressed with `compression` if
+    // it is long enough, returns its offset
+    async fn append_record(&mut self, mut v_log_entry: ValueLogEntry) -> Result<usize, Error> {
+        v_log_entry.checksum_type = self.checksum_type;
+        if !v_log_entry.is_tombstone
+            && !v_log_entry.is_redirect
+            && v_log_entry.value.len() >= self.compression_threshold
+        {
+            if let Some(compressed) = self.compression.compress(&v_log_entry.value) {
+                v_log_entry.vsize = compressed.len();
+                v_log_entry.value = compressed;
+                v_log_entry.is_compressed = true;
+            }
+        }
+        let format = self.content.file.format;
+        let mut buffer = self.buffer.lock().await;
+        if buffer.is_enabled() {
+            let last_offset = buffer
+                .append(&self.content.file, format, &v_log_entry.serialize(format))
+                .await?;
+            self.size = last_offset + v_log_entry.serialized_len(format);
+            self.last_offset = last_offset;
+            return Ok(last_offset);
+        }
+        // Records buffered by a clone of the log precede this one
+        buffer.write_to(&self.content.file).await?;
+        let path = self.content.path.to_owned();
+        let file = self.content.file.node.w_lock().await;
+        // Every handle on the file appends to its end, which is where the entry starts
+        let start = file.metadata().await.map_err(GetFileMetaDataError)?.len();
+        let file_header = if start == 0 { format.header() } else { Vec::new() };
+        let last_offset = self.content.file.base() + start as usize + file_header.len();
+        let entry_len = v_log_entry.serialized_len(format);
+        self.content
+            .file
+            .preallocate(&file, start, file_header.len() + entry_len);
+        let (header, footer) = v_log_entry.framing(format);
+        let value = mem::take(&mut v_log_entry.value);
+        // The record is written with a single `writev` from its parts so the value is never copied. Waits for the
+        // write to reach the file so that a failure, e.g. on a full disk, is reported by this append
+        let res = match file.try_clone().await {
+            Ok(clone) => {
+                let clone = clone.into_std().await;
+                tokio::task::spawn_blocking(move || {
+                    write_all_vectored(&clone, &[&file_header, &header, &value, &footer])
+                })
+                .await
+                .unwrap_or_else(|err| Err(io::Error::other(err)))
+            }
+            Err(error) => Err(error),
+        };
+        if let Err(error) = res {
+            // The part of the entry that was written would be replayed as a torn entry
+            file.set_len(start).await.map_err(|error| FileWriteError {
+                path: path.to_owned(),
+                error,
+            })?;
+            return Err(FileWriteError { path, error });
+        }
+        drop(file);
+        self.size = last_offset + entry_len;
+        self.last_offset = last_offset;
+        Ok(last_offset)
+    }
+
+    /// Appends an entry whose value of `len` bytes is read from `reader` in chunks of `VLOG_STREAM_CHUNK_SIZE`
+    ///
+    /// The file stays locked until the whole entry is written so no other append can interleave with it, the
+    /// value is not compressed. If `reader` fails or ends early the partially written entry is truncated away.
+    /// Returns the location of the value, which is appended to the log of its class as `append_entry` does.
+    pub async fn append_stream<R: AsyncRead + Unpin>(
+        &mut self,
+        key: &[u8],
+        reader: R,
+        len: usize,
+        created_at: u64,
+        written_at: WriteTime,
+    ) -> Result<usize, Error> {
+        let class = self.class_of_len(len);
+        let Some(log) = self.class_log_mut(class) else {
+            return self.write_stream(key, reader, len, created_at, written_at).await;
+        };
+        let location = class.location(log.write_stream(key, reader, len, created_at, written_at).await?);
+        self.append_redirect(key, location, created_at, written_at, None)
+            .await?;
+        Ok(location)
+    }
+
+    // Appends to this log an entry whose value of `len` bytes is read from `reader`, returns its offset
+    async fn write_stream<R: AsyncRead + Unpin>(
+        &mut self,
+        key: &[u8],
+        mut reader: R,
+        len: usize,
+        created_at: u64,
+        written_at: WriteTime,
+    ) -> Result<usize, Error> {
+        if len > u32::MAX as usize {
+            return Err(ValueTooLarge {
+                size: len,
+                max: u32::MAX as usize,
+            });
+        }
+        let mut entry = ValueLogEntry::new(key.len(), len, key.to_vec(), Vec::new(), created_at, false);
+        entry.written_at = written_at;
+        entry.checksum_type = self.checksum_type;
+        let format = self.content.file.format;
+        let header = entry.header(format);
+        let trailer = entry.trailer(format);
+
+        // The buffer is held until the entry is written so no record is buffered meanwhile
+        let mut buffer = self.buffer.lock().await;
+        buffer.write_to(&self.content.file).await?;
+        let path = self.content.path.to_owned();
+        let mut file = self.content.file.node.w_lock().await;
+        let start = file.metadata().await.map_err(GetFileMetaDataError)?.len();
+        let file_header = if start == 0 { format.header() } else { Vec::new() };
+        let last_offset = self.content.file.base() + start as usize + file_header.len();
+        let entry_len = file_header.len() + header.len() + len + trailer.len() + self.checksum_type.size();
+        self.content.file.preallocate(&file, start, entry_len);
+        let mut chunk = vec![0; VLOG_STREAM_CHUNK_SIZE.min(len)];
+        let mut written = 0;
+        let mut checksum = Checksum::new(self.checksum_type);
+        checksum.update(&header);
+        let mut res = file
+            .write_all(&[file_header, header.to_owned()].concat())
+            .await
+            .map_err(|error| FileWriteError {
+                path: path.to_owned(),
+                error,
+            });
+        while res.is_ok() && written < len {
+            let to_read = chunk.len().min(len - written);
+            res = match reader.read(&mut chunk[..to_read]).await {
+                Ok(0) => Err(UnexpectedEOF(io::Error::new(io::ErrorKind::UnexpectedEof, EOF))),
+                Ok(bytes_read) => {
+                    written += bytes_read;
+                    checksum.update(&chunk[..bytes_read]);
+                    file.write_all(&chunk[..bytes_read])
+                        .await
+                        .map_err(|error| FileWriteError {
+                            path: path.to_owned(),
+                            error,
+                        })
+                }
+                Err(error) => Err(FileReadError {
+                    path: path.to_owned(),
+                    error,
+                }),
+            };
+        }
+        if res.is_ok() {
+            checksum.update(&trailer);
+            res = match file.write_all(&[trailer.to_owned(), checksum.finish()].concat()).await {
+                Ok(()) => file.flush().await,
+                Err(error) => Err(error),
+            }
+            .map_err(|error| FileWriteError {
+                path: path.to_owned(),
+                error,
+            });
+        }
+        if let Err(err) = res {
+            file.set_len(start)
+                .await
+                .map_err(|error| FileWriteError { path, error })?;
+            return Err(err);
+        }
+        drop(file);
+        drop(buffer);
+        self.size = last_offset + header.len() + len + trailer.len() + self.checksum_type.size();
+        self.last_offset = last_offset;
+        Ok(last_offset)
+    }
+
+    /// Returns the value stored at `location` along with its tombstone flag, from the log of its class
+    pub async fn get(&self, location: usize) -> Result<Option<(Vec<u8>, bool)>, Error> {
+        match ValueClass::of(location) {
+            ValueClass::Small => {
+                self.write_buffer_holding(location).await?;
+                self.content.file.get(location).await
+            }
+            class => match self.class_log(class) {
+                Some(log) => log.content.file.get(ValueClass::offset(location)).await,
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Returns the values stored at `locations` along with their tombstone flags, in the order of `locations`
+    ///
+    /// The locations are read sorted by offset with a single sweep over the log of each class, see
+    /// `VLogFs::get_many`, instead of seeking to each of them in turn.
+    pub async fn get_many(&self, locations: &[usize]) -> Result<Vec<Option<(Vec<u8>, bool)>>, Error> {
+        let mut order: Vec<usize> = (0..locations.len()).collect();
+        order.sort_unstable_by_key(|i| locations[*i]);
+        let mut sorted: Vec<usize> = order.iter().map(|i| locations[*i]).collect();
+        sorted.dedup();
+        let mut found = Vec::with_capacity(sorted.len());
+        // The class lives in the high bits of a location so the locations of a class are contiguous once sorted
+        for class_locations in sorted.chunk_by(|a, b| ValueClass::of(*a) == ValueClass::of(*b)) {
+            let offsets: Vec<usize> = class_locations.iter().map(|l| ValueClass::offset(*l)).collect();
+            let values = match ValueClass::of(class_locations[0]) {
+                ValueClass::Small => {
+                    self.write_buffer_holding(offsets[offsets.len() - 1]).await?;
+                    self.content.file.get_many(&offsets).await?
+                }
+                class => match self.class_log(class) {
+                    Some(log) => log.content.file.get_many(&offsets).await?,
+                    None => vec![None; offsets.len()],
+                },
+            };
+            found.extend(values);
+        }
+
+        let mut values = vec![None; locations.len()];
+        let mut found = found.into_iter();
+        let mut previous: Option<usize> = None;
+        for i in order {
+            values[i] = match previous {
+                // A location asked for twice
+                Some(p) if locations[p] == locations[i] => values[p].clone(),
+                _ => found.next().flatten(),
+            };
+            previous = Some(i);
+        }
+        Ok(values)
     }
 
-    pub async fn get(&self, start_offset: usize) -> Result<Option<(Vec<u8>, bool)>, Error> {
-        self.content.file.get(start_offset).await
+    /// Returns a reader over the value stored at `location` along with its tombstone flag
+    pub async fn get_stream(&self, location: usize) -> Result<Option<(ValueReader, bool)>, Error> {
+        match ValueClass::of(location) {
+            ValueClass::Small => {
+                self.write_buffer_holding(location).await?;
+                self.content.file.get_stream(location).await
+            }
+            class => match self.class_log(class) {
+                Some(log) => log.content.file.get_stream(ValueClass::offset(location)).await,
+                None => Ok(None),
+            },
+        }
     }
 
+    /// Syncs this log and the logs of the other classes, buffered records are written first
     pub async fn sync_to_disk(&self) -> Result<(), Error> {
+        self.write_buffer().await?;
+        for (_, log) in self.classes.iter() {
+            log.content.file.node.sync_all().await?;
+        }
         self.content.file.node.sync_all().await
     }
 
-    pub async fn recover(&mut self, start_offset: usize) -> Result<Vec<ValueLogEntry>, Error> {
-        self.content.file.recover(start_offset).await
+    /// Replays the entries stored from `start_offset`, see `VLogFs::recover`
+    pub async fn recover(&self, start_offset: usize, truncate: bool) -> Result<Vec<ValueLogEntry>, Error> {
+        self.write_buffer().await?;
+        self.content.file.recover(start_offset, truncate).await
+    }
+
+    /// Returns an iterator decoding the records of the log from `offset`, its first record if `offset` precedes it
+    ///
+    /// Only the records of this log are returned, those of the other classes are read with `class_log`. Buffered
+    /// records are written first, see `ValueLogIter`.
+    pub async fn iter_from(&self, offset: ValOffset) -> Result<ValueLogIter, Error> {
+        self.write_buffer().await?;
+        ValueLogIter::open(self.content.path.to_owned(), offset).await
+    }
+
+    /// Verifies the checksums of the entries stored from `start_offset`, see `VLogFs::verify`
+    pub async fn verify(
+        &self,
+        start_offset: usize,
+    ) -> Result<(Vec<(ValOffset, Key)>, Option<(ValOffset, Error)>), Error> {
+        self.write_buffer().await?;
+        self.content.file.verify(start_offset).await
     }
 
     pub async fn read_chunk_to_garbage_collect(
         &self,
         bytes_to_collect: usize,
     ) -> Result<(Vec<ValueLogEntry>, TotalBytesRead), Error> {
+        self.write_buffer().await?;
         self.content
             .file
             .read_chunk_to_garbage_collect(bytes_to_collect, self.tail_offset as u64)
@@ -189,6 +688,7 @@ impl ValueLog {
 
     // CAUTION: This deletes the value log file
     pub async fn clear_all(&mut self) {
+        self.buffer.lock().await.clear();
         if self.content.file.node.metadata().await.is_ok() {
             if let Err(err) = self.content.file.node.remove_dir_all().await {
                 log::error!("{}", err);
@@ -205,6 +705,143 @@ impl ValueLog {
     pub fn set_tail(&mut self, tail: usize) {
         self.tail_offset = tail;
     }
+
+    /// Returns the layout of the entries of the file, see `ValueLogFormat`
+    pub fn format(&self) -> ValueLogFormat {
+        self.content.file.format
+    }
+
+    /// Returns the offset of the first entry of the file, entries before it were dropped by garbage collection
+    pub fn first_offset(&self) -> usize {
+        self.content.file.first_offset()
+    }
+
+    /// Returns the offset of the first byte of the file, see `VLogFileNode::truncate_before`
+    pub fn base(&self) -> usize {
+        self.content.file.base()
+    }
+
+    /// Returns the length of the entry stored at `offset`, see `VLogFileNode::entry_len`. `None` for a value of
+    /// another class, its space is not counted in this log
+    pub(crate) async fn entry_len(&self, offset: usize) -> Result<Option<usize>, Error> {
+        if ValueClass::of(offset) != ValueClass::Small {
+            return Ok(None);
+        }
+        self.write_buffer_holding(offset).await?;
+        self.content.file.entry_len(offset).await
+    }
+
+    /// Returns the records stored from `start_offset` without reading their values, see `VLogFileNode::spans`
+    pub(crate) async fn spans(&self, start_offset: usize) -> Result<Vec<RecordSpan>, Error> {
+        self.write_buffer().await?;
+        Ok(self.content.file.spans(start_offset).await?.0)
+    }
+
+    /// Marks the record of `span` reclaimed, see `VLogFileNode::mark_reclaimed`
+    pub(crate) async fn mark_reclaimed(&self, span: &RecordSpan) -> Result<(), Error> {
+        self.content.file.mark_reclaimed(span).await
+    }
+
+    /// Drops the entries of the file preceding `offset`, returns the number of bytes dropped
+    pub(crate) async fn truncate_before(&self, offset: usize) -> Result<usize, Error> {
+        self.write_buffer().await?;
+        self.content.file.truncate_before(offset).await
+    }
+
+    pub fn set_checksum_type(&mut self, checksum_type: ChecksumType) {
+        self.checksum_type = checksum_type;
+        for (_, log) in self.classes.iter_mut() {
+            log.checksum_type = checksum_type;
+        }
+    }
+
+    /// Compresses the values appended from now on that are at least `threshold` bytes long with `compression`
+    pub fn set_compression(&mut self, compression: CompressionType, threshold: usize) {
+        self.compression = compression;
+        self.compression_threshold = threshold;
+        for (_, log) in self.classes.iter_mut() {
+            log.compression = compression;
+            log.compression_threshold = threshold;
+        }
+    }
+
+    /// Buffers the records appended to this log until they take `capacity` bytes or wait `interval` milliseconds,
+    /// see `AppendBuffer`. A capacity of 0 writes every record right away
+    pub async fn set_write_buffer(&self, capacity: usize, interval: u64) -> Result<(), Error> {
+        let mut buffer = self.buffer.lock().await;
+        buffer.set_limits(capacity, interval);
+        if !buffer.is_enabled() {
+            buffer.write_to(&self.content.file).await?;
+        }
+        Ok(())
+    }
+
+    /// Allocates `size` bytes of disk space at once past the end of this log and of the logs of the other classes
+    /// ahead of the appends, 0 allocates nothing ahead, see `VLogFileNode::preallocate`
+    pub fn set_preallocation(&self, size: usize) {
+        self.content.file.set_preallocation(size);
+        for (_, log) in self.classes.iter() {
+            log.content.file.set_preallocation(size);
+        }
+    }
+
+    /// Starts writing the buffered records once they waited `interval` milliseconds even if nothing is appended
+    pub fn start_buffer_flush_task(&self, interval: u64) {
+        AppendBuffer::start_flush_task(&self.buffer, self.content.file.to_owned(), interval);
+    }
+
+    /// Syncs this log and the logs of the other classes every `interval` milliseconds until `shut_down` is set,
+    /// whether or not the writes asked for a sync
+    ///
+    /// This bounds how long a write acknowledged without `WriteOptions::sync` can stay out of the disk. A failed
+    /// sync is recorded in `background_errors` so that later writes fail instead of being acknowledged.
+    pub fn start_sync_task(&self, interval: u64, shut_down: Arc<AtomicBool>, background_errors: BackgroundErrors) {
+        let vlog = self.to_owned();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(interval.max(1))).await;
+                if shut_down.load(Ordering::SeqCst) {
+                    return;
+                }
+                if background_errors.get().is_some() {
+                    continue;
+                }
+                if let Err(err) = vlog.sync_to_disk().await {
+                    error!("Value log could not be synced: {}", err);
+                    background_errors.record(err);
+                }
+            }
+        });
+    }
+
+    /// Writes the buffered records to the file
+    pub async fn write_buffer(&self) -> Result<(), Error> {
+        self.buffer.lock().await.write_to(&self.content.file).await
+    }
+
+    // Writes the buffered records to the file if the record at `offset` may be one of them
+    async fn write_buffer_holding(&self, offset: usize) -> Result<(), Error> {
+        let mut buffer = self.buffer.lock().await;
+        if buffer.holds(offset) {
+            buffer.write_to(&self.content.file).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the offset the next entry is appended at
+    pub(crate) async fn end_offset(&self) -> usize {
+        if let Some(end) = self.buffer.lock().await.end() {
+            return end;
+        }
+        let file_len = self
+            .content
+            .file
+            .node
+            .metadata()
+            .await
+            .map_or(0, |metadata| metadata.len() as usize);
+        self.base() + file_len
+    }
 }
 
 impl ValueLogEntry {
@@ -215,35 +852,177 @@ impl ValueLogEntry {
             key,
             value,
             created_at,
+            written_at: Utc::now().timestamp_millis() as u64,
             is_tombstone,
+            expires_at: None,
+            checksum_type: ChecksumType::default(),
+            is_rewritten: false,
+            is_compressed: false,
+            is_redirect: false,
         }
     }
 
-    fn serialize(&self) -> Vec<u8> {
-        let entry_len = SIZE_OF_U32 + SIZE_OF_U32 + SIZE_OF_U64 + self.key.len() + self.value.len() + SIZE_OF_U8;
-
-        let mut serialized_data = Vec::with_capacity(entry_len);
+    /// Returns the location of the value a pointer points to, `None` if the entry is not a pointer
+    pub(crate) fn redirect(&self) -> Option<ValOffset> {
+        let location: [u8; SIZE_OF_U64] = self.value.as_slice().try_into().ok()?;
+        self.is_redirect.then_some(u64::from_le_bytes(location) as ValOffset)
+    }
 
-        serialized_data.extend_from_slice(&(self.key.len() as u32).to_le_bytes());
+    /// Returns the number of bytes the entry occupies in a value log of format `format`
+    pub(crate) fn serialized_len(&self, format: ValueLogFormat) -> usize {
+        let framing_len = match format {
+            ValueLogFormat::Legacy => 0,
+            ValueLogFormat::V1 => SIZE_OF_U8 + SIZE_OF_U32,
+        };
+        framing_len
+            + SIZE_OF_U32
+            + SIZE_OF_U32
+            + SIZE_OF_U64
+            + self.key.len()
+            + self.value.len()
+            + flags_len(self.expires_at)
+            + self.trailer(format).len()
+            + self.checksum_type.size()
+    }
 
-        serialized_data.extend_from_slice(&(self.value.len() as u32).to_le_bytes());
+    // Encodes the flags byte of the entry, `XXHASH64_FLAG` records the algorithm of its checksum and
+    // `COMPRESSED_FLAG` and `REDIRECT_FLAG` what its value holds
+    fn encode_flags(&self) -> Vec<u8> {
+        let mut flags = encode_flags(self.is_tombstone, self.expires_at);
+        if self.checksum_type == ChecksumType::XxHash64 {
+            flags[0] |= XXHASH64_FLAG;
+        }
+        if self.is_rewritten {
+            flags[0] |= REWRITTEN_FLAG;
+        }
+        if self.is_compressed {
+            flags[0] |= COMPRESSED_FLAG;
+        }
+        if self.is_redirect {
+            flags[0] |= REDIRECT_FLAG;
+        }
+        flags
+    }
 
-        serialized_data.extend_from_slice(&self.created_at.to_le_bytes());
+    // Encodes the bytes of the entry that precede its value, the value is `vsize` bytes long
+    fn header(&self, format: ValueLogFormat) -> Vec<u8> {
+        let flags = self.encode_flags();
+        let mut header = Vec::with_capacity(
+            SIZE_OF_U8 + SIZE_OF_U32 * 3 + SIZE_OF_U64 + flags_len(self.expires_at) + self.key.len(),
+        );
+        match format {
+            ValueLogFormat::Legacy => {
+                header.extend_from_slice(&(self.key.len() as u32).to_le_bytes());
+                header.extend_from_slice(&(self.vsize as u32).to_le_bytes());
+                header.extend_from_slice(&self.created_at.to_le_bytes());
+                header.extend_from_slice(&flags);
+            }
+            ValueLogFormat::V1 => {
+                // Length of what follows the length field, up to and including the checksum
+                let record_len = flags.len()
+                    + SIZE_OF_U64
+                    + SIZE_OF_U32
+                    + SIZE_OF_U32
+                    + self.key.len()
+                    + self.vsize
+                    + self.trailer(format).len()
+                    + self.checksum_type.size();
+                header.push(VLOG_RECORD_MAGIC);
+                header.extend_from_slice(&(record_len as u32).to_le_bytes());
+                header.push(flags[0]);
+                header.extend_from_slice(&self.created_at.to_le_bytes());
+                header.extend_from_slice(&flags[SIZE_OF_U8..]);
+                header.extend_from_slice(&(self.key.len() as u32).to_le_bytes());
+                header.extend_from_slice(&(self.vsize as u32).to_le_bytes());
+            }
+        }
+        header.extend_from_slice(&self.key);
+        header
+    }
 
-        serialized_data.push(self.is_tombstone as u8);
+    // Encodes the fields of the entry that follow its value, before its checksum: the time it was written at. Legacy
+    // entries end with their value
+    fn trailer(&self, format: ValueLogFormat) -> Vec<u8> {
+        match format {
+            ValueLogFormat::Legacy => Vec::new(),
+            ValueLogFormat::V1 => self.written_at.to_le_bytes().to_vec(),
+        }
+    }
 
-        serialized_data.extend_from_slice(&self.key);
+    // Encodes the bytes of the entry that precede its value and those that follow it, its trailer and checksum
+    fn framing(&self, format: ValueLogFormat) -> (Vec<u8>, Vec<u8>) {
+        let header = self.header(format);
+        let mut footer = self.trailer(format);
+        let mut checksum = Checksum::new(self.checksum_type);
+        checksum.update(&header);
+        checksum.update(&self.value);
+        checksum.update(&footer);
+        footer.extend_from_slice(&checksum.finish());
+        (header, footer)
+    }
 
+    fn serialize(&self, format: ValueLogFormat) -> Vec<u8> {
+        let (header, footer) = self.framing(format);
+        let mut serialized_data = Vec::with_capacity(self.serialized_len(format));
+        serialized_data.extend_from_slice(&header);
         serialized_data.extend_from_slice(&self.value);
-
+        serialized_data.extend_from_slice(&footer);
         serialized_data
     }
 }
 
+// Writes every byte of `bufs` at the end of `file`, with as few `writev` calls as the kernel allows
+fn write_all_vectored(mut file: &std::fs::File, bufs: &[&[u8]]) -> io::Result<()> {
+    let mut slices: Vec<IoSlice> = bufs.iter().map(|buf| IoSlice::new(buf)).collect();
+    let mut slices = &mut slices[..];
+    IoSlice::advance_slices(&mut slices, 0);
+    while !slices.is_empty() {
+        match file.write_vectored(slices) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+            Ok(written) => IoSlice::advance_slices(&mut slices, written),
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+impl ValueLogFormat {
+    /// Returns the number of bytes preceding the first entry of a value log of this format
+    pub fn header_len(&self) -> usize {
+        match self {
+            ValueLogFormat::Legacy => 0,
+            ValueLogFormat::V1 => VLOG_HEADER_SIZE,
+        }
+    }
+
+    /// Returns the header written at the start of a value log of this format
+    pub(crate) fn header(&self) -> Vec<u8> {
+        match self {
+            ValueLogFormat::Legacy => Vec::new(),
+            ValueLogFormat::V1 => [&VLOG_MAGIC[..], &VLOG_FORMAT_VERSION.to_le_bytes()].concat(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_serialized_deserialized() {}
+
+    #[test]
+    fn test_write_all_vectored() {
+        let entry = ValueLogEntry::new(3, 5, b"key".to_vec(), b"value".to_vec(), 7, false);
+        let (header, footer) = entry.framing(ValueLogFormat::V1);
+        let file = tempfile::tempfile().unwrap();
+        write_all_vectored(&file, &[&[], &header, &entry.value, &footer]).unwrap();
+
+        let mut written = Vec::new();
+        let mut reader = &file;
+        std::io::Seek::rewind(&mut reader).unwrap();
+        std::io::Read::read_to_end(&mut reader, &mut written).unwrap();
+        assert_eq!(written, entry.serialize(ValueLogFormat::V1));
+    }
 }